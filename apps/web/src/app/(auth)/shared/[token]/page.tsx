@@ -0,0 +1,153 @@
+'use client';
+
+import { use, useState } from 'react';
+import { useQuery } from '@tanstack/react-query';
+import { shareLinks as shareApi } from '@/lib/api';
+import type { SharedTransaction } from '@/lib/api';
+import { Card, CardContent, CardHeader, CardTitle, Button } from '@opacore/ui';
+import { Table, TableBody, TableCell, TableHead, TableHeader, TableRow } from '@opacore/ui';
+import { XCircle, Eye } from 'lucide-react';
+
+function usd(value: number) {
+  return `$${value.toLocaleString(undefined, { minimumFractionDigits: 2, maximumFractionDigits: 2 })}`;
+}
+
+export default function SharedPortfolioPage({ params }: { params: Promise<{ token: string }> }) {
+  const { token } = use(params);
+  const [pages, setPages] = useState<SharedTransaction[][]>([]);
+  const [cursor, setCursor] = useState<string | null>(null);
+  const [loadingMore, setLoadingMore] = useState(false);
+
+  const { data: portfolio, isLoading, error } = useQuery({
+    queryKey: ['shared-portfolio', token],
+    queryFn: () => shareApi.publicGet(token),
+  });
+
+  const { data: firstPage } = useQuery({
+    queryKey: ['shared-transactions', token],
+    queryFn: () => shareApi.publicTransactions(token),
+    enabled: !!portfolio,
+  });
+
+  const loadMore = async () => {
+    const after = cursor ?? firstPage?.next_cursor;
+    if (!after) return;
+    setLoadingMore(true);
+    try {
+      const page = await shareApi.publicTransactions(token, after);
+      setPages((prev) => [...prev, page.data]);
+      setCursor(page.next_cursor ?? '');
+    } finally {
+      setLoadingMore(false);
+    }
+  };
+
+  if (isLoading) {
+    return (
+      <div className="min-h-screen flex items-center justify-center bg-background">
+        <p className="text-muted-foreground">Loading...</p>
+      </div>
+    );
+  }
+
+  if (error || !portfolio) {
+    return (
+      <div className="min-h-screen flex items-center justify-center bg-background">
+        <Card className="max-w-md w-full mx-4">
+          <CardContent className="pt-6 text-center">
+            <XCircle className="h-12 w-12 text-destructive mx-auto mb-4" />
+            <h2 className="text-lg font-semibold mb-2">Not Found</h2>
+            <p className="text-sm text-muted-foreground">
+              This share link may be invalid, expired or revoked.
+            </p>
+          </CardContent>
+        </Card>
+      </div>
+    );
+  }
+
+  const { summary } = portfolio;
+  const transactions = [...(firstPage?.data ?? []), ...pages.flat()];
+  const hasMore = cursor === null ? !!firstPage?.next_cursor : cursor !== '';
+  const walletLabel = (id: string | null) => portfolio.wallets.find((w) => w.id === id)?.label ?? '—';
+
+  return (
+    <div className="min-h-screen bg-background p-4">
+      <div className="max-w-4xl mx-auto space-y-6">
+        <div className="text-center">
+          <h1 className="text-2xl font-bold">{portfolio.name}</h1>
+          {portfolio.description && (
+            <p className="text-sm text-muted-foreground">{portfolio.description}</p>
+          )}
+          <p className="flex items-center justify-center gap-1 text-xs text-muted-foreground mt-1">
+            <Eye className="h-3 w-3" />
+            Read-only view, available until {new Date(portfolio.expires_at).toLocaleDateString()}
+          </p>
+        </div>
+
+        <div className="grid gap-4 sm:grid-cols-2 lg:grid-cols-4">
+          {[
+            ['Balance', `${(summary.total_balance_sat / 1e8).toFixed(8)} BTC`],
+            ['Current Value', usd(summary.current_value_usd)],
+            ['Cost Basis', usd(summary.total_cost_basis_usd)],
+            ['Unrealized Gain', usd(summary.unrealized_gain_usd)],
+          ].map(([label, value]) => (
+            <Card key={label}>
+              <CardHeader className="pb-2">
+                <CardTitle className="text-sm font-medium text-muted-foreground">{label}</CardTitle>
+              </CardHeader>
+              <CardContent>
+                <div className="text-xl font-bold">{value}</div>
+              </CardContent>
+            </Card>
+          ))}
+        </div>
+
+        <Card>
+          <CardHeader>
+            <CardTitle className="text-base">
+              Transactions ({firstPage?.total ?? summary.transaction_count})
+            </CardTitle>
+          </CardHeader>
+          <CardContent>
+            <Table>
+              <TableHeader>
+                <TableRow>
+                  <TableHead>Date</TableHead>
+                  <TableHead>Type</TableHead>
+                  <TableHead>Wallet</TableHead>
+                  <TableHead className="text-right">Amount (BTC)</TableHead>
+                  <TableHead className="text-right">Value</TableHead>
+                </TableRow>
+              </TableHeader>
+              <TableBody>
+                {transactions.map((tx) => (
+                  <TableRow key={tx.id}>
+                    <TableCell>{tx.transacted_at.slice(0, 10)}</TableCell>
+                    <TableCell className="capitalize">{tx.tx_type}</TableCell>
+                    <TableCell>{walletLabel(tx.wallet_id)}</TableCell>
+                    <TableCell className="text-right font-mono">{(tx.amount_sat / 1e8).toFixed(8)}</TableCell>
+                    <TableCell className="text-right">
+                      {tx.fiat_amount != null ? `${tx.fiat_amount.toFixed(2)} ${tx.fiat_currency.toUpperCase()}` : '—'}
+                    </TableCell>
+                  </TableRow>
+                ))}
+              </TableBody>
+            </Table>
+            {hasMore && (
+              <div className="flex justify-center pt-4">
+                <Button variant="outline" size="sm" onClick={loadMore} disabled={loadingMore}>
+                  {loadingMore ? 'Loading...' : 'Load more'}
+                </Button>
+              </div>
+            )}
+          </CardContent>
+        </Card>
+
+        <p className="text-center text-xs text-muted-foreground">
+          Powered by opacore &mdash; Non-custodial Bitcoin portfolio tracking
+        </p>
+      </div>
+    </div>
+  );
+}
@@ -1,7 +1,7 @@
 'use client';
 
 import { useQuery } from '@tanstack/react-query';
-import { portfolios, prices, transactions as txApi } from '@/lib/api';
+import { dashboard, portfolios, transactions as txApi } from '@/lib/api';
 import { StatsCards } from '@/components/dashboard/stats-cards';
 import { RecentTransactions } from '@/components/dashboard/recent-transactions';
 import { PriceChart } from '@/components/dashboard/price-chart';
@@ -19,9 +19,10 @@ export default function DashboardPage() {
     queryFn: () => portfolios.list(),
   });
 
-  const { data: currentPrice } = useQuery({
-    queryKey: ['prices', 'current'],
-    queryFn: () => prices.current('usd'),
+  // Totals across every portfolio, with the current price, in one call
+  const { data: overview } = useQuery({
+    queryKey: ['dashboard'],
+    queryFn: () => dashboard.get(),
   });
 
   const firstPortfolioId = portfolioList?.[0]?.id;
@@ -32,26 +33,20 @@ export default function DashboardPage() {
     enabled: !!firstPortfolioId,
   });
 
-  const { data: summary } = useQuery({
-    queryKey: ['portfolio-summary', firstPortfolioId],
-    queryFn: () => portfolios.summary(firstPortfolioId!),
-    enabled: !!firstPortfolioId,
-  });
-
   const { data: recentTxs } = useQuery({
     queryKey: ['transactions', firstPortfolioId, 'recent'],
     queryFn: () => txApi.list({ portfolioId: firstPortfolioId!, limit: 5 }),
     enabled: !!firstPortfolioId,
   });
 
-  const btcPrice = currentPrice?.price ?? 0;
-  const totalBtc = summary ? (summary.total_balance_sat / 1e8).toFixed(8) : undefined;
-  // Show '—' (undefined) when summary is loading OR when price hasn't arrived yet
+  const btcPrice = overview?.current_price ?? 0;
+  const totalBtc = overview ? (overview.total_balance_sat / 1e8).toFixed(8) : undefined;
+  // Show '—' (undefined) when the overview is loading OR when price hasn't arrived yet
   // (balance exists but value is still 0 — avoids scary "$0.00" right after sync)
-  const hasBtc = (summary?.total_balance_sat ?? 0) > 0;
-  const priceReady = (summary?.current_value_usd ?? 0) > 0 || !hasBtc;
-  const totalValue = summary && priceReady ? (summary.current_value_usd?.toFixed(2) ?? '0.00') : undefined;
-  const totalCostBasis = summary ? (summary.total_cost_basis_usd?.toFixed(2) ?? '0.00') : undefined;
+  const hasBtc = (overview?.total_balance_sat ?? 0) > 0;
+  const priceReady = (overview?.total_value ?? 0) > 0 || !hasBtc;
+  const totalValue = overview && priceReady ? overview.total_value.toFixed(2) : undefined;
+  const totalCostBasis = overview ? overview.total_cost_basis.toFixed(2) : undefined;
 
   if (portfoliosLoading) {
     return (
@@ -20,6 +20,7 @@ export default function SettingsPage() {
   const [isPending, setIsPending] = useState(false);
 
   const [deleteConfirm, setDeleteConfirm] = useState('');
+  const [deletePassword, setDeletePassword] = useState('');
   const [deleteError, setDeleteError] = useState('');
   const [isDeleting, setIsDeleting] = useState(false);
 
@@ -61,9 +62,13 @@ export default function SettingsPage() {
       setDeleteError('Please type "delete" to confirm.');
       return;
     }
+    if (!deletePassword) {
+      setDeleteError('Please enter your password.');
+      return;
+    }
     setIsDeleting(true);
     try {
-      await auth.deleteAccount();
+      await auth.deleteAccount(deletePassword);
       router.push('/');
     } catch (err) {
       const message = err instanceof Error ? err.message : 'Failed to delete account.';
@@ -292,6 +297,16 @@ export default function SettingsPage() {
               onChange={(e) => setDeleteConfirm(e.target.value)}
             />
           </div>
+          <div className="space-y-2">
+            <Label htmlFor="delete-password">Password</Label>
+            <Input
+              id="delete-password"
+              type="password"
+              autoComplete="current-password"
+              value={deletePassword}
+              onChange={(e) => setDeletePassword(e.target.value)}
+            />
+          </div>
           <Button
             variant="destructive"
             onClick={handleDeleteAccount}
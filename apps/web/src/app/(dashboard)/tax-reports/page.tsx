@@ -16,6 +16,8 @@ const METHODS = [
   { value: 'fifo', label: 'FIFO', description: 'First In, First Out' },
   { value: 'lifo', label: 'LIFO', description: 'Last In, First Out' },
   { value: 'hifo', label: 'HIFO', description: 'Highest In, First Out' },
+  { value: 'specid', label: 'Specific ID', description: 'Assigned lots, then FIFO' },
+  { value: 'section104', label: 'UK pooling', description: 'Same day, 30 days, then Section 104 pool' },
 ] as const;
 
 function formatUsd(n: number, signed = false) {
@@ -30,7 +32,7 @@ function formatUsd(n: number, signed = false) {
 
 export default function TaxReportsPage() {
   const [year, setYear] = useState(CURRENT_YEAR - 1); // Default to last tax year
-  const [method, setMethod] = useState<'fifo' | 'lifo' | 'hifo'>('fifo');
+  const [method, setMethod] = useState<'fifo' | 'lifo' | 'hifo' | 'specid' | 'section104'>('fifo');
 
   const { data: portfolioList } = useQuery({
     queryKey: ['portfolios'],
@@ -49,6 +51,18 @@ export default function TaxReportsPage() {
     ? taxApi.csvUrl(portfolioId, year, method)
     : null;
 
+  const pdfUrl = portfolioId
+    ? taxApi.pdfUrl(portfolioId, year, method)
+    : null;
+
+  const exports = portfolioId
+    ? ([
+        ['koinly', 'Koinly'],
+        ['cointracker', 'CoinTracker'],
+        ['turbotax', 'TurboTax'],
+      ] as const).map(([format, label]) => ({ label, url: taxApi.exportUrl(portfolioId, format, year, method) }))
+    : [];
+
   const hasGains = report && report.disposition_count > 0;
   const netGain = report ? report.total_gains : 0;
   const isNetProfit = netGain >= 0;
@@ -114,6 +128,22 @@ export default function TaxReportsPage() {
             </Button>
           </a>
         )}
+        {pdfUrl && (
+          <a href={pdfUrl} download>
+            <Button variant="outline" size="sm" className="gap-2">
+              <Download className="h-4 w-4" />
+              PDF report
+            </Button>
+          </a>
+        )}
+        {exports.map((e) => (
+          <a key={e.label} href={e.url} download>
+            <Button variant="ghost" size="sm" className="gap-2">
+              <Download className="h-4 w-4" />
+              {e.label}
+            </Button>
+          </a>
+        ))}
       </div>
 
       {/* Loading */}
@@ -131,6 +161,15 @@ export default function TaxReportsPage() {
         </div>
       )}
 
+      {/* Missing prices */}
+      {!isLoading && !error && report && report.warnings.length > 0 && (
+        <div className="flex items-center gap-2 rounded-lg border border-amber-200 bg-amber-50 px-4 py-3 text-sm text-amber-800">
+          <AlertCircle className="h-4 w-4 shrink-0" />
+          {report.warnings.length} transaction{report.warnings.length !== 1 ? 's have' : ' has'} no price and{' '}
+          {report.warnings.length !== 1 ? 'are' : 'is'} counted at $0. Sync prices or enter them to correct this report.
+        </div>
+      )}
+
       {/* No disposals */}
       {!isLoading && !error && report && !hasGains && (
         <Card>
@@ -27,7 +27,18 @@ export default function NewTransactionPage() {
 
   const [form, setForm] = useState({
     portfolioId: '',
-    type: 'buy' as 'buy' | 'sell' | 'send' | 'receive' | 'transfer',
+    type: 'buy' as
+      | 'buy'
+      | 'sell'
+      | 'send'
+      | 'receive'
+      | 'transfer'
+      | 'income'
+      | 'mining'
+      | 'gift'
+      | 'donation'
+      | 'lost'
+      | 'fee',
     amountBtc: '',
     pricePerBtc: '',
     fee: '',
@@ -39,6 +50,7 @@ export default function NewTransactionPage() {
     onSuccess: async () => {
       await queryClient.invalidateQueries({ queryKey: ['transactions'] });
       await queryClient.invalidateQueries({ queryKey: ['portfolio-summary'] });
+      await queryClient.invalidateQueries({ queryKey: ['dashboard'] });
       router.push('/transactions');
     },
   });
@@ -124,6 +136,12 @@ export default function NewTransactionPage() {
                   <option value="send">Send</option>
                   <option value="receive">Receive</option>
                   <option value="transfer">Transfer</option>
+                  <option value="income">Income</option>
+                  <option value="mining">Mining</option>
+                  <option value="gift">Gift</option>
+                  <option value="donation">Donation</option>
+                  <option value="lost">Lost</option>
+                  <option value="fee">Fee</option>
                 </Select>
               </div>
 
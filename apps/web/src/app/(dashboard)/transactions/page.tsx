@@ -42,6 +42,12 @@ const TX_TYPE_LABEL: Record<string, string> = {
   receive: 'Receive',
   send: 'Transfer',
   transfer: 'Transfer',
+  income: 'Income',
+  mining: 'Mining',
+  gift: 'Gift',
+  donation: 'Donation',
+  lost: 'Lost',
+  fee: 'Fee',
 };
 
 const TX_TYPE_VARIANT: Record<string, 'default' | 'secondary' | 'destructive' | 'outline'> = {
@@ -50,8 +56,13 @@ const TX_TYPE_VARIANT: Record<string, 'default' | 'secondary' | 'destructive' |
   receive: 'secondary',
   send: 'outline',
   transfer: 'outline',
+  income: 'secondary',
+  mining: 'secondary',
+  lost: 'destructive',
 };
 
+const OUTGOING_TYPES = ['send', 'sell', 'gift', 'donation', 'lost', 'fee'];
+
 export default function TransactionsPage() {
   const queryClient = useQueryClient();
   const [reclassifyingId, setReclassifyingId] = useState<string | null>(null);
@@ -94,6 +105,7 @@ export default function TransactionsPage() {
       setReclassifyingId(null);
       queryClient.invalidateQueries({ queryKey: ['transactions'] });
       queryClient.invalidateQueries({ queryKey: ['portfolio-summary'] });
+      queryClient.invalidateQueries({ queryKey: ['dashboard'] });
     },
   });
 
@@ -165,7 +177,7 @@ export default function TransactionsPage() {
                 const price = tx.price_usd;
                 const total = price ? amount * price : null;
                 const date = new Date(tx.transacted_at);
-                const isOutgoing = tx.tx_type === 'send' || tx.tx_type === 'sell';
+                const isOutgoing = OUTGOING_TYPES.includes(tx.tx_type);
                 const isSend = tx.tx_type === 'send';
                 const isSell = tx.tx_type === 'sell';
                 const isReclassifying = reclassifyingId === tx.id;
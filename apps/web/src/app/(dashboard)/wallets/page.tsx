@@ -56,6 +56,7 @@ export default function WalletsPage() {
       queryClient.invalidateQueries({ queryKey: ['wallets'] });
       queryClient.invalidateQueries({ queryKey: ['transactions'] });
       queryClient.invalidateQueries({ queryKey: ['portfolio-summary'] });
+      queryClient.invalidateQueries({ queryKey: ['dashboard'] });
     },
     onError: () => {
       setSyncingId(null);
@@ -88,6 +89,7 @@ export default function WalletsPage() {
           queryClient.invalidateQueries({ queryKey: ['wallets'] });
           queryClient.invalidateQueries({ queryKey: ['transactions'] });
           queryClient.invalidateQueries({ queryKey: ['portfolio-summary'] });
+          queryClient.invalidateQueries({ queryKey: ['dashboard'] });
         } catch {
           // swallow — don't block remaining wallets
         }
@@ -10,14 +10,25 @@ class ApiError extends Error {
   }
 }
 
+// Double-submit CSRF token: the server sets this cookie with the session and expects
+// it back in X-CSRF-Token on anything but GET/HEAD.
+function csrfToken(): string | undefined {
+  if (typeof document === 'undefined') return undefined;
+  const cookie = document.cookie.split('; ').find((c) => c.startsWith('opacore_csrf='));
+  return cookie ? decodeURIComponent(cookie.slice('opacore_csrf='.length)) : undefined;
+}
+
 async function request<T>(path: string, options?: RequestInit): Promise<T> {
+  const method = (options?.method ?? 'GET').toUpperCase();
+  const csrf = method === 'GET' || method === 'HEAD' ? undefined : csrfToken();
   const res = await fetch(`${API_BASE}${path}`, {
     credentials: 'include',
+    ...options,
     headers: {
       'Content-Type': 'application/json',
+      ...(csrf ? { 'X-CSRF-Token': csrf } : {}),
       ...options?.headers,
     },
-    ...options,
   });
 
   if (!res.ok) {
@@ -39,6 +50,7 @@ export interface UserPublic {
   name: string;
   default_currency: string;
   email_verified: boolean;
+  is_admin: boolean;
   created_at: string;
   updated_at: string;
 }
@@ -48,6 +60,16 @@ export interface RegisterResponse {
   email: string;
 }
 
+export interface DeleteAccountSummary {
+  portfolios: number;
+  wallets: number;
+  transactions: number;
+  invoices: number;
+  sessions: number;
+  tokens: number;
+  bdk_files_removed: number;
+}
+
 export const auth = {
   register: (data: { email: string; password: string; name: string }) =>
     request<RegisterResponse>('/auth/register', { method: 'POST', body: JSON.stringify(data) }),
@@ -74,10 +96,78 @@ export const auth = {
   resetPassword: (token: string, new_password: string) =>
     request<void>('/auth/reset-password', { method: 'POST', body: JSON.stringify({ token, new_password }) }),
 
-  deleteAccount: () =>
-    request<void>('/auth/account', { method: 'DELETE' }),
+  deleteAccount: (password: string) =>
+    request<DeleteAccountSummary>('/auth/me', { method: 'DELETE', body: JSON.stringify({ password }) }),
 };
 
+// ── Dashboard ──
+
+// Totals across every portfolio the user can see, in USD
+export interface Dashboard {
+  currency: string;
+  current_price: number;
+  total_balance_sat: number;
+  total_value: number;
+  total_cost_basis: number;
+  unrealized_gain: number;
+  // Against the value at the end of yesterday / 30 days ago; null without a cached price
+  change_24h: ValueChange | null;
+  change_30d: ValueChange | null;
+  pending_invoices: { count: number; amount_sat: number };
+  portfolios: DashboardPortfolio[];
+  wallets: WalletStatus[];
+}
+
+export interface ValueChange {
+  amount: number;
+  percent: number | null;
+}
+
+export interface DashboardPortfolio {
+  id: string;
+  name: string;
+  balance_sat: number;
+  value: number;
+  cost_basis: number;
+  unrealized_gain: number;
+}
+
+export interface WalletStatus {
+  id: string;
+  portfolio_id: string;
+  label: string;
+  balance_sat: number;
+  last_synced_at: string | null;
+  last_sync_backend: string | null;
+  // Latest full sync job: running | completed | failed | interrupted
+  sync_status: string | null;
+  sync_error: string | null;
+}
+
+export const dashboard = {
+  get: () => request<Dashboard>('/dashboard'),
+  // Defaults to the user's default currency
+  netWorth: (currency?: string) =>
+    request<NetWorth>(`/networth${currency ? `?currency=${currency}` : ''}`),
+};
+
+// Totals across the portfolios the user owns, in `currency`
+export interface NetWorth {
+  currency: string;
+  current_price: number;
+  total_balance_sat: number;
+  total_value: number;
+  total_cost_basis: number;
+  unrealized_gain: number;
+  portfolios: PortfolioContribution[];
+}
+
+// `share` of the total value, 0 to 1
+export interface PortfolioContribution extends DashboardPortfolio {
+  base_currency: string;
+  share: number;
+}
+
 // ── Portfolios ──
 
 export interface Portfolio {
@@ -85,34 +175,252 @@ export interface Portfolio {
   user_id: string;
   name: string;
   description: string | null;
+  // Defaults for cost basis, summaries and tax reports, which are reported in base_currency
+  cost_basis_method: string;
+  base_currency: string;
   created_at: string;
   updated_at: string;
 }
 
+// `&method=` when a method is given; otherwise the portfolio's own default applies
+const methodParam = (method?: string) => (method ? `&method=${method}` : '');
+
 export const portfolios = {
   list: () => request<Portfolio[]>('/portfolios'),
 
   get: (id: string) => request<Portfolio>(`/portfolios/${id}`),
 
-  create: (data: { name: string; description?: string }) =>
+  create: (data: { name: string; description?: string; cost_basis_method?: string; base_currency?: string }) =>
     request<Portfolio>('/portfolios', { method: 'POST', body: JSON.stringify(data) }),
 
-  update: (id: string, data: { name?: string; description?: string }) =>
+  update: (id: string, data: { name?: string; description?: string; cost_basis_method?: string; base_currency?: string }) =>
     request<Portfolio>(`/portfolios/${id}`, { method: 'PUT', body: JSON.stringify(data) }),
 
   delete: (id: string) => request<void>(`/portfolios/${id}`, { method: 'DELETE' }),
 
-  summary: (id: string, method = 'fifo') =>
-    request<PortfolioSummary>(`/portfolios/${id}/summary?method=${method}`),
+  summary: (id: string, method?: string) =>
+    request<PortfolioSummary>(`/portfolios/${id}/summary?${methodParam(method)}`),
 
-  costBasis: (id: string, method = 'fifo', year?: number) => {
-    let url = `/portfolios/${id}/cost-basis?method=${method}`;
+  costBasis: (id: string, method?: string, year?: number) => {
+    let url = `/portfolios/${id}/cost-basis?${methodParam(method)}`;
     if (year) url += `&year=${year}`;
     return request<CostBasisResult>(url);
   },
+
+  // Open lots with unrealized gains, for tax-loss harvesting
+  lots: (id: string, method?: string) =>
+    request<LotsResponse>(`/portfolios/${id}/lots?${methodParam(method)}`),
+
+  // Daily value for charting; defaults to first transaction through today, in the portfolio's currency
+  valueHistory: (id: string, params: { start?: string; end?: string; currency?: string } = {}) => {
+    const qs = new URLSearchParams();
+    if (params.start) qs.set('start', params.start);
+    if (params.end) qs.set('end', params.end);
+    if (params.currency) qs.set('currency', params.currency);
+    return request<ValueHistory>(`/portfolios/${id}/value-history?${qs}`);
+  },
+
+  // Totals by label and transaction type between two dates (inclusive)
+  byLabel: (id: string, params: { start?: string; end?: string } = {}) => {
+    const qs = new URLSearchParams();
+    if (params.start) qs.set('start', params.start);
+    if (params.end) qs.set('end', params.end);
+    return request<LabelBreakdown>(`/portfolios/${id}/analysis/by-label?${qs}`);
+  },
+
+  // Monthly inflow/outflow in the portfolio's currency, split between synced and manual transactions
+  cashFlow: (id: string, params: { start?: string; end?: string } = {}) => {
+    const qs = new URLSearchParams();
+    if (params.start) qs.set('start', params.start);
+    if (params.end) qs.set('end', params.end);
+    return request<CashFlow>(`/portfolios/${id}/analysis/cash-flow?${qs}`);
+  },
+
+  // Average purchase price and sats per unit of currency over time; method sets the break-even price
+  dca: (id: string, method?: string) =>
+    request<DcaAnalysis>(`/portfolios/${id}/analysis/dca?${methodParam(method)}`),
+
+  // Balance by lot age, and what turns long-term in the next 30/60/90 days
+  holdingsAge: (id: string, method?: string) =>
+    request<HoldingsAge>(`/portfolios/${id}/analysis/holdings-age?${methodParam(method)}`),
+
+  // Miner fees paid from the portfolio's wallets, per wallet and per month
+  fees: (id: string, params: { start?: string; end?: string } = {}) => {
+    const qs = new URLSearchParams();
+    if (params.start) qs.set('start', params.start);
+    if (params.end) qs.set('end', params.end);
+    return request<FeeAnalytics>(`/portfolios/${id}/analysis/fees?${qs}`);
+  },
+
+  // Actual value against lump-sum and monthly DCA buy-and-hold with the same money
+  benchmark: (id: string) => request<Benchmark>(`/portfolios/${id}/analysis/benchmark`),
 };
 
+// `vs_*` is actual less the strategy: positive means trading beat it
+export interface Benchmark {
+  currency: string;
+  start: string;
+  end: string;
+  contributed: number;
+  withdrawn: number;
+  unpriced: number;
+  actual_value: number | null;
+  lump_sum_value: number | null;
+  dca_value: number | null;
+  vs_lump_sum: number | null;
+  vs_dca: number | null;
+  points: BenchmarkPoint[];
+}
+
+// `actual_value` includes everything withdrawn, as if kept in cash
+export interface BenchmarkPoint {
+  date: string;
+  price: number | null;
+  contributed: number;
+  withdrawn: number;
+  actual_value: number | null;
+  lump_sum_value: number | null;
+  dca_value: number | null;
+}
+
+export interface FeeAnalytics {
+  currency: string;
+  total: FeeTotal;
+  wallets: (FeeTotal & { wallet_id: string; label: string })[];
+  months: (FeeTotal & { month: string })[];
+}
+
+// `fee_value` is at the price on the day each fee was paid
+export interface FeeTotal {
+  tx_count: number;
+  fee_sat: number;
+  fee_value: number;
+  unpriced: number;
+}
+
+export interface HoldingsAge {
+  currency: string;
+  current_price: number;
+  total_balance_sat: number;
+  buckets: AgeBucket[];
+  becoming_long_term: UpcomingLongTerm[];
+}
+
+export interface Holding {
+  lot_count: number;
+  balance_sat: number;
+  cost_basis: number;
+  value: number;
+}
+
+// `bucket` is one of '<3m', '3-12m', '1-2y', '>2y'; `share` is 0 to 1
+export interface AgeBucket extends Holding {
+  bucket: string;
+  share: number;
+}
+
+// Cumulative: the 60-day window includes the 30-day one
+export interface UpcomingLongTerm extends Holding {
+  within_days: number;
+}
+
+export interface DcaAnalysis {
+  currency: string;
+  total_invested: number;
+  total_bought_sat: number;
+  purchase_count: number;
+  unpriced: number;
+  average_cost: number | null;
+  break_even_price: number | null;
+  months: DcaMonth[];
+}
+
+export interface DcaMonth {
+  month: string;
+  invested: number;
+  bought_sat: number;
+  sats_per_unit: number | null;
+  average_price: number | null;
+  cumulative_invested: number;
+  cumulative_bought_sat: number;
+  average_cost: number | null;
+}
+
+export interface CashFlow {
+  currency: string;
+  months: MonthFlow[];
+}
+
+// `manual` is everything not synced from a wallet: entered, imported or from invoices
+export interface MonthFlow {
+  month: string;
+  total: Flow;
+  chain: Flow;
+  manual: Flow;
+}
+
+export interface Flow {
+  inflow_sat: number;
+  outflow_sat: number;
+  net_sat: number;
+  inflow: number;
+  outflow: number;
+  net: number;
+  unpriced: number;
+}
+
+export interface LabelBreakdown {
+  start: string | null;
+  end: string | null;
+  groups: LabelGroup[];
+}
+
+// A transaction with several labels counts in each; unlabelled ones have a null label_id
+export interface LabelGroup {
+  label_id: string | null;
+  label: string | null;
+  tx_type: string;
+  count: number;
+  amount_sat: number;
+  fee_sat: number;
+  fiat: Record<string, number>;
+  unpriced: number;
+}
+
+export interface ValueHistory {
+  currency: string;
+  start: string;
+  end: string;
+  points: ValuePoint[];
+}
+
+// End-of-day balance at the latest cached price on or before `date` (from `price_date`)
+export interface ValuePoint {
+  date: string;
+  balance_sat: number;
+  price: number | null;
+  price_date: string | null;
+  value: number | null;
+}
+
+export interface UnrealizedLot extends OpenLot {
+  current_value_usd: number;
+  unrealized_gain_usd: number;
+  is_long_term: boolean;
+  holding_period_days: number;
+}
+
+export interface LotsResponse {
+  currency: string;
+  current_price_usd: number;
+  total_unrealized_gain_usd: number;
+  unrealized_loss_usd: number;
+  lots: UnrealizedLot[];
+}
+
+// Amounts are in `currency`, the portfolio's base currency, despite the _usd names
 export interface PortfolioSummary {
+  currency: string;
   total_balance_sat: number;
   total_cost_basis_usd: number;
   current_value_usd: number;
@@ -124,6 +432,7 @@ export interface PortfolioSummary {
 }
 
 export interface CostBasisResult {
+  currency: string;
   method: string;
   gains: GainLoss[];
   total_realized_gain_usd: number;
@@ -132,9 +441,21 @@ export interface CostBasisResult {
   remaining_lots: number;
   remaining_balance_sat: number;
   remaining_cost_basis_usd: number;
+  unmatched_sell_sat: number;
+  warnings: MissingPrice[];
+}
+
+// A transaction counted at $0 because it has no price
+export interface MissingPrice {
+  transaction_id: string | null;
+  tx_type: string;
+  transacted_at: string;
+  amount_sat: number;
 }
 
 export interface GainLoss {
+  tx_type: string;
+  acquired_date: string | null;
   sell_date: string;
   sell_amount_sat: number;
   sell_price_usd: number;
@@ -145,6 +466,67 @@ export interface GainLoss {
   holding_period_days: number;
 }
 
+// ── Share links ──
+
+export interface ShareLink {
+  id: string;
+  portfolio_id: string;
+  name: string;
+  prefix: string;
+  expires_at: string;
+  view_count: number;
+  last_viewed_at: string | null;
+  created_by: string | null;
+  created_at: string;
+}
+
+export interface CreatedShareLink extends ShareLink {
+  token: string;
+  url: string;
+}
+
+export interface SharedPortfolio {
+  name: string;
+  description: string | null;
+  wallets: { id: string; label: string; wallet_type: string }[];
+  summary: PortfolioSummary;
+  expires_at: string;
+}
+
+export interface SharedTransaction {
+  id: string;
+  wallet_id: string | null;
+  tx_type: string;
+  amount_sat: number;
+  fee_sat: number | null;
+  price_usd: number | null;
+  fiat_amount: number | null;
+  fiat_currency: string;
+  txid: string | null;
+  block_height: number | null;
+  transacted_at: string;
+}
+
+export const shareLinks = {
+  list: (portfolioId: string) => request<ShareLink[]>(`/portfolios/${portfolioId}/share-links`),
+
+  create: (portfolioId: string, data: { name: string; expires_in_days?: number }) =>
+    request<CreatedShareLink>(`/portfolios/${portfolioId}/share-links`, {
+      method: 'POST',
+      body: JSON.stringify(data),
+    }),
+
+  revoke: (portfolioId: string, linkId: string) =>
+    request<void>(`/portfolios/${portfolioId}/share-links/${linkId}`, { method: 'DELETE' }),
+
+  publicGet: (token: string) => request<SharedPortfolio>(`/shared/${token}`),
+
+  publicTransactions: (token: string, after?: string) =>
+    request<{ data: SharedTransaction[]; total: number; next_cursor: string | null }>(
+      `/shared/${token}/transactions${after ? `?after=${encodeURIComponent(after)}` : ''}`,
+    ),
+};
+
 // ── Transactions ──
 
 export interface Transaction {
@@ -324,6 +706,7 @@ export const wallets = {
 // ── Tax ──
 
 export interface TaxReport {
+  currency: string;
   year: number;
   method: string;
   short_term_gains: number;
@@ -333,6 +716,31 @@ export interface TaxReport {
   total_cost_basis: number;
   disposition_count: number;
   dispositions: TaxDisposition[];
+  warnings: MissingPrice[];
+}
+
+// Realized gains and income by quarter of the tax year, for estimated tax payments
+export interface QuarterlyGains {
+  currency: string;
+  year: number;
+  method: string;
+  quarters: QuarterGains[];
+  total_gains: number;
+  total_income: number;
+  warnings: MissingPrice[];
+}
+
+export interface QuarterGains {
+  quarter: number;
+  period_start: string;
+  period_end: string;
+  short_term_gains: number;
+  long_term_gains: number;
+  total_gains: number;
+  total_proceeds: number;
+  disposition_count: number;
+  total_income: number;
+  year_to_date_gains: number;
 }
 
 export interface TaxDisposition {
@@ -346,12 +754,73 @@ export interface TaxDisposition {
   holding_days: number;
 }
 
+export interface TaxYearLock {
+  portfolio_id: string;
+  year: number;
+  period_start: string;
+  period_end: string;
+  locked_by: string | null;
+  locked_at: string;
+}
+
+export interface OpenLot {
+  lot_id: string | null;
+  tx_type: string;
+  acquired_at: string;
+  amount_sat: number;
+  price_usd: number;
+  cost_basis_usd: number;
+}
+
+export interface LotAssignment {
+  lot_id: string;
+  amount_sat: number;
+}
+
 export const tax = {
-  report: (portfolioId: string, year: number, method = 'fifo') =>
-    request<TaxReport>(`/portfolios/${portfolioId}/tax/report?year=${year}&method=${method}`),
+  report: (portfolioId: string, year: number, method?: string) =>
+    request<TaxReport>(`/portfolios/${portfolioId}/tax/report?year=${year}${methodParam(method)}`),
+
+  quarterly: (portfolioId: string, year: number, method?: string) =>
+    request<QuarterlyGains>(`/portfolios/${portfolioId}/tax/quarterly?year=${year}${methodParam(method)}`),
+
+  csvUrl: (portfolioId: string, year: number, method?: string) =>
+    `${API_BASE}/portfolios/${portfolioId}/tax/csv?year=${year}${methodParam(method)}`,
 
-  csvUrl: (portfolioId: string, year: number, method = 'fifo') =>
-    `${API_BASE}/portfolios/${portfolioId}/tax/csv?year=${year}&method=${method}`,
+  // All of the user's portfolios as one holding
+  combinedReport: (year: number, method = 'fifo') =>
+    request<TaxReport>(`/tax/report?year=${year}&method=${method}`),
+
+  combinedCsvUrl: (year: number, method = 'fifo') =>
+    `${API_BASE}/tax/csv?year=${year}&method=${method}`,
+
+  // Locked (filed) tax years: their transactions can't be edited or deleted
+  locks: (portfolioId: string) => request<TaxYearLock[]>(`/portfolios/${portfolioId}/tax/locks`),
+
+  lockYear: (portfolioId: string, year: number) =>
+    request<TaxYearLock>(`/portfolios/${portfolioId}/tax/locks/${year}`, { method: 'PUT' }),
+
+  unlockYear: (portfolioId: string, year: number) =>
+    request<void>(`/portfolios/${portfolioId}/tax/locks/${year}`, { method: 'DELETE' }),
+
+  pdfUrl: (portfolioId: string, year: number, method?: string) =>
+    `${API_BASE}/portfolios/${portfolioId}/tax/pdf?year=${year}${methodParam(method)}`,
+
+  // CSV in another tax tool's import format
+  exportUrl: (portfolioId: string, format: 'koinly' | 'cointracker' | 'turbotax', year: number, method?: string) =>
+    `${API_BASE}/portfolios/${portfolioId}/tax/export?format=${format}&year=${year}${methodParam(method)}`,
+
+  // Specific identification (method=specid): the lots a disposal consumes
+  openLots: (transactionId: string) => request<OpenLot[]>(`/transactions/${transactionId}/open-lots`),
+
+  lotAssignments: (transactionId: string) =>
+    request<LotAssignment[]>(`/transactions/${transactionId}/lot-assignments`),
+
+  assignLots: (transactionId: string, assignments: LotAssignment[]) =>
+    request<LotAssignment[]>(`/transactions/${transactionId}/lot-assignments`, {
+      method: 'PUT',
+      body: JSON.stringify({ assignments }),
+    }),
 };
 
 // ── Invoices ──
@@ -401,11 +870,12 @@ export interface PublicInvoice {
 }
 
 export const invoices = {
-  list: (portfolioId: string, params?: { status?: string; type?: string }) => {
+  list: async (portfolioId: string, params?: { status?: string; type?: string }) => {
     const qs = new URLSearchParams();
     if (params?.status) qs.set('status', params.status);
     if (params?.type) qs.set('type', params.type);
-    return request<Invoice[]>(`/portfolios/${portfolioId}/invoices?${qs}`);
+    const res = await request<{ data: Invoice[] }>(`/portfolios/${portfolioId}/invoices?${qs}`);
+    return res.data;
   },
 
   get: (portfolioId: string, invoiceId: string) =>
@@ -0,0 +1,145 @@
+use axum::http::Method;
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::auth::session;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::cache::AppCache;
+
+/// Every token starts with this, so leaked keys are easy to recognize and scan for.
+pub const TOKEN_PREFIX: &str = "opk_";
+/// Characters of a token kept in the clear to tell keys apart.
+const DISPLAY_PREFIX_CHARS: usize = 12;
+/// `last_used_at` is only rewritten once it is this stale.
+const LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+
+/// What an API key may do. Keys never reach the account and credential routes under
+/// `/api/v1/auth`, beyond reading who they belong to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Every GET.
+    Read,
+    /// Creating, editing, importing and deleting transactions.
+    TransactionsWrite,
+    /// Creating, editing, sending and deleting invoices and customers.
+    InvoicesWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::TransactionsWrite, Scope::InvoicesWrite];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::TransactionsWrite => "transactions:write",
+            Scope::InvoicesWrite => "invoices:write",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Scope::ALL.into_iter().find(|s| s.name() == name)
+    }
+}
+
+/// The API key a request authenticated with, kept in the request extensions.
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth {
+    pub id: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// A new random token, its display prefix and the hash that is stored.
+pub fn generate() -> (String, String, String) {
+    use base64::Engine;
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("{TOKEN_PREFIX}{}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes));
+    let prefix = token[..DISPLAY_PREFIX_CHARS].to_string();
+    let hash = hash(&token);
+    (token, prefix, hash)
+}
+
+/// Only a token's SHA-256 is stored; the token itself is shown once, at creation.
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Look up a live API key by its token, marking it as used.
+pub fn authenticate(pool: &DbPool, cache: &AppCache, token: &str) -> AppResult<(ApiKeyAuth, User)> {
+    let conn = pool.get()?;
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let result = conn.query_row(
+        "SELECT id, user_id, scopes, last_used_at FROM api_keys
+         WHERE token_hash = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+        rusqlite::params![hash(token), now],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        },
+    );
+    let (id, user_id, scopes, last_used_at) = match result {
+        Ok(key) => key,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(AppError::Unauthorized),
+        Err(e) => return Err(AppError::Database(e)),
+    };
+
+    let stale_before = (Utc::now() - Duration::minutes(LAST_USED_RESOLUTION_MINUTES))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    if last_used_at.is_none_or(|used| used < stale_before) {
+        conn.execute("UPDATE api_keys SET last_used_at = ?1 WHERE id = ?2", rusqlite::params![now, id])?;
+    }
+
+    let scopes = serde_json::from_str::<Vec<String>>(&scopes)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|s| Scope::parse(s))
+        .collect();
+    let user = match cache.user(&conn, &user_id) {
+        Ok(user) => user,
+        Err(AppError::Database(rusqlite::Error::QueryReturnedNoRows)) => return Err(AppError::Unauthorized),
+        Err(e) => return Err(e),
+    };
+    session::require_enabled(&user)?;
+    Ok((ApiKeyAuth { id, scopes }, user))
+}
+
+/// Check that the key's scopes cover a request.
+pub fn authorize(key: &ApiKeyAuth, method: &Method, path: &str) -> AppResult<()> {
+    let segments: Vec<&str> = path.trim_start_matches("/api/v1/").split('/').collect();
+    if segments[0] == "auth" && !(method == Method::GET && matches!(segments[1..], ["me"] | ["quotas"])) {
+        return Err(AppError::Forbidden("API keys can't be used for account settings".into()));
+    }
+    if segments[0] == "import" {
+        return Err(AppError::Forbidden("API keys can't restore account exports".into()));
+    }
+    // The archive holds every descriptor and xpub, and jobs hand out its download link
+    if matches!(segments[0], "export" | "jobs") {
+        return Err(AppError::Forbidden("API keys can't export the account".into()));
+    }
+    if segments[0] == "admin" {
+        return Err(AppError::Forbidden("API keys can't use the admin routes".into()));
+    }
+
+    let needed = if method == Method::GET || method == Method::HEAD {
+        Scope::Read
+    } else if segments.iter().any(|s| matches!(*s, "transactions" | "import" | "recurring")) {
+        Scope::TransactionsWrite
+    } else if segments.iter().any(|s| matches!(*s, "invoices" | "customers")) {
+        Scope::InvoicesWrite
+    } else {
+        return Err(AppError::Forbidden("API keys can't make changes here".into()));
+    };
+    if !key.scopes.contains(&needed) {
+        tracing::info!("API key {} denied {method} {path}: lacks {}", key.id, needed.name());
+        return Err(AppError::Forbidden(format!("API key lacks the {} scope", needed.name())));
+    }
+    Ok(())
+}
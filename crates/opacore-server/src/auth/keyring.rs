@@ -0,0 +1,111 @@
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Server secrets used to sign values handed to clients (session cookies, and
+/// anything else that must round-trip untampered).
+///
+/// The first key signs; every key verifies until its `accept_until` date. Rotating
+/// is: put a new key first, keep the old one with a date at least as far out as the
+/// longest-lived value it signed, then drop it once that date has passed.
+#[derive(Clone)]
+pub struct KeyRing {
+    keys: Vec<SigningKey>,
+}
+
+#[derive(Clone)]
+struct SigningKey {
+    id: String,
+    secret: String,
+    accept_until: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the secrets themselves
+        f.debug_list()
+            .entries(self.keys.iter().map(|k| (&k.id, k.accept_until)))
+            .finish()
+    }
+}
+
+impl KeyRing {
+    /// Parse `SESSION_SECRETS`: comma-separated `id:secret` entries, newest first. A
+    /// retired key may end in `:YYYY-MM-DD`, the last day it is still accepted.
+    /// Key ids may not contain `.` or `:`, and secrets may not contain `,`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut keys: Vec<SigningKey> = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, rest) = entry
+                .split_once(':')
+                .ok_or_else(|| "key entries must be id:secret".to_string())?;
+            let (secret, accept_until) = match rest.rsplit_once(':') {
+                Some((secret, date)) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                    Ok(d) => (secret, d.succ_opt().and_then(|d| d.and_hms_opt(0, 0, 0)).map(|t| t.and_utc())),
+                    Err(_) => (rest, None),
+                },
+                None => (rest, None),
+            };
+            if id.is_empty() || id.contains('.') || secret.is_empty() {
+                return Err(format!("invalid key entry '{id}'"));
+            }
+            if keys.iter().any(|k| k.id == id) {
+                return Err(format!("duplicate key id '{id}'"));
+            }
+            keys.push(SigningKey {
+                id: id.to_string(),
+                secret: secret.to_string(),
+                accept_until,
+            });
+        }
+        if keys.is_empty() {
+            return Err("no keys given".into());
+        }
+        Ok(Self { keys })
+    }
+
+    /// A ring holding just the legacy single `SESSION_SECRET`.
+    pub fn single(secret: String) -> Self {
+        Self {
+            keys: vec![SigningKey { id: "default".into(), secret, accept_until: None }],
+        }
+    }
+
+    /// Id of the key new signatures are made with.
+    pub fn signing_key_id(&self) -> &str {
+        &self.keys[0].id
+    }
+
+    /// `value.kid.signature`. `purpose` separates signatures made for different uses,
+    /// so a value signed for one can't be replayed as another.
+    pub fn sign(&self, purpose: &str, value: &str) -> String {
+        let key = &self.keys[0];
+        let sig = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac(key, purpose, value).finalize().into_bytes());
+        format!("{value}.{}.{sig}", key.id)
+    }
+
+    /// The original value if `signed` was produced by [`sign`](Self::sign) with the
+    /// same purpose and a key that is still accepted.
+    pub fn verify<'a>(&self, purpose: &str, signed: &'a str) -> Option<&'a str> {
+        let (rest, sig) = signed.rsplit_once('.')?;
+        let (value, kid) = rest.rsplit_once('.')?;
+        let key = self.keys.iter().find(|k| k.id == kid)?;
+        if key.accept_until.is_some_and(|until| Utc::now() >= until) {
+            return None;
+        }
+        let sig = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(sig).ok()?;
+        mac(key, purpose, value).verify_slice(&sig).ok()?;
+        Some(value)
+    }
+}
+
+fn mac(key: &SigningKey, purpose: &str, value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key.secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(purpose.as_bytes());
+    mac.update(b"\0");
+    mac.update(value.as_bytes());
+    mac
+}
@@ -0,0 +1,89 @@
+use chrono::{Duration, Utc};
+
+use crate::error::{AppError, AppResult};
+
+/// Failed sign-ins allowed before the account is locked.
+const FREE_ATTEMPTS: i64 = 5;
+/// Lockout after the first failure past the free ones; each further failure doubles it.
+const BASE_LOCKOUT_SECS: i64 = 60;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+/// Failures this long after the previous one start the count again.
+const FAILURE_MEMORY_HOURS: i64 = 24;
+
+/// Attempts are counted per email, whether or not an account has it, so a lockout
+/// doesn't reveal which emails are registered.
+fn key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Refuse a sign-in while the email is locked out, even with the right password.
+pub fn check(conn: &rusqlite::Connection, email: &str) -> AppResult<()> {
+    let locked_until: Option<String> = match conn.query_row(
+        "SELECT locked_until FROM login_attempts WHERE email = ?1",
+        rusqlite::params![key(email)],
+        |row| row.get(0),
+    ) {
+        Ok(locked_until) => locked_until,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let remaining = locked_until
+        .and_then(|until| chrono::DateTime::parse_from_rfc3339(&until).ok())
+        .map(|until| (until.with_timezone(&Utc) - Utc::now()).num_seconds())
+        .filter(|secs| *secs > 0);
+    match remaining {
+        Some(secs) => Err(AppError::TooManyRequests(
+            format!("Too many failed sign-ins. Try again in {}.", describe(secs)),
+            secs as u64,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Count a failed sign-in, locking the email once it is past the free attempts.
+pub fn record_failure(conn: &rusqlite::Connection, email: &str) -> AppResult<()> {
+    let now = Utc::now();
+    let forget_before = (now - Duration::hours(FAILURE_MEMORY_HOURS))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    let email = key(email);
+    let previous: i64 = match conn.query_row(
+        "SELECT failed_count FROM login_attempts WHERE email = ?1 AND last_failed_at >= ?2",
+        rusqlite::params![email, forget_before],
+        |row| row.get(0),
+    ) {
+        Ok(count) => count,
+        Err(rusqlite::Error::QueryReturnedNoRows) => 0,
+        Err(e) => return Err(e.into()),
+    };
+
+    let failed_count = previous + 1;
+    let locked_until = (failed_count >= FREE_ATTEMPTS).then(|| {
+        let doublings = (failed_count - FREE_ATTEMPTS).min(10) as u32;
+        let secs = (BASE_LOCKOUT_SECS << doublings).min(MAX_LOCKOUT_SECS);
+        tracing::warn!("Sign-ins for {email} locked for {secs}s after {failed_count} failures");
+        (now + Duration::seconds(secs)).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+    });
+    conn.execute(
+        "INSERT INTO login_attempts (email, failed_count, last_failed_at, locked_until) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (email) DO UPDATE SET
+             failed_count = excluded.failed_count,
+             last_failed_at = excluded.last_failed_at,
+             locked_until = excluded.locked_until",
+        rusqlite::params![email, failed_count, now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(), locked_until],
+    )?;
+    Ok(())
+}
+
+/// Forget failures after a successful sign-in or password reset.
+pub fn clear(conn: &rusqlite::Connection, email: &str) -> AppResult<()> {
+    conn.execute("DELETE FROM login_attempts WHERE email = ?1", rusqlite::params![key(email)])?;
+    Ok(())
+}
+
+fn describe(secs: i64) -> String {
+    match secs {
+        ..=60 => "a minute".to_string(),
+        _ => format!("{} minutes", (secs + 59) / 60),
+    }
+}
@@ -1,45 +1,131 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::{Request, State},
+    extract::{ConnectInfo, Request, State},
+    http::{header, Method},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
 use axum_extra::extract::CookieJar;
+use axum_extra::extract::cookie::Cookie;
 
+use crate::config::Config;
 use crate::error::AppError;
-use crate::models::User;
+use crate::models::{Session, User};
 use crate::routes::AppState;
-use crate::auth::session;
+use crate::auth::{api_keys, session};
 
 pub const SESSION_COOKIE: &str = "opacore_session";
+/// Key ring purpose for session cookie signatures.
+pub const SESSION_PURPOSE: &str = "session";
+/// Readable by the web app, which echoes it in `CSRF_HEADER` on requests that change things.
+pub const CSRF_COOKIE: &str = "opacore_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+const CSRF_PURPOSE: &str = "csrf";
+
+/// The signed cookie that carries a new session's token.
+pub fn build_session_cookie(config: &Config, token: &str) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, config.session_keys.sign(SESSION_PURPOSE, token)))
+        .path("/")
+        .max_age(time::Duration::seconds(config.sessions.absolute.as_secs() as i64))
+        .http_only(true)
+        .secure(config.secure_cookies)
+        .same_site(axum_extra::extract::cookie::SameSite::Lax)
+        .build()
+}
+
+/// The CSRF token of a session: its id under the server's signature. Tied to the
+/// session, so a cookie planted from a sibling subdomain can't be made to match.
+pub fn build_csrf_cookie(config: &Config, session_id: &str) -> Cookie<'static> {
+    Cookie::build((CSRF_COOKIE, config.session_keys.sign(CSRF_PURPOSE, session_id)))
+        .path("/")
+        .max_age(time::Duration::seconds(config.sessions.absolute.as_secs() as i64))
+        .secure(config.secure_cookies)
+        .same_site(axum_extra::extract::cookie::SameSite::Lax)
+        .build()
+}
+
+/// Expire the session and CSRF cookies.
+pub fn remove_session_cookies(jar: CookieJar) -> CookieJar {
+    let expired = |name: &'static str| Cookie::build(name).path("/").max_age(time::Duration::ZERO).build();
+    jar.add(expired(SESSION_COOKIE)).add(expired(CSRF_COOKIE))
+}
 
+/// Authenticates with an API key sent as `Authorization: Bearer opk_...`, or else the
+/// session cookie. API key requests carry an `ApiKeyAuth` extension instead of a `Session`.
+///
+/// Cookie-authenticated requests other than GET and HEAD must send the session's CSRF
+/// token in `X-CSRF-Token`, matching the `opacore_csrf` cookie (double submit). Sessions
+/// without a valid CSRF cookie get one on their next request.
+///
+/// Either way the caller's `ClientInfo` goes in the request extensions too.
 pub async fn require_auth(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
     jar: CookieJar,
     mut request: Request,
     next: Next,
 ) -> Result<Response, AppError> {
-    let token = jar
-        .get(SESSION_COOKIE)
-        .map(|c| c.value().to_string())
+    let client = session::client_info(&state.config, request.headers(), peer);
+    request.extensions_mut().insert(client);
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string());
+    if let Some(token) = bearer {
+        let (key, user) = api_keys::authenticate(&state.db, &state.cache, &token)?;
+        if !user.email_verified {
+            return Err(AppError::Forbidden("Email not verified".to_string()));
+        }
+        api_keys::authorize(&key, request.method(), request.uri().path())?;
+        request.extensions_mut().insert(user);
+        request.extensions_mut().insert(key);
+        return Ok(next.run(request).await);
+    }
+
+    let cookie = jar.get(SESSION_COOKIE).ok_or(AppError::Unauthorized)?;
+    // Cookies signed by a retired key, or not at all, are treated as logged out
+    let token = state
+        .config
+        .session_keys
+        .verify(SESSION_PURPOSE, cookie.value())
         .ok_or(AppError::Unauthorized)?;
 
-    let (_session, user) = session::validate_session(&state.db, &token)?;
+    let (session, user) = session::validate_session(&state.db, &state.cache, &state.config.sessions, token)?;
+
+    let valid_csrf = |t: &str| state.config.session_keys.verify(CSRF_PURPOSE, t) == Some(session.id.as_str());
+    let csrf_cookie = jar.get(CSRF_COOKIE).map(|c| c.value());
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        let sent = request.headers().get(CSRF_HEADER).and_then(|v| v.to_str().ok());
+        if sent.is_none() || sent != csrf_cookie || !sent.is_some_and(valid_csrf) {
+            return Err(AppError::Forbidden("Missing or invalid CSRF token".to_string()));
+        }
+    }
+    let reissue_csrf = !csrf_cookie.is_some_and(valid_csrf);
 
     // Defense-in-depth: reject unverified users even if they somehow have a session
     if !user.email_verified {
         return Err(AppError::Forbidden("Email not verified".to_string()));
     }
 
+    let csrf_cookie = reissue_csrf.then(|| build_csrf_cookie(&state.config, &session.id));
     request.extensions_mut().insert(user);
-    Ok(next.run(request).await)
+    request.extensions_mut().insert(session);
+    let response = next.run(request).await;
+    Ok(match csrf_cookie {
+        Some(cookie) => (CookieJar::new().add(cookie), response).into_response(),
+        None => response,
+    })
 }
 
-pub trait RequestExt {
-    fn user(&self) -> &User;
-}
-
-impl RequestExt for axum::extract::Extension<User> {
-    fn user(&self) -> &User {
-        &self.0
+/// Runs after `require_auth` on the `/api/v1/admin` routes: only admins signed in with a
+/// session (not an API key) get through. Anyone else is told the route doesn't exist.
+pub async fn require_admin(request: Request, next: Next) -> Result<Response, AppError> {
+    let is_admin = request.extensions().get::<User>().is_some_and(|user| user.is_admin);
+    if !is_admin || request.extensions().get::<Session>().is_none() {
+        return Err(AppError::NotFound("Not found".to_string()));
     }
+    Ok(next.run(request).await)
 }
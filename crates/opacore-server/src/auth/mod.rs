@@ -1,4 +1,9 @@
+pub mod api_keys;
+pub mod keyring;
+pub mod lockout;
 pub mod middleware;
 pub mod password;
+pub mod policy;
 pub mod session;
 pub mod verification;
+pub mod webauthn;
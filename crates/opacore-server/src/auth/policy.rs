@@ -0,0 +1,210 @@
+use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
+
+/// What a request wants to do with a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    /// View the resource and anything derived from it (reports, exports).
+    Read,
+    /// Create, change or delete records inside it.
+    Write,
+    /// Delete or reconfigure the resource itself.
+    Manage,
+}
+
+/// A user's standing on a portfolio: `Owner` for its creator, otherwise their role in
+/// the organization it is shared with. The same roles apply within an organization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Accountant,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Accountant => "accountant",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(Role::Owner),
+            "admin" => Some(Role::Admin),
+            "accountant" => Some(Role::Accountant),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+
+    fn permits(self, access: Access) -> bool {
+        match (self, access) {
+            (Role::Owner | Role::Admin, _) => true,
+            // Bookkeepers record transactions and invoices but don't reconfigure anything
+            (Role::Accountant, Access::Read | Access::Write) => true,
+            (Role::Viewer, Access::Read) => true,
+            _ => false,
+        }
+    }
+}
+
+fn portfolio_role(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    portfolio_id: &str,
+) -> AppResult<Option<Role>> {
+    let Some(owner) = cache.portfolio_owner(conn, portfolio_id)? else {
+        return Ok(None);
+    };
+    if owner.user_id == user_id {
+        return Ok(Some(Role::Owner));
+    }
+    let Some(organization_id) = owner.organization_id else {
+        return Ok(None);
+    };
+    Ok(cache
+        .member_role(conn, &organization_id, user_id)?
+        .as_deref()
+        .and_then(Role::parse))
+}
+
+/// Members can see what they aren't allowed to change, so that is reported as forbidden;
+/// anyone else gets `not_found`.
+fn require(role: Option<Role>, access: Access, not_found: impl FnOnce() -> AppError) -> AppResult<()> {
+    match role {
+        Some(role) if role.permits(access) => Ok(()),
+        Some(role) => Err(AppError::Forbidden(format!("The {} role doesn't allow this", role.name()))),
+        None => Err(not_found()),
+    }
+}
+
+/// Require `access` to a portfolio. Missing and forbidden portfolios are both reported as
+/// not found, so IDs can't be probed.
+pub fn portfolio(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    portfolio_id: &str,
+    access: Access,
+) -> AppResult<()> {
+    require(portfolio_role(cache, conn, user_id, portfolio_id)?, access, || {
+        AppError::NotFound("Portfolio not found".into())
+    })
+}
+
+/// The user's role on a portfolio they can at least read.
+pub fn portfolio_role_of(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    portfolio_id: &str,
+) -> AppResult<Role> {
+    portfolio_role(cache, conn, user_id, portfolio_id)?.ok_or_else(|| AppError::NotFound("Portfolio not found".into()))
+}
+
+/// Require at least `minimum` in an organization. Non-members get not found.
+pub fn organization(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    organization_id: &str,
+    minimum: Role,
+) -> AppResult<Role> {
+    match cache.member_role(conn, organization_id, user_id)?.as_deref().and_then(Role::parse) {
+        Some(role) if role >= minimum => Ok(role),
+        Some(role) => Err(AppError::Forbidden(format!("The {} role doesn't allow this", role.name()))),
+        None => Err(AppError::NotFound("Organization not found".into())),
+    }
+}
+
+/// Require `access` to a wallet in a given portfolio.
+pub fn wallet(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    portfolio_id: &str,
+    wallet_id: &str,
+    access: Access,
+) -> AppResult<()> {
+    let not_found = || AppError::NotFound("Wallet not found".into());
+    require(portfolio_role(cache, conn, user_id, portfolio_id)?, access, not_found)?;
+
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM wallets WHERE id = ?1 AND portfolio_id = ?2)",
+        rusqlite::params![wallet_id, portfolio_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(not_found());
+    }
+    Ok(())
+}
+
+/// Require `access` to a wallet addressed by ID alone. Returns its portfolio.
+pub fn wallet_by_id(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    wallet_id: &str,
+    access: Access,
+) -> AppResult<String> {
+    let portfolio_id = parent_portfolio(conn, "SELECT portfolio_id FROM wallets WHERE id = ?1", wallet_id)
+        .map_err(|e| not_found_as(e, "Wallet not found"))?;
+    wallet(cache, conn, user_id, &portfolio_id, wallet_id, access)?;
+    Ok(portfolio_id)
+}
+
+/// Require `access` to a transaction addressed by ID alone. Returns its portfolio.
+pub fn transaction(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    transaction_id: &str,
+    access: Access,
+) -> AppResult<String> {
+    let not_found = || AppError::NotFound("Transaction not found".into());
+    let portfolio_id = parent_portfolio(conn, "SELECT portfolio_id FROM transactions WHERE id = ?1", transaction_id)
+        .map_err(|e| not_found_as(e, "Transaction not found"))?;
+    require(portfolio_role(cache, conn, user_id, &portfolio_id)?, access, not_found)?;
+    Ok(portfolio_id)
+}
+
+/// Require `access` to an invoice in a given portfolio.
+pub fn invoice(
+    cache: &AppCache,
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    portfolio_id: &str,
+    invoice_id: &str,
+    access: Access,
+) -> AppResult<()> {
+    let not_found = || AppError::NotFound("Invoice not found".into());
+    require(portfolio_role(cache, conn, user_id, portfolio_id)?, access, not_found)?;
+
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM invoices WHERE id = ?1 AND portfolio_id = ?2)",
+        rusqlite::params![invoice_id, portfolio_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(not_found());
+    }
+    Ok(())
+}
+
+fn parent_portfolio(conn: &rusqlite::Connection, sql: &str, id: &str) -> AppResult<String> {
+    Ok(conn.query_row(sql, rusqlite::params![id], |row| row.get(0))?)
+}
+
+fn not_found_as(err: AppError, message: &str) -> AppError {
+    match err {
+        AppError::Database(rusqlite::Error::QueryReturnedNoRows) => AppError::NotFound(message.into()),
+        e => e,
+    }
+}
@@ -1,14 +1,59 @@
+use std::net::SocketAddr;
+
+use axum::http::{header, HeaderMap};
 use chrono::{Duration, Utc};
 use uuid::Uuid;
 
+use crate::config::{Config, SessionLifetimes};
 use crate::db::DbPool;
 use crate::error::{AppError, AppResult};
 use crate::models::{Session, User};
+use crate::services::cache::AppCache;
+
+/// `last_used_at` (and with it the idle deadline) is only rewritten once it is this
+/// stale, so most requests don't write.
+const LAST_USED_RESOLUTION_MINUTES: i64 = 5;
+const MAX_USER_AGENT_CHARS: usize = 512;
 
-const SESSION_DURATION_DAYS: i64 = 30;
+/// Disabled accounts can't sign in or use the API until an admin enables them again.
+pub fn require_enabled(user: &User) -> AppResult<()> {
+    if user.disabled_at.is_some() {
+        return Err(AppError::Forbidden("This account has been disabled".to_string()));
+    }
+    Ok(())
+}
+
+/// Where a sign-in came from, as recorded on its session. `require_auth` also puts the
+/// caller's in the request extensions, for the audit log.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+}
+
+/// The client's IP is the peer address, or with `TRUST_PROXY_HEADERS` the first
+/// `X-Forwarded-For` entry (set by the proxy in front of the app).
+pub fn client_info(config: &Config, headers: &HeaderMap, peer: SocketAddr) -> ClientInfo {
+    let forwarded = config
+        .trust_proxy_headers
+        .then(|| headers.get("x-forwarded-for")?.to_str().ok()?.split(',').next())
+        .flatten()
+        .map(str::trim)
+        .filter(|ip| ip.parse::<std::net::IpAddr>().is_ok());
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .map(|ua| ua.trim().chars().take(MAX_USER_AGENT_CHARS).collect::<String>())
+        .filter(|ua| !ua.is_empty());
+    ClientInfo {
+        ip_address: forwarded.map_or_else(|| peer.ip().to_string(), str::to_string),
+        user_agent,
+    }
+}
 
 pub fn create_session(
     pool: &DbPool,
+    lifetimes: &SessionLifetimes,
     user_id: &str,
     ip_address: Option<&str>,
     user_agent: Option<&str>,
@@ -16,7 +61,7 @@ pub fn create_session(
     let conn = pool.get()?;
     let id = Uuid::new_v4().to_string();
     let token = generate_token();
-    let expires_at = (Utc::now() + Duration::days(SESSION_DURATION_DAYS))
+    let expires_at = (Utc::now() + lifetimes.idle.min(lifetimes.absolute))
         .format("%Y-%m-%dT%H:%M:%S%.3fZ")
         .to_string();
 
@@ -33,48 +78,73 @@ pub fn create_session(
         ip_address: ip_address.map(|s| s.to_string()),
         user_agent: user_agent.map(|s| s.to_string()),
         created_at: Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        last_used_at: None,
     })
 }
 
-pub fn validate_session(pool: &DbPool, token: &str) -> AppResult<(Session, User)> {
+/// Look up a live session by token. The session row is always read from the
+/// database (so logout takes effect immediately); the user comes from the cache.
+/// Marks the session as used when its `last_used_at` is stale, which also moves its
+/// expiry to the idle timeout from now (capped at its absolute lifetime).
+pub fn validate_session(
+    pool: &DbPool,
+    cache: &AppCache,
+    lifetimes: &SessionLifetimes,
+    token: &str,
+) -> AppResult<(Session, User)> {
     let conn = pool.get()?;
     let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
 
-    let mut stmt = conn.prepare(
-        "SELECT s.id, s.user_id, s.token, s.expires_at, s.ip_address, s.user_agent, s.created_at,
-                u.id, u.email, u.name, u.password_hash, u.default_currency, u.email_verified, u.created_at, u.updated_at
-         FROM sessions s
-         JOIN users u ON u.id = s.user_id
-         WHERE s.token = ?1 AND s.expires_at > ?2",
-    )?;
+    let result = conn.query_row(
+        "SELECT id, user_id, token, expires_at, ip_address, user_agent, created_at, last_used_at
+         FROM sessions
+         WHERE token = ?1 AND expires_at > ?2",
+        rusqlite::params![token, now],
+        |row| {
+            Ok(Session {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                token: row.get(2)?,
+                expires_at: row.get(3)?,
+                ip_address: row.get(4)?,
+                user_agent: row.get(5)?,
+                created_at: row.get(6)?,
+                last_used_at: row.get(7)?,
+            })
+        },
+    );
+
+    let mut session = match result {
+        Ok(session) => session,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(AppError::Unauthorized),
+        Err(e) => return Err(AppError::Database(e)),
+    };
 
-    let result = stmt.query_row(rusqlite::params![token, now], |row| {
-        let session = Session {
-            id: row.get(0)?,
-            user_id: row.get(1)?,
-            token: row.get(2)?,
-            expires_at: row.get(3)?,
-            ip_address: row.get(4)?,
-            user_agent: row.get(5)?,
-            created_at: row.get(6)?,
-        };
-        let user = User {
-            id: row.get(7)?,
-            email: row.get(8)?,
-            name: row.get(9)?,
-            password_hash: row.get(10)?,
-            default_currency: row.get(11)?,
-            email_verified: row.get::<_, i32>(12)? != 0,
-            created_at: row.get(13)?,
-            updated_at: row.get(14)?,
-        };
-        Ok((session, user))
-    });
-
-    match result {
-        Ok(pair) => Ok(pair),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Err(AppError::Unauthorized),
-        Err(e) => Err(AppError::Database(e)),
+    let stale_before = (Utc::now() - Duration::minutes(LAST_USED_RESOLUTION_MINUTES))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    if session.last_used_at.as_ref().is_none_or(|used| *used < stale_before) {
+        let idle_deadline = Utc::now() + lifetimes.idle;
+        let expires_at = chrono::DateTime::parse_from_rfc3339(&session.created_at)
+            .map(|created| (created.with_timezone(&Utc) + lifetimes.absolute).min(idle_deadline))
+            .unwrap_or(idle_deadline)
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string();
+        conn.execute(
+            "UPDATE sessions SET last_used_at = ?1, expires_at = ?2 WHERE id = ?3",
+            rusqlite::params![now, expires_at, session.id],
+        )?;
+        session.last_used_at = Some(now);
+        session.expires_at = expires_at;
+    }
+
+    match cache.user(&conn, &session.user_id) {
+        Ok(user) => {
+            require_enabled(&user)?;
+            Ok((session, user))
+        }
+        Err(AppError::Database(rusqlite::Error::QueryReturnedNoRows)) => Err(AppError::Unauthorized),
+        Err(e) => Err(e),
     }
 }
 
@@ -84,10 +154,34 @@ pub fn delete_session(pool: &DbPool, token: &str) -> AppResult<()> {
     Ok(())
 }
 
-pub fn delete_user_sessions(pool: &DbPool, user_id: &str) -> AppResult<()> {
+/// Sign the user out everywhere but the session `keep_session_id`.
+pub fn delete_other_sessions(pool: &DbPool, user_id: &str, keep_session_id: &str) -> AppResult<usize> {
     let conn = pool.get()?;
-    conn.execute("DELETE FROM sessions WHERE user_id = ?1", rusqlite::params![user_id])?;
-    Ok(())
+    let deleted = conn.execute(
+        "DELETE FROM sessions WHERE user_id = ?1 AND id != ?2",
+        rusqlite::params![user_id, keep_session_id],
+    )?;
+    Ok(deleted)
+}
+
+/// Delete sessions that have expired, whether idle or past their absolute lifetime.
+pub fn purge_expired(pool: &DbPool) -> AppResult<usize> {
+    let conn = pool.get()?;
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let deleted = conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", rusqlite::params![now])?;
+    Ok(deleted)
+}
+
+pub async fn run_session_purger(pool: DbPool, interval: std::time::Duration) {
+    tracing::info!("Session purger started (interval: {}s)", interval.as_secs());
+    loop {
+        match purge_expired(&pool) {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Purged {n} expired sessions"),
+            Err(e) => tracing::warn!("Session purge failed: {e}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
 }
 
 fn generate_token() -> String {
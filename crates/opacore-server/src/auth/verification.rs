@@ -119,6 +119,56 @@ pub fn validate_and_consume_reset_token(pool: &DbPool, token: &str) -> AppResult
     Ok(user_id)
 }
 
+/// Create a token confirming `new_email` for a user. Replaces any pending change.
+pub fn create_email_change_token(pool: &DbPool, user_id: &str, new_email: &str) -> AppResult<String> {
+    let conn = pool.get()?;
+
+    conn.execute(
+        "DELETE FROM email_change_tokens WHERE user_id = ?1",
+        rusqlite::params![user_id],
+    )?;
+
+    let id = Uuid::new_v4().to_string();
+    let token = generate_token();
+    let expires_at = (Utc::now() + Duration::hours(24))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+
+    conn.execute(
+        "INSERT INTO email_change_tokens (id, user_id, new_email, token, expires_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![id, user_id, new_email, token, expires_at],
+    )?;
+
+    Ok(token)
+}
+
+/// Validate an email change token. Returns the user_id and the confirmed new email,
+/// then deletes the token.
+pub fn validate_and_consume_email_change_token(pool: &DbPool, token: &str) -> AppResult<(String, String)> {
+    let conn = pool.get()?;
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    let (user_id, new_email): (String, String) = conn
+        .query_row(
+            "SELECT user_id, new_email FROM email_change_tokens WHERE token = ?1 AND expires_at > ?2",
+            rusqlite::params![token, now],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                AppError::BadRequest("Invalid or expired confirmation token".to_string())
+            }
+            _ => AppError::Database(e),
+        })?;
+
+    conn.execute(
+        "DELETE FROM email_change_tokens WHERE user_id = ?1 OR expires_at < ?2",
+        rusqlite::params![user_id, now],
+    )?;
+
+    Ok((user_id, new_email))
+}
+
 fn generate_token() -> String {
     use base64::Engine;
     let mut bytes = [0u8; 32];
@@ -0,0 +1,548 @@
+//! Passkey (WebAuthn) ceremonies. Browsers send their results as the JSON encoding of a
+//! `PublicKeyCredential` (`credential.toJSON()`), which includes the new key as a
+//! SubjectPublicKeyInfo, so no CBOR needs decoding. Attestation isn't requested: a
+//! passkey is trusted because the signed-in user registered it.
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use ring::signature;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::WebAuthnConfig;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+
+/// How long the browser has to complete a ceremony.
+pub const CHALLENGE_TTL_SECS: i64 = 5 * 60;
+
+/// COSE algorithm identifiers, in order of preference.
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [ES256, EDDSA, RS256];
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// base64url as browsers write it: unpadded, though padded input is accepted.
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Copy)]
+pub enum Ceremony {
+    Register,
+    Authenticate,
+}
+
+impl Ceremony {
+    fn name(self) -> &'static str {
+        match self {
+            Ceremony::Register => "register",
+            Ceremony::Authenticate => "authenticate",
+        }
+    }
+
+    /// The `type` the browser puts in the client data.
+    fn client_data_type(self) -> &'static str {
+        match self {
+            Ceremony::Register => "webauthn.create",
+            Ceremony::Authenticate => "webauthn.get",
+        }
+    }
+}
+
+/// Result of `navigator.credentials.create()`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    /// Missing when the browser can't express the key's algorithm as SPKI.
+    pub public_key: Option<String>,
+    pub public_key_algorithm: i64,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// Result of `navigator.credentials.get()`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// A passkey that passed registration, ready to store.
+#[derive(Debug)]
+pub struct NewPasskey {
+    /// base64url, unpadded.
+    pub credential_id: String,
+    /// SubjectPublicKeyInfo (DER).
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+    pub transports: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+struct AuthenticatorData {
+    sign_count: u32,
+    /// Present when registering.
+    credential_id: Option<Vec<u8>>,
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    BASE64URL.encode(bytes)
+}
+
+fn decode(field: &str, value: &str) -> AppResult<Vec<u8>> {
+    BASE64URL
+        .decode(value)
+        .map_err(|_| AppError::BadRequest(format!("{field} is not valid base64url")))
+}
+
+/// The WebAuthn user handle for a user: their id, base64url-encoded.
+pub fn user_handle(user_id: &str) -> String {
+    encode(user_id.as_bytes())
+}
+
+/// Start a ceremony. Registration challenges are tied to the user registering.
+pub fn create_challenge(pool: &DbPool, ceremony: Ceremony, user_id: Option<&str>) -> AppResult<String> {
+    let conn = pool.get()?;
+    let now = Utc::now();
+
+    // Drop abandoned ceremonies while we're here
+    conn.execute(
+        "DELETE FROM webauthn_challenges WHERE expires_at < ?1",
+        rusqlite::params![now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()],
+    )?;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let challenge = encode(&bytes);
+    let expires_at = (now + Duration::seconds(CHALLENGE_TTL_SECS))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    conn.execute(
+        "INSERT INTO webauthn_challenges (challenge, ceremony, user_id, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![challenge, ceremony.name(), user_id, expires_at],
+    )?;
+    Ok(challenge)
+}
+
+/// Use up a challenge, returning the user it was issued to.
+fn consume_challenge(conn: &rusqlite::Connection, ceremony: Ceremony, challenge: &str) -> AppResult<Option<String>> {
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.query_row(
+        "DELETE FROM webauthn_challenges WHERE challenge = ?1 AND ceremony = ?2 AND expires_at > ?3 RETURNING user_id",
+        rusqlite::params![challenge, ceremony.name(), now],
+        |row| row.get(0),
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => {
+            AppError::BadRequest("Passkey challenge is invalid or expired, please try again".to_string())
+        }
+        _ => AppError::Database(e),
+    })
+}
+
+/// Check the client data of a ceremony and return its challenge.
+fn verify_client_data(config: &WebAuthnConfig, ceremony: Ceremony, client_data_json: &[u8]) -> AppResult<String> {
+    let client: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| AppError::BadRequest("clientDataJSON is malformed".to_string()))?;
+    if client.kind != ceremony.client_data_type() {
+        return Err(AppError::BadRequest(format!("Expected a {} response", ceremony.client_data_type())));
+    }
+    if client.origin != config.origin || client.cross_origin {
+        tracing::warn!("Passkey {} from unexpected origin {}", ceremony.name(), client.origin);
+        return Err(AppError::BadRequest("Passkey was used from an unexpected origin".to_string()));
+    }
+    Ok(client.challenge)
+}
+
+/// Parse authenticator data, checking it is for our relying party and that the user
+/// was verified (PIN, biometrics), since a passkey replaces the password.
+fn parse_authenticator_data(config: &WebAuthnConfig, data: &[u8]) -> AppResult<AuthenticatorData> {
+    let malformed = || AppError::BadRequest("authenticatorData is malformed".to_string());
+    if data.len() < 37 {
+        return Err(malformed());
+    }
+    if data[..32] != Sha256::digest(config.rp_id.as_bytes())[..] {
+        return Err(AppError::BadRequest("Passkey belongs to a different site".to_string()));
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 || flags & FLAG_USER_VERIFIED == 0 {
+        return Err(AppError::BadRequest("The authenticator did not verify the user".to_string()));
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    // Attested credential data: AAGUID (16), credential id length (2), credential id, key
+    let credential_id = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        let rest = data.get(37 + 16..).ok_or_else(malformed)?;
+        let len = u16::from_be_bytes([*rest.first().ok_or_else(malformed)?, *rest.get(1).ok_or_else(malformed)?]) as usize;
+        Some(rest.get(2..2 + len).ok_or_else(malformed)?.to_vec())
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData { sign_count, credential_id })
+}
+
+/// Check a `create()` result against the challenge issued to `user_id`.
+pub fn verify_registration(
+    pool: &DbPool,
+    config: &WebAuthnConfig,
+    user_id: &str,
+    credential: &RegistrationCredential,
+) -> AppResult<NewPasskey> {
+    if credential.kind != "public-key" {
+        return Err(AppError::BadRequest("Expected a public-key credential".to_string()));
+    }
+    let response = &credential.response;
+    let client_data = decode("clientDataJSON", &response.client_data_json)?;
+    let challenge = verify_client_data(config, Ceremony::Register, &client_data)?;
+    {
+        let conn = pool.get()?;
+        if consume_challenge(&conn, Ceremony::Register, &challenge)?.as_deref() != Some(user_id) {
+            return Err(AppError::BadRequest("Passkey challenge is invalid or expired, please try again".to_string()));
+        }
+    }
+
+    let auth_data = parse_authenticator_data(config, &decode("authenticatorData", &response.authenticator_data)?)?;
+    let raw_id = decode("rawId", &credential.raw_id)?;
+    if auth_data.credential_id.as_deref() != Some(&raw_id[..]) {
+        return Err(AppError::BadRequest("authenticatorData does not match the credential".to_string()));
+    }
+
+    if !SUPPORTED_ALGORITHMS.contains(&response.public_key_algorithm) {
+        return Err(AppError::BadRequest("This passkey uses an unsupported algorithm".to_string()));
+    }
+    let public_key = response
+        .public_key
+        .as_deref()
+        .ok_or_else(|| AppError::BadRequest("The browser did not report the passkey's public key".to_string()))?;
+    let public_key = decode("publicKey", public_key)?;
+    if !valid_key(response.public_key_algorithm, &public_key) {
+        return Err(AppError::BadRequest("publicKey does not match publicKeyAlgorithm".to_string()));
+    }
+
+    Ok(NewPasskey {
+        credential_id: encode(&raw_id),
+        public_key,
+        algorithm: response.public_key_algorithm,
+        sign_count: auth_data.sign_count,
+        transports: response.transports.clone(),
+    })
+}
+
+/// Check a `get()` result against an outstanding sign-in challenge and the stored
+/// passkey, and return the user it signs in.
+pub fn verify_authentication(
+    pool: &DbPool,
+    config: &WebAuthnConfig,
+    credential: &AuthenticationCredential,
+) -> AppResult<String> {
+    if credential.kind != "public-key" {
+        return Err(AppError::BadRequest("Expected a public-key credential".to_string()));
+    }
+    let response = &credential.response;
+    let client_data = decode("clientDataJSON", &response.client_data_json)?;
+    let challenge = verify_client_data(config, Ceremony::Authenticate, &client_data)?;
+
+    let conn = pool.get()?;
+    consume_challenge(&conn, Ceremony::Authenticate, &challenge)?;
+
+    let credential_id = encode(&decode("rawId", &credential.raw_id)?);
+    let stored = conn.query_row(
+        "SELECT id, user_id, public_key, algorithm, sign_count FROM webauthn_credentials WHERE credential_id = ?1",
+        rusqlite::params![credential_id],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, u32>(4)?,
+            ))
+        },
+    );
+    let (passkey_id, user_id, public_key, algorithm, stored_count) = match stored {
+        Ok(stored) => stored,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Err(AppError::Unauthorized),
+        Err(e) => return Err(AppError::Database(e)),
+    };
+    if let Some(handle) = &response.user_handle {
+        if decode("userHandle", handle)? != user_id.as_bytes() {
+            return Err(AppError::Unauthorized);
+        }
+    }
+
+    let auth_data_bytes = decode("authenticatorData", &response.authenticator_data)?;
+    let auth_data = parse_authenticator_data(config, &auth_data_bytes)?;
+
+    let mut signed = auth_data_bytes;
+    signed.extend_from_slice(&Sha256::digest(&client_data));
+    let signature = decode("signature", &response.signature)?;
+    if !verify_signature(algorithm, &public_key, &signed, &signature) {
+        return Err(AppError::Unauthorized);
+    }
+
+    // Authenticators that count signatures only count up; going back means a copy of the key
+    if (stored_count != 0 || auth_data.sign_count != 0) && auth_data.sign_count <= stored_count {
+        tracing::warn!(
+            "Passkey {passkey_id} of user {user_id} reused signature count {} (stored {stored_count}), possibly cloned",
+            auth_data.sign_count
+        );
+        return Err(AppError::Unauthorized);
+    }
+
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE webauthn_credentials SET sign_count = ?1, last_used_at = ?2 WHERE id = ?3",
+        rusqlite::params![auth_data.sign_count, now, passkey_id],
+    )?;
+    Ok(user_id)
+}
+
+fn valid_key(algorithm: i64, spki: &[u8]) -> bool {
+    match (algorithm, spki_key(spki)) {
+        // Uncompressed P-256 point
+        (ES256, Some(key)) => key.len() == 65 && key[0] == 0x04,
+        (EDDSA, Some(key)) => key.len() == 32,
+        // RSAPublicKey, a DER sequence of modulus and exponent
+        (RS256, Some(key)) => matches!(der_element(key), Some((0x30, _, []))),
+        _ => false,
+    }
+}
+
+fn verify_signature(algorithm: i64, spki: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    let Some(key) = spki_key(spki) else { return false };
+    let verifier: &dyn signature::VerificationAlgorithm = match algorithm {
+        ES256 => &signature::ECDSA_P256_SHA256_ASN1,
+        EDDSA => &signature::ED25519,
+        RS256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        _ => return false,
+    };
+    signature::UnparsedPublicKey::new(verifier, key).verify(message, sig).is_ok()
+}
+
+/// The key inside a SubjectPublicKeyInfo: the contents of its BIT STRING.
+fn spki_key(spki: &[u8]) -> Option<&[u8]> {
+    let (0x30, body, []) = der_element(spki)? else { return None };
+    let (0x30, _algorithm, rest) = der_element(body)? else { return None };
+    let (0x03, bits, []) = der_element(rest)? else { return None };
+    // The first byte counts unused bits in the last one, which keys never have
+    match bits.split_first()? {
+        (0, key) => Some(key),
+        _ => None,
+    }
+}
+
+/// One DER element at the start of `input`: its tag, its contents and what follows it.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n].iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ES256 sign-in to opacore.com from https://app.opacore.com, signature count 2
+    const CREDENTIAL_ID: &str = "ZGVmZ2hpamtsbW5vcHFycw";
+    const PUBLIC_KEY: &str = "MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEcl9MaEVhG6tYxUKsumqfSyZoL3GRU9yZPiJKUnlG9uzjIZ7SmYibj3CeYswPFm7M64VzERr79HrvU2g5s6J5CA";
+    const CHALLENGE: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8";
+    const CLIENT_DATA: &str = "eyJ0eXBlIjoid2ViYXV0aG4uZ2V0IiwiY2hhbGxlbmdlIjoiQUFFQ0F3UUZCZ2NJQ1FvTERBME9EeEFSRWhNVUZSWVhHQmthR3h3ZEhoOCIsIm9yaWdpbiI6Imh0dHBzOi8vYXBwLm9wYWNvcmUuY29tIiwiY3Jvc3NPcmlnaW4iOmZhbHNlfQ";
+    const AUTHENTICATOR_DATA: &str = "s7CmfPRcB8gCgFHxjHy9uphZl-W6dWUF4YaBX39shzQFAAAAAg";
+    const SIGNATURE: &str = "MEUCIQD-GTVTarJZ0Td6C5CTYsajUQzA561fDodrjTsBttXJIAIgbFkgkme4wf4OZGSOO4OayO-rLFIyG38udNqjG2NRcn0";
+    const USER_ID: &str = "passkey-user";
+
+    /// A database holding the passkey with `sign_count` and an open sign-in challenge,
+    /// removed again when dropped.
+    struct TestDb {
+        pool: DbPool,
+        path: std::path::PathBuf,
+    }
+
+    impl TestDb {
+        fn new(sign_count: u32) -> Self {
+            let path = std::env::temp_dir().join(format!("opacore-webauthn-{}.sqlite", uuid::Uuid::new_v4()));
+            let pool = crate::db::create_pool(path.to_str().unwrap());
+            let expires_at = (Utc::now() + Duration::seconds(CHALLENGE_TTL_SECS)).format("%Y-%m-%dT%H:%M:%S%.3fZ");
+            pool.get()
+                .unwrap()
+                .execute_batch(&format!(
+                    "INSERT INTO users (id, email, name, password_hash) VALUES ('{USER_ID}', 'passkey@example.com', 'Passkey', '');
+                     INSERT INTO webauthn_credentials (id, user_id, credential_id, public_key, algorithm, sign_count, name)
+                     VALUES ('passkey-1', '{USER_ID}', '{CREDENTIAL_ID}', X'{}', {ES256}, {sign_count}, 'Laptop');
+                     INSERT INTO webauthn_challenges (challenge, ceremony, expires_at)
+                     VALUES ('{CHALLENGE}', 'authenticate', '{expires_at}');",
+                    hex::encode(decode("publicKey", PUBLIC_KEY).unwrap()),
+                ))
+                .unwrap();
+            Self { pool, path }
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{suffix}", self.path.display()));
+            }
+        }
+    }
+
+    fn config() -> WebAuthnConfig {
+        WebAuthnConfig {
+            rp_id: "opacore.com".to_string(),
+            rp_name: "Opacore".to_string(),
+            origin: "https://app.opacore.com".to_string(),
+        }
+    }
+
+    fn credential() -> AuthenticationCredential {
+        AuthenticationCredential {
+            raw_id: CREDENTIAL_ID.to_string(),
+            kind: "public-key".to_string(),
+            response: AssertionResponse {
+                client_data_json: CLIENT_DATA.to_string(),
+                authenticator_data: AUTHENTICATOR_DATA.to_string(),
+                signature: SIGNATURE.to_string(),
+                user_handle: Some(user_handle(USER_ID)),
+            },
+        }
+    }
+
+    fn bad_request(result: AppResult<String>) -> String {
+        match result {
+            Err(AppError::BadRequest(message)) => message,
+            other => panic!("expected a bad request, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_assertion() {
+        let db = TestDb::new(1);
+        assert_eq!(verify_authentication(&db.pool, &config(), &credential()).unwrap(), USER_ID);
+        let stored: u32 = db
+            .pool
+            .get()
+            .unwrap()
+            .query_row("SELECT sign_count FROM webauthn_credentials WHERE id = 'passkey-1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, 2);
+    }
+
+    #[test]
+    fn rejects_another_origin() {
+        let db = TestDb::new(1);
+        let config = WebAuthnConfig { origin: "https://opacore.example".to_string(), ..config() };
+        let message = bad_request(verify_authentication(&db.pool, &config, &credential()));
+        assert_eq!(message, "Passkey was used from an unexpected origin");
+    }
+
+    #[test]
+    fn rejects_another_rp_id() {
+        let db = TestDb::new(1);
+        let config = WebAuthnConfig { rp_id: "opacore.example".to_string(), ..config() };
+        let message = bad_request(verify_authentication(&db.pool, &config, &credential()));
+        assert_eq!(message, "Passkey belongs to a different site");
+    }
+
+    #[test]
+    fn rejects_a_user_who_was_not_present() {
+        let db = TestDb::new(1);
+        let mut data = decode("authenticatorData", AUTHENTICATOR_DATA).unwrap();
+        data[32] &= !FLAG_USER_PRESENT;
+        let mut credential = credential();
+        credential.response.authenticator_data = encode(&data);
+        let message = bad_request(verify_authentication(&db.pool, &config(), &credential));
+        assert_eq!(message, "The authenticator did not verify the user");
+    }
+
+    #[test]
+    fn rejects_a_signature_count_that_does_not_go_up() {
+        for stored in [2, 9] {
+            let db = TestDb::new(stored);
+            let result = verify_authentication(&db.pool, &config(), &credential());
+            assert!(matches!(result, Err(AppError::Unauthorized)), "stored count {stored}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let db = TestDb::new(1);
+        let mut signature = decode("signature", SIGNATURE).unwrap();
+        *signature.last_mut().unwrap() ^= 0x01;
+        let mut credential = credential();
+        credential.response.signature = encode(&signature);
+        let result = verify_authentication(&db.pool, &config(), &credential);
+        assert!(matches!(result, Err(AppError::Unauthorized)), "{result:?}");
+    }
+
+    #[test]
+    fn rejects_data_the_signature_does_not_cover() {
+        let db = TestDb::new(1);
+        let mut data = decode("authenticatorData", AUTHENTICATOR_DATA).unwrap();
+        data[36] = 3;
+        let mut credential = credential();
+        credential.response.authenticator_data = encode(&data);
+        let result = verify_authentication(&db.pool, &config(), &credential);
+        assert!(matches!(result, Err(AppError::Unauthorized)), "{result:?}");
+    }
+}
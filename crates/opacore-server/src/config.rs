@@ -1,26 +1,295 @@
 use std::env;
+use std::time::Duration;
+
+use crate::auth::keyring::KeyRing;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub server_port: u16,
     pub sqlite_path: String,
     pub bdk_wallets_dir: String,
-    pub session_secret: String,
-    pub esplora_url: String,
+    /// Where finished account exports wait to be downloaded.
+    pub exports_dir: String,
+    /// How long emailed download links stay valid.
+    pub download_link_ttl_hours: u64,
+    /// Largest account export accepted for restoring, in bytes.
+    pub account_import_max_bytes: usize,
+    /// Where transaction attachments (receipts, invoices) are kept.
+    pub attachment_storage: AttachmentStorage,
+    /// Largest attachment accepted, in bytes.
+    pub attachment_max_bytes: usize,
+    /// Keys for signing session cookies; see `SESSION_SECRETS`.
+    pub session_keys: KeyRing,
+    pub sessions: SessionLifetimes,
+    /// Esplora backends per network, in failover priority order.
+    pub esplora_urls: Vec<String>,
+    pub esplora_testnet_urls: Vec<String>,
+    pub esplora_signet_urls: Vec<String>,
+    pub esplora_regtest_urls: Vec<String>,
     pub coingecko_api_url: String,
+    /// How long a fetched BTC spot price is reused before asking the provider again.
+    pub current_price_ttl: Duration,
+    /// Exchange API bases used by exchange connections.
+    pub kraken_api_url: String,
+    pub coinbase_api_url: String,
+    /// mempool.space-compatible WebSocket for instant invoice payment detection; polling only if unset.
+    pub mempool_ws_url: Option<String>,
+    /// Confirmations an invoice payment needs before the invoice is paid, unless the
+    /// invoice sets its own `min_confirmations`.
+    pub invoice_min_confirmations: u64,
+    /// How long a fiat-denominated invoice's sat amount holds before the payment page requotes it.
+    pub invoice_rate_validity_secs: u64,
+    /// Node that issues BOLT11s for invoices; on-chain only if unset.
+    pub lightning: Option<LightningConfig>,
     pub cors_origin: String,
     pub secure_cookies: bool,
+    /// Take the client IP recorded on sessions from `X-Forwarded-For`, when behind a proxy.
+    pub trust_proxy_headers: bool,
     pub resend_api_key: Option<String>,
     pub admin_email: Option<String>,
     pub from_email: String,
     pub app_url: String,
+    /// Relying party that passkeys are registered with.
+    pub webauthn: WebAuthnConfig,
     pub stripe_secret_key: Option<String>,
     pub stripe_webhook_secret: Option<String>,
     pub stripe_price_id: Option<String>,
+    /// Default per-user quotas; individual users can be overridden in `user_quotas`.
+    pub quotas: QuotaLimits,
+    /// OTLP/HTTP collector base URL (e.g. http://localhost:4318); spans are only exported if set.
+    pub otlp_endpoint: Option<String>,
+    pub otel_service_name: String,
+    /// Deadline for producing a response; sync and bulk backfill routes are exempt.
+    pub request_timeout_secs: u64,
+    /// Cadence of the background tasks.
+    pub intervals: TaskIntervals,
+}
+
+#[derive(Debug, Clone)]
+pub enum AttachmentStorage {
+    /// Files in a local directory.
+    Local { dir: String },
+    /// An S3-compatible bucket (AWS S3, MinIO, R2, ...), addressed path-style.
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.us-east-1.amazonaws.com` or `http://localhost:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl AttachmentStorage {
+    fn from_env() -> Self {
+        let required = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or_else(|| panic!("{name} must be set when ATTACHMENT_STORAGE=s3"))
+        };
+        match env::var("ATTACHMENT_STORAGE").unwrap_or_default().trim() {
+            "" | "local" => AttachmentStorage::Local {
+                dir: env::var("ATTACHMENTS_DIR").unwrap_or_else(|_| "./data/attachments".to_string()),
+            },
+            "s3" => AttachmentStorage::S3(S3Config {
+                endpoint: required("S3_ENDPOINT").trim_end_matches('/').to_string(),
+                bucket: required("S3_BUCKET"),
+                region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                access_key_id: required("S3_ACCESS_KEY_ID"),
+                secret_access_key: required("S3_SECRET_ACCESS_KEY"),
+            }),
+            other => panic!("ATTACHMENT_STORAGE must be 'local' or 's3', not '{other}'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LightningConfig {
+    pub backend: LightningBackend,
+    /// REST base URL of the node or LNbits instance.
+    pub url: String,
+    /// PEM certificate to trust for `url`, for nodes with a self-signed one.
+    pub tls_cert: Option<Vec<u8>>,
+    /// Lifetime of a BOLT11 when the invoice itself doesn't expire sooner.
+    pub invoice_expiry_secs: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum LightningBackend {
+    /// LND's REST API, with an invoice macaroon.
+    Lnd { macaroon_hex: String },
+    /// Core Lightning's `clnrest`, with a rune allowing `invoice`, `listinvoices` and `waitanyinvoice`.
+    Cln { rune: String },
+    /// An LNbits wallet, with its invoice/read key.
+    Lnbits { api_key: String },
+}
+
+impl LightningConfig {
+    fn from_env() -> Option<Self> {
+        let required = |name: &str| {
+            env::var(name)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| panic!("{name} must be set when LIGHTNING_BACKEND is set"))
+        };
+        let backend = match env::var("LIGHTNING_BACKEND").unwrap_or_default().trim() {
+            "" => return None,
+            "lnd" => LightningBackend::Lnd { macaroon_hex: required("LND_MACAROON_HEX") },
+            "cln" => LightningBackend::Cln { rune: required("CLN_RUNE") },
+            "lnbits" => LightningBackend::Lnbits { api_key: required("LNBITS_API_KEY") },
+            other => panic!("LIGHTNING_BACKEND must be 'lnd', 'cln' or 'lnbits', not '{other}'"),
+        };
+        let tls_cert = env::var("LIGHTNING_TLS_CERT_PATH").ok().filter(|p| !p.trim().is_empty()).map(|path| {
+            std::fs::read(path.trim()).unwrap_or_else(|e| panic!("LIGHTNING_TLS_CERT_PATH can't be read: {e}"))
+        });
+        Some(Self {
+            backend,
+            url: required("LIGHTNING_URL").trim_end_matches('/').to_string(),
+            tls_cert,
+            invoice_expiry_secs: bounded("LIGHTNING_INVOICE_EXPIRY_SECS", 86_400, 600, 30 * 86_400),
+        })
+    }
+}
+
+/// A session ends once it has gone unused for `idle`, or `absolute` after sign-in,
+/// whichever comes first. Activity pushes the idle deadline back.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLifetimes {
+    pub idle: Duration,
+    pub absolute: Duration,
+}
+
+impl SessionLifetimes {
+    fn from_env() -> Self {
+        Self {
+            idle: Duration::from_secs(bounded("SESSION_IDLE_TIMEOUT_HOURS", 30 * 24, 1, 365 * 24) * 3600),
+            absolute: Duration::from_secs(bounded("SESSION_MAX_LIFETIME_DAYS", 90, 1, 365) * 86_400),
+        }
+    }
+}
+
+/// Browsers only offer a passkey on its relying party's domain (and subdomains), and
+/// sign a ceremony's client data with the page's origin, which must be `origin`.
+#[derive(Debug, Clone)]
+pub struct WebAuthnConfig {
+    /// Registrable domain the passkeys belong to, e.g. `opacore.com`.
+    pub rp_id: String,
+    /// Shown by the browser when creating a passkey.
+    pub rp_name: String,
+    /// Where the web app runs, e.g. `https://app.opacore.com`.
+    pub origin: String,
+}
+
+impl WebAuthnConfig {
+    fn from_env(app_url: &str) -> Self {
+        let app = reqwest::Url::parse(app_url).unwrap_or_else(|e| panic!("APP_URL is not a valid URL: {e}"));
+        let var = |name: &str| env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Self {
+            rp_id: var("WEBAUTHN_RP_ID")
+                .or_else(|| app.host_str().map(str::to_string))
+                .unwrap_or_else(|| panic!("WEBAUTHN_RP_ID must be set when APP_URL has no host")),
+            rp_name: var("WEBAUTHN_RP_NAME").unwrap_or_else(|| "Opacore".to_string()),
+            origin: var("WEBAUTHN_ORIGIN")
+                .map(|o| o.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| app.origin().ascii_serialization()),
+        }
+    }
+}
+
+/// Per-user resource limits. `None` means unlimited (the self-hosting default).
+#[derive(Debug, Clone, Default)]
+pub struct QuotaLimits {
+    pub max_portfolios: Option<u32>,
+    pub max_wallets: Option<u32>,
+    pub monthly_emails: Option<u32>,
+    pub max_watched_addresses: Option<u32>,
+    pub max_api_keys: Option<u32>,
+    pub max_webhook_endpoints: Option<u32>,
+}
+
+/// How often background tasks run, and how hard they lean on Esplora while doing so.
+/// Each value is read from its own env var and must fall in the documented safe range.
+#[derive(Debug, Clone)]
+pub struct TaskIntervals {
+    /// Polling for invoice payments.
+    pub invoice_check: Duration,
+    /// Pause between invoice checks within one round.
+    pub invoice_check_delay: Duration,
+    /// Invoices checked per round; larger backlogs are worked through over several rounds.
+    pub invoice_check_batch: i64,
+    /// Safety-net polling while the mempool WebSocket watch is connected.
+    pub invoice_ws_sweep: Duration,
+    /// How often the WebSocket watch re-reads open invoices and pings the server.
+    pub mempool_ws_refresh: Duration,
+    pub alert_check: Duration,
+    /// Pause between alert emails.
+    pub alert_email_delay: Duration,
+    pub watch_check: Duration,
+    pub exchange_sync: Duration,
+    pub esplora_health_check: Duration,
+    pub event_poll: Duration,
+    pub event_prune: Duration,
+    /// Deleting stored files of removed attachments.
+    pub attachment_purge: Duration,
+    /// Materializing due recurring transactions.
+    pub recurring_check: Duration,
+    /// Sending queued webhook deliveries and due retries.
+    pub webhook_delivery: Duration,
+    /// Emailing payment reminders for invoices nearing `due_at`.
+    pub invoice_reminder: Duration,
+    /// Deleting expired sessions.
+    pub session_purge: Duration,
+}
+
+impl TaskIntervals {
+    fn from_env() -> Self {
+        Self {
+            invoice_check: Duration::from_secs(bounded("INVOICE_CHECK_INTERVAL_SECS", 60, 5, 3600)),
+            invoice_check_delay: Duration::from_millis(bounded("INVOICE_CHECK_DELAY_MS", 500, 0, 10_000)),
+            invoice_check_batch: bounded("INVOICE_CHECK_BATCH_SIZE", 10, 1, 500) as i64,
+            invoice_ws_sweep: Duration::from_secs(bounded("INVOICE_WS_SWEEP_INTERVAL_SECS", 600, 60, 86_400)),
+            mempool_ws_refresh: Duration::from_secs(bounded("MEMPOOL_WS_REFRESH_SECS", 30, 5, 300)),
+            alert_check: Duration::from_secs(bounded("ALERT_CHECK_INTERVAL_SECS", 300, 30, 86_400)),
+            alert_email_delay: Duration::from_millis(bounded("ALERT_EMAIL_DELAY_MS", 200, 0, 10_000)),
+            watch_check: Duration::from_secs(bounded("WATCH_CHECK_INTERVAL_SECS", 120, 15, 86_400)),
+            exchange_sync: Duration::from_secs(bounded("EXCHANGE_SYNC_INTERVAL_SECS", 3600, 300, 86_400)),
+            esplora_health_check: Duration::from_secs(bounded("ESPLORA_HEALTH_CHECK_INTERVAL_SECS", 60, 10, 3600)),
+            event_poll: Duration::from_secs(bounded("EVENT_POLL_INTERVAL_SECS", 5, 1, 300)),
+            event_prune: Duration::from_secs(bounded("EVENT_PRUNE_INTERVAL_SECS", 3600, 60, 86_400)),
+            attachment_purge: Duration::from_secs(bounded("ATTACHMENT_PURGE_INTERVAL_SECS", 3600, 60, 86_400)),
+            recurring_check: Duration::from_secs(bounded("RECURRING_CHECK_INTERVAL_SECS", 300, 60, 86_400)),
+            webhook_delivery: Duration::from_secs(bounded("WEBHOOK_DELIVERY_INTERVAL_SECS", 10, 1, 600)),
+            invoice_reminder: Duration::from_secs(bounded("INVOICE_REMINDER_INTERVAL_SECS", 3600, 60, 86_400)),
+            session_purge: Duration::from_secs(bounded("SESSION_PURGE_INTERVAL_SECS", 3600, 60, 86_400)),
+        }
+    }
 }
 
 impl Config {
     pub fn from_env() -> Self {
+        // ESPLORA_URLS (comma-separated) takes precedence over the single ESPLORA_URL
+        let esplora_urls = url_list("ESPLORA_URLS").unwrap_or_else(|| {
+            vec![env::var("ESPLORA_URL")
+                .unwrap_or_else(|_| "https://blockstream.info/api".to_string())
+                .trim_end_matches('/')
+                .to_string()]
+        });
+        // Testnet/signet default to the mainnet hosts' /testnet/api and /signet/api paths
+        let esplora_testnet_urls = url_list("ESPLORA_TESTNET_URLS").unwrap_or_else(|| {
+            esplora_urls.iter().map(|u| u.replace("/api", "/testnet/api")).collect()
+        });
+        let esplora_signet_urls = url_list("ESPLORA_SIGNET_URLS").unwrap_or_else(|| {
+            esplora_urls.iter().map(|u| u.replace("/api", "/signet/api")).collect()
+        });
+        let esplora_regtest_urls =
+            url_list("ESPLORA_REGTEST_URLS").unwrap_or_else(|| esplora_urls.clone());
+        let app_url = env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+
         Self {
             server_port: env::var("SERVER_PORT")
                 .unwrap_or_else(|_| "4000".to_string())
@@ -30,27 +299,104 @@ impl Config {
                 .unwrap_or_else(|_| "./data/opacore.db".to_string()),
             bdk_wallets_dir: env::var("BDK_WALLETS_DIR")
                 .unwrap_or_else(|_| "./data/wallets".to_string()),
-            session_secret: env::var("SESSION_SECRET")
-                .unwrap_or_else(|_| "change-me-to-a-random-32-char-string".to_string()),
-            esplora_url: env::var("ESPLORA_URL")
-                .unwrap_or_else(|_| "https://blockstream.info/api".to_string()),
+            exports_dir: env::var("EXPORTS_DIR")
+                .unwrap_or_else(|_| "./data/exports".to_string()),
+            download_link_ttl_hours: bounded("DOWNLOAD_LINK_TTL_HOURS", 24, 1, 168),
+            account_import_max_bytes: bounded("ACCOUNT_IMPORT_MAX_MB", 100, 1, 1024) as usize * 1024 * 1024,
+            attachment_storage: AttachmentStorage::from_env(),
+            attachment_max_bytes: bounded("ATTACHMENT_MAX_MB", 10, 1, 100) as usize * 1024 * 1024,
+            // SESSION_SECRETS (a key ring) takes precedence over the single SESSION_SECRET
+            session_keys: match env::var("SESSION_SECRETS").ok().filter(|v| !v.trim().is_empty()) {
+                Some(v) => KeyRing::parse(&v)
+                    .unwrap_or_else(|e| panic!("SESSION_SECRETS is invalid: {e}")),
+                None => KeyRing::single(
+                    env::var("SESSION_SECRET")
+                        .unwrap_or_else(|_| "change-me-to-a-random-32-char-string".to_string()),
+                ),
+            },
+            sessions: SessionLifetimes::from_env(),
+            esplora_urls,
+            esplora_testnet_urls,
+            esplora_signet_urls,
+            esplora_regtest_urls,
             coingecko_api_url: env::var("COINGECKO_API_URL")
                 .unwrap_or_else(|_| "https://api.coingecko.com/api/v3".to_string()),
+            current_price_ttl: Duration::from_secs(bounded("CURRENT_PRICE_TTL_SECS", 60, 5, 3600)),
+            kraken_api_url: env::var("KRAKEN_API_URL")
+                .unwrap_or_else(|_| "https://api.kraken.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            coinbase_api_url: env::var("COINBASE_API_URL")
+                .unwrap_or_else(|_| "https://api.coinbase.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            mempool_ws_url: env::var("MEMPOOL_WS_URL").ok().filter(|u| !u.is_empty()),
+            invoice_min_confirmations: bounded("INVOICE_MIN_CONFIRMATIONS", 1, 0, 100),
+            invoice_rate_validity_secs: bounded("INVOICE_RATE_VALIDITY_SECS", 900, 60, 86_400),
+            lightning: LightningConfig::from_env(),
             cors_origin: env::var("CORS_ORIGIN")
                 .unwrap_or_else(|_| "http://localhost:3000".to_string()),
             secure_cookies: env::var("SECURE_COOKIES")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap_or(false),
+            trust_proxy_headers: env::var("TRUST_PROXY_HEADERS")
+                .map(|v| v.trim() == "true")
+                .unwrap_or(false),
             resend_api_key: env::var("RESEND_API_KEY").ok(),
             admin_email: env::var("ADMIN_EMAIL").ok(),
             from_email: env::var("FROM_EMAIL")
                 .unwrap_or_else(|_| "noreply@opacore.com".to_string()),
-            app_url: env::var("APP_URL")
-                .unwrap_or_else(|_| "http://localhost:3000".to_string()),
+            webauthn: WebAuthnConfig::from_env(&app_url),
+            app_url,
             stripe_secret_key: env::var("STRIPE_SECRET_KEY").ok(),
             stripe_webhook_secret: env::var("STRIPE_WEBHOOK_SECRET").ok(),
             stripe_price_id: env::var("STRIPE_PRICE_ID").ok(),
+            quotas: QuotaLimits {
+                max_portfolios: env::var("QUOTA_MAX_PORTFOLIOS").ok().and_then(|v| v.parse().ok()),
+                max_wallets: env::var("QUOTA_MAX_WALLETS").ok().and_then(|v| v.parse().ok()),
+                monthly_emails: env::var("QUOTA_MONTHLY_EMAILS").ok().and_then(|v| v.parse().ok()),
+                max_watched_addresses: env::var("QUOTA_MAX_WATCHED_ADDRESSES").ok().and_then(|v| v.parse().ok()),
+                max_api_keys: Some(env::var("QUOTA_MAX_API_KEYS").ok().and_then(|v| v.parse().ok()).unwrap_or(25)),
+                max_webhook_endpoints: Some(
+                    env::var("QUOTA_MAX_WEBHOOK_ENDPOINTS").ok().and_then(|v| v.parse().ok()).unwrap_or(10),
+                ),
+            },
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().filter(|u| !u.is_empty()),
+            otel_service_name: env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| "opacore-server".to_string()),
+            request_timeout_secs: env::var("REQUEST_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            intervals: TaskIntervals::from_env(),
         }
     }
 }
+
+/// Parse a comma-separated list of URLs, dropping blanks and trailing slashes.
+fn url_list(var: &str) -> Option<Vec<String>> {
+    let urls: Vec<String> = env::var(var)
+        .ok()?
+        .split(',')
+        .map(|u| u.trim().trim_end_matches('/').to_string())
+        .filter(|u| !u.is_empty())
+        .collect();
+    if urls.is_empty() {
+        None
+    } else {
+        Some(urls)
+    }
+}
+
+/// A whole-number env var within `min..=max`, or `default` if unset. Panics outside the
+/// range rather than let a typo hammer a public Esplora or stall payment detection.
+fn bounded(name: &str, default: u64, min: u64, max: u64) -> u64 {
+    match env::var(name).ok().filter(|v| !v.trim().is_empty()) {
+        None => default,
+        Some(v) => match v.trim().parse::<u64>() {
+            Ok(n) if (min..=max).contains(&n) => n,
+            _ => panic!("{name} must be a whole number from {min} to {max}"),
+        },
+    }
+}
@@ -189,5 +189,180 @@ pub fn run(conn: &Connection) -> rusqlite::Result<()> {
         )?;
     }
 
+    // Migration: record which Esplora backend served each wallet sync
+    if !column_exists(conn, "wallets", "last_sync_backend")? {
+        conn.execute_batch("ALTER TABLE wallets ADD COLUMN last_sync_backend TEXT;")?;
+    }
+
+    // Migration: link a replacement transaction to the one it replaced via RBF
+    if !column_exists(conn, "transactions", "replaces_txid")? {
+        conn.execute_batch("ALTER TABLE transactions ADD COLUMN replaces_txid TEXT;")?;
+    }
+
+    // Migration: record where an invoice address sits in its linked wallet, for address proofs
+    if !column_exists(conn, "invoices", "address_index")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN address_keychain TEXT;
+             ALTER TABLE invoices ADD COLUMN address_index INTEGER;
+             ALTER TABLE invoices ADD COLUMN address_derivation_path TEXT;
+             ALTER TABLE invoices ADD COLUMN address_proof INTEGER NOT NULL DEFAULT 0;",
+        )?;
+    }
+
+    // Migration: per-user override for the watched address quota
+    if !column_exists(conn, "user_quotas", "max_watched_addresses")? {
+        conn.execute_batch("ALTER TABLE user_quotas ADD COLUMN max_watched_addresses INTEGER;")?;
+    }
+
+    // Migration: link the parts of a split transaction to the transaction they divide
+    if !column_exists(conn, "transactions", "parent_id")? {
+        conn.execute_batch(
+            "ALTER TABLE transactions ADD COLUMN parent_id TEXT REFERENCES transactions(id) ON DELETE CASCADE;",
+        )?;
+    }
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_transactions_parent_id ON transactions(parent_id);")?;
+
+    // Migration: confirmations an invoice payment needs before the invoice counts as paid
+    if !column_exists(conn, "invoices", "min_confirmations")? {
+        conn.execute_batch("ALTER TABLE invoices ADD COLUMN min_confirmations INTEGER NOT NULL DEFAULT 1;")?;
+    }
+
+    // Migration: emailing invoices to customers, with an optional reminder before due_at
+    if !column_exists(conn, "invoices", "sent_at")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN sent_at TEXT;
+             ALTER TABLE invoices ADD COLUMN last_sent_at TEXT;
+             ALTER TABLE invoices ADD COLUMN send_count INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE invoices ADD COLUMN reminder_days_before INTEGER;
+             ALTER TABLE invoices ADD COLUMN reminder_sent_at TEXT;",
+        )?;
+    }
+
+    // Migration: link invoices to the customer directory
+    if !column_exists(conn, "invoices", "customer_id")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN customer_id TEXT REFERENCES customers(id) ON DELETE SET NULL;",
+        )?;
+    }
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_invoices_customer_id ON invoices(customer_id);")?;
+
+    // Migration: Lightning invoices alongside the on-chain address
+    if !column_exists(conn, "invoices", "lightning_invoice")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN paid_via TEXT;
+             ALTER TABLE invoices ADD COLUMN lightning_invoice TEXT;
+             ALTER TABLE invoices ADD COLUMN lightning_payment_hash TEXT;
+             ALTER TABLE invoices ADD COLUMN lightning_expires_at TEXT;
+             UPDATE invoices SET paid_via = 'onchain' WHERE paid_at IS NOT NULL;",
+        )?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_invoices_lightning_payment_hash ON invoices(lightning_payment_hash);",
+    )?;
+
+    // Migration: fiat-denominated invoices with a time-limited sat quote
+    if !column_exists(conn, "invoices", "fiat_denominated")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN fiat_denominated INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE invoices ADD COLUMN rate_btc_price REAL;
+             ALTER TABLE invoices ADD COLUMN rate_locked_at TEXT;
+             ALTER TABLE invoices ADD COLUMN rate_expires_at TEXT;",
+        )?;
+    }
+
+    // Migration: counterparties, and transactions booked from paid invoices
+    if !column_exists(conn, "transactions", "invoice_id")? {
+        conn.execute_batch(
+            "ALTER TABLE transactions ADD COLUMN counterparty TEXT;
+             ALTER TABLE transactions ADD COLUMN invoice_id TEXT REFERENCES invoices(id) ON DELETE SET NULL;",
+        )?;
+    }
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_transactions_invoice_id ON transactions(invoice_id);")?;
+
+    // Migration: payment links that can be switched off
+    if !column_exists(conn, "invoices", "share_enabled")? {
+        conn.execute_batch("ALTER TABLE invoices ADD COLUMN share_enabled INTEGER NOT NULL DEFAULT 1;")?;
+    }
+
+    // Migration: payment page view counts
+    if !column_exists(conn, "invoices", "view_count")? {
+        conn.execute_batch(
+            "ALTER TABLE invoices ADD COLUMN view_count INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE invoices ADD COLUMN last_viewed_at TEXT;",
+        )?;
+    }
+
+    // Migration: default invoice expiry per portfolio
+    if !column_exists(conn, "portfolios", "invoice_expiry_hours")? {
+        conn.execute_batch("ALTER TABLE portfolios ADD COLUMN invoice_expiry_hours INTEGER;")?;
+    }
+
+    // Migration: when each session was last used
+    if !column_exists(conn, "sessions", "last_used_at")? {
+        conn.execute_batch("ALTER TABLE sessions ADD COLUMN last_used_at TEXT;")?;
+    }
+
+    // Migration: portfolios shared with an organization
+    if !column_exists(conn, "portfolios", "organization_id")? {
+        conn.execute_batch(
+            "ALTER TABLE portfolios ADD COLUMN organization_id TEXT REFERENCES organizations(id) ON DELETE SET NULL;",
+        )?;
+    }
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_portfolios_organization_id ON portfolios(organization_id);",
+    )?;
+
+    // Migration: admins, and accounts disabled by one
+    if !column_exists(conn, "users", "is_admin")? {
+        conn.execute_batch("ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0;")?;
+    }
+    if !column_exists(conn, "users", "disabled_at")? {
+        conn.execute_batch("ALTER TABLE users ADD COLUMN disabled_at TEXT;")?;
+    }
+
+    // Migration: sends and receives linked as transfers between the user's own wallets
+    if !column_exists(conn, "transactions", "linked_transaction_id")? {
+        conn.execute_batch(
+            "ALTER TABLE transactions ADD COLUMN linked_transaction_id TEXT REFERENCES transactions(id) ON DELETE SET NULL;",
+        )?;
+    }
+
+    // Migration: network fees as taxable disposals
+    if !column_exists(conn, "tax_settings", "fees_taxable")? {
+        conn.execute_batch("ALTER TABLE tax_settings ADD COLUMN fees_taxable INTEGER NOT NULL DEFAULT 0;")?;
+    }
+
+    // Migration: refuse tax reports while transactions lack prices
+    if !column_exists(conn, "tax_settings", "require_prices")? {
+        conn.execute_batch("ALTER TABLE tax_settings ADD COLUMN require_prices INTEGER NOT NULL DEFAULT 0;")?;
+    }
+
+    // Migration: per-portfolio cost-basis method and reporting currency
+    if !column_exists(conn, "portfolios", "cost_basis_method")? {
+        conn.execute_batch("ALTER TABLE portfolios ADD COLUMN cost_basis_method TEXT NOT NULL DEFAULT 'fifo';")?;
+    }
+    if !column_exists(conn, "portfolios", "base_currency")? {
+        conn.execute_batch("ALTER TABLE portfolios ADD COLUMN base_currency TEXT NOT NULL DEFAULT 'usd';")?;
+    }
+
+    // Migration: per-user override for the API key quota
+    if !column_exists(conn, "user_quotas", "max_api_keys")? {
+        conn.execute_batch("ALTER TABLE user_quotas ADD COLUMN max_api_keys INTEGER;")?;
+    }
+
+    // Migration: per-user override for the webhook endpoint quota
+    if !column_exists(conn, "user_quotas", "max_webhook_endpoints")? {
+        conn.execute_batch("ALTER TABLE user_quotas ADD COLUMN max_webhook_endpoints INTEGER;")?;
+    }
+
     Ok(())
 }
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        rusqlite::params![table, column],
+        |row| row.get::<_, i32>(0),
+    )
+    .map(|c| c > 0)
+}
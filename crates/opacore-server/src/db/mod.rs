@@ -20,6 +20,8 @@ pub fn create_pool(sqlite_path: &str) -> DbPool {
                 | OpenFlags::SQLITE_OPEN_FULL_MUTEX,
         )
         .with_init(|conn| {
+            // Every statement is reported to tracing, where the OTLP exporter turns it into a span
+            conn.profile(Some(trace_statement));
             conn.execute_batch(
                 "PRAGMA journal_mode = WAL;
                  PRAGMA foreign_keys = ON;
@@ -38,3 +40,13 @@ pub fn create_pool(sqlite_path: &str) -> DbPool {
 
     pool
 }
+
+fn trace_statement(sql: &str, elapsed: std::time::Duration) {
+    tracing::event!(
+        name: crate::telemetry::DB_QUERY_EVENT,
+        tracing::Level::TRACE,
+        db.system = "sqlite",
+        db.statement = sql,
+        elapsed_us = elapsed.as_micros() as u64,
+    );
+}
@@ -8,6 +8,8 @@ CREATE TABLE IF NOT EXISTS users (
     password_hash   TEXT NOT NULL,
     default_currency TEXT NOT NULL DEFAULT 'usd',
     email_verified  INTEGER NOT NULL DEFAULT 1,
+    is_admin        INTEGER NOT NULL DEFAULT 0,  -- may use the /api/v1/admin routes
+    disabled_at     TEXT,                        -- set while an admin has the account disabled
     created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
@@ -19,11 +21,59 @@ CREATE TABLE IF NOT EXISTS sessions (
     expires_at      TEXT NOT NULL,
     ip_address      TEXT,
     user_agent      TEXT,
+    last_used_at    TEXT,
     created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 CREATE INDEX IF NOT EXISTS idx_sessions_token ON sessions(token);
 CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
 
+-- Personal access tokens for scripts and integrations
+CREATE TABLE IF NOT EXISTS api_keys (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    prefix          TEXT NOT NULL,              -- start of the token, to tell keys apart
+    token_hash      TEXT NOT NULL UNIQUE,       -- SHA-256 (hex) of the token
+    scopes          TEXT NOT NULL,              -- JSON array: read, transactions:write, invoices:write
+    last_used_at    TEXT,
+    expires_at      TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
+
+-- Failed password sign-ins per email (lowercased), for per-account lockout
+CREATE TABLE IF NOT EXISTS login_attempts (
+    email           TEXT PRIMARY KEY NOT NULL,
+    failed_count    INTEGER NOT NULL,
+    last_failed_at  TEXT NOT NULL,
+    locked_until    TEXT
+);
+
+-- Where each user has signed in from, so sign-ins from somewhere new can be flagged
+CREATE TABLE IF NOT EXISTS known_logins (
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    ip_address      TEXT NOT NULL,
+    user_agent      TEXT NOT NULL,              -- '' when the client sent none
+    first_seen_at   TEXT NOT NULL,
+    last_seen_at    TEXT NOT NULL,
+    PRIMARY KEY (user_id, ip_address, user_agent)
+);
+
+-- Security-sensitive actions (sign-ins, password changes, API keys, wallets, exports),
+-- shown to the user and to admins
+CREATE TABLE IF NOT EXISTS audit_log (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    action          TEXT NOT NULL,
+    target_id       TEXT,               -- the API key, wallet or job acted on
+    details         TEXT NOT NULL DEFAULT '{}',  -- JSON
+    ip_address      TEXT,
+    user_agent      TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_user_id ON audit_log(user_id, id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action, id);
+
 CREATE TABLE IF NOT EXISTS email_verification_tokens (
     id              TEXT PRIMARY KEY NOT NULL,
     user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
@@ -44,6 +94,79 @@ CREATE TABLE IF NOT EXISTS password_reset_tokens (
 CREATE INDEX IF NOT EXISTS idx_prt_token ON password_reset_tokens(token);
 CREATE INDEX IF NOT EXISTS idx_prt_user_id ON password_reset_tokens(user_id);
 
+-- A pending change of a user's email, applied once the new address is confirmed
+CREATE TABLE IF NOT EXISTS email_change_tokens (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    new_email       TEXT NOT NULL,
+    token           TEXT NOT NULL UNIQUE,
+    expires_at      TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_ect_token ON email_change_tokens(token);
+CREATE INDEX IF NOT EXISTS idx_ect_user_id ON email_change_tokens(user_id);
+
+-- Passkeys (WebAuthn credentials) a user can sign in with instead of their password
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id   TEXT NOT NULL UNIQUE,       -- base64url, unpadded
+    public_key      BLOB NOT NULL,              -- SubjectPublicKeyInfo (DER)
+    algorithm       INTEGER NOT NULL,           -- COSE: -7 ES256, -8 EdDSA, -257 RS256
+    sign_count      INTEGER NOT NULL DEFAULT 0,
+    transports      TEXT,                       -- JSON array of hints, e.g. ["internal","hybrid"]
+    name            TEXT NOT NULL,
+    last_used_at    TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
+
+-- Outstanding passkey ceremonies; each challenge can be answered once
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+    challenge       TEXT PRIMARY KEY NOT NULL,  -- base64url, unpadded
+    ceremony        TEXT NOT NULL CHECK (ceremony IN ('register', 'authenticate')),
+    user_id         TEXT REFERENCES users(id) ON DELETE CASCADE,  -- who is registering; NULL for sign-in
+    expires_at      TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_webauthn_challenges_expires_at ON webauthn_challenges(expires_at);
+
+-- ============================================================
+-- ORGANIZATIONS
+-- ============================================================
+-- Teams that share portfolios. Members get their role on every portfolio shared with
+-- the organization; a portfolio's creator always keeps full control of it.
+CREATE TABLE IF NOT EXISTS organizations (
+    id              TEXT PRIMARY KEY NOT NULL,
+    name            TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    organization_id TEXT NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role            TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'accountant', 'viewer')),
+    added_by        TEXT REFERENCES users(id) ON DELETE SET NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (organization_id, user_id)
+);
+CREATE INDEX IF NOT EXISTS idx_organization_members_user ON organization_members(user_id);
+
+-- Pending invitations by email; the invitee becomes a member once they accept.
+CREATE TABLE IF NOT EXISTS organization_invitations (
+    id              TEXT PRIMARY KEY NOT NULL,
+    organization_id TEXT NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    email           TEXT NOT NULL COLLATE NOCASE,
+    role            TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'accountant', 'viewer')),
+    invited_by      TEXT REFERENCES users(id) ON DELETE SET NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    UNIQUE (organization_id, email)
+);
+CREATE INDEX IF NOT EXISTS idx_organization_invitations_email ON organization_invitations(email);
+
 -- ============================================================
 -- PORTFOLIOS
 -- ============================================================
@@ -52,11 +175,52 @@ CREATE TABLE IF NOT EXISTS portfolios (
     user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
     name            TEXT NOT NULL,
     description     TEXT,
+    invoice_expiry_hours INTEGER,       -- expires_at of new one-time invoices that don't set one
+    organization_id TEXT REFERENCES organizations(id) ON DELETE SET NULL,  -- shared with its members
+    cost_basis_method TEXT NOT NULL DEFAULT 'fifo',  -- used when a report doesn't ask for one
+    base_currency   TEXT NOT NULL DEFAULT 'usd',     -- fiat its figures are reported in
     created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 CREATE INDEX IF NOT EXISTS idx_portfolios_user_id ON portfolios(user_id);
 
+-- Reusable portfolio structures (wallet definitions and labels, no history)
+CREATE TABLE IF NOT EXISTS portfolio_templates (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    structure       TEXT NOT NULL,      -- JSON PortfolioStructure
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_portfolio_templates_user_name ON portfolio_templates(user_id, name);
+
+-- How a portfolio's invoices present the merchant on the payment page, PDF and emails
+CREATE TABLE IF NOT EXISTS portfolio_branding (
+    portfolio_id    TEXT PRIMARY KEY NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    business_name   TEXT,
+    logo_url        TEXT,               -- https only
+    accent_color    TEXT,               -- #rrggbb
+    payment_instructions TEXT,
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- Links giving anyone holding them a read-only view of a portfolio's summary and
+-- transactions, without signing in
+CREATE TABLE IF NOT EXISTS portfolio_share_links (
+    id              TEXT PRIMARY KEY NOT NULL,
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    created_by      TEXT REFERENCES users(id) ON DELETE SET NULL,
+    name            TEXT NOT NULL,
+    prefix          TEXT NOT NULL,              -- start of the token, to tell links apart
+    token_hash      TEXT NOT NULL UNIQUE,       -- SHA-256 (hex) of the token
+    expires_at      TEXT NOT NULL,
+    view_count      INTEGER NOT NULL DEFAULT 0,
+    last_viewed_at  TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_portfolio_share_links_portfolio_id ON portfolio_share_links(portfolio_id);
+
 -- ============================================================
 -- WALLETS / DESCRIPTORS
 -- ============================================================
@@ -73,11 +237,45 @@ CREATE TABLE IF NOT EXISTS wallets (
     gap_limit       INTEGER NOT NULL DEFAULT 20,
     last_synced_at  TEXT,
     last_sync_height INTEGER,
+    last_sync_backend TEXT,
     created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
 CREATE INDEX IF NOT EXISTS idx_wallets_portfolio_id ON wallets(portfolio_id);
 
+-- Coin control metadata for individual UTXOs; rows outlive the UTXO being spent
+CREATE TABLE IF NOT EXISTS utxo_metadata (
+    wallet_id       TEXT NOT NULL REFERENCES wallets(id) ON DELETE CASCADE,
+    txid            TEXT NOT NULL,
+    vout            INTEGER NOT NULL,
+    label           TEXT,
+    notes           TEXT,
+    frozen          INTEGER NOT NULL DEFAULT 0,
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (wallet_id, txid, vout)
+);
+
+-- ============================================================
+-- SYNC JOBS (checkpointed full scans)
+-- ============================================================
+CREATE TABLE IF NOT EXISTS sync_jobs (
+    id                 TEXT PRIMARY KEY NOT NULL,
+    wallet_id          TEXT NOT NULL REFERENCES wallets(id) ON DELETE CASCADE,
+    status             TEXT NOT NULL DEFAULT 'running',  -- running | completed | failed | interrupted
+    stop_gap           INTEGER NOT NULL,
+    keychain           TEXT NOT NULL DEFAULT 'external',  -- keychain currently being scanned
+    next_index         INTEGER NOT NULL DEFAULT 0,        -- first derivation index not yet scanned
+    last_active_index  INTEGER,                           -- last used index on the current keychain
+    backend            TEXT,
+    transactions_found INTEGER,
+    new_transactions   INTEGER,
+    error              TEXT,
+    started_at         TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at         TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    finished_at        TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_sync_jobs_wallet_id ON sync_jobs(wallet_id, started_at);
+
 -- ============================================================
 -- TRANSACTIONS
 -- ============================================================
@@ -95,6 +293,11 @@ CREATE TABLE IF NOT EXISTS transactions (
     block_height    INTEGER,
     block_time      TEXT,
     source          TEXT NOT NULL DEFAULT 'manual',
+    replaces_txid   TEXT,
+    parent_id       TEXT REFERENCES transactions(id) ON DELETE CASCADE,   -- set on the parts of a split transaction
+    counterparty    TEXT,               -- who paid or was paid, e.g. an invoice's customer
+    invoice_id      TEXT REFERENCES invoices(id) ON DELETE SET NULL,      -- the paid invoice this records
+    linked_transaction_id TEXT REFERENCES transactions(id) ON DELETE SET NULL,  -- other side of a transfer between own wallets
     transacted_at   TEXT NOT NULL,
     created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
@@ -102,6 +305,109 @@ CREATE TABLE IF NOT EXISTS transactions (
 CREATE INDEX IF NOT EXISTS idx_transactions_portfolio_date ON transactions(portfolio_id, transacted_at);
 CREATE INDEX IF NOT EXISTS idx_transactions_txid ON transactions(txid);
 CREATE INDEX IF NOT EXISTS idx_transactions_wallet_id ON transactions(wallet_id);
+-- Keyset pagination: newest first, id breaks ties
+CREATE INDEX IF NOT EXISTS idx_transactions_portfolio_keyset ON transactions(portfolio_id, transacted_at, id);
+
+-- Wallet addresses with usage stats, refreshed after every sync
+CREATE TABLE IF NOT EXISTS wallet_addresses (
+    wallet_id           TEXT NOT NULL REFERENCES wallets(id) ON DELETE CASCADE,
+    keychain            TEXT NOT NULL,      -- 'external' or 'internal'
+    idx                 INTEGER NOT NULL,
+    address             TEXT NOT NULL,
+    used                INTEGER NOT NULL DEFAULT 0,
+    total_received_sat  INTEGER NOT NULL DEFAULT 0,
+    balance_sat         INTEGER NOT NULL DEFAULT 0,
+    tx_count            INTEGER NOT NULL DEFAULT 0,
+    updated_at          TEXT NOT NULL,
+    PRIMARY KEY (wallet_id, keychain, idx)
+);
+
+-- Inputs and outputs of on-chain transactions, recorded at sync time
+CREATE TABLE IF NOT EXISTS transaction_inputs (
+    transaction_id  TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    vin             INTEGER NOT NULL,
+    prev_txid       TEXT NOT NULL,
+    prev_vout       INTEGER NOT NULL,
+    address         TEXT,               -- NULL when the spent output isn't known or has no address
+    value_sat       INTEGER,
+    is_mine         INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (transaction_id, vin)
+);
+
+CREATE TABLE IF NOT EXISTS transaction_outputs (
+    transaction_id  TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    vout            INTEGER NOT NULL,
+    address         TEXT,               -- NULL for OP_RETURN and other non-address scripts
+    value_sat       INTEGER NOT NULL,
+    is_mine         INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (transaction_id, vout)
+);
+
+-- Every change made to a transaction by a user (or on their behalf by an import or fix).
+-- No foreign key to transactions: the history of a deleted row stays readable.
+CREATE TABLE IF NOT EXISTS transaction_audit (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    transaction_id  TEXT NOT NULL,
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    user_id         TEXT REFERENCES users(id) ON DELETE SET NULL,
+    action          TEXT NOT NULL CHECK (action IN ('create', 'update', 'delete', 'labels')),
+    changes         TEXT NOT NULL,      -- JSON: the row for create/delete, {field: {from, to}} otherwise
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_transaction_audit_portfolio ON transaction_audit(portfolio_id, id);
+CREATE INDEX IF NOT EXISTS idx_transaction_audit_transaction ON transaction_audit(transaction_id, id);
+
+-- Manual transactions that repeat (e.g. weekly DCA buys at an exchange without an API).
+-- Occurrence n falls at starts_at + n periods; the background task materializes each
+-- one as a transaction once due. Exactly one of amount_sat and fiat_amount is set.
+CREATE TABLE IF NOT EXISTS recurring_transactions (
+    id              TEXT PRIMARY KEY NOT NULL,
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    wallet_id       TEXT REFERENCES wallets(id) ON DELETE CASCADE,
+    user_id         TEXT REFERENCES users(id) ON DELETE SET NULL,   -- creator
+    tx_type         TEXT NOT NULL,
+    amount_sat      INTEGER,            -- fixed amount each time, or
+    fiat_amount     REAL,               -- fixed spend, converted at each occurrence's price
+    fiat_currency   TEXT NOT NULL DEFAULT 'usd',
+    frequency       TEXT NOT NULL,      -- 'daily', 'weekly' or 'monthly'
+    starts_at       TEXT NOT NULL,
+    ends_at         TEXT,
+    occurrences     INTEGER NOT NULL DEFAULT 0,     -- materialized so far
+    next_run_at     TEXT,               -- NULL once past ends_at
+    is_active       INTEGER NOT NULL DEFAULT 1,
+    last_error      TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_recurring_transactions_portfolio ON recurring_transactions(portfolio_id);
+CREATE INDEX IF NOT EXISTS idx_recurring_transactions_due ON recurring_transactions(is_active, next_run_at);
+
+-- Receipts and other documents backing a transaction. The file itself lives in the
+-- configured attachment storage under `storage_key`.
+CREATE TABLE IF NOT EXISTS transaction_attachments (
+    id              TEXT PRIMARY KEY NOT NULL,
+    transaction_id  TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    user_id         TEXT REFERENCES users(id) ON DELETE SET NULL,   -- uploader
+    file_name       TEXT NOT NULL,
+    content_type    TEXT NOT NULL,
+    size_bytes      INTEGER NOT NULL,
+    sha256          TEXT NOT NULL,
+    storage_key     TEXT NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_transaction_attachments_transaction ON transaction_attachments(transaction_id, created_at);
+
+-- Stored files to delete. Filled by trigger so every way a row goes (its transaction,
+-- wallet, portfolio or account being deleted) leaves the file to be purged.
+CREATE TABLE IF NOT EXISTS attachment_deletions (
+    storage_key     TEXT PRIMARY KEY NOT NULL,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE TRIGGER IF NOT EXISTS trg_transaction_attachments_deleted
+AFTER DELETE ON transaction_attachments
+BEGIN
+    INSERT OR IGNORE INTO attachment_deletions (storage_key) VALUES (OLD.storage_key);
+END;
 
 -- ============================================================
 -- LABELS
@@ -115,15 +421,175 @@ CREATE TABLE IF NOT EXISTS labels (
 );
 CREATE UNIQUE INDEX IF NOT EXISTS idx_labels_user_name ON labels(user_id, name);
 
+-- Saved column mappings for importing arbitrary CSV exports
+CREATE TABLE IF NOT EXISTS import_templates (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    mapping         TEXT NOT NULL,      -- JSON ColumnMapping
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_import_templates_user_name ON import_templates(user_id, name);
+
+-- Read-only exchange API keys whose trade history is imported in the background
+CREATE TABLE IF NOT EXISTS exchange_connections (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    exchange        TEXT NOT NULL CHECK (exchange IN ('coinbase', 'kraken')),
+    label           TEXT,
+    api_key         TEXT NOT NULL,
+    api_secret      TEXT NOT NULL,
+    cursor          TEXT,               -- exchange-specific position of the last imported trade
+    last_synced_at  TEXT,
+    last_error      TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_exchange_connections_user_id ON exchange_connections(user_id);
+
 CREATE TABLE IF NOT EXISTS transaction_labels (
     transaction_id  TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
     label_id        TEXT NOT NULL REFERENCES labels(id) ON DELETE CASCADE,
     PRIMARY KEY (transaction_id, label_id)
 );
 
+-- ============================================================
+-- TAX SETTINGS (per user; defaults to US rules when absent)
+-- ============================================================
+CREATE TABLE IF NOT EXISTS tax_settings (
+    user_id                 TEXT PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    long_term_months        INTEGER NOT NULL DEFAULT 12,
+    long_term_days          INTEGER,             -- overrides long_term_months when set
+    fiscal_year_start_month INTEGER NOT NULL DEFAULT 1,
+    fiscal_year_start_day   INTEGER NOT NULL DEFAULT 1,
+    fees_taxable            INTEGER NOT NULL DEFAULT 0,  -- network fees realize a gain or loss
+    require_prices          INTEGER NOT NULL DEFAULT 0,  -- refuse tax reports with unpriced transactions
+    updated_at              TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- Lots a disposal consumes under specific identification (the `specid` method). A lot
+-- is named by the acquiring transaction; sats left unassigned are matched FIFO.
+CREATE TABLE IF NOT EXISTS disposal_lot_assignments (
+    id              TEXT PRIMARY KEY NOT NULL,
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    disposal_id     TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    lot_id          TEXT NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+    amount_sat      INTEGER NOT NULL CHECK (amount_sat > 0),
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    UNIQUE (disposal_id, lot_id)
+);
+CREATE INDEX IF NOT EXISTS idx_disposal_lot_assignments_portfolio ON disposal_lot_assignments(portfolio_id);
+CREATE INDEX IF NOT EXISTS idx_disposal_lot_assignments_lot ON disposal_lot_assignments(lot_id);
+
+-- Bumped on every change to a portfolio's transactions or lot assignments, so results
+-- computed from them (cached cost basis) can tell they're stale. Triggers rather than
+-- application code, since transactions are written from many places.
+CREATE TABLE IF NOT EXISTS portfolio_revisions (
+    portfolio_id    TEXT PRIMARY KEY NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    revision        INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_transactions_revision_insert
+AFTER INSERT ON transactions
+BEGIN
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = NEW.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_transactions_revision_update
+AFTER UPDATE ON transactions
+BEGIN
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = NEW.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = OLD.portfolio_id AND OLD.portfolio_id <> NEW.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_transactions_revision_delete
+AFTER DELETE ON transactions
+BEGIN
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = OLD.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_lot_assignments_revision_insert
+AFTER INSERT ON disposal_lot_assignments
+BEGIN
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = NEW.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_lot_assignments_revision_delete
+AFTER DELETE ON disposal_lot_assignments
+BEGIN
+    INSERT INTO portfolio_revisions (portfolio_id, revision)
+    SELECT id, 1 FROM portfolios WHERE id = OLD.portfolio_id
+    ON CONFLICT(portfolio_id) DO UPDATE SET revision = revision + 1;
+END;
+
+-- Tax years already filed: transactions dated in one can't be edited or deleted until
+-- it's unlocked. Site admins can override, which is audit-logged.
+CREATE TABLE IF NOT EXISTS tax_year_locks (
+    portfolio_id    TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    year            INTEGER NOT NULL,
+    period_start    TEXT NOT NULL,  -- YYYY-MM-DD, by the locking user's tax rules
+    period_end      TEXT NOT NULL,
+    locked_by       TEXT REFERENCES users(id) ON DELETE SET NULL,
+    locked_at       TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (portfolio_id, year)
+);
+
+-- ============================================================
+-- QUOTAS
+-- ============================================================
+-- Per-user overrides of the configured default quotas.
+-- NULL = use the default, negative = unlimited.
+CREATE TABLE IF NOT EXISTS user_quotas (
+    user_id         TEXT PRIMARY KEY NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    max_portfolios  INTEGER,
+    max_wallets     INTEGER,
+    monthly_emails  INTEGER,
+    max_watched_addresses INTEGER,
+    max_api_keys    INTEGER,
+    max_webhook_endpoints INTEGER,
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- Metered usage per calendar month (e.g. emails sent)
+CREATE TABLE IF NOT EXISTS usage_counters (
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    resource        TEXT NOT NULL,
+    period          TEXT NOT NULL,  -- YYYY-MM
+    count           INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, resource, period)
+);
+
 -- ============================================================
 -- INVOICES
 -- ============================================================
+-- Customer directory per portfolio; invoices copy the name and email they were issued with
+CREATE TABLE IF NOT EXISTS customers (
+    id                  TEXT PRIMARY KEY NOT NULL,
+    portfolio_id        TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
+    name                TEXT NOT NULL,
+    email               TEXT,
+    company             TEXT,
+    billing_address     TEXT,
+    tax_id              TEXT,
+    default_currency    TEXT,               -- fiat currency for new invoices
+    notes               TEXT,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_customers_portfolio_name ON customers(portfolio_id, name);
+
 CREATE TABLE IF NOT EXISTS invoices (
     id                  TEXT PRIMARY KEY NOT NULL,
     portfolio_id        TEXT NOT NULL REFERENCES portfolios(id) ON DELETE CASCADE,
@@ -132,21 +598,43 @@ CREATE TABLE IF NOT EXISTS invoices (
     invoice_number      TEXT,
     customer_name       TEXT,
     customer_email      TEXT,
+    customer_id         TEXT REFERENCES customers(id) ON DELETE SET NULL,
     description         TEXT,
     amount_sat          INTEGER NOT NULL DEFAULT 0,
     amount_fiat         REAL,
     fiat_currency       TEXT NOT NULL DEFAULT 'usd',
     btc_price_at_creation REAL,
+    fiat_denominated    INTEGER NOT NULL DEFAULT 0,  -- amount_sat is requoted from amount_fiat
+    rate_btc_price      REAL,               -- price amount_sat was last quoted at
+    rate_locked_at      TEXT,
+    rate_expires_at     TEXT,               -- requoted on the next payment page load after this
     btc_address         TEXT NOT NULL,
     wallet_id           TEXT REFERENCES wallets(id) ON DELETE SET NULL,
     status              TEXT NOT NULL DEFAULT 'draft',
     share_token         TEXT NOT NULL UNIQUE,
+    share_enabled       INTEGER NOT NULL DEFAULT 1,  -- 0 takes the public payment page down
     issued_at           TEXT,
     due_at              TEXT,
     expires_at          TEXT,
     paid_at             TEXT,
     paid_txid           TEXT,
     paid_amount_sat     INTEGER,
+    paid_via            TEXT,               -- 'onchain' or 'lightning'
+    lightning_invoice   TEXT,               -- BOLT11 issued by the configured Lightning backend
+    lightning_payment_hash TEXT,
+    lightning_expires_at TEXT,
+    address_keychain    TEXT,               -- set when btc_address was derived from wallet_id
+    address_index       INTEGER,
+    address_derivation_path TEXT,           -- comma-separated when the script has several keys
+    address_proof       INTEGER NOT NULL DEFAULT 0,  -- publish the wallet's public descriptor
+    min_confirmations   INTEGER NOT NULL DEFAULT 1,  -- before a payment marks the invoice paid
+    sent_at             TEXT,               -- first emailed to the customer
+    last_sent_at        TEXT,
+    send_count          INTEGER NOT NULL DEFAULT 0,
+    reminder_days_before INTEGER,           -- email a reminder this many days before due_at
+    reminder_sent_at    TEXT,
+    view_count          INTEGER NOT NULL DEFAULT 0,  -- payment page views, see invoice_views
+    last_viewed_at      TEXT,
     created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
     updated_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
@@ -154,6 +642,89 @@ CREATE INDEX IF NOT EXISTS idx_invoices_portfolio_id ON invoices(portfolio_id);
 CREATE INDEX IF NOT EXISTS idx_invoices_status ON invoices(status);
 CREATE INDEX IF NOT EXISTS idx_invoices_share_token ON invoices(share_token);
 CREATE INDEX IF NOT EXISTS idx_invoices_btc_address ON invoices(btc_address);
+CREATE INDEX IF NOT EXISTS idx_invoices_portfolio_created ON invoices(portfolio_id, created_at, id);
+
+-- Communication log per invoice: internal merchant notes and messages shown on the public page
+CREATE TABLE IF NOT EXISTS invoice_notes (
+    id                  TEXT PRIMARY KEY NOT NULL,
+    invoice_id          TEXT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    author              TEXT NOT NULL CHECK(author IN ('merchant', 'customer')),
+    author_user_id      TEXT REFERENCES users(id) ON DELETE SET NULL,
+    author_name         TEXT,
+    visibility          TEXT NOT NULL CHECK(visibility IN ('internal', 'public')),
+    body                TEXT NOT NULL,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_invoice_notes_invoice_id ON invoice_notes(invoice_id, created_at);
+
+-- Anonymous payment page views. Repeat loads by the same kind of browser within a
+-- while count as one view, so the page polling for payment doesn't inflate the count.
+CREATE TABLE IF NOT EXISTS invoice_views (
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    invoice_id          TEXT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    user_agent          TEXT NOT NULL,      -- coarse: browser and OS, e.g. "Firefox on Android"
+    viewed_at           TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_invoice_views_invoice_id ON invoice_views(invoice_id, viewed_at);
+
+-- Transactions that paid less than an invoice's amount, so each is announced once
+CREATE TABLE IF NOT EXISTS invoice_partial_payments (
+    invoice_id          TEXT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    txid                TEXT NOT NULL,
+    amount_sat          INTEGER NOT NULL,
+    seen_at             TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    PRIMARY KEY (invoice_id, txid)
+);
+
+-- Timeline of an invoice: status changes, with who made them, and what happened in
+-- between (views, resends, partial payments)
+CREATE TABLE IF NOT EXISTS invoice_events (
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    invoice_id          TEXT NOT NULL REFERENCES invoices(id) ON DELETE CASCADE,
+    event               TEXT NOT NULL,
+    from_status         TEXT,               -- both NULL unless the status changed
+    to_status           TEXT,
+    actor               TEXT NOT NULL CHECK(actor IN ('merchant', 'customer', 'system')),
+    actor_user_id       TEXT REFERENCES users(id) ON DELETE SET NULL,
+    detail              TEXT,               -- JSON, e.g. the txid and amount of a payment
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_invoice_events_invoice_id ON invoice_events(invoice_id, id);
+
+-- ============================================================
+-- WEBHOOKS
+-- Merchant endpoints that receive signed invoice events
+-- ============================================================
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id                  TEXT PRIMARY KEY NOT NULL,
+    user_id             TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    url                 TEXT NOT NULL,
+    secret              TEXT NOT NULL,
+    event_types         TEXT,               -- comma-separated; NULL subscribes to all
+    description         TEXT,
+    is_active           INTEGER NOT NULL DEFAULT 1,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_webhook_endpoints_user_id ON webhook_endpoints(user_id);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id                  TEXT PRIMARY KEY NOT NULL,
+    endpoint_id         TEXT NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    event_id            INTEGER NOT NULL,   -- domain_events.id it was made from
+    event_type          TEXT NOT NULL,
+    payload             TEXT NOT NULL,      -- the exact JSON body sent
+    status              TEXT NOT NULL DEFAULT 'pending' CHECK(status IN ('pending', 'delivered', 'failed')),
+    attempts            INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at     TEXT,               -- NULL once delivered or given up
+    last_status_code    INTEGER,
+    last_error          TEXT,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    delivered_at        TEXT,
+    UNIQUE(endpoint_id, event_id)
+);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_endpoint ON webhook_deliveries(endpoint_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
 
 -- ============================================================
 -- PRICE HISTORY
@@ -167,6 +738,13 @@ CREATE TABLE IF NOT EXISTS price_history (
     PRIMARY KEY (date, currency)
 );
 
+-- Latest BTC spot price fetched per currency, served when the providers are down
+CREATE TABLE IF NOT EXISTS spot_prices (
+    currency        TEXT PRIMARY KEY NOT NULL,
+    price           REAL NOT NULL,
+    fetched_at      TEXT NOT NULL
+);
+
 -- ============================================================
 -- ALERTS
 -- ============================================================
@@ -186,6 +764,37 @@ CREATE TABLE IF NOT EXISTS alerts (
 CREATE INDEX IF NOT EXISTS idx_alerts_user_id ON alerts(user_id);
 CREATE INDEX IF NOT EXISTS idx_alerts_active ON alerts(is_active, alert_type);
 
+-- ============================================================
+-- WATCHED ADDRESSES
+-- Third-party addresses tracked for activity only; never part of a portfolio or cost basis
+-- ============================================================
+CREATE TABLE IF NOT EXISTS watched_addresses (
+    id                  TEXT PRIMARY KEY NOT NULL,
+    user_id             TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    address             TEXT NOT NULL,
+    network             TEXT NOT NULL DEFAULT 'bitcoin',
+    label               TEXT,
+    notify              INTEGER NOT NULL DEFAULT 1,
+    tx_count            INTEGER NOT NULL DEFAULT 0,
+    total_received_sat  INTEGER NOT NULL DEFAULT 0,
+    balance_sat         INTEGER NOT NULL DEFAULT 0,
+    last_checked_at     TEXT,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    UNIQUE(user_id, address)
+);
+CREATE INDEX IF NOT EXISTS idx_watched_addresses_user_id ON watched_addresses(user_id);
+
+CREATE TABLE IF NOT EXISTS watched_address_txs (
+    watch_id            TEXT NOT NULL REFERENCES watched_addresses(id) ON DELETE CASCADE,
+    txid                TEXT NOT NULL,
+    net_sat             INTEGER NOT NULL,  -- received minus spent by the address
+    block_height        INTEGER,           -- NULL while unconfirmed
+    block_time          TEXT,
+    first_seen_at       TEXT NOT NULL,
+    PRIMARY KEY (watch_id, txid)
+);
+
 -- ============================================================
 -- BILLING
 -- ============================================================
@@ -206,3 +815,40 @@ CREATE TABLE IF NOT EXISTS stripe_events (
     event_id     TEXT PRIMARY KEY NOT NULL,
     processed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
 );
+
+-- ============================================================
+-- DOMAIN EVENTS (written in the same SQLite transaction as the change)
+-- ============================================================
+CREATE TABLE IF NOT EXISTS domain_events (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_type      TEXT NOT NULL,  -- transaction.* | invoice.paid | invoice.partially_paid | invoice.expired | wallet.synced
+    portfolio_id    TEXT NOT NULL,
+    payload         TEXT NOT NULL,  -- JSON of the event
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+CREATE INDEX IF NOT EXISTS idx_domain_events_portfolio ON domain_events(portfolio_id, id);
+
+-- How far each background consumer has processed the event log
+CREATE TABLE IF NOT EXISTS domain_event_cursors (
+    consumer        TEXT PRIMARY KEY NOT NULL,
+    last_event_id   INTEGER NOT NULL DEFAULT 0,
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- ============================================================
+-- BACKGROUND JOBS (long account-level work, reported by email)
+-- ============================================================
+CREATE TABLE IF NOT EXISTS jobs (
+    id              TEXT PRIMARY KEY NOT NULL,
+    user_id         TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind            TEXT NOT NULL CHECK (kind IN ('account_export', 'price_backfill')),
+    status          TEXT NOT NULL DEFAULT 'running',  -- running | completed | failed | interrupted
+    result          TEXT,               -- JSON summary
+    error           TEXT,
+    file_path       TEXT,               -- cleared once the download has expired and the file is deleted
+    expires_at      TEXT,
+    created_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    updated_at      TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    finished_at     TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_jobs_user_id ON jobs(user_id, created_at);
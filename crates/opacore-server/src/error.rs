@@ -1,4 +1,4 @@
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
 
@@ -27,6 +27,18 @@ pub enum AppError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+
+    /// An outbound call to the named upstream (esplora, coingecko, ...) timed out.
+    #[error("Upstream timed out: {0}")]
+    UpstreamTimeout(String),
+
+    /// Too many attempts; the client may retry after the given number of seconds.
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String, u64),
+
+    /// The request as a whole ran past its deadline.
+    #[error("Request timed out")]
+    Timeout,
 }
 
 impl IntoResponse for AppError {
@@ -49,6 +61,21 @@ impl IntoResponse for AppError {
                 tracing::error!("Internal error: {msg}");
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
             }
+            AppError::UpstreamTimeout(upstream) => {
+                tracing::warn!("Upstream {upstream} timed out");
+                let body = json!({ "error": "Upstream service timed out", "upstream": upstream });
+                return (StatusCode::GATEWAY_TIMEOUT, axum::Json(body)).into_response();
+            }
+            AppError::TooManyRequests(msg, retry_after_secs) => {
+                let body = json!({ "error": msg, "retry_after_secs": retry_after_secs });
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    axum::Json(body),
+                )
+                    .into_response();
+            }
+            AppError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "Request timed out".to_string()),
         };
 
         let body = json!({ "error": message });
@@ -5,6 +5,7 @@ mod models;
 mod services;
 mod auth;
 mod routes;
+mod telemetry;
 
 use config::Config;
 use routes::{AppState, create_router};
@@ -12,7 +13,6 @@ use axum::http::{header, HeaderValue, Method};
 use std::net::SocketAddr;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() {
@@ -20,37 +20,122 @@ async fn main() {
     dotenvy::from_filename("../../.env").ok();
     dotenvy::dotenv().ok();
 
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| EnvFilter::new("opacore_server=debug,tower_http=debug")),
-        )
-        .init();
-
     let config = Config::from_env();
+
+    // Initialize tracing (logs, plus OTLP span export if configured)
+    telemetry::init(&config);
     let port = config.server_port;
 
     // Create database pool and run migrations
     let pool = db::create_pool(&config.sqlite_path);
     tracing::info!("Database initialized at {}", config.sqlite_path);
+    tracing::info!("Signing sessions with key '{}'", config.session_keys.signing_key_id());
+
+    // Sync jobs left running by a previous process resume on their next sync
+    services::sync::mark_interrupted_sync_jobs(&pool);
+    // Background jobs don't resume; clear them and any downloads past their expiry
+    services::jobs::mark_interrupted_jobs(&pool);
+    services::jobs::prune_expired_downloads(&pool);
+    services::admin::promote_configured_admin(&pool, config.admin_email.as_deref());
 
     // Build app state
     let state = AppState {
         db: pool,
         config: config.clone(),
+        esplora: services::esplora::EsploraBackends::from_config(&config),
+        cache: services::cache::AppCache::new(config.current_price_ttl),
+        sync_progress: services::sync_progress::SyncProgressHub::default(),
+        sync_locks: services::sync_lock::WalletSyncLocks::default(),
     };
 
-    // Spawn background invoice payment checker
+    // Spawn Esplora backend health checker (demotes dead instances for failover)
+    tokio::spawn(services::esplora::run_health_checker(
+        state.esplora.clone(),
+        config.intervals.esplora_health_check,
+    ));
+
+    // Spawn mempool.space WebSocket address watch (instant invoice payment detection), if configured
+    let ws_status = services::mempool_ws::WatchStatus::default();
+    if let Some(ws_url) = config.mempool_ws_url.clone() {
+        tokio::spawn(services::mempool_ws::run_address_watch(
+            state.db.clone(),
+            state.esplora.clone(),
+            state.cache.clone(),
+            ws_url,
+            ws_status.clone(),
+            config.intervals.mempool_ws_refresh,
+        ));
+    }
+
+    // Spawn Lightning settlement watch (marks invoices paid as their BOLT11s settle), if configured
+    if let Some(lightning) = config.lightning.clone() {
+        tokio::spawn(services::lightning::run_settlement_watch(state.db.clone(), lightning));
+    }
+
+    // Spawn background invoice payment checker (falls back to polling when the watch is down)
     tokio::spawn(services::invoice_checker::run_invoice_checker(
         state.db.clone(),
-        state.config.esplora_url.clone(),
+        state.esplora.clone(),
+        state.cache.clone(),
+        ws_status,
+        config.lightning.clone(),
+        config.intervals.clone(),
     ));
 
-    // Spawn background alert checker (price + balance alerts, every 5 minutes)
+    // Spawn background alert checker (price + balance alerts)
     tokio::spawn(services::alerts::run_alert_checker(
         state.db.clone(),
         state.config.clone(),
+        state.cache.clone(),
+    ));
+
+    // Spawn watched address checker (third-party addresses)
+    tokio::spawn(services::watch::run_watch_checker(
+        state.db.clone(),
+        state.config.clone(),
+        state.esplora.clone(),
+    ));
+
+    // Spawn exchange trade importer (read-only API connections)
+    tokio::spawn(services::exchange_api::run_exchange_sync(
+        state.db.clone(),
+        state.config.clone(),
+    ));
+
+    // Spawn domain event dispatcher (price backfill, payment notifications)
+    tokio::spawn(services::events::run_event_dispatcher(
+        state.db.clone(),
+        state.config.clone(),
+    ));
+
+    // Spawn attachment purger (stored files of deleted attachments)
+    tokio::spawn(services::attachments::run_attachment_purger(
+        state.db.clone(),
+        state.config.clone(),
+    ));
+
+    // Spawn recurring transaction materializer (scheduled manual entries)
+    tokio::spawn(services::recurring::run_recurring_materializer(
+        state.db.clone(),
+        state.config.clone(),
+    ));
+
+    // Spawn webhook delivery worker (signed invoice events, retried with backoff)
+    tokio::spawn(services::webhooks::run_webhook_deliveries(
+        state.db.clone(),
+        state.config.intervals.clone(),
+    ));
+
+    // Spawn invoice reminder emails (unpaid invoices nearing their due date)
+    tokio::spawn(services::invoice_emails::run_invoice_reminders(
+        state.db.clone(),
+        state.config.clone(),
+    ));
+
+    // Spawn session purger (expired sign-ins)
+    tokio::spawn(auth::session::run_session_purger(
+        state.db.clone(),
+        state.config.intervals.session_purge,
     ));
 
     // Backfill missing transaction prices at startup (Kraken + blockchain.info, no key required)
@@ -63,11 +148,35 @@ async fn main() {
     let cors = CorsLayer::new()
         .allow_origin(config.cors_origin.parse::<HeaderValue>().unwrap())
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS])
-        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, header::COOKIE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::COOKIE,
+            header::HeaderName::from_static(auth::middleware::CSRF_HEADER),
+        ])
         .allow_credentials(true);
 
+    // Request spans join the caller's trace via `traceparent` and record the response status
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(|req: &axum::http::Request<_>| {
+            tracing::debug_span!(
+                "request",
+                method = %req.method(),
+                uri = %req.uri(),
+                version = ?req.version(),
+                otel.name = %format!("{} {}", req.method(), req.uri().path()),
+                otel.kind = "server",
+                traceparent = req.headers().get("traceparent").and_then(|v| v.to_str().ok()).unwrap_or(""),
+                http.status_code = tracing::field::Empty,
+            )
+        })
+        .on_response(|res: &axum::http::Response<_>, latency: std::time::Duration, span: &tracing::Span| {
+            span.record("http.status_code", res.status().as_u16());
+            tracing::debug!(latency = ?latency, status = res.status().as_u16(), "finished processing request");
+        });
+
     let app = create_router(state)
-        .layer(TraceLayer::new_for_http())
+        .layer(trace)
         .layer(cors);
 
     // Start server
@@ -9,6 +9,9 @@ pub struct User {
     pub password_hash: String,
     pub default_currency: String,
     pub email_verified: bool,
+    pub is_admin: bool,
+    /// Set while an admin has the account disabled; it can't sign in or use API keys.
+    pub disabled_at: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -20,6 +23,7 @@ pub struct UserPublic {
     pub name: String,
     pub default_currency: String,
     pub email_verified: bool,
+    pub is_admin: bool,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -32,6 +36,7 @@ impl From<User> for UserPublic {
             name: u.name,
             default_currency: u.default_currency,
             email_verified: u.email_verified,
+            is_admin: u.is_admin,
             created_at: u.created_at,
             updated_at: u.updated_at,
         }
@@ -47,4 +52,6 @@ pub struct Session {
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
     pub created_at: String,
+    /// Updated at most every few minutes; see `session::validate_session`.
+    pub last_used_at: Option<String>,
 }
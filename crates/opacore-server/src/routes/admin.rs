@@ -0,0 +1,238 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::verification;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::audit::{self, AuditLogEntry, AuditLogQuery};
+use crate::services::{self, admin::UsageStats};
+
+/// Sync failures counted against each user in the list.
+const SYNC_FAILURE_WINDOW_DAYS: i64 = 7;
+
+#[derive(Debug, Serialize)]
+pub struct AdminUser {
+    pub id: String,
+    pub email: String,
+    pub name: String,
+    pub email_verified: bool,
+    pub is_admin: bool,
+    pub disabled_at: Option<String>,
+    pub portfolio_count: i64,
+    pub wallet_count: i64,
+    pub transaction_count: i64,
+    /// Failed wallet syncs in the last 7 days.
+    pub sync_failures: i64,
+    /// When one of their sessions was last used.
+    pub last_active_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserList {
+    pub data: Vec<AdminUser>,
+    pub total: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListUsersQuery {
+    /// Part of an email or name.
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminAuditLogQuery {
+    pub user_id: Option<String>,
+    pub action: Option<String>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Selects users with their usage; binds the start of the sync failure window to `?1`.
+const ADMIN_USER_COLS: &str = "u.id, u.email, u.name, u.email_verified, u.is_admin, u.disabled_at,
+    (SELECT COUNT(*) FROM portfolios p WHERE p.user_id = u.id),
+    (SELECT COUNT(*) FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE p.user_id = u.id),
+    (SELECT COUNT(*) FROM transactions t JOIN portfolios p ON p.id = t.portfolio_id WHERE p.user_id = u.id),
+    (SELECT COUNT(*) FROM sync_jobs j JOIN wallets w ON w.id = j.wallet_id JOIN portfolios p ON p.id = w.portfolio_id
+        WHERE p.user_id = u.id AND j.status = 'failed' AND COALESCE(j.finished_at, j.updated_at) > ?1),
+    (SELECT MAX(COALESCE(s.last_used_at, s.created_at)) FROM sessions s WHERE s.user_id = u.id),
+    u.created_at";
+
+fn row_to_admin_user(row: &rusqlite::Row) -> rusqlite::Result<AdminUser> {
+    Ok(AdminUser {
+        id: row.get(0)?,
+        email: row.get(1)?,
+        name: row.get(2)?,
+        email_verified: row.get::<_, i32>(3)? != 0,
+        is_admin: row.get::<_, i32>(4)? != 0,
+        disabled_at: row.get(5)?,
+        portfolio_count: row.get(6)?,
+        wallet_count: row.get(7)?,
+        transaction_count: row.get(8)?,
+        sync_failures: row.get(9)?,
+        last_active_at: row.get(10)?,
+        created_at: row.get(11)?,
+    })
+}
+
+fn failure_window_start() -> String {
+    (chrono::Utc::now() - chrono::Duration::days(SYNC_FAILURE_WINDOW_DAYS))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+fn load_admin_user(conn: &rusqlite::Connection, user_id: &str) -> AppResult<AdminUser> {
+    conn.query_row(
+        &format!("SELECT {ADMIN_USER_COLS} FROM users u WHERE u.id = ?2"),
+        rusqlite::params![failure_window_start(), user_id],
+        row_to_admin_user,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("User not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// GET /api/v1/admin/users?q=&limit=50&offset=0
+///
+/// Newest sign-ups first.
+pub async fn list_users(
+    State(state): State<AppState>,
+    Query(query): Query<ListUsersQuery>,
+) -> AppResult<Json<AdminUserList>> {
+    let conn = state.db.get()?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let pattern = format!("%{}%", query.q.as_deref().unwrap_or("").trim());
+
+    let total: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM users u WHERE u.email LIKE ?1 OR u.name LIKE ?1",
+        rusqlite::params![pattern],
+        |row| row.get(0),
+    )?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ADMIN_USER_COLS} FROM users u WHERE u.email LIKE ?2 OR u.name LIKE ?2
+         ORDER BY u.created_at DESC, u.id LIMIT ?3 OFFSET ?4"
+    ))?;
+    let data = stmt
+        .query_map(
+            rusqlite::params![failure_window_start(), pattern, limit, offset],
+            row_to_admin_user,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(AdminUserList { data, total }))
+}
+
+/// GET /api/v1/admin/users/{user_id}
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> AppResult<Json<AdminUser>> {
+    let conn = state.db.get()?;
+    Ok(Json(load_admin_user(&conn, &user_id)?))
+}
+
+/// GET /api/v1/admin/stats
+pub async fn stats(State(state): State<AppState>) -> AppResult<Json<UsageStats>> {
+    let conn = state.db.get()?;
+    Ok(Json(services::admin::usage_stats(&conn)?))
+}
+
+/// GET /api/v1/admin/audit-log?user_id=&action=&before_id=&limit=100
+///
+/// Every user's entries, newest first, unless `user_id` is given.
+pub async fn audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AdminAuditLogQuery>,
+) -> AppResult<Json<Vec<AuditLogEntry>>> {
+    let conn = state.db.get()?;
+    let filter = AuditLogQuery {
+        action: query.action,
+        before_id: query.before_id,
+        limit: query.limit,
+    };
+    Ok(Json(audit::list(&conn, query.user_id.as_deref(), &filter)?))
+}
+
+/// POST /api/v1/admin/users/{user_id}/disable
+///
+/// Signs the user out everywhere; their sessions, API keys and sign-ins are refused
+/// until they are enabled again. Their data and background work are left alone.
+pub async fn disable_user(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    Path(user_id): Path<String>,
+) -> AppResult<Json<AdminUser>> {
+    if user_id == admin.id {
+        return Err(AppError::BadRequest("You can't disable your own account".into()));
+    }
+    let mut conn = state.db.get()?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let tx = conn.transaction()?;
+    let updated = tx.execute(
+        "UPDATE users SET disabled_at = COALESCE(disabled_at, ?1), updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![now, user_id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    let sessions = tx.execute("DELETE FROM sessions WHERE user_id = ?1", rusqlite::params![user_id])?;
+    tx.commit()?;
+    state.cache.invalidate_user(&user_id);
+    tracing::info!("Admin {} disabled user {user_id} ({sessions} sessions ended)", admin.id);
+
+    Ok(Json(load_admin_user(&conn, &user_id)?))
+}
+
+/// POST /api/v1/admin/users/{user_id}/enable
+pub async fn enable_user(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    Path(user_id): Path<String>,
+) -> AppResult<Json<AdminUser>> {
+    let conn = state.db.get()?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let updated = conn.execute(
+        "UPDATE users SET disabled_at = NULL, updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![now, user_id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    state.cache.invalidate_user(&user_id);
+    tracing::info!("Admin {} enabled user {user_id}", admin.id);
+
+    Ok(Json(load_admin_user(&conn, &user_id)?))
+}
+
+/// POST /api/v1/admin/users/{user_id}/resend-verification
+///
+/// Unlike the public endpoint this says what happened, and doesn't count against the
+/// user's email quota.
+pub async fn resend_verification(
+    State(state): State<AppState>,
+    Extension(admin): Extension<User>,
+    Path(user_id): Path<String>,
+) -> AppResult<Json<serde_json::Value>> {
+    let user = {
+        let conn = state.db.get()?;
+        load_admin_user(&conn, &user_id)?
+    };
+    if user.email_verified {
+        return Err(AppError::Conflict("The user's email is already verified".into()));
+    }
+
+    let token = verification::create_verification_token(&state.db, &user.id)?;
+    services::email::send_verification_email(&state.config, &user.email, &user.name, &token).await?;
+    tracing::info!("Admin {} resent the verification email of user {user_id}", admin.id);
+
+    Ok(Json(serde_json::json!({
+        "message": format!("A verification link has been sent to {}.", user.email),
+    })))
+}
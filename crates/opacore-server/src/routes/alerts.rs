@@ -6,6 +6,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access};
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
@@ -96,12 +97,10 @@ pub async fn create(
                 ));
             }
         }
-        "balance_change" => {
-            if body.wallet_id.is_none() && body.portfolio_id.is_none() {
-                return Err(AppError::BadRequest(
-                    "balance_change alerts require wallet_id or portfolio_id".into(),
-                ));
-            }
+        "balance_change" if body.wallet_id.is_none() && body.portfolio_id.is_none() => {
+            return Err(AppError::BadRequest(
+                "balance_change alerts require wallet_id or portfolio_id".into(),
+            ));
         }
         _ => {}
     }
@@ -109,29 +108,11 @@ pub async fn create(
     let conn = state.db.get()?;
 
     if let Some(ref portfolio_id) = body.portfolio_id {
-        let exists: bool = conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-            rusqlite::params![portfolio_id, user.id],
-            |row| row.get(0),
-        )?;
-        if !exists {
-            return Err(AppError::NotFound("Portfolio not found".into()));
-        }
+        policy::portfolio(&state.cache, &conn, &user.id, portfolio_id, Access::Read)?;
     }
 
     if let Some(ref wallet_id) = body.wallet_id {
-        let exists: bool = conn.query_row(
-            "SELECT EXISTS(
-                SELECT 1 FROM wallets w
-                JOIN portfolios p ON p.id = w.portfolio_id
-                WHERE w.id = ?1 AND p.user_id = ?2
-             )",
-            rusqlite::params![wallet_id, user.id],
-            |row| row.get(0),
-        )?;
-        if !exists {
-            return Err(AppError::NotFound("Wallet not found".into()));
-        }
+        policy::wallet_by_id(&state.cache, &conn, &user.id, wallet_id, Access::Read)?;
     }
 
     let id = Uuid::new_v4().to_string();
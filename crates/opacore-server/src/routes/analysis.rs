@@ -2,79 +2,370 @@ use axum::{
     extract::{Path, Query, State},
     Extension, Json,
 };
-use serde::Deserialize;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
 
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+use crate::auth::policy::{self, Access};
 use crate::models::User;
 use crate::routes::AppState;
-use crate::services::costbasis::{self, CostBasisMethod};
-use crate::services::prices;
+use crate::services::business::{self, MetricsPeriod};
+use crate::services::costbasis::{self, CostBasisMethod, InCurrency, PortfolioDefaults};
+use crate::services::tax::{self, TaxRules};
+use crate::services::{benchmark, cash_flow, dashboard, dca, fee_analytics, holdings_age, label_breakdown, networth, prices, value_history};
 
 #[derive(Debug, Deserialize)]
 pub struct CostBasisQuery {
     pub method: Option<CostBasisMethod>,
     pub year: Option<i32>,
+    /// Recalculate rather than reuse a cached result.
+    #[serde(default)]
+    pub force: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SummaryQuery {
     pub method: Option<CostBasisMethod>,
+    /// Recalculate rather than reuse a cached result.
+    #[serde(default)]
+    pub force: bool,
 }
 
-/// GET /api/v1/portfolios/:id/cost-basis?method=fifo&year=2024
+#[derive(Debug, Deserialize)]
+pub struct ValueHistoryQuery {
+    /// `YYYY-MM-DD`.
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+    /// Defaults to the portfolio's currency.
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DateRangeQuery {
+    /// `YYYY-MM-DD`, inclusive.
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NetWorthQuery {
+    /// Defaults to the user's default currency.
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LotsResponse {
+    /// The portfolio's currency, which the amounts below are in.
+    pub currency: String,
+    pub current_price_usd: f64,
+    pub total_unrealized_gain_usd: f64,
+    /// Sum of the lots now worth less than they cost: the losses selling could realize.
+    pub unrealized_loss_usd: f64,
+    pub lots: Vec<costbasis::UnrealizedLot>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BusinessMetricsQuery {
+    pub period: Option<MetricsPeriod>,
+}
+
+/// GET /api/v1/portfolios/:id/cost-basis?method=fifo&year=2024&force=true
+///
+/// In the portfolio's currency, with its method unless `method` is given.
 pub async fn cost_basis(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path(portfolio_id): Path<String>,
     Query(query): Query<CostBasisQuery>,
-) -> AppResult<Json<costbasis::CostBasisResult>> {
-    // Verify ownership
-    let conn = state.db.get()?;
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user.id],
-        |row| row.get(0),
+) -> AppResult<Json<InCurrency<costbasis::CostBasisResult>>> {
+    let (rules, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    let method = query.method.unwrap_or(defaults.method);
+    let result = costbasis::calculate_cost_basis(
+        &state.db,
+        (!query.force).then_some(&state.cache),
+        &portfolio_id,
+        method,
+        &defaults.currency,
+        query.year,
+        &rules,
     )?;
-    if !exists {
-        return Err(crate::error::AppError::NotFound("Portfolio not found".into()));
-    }
+
+    Ok(Json(InCurrency { currency: defaults.currency, value: result }))
+}
+
+/// GET /api/v1/portfolios/:id/summary?method=fifo&force=true
+pub async fn summary(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<SummaryQuery>,
+) -> AppResult<Json<InCurrency<costbasis::PortfolioSummary>>> {
+    let (rules, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    // Get current BTC price — fall back to most recent cached price if live fetch fails
+    let current_price = prices::current_price(
+        &state.db,
+        &state.cache,
+        &state.config.coingecko_api_url,
+        &defaults.currency,
+    )
+    .await
+    .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, &defaults.currency).unwrap_or(0.0));
+
+    let method = query.method.unwrap_or(defaults.method);
+    let result = costbasis::portfolio_summary(
+        &state.db,
+        (!query.force).then_some(&state.cache),
+        &portfolio_id,
+        current_price,
+        method,
+        &defaults.currency,
+        &rules,
+    )?;
+
+    Ok(Json(InCurrency { currency: defaults.currency, value: result }))
+}
+
+/// GET /api/v1/portfolios/:id/lots?method=fifo
+///
+/// Each lot still held, oldest first, with its unrealized gain and whether selling it
+/// today would be long-term.
+pub async fn lots(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<SummaryQuery>,
+) -> AppResult<Json<LotsResponse>> {
+    let (rules, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    let current_price = prices::current_price(
+        &state.db,
+        &state.cache,
+        &state.config.coingecko_api_url,
+        &defaults.currency,
+    )
+    .await
+    .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, &defaults.currency).unwrap_or(0.0));
+
+    let method = query.method.unwrap_or(defaults.method);
+    let lots = costbasis::unrealized_lots(&state.db, &portfolio_id, method, &defaults.currency, current_price, &rules)?;
+
+    Ok(Json(LotsResponse {
+        currency: defaults.currency,
+        current_price_usd: current_price,
+        total_unrealized_gain_usd: lots.iter().map(|l| l.unrealized_gain_usd).sum(),
+        unrealized_loss_usd: lots.iter().map(|l| l.unrealized_gain_usd.min(0.0)).sum(),
+        lots,
+    }))
+}
+
+/// GET /api/v1/portfolios/:id/business-metrics?period=month
+pub async fn business_metrics(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<BusinessMetricsQuery>,
+) -> AppResult<Json<business::BusinessMetrics>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
     drop(conn);
 
-    let method = query.method.unwrap_or_default();
-    let result = costbasis::calculate_cost_basis(&state.db, &portfolio_id, method, query.year)?;
+    let period = query.period.unwrap_or_default();
+    let result = business::business_metrics(&state.db, &portfolio_id, period)?;
 
     Ok(Json(result))
 }
 
-/// GET /api/v1/portfolios/:id/summary?method=fifo
-pub async fn summary(
+/// GET /api/v1/portfolios/:id/value-history?start=2024-01-01&end=2024-12-31&currency=usd
+pub async fn value_history(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path(portfolio_id): Path<String>,
-    Query(query): Query<SummaryQuery>,
-) -> AppResult<Json<costbasis::PortfolioSummary>> {
-    // Verify ownership
+    Query(query): Query<ValueHistoryQuery>,
+) -> AppResult<Json<value_history::ValueHistory>> {
+    let (_, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    let currency = match query.currency {
+        Some(currency) => prices::currency_code(&currency)
+            .ok_or_else(|| AppError::BadRequest("currency must be a 3-letter currency code".into()))?,
+        None => defaults.currency,
+    };
+    validate_range(query.start, query.end)?;
+    let history = value_history::value_history(&state.db, &portfolio_id, &currency, query.start, query.end)?;
+
+    Ok(Json(history))
+}
+
+/// GET /api/v1/portfolios/:id/analysis/by-label?start=2024-01-01&end=2024-12-31
+///
+/// Totals by label and transaction type, e.g. what was spent on hosting in a year.
+pub async fn by_label(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<DateRangeQuery>,
+) -> AppResult<Json<label_breakdown::LabelBreakdown>> {
     let conn = state.db.get()?;
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user.id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(crate::error::AppError::NotFound("Portfolio not found".into()));
-    }
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
     drop(conn);
 
-    // Get current BTC price — fall back to most recent cached price if live fetch fails
-    let current_price = prices::fetch_current_price(
+    validate_range(query.start, query.end)?;
+    let result = label_breakdown::label_breakdown(&state.db, &portfolio_id, query.start, query.end)?;
+
+    Ok(Json(result))
+}
+
+/// GET /api/v1/portfolios/:id/analysis/cash-flow?start=2024-01-01&end=2024-12-31
+///
+/// Monthly inflow, outflow and net, overall and split between synced and manual
+/// transactions, in the portfolio's currency.
+pub async fn cash_flow(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<DateRangeQuery>,
+) -> AppResult<Json<cash_flow::CashFlow>> {
+    let (_, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    validate_range(query.start, query.end)?;
+    let result = cash_flow::cash_flow(&state.db, &portfolio_id, &defaults.currency, query.start, query.end)?;
+
+    Ok(Json(result))
+}
+
+/// GET /api/v1/portfolios/:id/analysis/dca?method=fifo&force=true
+///
+/// Average purchase price and sats per unit of currency over time, in the portfolio's
+/// currency. `method` only affects the break-even price.
+pub async fn dca(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<SummaryQuery>,
+) -> AppResult<Json<dca::DcaAnalysis>> {
+    let (rules, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    let method = query.method.unwrap_or(defaults.method);
+    let result = dca::dca_analysis(
+        &state.db,
+        (!query.force).then_some(&state.cache),
+        &portfolio_id,
+        method,
+        &defaults.currency,
+        &rules,
+    )?;
+
+    Ok(Json(result))
+}
+
+/// GET /api/v1/portfolios/:id/analysis/holdings-age?method=fifo
+///
+/// The balance by how long its lots have been held, and what turns long-term in the
+/// next 30, 60 and 90 days.
+pub async fn holdings_age(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<SummaryQuery>,
+) -> AppResult<Json<holdings_age::HoldingsAge>> {
+    let (rules, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    let current_price = prices::current_price(
+        &state.db,
+        &state.cache,
         &state.config.coingecko_api_url,
-        "usd",
+        &defaults.currency,
     )
     .await
-    .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, "usd").unwrap_or(0.0));
+    .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, &defaults.currency).unwrap_or(0.0));
+
+    let method = query.method.unwrap_or(defaults.method);
+    let result = holdings_age::holdings_age(&state.db, &portfolio_id, method, &defaults.currency, current_price, &rules)?;
 
-    let method = query.method.unwrap_or_default();
-    let result = costbasis::portfolio_summary(&state.db, &portfolio_id, current_price, method)?;
+    Ok(Json(result))
+}
+
+/// GET /api/v1/portfolios/:id/analysis/fees?start=2024-01-01&end=2024-12-31
+///
+/// Miner fees paid from the portfolio's wallets, per wallet and per month, in the
+/// portfolio's currency.
+pub async fn fees(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<DateRangeQuery>,
+) -> AppResult<Json<fee_analytics::FeeAnalytics>> {
+    let (_, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    validate_range(query.start, query.end)?;
+    let result = fee_analytics::fee_analytics(&state.db, &portfolio_id, &defaults.currency, query.start, query.end)?;
 
     Ok(Json(result))
 }
+
+/// GET /api/v1/portfolios/:id/analysis/benchmark
+///
+/// Daily value from the first transaction to today against lump-sum and DCA buy-and-hold,
+/// in the portfolio's currency.
+pub async fn benchmark(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<benchmark::Benchmark>> {
+    let (_, defaults) = portfolio_settings(&state, &user, &portfolio_id)?;
+
+    Ok(Json(benchmark::benchmark(&state.db, &portfolio_id, &defaults.currency)?))
+}
+
+/// GET /api/v1/dashboard
+///
+/// Balances, value, gains, pending invoices and wallet sync status across every
+/// portfolio the user can see, in one call.
+pub async fn dashboard(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<dashboard::Dashboard>> {
+    let current_price = prices::current_price(&state.db, &state.cache, &state.config.coingecko_api_url, "usd")
+        .await
+        .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, "usd").unwrap_or(0.0));
+
+    Ok(Json(dashboard::dashboard(&state.db, &state.cache, &user.id, current_price)?))
+}
+
+fn validate_range(start: Option<NaiveDate>, end: Option<NaiveDate>) -> AppResult<()> {
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return Err(AppError::BadRequest("start must not be after end".into()));
+        }
+    }
+    Ok(())
+}
+
+/// GET /api/v1/networth?currency=eur
+///
+/// Balances and values summed across every portfolio the user owns, with each one's part.
+pub async fn net_worth(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<NetWorthQuery>,
+) -> AppResult<Json<networth::NetWorth>> {
+    let currency = match query.currency {
+        Some(currency) => prices::currency_code(&currency)
+            .ok_or_else(|| AppError::BadRequest("currency must be a 3-letter currency code".into()))?,
+        None => user.default_currency.clone(),
+    };
+
+    let current_price = prices::current_price(&state.db, &state.cache, &state.config.coingecko_api_url, &currency)
+        .await
+        .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, &currency).unwrap_or(0.0));
+
+    Ok(Json(networth::net_worth(&state.db, &state.cache, &user.id, &currency, current_price)?))
+}
+
+/// Check the user can read the portfolio and return their tax rules and its defaults.
+fn portfolio_settings(state: &AppState, user: &User, portfolio_id: &str) -> AppResult<(TaxRules, PortfolioDefaults)> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, portfolio_id, Access::Read)?;
+    Ok((tax::tax_rules(&conn, &user.id)?, costbasis::portfolio_defaults(&conn, portfolio_id)?))
+}
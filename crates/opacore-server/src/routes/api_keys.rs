@@ -0,0 +1,174 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::api_keys::{self, Scope};
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::quotas::{self, Quota};
+
+const MAX_NAME_CHARS: usize = 100;
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(Debug, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Start of the token, e.g. `opk_AbC123xy`.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<String>,
+    /// `None` for keys that don't expire.
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+/// A key as returned once, at creation: the only time its token is shown.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    /// Any of "read", "transactions:write" and "invoices:write".
+    pub scopes: Vec<String>,
+    /// 1 to 365; the key never expires if unset.
+    pub expires_in_days: Option<i64>,
+}
+
+const API_KEY_COLS: &str = "id, name, prefix, scopes, last_used_at, expires_at, created_at";
+
+fn row_to_api_key(row: &rusqlite::Row) -> rusqlite::Result<ApiKey> {
+    let scopes: String = row.get(3)?;
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        prefix: row.get(2)?,
+        scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+        last_used_at: row.get(4)?,
+        expires_at: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+/// GET /api/v1/auth/api-keys
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<ApiKey>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {API_KEY_COLS} FROM api_keys WHERE user_id = ?1 ORDER BY created_at DESC"
+    ))?;
+    let keys = stmt
+        .query_map(rusqlite::params![user.id], row_to_api_key)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(keys))
+}
+
+/// POST /api/v1/auth/api-keys
+///
+/// Create a personal access token, sent as `Authorization: Bearer <token>`.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Json(body): Json<CreateApiKeyRequest>,
+) -> AppResult<impl IntoResponse> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AppError::BadRequest(format!("name must be 1 to {MAX_NAME_CHARS} characters")));
+    }
+    let mut scopes: Vec<&'static str> = Vec::new();
+    for requested in &body.scopes {
+        let scope = Scope::parse(requested.trim()).ok_or_else(|| {
+            let valid: Vec<&str> = Scope::ALL.iter().map(|s| s.name()).collect();
+            AppError::BadRequest(format!("Unknown scope '{requested}'; expected one of {}", valid.join(", ")))
+        })?;
+        if !scopes.contains(&scope.name()) {
+            scopes.push(scope.name());
+        }
+    }
+    if scopes.is_empty() {
+        return Err(AppError::BadRequest("An API key needs at least one scope".into()));
+    }
+    let expires_at = match body.expires_in_days {
+        None => None,
+        Some(days) if (1..=MAX_EXPIRY_DAYS).contains(&days) => Some(
+            (chrono::Utc::now() + chrono::Duration::days(days))
+                .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+                .to_string(),
+        ),
+        Some(_) => {
+            return Err(AppError::BadRequest(format!("expires_in_days must be from 1 to {MAX_EXPIRY_DAYS}")));
+        }
+    };
+
+    let conn = state.db.get()?;
+    quotas::check(&conn, &state.config, &user.id, Quota::ApiKeys)?;
+
+    let id = Uuid::new_v4().to_string();
+    let (token, prefix, token_hash) = api_keys::generate();
+    let scopes = serde_json::to_string(&scopes).unwrap_or_else(|_| "[]".to_string());
+    let key = conn.query_row(
+        &format!(
+            "INSERT INTO api_keys (id, user_id, name, prefix, token_hash, scopes, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING {API_KEY_COLS}"
+        ),
+        rusqlite::params![id, user.id, name, prefix, token_hash, scopes, expires_at],
+        row_to_api_key,
+    )?;
+    audit::record(
+        &conn,
+        &user.id,
+        AuditEvent::ApiKeyCreated,
+        Some(&id),
+        serde_json::json!({ "name": key.name, "prefix": key.prefix, "scopes": key.scopes }),
+        &client,
+    )?;
+    tracing::info!("User {} created API key {id} with scopes {scopes}", user.id);
+
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { key, token })))
+}
+
+/// DELETE /api/v1/auth/api-keys/{key_id}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(key_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let (name, prefix): (String, String) = conn
+        .query_row(
+            "DELETE FROM api_keys WHERE id = ?1 AND user_id = ?2 RETURNING name, prefix",
+            rusqlite::params![key_id, user.id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("API key not found".to_string()),
+            e => AppError::Database(e),
+        })?;
+    audit::record(
+        &conn,
+        &user.id,
+        AuditEvent::ApiKeyDeleted,
+        Some(&key_id),
+        serde_json::json!({ "name": name, "prefix": prefix }),
+        &client,
+    )?;
+    Ok(StatusCode::NO_CONTENT)
+}
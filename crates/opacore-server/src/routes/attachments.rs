@@ -0,0 +1,189 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::attachments::{self, Attachment, ATTACHMENT_COLS};
+
+/// Most files one transaction can carry.
+const MAX_ATTACHMENTS_PER_TRANSACTION: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub file_name: String,
+}
+
+/// GET /api/v1/transactions/{transaction_id}/attachments
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(transaction_id): Path<String>,
+) -> AppResult<Json<Vec<Attachment>>> {
+    let conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Read)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ATTACHMENT_COLS} FROM transaction_attachments WHERE transaction_id = ?1 ORDER BY created_at, id"
+    ))?;
+    let items = stmt
+        .query_map(rusqlite::params![transaction_id], attachments::row_to_attachment)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(items))
+}
+
+/// POST /api/v1/transactions/{transaction_id}/attachments?file_name=receipt.pdf
+///
+/// The request body is the file itself, typed by its `Content-Type` header and at most
+/// `ATTACHMENT_MAX_MB` long.
+pub async fn upload(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(transaction_id): Path<String>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<(StatusCode, Json<Attachment>)> {
+    if body.is_empty() {
+        return Err(AppError::BadRequest("Attachment is empty".into()));
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    if content_type.len() > 100 {
+        return Err(AppError::BadRequest("Content-Type is too long".into()));
+    }
+
+    {
+        let conn = state.db.get()?;
+        policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM transaction_attachments WHERE transaction_id = ?1",
+            rusqlite::params![transaction_id],
+            |row| row.get(0),
+        )?;
+        if count >= MAX_ATTACHMENTS_PER_TRANSACTION {
+            return Err(AppError::Conflict(format!(
+                "A transaction can have at most {MAX_ATTACHMENTS_PER_TRANSACTION} attachments"
+            )));
+        }
+    }
+
+    let attachment = Attachment {
+        id: Uuid::new_v4().to_string(),
+        transaction_id,
+        user_id: Some(user.id.clone()),
+        file_name: attachments::clean_file_name(&query.file_name),
+        content_type,
+        size_bytes: body.len() as i64,
+        sha256: attachments::sha256_hex(&body),
+        storage_key: Uuid::new_v4().to_string(),
+        created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+    };
+    attachments::put(&state.config.attachment_storage, &attachment.storage_key, body.to_vec(), &attachment.content_type)
+        .await?;
+
+    // The file is stored before its row exists; if the row can't be written (say the
+    // transaction was deleted meanwhile), remove the file again.
+    let inserted = state.db.get().map_err(AppError::from).and_then(|conn| {
+        conn.execute(
+            "INSERT INTO transaction_attachments
+                (id, transaction_id, user_id, file_name, content_type, size_bytes, sha256, storage_key, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                attachment.id,
+                attachment.transaction_id,
+                attachment.user_id,
+                attachment.file_name,
+                attachment.content_type,
+                attachment.size_bytes,
+                attachment.sha256,
+                attachment.storage_key,
+                attachment.created_at,
+            ],
+        )
+        .map_err(AppError::from)
+    });
+    if let Err(e) = inserted {
+        if let Err(purge_err) = attachments::delete(&state.config.attachment_storage, &attachment.storage_key).await {
+            tracing::warn!("Orphaned attachment {}: {purge_err}", attachment.storage_key);
+        }
+        return Err(match e {
+            AppError::Database(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                AppError::NotFound("Transaction not found".into())
+            }
+            e => e,
+        });
+    }
+
+    Ok((StatusCode::CREATED, Json(attachment)))
+}
+
+/// GET /api/v1/transactions/{transaction_id}/attachments/{attachment_id}
+pub async fn download(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((transaction_id, attachment_id)): Path<(String, String)>,
+) -> AppResult<impl IntoResponse> {
+    let attachment = {
+        let conn = state.db.get()?;
+        policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Read)?;
+        attachments::load_attachment(&conn, &transaction_id, &attachment_id)?
+    };
+    let data = attachments::get(&state.config.attachment_storage, &attachment.storage_key).await?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", attachment.file_name),
+            ),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    ))
+}
+
+/// DELETE /api/v1/transactions/{transaction_id}/attachments/{attachment_id}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((transaction_id, attachment_id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    {
+        let conn = state.db.get()?;
+        policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+        attachments::load_attachment(&conn, &transaction_id, &attachment_id)?;
+        conn.execute(
+            "DELETE FROM transaction_attachments WHERE id = ?1",
+            rusqlite::params![attachment_id],
+        )?;
+    }
+
+    // The delete queued the file; remove it now rather than at the next purge round
+    let (pool, config) = (state.db.clone(), state.config.clone());
+    tokio::spawn(async move {
+        if let Err(e) = attachments::purge_deleted(&pool, &config).await {
+            tracing::warn!("Attachment purge failed: {e}");
+        }
+    });
+
+    Ok(StatusCode::NO_CONTENT)
+}
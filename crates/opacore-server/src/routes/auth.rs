@@ -1,18 +1,24 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Extension, Json,
 };
 use axum_extra::extract::CookieJar;
-use axum_extra::extract::cookie::Cookie;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::auth::{middleware::SESSION_COOKIE, password, session, verification};
+use crate::auth::middleware::{
+    build_csrf_cookie, build_session_cookie, remove_session_cookies, SESSION_COOKIE, SESSION_PURPOSE,
+};
+use crate::auth::session::ClientInfo;
+use crate::auth::{lockout, password, session, verification};
 use crate::error::{AppError, AppResult};
-use crate::models::{User, UserPublic};
+use crate::models::{Session, User, UserPublic};
 use crate::routes::AppState;
+use crate::services::audit::{self, AuditEvent, AuditLogEntry, AuditLogQuery};
 use crate::services;
 
 #[derive(Debug, Deserialize)]
@@ -111,6 +117,10 @@ pub async fn register(
 
     // Create verification token
     let token = verification::create_verification_token(&state.db, &user_id)?;
+    {
+        let conn = state.db.get()?;
+        services::quotas::consume_email(&conn, &state.config, &user_id)?;
+    }
 
     // Send emails in background (don't block response)
     let config = state.config.clone();
@@ -138,13 +148,16 @@ pub async fn register(
 
 pub async fn login(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     jar: CookieJar,
     Json(body): Json<LoginRequest>,
 ) -> AppResult<impl IntoResponse> {
     let user = {
         let conn = state.db.get()?;
+        lockout::check(&conn, &body.email)?;
         let user_result = conn.query_row(
-            "SELECT id, email, name, password_hash, default_currency, email_verified, created_at, updated_at FROM users WHERE email = ?1",
+            "SELECT id, email, name, password_hash, default_currency, email_verified, created_at, updated_at, is_admin, disabled_at FROM users WHERE email = ?1",
             rusqlite::params![body.email],
             |row| {
                 Ok(User {
@@ -156,6 +169,8 @@ pub async fn login(
                     email_verified: row.get::<_, i32>(5)? != 0,
                     created_at: row.get(6)?,
                     updated_at: row.get(7)?,
+                    is_admin: row.get::<_, i32>(8)? != 0,
+                    disabled_at: row.get(9)?,
                 })
             },
         );
@@ -163,16 +178,25 @@ pub async fn login(
         match user_result {
             Ok(u) => u,
             Err(rusqlite::Error::QueryReturnedNoRows) => {
+                lockout::record_failure(&conn, &body.email)?;
                 return Err(AppError::Unauthorized);
             }
             Err(e) => return Err(AppError::Database(e)),
         }
     };
 
+    let client = session::client_info(&state.config, &headers, peer);
     let valid = password::verify_password(&body.password, &user.password_hash)?;
-    if !valid {
-        return Err(AppError::Unauthorized);
+    {
+        let conn = state.db.get()?;
+        if !valid {
+            lockout::record_failure(&conn, &body.email)?;
+            audit::record(&conn, &user.id, AuditEvent::LoginFailed, None, serde_json::json!({}), &client)?;
+            return Err(AppError::Unauthorized);
+        }
+        lockout::clear(&conn, &body.email)?;
     }
+    session::require_enabled(&user)?;
 
     // Check email verification
     if !user.email_verified {
@@ -181,47 +205,44 @@ pub async fn login(
         ));
     }
 
-    let sess = session::create_session(&state.db, &user.id, None, None)?;
-    let cookie = build_session_cookie(sess.token, state.config.secure_cookies);
+    let sess = session::create_session(&state.db, &state.config.sessions, &user.id, Some(&client.ip_address), client.user_agent.as_deref())?;
+    audit::record_login(&state.db, &user.id, &sess.id, "password", &client)?;
+    services::login_alerts::on_login(&state.db, &state.config, &user, &sess.id, &client);
+    let cookies = jar
+        .add(build_session_cookie(&state.config, &sess.token))
+        .add(build_csrf_cookie(&state.config, &sess.id));
     let user_public: UserPublic = user.into();
 
-    Ok((jar.add(cookie), Json(user_public)))
+    Ok((cookies, Json(user_public)))
 }
 
 pub async fn verify_email(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     jar: CookieJar,
     Json(body): Json<VerifyEmailRequest>,
 ) -> AppResult<impl IntoResponse> {
     let user_id = verification::validate_and_consume_token(&state.db, &body.token)?;
-
-    // Create a session so the user is logged in after verification
-    let sess = session::create_session(&state.db, &user_id, None, None)?;
-    let cookie = build_session_cookie(sess.token, state.config.secure_cookies);
+    state.cache.invalidate_user(&user_id);
 
     // Fetch the verified user for the response
     let user = {
         let conn = state.db.get()?;
-        conn.query_row(
-            "SELECT id, email, name, password_hash, default_currency, email_verified, created_at, updated_at FROM users WHERE id = ?1",
-            rusqlite::params![user_id],
-            |row| {
-                Ok(User {
-                    id: row.get(0)?,
-                    email: row.get(1)?,
-                    name: row.get(2)?,
-                    password_hash: row.get(3)?,
-                    default_currency: row.get(4)?,
-                    email_verified: row.get::<_, i32>(5)? != 0,
-                    created_at: row.get(6)?,
-                    updated_at: row.get(7)?,
-                })
-            },
-        )?
+        state.cache.user(&conn, &user_id)?
     };
+    session::require_enabled(&user)?;
+
+    // Create a session so the user is logged in after verification
+    let client = session::client_info(&state.config, &headers, peer);
+    let sess = session::create_session(&state.db, &state.config.sessions, &user_id, Some(&client.ip_address), client.user_agent.as_deref())?;
+    audit::record_login(&state.db, &user_id, &sess.id, "email_verification", &client)?;
+    let cookies = jar
+        .add(build_session_cookie(&state.config, &sess.token))
+        .add(build_csrf_cookie(&state.config, &sess.id));
 
     let user_public: UserPublic = user.into();
-    Ok((jar.add(cookie), Json(user_public)))
+    Ok((cookies, Json(user_public)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -230,9 +251,14 @@ pub struct ChangePasswordRequest {
     pub new_password: String,
 }
 
+/// POST /api/v1/auth/change-password
+///
+/// Needs the current password. Every other session is signed out; this one stays.
 pub async fn change_password(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(session): Extension<Session>,
+    Extension(client): Extension<ClientInfo>,
     Json(body): Json<ChangePasswordRequest>,
 ) -> AppResult<impl IntoResponse> {
     if body.new_password.len() < 8 {
@@ -254,10 +280,247 @@ pub async fn change_password(
         "UPDATE users SET password_hash = ?1, updated_at = ?2 WHERE id = ?3",
         rusqlite::params![new_hash, now, user.id],
     )?;
+    state.cache.invalidate_user(&user.id);
+    audit::record(&conn, &user.id, AuditEvent::PasswordChanged, None, serde_json::json!({ "via": "change" }), &client)?;
+    let revoked = session::delete_other_sessions(&state.db, &user.id, &session.id)?;
+    tracing::info!("Password changed for user {}, {revoked} other sessions signed out", user.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailRequest {
+    pub new_email: String,
+    pub current_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailChangeRequest {
+    pub token: String,
+}
+
+/// POST /api/v1/auth/email
+///
+/// Start changing the account's email. Needs the current password; the change is only
+/// made once the new address confirms it (see `confirm_email_change`).
+pub async fn change_email(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<ChangeEmailRequest>,
+) -> AppResult<impl IntoResponse> {
+    // The new address has to confirm, which needs a mail provider
+    if state.config.resend_api_key.is_none() {
+        return Err(AppError::BadRequest("Email is not configured on this server".into()));
+    }
+    let new_email = body.new_email.trim();
+    if new_email.is_empty() || !new_email.contains('@') || new_email.contains(char::is_whitespace) {
+        return Err(AppError::BadRequest("Invalid email address".to_string()));
+    }
+    if new_email.eq_ignore_ascii_case(&user.email) {
+        return Err(AppError::BadRequest("That is already your email address".to_string()));
+    }
+    if !password::verify_password(&body.current_password, &user.password_hash)? {
+        return Err(AppError::Unauthorized);
+    }
+
+    {
+        let conn = state.db.get()?;
+        let taken: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = ?1)",
+            rusqlite::params![new_email],
+            |row| row.get(0),
+        )?;
+        if taken {
+            return Err(AppError::Conflict("An account with this email already exists".to_string()));
+        }
+    }
+
+    {
+        let conn = state.db.get()?;
+        if !services::quotas::consume_email(&conn, &state.config, &user.id)? {
+            return Err(AppError::Forbidden("Monthly email quota reached".into()));
+        }
+    }
+    let token = verification::create_email_change_token(&state.db, &user.id, new_email)?;
+
+    let config = state.config.clone();
+    let (to, name) = (new_email.to_string(), user.name.clone());
+    tokio::spawn(async move {
+        if let Err(e) = services::email::send_email_change_confirmation(&config, &to, &name, &token).await {
+            tracing::error!("Failed to send email change confirmation: {e}");
+        }
+    });
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "message": "Check your new inbox for a confirmation link. Your email changes once you click it.",
+            "email": new_email,
+        })),
+    ))
+}
+
+/// POST /api/v1/auth/email/confirm
+///
+/// Apply a pending email change from the link sent to the new address, and tell the
+/// old address about it.
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Json(body): Json<ConfirmEmailChangeRequest>,
+) -> AppResult<impl IntoResponse> {
+    let (user_id, new_email) = verification::validate_and_consume_email_change_token(&state.db, &body.token)?;
+    let (old_email, name) = apply_email_change(&state, &user_id, &new_email)?;
+
+    let config = state.config.clone();
+    let notice_to = new_email.clone();
+    tokio::spawn(async move {
+        if let Err(e) = services::email::send_email_changed_notice(&config, &old_email, &name, &notice_to).await {
+            tracing::warn!("Failed to send email change notice: {e}");
+        }
+    });
+
+    Ok(Json(serde_json::json!({ "message": "Email changed.", "email": new_email })))
+}
+
+/// Switch a user's email, returning their old email and name. Fails with a conflict if
+/// another account took the address in the meantime.
+fn apply_email_change(state: &AppState, user_id: &str, new_email: &str) -> AppResult<(String, String)> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let conn = state.db.get()?;
+    let (old_email, name): (String, String) = conn.query_row(
+        "SELECT email, name FROM users WHERE id = ?1",
+        rusqlite::params![user_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let result = conn.execute(
+        "UPDATE users SET email = ?1, email_verified = 1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![new_email, now, user_id],
+    );
+    match result {
+        Ok(_) => {}
+        Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            return Err(AppError::Conflict("An account with this email already exists".to_string()));
+        }
+        Err(e) => return Err(AppError::Database(e)),
+    }
+    state.cache.invalidate_user(user_id);
+    Ok((old_email, name))
+}
 
+/// A signed-in device, as listed to its user. The token is never shown.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub expires_at: String,
+    /// The session making this request.
+    pub current: bool,
+}
+
+/// GET /api/v1/auth/sessions
+///
+/// The user's live sessions, most recently used first.
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(current): Extension<Session>,
+) -> AppResult<Json<Vec<SessionInfo>>> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, ip_address, user_agent, created_at, last_used_at, expires_at
+         FROM sessions
+         WHERE user_id = ?1 AND expires_at > ?2
+         ORDER BY COALESCE(last_used_at, created_at) DESC",
+    )?;
+    let sessions = stmt
+        .query_map(rusqlite::params![user.id, now], |row| {
+            let id: String = row.get(0)?;
+            Ok(SessionInfo {
+                current: id == current.id,
+                id,
+                ip_address: row.get(1)?,
+                user_agent: row.get(2)?,
+                created_at: row.get(3)?,
+                last_used_at: row.get(4)?,
+                expires_at: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(sessions))
+}
+
+/// GET /api/v1/auth/audit-log?action=&before_id=&limit=100
+///
+/// The user's sign-ins, password changes, API keys, wallets and exports, newest first.
+pub async fn audit_log(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<AuditLogQuery>,
+) -> AppResult<Json<Vec<AuditLogEntry>>> {
+    let conn = state.db.get()?;
+    Ok(Json(audit::list(&conn, Some(&user.id), &query)?))
+}
+
+/// DELETE /api/v1/auth/sessions/{session_id}
+///
+/// Sign out one device. Revoking the current session signs this client out too.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(session_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let deleted = conn.execute(
+        "DELETE FROM sessions WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![session_id, user.id],
+    )?;
+    if deleted == 0 {
+        return Err(AppError::NotFound("Session not found".to_string()));
+    }
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RevokeSessionLinkRequest {
+    pub token: String,
+}
+
+/// POST /api/v1/auth/sessions/revoke
+///
+/// Sign out the session named by the link in a new sign-in alert, without signing in.
+pub async fn revoke_session_from_link(
+    State(state): State<AppState>,
+    Json(body): Json<RevokeSessionLinkRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let session_id = services::login_alerts::verify_revoke_token(&state.config, &body.token)
+        .ok_or_else(|| AppError::BadRequest("Link is invalid or has expired".to_string()))?;
+    let conn = state.db.get()?;
+    let revoked = conn.execute("DELETE FROM sessions WHERE id = ?1", rusqlite::params![session_id])? > 0;
+    if revoked {
+        tracing::info!("Session {session_id} revoked from a sign-in alert");
+    }
+    Ok(Json(serde_json::json!({
+        "revoked": revoked,
+        "message": "That session is signed out. Change your password to keep it from signing in again.",
+    })))
+}
+
+/// DELETE /api/v1/auth/sessions
+///
+/// Log out everywhere else: every session but the current one is revoked.
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(current): Extension<Session>,
+) -> AppResult<Json<serde_json::Value>> {
+    let revoked = session::delete_other_sessions(&state.db, &user.id, &current.id)?;
+    Ok(Json(serde_json::json!({ "revoked": revoked })))
+}
+
 pub async fn resend_verification(
     State(state): State<AppState>,
     Json(body): Json<ResendVerificationRequest>,
@@ -291,6 +554,14 @@ pub async fn resend_verification(
         return Ok(Json(success_msg));
     }
 
+    // Over quota looks the same as success so the response can't be used to probe accounts
+    {
+        let conn = state.db.get()?;
+        if !services::quotas::consume_email(&conn, &state.config, &user_id)? {
+            return Ok(Json(success_msg));
+        }
+    }
+
     let token = verification::create_verification_token(&state.db, &user_id)?;
 
     let config = state.config.clone();
@@ -340,6 +611,13 @@ pub async fn forgot_password(
         return Ok(success);
     };
 
+    {
+        let conn = state.db.get()?;
+        if !services::quotas::consume_email(&conn, &state.config, &user_id)? {
+            return Ok(success);
+        }
+    }
+
     let token = verification::create_reset_token(&state.db, &user_id)?;
 
     let config = state.config.clone();
@@ -355,6 +633,8 @@ pub async fn forgot_password(
 
 pub async fn reset_password(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(body): Json<ResetPasswordRequest>,
 ) -> AppResult<impl IntoResponse> {
     if body.new_password.len() < 8 {
@@ -369,10 +649,16 @@ pub async fn reset_password(
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
 
     let conn = state.db.get()?;
-    conn.execute(
-        "UPDATE users SET password_hash = ?1, updated_at = ?2 WHERE id = ?3",
+    let email: String = conn.query_row(
+        "UPDATE users SET password_hash = ?1, updated_at = ?2 WHERE id = ?3 RETURNING email",
         rusqlite::params![new_hash, now, user_id],
+        |row| row.get(0),
     )?;
+    state.cache.invalidate_user(&user_id);
+    let client = session::client_info(&state.config, &headers, peer);
+    audit::record(&conn, &user_id, AuditEvent::PasswordChanged, None, serde_json::json!({ "via": "reset" }), &client)?;
+    // Whoever can reset the password is the owner; let them straight back in
+    lockout::clear(&conn, &email)?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -381,48 +667,151 @@ pub async fn logout(
     State(state): State<AppState>,
     jar: CookieJar,
 ) -> AppResult<impl IntoResponse> {
-    if let Some(cookie) = jar.get(SESSION_COOKIE) {
-        session::delete_session(&state.db, cookie.value())?;
+    if let Some(token) = jar
+        .get(SESSION_COOKIE)
+        .and_then(|c| state.config.session_keys.verify(SESSION_PURPOSE, c.value()))
+    {
+        session::delete_session(&state.db, token)?;
     }
 
-    let removal = Cookie::build(SESSION_COOKIE)
-        .path("/")
-        .max_age(time::Duration::ZERO)
-        .http_only(true)
-        .build();
-
-    Ok((jar.add(removal), Json(serde_json::json!({"ok": true}))))
+    Ok((remove_session_cookies(jar), Json(serde_json::json!({"ok": true}))))
 }
 
 pub async fn me(Extension(user): Extension<User>) -> Json<UserPublic> {
     Json(user.into())
 }
 
+/// GET /api/v1/auth/quotas
+/// The account's limits and current usage.
+pub async fn quotas(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<services::quotas::QuotaStatus>>> {
+    let conn = state.db.get()?;
+    Ok(Json(services::quotas::statuses(&conn, &state.config, &user.id)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+/// What deleting an account removed.
+#[derive(Debug, Serialize)]
+pub struct DeleteAccountSummary {
+    pub portfolios: usize,
+    pub wallets: usize,
+    pub transactions: usize,
+    pub invoices: usize,
+    pub sessions: usize,
+    pub tokens: usize,
+    pub bdk_files_removed: usize,
+}
+
+/// DELETE /api/v1/auth/me
+///
+/// Needs the password. Everything the account owns is deleted in one transaction;
+/// files on disk (BDK wallets, job output) go once it has committed.
 pub async fn delete_account(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     jar: CookieJar,
+    Json(body): Json<DeleteAccountRequest>,
 ) -> AppResult<impl IntoResponse> {
-    {
-        let conn = state.db.get()?;
-        conn.execute("DELETE FROM users WHERE id = ?1", rusqlite::params![user.id])?;
+    if !password::verify_password(&body.password, &user.password_hash)? {
+        return Err(AppError::Unauthorized);
     }
 
-    let removal = Cookie::build(SESSION_COOKIE)
-        .path("/")
-        .max_age(time::Duration::ZERO)
-        .http_only(true)
-        .build();
+    let (summary, portfolio_ids, wallet_ids, job_files) = {
+        let mut conn = state.db.get()?;
+        let tx = conn.transaction()?;
+        let ids = |sql: &str| -> AppResult<Vec<String>> {
+            Ok(tx
+                .prepare(sql)?
+                .query_map(rusqlite::params![user.id], |row| row.get(0))?
+                .collect::<Result<_, _>>()?)
+        };
+        let portfolio_ids = ids("SELECT id FROM portfolios WHERE user_id = ?1")?;
+        let wallet_ids = ids(
+            "SELECT w.id FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE p.user_id = ?1",
+        )?;
+        let job_files = ids("SELECT file_path FROM jobs WHERE user_id = ?1 AND file_path IS NOT NULL")?;
+        // Organizations nobody else owns go too; portfolios others shared with them stop being shared
+        let sole_owned = "SELECT m.organization_id FROM organization_members m
+             WHERE m.user_id = ?1 AND m.role = 'owner' AND NOT EXISTS (
+                 SELECT 1 FROM organization_members o
+                 WHERE o.organization_id = m.organization_id AND o.role = 'owner' AND o.user_id != ?1)";
+        let mut portfolio_ids = portfolio_ids;
+        portfolio_ids.extend(ids(&format!(
+            "SELECT id FROM portfolios WHERE organization_id IN ({sole_owned}) AND user_id != ?1"
+        ))?);
+
+        // Children first, so each count is what was actually removed rather than a cascade
+        let delete = |sql: &str| tx.execute(sql, rusqlite::params![user.id]);
+        let invoices = delete(
+            "DELETE FROM invoices WHERE portfolio_id IN (SELECT id FROM portfolios WHERE user_id = ?1)",
+        )?;
+        let transactions = delete(
+            "DELETE FROM transactions WHERE portfolio_id IN (SELECT id FROM portfolios WHERE user_id = ?1)",
+        )?;
+        let wallets = delete(
+            "DELETE FROM wallets WHERE portfolio_id IN (SELECT id FROM portfolios WHERE user_id = ?1)",
+        )?;
+        let portfolios = delete("DELETE FROM portfolios WHERE user_id = ?1")?;
+        delete(&format!("DELETE FROM organizations WHERE id IN ({sole_owned})"))?;
+        let sessions = delete("DELETE FROM sessions WHERE user_id = ?1")?;
+        let tokens = delete("DELETE FROM email_verification_tokens WHERE user_id = ?1")?
+            + delete("DELETE FROM password_reset_tokens WHERE user_id = ?1")?
+            + delete("DELETE FROM email_change_tokens WHERE user_id = ?1")?
+            + delete("DELETE FROM api_keys WHERE user_id = ?1")?;
+        delete("DELETE FROM users WHERE id = ?1")?;
+        tx.commit()?;
+
+        let summary = DeleteAccountSummary {
+            portfolios,
+            wallets,
+            transactions,
+            invoices,
+            sessions,
+            tokens,
+            bdk_files_removed: 0,
+        };
+        (summary, portfolio_ids, wallet_ids, job_files)
+    };
 
-    Ok((jar.add(removal), StatusCode::NO_CONTENT))
-}
+    for id in &portfolio_ids {
+        state.cache.invalidate_portfolio(id);
+    }
+    state.cache.invalidate_user(&user.id);
+
+    // The rows are gone, so a file that can't be removed is only logged
+    let mut summary = summary;
+    for wallet_id in &wallet_ids {
+        match services::wallet::remove_bdk_wallet_files(&state.config.bdk_wallets_dir, wallet_id) {
+            Ok(removed) => summary.bdk_files_removed += removed,
+            Err(e) => tracing::warn!("{e}"),
+        }
+    }
+    services::jobs::remove_files(&job_files);
+    tracing::info!(
+        "Deleted account {}: {} portfolios, {} wallets, {} transactions, {} invoices, {} sessions, {} tokens, {} BDK files",
+        user.id,
+        summary.portfolios,
+        summary.wallets,
+        summary.transactions,
+        summary.invoices,
+        summary.sessions,
+        summary.tokens,
+        summary.bdk_files_removed
+    );
+
+    // The account is gone, so the confirmation can't count against its email quota
+    let config = state.config.clone();
+    tokio::spawn(async move {
+        if let Err(e) = services::email::send_account_deleted_email(&config, &user.email, &user.name).await {
+            tracing::warn!("Failed to send account deletion confirmation: {e}");
+        }
+    });
 
-fn build_session_cookie(token: String, secure: bool) -> Cookie<'static> {
-    Cookie::build((SESSION_COOKIE, token))
-        .path("/")
-        .max_age(time::Duration::days(30))
-        .http_only(true)
-        .secure(secure)
-        .same_site(axum_extra::extract::cookie::SameSite::Lax)
-        .build()
+    Ok((remove_session_cookies(jar), Json(summary)))
 }
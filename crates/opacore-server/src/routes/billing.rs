@@ -9,36 +9,13 @@ use serde::Serialize;
 use sha2::Sha256;
 use std::collections::HashMap;
 
-use crate::db::DbPool;
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::http;
 
 type HmacSha256 = Hmac<Sha256>;
 
-// ── Public helper ──────────────────────────────────────────────────────────────
-
-/// Returns true if the user has an active Pro subscription.
-/// If billing is disabled (no STRIPE_SECRET_KEY), always returns true so
-/// self-hosted instances get all features for free.
-pub fn is_pro(pool: &DbPool, config: &crate::config::Config, user_id: &str) -> bool {
-    if config.stripe_secret_key.is_none() {
-        return true;
-    }
-    let conn = match pool.get() {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
-    conn.query_row(
-        "SELECT COUNT(*) FROM subscriptions
-         WHERE user_id = ?1 AND plan = 'pro' AND status IN ('active', 'trialing')",
-        rusqlite::params![user_id],
-        |row| row.get::<_, i32>(0),
-    )
-    .map(|c| c > 0)
-    .unwrap_or(false)
-}
-
 // ── Response types ─────────────────────────────────────────────────────────────
 
 #[derive(Serialize)]
@@ -117,7 +94,7 @@ pub async fn checkout(
         .as_deref()
         .ok_or_else(|| AppError::Internal("Stripe price not configured".to_string()))?;
 
-    let http = reqwest::Client::new();
+    let http = http::client()?;
 
     // Get or create Stripe customer
     let stripe_customer_id = {
@@ -146,7 +123,7 @@ pub async fn checkout(
                 .form(&params)
                 .send()
                 .await
-                .map_err(|e| AppError::Internal(format!("Stripe customer create failed: {e}")))?;
+                .map_err(|e| http::upstream_error("stripe", "Stripe customer create failed", e))?;
 
             if !resp.status().is_success() {
                 let body = resp.text().await.unwrap_or_default();
@@ -156,7 +133,7 @@ pub async fn checkout(
             let data: serde_json::Value = resp
                 .json()
                 .await
-                .map_err(|e| AppError::Internal(format!("Stripe response parse failed: {e}")))?;
+                .map_err(|e| http::upstream_error("stripe", "Stripe response parse failed", e))?;
 
             let customer_id = data["id"]
                 .as_str()
@@ -197,7 +174,7 @@ pub async fn checkout(
         ])
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Stripe checkout create failed: {e}")))?;
+        .map_err(|e| http::upstream_error("stripe", "Stripe checkout create failed", e))?;
 
     if !resp.status().is_success() {
         let body = resp.text().await.unwrap_or_default();
@@ -207,7 +184,7 @@ pub async fn checkout(
     let data: serde_json::Value = resp
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("Stripe response parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("stripe", "Stripe response parse failed", e))?;
 
     let url = data["url"]
         .as_str()
@@ -241,14 +218,14 @@ pub async fn portal(
 
     let return_url = format!("{}/settings", state.config.app_url);
 
-    let http = reqwest::Client::new();
+    let http = http::client()?;
     let resp = http
         .post("https://api.stripe.com/v1/billing_portal/sessions")
         .bearer_auth(secret_key)
         .form(&[("customer", &stripe_customer_id), ("return_url", &return_url)])
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Stripe portal create failed: {e}")))?;
+        .map_err(|e| http::upstream_error("stripe", "Stripe portal create failed", e))?;
 
     if !resp.status().is_success() {
         let body = resp.text().await.unwrap_or_default();
@@ -258,7 +235,7 @@ pub async fn portal(
     let data: serde_json::Value = resp
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("Stripe response parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("stripe", "Stripe response parse failed", e))?;
 
     let url = data["url"]
         .as_str()
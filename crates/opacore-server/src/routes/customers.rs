@@ -0,0 +1,344 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::customers::{self, Customer, CUSTOMER_COLS};
+
+/// Most customers one portfolio can have.
+const MAX_PER_PORTFOLIO: i64 = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCustomerRequest {
+    pub name: String,
+    pub email: Option<String>,
+    pub company: Option<String>,
+    pub billing_address: Option<String>,
+    pub tax_id: Option<String>,
+    pub default_currency: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// Empty strings clear the optional fields.
+#[derive(Debug, Deserialize)]
+pub struct UpdateCustomerRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub company: Option<String>,
+    pub billing_address: Option<String>,
+    pub tax_id: Option<String>,
+    pub default_currency: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListCustomersQuery {
+    /// Matches name, email or company.
+    pub q: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatementQuery {
+    /// First day of the statement (YYYY-MM-DD), by issue date.
+    pub start: Option<String>,
+    /// Last day, inclusive.
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementLine {
+    pub invoice_id: String,
+    pub invoice_number: Option<String>,
+    pub description: Option<String>,
+    pub issued_at: Option<String>,
+    pub due_at: Option<String>,
+    pub status: String,
+    pub amount_sat: i64,
+    pub amount_fiat: Option<f64>,
+    pub fiat_currency: String,
+    pub paid_at: Option<String>,
+    pub paid_amount_sat: Option<i64>,
+    pub paid_txid: Option<String>,
+    pub paid_via: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomerStatement {
+    pub customer: Customer,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub invoices: Vec<StatementLine>,
+    /// Everything issued in the period, paid or not.
+    pub invoiced_sat: i64,
+    pub paid_sat: i64,
+    /// Issued and still awaiting payment.
+    pub outstanding_sat: i64,
+    /// The part of `outstanding_sat` past its due date.
+    pub overdue_sat: i64,
+}
+
+/// Trimmed, with blank meaning absent.
+fn clean(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn validate_email(email: Option<&str>) -> AppResult<()> {
+    if email.is_some_and(|e| !e.contains('@') || e.contains(char::is_whitespace)) {
+        return Err(AppError::BadRequest("Invalid email address".into()));
+    }
+    Ok(())
+}
+
+/// Lowercase ISO 4217 code, as invoices store it.
+fn validate_currency(currency: Option<String>) -> AppResult<Option<String>> {
+    match currency {
+        Some(c) if c.len() == 3 && c.chars().all(|c| c.is_ascii_alphabetic()) => Ok(Some(c.to_lowercase())),
+        Some(_) => Err(AppError::BadRequest("default_currency must be a 3-letter currency code".into())),
+        None => Ok(None),
+    }
+}
+
+fn parse_date(value: Option<&str>, name: &str) -> AppResult<Option<chrono::NaiveDate>> {
+    value
+        .map(|v| {
+            chrono::NaiveDate::parse_from_str(v.trim(), "%Y-%m-%d")
+                .map_err(|_| AppError::BadRequest(format!("Invalid {name}: expected YYYY-MM-DD")))
+        })
+        .transpose()
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/customers
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<ListCustomersQuery>,
+) -> AppResult<Json<Vec<Customer>>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+
+    let pattern = clean(query.q).map(|q| format!("%{}%", q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")));
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {CUSTOMER_COLS} FROM customers
+         WHERE portfolio_id = ?1
+           AND (?2 IS NULL OR name LIKE ?2 ESCAPE '\\' OR email LIKE ?2 ESCAPE '\\' OR company LIKE ?2 ESCAPE '\\')
+         ORDER BY name COLLATE NOCASE, created_at"
+    ))?;
+    let customers = stmt
+        .query_map(rusqlite::params![portfolio_id, pattern], customers::row_to_customer)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(customers))
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/customers
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Json(body): Json<CreateCustomerRequest>,
+) -> AppResult<(StatusCode, Json<Customer>)> {
+    let name = clean(Some(body.name)).ok_or_else(|| AppError::BadRequest("Name is required".into()))?;
+    let email = clean(body.email);
+    validate_email(email.as_deref())?;
+    let default_currency = validate_currency(clean(body.default_currency))?;
+
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM customers WHERE portfolio_id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_PER_PORTFOLIO {
+        return Err(AppError::Conflict(format!("A portfolio can have at most {MAX_PER_PORTFOLIO} customers")));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO customers (id, portfolio_id, name, email, company, billing_address, tax_id, default_currency, notes, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        rusqlite::params![
+            id, portfolio_id, name, email, clean(body.company), clean(body.billing_address),
+            clean(body.tax_id), default_currency, clean(body.notes), now, now
+        ],
+    )?;
+
+    Ok((StatusCode::CREATED, Json(customers::load(&conn, &portfolio_id, &id)?)))
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/customers/{customer_id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, customer_id)): Path<(String, String)>,
+) -> AppResult<Json<Customer>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+    Ok(Json(customers::load(&conn, &portfolio_id, &customer_id)?))
+}
+
+/// PUT /api/v1/portfolios/{portfolio_id}/customers/{customer_id}
+///
+/// Draft invoices pick up a new name or email; invoices already sent keep the ones they
+/// were issued with.
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, customer_id)): Path<(String, String)>,
+    Json(body): Json<UpdateCustomerRequest>,
+) -> AppResult<Json<Customer>> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    let existing = customers::load(&conn, &portfolio_id, &customer_id)?;
+
+    let name = match body.name {
+        Some(name) => clean(Some(name)).ok_or_else(|| AppError::BadRequest("Name can't be empty".into()))?,
+        None => existing.name,
+    };
+    let email = match body.email {
+        Some(email) => clean(Some(email)),
+        None => existing.email,
+    };
+    validate_email(email.as_deref())?;
+    let default_currency = match body.default_currency {
+        Some(currency) => validate_currency(clean(Some(currency)))?,
+        None => existing.default_currency,
+    };
+    let company = body.company.map_or(existing.company, |v| clean(Some(v)));
+    let billing_address = body.billing_address.map_or(existing.billing_address, |v| clean(Some(v)));
+    let tax_id = body.tax_id.map_or(existing.tax_id, |v| clean(Some(v)));
+    let notes = body.notes.map_or(existing.notes, |v| clean(Some(v)));
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "UPDATE customers SET name = ?1, email = ?2, company = ?3, billing_address = ?4, tax_id = ?5,
+             default_currency = ?6, notes = ?7, updated_at = ?8
+         WHERE id = ?9",
+        rusqlite::params![name, email, company, billing_address, tax_id, default_currency, notes, now, customer_id],
+    )?;
+    tx.execute(
+        "UPDATE invoices SET customer_name = ?1, customer_email = ?2, updated_at = ?3
+         WHERE customer_id = ?4 AND status = 'draft'",
+        rusqlite::params![name, email, now, customer_id],
+    )?;
+    tx.commit()?;
+
+    Ok(Json(customers::load(&conn, &portfolio_id, &customer_id)?))
+}
+
+/// DELETE /api/v1/portfolios/{portfolio_id}/customers/{customer_id}
+///
+/// The customer's invoices are kept, unlinked, with the name and email they were issued to.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, customer_id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    let affected = conn.execute(
+        "DELETE FROM customers WHERE id = ?1 AND portfolio_id = ?2",
+        rusqlite::params![customer_id, portfolio_id],
+    )?;
+    if affected == 0 {
+        return Err(AppError::NotFound("Customer not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/customers/{customer_id}/statement
+///
+/// The customer's invoices issued in the period, oldest first, with what was paid and
+/// what is still owed. Drafts, cancelled invoices and payment links are left out; the
+/// full history is `GET .../invoices?customer_id=`.
+pub async fn statement(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, customer_id)): Path<(String, String)>,
+    Query(query): Query<StatementQuery>,
+) -> AppResult<Json<CustomerStatement>> {
+    let start = parse_date(query.start.as_deref(), "start")?;
+    let end = parse_date(query.end.as_deref(), "end")?;
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return Err(AppError::BadRequest("start must not be after end".into()));
+        }
+    }
+
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+    let customer = customers::load(&conn, &portfolio_id, &customer_id)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, invoice_number, description, issued_at, due_at, status, amount_sat, amount_fiat,
+                fiat_currency, paid_at, paid_amount_sat, paid_txid, paid_via
+         FROM invoices
+         WHERE customer_id = ?1 AND portfolio_id = ?2 AND type = 'invoice'
+           AND status NOT IN ('draft', 'cancelled')
+           AND (?3 IS NULL OR date(issued_at) >= ?3) AND (?4 IS NULL OR date(issued_at) <= ?4)
+         ORDER BY issued_at, id",
+    )?;
+    let invoices = stmt
+        .query_map(
+            rusqlite::params![
+                customer_id,
+                portfolio_id,
+                start.map(|d| d.to_string()),
+                end.map(|d| d.to_string())
+            ],
+            |row| {
+                Ok(StatementLine {
+                    invoice_id: row.get(0)?,
+                    invoice_number: row.get(1)?,
+                    description: row.get(2)?,
+                    issued_at: row.get(3)?,
+                    due_at: row.get(4)?,
+                    status: row.get(5)?,
+                    amount_sat: row.get(6)?,
+                    amount_fiat: row.get(7)?,
+                    fiat_currency: row.get(8)?,
+                    paid_at: row.get(9)?,
+                    paid_amount_sat: row.get(10)?,
+                    paid_txid: row.get(11)?,
+                    paid_via: row.get(12)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut totals = (0, 0, 0, 0);
+    for line in &invoices {
+        totals.0 += line.amount_sat;
+        if line.status == "paid" {
+            totals.1 += line.paid_amount_sat.unwrap_or(line.amount_sat);
+        }
+        if matches!(line.status.as_str(), "sent" | "pending_confirmation") {
+            totals.2 += line.amount_sat;
+            if line.due_at.as_deref().is_some_and(|due| due < now.as_str()) {
+                totals.3 += line.amount_sat;
+            }
+        }
+    }
+    let (invoiced_sat, paid_sat, outstanding_sat, overdue_sat) = totals;
+
+    Ok(Json(CustomerStatement {
+        customer,
+        start: start.map(|d| d.to_string()),
+        end: end.map(|d| d.to_string()),
+        invoices,
+        invoiced_sat,
+        paid_sat,
+        outstanding_sat,
+        overdue_sat,
+    }))
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::data_quality::{self, DataQualityReport, IssueCounts, IssueKind};
+
+#[derive(Debug, Deserialize)]
+pub struct FixMissingPriceRequest {
+    /// Limit the fix to these transactions; all flagged ones if omitted.
+    pub transaction_ids: Option<Vec<String>>,
+    /// Use this BTC price instead of looking up the historical one.
+    pub price_usd: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixImpreciseTimeRequest {
+    /// Transaction id to RFC 3339 timestamp on the same day. Others use their block time.
+    #[serde(default)]
+    pub times: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FixShortfallRequest {
+    /// BTC price for the corrections' cost basis; the historical price if omitted.
+    pub price_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FixResponse {
+    pub fixed: usize,
+    /// Issues left after the fix.
+    pub remaining: IssueCounts,
+}
+
+/// GET /api/v1/portfolios/{id}/data-quality
+///
+/// Transactions that will distort cost basis and reports, grouped by issue class.
+pub async fn report(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<DataQualityReport>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+    Ok(Json(data_quality::scan(&conn, &portfolio_id)?))
+}
+
+/// POST /api/v1/portfolios/{id}/data-quality/missing-price
+pub async fn fix_missing_price(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Json(body): Json<FixMissingPriceRequest>,
+) -> AppResult<Json<FixResponse>> {
+    {
+        let conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    }
+    if body.price_usd.is_some_and(|p| !p.is_finite() || p <= 0.0) {
+        return Err(AppError::BadRequest("price_usd must be positive".into()));
+    }
+
+    let fixed = data_quality::fix_missing_prices(
+        &state.db,
+        &state.config.coingecko_api_url,
+        &user,
+        &client,
+        &portfolio_id,
+        body.transaction_ids.as_deref(),
+        body.price_usd,
+    )
+    .await?;
+    respond(&state, &portfolio_id, fixed)
+}
+
+/// POST /api/v1/portfolios/{id}/data-quality/imprecise-time
+pub async fn fix_imprecise_time(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Json(body): Json<FixImpreciseTimeRequest>,
+) -> AppResult<Json<FixResponse>> {
+    let fixed = {
+        let mut conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+        let db_tx = conn.transaction()?;
+        let fixed = data_quality::fix_imprecise_times(&db_tx, &user, &client, &portfolio_id, &body.times)?;
+        db_tx.commit()?;
+        fixed
+    };
+    respond(&state, &portfolio_id, fixed)
+}
+
+/// POST /api/v1/portfolios/{id}/data-quality/negative-balance
+///
+/// Inserts a `receive` correction before each point the balance goes negative.
+pub async fn fix_negative_balance(
+    state: State<AppState>,
+    user: Extension<User>,
+    client: Extension<ClientInfo>,
+    portfolio_id: Path<String>,
+    Json(body): Json<FixShortfallRequest>,
+) -> AppResult<Json<FixResponse>> {
+    fix_shortfall(state, user, client, portfolio_id, IssueKind::NegativeBalance, body)
+}
+
+/// POST /api/v1/portfolios/{id}/data-quality/oversold
+///
+/// Inserts a `buy` correction before each sell that exceeds the lots held.
+pub async fn fix_oversold(
+    state: State<AppState>,
+    user: Extension<User>,
+    client: Extension<ClientInfo>,
+    portfolio_id: Path<String>,
+    Json(body): Json<FixShortfallRequest>,
+) -> AppResult<Json<FixResponse>> {
+    fix_shortfall(state, user, client, portfolio_id, IssueKind::Oversold, body)
+}
+
+fn fix_shortfall(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    kind: IssueKind,
+    body: FixShortfallRequest,
+) -> AppResult<Json<FixResponse>> {
+    if body.price_usd.is_some_and(|p| !p.is_finite() || p < 0.0) {
+        return Err(AppError::BadRequest("price_usd must not be negative".into()));
+    }
+    let result = {
+        let mut conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+        let db_tx = conn.transaction()?;
+        let result = data_quality::fix_shortfalls(&db_tx, &user, &client, &portfolio_id, kind, body.price_usd)?;
+        db_tx.commit()?;
+        result
+    };
+    respond(&state, &portfolio_id, result.imported)
+}
+
+fn respond(state: &AppState, portfolio_id: &str, fixed: usize) -> AppResult<Json<FixResponse>> {
+    let conn = state.db.get()?;
+    let remaining = data_quality::scan(&conn, portfolio_id)?.counts;
+    Ok(Json(FixResponse { fixed, remaining }))
+}
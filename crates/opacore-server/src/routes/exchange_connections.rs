@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::exchange_api::{self, Exchange, ExchangeConnection, CONNECTION_COLS};
+use crate::services::import::ImportResult;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateConnectionRequest {
+    pub portfolio_id: String,
+    pub exchange: Exchange,
+    pub label: Option<String>,
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionTestResult {
+    pub ok: bool,
+    /// BTC held on the exchange, as a sanity check that the right account is connected.
+    pub btc_balance_sat: i64,
+}
+
+/// GET /api/v1/exchange-connections
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<ExchangeConnection>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {CONNECTION_COLS} FROM exchange_connections WHERE user_id = ?1 ORDER BY created_at DESC"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], exchange_api::row_to_connection)?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// POST /api/v1/exchange-connections
+///
+/// The credentials are tried before anything is saved. The first import runs in the
+/// background; after that the importer picks up new trades on its interval. Only read
+/// permissions are needed (Kraken: "Query Funds" and "Query Closed Orders & Trades";
+/// Coinbase: `wallet:accounts:read` and `wallet:transactions:read`).
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<CreateConnectionRequest>,
+) -> AppResult<(StatusCode, Json<ExchangeConnection>)> {
+    let api_key = body.api_key.trim().to_string();
+    let api_secret = body.api_secret.trim().to_string();
+    if api_key.is_empty() || api_secret.is_empty() {
+        return Err(AppError::BadRequest("API key and secret are required".into()));
+    }
+    {
+        let conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &body.portfolio_id, Access::Write)?;
+    }
+
+    exchange_api::test(&state.config, body.exchange, &api_key, &api_secret).await?;
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let label = body.label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    {
+        let conn = state.db.get()?;
+        conn.execute(
+            "INSERT INTO exchange_connections (id, user_id, portfolio_id, exchange, label, api_key, api_secret, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                id, user.id, body.portfolio_id, body.exchange.source(), label,
+                api_key, api_secret, now, now
+            ],
+        )?;
+    }
+
+    let connection = load_connection(&state, &user, &id)?;
+    let (pool, config, initial) = (state.db.clone(), state.config.clone(), connection.clone());
+    tokio::spawn(async move {
+        if let Err(e) = exchange_api::sync_connection(&pool, &config, &initial).await {
+            tracing::warn!("Initial import for exchange connection {} failed: {e}", initial.id);
+        }
+    });
+
+    Ok((StatusCode::CREATED, Json(connection)))
+}
+
+/// GET /api/v1/exchange-connections/{id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(connection_id): Path<String>,
+) -> AppResult<Json<ExchangeConnection>> {
+    Ok(Json(load_connection(&state, &user, &connection_id)?))
+}
+
+/// DELETE /api/v1/exchange-connections/{id}
+///
+/// Transactions already imported stay in the portfolio.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(connection_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let affected = conn.execute(
+        "DELETE FROM exchange_connections WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![connection_id, user.id],
+    )?;
+
+    if affected == 0 {
+        return Err(AppError::NotFound("Exchange connection not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/exchange-connections/{id}/test
+pub async fn test(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(connection_id): Path<String>,
+) -> AppResult<Json<ConnectionTestResult>> {
+    let connection = load_connection(&state, &user, &connection_id)?;
+    let btc_balance_sat =
+        exchange_api::test(&state.config, connection.exchange, &connection.api_key, &connection.api_secret).await?;
+    Ok(Json(ConnectionTestResult { ok: true, btc_balance_sat }))
+}
+
+/// POST /api/v1/exchange-connections/{id}/sync
+///
+/// Import new trades now instead of waiting for the background importer.
+pub async fn sync(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(connection_id): Path<String>,
+) -> AppResult<Json<ImportResult>> {
+    let connection = load_connection(&state, &user, &connection_id)?;
+    {
+        let conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &connection.portfolio_id, Access::Write)?;
+    }
+    let result = exchange_api::sync_connection(&state.db, &state.config, &connection).await?;
+    Ok(Json(result))
+}
+
+fn load_connection(state: &AppState, user: &User, connection_id: &str) -> AppResult<ExchangeConnection> {
+    let conn = state.db.get()?;
+    conn.query_row(
+        &format!("SELECT {CONNECTION_COLS} FROM exchange_connections WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![connection_id, user.id],
+        exchange_api::row_to_connection,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Exchange connection not found".into()),
+        e => AppError::Database(e),
+    })
+}
@@ -1,9 +1,14 @@
-use axum::{extract::State, Extension, Json};
+use axum::{
+    extract::{Query, State},
+    Extension, Json,
+};
+use serde::Deserialize;
 
 use crate::error::AppResult;
 use crate::models::User;
 use crate::routes::AppState;
 use crate::services::fees;
+use crate::services::wallet::parse_network;
 
 /// GET /api/v1/fees/recommended
 pub async fn recommended(
@@ -13,3 +18,20 @@ pub async fn recommended(
     let rates = fees::fetch_fee_rates().await?;
     Ok(Json(rates))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct EstimatesQuery {
+    pub network: Option<String>,
+}
+
+/// GET /api/v1/fees/estimates
+///
+/// Public so invoice payment pages can show fee guidance without a session.
+pub async fn estimates(
+    State(state): State<AppState>,
+    Query(query): Query<EstimatesQuery>,
+) -> AppResult<Json<fees::FeeEstimates>> {
+    let network = parse_network(query.network.as_deref().unwrap_or("bitcoin"))?;
+    let estimates = fees::estimates(&state.esplora, &state.cache, network).await?;
+    Ok(Json(estimates))
+}
@@ -0,0 +1,151 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::exchange_import::{self, ColumnMapping, ImportTemplate, TEMPLATE_COLS};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTemplateRequest {
+    pub name: String,
+    pub mapping: ColumnMapping,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTemplateRequest {
+    pub name: Option<String>,
+    pub mapping: Option<ColumnMapping>,
+}
+
+/// GET /api/v1/import-templates
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<ImportTemplate>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {TEMPLATE_COLS} FROM import_templates WHERE user_id = ?1 ORDER BY name"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], exchange_import::row_to_template)?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// POST /api/v1/import-templates
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<CreateTemplateRequest>,
+) -> AppResult<(StatusCode, Json<ImportTemplate>)> {
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Name is required".into()));
+    }
+    body.mapping.validate()?;
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mapping = serde_json::to_string(&body.mapping)
+        .map_err(|e| AppError::Internal(format!("Failed to encode mapping: {e}")))?;
+
+    let conn = state.db.get()?;
+    let result = conn.execute(
+        "INSERT INTO import_templates (id, user_id, name, mapping, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![id, user.id, name, mapping, now, now],
+    );
+    match result {
+        Ok(_) => {}
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            return Err(AppError::Conflict("Template with this name already exists".into()));
+        }
+        Err(e) => return Err(AppError::Database(e)),
+    }
+
+    Ok((StatusCode::CREATED, Json(ImportTemplate {
+        id,
+        user_id: user.id,
+        name,
+        mapping: body.mapping,
+        created_at: now.clone(),
+        updated_at: now,
+    })))
+}
+
+/// GET /api/v1/import-templates/{id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+) -> AppResult<Json<ImportTemplate>> {
+    let conn = state.db.get()?;
+    Ok(Json(exchange_import::load_template(&conn, &user.id, &template_id)?))
+}
+
+/// PUT /api/v1/import-templates/{id}
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+    Json(body): Json<UpdateTemplateRequest>,
+) -> AppResult<Json<ImportTemplate>> {
+    let conn = state.db.get()?;
+    let existing = exchange_import::load_template(&conn, &user.id, &template_id)?;
+
+    let name = match body.name.map(|n| n.trim().to_string()) {
+        Some(n) if n.is_empty() => return Err(AppError::BadRequest("Name is required".into())),
+        Some(n) => n,
+        None => existing.name,
+    };
+    let mapping = body.mapping.unwrap_or(existing.mapping);
+    mapping.validate()?;
+    let encoded = serde_json::to_string(&mapping)
+        .map_err(|e| AppError::Internal(format!("Failed to encode mapping: {e}")))?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    let result = conn.execute(
+        "UPDATE import_templates SET name = ?1, mapping = ?2, updated_at = ?3 WHERE id = ?4",
+        rusqlite::params![name, encoded, now, template_id],
+    );
+    match result {
+        Ok(_) => {}
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            return Err(AppError::Conflict("Template with this name already exists".into()));
+        }
+        Err(e) => return Err(AppError::Database(e)),
+    }
+
+    Ok(Json(ImportTemplate {
+        name,
+        mapping,
+        updated_at: now,
+        ..existing
+    }))
+}
+
+/// DELETE /api/v1/import-templates/{id}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let affected = conn.execute(
+        "DELETE FROM import_templates WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![template_id, user.id],
+    )?;
+
+    if affected == 0 {
+        return Err(AppError::NotFound("Import template not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,172 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::exchange_import::{self, ExchangeFormat};
+use crate::services::import::{self, ImportFormat, ImportResult, ImportRowError, ImportedTransaction};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Attach imported transactions to this wallet so a later chain sync
+    /// recognises them instead of inserting duplicates.
+    pub wallet_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeImportQuery {
+    pub format: ExchangeFormat,
+    /// Required with `format=custom`: the saved column mapping to apply.
+    pub template_id: Option<String>,
+    pub wallet_id: Option<String>,
+    /// Without this the upload is only parsed and checked, and nothing is written.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PreviewRow {
+    #[serde(flatten)]
+    pub transaction: ImportedTransaction,
+    /// Already in the portfolio; confirming will skip it.
+    pub duplicate: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportPreview {
+    pub format: ExchangeFormat,
+    pub rows: Vec<PreviewRow>,
+    pub new_rows: usize,
+    pub duplicates: usize,
+    pub ignored_rows: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ExchangeImportResponse {
+    Preview(ImportPreview),
+    Imported(ImportResult),
+}
+
+/// POST /api/v1/portfolios/:portfolio_id/import/:format
+/// Body is the raw CSV export (Electrum history or Wasabi transactions).
+pub async fn import_history(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path((portfolio_id, format)): Path<(String, ImportFormat)>,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> AppResult<Json<ImportResult>> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+
+    if let Some(ref wallet_id) = query.wallet_id {
+        policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, wallet_id, Access::Write)?;
+    }
+
+    let (txs, errors) = import::parse(format, &body)?;
+    if txs.is_empty() && !errors.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "No rows could be imported ({} invalid)",
+            errors.len()
+        )));
+    }
+
+    let tx = conn.transaction()?;
+    let mut result = import::insert_transactions(
+        &tx,
+        &user.id,
+        Some((&user, &client)),
+        &portfolio_id,
+        query.wallet_id.as_deref(),
+        format.source(),
+        &txs,
+    )?;
+    tx.commit()?;
+
+    tracing::info!(
+        "Imported {} {} transactions into portfolio {portfolio_id} ({} duplicates, {} invalid rows)",
+        result.imported, format.source(), result.skipped_duplicates, errors.len()
+    );
+
+    result.errors = errors;
+    Ok(Json(result))
+}
+
+/// POST /api/v1/portfolios/:portfolio_id/transactions/import?format=coinbase|kraken|strike|river|generic|custom
+/// Body is the raw CSV report; `custom` reads it with a saved import template. Returns a preview of the parsed rows, with duplicates
+/// flagged, unless `confirm=true` is set, in which case the new rows are inserted.
+pub async fn import_exchange(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<ExchangeImportQuery>,
+    body: String,
+) -> AppResult<Json<ExchangeImportResponse>> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+
+    if let Some(ref wallet_id) = query.wallet_id {
+        policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, wallet_id, Access::Write)?;
+    }
+
+    let parsed = match (query.format, query.template_id.as_deref()) {
+        (ExchangeFormat::Custom, Some(template_id)) => {
+            let template = exchange_import::load_template(&conn, &user.id, template_id)?;
+            exchange_import::parse_mapped(&template.mapping, &body)?
+        }
+        (format, _) => exchange_import::parse(format, &body)?,
+    };
+    if parsed.transactions.is_empty() && !parsed.errors.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "No rows could be imported ({} invalid)",
+            parsed.errors.len()
+        )));
+    }
+
+    if !query.confirm {
+        let mut rows = Vec::with_capacity(parsed.transactions.len());
+        for transaction in parsed.transactions {
+            let duplicate = import::is_duplicate(&conn, &portfolio_id, &transaction)?;
+            rows.push(PreviewRow { transaction, duplicate });
+        }
+        let duplicates = rows.iter().filter(|r| r.duplicate).count();
+        return Ok(Json(ExchangeImportResponse::Preview(ImportPreview {
+            format: query.format,
+            new_rows: rows.len() - duplicates,
+            rows,
+            duplicates,
+            ignored_rows: parsed.ignored_rows,
+            errors: parsed.errors,
+        })));
+    }
+
+    let tx = conn.transaction()?;
+    let mut result = import::insert_transactions(
+        &tx,
+        &user.id,
+        Some((&user, &client)),
+        &portfolio_id,
+        query.wallet_id.as_deref(),
+        query.format.source(),
+        &parsed.transactions,
+    )?;
+    tx.commit()?;
+
+    tracing::info!(
+        "Imported {} {} transactions into portfolio {portfolio_id} ({} duplicates, {} invalid rows)",
+        result.imported, query.format.source(), result.skipped_duplicates, parsed.errors.len()
+    );
+
+    result.errors = parsed.errors;
+    Ok(Json(ExchangeImportResponse::Imported(result)))
+}
@@ -0,0 +1,257 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+
+/// Longest note or message accepted, in characters.
+const MAX_BODY_CHARS: usize = 4000;
+/// Customer messages accepted per invoice, so a leaked payment link can't be used to
+/// flood the merchant's log.
+const MAX_CUSTOMER_MESSAGES: i64 = 50;
+
+#[derive(Debug, Serialize)]
+pub struct InvoiceNote {
+    pub id: String,
+    pub invoice_id: String,
+    /// "merchant" or "customer".
+    pub author: String,
+    pub author_user_id: Option<String>,
+    pub author_name: Option<String>,
+    /// "internal" notes are only shown to the merchant; "public" ones also appear on the payment page.
+    pub visibility: String,
+    pub body: String,
+    pub created_at: String,
+}
+
+/// A message as shown on the public payment page.
+#[derive(Debug, Serialize)]
+pub struct PublicInvoiceMessage {
+    pub author: String,
+    pub author_name: Option<String>,
+    pub body: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateNoteRequest {
+    pub body: String,
+    /// Defaults to "internal".
+    pub visibility: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateMessageRequest {
+    pub name: Option<String>,
+    pub body: String,
+}
+
+const NOTE_COLS: &str = "id, invoice_id, author, author_user_id, author_name, visibility, body, created_at";
+
+fn row_to_note(row: &rusqlite::Row) -> rusqlite::Result<InvoiceNote> {
+    Ok(InvoiceNote {
+        id: row.get(0)?,
+        invoice_id: row.get(1)?,
+        author: row.get(2)?,
+        author_user_id: row.get(3)?,
+        author_name: row.get(4)?,
+        visibility: row.get(5)?,
+        body: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+fn validate_body(body: &str) -> AppResult<String> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(AppError::BadRequest("Message cannot be empty".into()));
+    }
+    if body.chars().count() > MAX_BODY_CHARS {
+        return Err(AppError::BadRequest(format!(
+            "Message is too long (max {MAX_BODY_CHARS} characters)"
+        )));
+    }
+    Ok(body.to_string())
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/invoices/{id}/notes
+///
+/// The full log, internal notes included, oldest first.
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+) -> AppResult<Json<Vec<InvoiceNote>>> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Read)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {NOTE_COLS} FROM invoice_notes WHERE invoice_id = ?1 ORDER BY created_at"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![invoice_id], row_to_note)?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/invoices/{id}/notes
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+    Json(body): Json<CreateNoteRequest>,
+) -> AppResult<(StatusCode, Json<InvoiceNote>)> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
+
+    let text = validate_body(&body.body)?;
+    let visibility = body.visibility.unwrap_or_else(|| "internal".to_string());
+    if !["internal", "public"].contains(&visibility.as_str()) {
+        return Err(AppError::BadRequest(
+            "Invalid visibility. Must be one of: internal, public".into(),
+        ));
+    }
+
+    let note = InvoiceNote {
+        id: Uuid::new_v4().to_string(),
+        invoice_id,
+        author: "merchant".into(),
+        author_user_id: Some(user.id),
+        author_name: Some(user.name),
+        visibility,
+        body: text,
+        created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+    };
+    insert_note(&conn, &note)?;
+
+    Ok((StatusCode::CREATED, Json(note)))
+}
+
+/// DELETE /api/v1/portfolios/{portfolio_id}/invoices/{id}/notes/{note_id}
+///
+/// Also removes customer messages, e.g. spam posted through the payment page.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id, note_id)): Path<(String, String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
+
+    let affected = conn.execute(
+        "DELETE FROM invoice_notes WHERE id = ?1 AND invoice_id = ?2",
+        rusqlite::params![note_id, invoice_id],
+    )?;
+
+    if affected == 0 {
+        return Err(AppError::NotFound("Note not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/invoices/pay/{share_token}/messages — Public endpoint (no auth)
+pub async fn public_list(
+    State(state): State<AppState>,
+    Path(share_token): Path<String>,
+) -> AppResult<Json<Vec<PublicInvoiceMessage>>> {
+    let conn = state.db.get()?;
+    let invoice_id = invoice_by_share_token(&conn, &share_token)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT author, author_name, body, created_at FROM invoice_notes
+         WHERE invoice_id = ?1 AND visibility = 'public'
+         ORDER BY created_at",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![invoice_id], |row| {
+        Ok(PublicInvoiceMessage {
+            author: row.get(0)?,
+            author_name: row.get(1)?,
+            body: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    })?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// POST /api/v1/invoices/pay/{share_token}/messages — Public endpoint (no auth)
+///
+/// Lets the payer reply on the payment page. Customer messages are always public.
+pub async fn public_create(
+    State(state): State<AppState>,
+    Path(share_token): Path<String>,
+    Json(body): Json<CreateMessageRequest>,
+) -> AppResult<(StatusCode, Json<PublicInvoiceMessage>)> {
+    let conn = state.db.get()?;
+    let invoice_id = invoice_by_share_token(&conn, &share_token)?;
+
+    let text = validate_body(&body.body)?;
+    let name = body
+        .name
+        .map(|n| n.trim().chars().take(100).collect::<String>())
+        .filter(|n| !n.is_empty());
+
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM invoice_notes WHERE invoice_id = ?1 AND author = 'customer'",
+        rusqlite::params![invoice_id],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_CUSTOMER_MESSAGES {
+        return Err(AppError::Forbidden(
+            "This invoice has reached its message limit; please contact the merchant directly".into(),
+        ));
+    }
+
+    let note = InvoiceNote {
+        id: Uuid::new_v4().to_string(),
+        invoice_id,
+        author: "customer".into(),
+        author_user_id: None,
+        author_name: name,
+        visibility: "public".into(),
+        body: text,
+        created_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+    };
+    insert_note(&conn, &note)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PublicInvoiceMessage {
+            author: note.author,
+            author_name: note.author_name,
+            body: note.body,
+            created_at: note.created_at,
+        }),
+    ))
+}
+
+fn invoice_by_share_token(conn: &rusqlite::Connection, share_token: &str) -> AppResult<String> {
+    conn.query_row(
+        "SELECT id FROM invoices WHERE share_token = ?1 AND share_enabled = 1",
+        rusqlite::params![share_token],
+        |row| row.get(0),
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+fn insert_note(conn: &rusqlite::Connection, note: &InvoiceNote) -> AppResult<()> {
+    conn.execute(
+        "INSERT INTO invoice_notes (id, invoice_id, author, author_user_id, author_name, visibility, body, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            note.id, note.invoice_id, note.author, note.author_user_id,
+            note.author_name, note.visibility, note.body, note.created_at
+        ],
+    )?;
+    Ok(())
+}
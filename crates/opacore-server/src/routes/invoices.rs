@@ -1,15 +1,33 @@
+use std::collections::HashSet;
+
 use axum::{
     extract::{Path, Query, State},
+    response::IntoResponse,
     Extension, Json,
 };
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, StatusCode};
+use bdk_wallet::rusqlite::Connection as BdkConnection;
+use bdk_wallet::{KeychainKind, PersistedWallet};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access};
+use crate::config::LightningConfig;
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::branding::{self, Branding};
+use crate::services::customers::{self, Customer};
 use crate::services::invoice_checker;
+use crate::services::invoice_emails;
+use crate::services::invoice_history::{self, Actor, InvoiceEvent, InvoiceHistoryEntry, HISTORY_COLS};
+use crate::services::lightning;
+use crate::services::pagination::{self, Cursor};
+use crate::services::pdf::{self, Font, PdfPage};
+use crate::services::prices;
+use crate::services::qr::QrCode;
+use crate::services::quotas;
+use crate::services::wallet as wallet_svc;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Invoice {
@@ -21,21 +39,56 @@ pub struct Invoice {
     pub invoice_number: Option<String>,
     pub customer_name: Option<String>,
     pub customer_email: Option<String>,
+    /// The directory entry the customer name and email were taken from.
+    pub customer_id: Option<String>,
     pub description: Option<String>,
     pub amount_sat: i64,
     pub amount_fiat: Option<f64>,
     pub fiat_currency: String,
     pub btc_price_at_creation: Option<f64>,
+    /// `amount_fiat` is what's owed, and `amount_sat` a quote for it that the payment page
+    /// renews at the current price once `rate_expires_at` passes.
+    pub fiat_denominated: bool,
+    /// The price `amount_sat` was last quoted at, and when.
+    pub rate_btc_price: Option<f64>,
+    pub rate_locked_at: Option<String>,
+    pub rate_expires_at: Option<String>,
     pub btc_address: String,
+    /// BOLT11 for paying over Lightning instead, while `lightning_expires_at` is ahead.
+    pub lightning_invoice: Option<String>,
+    pub lightning_payment_hash: Option<String>,
+    pub lightning_expires_at: Option<String>,
     pub wallet_id: Option<String>,
     pub status: String,
     pub share_token: String,
+    /// Off, the payment page and other public endpoints for `share_token` are gone.
+    pub share_enabled: bool,
     pub issued_at: Option<String>,
     pub due_at: Option<String>,
     pub expires_at: Option<String>,
     pub paid_at: Option<String>,
     pub paid_txid: Option<String>,
     pub paid_amount_sat: Option<i64>,
+    /// `onchain` or `lightning`.
+    pub paid_via: Option<String>,
+    /// Where `btc_address` sits in the linked wallet, if it was derived from one.
+    pub address_keychain: Option<String>,
+    pub address_index: Option<u32>,
+    pub address_derivation_path: Option<String>,
+    /// Whether the public page may reveal the wallet's descriptor so customers can verify the address.
+    pub address_proof: bool,
+    /// Confirmations the payment needs; until then the invoice is `pending_confirmation`.
+    pub min_confirmations: i64,
+    /// When the invoice was first and last emailed to the customer.
+    pub sent_at: Option<String>,
+    pub last_sent_at: Option<String>,
+    pub send_count: i64,
+    /// Days before `due_at` to email the customer a reminder, if still unpaid.
+    pub reminder_days_before: Option<i64>,
+    pub reminder_sent_at: Option<String>,
+    /// Payment page views, with repeat loads by the same browser close together counted once.
+    pub view_count: i64,
+    pub last_viewed_at: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -49,13 +102,26 @@ pub struct CreateInvoiceRequest {
     pub invoice_number: Option<String>,
     pub customer_name: Option<String>,
     pub customer_email: Option<String>,
+    /// A customer from the portfolio's directory, whose name, email and default currency
+    /// fill in `customer_name`, `customer_email` and `fiat_currency` when left out.
+    pub customer_id: Option<String>,
     pub description: Option<String>,
     pub amount_sat: Option<i64>,
     pub amount_fiat: Option<f64>,
     pub fiat_currency: Option<String>,
     pub btc_price_at_creation: Option<f64>,
-    pub btc_address: String,
+    /// Bill `amount_fiat` rather than a fixed `amount_sat`: the sat amount is quoted at the
+    /// current price and held for `INVOICE_RATE_VALIDITY_SECS`, then requoted.
+    pub fiat_denominated: Option<bool>,
+    /// Left out to derive a fresh receive address from `wallet_id`.
+    pub btc_address: Option<String>,
     pub wallet_id: Option<String>,
+    /// Also issue a BOLT11 from the Lightning backend. Defaults to on when one is
+    /// configured, for one-time invoices with an amount.
+    pub lightning: Option<bool>,
+    pub address_proof: Option<bool>,
+    /// Defaults to `INVOICE_MIN_CONFIRMATIONS`.
+    pub min_confirmations: Option<i64>,
     pub due_at: Option<String>,
     pub expires_at: Option<String>,
 }
@@ -65,9 +131,27 @@ pub struct UpdateInvoiceRequest {
     pub status: Option<String>,
     pub customer_name: Option<String>,
     pub customer_email: Option<String>,
+    /// Links the invoice to a directory customer, taking its name and email unless they
+    /// are given too; an empty string unlinks it.
+    pub customer_id: Option<String>,
     pub description: Option<String>,
     pub due_at: Option<String>,
     pub expires_at: Option<String>,
+    pub address_proof: Option<bool>,
+    pub min_confirmations: Option<i64>,
+    /// Takes the payment link down, or back up, keeping its token.
+    pub share_enabled: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SendInvoiceRequest {
+    /// Defaults to the invoice's `customer_email`, which is updated when given.
+    pub to: Option<String>,
+    /// A note shown above the invoice details.
+    pub message: Option<String>,
+    /// Email a reminder this many days (1–30) before `due_at` if still unpaid; 0 turns
+    /// it off, leaving it out keeps the current setting.
+    pub reminder_days_before: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,8 +159,18 @@ pub struct ListInvoicesQuery {
     #[serde(rename = "type")]
     pub record_type: Option<String>,
     pub status: Option<String>,
+    pub customer_id: Option<String>,
     pub limit: Option<i64>,
     pub offset: Option<i64>,
+    /// `next_cursor` from the previous page; takes precedence over `offset`.
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvoiceListResponse {
+    pub data: Vec<Invoice>,
+    /// Pass as `after` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
 }
 
 /// Public-facing invoice data (no sensitive fields)
@@ -91,15 +185,47 @@ pub struct PublicInvoice {
     pub amount_sat: i64,
     pub amount_fiat: Option<f64>,
     pub fiat_currency: String,
+    pub fiat_denominated: bool,
+    /// What `amount_sat` was quoted at and until when, for fiat-denominated invoices.
+    pub rate_btc_price: Option<f64>,
+    pub rate_expires_at: Option<String>,
     pub btc_address: String,
+    /// BOLT11, only while it can still be paid.
+    pub lightning_invoice: Option<String>,
     pub status: String,
     pub expires_at: Option<String>,
     pub paid_at: Option<String>,
     pub paid_txid: Option<String>,
     pub paid_amount_sat: Option<i64>,
+    pub paid_via: Option<String>,
+    /// True if `/proof` can show where the address came from.
+    pub address_proof_available: bool,
+    pub min_confirmations: i64,
+    /// BIP-21 `bitcoin:` URI for wallets to open or scan (see `/qr`).
+    pub payment_uri: String,
+    pub branding: Branding,
 }
 
-const INVOICE_COLS: &str = "id, portfolio_id, type, reusable, invoice_number, customer_name, customer_email, description, amount_sat, amount_fiat, fiat_currency, btc_price_at_creation, btc_address, wallet_id, status, share_token, issued_at, due_at, expires_at, paid_at, paid_txid, paid_amount_sat, created_at, updated_at";
+#[derive(Debug, Deserialize)]
+pub struct QrQuery {
+    /// `svg` (default) or `png`.
+    pub format: Option<String>,
+}
+
+/// Evidence that an invoice address belongs to the merchant's wallet. The descriptor is
+/// only included if the merchant opted in; with it, a customer can derive the address at
+/// `derivation_paths` themselves instead of trusting this server.
+#[derive(Debug, Serialize)]
+pub struct AddressProof {
+    pub address: String,
+    pub keychain: String,
+    pub index: u32,
+    pub derivation_paths: Vec<String>,
+    pub network: Option<String>,
+    pub descriptor: Option<String>,
+}
+
+const INVOICE_COLS: &str = "id, portfolio_id, type, reusable, invoice_number, customer_name, customer_email, description, amount_sat, amount_fiat, fiat_currency, btc_price_at_creation, btc_address, wallet_id, status, share_token, issued_at, due_at, expires_at, paid_at, paid_txid, paid_amount_sat, created_at, updated_at, address_keychain, address_index, address_derivation_path, address_proof, min_confirmations, sent_at, last_sent_at, send_count, reminder_days_before, reminder_sent_at, customer_id, paid_via, lightning_invoice, lightning_payment_hash, lightning_expires_at, fiat_denominated, rate_btc_price, rate_locked_at, rate_expires_at, share_enabled, view_count, last_viewed_at";
 
 fn row_to_invoice(row: &rusqlite::Row) -> rusqlite::Result<Invoice> {
     Ok(Invoice {
@@ -127,10 +253,32 @@ fn row_to_invoice(row: &rusqlite::Row) -> rusqlite::Result<Invoice> {
         paid_amount_sat: row.get(21)?,
         created_at: row.get(22)?,
         updated_at: row.get(23)?,
+        address_keychain: row.get(24)?,
+        address_index: row.get(25)?,
+        address_derivation_path: row.get(26)?,
+        address_proof: row.get::<_, i32>(27).map(|v| v != 0)?,
+        min_confirmations: row.get(28)?,
+        sent_at: row.get(29)?,
+        last_sent_at: row.get(30)?,
+        send_count: row.get(31)?,
+        reminder_days_before: row.get(32)?,
+        reminder_sent_at: row.get(33)?,
+        customer_id: row.get(34)?,
+        paid_via: row.get(35)?,
+        lightning_invoice: row.get(36)?,
+        lightning_payment_hash: row.get(37)?,
+        lightning_expires_at: row.get(38)?,
+        fiat_denominated: row.get::<_, i32>(39).map(|v| v != 0)?,
+        rate_btc_price: row.get(40)?,
+        rate_locked_at: row.get(41)?,
+        rate_expires_at: row.get(42)?,
+        share_enabled: row.get::<_, i32>(43).map(|v| v != 0)?,
+        view_count: row.get(44)?,
+        last_viewed_at: row.get(45)?,
     })
 }
 
-fn invoice_to_public(invoice: &Invoice) -> PublicInvoice {
+fn invoice_to_public(invoice: &Invoice, branding: Branding) -> PublicInvoice {
     PublicInvoice {
         record_type: invoice.record_type.clone(),
         reusable: invoice.reusable,
@@ -140,29 +288,265 @@ fn invoice_to_public(invoice: &Invoice) -> PublicInvoice {
         amount_sat: invoice.amount_sat,
         amount_fiat: invoice.amount_fiat,
         fiat_currency: invoice.fiat_currency.clone(),
+        fiat_denominated: invoice.fiat_denominated,
+        rate_btc_price: invoice.rate_btc_price,
+        rate_expires_at: invoice.rate_expires_at.clone(),
         btc_address: invoice.btc_address.clone(),
+        lightning_invoice: open_lightning_invoice(invoice).map(String::from),
         status: invoice.status.clone(),
         expires_at: invoice.expires_at.clone(),
         paid_at: invoice.paid_at.clone(),
         paid_txid: invoice.paid_txid.clone(),
         paid_amount_sat: invoice.paid_amount_sat,
+        paid_via: invoice.paid_via.clone(),
+        address_proof_available: invoice.address_index.is_some(),
+        min_confirmations: invoice.min_confirmations,
+        payment_uri: payment_uri(invoice),
+        branding,
     }
 }
 
-fn verify_portfolio_ownership(
-    conn: &rusqlite::Connection,
-    portfolio_id: &str,
-    user_id: &str,
-) -> AppResult<()> {
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user_id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Portfolio not found".into()));
+/// Percent-encode a BIP-21 query value, leaving only RFC 3986 unreserved characters.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Longest label or message put in a payment URI, so it always fits in a scannable QR code.
+const URI_TEXT_MAX_CHARS: usize = 100;
+
+/// The invoice's BOLT11 while it can still be paid: issued, unexpired, and the invoice open.
+fn open_lightning_invoice(invoice: &Invoice) -> Option<&str> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    if !matches!(invoice.status.as_str(), "draft" | "sent") {
+        return None;
     }
-    Ok(())
+    invoice.lightning_expires_at.as_deref().filter(|expires_at| *expires_at > now.as_str())?;
+    invoice.lightning_invoice.as_deref()
+}
+
+/// BIP-21 URI for paying `invoice`: its address, the amount in BTC when it has one, and
+/// the invoice number and description (shortened) as label and message. An open BOLT11
+/// goes in `lightning`, so wallets that support it can pay either way.
+fn payment_uri(invoice: &Invoice) -> String {
+    let text = |s: &str| uri_encode(&s.chars().take(URI_TEXT_MAX_CHARS).collect::<String>());
+    let mut params = Vec::new();
+    if invoice.amount_sat > 0 {
+        params.push(format!("amount={}", invoice_emails::format_btc(invoice.amount_sat)));
+    }
+    if let Some(number) = invoice.invoice_number.as_deref().filter(|n| !n.is_empty()) {
+        params.push(format!("label={}", text(&format!("Invoice {number}"))));
+    }
+    if let Some(description) = invoice.description.as_deref().filter(|d| !d.is_empty()) {
+        params.push(format!("message={}", text(description)));
+    }
+    if let Some(bolt11) = open_lightning_invoice(invoice) {
+        params.push(format!("lightning={}", uri_encode(bolt11)));
+    }
+    if params.is_empty() {
+        format!("bitcoin:{}", invoice.btc_address)
+    } else {
+        format!("bitcoin:{}?{}", invoice.btc_address, params.join("&"))
+    }
+}
+
+/// Lines of payment instructions the PDF's footer has room for.
+const MAX_INSTRUCTION_LINES: usize = 10;
+
+/// The invoice as a one-page A4 document for the customer's accounting: merchant and
+/// customer, the line, BTC and fiat amounts, and a QR code of the payment URI. A linked
+/// directory `customer` adds their billing details; the portfolio's `branding` its
+/// business name, accent color and payment instructions.
+fn invoice_pdf(
+    invoice: &Invoice,
+    customer: Option<&Customer>,
+    branding: &Branding,
+    merchant_name: &str,
+    merchant_email: &str,
+    pay_url: &str,
+) -> AppResult<Vec<u8>> {
+    const LEFT: f64 = 50.0;
+    const RIGHT: f64 = pdf::PAGE_WIDTH - 50.0;
+    let date = |ts: &str| ts.get(..10).unwrap_or(ts).to_string();
+    let reference = invoice.invoice_number.as_deref().filter(|n| !n.is_empty());
+    let accent = branding.accent_color.as_deref().and_then(branding::parse_color);
+    let mut page = PdfPage::new();
+
+    // Merchant on the left, invoice reference and dates on the right
+    page.text(LEFT, 72.0, Font::Bold, 20.0, 0.0, branding.business_name.as_deref().unwrap_or(merchant_name));
+    page.text(LEFT, 90.0, Font::Regular, 10.0, 0.4, merchant_email);
+    match accent {
+        Some(rgb) => page.text_rgb(RIGHT - pdf::text_width(Font::Bold, 22.0, "INVOICE"), 72.0, Font::Bold, 22.0, rgb, "INVOICE"),
+        None => page.text_right(RIGHT, 72.0, Font::Bold, 22.0, 0.0, "INVOICE"),
+    }
+    let mut meta = Vec::new();
+    if let Some(number) = reference {
+        meta.push(format!("No. {number}"));
+    }
+    if let Some(issued_at) = invoice.issued_at.as_deref() {
+        meta.push(format!("Issued {}", date(issued_at)));
+    }
+    if let Some(due_at) = invoice.due_at.as_deref() {
+        meta.push(format!("Due {}", date(due_at)));
+    }
+    for (i, line) in meta.iter().enumerate() {
+        page.text_right(RIGHT, 92.0 + i as f64 * 14.0, Font::Regular, 10.0, 0.3, line);
+    }
+    match accent {
+        Some(rgb) => page.hline_rgb(LEFT, RIGHT, 140.0, 1.5, rgb),
+        None => page.hline(LEFT, RIGHT, 140.0, 0.75, 0.8),
+    }
+
+    // Bill to
+    let mut y = 168.0;
+    page.text(LEFT, y, Font::Bold, 8.0, 0.5, "BILL TO");
+    for line in [invoice.customer_name.as_deref(), invoice.customer_email.as_deref()].into_iter().flatten() {
+        y += 15.0;
+        page.text(LEFT, y, Font::Regular, 11.0, 0.0, line);
+    }
+    if let Some(customer) = customer {
+        let mut billing = Vec::new();
+        if let Some(company) = customer.company.as_deref() {
+            billing.push(company.to_string());
+        }
+        if let Some(address) = customer.billing_address.as_deref() {
+            billing.extend(pdf::wrap(Font::Regular, 10.0, address, RIGHT - LEFT));
+        }
+        billing.truncate(6);
+        if let Some(tax_id) = customer.tax_id.as_deref() {
+            billing.push(format!("Tax ID: {tax_id}"));
+        }
+        for line in billing {
+            y += 13.0;
+            page.text(LEFT, y, Font::Regular, 10.0, 0.3, &line);
+        }
+    }
+
+    // The line: what it's for and how much, in BTC and the invoice's fiat currency
+    let fiat = invoice.amount_fiat.or_else(|| {
+        invoice
+            .btc_price_at_creation
+            .filter(|_| invoice.amount_sat > 0)
+            .map(|price| invoice.amount_sat as f64 / 100_000_000.0 * price)
+    });
+    let (btc, sats) = if invoice.amount_sat > 0 {
+        (format!("{} BTC", invoice_emails::format_btc(invoice.amount_sat)), format!("{} sats", invoice.amount_sat))
+    } else {
+        ("Any amount".to_string(), String::new())
+    };
+    let fiat_col = RIGHT - 130.0;
+    y = y.max(200.0) + 30.0;
+    page.rect(LEFT, y, RIGHT - LEFT, 22.0, 0.94);
+    page.text(LEFT + 8.0, y + 15.0, Font::Bold, 9.0, 0.3, "DESCRIPTION");
+    page.text_right(fiat_col - 16.0, y + 15.0, Font::Bold, 9.0, 0.3, "AMOUNT (BTC)");
+    page.text_right(RIGHT - 8.0, y + 15.0, Font::Bold, 9.0, 0.3, &format!("AMOUNT ({})", invoice.fiat_currency.to_uppercase()));
+    y += 22.0;
+    let description = invoice.description.as_deref().filter(|d| !d.trim().is_empty()).unwrap_or("Payment");
+    let mut lines = pdf::wrap(Font::Regular, 10.0, description, fiat_col - 160.0 - LEFT);
+    if lines.len() > 12 {
+        lines.truncate(12);
+        lines[11].push('…');
+    }
+    page.text_right(fiat_col - 16.0, y + 18.0, Font::Regular, 10.0, 0.0, &btc);
+    page.text_right(fiat_col - 16.0, y + 31.0, Font::Regular, 8.0, 0.5, &sats);
+    if let Some(fiat) = fiat {
+        page.text_right(RIGHT - 8.0, y + 18.0, Font::Regular, 10.0, 0.0, &pdf::format_fiat(fiat, &invoice.fiat_currency));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        page.text(LEFT + 8.0, y + 18.0 + i as f64 * 13.0, Font::Regular, 10.0, 0.0, line);
+    }
+    y += 18.0 + (lines.len().max(2) as f64 - 1.0) * 13.0 + 12.0;
+    page.hline(LEFT, RIGHT, y, 0.5, 0.8);
+
+    // Totals
+    y += 24.0;
+    page.text_right(fiat_col - 16.0, y, Font::Bold, 12.0, 0.0, &btc);
+    page.text(fiat_col - 220.0, y, Font::Bold, 12.0, 0.0, "Total");
+    if let Some(fiat) = fiat {
+        page.text_right(RIGHT - 8.0, y, Font::Bold, 12.0, 0.0, &pdf::format_fiat(fiat, &invoice.fiat_currency));
+    }
+    if let Some(price) = invoice.rate_btc_price.or(invoice.btc_price_at_creation) {
+        y += 15.0;
+        page.text_right(
+            RIGHT - 8.0,
+            y,
+            Font::Regular,
+            8.0,
+            0.5,
+            &format!("at 1 BTC = {}", pdf::format_fiat(price, &invoice.fiat_currency)),
+        );
+    }
+    // Quoted amounts lapse; the payment page has the current one after that
+    if let Some(expires_at) = invoice.rate_expires_at.as_deref().filter(|_| invoice.status != "paid") {
+        y += 12.0;
+        let until = expires_at.get(..16).unwrap_or(expires_at).replace('T', " ");
+        page.text_right(RIGHT - 8.0, y, Font::Regular, 8.0, 0.5, &format!("BTC amount valid until {until} UTC"));
+    }
+
+    // How to pay, or proof that it was
+    y += 45.0;
+    if invoice.status == "paid" {
+        // No payment code once paid, so the document can't prompt a second payment
+        let text_x = LEFT;
+        page.text(text_x, y + 14.0, Font::Bold, 14.0, 0.0, "PAID");
+        let mut line_y = y + 32.0;
+        if let Some(paid_at) = invoice.paid_at.as_deref() {
+            page.text(text_x, line_y, Font::Regular, 10.0, 0.2, &format!("Received {}", date(paid_at)));
+            line_y += 16.0;
+        }
+        if let Some(paid_amount) = invoice.paid_amount_sat {
+            page.text(text_x, line_y, Font::Regular, 10.0, 0.2, &format!("{} BTC", invoice_emails::format_btc(paid_amount)));
+            line_y += 16.0;
+        }
+        let proof = match (invoice.paid_via.as_deref(), &invoice.paid_txid, &invoice.lightning_payment_hash) {
+            (Some("lightning"), _, Some(payment_hash)) => Some(("Lightning payment hash", payment_hash)),
+            (_, Some(txid), _) => Some(("Transaction", txid)),
+            _ => None,
+        };
+        if let Some((label, id)) = proof {
+            page.text(text_x, line_y, Font::Regular, 9.0, 0.4, label);
+            for (i, chunk) in id.as_bytes().chunks(32).enumerate() {
+                let chunk = String::from_utf8_lossy(chunk);
+                page.text(text_x, line_y + 13.0 + i as f64 * 11.0, Font::Mono, 8.5, 0.0, &chunk);
+            }
+        }
+    } else {
+        let qr = QrCode::encode(payment_uri(invoice).as_bytes())?;
+        page.qr(LEFT, y, 120.0, &qr);
+        let text_x = LEFT + 145.0;
+        let heading = if open_lightning_invoice(invoice).is_some() { "Pay with bitcoin or Lightning" } else { "Pay with bitcoin" };
+        page.text(text_x, y + 14.0, Font::Bold, 12.0, 0.0, heading);
+        let amount = if invoice.amount_sat > 0 { format!("Send {btc} to:") } else { "Send any amount to:".to_string() };
+        page.text(text_x, y + 32.0, Font::Regular, 10.0, 0.2, &amount);
+        page.text(text_x, y + 48.0, Font::Mono, 9.0, 0.0, &invoice.btc_address);
+        page.text(text_x, y + 70.0, Font::Regular, 9.0, 0.4, "Scan the code with your wallet, or pay online at:");
+        page.text(text_x, y + 84.0, Font::Regular, 9.0, 0.0, pay_url);
+    }
+
+    // The merchant's payment instructions along the bottom of the page
+    if let Some(instructions) = branding.payment_instructions.as_deref() {
+        let mut lines = pdf::wrap(Font::Regular, 8.5, instructions, RIGHT - LEFT);
+        if lines.len() > MAX_INSTRUCTION_LINES {
+            lines.truncate(MAX_INSTRUCTION_LINES);
+            lines[MAX_INSTRUCTION_LINES - 1].push('…');
+        }
+        let top = pdf::PAGE_HEIGHT - 50.0 - (lines.len() as f64 - 1.0) * 11.0;
+        page.hline(LEFT, RIGHT, top - 16.0, 0.5, 0.8);
+        for (i, line) in lines.iter().enumerate() {
+            page.text(LEFT, top + i as f64 * 11.0, Font::Regular, 8.5, 0.3, line);
+        }
+    }
+
+    let title = match reference {
+        Some(number) => format!("Invoice {number}"),
+        None => "Invoice".to_string(),
+    };
+    page.finish(&title)
 }
 
 /// GET /api/v1/portfolios/{portfolio_id}/invoices
@@ -171,57 +555,383 @@ pub async fn list(
     Extension(user): Extension<User>,
     Path(portfolio_id): Path<String>,
     Query(query): Query<ListInvoicesQuery>,
-) -> AppResult<Json<Vec<Invoice>>> {
+) -> AppResult<Json<InvoiceListResponse>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
 
     let limit = query.limit.unwrap_or(50).min(200);
-    let offset = query.offset.unwrap_or(0);
+    let mut offset = query.offset.unwrap_or(0);
 
     let mut where_clause = "WHERE portfolio_id = ?1".to_string();
-    let mut params: Vec<Box<dyn rusqlite::types::ToSql>> = vec![Box::new(portfolio_id)];
+    let mut params: Vec<rusqlite::types::Value> = vec![portfolio_id.into()];
 
     if let Some(ref record_type) = query.record_type {
-        params.push(Box::new(record_type.clone()));
+        params.push(record_type.clone().into());
         where_clause.push_str(&format!(" AND type = ?{}", params.len()));
     }
 
     if let Some(ref status) = query.status {
-        params.push(Box::new(status.clone()));
+        params.push(status.clone().into());
         where_clause.push_str(&format!(" AND status = ?{}", params.len()));
     }
 
-    params.push(Box::new(limit));
+    if let Some(ref customer_id) = query.customer_id {
+        params.push(customer_id.clone().into());
+        where_clause.push_str(&format!(" AND customer_id = ?{}", params.len()));
+    }
+
+    if let Some(ref after) = query.after {
+        where_clause.push_str(&Cursor::decode(after)?.condition("created_at", "id", true, &mut params));
+        offset = 0;
+    }
+
+    params.push((limit + 1).into());
     let limit_idx = params.len();
-    params.push(Box::new(offset));
+    params.push(offset.into());
     let offset_idx = params.len();
 
     let sql = format!(
-        "SELECT {INVOICE_COLS} FROM invoices {where_clause} ORDER BY created_at DESC LIMIT ?{limit_idx} OFFSET ?{offset_idx}"
+        "SELECT {INVOICE_COLS} FROM invoices {where_clause} ORDER BY created_at DESC, id DESC LIMIT ?{limit_idx} OFFSET ?{offset_idx}"
     );
 
     let mut stmt = conn.prepare(&sql)?;
-    let rows = stmt.query_map(
-        rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
-        row_to_invoice,
+    let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), row_to_invoice)?;
+    let mut data = rows.collect::<Result<Vec<_>, _>>()?;
+    let next_cursor = pagination::next_cursor(&mut data, limit, |invoice| Cursor {
+        key: invoice.created_at.clone().into(),
+        id: invoice.id.clone(),
+    });
+
+    Ok(Json(InvoiceListResponse { data, next_cursor }))
+}
+
+struct LinkedWallet {
+    descriptor: Option<String>,
+    xpub: Option<String>,
+    derivation_path: Option<String>,
+    address: Option<String>,
+    network: String,
+    wallet_type: String,
+    gap_limit: u32,
+}
+
+fn load_linked_wallet(conn: &rusqlite::Connection, wallet_id: &str, portfolio_id: &str) -> AppResult<LinkedWallet> {
+    conn.query_row(
+        "SELECT descriptor, xpub, derivation_path, address, network, wallet_type, gap_limit FROM wallets WHERE id = ?1 AND portfolio_id = ?2",
+        rusqlite::params![wallet_id, portfolio_id],
+        |row| {
+            Ok(LinkedWallet {
+                descriptor: row.get(0)?,
+                xpub: row.get(1)?,
+                derivation_path: row.get(2)?,
+                address: row.get(3)?,
+                network: row.get(4)?,
+                wallet_type: row.get(5)?,
+                gap_limit: row.get(6)?,
+            })
+        },
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Wallet not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+fn open_linked_wallet(
+    state: &AppState,
+    wallet_id: &str,
+    wallet: &LinkedWallet,
+) -> AppResult<PersistedWallet<BdkConnection>> {
+    let (external_desc, internal_desc) = wallet_svc::build_descriptors(
+        wallet.descriptor.as_deref(),
+        wallet.xpub.as_deref(),
+        wallet.derivation_path.as_deref(),
+        wallet.address.as_deref(),
     )?;
-    let data: Result<Vec<_>, _> = rows.collect();
+    let network = wallet_svc::parse_network(&wallet.network)?;
+    let (bdk_wallet, _bdk_conn) = wallet_svc::load_or_create_bdk_wallet(
+        &state.config.bdk_wallets_dir,
+        wallet_id,
+        &external_desc,
+        &internal_desc,
+        network,
+    )?;
+    Ok(bdk_wallet)
+}
+
+/// Locate `address` in the linked wallet: `(keychain, index, derivation paths)`.
+/// Single-address wallets have no derivation, so `None` is returned once the address matches.
+fn linked_address_derivation(
+    state: &AppState,
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    wallet_id: &str,
+    address: &str,
+) -> AppResult<Option<(String, u32, String)>> {
+    let wallet = load_linked_wallet(conn, wallet_id, portfolio_id)?;
+    if wallet.wallet_type == "address" {
+        if wallet.address.as_deref() != Some(address) {
+            return Err(AppError::BadRequest("BTC address does not match the linked wallet".into()));
+        }
+        return Ok(None);
+    }
+
+    let bdk_wallet = open_linked_wallet(state, wallet_id, &wallet)?;
+    let (keychain, index) = wallet_svc::find_address_derivation(&bdk_wallet, address, wallet.gap_limit)
+        .ok_or_else(|| AppError::BadRequest("BTC address was not derived from the linked wallet".into()))?;
+    let paths = wallet_svc::address_derivation_paths(&bdk_wallet, keychain, index).join(",");
+    let keychain = match keychain {
+        KeychainKind::External => "external",
+        KeychainKind::Internal => "internal",
+    };
+    Ok(Some((keychain.to_string(), index, paths)))
+}
 
-    Ok(Json(data?))
+/// Most confirmations an invoice can wait for.
+const MAX_MIN_CONFIRMATIONS: i64 = 100;
+
+fn validate_min_confirmations(min_confirmations: i64) -> AppResult<()> {
+    if !(0..=MAX_MIN_CONFIRMATIONS).contains(&min_confirmations) {
+        return Err(AppError::BadRequest(format!(
+            "min_confirmations must be between 0 and {MAX_MIN_CONFIRMATIONS}"
+        )));
+    }
+    Ok(())
+}
+
+/// Most days before `due_at` a reminder can be scheduled.
+const MAX_REMINDER_DAYS: i64 = 30;
+
+/// Shortest life given to a BOLT11, even for an invoice about to expire.
+const MIN_LIGHTNING_EXPIRY_SECS: i64 = 60;
+
+/// `expires_at` for a one-time invoice starting now, from the portfolio's
+/// `invoice_expiry_hours`; `None` when that isn't set.
+fn default_expiry(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<Option<String>> {
+    let hours: Option<i64> = conn.query_row(
+        "SELECT invoice_expiry_hours FROM portfolios WHERE id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| row.get(0),
+    )?;
+    Ok(hours.map(|hours| {
+        (chrono::Utc::now() + chrono::Duration::hours(hours))
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string()
+    }))
+}
+
+/// Issue a BOLT11 for a one-time invoice, valid until the invoice expires or for the
+/// backend's configured lifetime, whichever is sooner.
+async fn issue_lightning(
+    config: &LightningConfig,
+    amount_sat: i64,
+    invoice_number: Option<&str>,
+    description: Option<&str>,
+    expires_at: Option<&str>,
+) -> AppResult<lightning::IssuedInvoice> {
+    let memo = match (invoice_number.filter(|n| !n.is_empty()), description.filter(|d| !d.is_empty())) {
+        (Some(number), _) => format!("Invoice {number}"),
+        (None, Some(description)) => description.chars().take(URI_TEXT_MAX_CHARS).collect(),
+        (None, None) => "Payment".to_string(),
+    };
+    let mut expiry_secs = config.invoice_expiry_secs as i64;
+    if let Some(expires_at) = expires_at.and_then(|e| chrono::DateTime::parse_from_rfc3339(e).ok()) {
+        let remaining = (expires_at.with_timezone(&chrono::Utc) - chrono::Utc::now()).num_seconds();
+        expiry_secs = expiry_secs.min(remaining.max(MIN_LIGHTNING_EXPIRY_SECS));
+    }
+    lightning::create_invoice(config, amount_sat, &memo, expiry_secs as u64).await
+}
+
+/// The sooner of the invoice's expiry and its quote's, which a BOLT11 mustn't outlive:
+/// a Lightning payment is for a fixed amount.
+fn lightning_deadline<'a>(expires_at: Option<&'a str>, rate_expires_at: Option<&'a str>) -> Option<&'a str> {
+    let parse = |ts: &str| chrono::DateTime::parse_from_rfc3339(ts).ok();
+    match (expires_at, rate_expires_at) {
+        (Some(a), Some(b)) if parse(a).zip(parse(b)).is_some_and(|(a, b)| a <= b) => Some(a),
+        (_, Some(b)) => Some(b),
+        (a, None) => a,
+    }
+}
+
+/// A sat amount for a fiat one at the current price, held for `INVOICE_RATE_VALIDITY_SECS`.
+struct RateQuote {
+    amount_sat: i64,
+    btc_price: f64,
+    locked_at: String,
+    expires_at: String,
+}
+
+async fn quote_fiat(state: &AppState, amount_fiat: f64, currency: &str) -> AppResult<RateQuote> {
+    let btc_price =
+        prices::live_price(&state.db, &state.cache, &state.config.coingecko_api_url, &currency.to_lowercase()).await?;
+    let amount_sat = (amount_fiat / btc_price * 100_000_000.0).round() as i64;
+    if amount_sat <= 0 {
+        return Err(AppError::BadRequest("amount_fiat is less than one sat".into()));
+    }
+    let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::seconds(state.config.invoice_rate_validity_secs as i64);
+    Ok(RateQuote {
+        amount_sat,
+        btc_price,
+        locked_at: now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        expires_at: expires_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+    })
+}
+
+/// Requote an open fiat-denominated invoice whose quote has lapsed, reissuing its BOLT11
+/// for the new amount. Returns whether it did. While an old BOLT11 can still be paid the
+/// quote stands, since a payment of it would otherwise fall short.
+async fn requote(state: &AppState, invoice: &Invoice) -> AppResult<bool> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let Some(amount_fiat) = invoice.amount_fiat.filter(|_| invoice.fiat_denominated) else {
+        return Ok(false);
+    };
+    if !matches!(invoice.status.as_str(), "draft" | "sent")
+        || invoice.rate_expires_at.as_deref().is_some_and(|e| e > now.as_str())
+        || open_lightning_invoice(invoice).is_some()
+    {
+        return Ok(false);
+    }
+
+    let quote = quote_fiat(state, amount_fiat, &invoice.fiat_currency).await?;
+    let issued = match (&state.config.lightning, &invoice.lightning_payment_hash) {
+        (Some(config), Some(_)) => {
+            let issued = issue_lightning(
+                config,
+                quote.amount_sat,
+                invoice.invoice_number.as_deref(),
+                invoice.description.as_deref(),
+                lightning_deadline(invoice.expires_at.as_deref(), Some(&quote.expires_at)),
+            )
+            .await;
+            match issued {
+                Ok(issued) => Some(issued),
+                Err(e) => {
+                    tracing::warn!("Invoice {} requoted without a Lightning invoice: {e}", invoice.id);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let (lightning_invoice, lightning_payment_hash, lightning_expires_at) = match issued {
+        Some(issued) => (Some(issued.bolt11), Some(issued.payment_hash), Some(issued.expires_at)),
+        None => (None, None, None),
+    };
+
+    // Concurrent page loads both quote, but only the first one's is kept
+    let conn = state.db.get()?;
+    let updated = conn.execute(
+        "UPDATE invoices SET amount_sat = ?1, rate_btc_price = ?2, rate_locked_at = ?3, rate_expires_at = ?4,
+             lightning_invoice = COALESCE(?5, lightning_invoice),
+             lightning_payment_hash = COALESCE(?6, lightning_payment_hash),
+             lightning_expires_at = COALESCE(?7, lightning_expires_at),
+             updated_at = ?3
+         WHERE id = ?8 AND status IN ('draft', 'sent') AND rate_expires_at IS ?9",
+        rusqlite::params![
+            quote.amount_sat, quote.btc_price, quote.locked_at, quote.expires_at,
+            lightning_invoice, lightning_payment_hash, lightning_expires_at,
+            invoice.id, invoice.rate_expires_at
+        ],
+    )?;
+    if updated > 0 {
+        tracing::info!(
+            "Invoice {} requoted at {} {}: {} sats",
+            invoice.id,
+            quote.btc_price,
+            invoice.fiat_currency.to_uppercase(),
+            quote.amount_sat
+        );
+    }
+    Ok(updated > 0)
+}
+
+/// Reveal a fresh receive address in the linked wallet for a new invoice, skipping any
+/// already on another invoice: `(address, keychain, index, derivation paths)`.
+fn derive_invoice_address(
+    state: &AppState,
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    wallet_id: &str,
+) -> AppResult<(String, String, u32, String)> {
+    let wallet = load_linked_wallet(conn, wallet_id, portfolio_id)?;
+    if wallet.wallet_type == "address" {
+        return Err(AppError::BadRequest(
+            "A single-address wallet can't derive a new address; give btc_address instead".into(),
+        ));
+    }
+
+    let mut stmt = conn.prepare("SELECT btc_address FROM invoices WHERE wallet_id = ?1")?;
+    let taken = stmt
+        .query_map(rusqlite::params![wallet_id], |row| row.get(0))?
+        .collect::<Result<HashSet<String>, _>>()?;
+
+    // Held while the reveal is persisted so a concurrent sync can't write over it
+    let _sync_guard = state.sync_locks.try_acquire(wallet_id).ok_or_else(|| {
+        AppError::Conflict("A sync is running for this wallet, try again shortly".into())
+    })?;
+    let (external_desc, internal_desc) = wallet_svc::build_descriptors(
+        wallet.descriptor.as_deref(),
+        wallet.xpub.as_deref(),
+        wallet.derivation_path.as_deref(),
+        wallet.address.as_deref(),
+    )?;
+    let (mut bdk_wallet, mut bdk_conn) = wallet_svc::load_or_create_bdk_wallet(
+        &state.config.bdk_wallets_dir,
+        wallet_id,
+        &external_desc,
+        &internal_desc,
+        wallet_svc::parse_network(&wallet.network)?,
+    )?;
+    let (index, address) = wallet_svc::reveal_unused_address(&mut bdk_wallet, &mut bdk_conn, &taken)?;
+    let paths = wallet_svc::address_derivation_paths(&bdk_wallet, KeychainKind::External, index).join(",");
+    Ok((address, "external".to_string(), index, paths))
 }
 
 /// POST /api/v1/invoices
+///
+/// With a `wallet_id` and no `btc_address`, the invoice gets the wallet's next unused
+/// receive address, so every invoice is paid to an address of its own.
 pub async fn create(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
-    Json(body): Json<CreateInvoiceRequest>,
+    Json(mut body): Json<CreateInvoiceRequest>,
 ) -> AppResult<(StatusCode, Json<Invoice>)> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &body.portfolio_id, &user.id)?;
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &body.portfolio_id, Access::Write)?;
+
+    if let Some(customer_id) = body.customer_id.as_deref() {
+        let customer = customers::load(&conn, &body.portfolio_id, customer_id)?;
+        body.customer_name = body.customer_name.filter(|n| !n.is_empty()).or(Some(customer.name));
+        body.customer_email = body.customer_email.filter(|e| !e.is_empty()).or(customer.email);
+        body.fiat_currency = body.fiat_currency.or(customer.default_currency);
+    }
 
     let record_type = body.record_type.as_deref().unwrap_or("invoice");
     let reusable = body.reusable.unwrap_or(false);
 
+    // Fiat-denominated invoices get their sat amount from the current price
+    let fiat_denominated = body.fiat_denominated.unwrap_or(false);
+    let quote = if fiat_denominated {
+        if reusable {
+            return Err(AppError::BadRequest("A reusable invoice can't be fiat-denominated".into()));
+        }
+        if body.amount_sat.is_some() {
+            return Err(AppError::BadRequest(
+                "A fiat-denominated invoice takes amount_fiat, not amount_sat".into(),
+            ));
+        }
+        let amount_fiat = body.amount_fiat.filter(|a| a.is_finite() && *a > 0.0).ok_or_else(|| {
+            AppError::BadRequest("A fiat-denominated invoice needs a positive amount_fiat".into())
+        })?;
+        let quote = quote_fiat(&state, amount_fiat, body.fiat_currency.as_deref().unwrap_or("usd")).await?;
+        body.amount_sat = Some(quote.amount_sat);
+        body.btc_price_at_creation = body.btc_price_at_creation.or(Some(quote.btc_price));
+        Some(quote)
+    } else {
+        None
+    };
+
     // Type-specific validation
     match record_type {
         "invoice" => {
@@ -238,7 +948,7 @@ pub async fn create(
             }
         }
         "payment_link" => {
-            // Payment links only require btc_address (already required by struct)
+            // Payment links only require an address (checked below)
         }
         _ => {
             return Err(AppError::BadRequest(
@@ -247,29 +957,107 @@ pub async fn create(
         }
     }
 
-    if body.btc_address.is_empty() {
-        return Err(AppError::BadRequest("BTC address is required".into()));
+    let (btc_address, derivation) = match (body.btc_address, body.wallet_id.as_deref()) {
+        (Some(address), _) if address.is_empty() => {
+            return Err(AppError::BadRequest("BTC address is required".into()));
+        }
+        (Some(address), Some(wallet_id)) => {
+            let derivation = linked_address_derivation(&state, &conn, &body.portfolio_id, wallet_id, &address)?;
+            (address, derivation)
+        }
+        (Some(address), None) => (address, None),
+        (None, Some(wallet_id)) => {
+            let (address, keychain, index, paths) =
+                derive_invoice_address(&state, &conn, &body.portfolio_id, wallet_id)?;
+            (address, Some((keychain, index, paths)))
+        }
+        (None, None) => {
+            return Err(AppError::BadRequest("Either btc_address or wallet_id is required".into()));
+        }
+    };
+    let (address_keychain, address_index, address_derivation_path) = match derivation {
+        Some((keychain, index, paths)) => (Some(keychain), Some(index), Some(paths).filter(|p| !p.is_empty())),
+        None => (None, None, None),
+    };
+    let address_proof = body.address_proof.unwrap_or(false);
+    let min_confirmations = body
+        .min_confirmations
+        .unwrap_or(state.config.invoice_min_confirmations as i64);
+    validate_min_confirmations(min_confirmations)?;
+    if !reusable && body.expires_at.is_none() {
+        body.expires_at = default_expiry(&conn, &body.portfolio_id)?;
     }
 
+    // A BOLT11 can be paid once, for a fixed amount
+    let amount_sat = body.amount_sat.unwrap_or(0);
+    let lightning_eligible = !reusable && amount_sat > 0;
+    let lightning_config = match (body.lightning, &state.config.lightning) {
+        (Some(true), None) => {
+            return Err(AppError::BadRequest("Lightning is not configured on this server".into()));
+        }
+        (Some(true), Some(_)) if !lightning_eligible => {
+            return Err(AppError::BadRequest("Lightning needs a one-time invoice with an amount".into()));
+        }
+        (Some(true), Some(config)) => Some(config),
+        (None, Some(config)) if lightning_eligible => Some(config),
+        _ => None,
+    };
+    let issued = match lightning_config {
+        Some(config) => {
+            let issued = issue_lightning(
+                config,
+                amount_sat,
+                body.invoice_number.as_deref(),
+                body.description.as_deref(),
+                lightning_deadline(body.expires_at.as_deref(), quote.as_ref().map(|q| q.expires_at.as_str())),
+            )
+            .await;
+            match issued {
+                Ok(issued) => Some(issued),
+                // On by default, but a node that's down shouldn't stop on-chain invoicing
+                Err(e) if body.lightning.is_none() => {
+                    tracing::warn!("Invoice created without a Lightning invoice: {e}");
+                    None
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        None => None,
+    };
+    let (lightning_invoice, lightning_payment_hash, lightning_expires_at) = match issued {
+        Some(issued) => (Some(issued.bolt11), Some(issued.payment_hash), Some(issued.expires_at)),
+        None => (None, None, None),
+    };
+    let (rate_btc_price, rate_locked_at, rate_expires_at) = match quote {
+        Some(quote) => (Some(quote.btc_price), Some(quote.locked_at), Some(quote.expires_at)),
+        None => (None, None, None),
+    };
+
     let id = Uuid::new_v4().to_string();
     let share_token = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     let fiat_currency = body.fiat_currency.as_deref().unwrap_or("usd");
-    let amount_sat = body.amount_sat.unwrap_or(0);
     let reusable_int: i32 = if reusable { 1 } else { 0 };
 
-    conn.execute(
-        "INSERT INTO invoices (id, portfolio_id, type, reusable, invoice_number, customer_name, customer_email, description, amount_sat, amount_fiat, fiat_currency, btc_price_at_creation, btc_address, wallet_id, status, share_token, issued_at, due_at, expires_at, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, 'draft', ?15, ?16, ?17, ?18, ?19, ?20)",
+    let db_tx = conn.transaction()?;
+    db_tx.execute(
+        "INSERT INTO invoices (id, portfolio_id, type, reusable, invoice_number, customer_name, customer_email, description, amount_sat, amount_fiat, fiat_currency, btc_price_at_creation, btc_address, wallet_id, status, share_token, issued_at, due_at, expires_at, created_at, updated_at, address_keychain, address_index, address_derivation_path, address_proof, min_confirmations, customer_id, lightning_invoice, lightning_payment_hash, lightning_expires_at, fiat_denominated, rate_btc_price, rate_locked_at, rate_expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, 'draft', ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33)",
         rusqlite::params![
             id, body.portfolio_id, record_type, reusable_int,
             body.invoice_number, body.customer_name,
             body.customer_email, body.description, amount_sat,
             body.amount_fiat, fiat_currency, body.btc_price_at_creation,
-            body.btc_address, body.wallet_id, share_token,
-            now, body.due_at, body.expires_at, now, now
+            btc_address, body.wallet_id, share_token,
+            now, body.due_at, body.expires_at, now, now,
+            address_keychain, address_index, address_derivation_path, address_proof,
+            min_confirmations, body.customer_id,
+            lightning_invoice, lightning_payment_hash, lightning_expires_at,
+            fiat_denominated, rate_btc_price, rate_locked_at, rate_expires_at
         ],
     )?;
+    invoice_history::record(&db_tx, &id, InvoiceEvent::Created, None, Actor::Merchant(&user.id), None)?;
+    db_tx.commit()?;
 
     let invoice = Invoice {
         id,
@@ -279,21 +1067,43 @@ pub async fn create(
         invoice_number: body.invoice_number,
         customer_name: body.customer_name,
         customer_email: body.customer_email,
+        customer_id: body.customer_id,
         description: body.description,
         amount_sat,
         amount_fiat: body.amount_fiat,
         fiat_currency: fiat_currency.to_string(),
         btc_price_at_creation: body.btc_price_at_creation,
-        btc_address: body.btc_address,
+        fiat_denominated,
+        rate_btc_price,
+        rate_locked_at,
+        rate_expires_at,
+        btc_address,
+        lightning_invoice,
+        lightning_payment_hash,
+        lightning_expires_at,
         wallet_id: body.wallet_id,
         status: "draft".to_string(),
         share_token,
+        share_enabled: true,
         issued_at: Some(now.clone()),
         due_at: body.due_at,
         expires_at: body.expires_at,
         paid_at: None,
         paid_txid: None,
         paid_amount_sat: None,
+        paid_via: None,
+        address_keychain,
+        address_index,
+        address_derivation_path,
+        address_proof,
+        min_confirmations,
+        sent_at: None,
+        last_sent_at: None,
+        send_count: 0,
+        reminder_days_before: None,
+        reminder_sent_at: None,
+        view_count: 0,
+        last_viewed_at: None,
         created_at: now.clone(),
         updated_at: now,
     };
@@ -308,7 +1118,7 @@ pub async fn get(
     Path((portfolio_id, invoice_id)): Path<(String, String)>,
 ) -> AppResult<Json<Invoice>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Read)?;
 
     let invoice = conn
         .query_row(
@@ -333,8 +1143,8 @@ pub async fn update(
     Path((portfolio_id, invoice_id)): Path<(String, String)>,
     Json(body): Json<UpdateInvoiceRequest>,
 ) -> AppResult<Json<Invoice>> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    let mut conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
 
     let existing = conn
         .query_row(
@@ -361,17 +1171,45 @@ pub async fn update(
     }
 
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-    let status = body.status.unwrap_or(existing.status);
-    let customer_name = body.customer_name.or(existing.customer_name);
-    let customer_email = body.customer_email.or(existing.customer_email);
+    let status = body.status.unwrap_or_else(|| existing.status.clone());
+    let (customer_id, linked) = match body.customer_id.as_deref() {
+        Some("") => (None, None),
+        Some(customer_id) => {
+            let customer = customers::load(&conn, &portfolio_id, customer_id)?;
+            (Some(customer.id), Some((customer.name, customer.email)))
+        }
+        None => (existing.customer_id, None),
+    };
+    let (linked_name, linked_email) = match linked {
+        Some((name, email)) => (Some(name), email),
+        None => (None, None),
+    };
+    let customer_name = body.customer_name.or(linked_name).or(existing.customer_name);
+    let customer_email = body.customer_email.or(linked_email).or(existing.customer_email);
     let description = body.description.or(existing.description);
     let due_at = body.due_at.or(existing.due_at);
     let expires_at = body.expires_at.or(existing.expires_at);
+    let address_proof = body.address_proof.unwrap_or(existing.address_proof);
+    let min_confirmations = body.min_confirmations.unwrap_or(existing.min_confirmations);
+    validate_min_confirmations(min_confirmations)?;
+    let share_enabled = body.share_enabled.unwrap_or(existing.share_enabled);
 
-    conn.execute(
-        "UPDATE invoices SET status = ?1, customer_name = ?2, customer_email = ?3, description = ?4, due_at = ?5, expires_at = ?6, updated_at = ?7 WHERE id = ?8",
-        rusqlite::params![status, customer_name, customer_email, description, due_at, expires_at, now, invoice_id],
+    let db_tx = conn.transaction()?;
+    db_tx.execute(
+        "UPDATE invoices SET status = ?1, customer_name = ?2, customer_email = ?3, description = ?4, due_at = ?5, expires_at = ?6, address_proof = ?7, min_confirmations = ?8, updated_at = ?9, customer_id = ?10, share_enabled = ?11 WHERE id = ?12",
+        rusqlite::params![status, customer_name, customer_email, description, due_at, expires_at, address_proof, min_confirmations, now, customer_id, share_enabled, invoice_id],
     )?;
+    if status != existing.status {
+        invoice_history::record(
+            &db_tx,
+            &invoice_id,
+            InvoiceEvent::StatusChanged,
+            Some((&existing.status, &status)),
+            Actor::Merchant(&user.id),
+            None,
+        )?;
+    }
+    db_tx.commit()?;
 
     Ok(Json(Invoice {
         id: invoice_id,
@@ -379,9 +1217,13 @@ pub async fn update(
         status,
         customer_name,
         customer_email,
+        customer_id,
         description,
         due_at,
         expires_at,
+        address_proof,
+        min_confirmations,
+        share_enabled,
         updated_at: now,
         ..existing
     }))
@@ -394,7 +1236,7 @@ pub async fn delete(
     Path((portfolio_id, invoice_id)): Path<(String, String)>,
 ) -> AppResult<StatusCode> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
 
     let affected = conn.execute(
         "DELETE FROM invoices WHERE id = ?1 AND portfolio_id = ?2",
@@ -415,7 +1257,7 @@ pub async fn check_payment(
     Path((portfolio_id, invoice_id)): Path<(String, String)>,
 ) -> AppResult<Json<Invoice>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
 
     let invoice = conn
         .query_row(
@@ -434,16 +1276,27 @@ pub async fn check_payment(
         return Ok(Json(invoice));
     }
 
+    // A settled BOLT11 makes the on-chain check unnecessary
+    let mut updated = match (&state.config.lightning, invoice.lightning_payment_hash.as_deref()) {
+        (Some(config), Some(payment_hash)) => {
+            invoice_checker::check_lightning_payment(&state.db, config, payment_hash).await?
+        }
+        _ => false,
+    };
+
     // Check for payment on-chain
-    let updated = invoice_checker::check_invoice_payment(
-        &state.config.esplora_url,
-        &state.db,
-        &invoice.id,
-        &invoice.btc_address,
-        invoice.amount_sat,
-        invoice.reusable,
-    )
-    .await?;
+    if !updated {
+        updated = invoice_checker::check_invoice_payment(
+            &state.esplora,
+            &state.cache,
+            &state.db,
+            &invoice.id,
+            &invoice.btc_address,
+            invoice.amount_sat,
+            invoice.reusable,
+        )
+        .await?;
+    }
 
     if updated {
         // Re-fetch the updated invoice
@@ -459,16 +1312,411 @@ pub async fn check_payment(
     }
 }
 
+/// POST /api/v1/portfolios/{portfolio_id}/invoices/{id}/send
+///
+/// Emails the invoice with its payment link to the customer; a draft becomes `sent`.
+/// A one-time invoice without an expiry gets the portfolio's default, counted from
+/// now. Can be repeated to resend. Counts against the monthly email quota.
+pub async fn send(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+    body: Option<Json<SendInvoiceRequest>>,
+) -> AppResult<Json<Invoice>> {
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+    if state.config.resend_api_key.is_none() {
+        return Err(AppError::BadRequest("Email is not configured on this server".into()));
+    }
+    if let Some(days) = body.reminder_days_before {
+        if !(0..=MAX_REMINDER_DAYS).contains(&days) {
+            return Err(AppError::BadRequest(format!(
+                "reminder_days_before must be between 0 and {MAX_REMINDER_DAYS}"
+            )));
+        }
+    }
+    if body.message.as_ref().is_some_and(|m| m.chars().count() > invoice_emails::MESSAGE_MAX_CHARS) {
+        return Err(AppError::BadRequest(format!(
+            "message must be at most {} characters",
+            invoice_emails::MESSAGE_MAX_CHARS
+        )));
+    }
+    let to = body.to.as_deref().map(str::trim).filter(|t| !t.is_empty());
+    if to.is_some_and(|t| !t.contains('@') || t.contains(char::is_whitespace)) {
+        return Err(AppError::BadRequest("Invalid email address".into()));
+    }
+
+    let email = {
+        let conn = state.db.get()?;
+        policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
+        let (status, share_enabled): (String, bool) = conn
+            .query_row(
+                "SELECT status, share_enabled FROM invoices WHERE id = ?1 AND portfolio_id = ?2",
+                rusqlite::params![invoice_id, portfolio_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+                e => AppError::Database(e),
+            })?;
+        if status != "draft" && status != "sent" {
+            return Err(AppError::Conflict(format!("A {status} invoice can't be sent")));
+        }
+        if !share_enabled {
+            return Err(AppError::Conflict("The invoice's payment link is disabled".into()));
+        }
+        let mut email = invoice_emails::load(&conn, &invoice_id)?;
+        if let Some(to) = to {
+            email.customer_email = Some(to.to_string());
+        }
+        if email.customer_email.as_deref().is_none_or(str::is_empty) {
+            return Err(AppError::BadRequest("The invoice has no customer email; pass `to`".into()));
+        }
+        if !quotas::consume_email(&conn, &state.config, &user.id)? {
+            return Err(AppError::Forbidden("Monthly email quota reached".into()));
+        }
+        email
+    };
+
+    invoice_emails::send(&state.config, &email, invoice_emails::Kind::Invoice, body.message.as_deref()).await?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut conn = state.db.get()?;
+    let db_tx = conn.transaction()?;
+    let status: String = db_tx.query_row(
+        "SELECT status FROM invoices WHERE id = ?1",
+        rusqlite::params![invoice_id],
+        |row| row.get(0),
+    )?;
+    let expires_at = default_expiry(&db_tx, &portfolio_id)?;
+    // A new reminder setting gets a reminder of its own
+    let (reminder_days_before, reset_reminder) = match body.reminder_days_before {
+        Some(0) => (None, true),
+        Some(days) => (Some(days), true),
+        None => (None, false),
+    };
+    db_tx.execute(
+        "UPDATE invoices SET
+             status = CASE WHEN status = 'draft' THEN 'sent' ELSE status END,
+             customer_email = ?1,
+             sent_at = COALESCE(sent_at, ?2), last_sent_at = ?2, send_count = send_count + 1,
+             reminder_days_before = CASE WHEN ?3 THEN ?4 ELSE reminder_days_before END,
+             reminder_sent_at = CASE WHEN ?3 THEN NULL ELSE reminder_sent_at END,
+             expires_at = CASE WHEN reusable = 0 THEN COALESCE(expires_at, ?6) ELSE expires_at END,
+             updated_at = ?2
+         WHERE id = ?5",
+        rusqlite::params![email.customer_email, now, reset_reminder, reminder_days_before, invoice_id, expires_at],
+    )?;
+    invoice_history::record(
+        &db_tx,
+        &invoice_id,
+        InvoiceEvent::Sent,
+        (status == "draft").then_some(("draft", "sent")),
+        Actor::Merchant(&user.id),
+        Some(&serde_json::json!({ "to": email.customer_email })),
+    )?;
+    db_tx.commit()?;
+
+    let invoice = conn.query_row(
+        &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1"),
+        rusqlite::params![invoice_id],
+        row_to_invoice,
+    )?;
+    Ok(Json(invoice))
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/invoices/{id}/lightning
+///
+/// Issue a BOLT11 for an open invoice that has none, or whose last one expired unpaid.
+pub async fn renew_lightning(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+) -> AppResult<Json<Invoice>> {
+    let config = state
+        .config
+        .lightning
+        .as_ref()
+        .ok_or_else(|| AppError::BadRequest("Lightning is not configured on this server".into()))?;
+
+    let invoice = {
+        let conn = state.db.get()?;
+        policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
+        conn.query_row(
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1 AND portfolio_id = ?2"),
+            rusqlite::params![invoice_id, portfolio_id],
+            row_to_invoice,
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+            e => AppError::Database(e),
+        })?
+    };
+    if invoice.status != "draft" && invoice.status != "sent" {
+        return Err(AppError::Conflict(format!("A {} invoice can't take a Lightning payment", invoice.status)));
+    }
+    if invoice.reusable || invoice.amount_sat <= 0 {
+        return Err(AppError::BadRequest("Lightning needs a one-time invoice with an amount".into()));
+    }
+    // Replacing a BOLT11 that can still be paid would leave its payment unrecorded
+    if open_lightning_invoice(&invoice).is_some() {
+        return Err(AppError::Conflict("The invoice's Lightning invoice hasn't expired yet".into()));
+    }
+
+    // A lapsed quote is renewed first, and that reissues any BOLT11 the invoice had
+    let invoice = if requote(&state, &invoice).await? {
+        let conn = state.db.get()?;
+        let invoice = conn.query_row(
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1"),
+            rusqlite::params![invoice_id],
+            row_to_invoice,
+        )?;
+        if open_lightning_invoice(&invoice).is_some() {
+            return Ok(Json(invoice));
+        }
+        invoice
+    } else {
+        invoice
+    };
+
+    let issued = issue_lightning(
+        config,
+        invoice.amount_sat,
+        invoice.invoice_number.as_deref(),
+        invoice.description.as_deref(),
+        lightning_deadline(invoice.expires_at.as_deref(), invoice.rate_expires_at.as_deref()),
+    )
+    .await?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let conn = state.db.get()?;
+    conn.execute(
+        "UPDATE invoices SET lightning_invoice = ?1, lightning_payment_hash = ?2, lightning_expires_at = ?3, updated_at = ?4
+         WHERE id = ?5",
+        rusqlite::params![issued.bolt11, issued.payment_hash, issued.expires_at, now, invoice_id],
+    )?;
+
+    Ok(Json(Invoice {
+        lightning_invoice: Some(issued.bolt11),
+        lightning_payment_hash: Some(issued.payment_hash),
+        lightning_expires_at: Some(issued.expires_at),
+        updated_at: now,
+        ..invoice
+    }))
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/invoices/{id}/regenerate-token
+///
+/// Replace the share token, so links already given out stop working, and enable the
+/// new one. The invoice's address and amount stay the same.
+pub async fn regenerate_token(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+) -> AppResult<Json<Invoice>> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Write)?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let affected = conn.execute(
+        "UPDATE invoices SET share_token = ?1, share_enabled = 1, updated_at = ?2 WHERE id = ?3 AND portfolio_id = ?4",
+        rusqlite::params![Uuid::new_v4().to_string(), now, invoice_id, portfolio_id],
+    )?;
+    if affected == 0 {
+        return Err(AppError::NotFound("Invoice not found".into()));
+    }
+
+    let invoice = conn.query_row(
+        &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1"),
+        rusqlite::params![invoice_id],
+        row_to_invoice,
+    )?;
+    Ok(Json(invoice))
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/invoices/{id}/events
+///
+/// The invoice's history, oldest first: its status changes with who made them, and the
+/// first view, resends and partial payments in between.
+pub async fn events(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+) -> AppResult<Json<Vec<InvoiceHistoryEntry>>> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Read)?;
+
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM invoices WHERE id = ?1 AND portfolio_id = ?2)",
+        rusqlite::params![invoice_id, portfolio_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(AppError::NotFound("Invoice not found".into()));
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {HISTORY_COLS} FROM invoice_events WHERE invoice_id = ?1 ORDER BY id"
+    ))?;
+    let entries = stmt
+        .query_map(rusqlite::params![invoice_id], invoice_history::row_to_entry)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(entries))
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/invoices/{id}/pdf
+pub async fn pdf(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, invoice_id)): Path<(String, String)>,
+) -> AppResult<impl IntoResponse> {
+    let conn = state.db.get()?;
+    policy::invoice(&state.cache, &conn, &user.id, &portfolio_id, &invoice_id, Access::Read)?;
+
+    let invoice = conn
+        .query_row(
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1 AND portfolio_id = ?2"),
+            rusqlite::params![invoice_id, portfolio_id],
+            row_to_invoice,
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+            e => AppError::Database(e),
+        })?;
+    // The merchant is the portfolio's owner, whoever downloads it
+    let (merchant_name, merchant_email): (String, String) = conn.query_row(
+        "SELECT u.name, u.email FROM portfolios p JOIN users u ON u.id = p.user_id WHERE p.id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let customer = match invoice.customer_id.as_deref() {
+        Some(customer_id) => Some(customers::load(&conn, &portfolio_id, customer_id)?),
+        None => None,
+    };
+    let branding = branding::load(&conn, &portfolio_id)?;
+    drop(conn);
+
+    let pay_url = format!("{}/pay/{}", state.config.app_url, invoice.share_token);
+    let document = invoice_pdf(&invoice, customer.as_ref(), &branding, &merchant_name, &merchant_email, &pay_url)?;
+    let name: String = invoice
+        .invoice_number
+        .as_deref()
+        .filter(|n| !n.is_empty())
+        .unwrap_or(&invoice.id)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"invoice-{name}.pdf\"")),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        document,
+    ))
+}
+
+/// Loads of the payment page by the same kind of browser within this long count as one view.
+const VIEW_DEDUP_SECS: i64 = 30 * 60;
+
+/// Browser and OS family of a User-Agent, e.g. "Firefox on Android", coarse enough not to
+/// identify anyone. `None` for crawlers and scripts, whose loads aren't views.
+fn coarse_user_agent(user_agent: Option<&str>) -> Option<String> {
+    let Some(ua) = user_agent else {
+        return Some("Unknown".into());
+    };
+    let lower = ua.to_ascii_lowercase();
+    if ["bot", "crawl", "spider", "preview", "curl/", "wget/", "python", "headless"]
+        .iter()
+        .any(|marker| lower.contains(marker))
+    {
+        return None;
+    }
+    let browser = if ua.contains("Edg/") || ua.contains("EdgA/") || ua.contains("EdgiOS/") {
+        "Edge"
+    } else if ua.contains("OPR/") {
+        "Opera"
+    } else if ua.contains("Firefox/") || ua.contains("FxiOS/") {
+        "Firefox"
+    } else if ua.contains("Chrome/") || ua.contains("CriOS/") {
+        "Chrome"
+    } else if ua.contains("Safari/") {
+        "Safari"
+    } else {
+        "Other browser"
+    };
+    let os = if ua.contains("Android") {
+        "Android"
+    } else if ua.contains("iPhone") || ua.contains("iPad") {
+        "iOS"
+    } else if ua.contains("Windows") {
+        "Windows"
+    } else if ua.contains("Mac OS X") {
+        "macOS"
+    } else if ua.contains("Linux") {
+        "Linux"
+    } else {
+        "other OS"
+    };
+    Some(format!("{browser} on {os}"))
+}
+
+/// Log a payment page view unless the same kind of browser viewed it recently. The
+/// first view also goes in the invoice's history.
+fn record_view(conn: &mut rusqlite::Connection, invoice_id: &str, user_agent: &str) -> AppResult<()> {
+    let now = chrono::Utc::now();
+    let since = (now - chrono::Duration::seconds(VIEW_DEDUP_SECS))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    let now = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    let db_tx = conn.transaction()?;
+    let recent: bool = db_tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM invoice_views WHERE invoice_id = ?1 AND user_agent = ?2 AND viewed_at > ?3)",
+        rusqlite::params![invoice_id, user_agent, since],
+        |row| row.get(0),
+    )?;
+    if recent {
+        return Ok(());
+    }
+    db_tx.execute(
+        "INSERT INTO invoice_views (invoice_id, user_agent, viewed_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![invoice_id, user_agent, now],
+    )?;
+    let view_count: i64 = db_tx.query_row(
+        "UPDATE invoices SET view_count = view_count + 1, last_viewed_at = ?1 WHERE id = ?2 RETURNING view_count",
+        rusqlite::params![now, invoice_id],
+        |row| row.get(0),
+    )?;
+    if view_count == 1 {
+        invoice_history::record(
+            &db_tx,
+            invoice_id,
+            InvoiceEvent::Viewed,
+            None,
+            Actor::Customer,
+            Some(&serde_json::json!({ "user_agent": user_agent })),
+        )?;
+    }
+    db_tx.commit()?;
+    Ok(())
+}
+
 /// GET /api/v1/invoices/pay/{share_token} — Public endpoint (no auth)
+///
+/// Loads count as views (see [`record_view`]), except by crawlers. A fiat-denominated
+/// invoice whose quote has lapsed is requoted at the current price.
 pub async fn public_get(
     State(state): State<AppState>,
     Path(share_token): Path<String>,
+    headers: HeaderMap,
 ) -> AppResult<Json<PublicInvoice>> {
-    let conn = state.db.get()?;
+    let mut conn = state.db.get()?;
 
-    let invoice = conn
+    let mut invoice = conn
         .query_row(
-            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1"),
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1 AND share_enabled = 1"),
             rusqlite::params![share_token],
             row_to_invoice,
         )
@@ -479,10 +1727,16 @@ pub async fn public_get(
             e => AppError::Database(e),
         })?;
 
-    // Also trigger a payment check if status is 'sent'
-    if invoice.status == "sent" {
+    let user_agent = headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok());
+    if let Some(user_agent) = coarse_user_agent(user_agent) {
+        record_view(&mut conn, &invoice.id, &user_agent)?;
+    }
+
+    // Also trigger a payment check if a payment is due or waiting for confirmations
+    if invoice.status == "sent" || invoice.status == "pending_confirmation" {
         let _ = invoice_checker::check_invoice_payment(
-            &state.config.esplora_url,
+            &state.esplora,
+            &state.cache,
             &state.db,
             &invoice.id,
             &invoice.btc_address,
@@ -492,9 +1746,9 @@ pub async fn public_get(
         .await;
 
         // Re-fetch to get updated status
-        let invoice = conn
+        invoice = conn
             .query_row(
-                &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1"),
+                &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1 AND share_enabled = 1"),
                 rusqlite::params![share_token],
                 row_to_invoice,
             )
@@ -504,9 +1758,193 @@ pub async fn public_get(
                 }
                 e => AppError::Database(e),
             })?;
+    }
+
+    // Checked for payment at the old quote first, so one made just before it lapsed counts.
+    // If the price can't be had, the page shows the lapsed quote rather than failing.
+    match requote(&state, &invoice).await {
+        Ok(true) => {
+            invoice = conn.query_row(
+                &format!("SELECT {INVOICE_COLS} FROM invoices WHERE id = ?1"),
+                rusqlite::params![invoice.id],
+                row_to_invoice,
+            )?;
+        }
+        Ok(false) => {}
+        Err(e) => tracing::warn!("Couldn't requote invoice {}: {e}", invoice.id),
+    }
+
+    let branding = branding::load(&conn, &invoice.portfolio_id)?;
+    Ok(Json(invoice_to_public(&invoice, branding)))
+}
+
+/// Minimal payment state for integrations polling an invoice.
+#[derive(Debug, Serialize)]
+pub struct PublicInvoiceStatus {
+    pub status: String,
+    pub paid_amount_sat: Option<i64>,
+    pub confirmations: i64,
+}
+
+/// Confirmations after which a paid invoice's status is treated as settled for caching.
+const SETTLED_CONFIRMATIONS: i64 = 6;
+
+/// GET /api/v1/public/invoices/{share_token}/status — Public endpoint (no auth)
+///
+/// Stable, cheap alternative to the payment page payload for e-commerce plugins. It
+/// reports what the background checker has seen rather than querying for payments,
+/// and may be cached briefly; settled and closed invoices longer.
+pub async fn public_status(
+    State(state): State<AppState>,
+    Path(share_token): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let (status, paid_txid, paid_amount_sat, paid_via) = {
+        let conn = state.db.get()?;
+        conn.query_row(
+            "SELECT status, paid_txid, paid_amount_sat, paid_via FROM invoices WHERE share_token = ?1 AND share_enabled = 1",
+            rusqlite::params![share_token],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            },
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+            e => AppError::Database(e),
+        })?
+    };
+
+    let confirmations = match paid_txid {
+        Some(ref txid) => invoice_checker::payment_confirmations(&state.esplora, &state.cache, txid).await?,
+        None => 0,
+    };
+
+    let settled = match status.as_str() {
+        // Lightning payments are final once settled
+        "paid" if paid_via.as_deref() == Some("lightning") => true,
+        "paid" => confirmations >= SETTLED_CONFIRMATIONS,
+        "expired" | "cancelled" => true,
+        _ => false,
+    };
+    let cache_control = if settled { "public, max-age=300" } else { "public, max-age=10" };
+
+    Ok((
+        [(header::CACHE_CONTROL, cache_control)],
+        Json(PublicInvoiceStatus { status, paid_amount_sat, confirmations }),
+    ))
+}
+
+/// GET /api/v1/invoices/pay/{share_token}/qr?format=svg|png — Public endpoint (no auth)
+///
+/// The invoice's `payment_uri` as a QR code.
+pub async fn public_qr(
+    State(state): State<AppState>,
+    Path(share_token): Path<String>,
+    Query(query): Query<QrQuery>,
+) -> AppResult<impl IntoResponse> {
+    let format = query.format.as_deref().unwrap_or("svg");
+    if !matches!(format, "svg" | "png") {
+        return Err(AppError::BadRequest("format must be 'svg' or 'png'".into()));
+    }
+    let invoice = {
+        let conn = state.db.get()?;
+        conn.query_row(
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1 AND share_enabled = 1"),
+            rusqlite::params![share_token],
+            row_to_invoice,
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+            e => AppError::Database(e),
+        })?
+    };
+
+    let qr = QrCode::encode(payment_uri(&invoice).as_bytes())?;
+    let (content_type, body) = if format == "png" {
+        ("image/png", qr.to_png(8)?)
+    } else {
+        ("image/svg+xml", qr.to_svg().into_bytes())
+    };
+    // A requote changes the amount in the URI
+    let cache_control = if invoice.fiat_denominated { "public, max-age=10" } else { "public, max-age=300" };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        body,
+    ))
+}
+
+/// GET /api/v1/invoices/pay/{share_token}/proof — Public endpoint (no auth)
+///
+/// Shows where the invoice address sits in the merchant's wallet. The wallet's public
+/// descriptor is only included when the merchant enabled `address_proof` on the invoice.
+pub async fn public_proof(
+    State(state): State<AppState>,
+    Path(share_token): Path<String>,
+) -> AppResult<Json<AddressProof>> {
+    let conn = state.db.get()?;
+
+    let invoice = conn
+        .query_row(
+            &format!("SELECT {INVOICE_COLS} FROM invoices WHERE share_token = ?1 AND share_enabled = 1"),
+            rusqlite::params![share_token],
+            row_to_invoice,
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                AppError::NotFound("Invoice not found".into())
+            }
+            e => AppError::Database(e),
+        })?;
+
+    let (Some(keychain), Some(index)) = (invoice.address_keychain.clone(), invoice.address_index) else {
+        return Err(AppError::NotFound(
+            "This invoice's address was not derived from a linked wallet".into(),
+        ));
+    };
+    let derivation_paths = invoice
+        .address_derivation_path
+        .as_deref()
+        .map(|p| p.split(',').map(String::from).collect())
+        .unwrap_or_default();
 
-        return Ok(Json(invoice_to_public(&invoice)));
+    let mut proof = AddressProof {
+        address: invoice.btc_address.clone(),
+        keychain,
+        index,
+        derivation_paths,
+        network: None,
+        descriptor: None,
+    };
+
+    // The wallet may since have been deleted, in which case only the derivation is left
+    let wallet_id = match invoice.wallet_id.as_deref() {
+        Some(id) if invoice.address_proof => id,
+        _ => return Ok(Json(proof)),
+    };
+    let wallet = load_linked_wallet(&conn, wallet_id, &invoice.portfolio_id)?;
+    let bdk_wallet = open_linked_wallet(&state, wallet_id, &wallet)?;
+
+    let keychain = if proof.keychain == "internal" {
+        KeychainKind::Internal
+    } else {
+        KeychainKind::External
+    };
+    if bdk_wallet.peek_address(keychain, index).address.to_string() != invoice.btc_address {
+        tracing::error!("Invoice {} address no longer matches its wallet derivation", invoice.id);
+        return Err(AppError::Conflict(
+            "Invoice address does not match the linked wallet".into(),
+        ));
     }
 
-    Ok(Json(invoice_to_public(&invoice)))
+    proof.network = Some(wallet.network);
+    proof.descriptor = Some(bdk_wallet.public_descriptor(keychain).to_string());
+    Ok(Json(proof))
 }
@@ -0,0 +1,157 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::{account_import, export};
+use crate::services::jobs::{self, Job, JobKind, JobParams, JOB_COLS};
+
+/// GET /api/v1/jobs
+///
+/// The user's 50 most recent background jobs.
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<Job>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {JOB_COLS} FROM jobs WHERE user_id = ?1 ORDER BY created_at DESC LIMIT 50"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], jobs::row_to_job)?;
+    let data = rows
+        .map(|r| r.map(|job| job.with_download_url(&state.config)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(data))
+}
+
+/// GET /api/v1/jobs/{id}
+///
+/// Poll a job; once a file is ready this carries the same signed link as the email.
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(job_id): Path<String>,
+) -> AppResult<Json<Job>> {
+    let conn = state.db.get()?;
+    Ok(Json(jobs::load_job(&conn, &user.id, &job_id)?.with_download_url(&state.config)))
+}
+
+/// POST /api/v1/auth/account/export
+///
+/// Export everything in the account as a ZIP archive of JSON and CSV files. Returns 202
+/// with the job at once; poll `export_status` or wait for the emailed download link,
+/// which expires after `DOWNLOAD_LINK_TTL_HOURS`.
+pub async fn export_account(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+) -> AppResult<(StatusCode, Json<Job>)> {
+    let job = {
+        let conn = state.db.get()?;
+        let running: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM jobs WHERE user_id = ?1 AND kind = ?2 AND status = 'running')",
+            rusqlite::params![user.id, JobKind::AccountExport.name()],
+            |row| row.get(0),
+        )?;
+        if running {
+            return Err(AppError::Conflict("An account export is already running".into()));
+        }
+        let job = jobs::start_job(&conn, &user.id, JobKind::AccountExport)?;
+        audit::record(&conn, &user.id, AuditEvent::DataExported, Some(&job.id), serde_json::json!({ "kind": "account" }), &client)?;
+        job
+    };
+
+    jobs::spawn(state.db.clone(), state.config.clone(), job.clone(), JobKind::AccountExport, JobParams::default());
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// GET /api/v1/export/{job_id}
+///
+/// An account export's status, with its download link once the archive is ready.
+pub async fn export_status(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(job_id): Path<String>,
+) -> AppResult<Json<Job>> {
+    let conn = state.db.get()?;
+    let job = jobs::load_job(&conn, &user.id, &job_id)?;
+    if job.kind != JobKind::AccountExport.name() {
+        return Err(AppError::NotFound("Export not found".into()));
+    }
+    Ok(Json(job.with_download_url(&state.config)))
+}
+
+/// POST /api/v1/import
+///
+/// Restore an account export into this account, which must hold no data yet. The body
+/// is the ZIP archive (or older JSON file) as downloaded, at most `ACCOUNT_IMPORT_MAX_MB`.
+/// Returns how many rows of each table were restored.
+pub async fn import_account(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    body: Bytes,
+) -> AppResult<(StatusCode, Json<serde_json::Value>)> {
+    let (pool, config) = (state.db.clone(), state.config.clone());
+    let user_id = user.id.clone();
+    let counts = tokio::task::spawn_blocking(move || {
+        let export = account_import::parse_export(&body)?;
+        let mut conn = pool.get()?;
+        account_import::restore(&mut conn, &config, &user_id, &export)
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Import task failed: {e}")))??;
+
+    tracing::info!("Restored an account export into user {}", user.id);
+    Ok((StatusCode::CREATED, Json(serde_json::Value::Object(counts))))
+}
+
+/// GET /api/v1/downloads/{token}
+///
+/// Public: the signed token is the credential, so emailed links work without a session.
+pub async fn download(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let job_id = jobs::verify_download(&state.config, &token)
+        .ok_or_else(|| AppError::NotFound("Download link is invalid or has expired".into()))?;
+    let path = {
+        let conn = state.db.get()?;
+        jobs::download_file(&conn, &job_id)?
+    };
+    // Exports from before archives were introduced are plain JSON
+    let (content_type, extension) = if path.ends_with(".zip") {
+        ("application/zip", "zip")
+    } else {
+        ("application/json", "json")
+    };
+    let mut file = std::fs::File::open(&path).map_err(|e| {
+        tracing::warn!("Job {job_id}: download file {path} unreadable: {e}");
+        AppError::NotFound("Download has expired".into())
+    })?;
+
+    let body = export::stream_body(move |out| {
+        std::io::copy(&mut file, out).map_err(export::write_failed)?;
+        Ok(())
+    });
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"opacore_export_{job_id}.{extension}\""),
+            ),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        body,
+    ))
+}
@@ -6,9 +6,11 @@ use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access};
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::transaction_audit::{self, AuditAction};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Label {
@@ -162,20 +164,14 @@ pub async fn assign_to_transaction(
     Path(transaction_id): Path<String>,
     Json(body): Json<AssignLabelsRequest>,
 ) -> AppResult<StatusCode> {
-    let conn = state.db.get()?;
+    let mut conn = state.db.get()?;
 
-    // Verify transaction belongs to user (via portfolio)
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM transactions t JOIN portfolios p ON p.id = t.portfolio_id WHERE t.id = ?1 AND p.user_id = ?2)",
-        rusqlite::params![transaction_id, user.id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Transaction not found".into()));
-    }
+    let portfolio_id = policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+    let db_tx = conn.transaction()?;
+    let before = label_ids(&db_tx, &transaction_id)?;
 
     // Clear existing labels for this transaction
-    conn.execute(
+    db_tx.execute(
         "DELETE FROM transaction_labels WHERE transaction_id = ?1",
         rusqlite::params![transaction_id],
     )?;
@@ -183,7 +179,7 @@ pub async fn assign_to_transaction(
     // Insert new labels
     for label_id in &body.label_ids {
         // Verify label belongs to user
-        let label_exists: bool = conn.query_row(
+        let label_exists: bool = db_tx.query_row(
             "SELECT EXISTS(SELECT 1 FROM labels WHERE id = ?1 AND user_id = ?2)",
             rusqlite::params![label_id, user.id],
             |row| row.get(0),
@@ -192,15 +188,36 @@ pub async fn assign_to_transaction(
             return Err(AppError::NotFound(format!("Label {label_id} not found")));
         }
 
-        conn.execute(
+        db_tx.execute(
             "INSERT INTO transaction_labels (transaction_id, label_id) VALUES (?1, ?2)",
             rusqlite::params![transaction_id, label_id],
         )?;
     }
 
+    let after = label_ids(&db_tx, &transaction_id)?;
+    if before != after {
+        transaction_audit::record(
+            &db_tx,
+            &portfolio_id,
+            &transaction_id,
+            Some(&user.id),
+            AuditAction::Labels,
+            &serde_json::json!({ "from": before, "to": after }),
+        )?;
+    }
+    db_tx.commit()?;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
+fn label_ids(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT label_id FROM transaction_labels WHERE transaction_id = ?1 ORDER BY label_id")?;
+    let ids = stmt
+        .query_map(rusqlite::params![transaction_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
 pub async fn get_transaction_labels(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
@@ -208,15 +225,7 @@ pub async fn get_transaction_labels(
 ) -> AppResult<Json<Vec<Label>>> {
     let conn = state.db.get()?;
 
-    // Verify transaction belongs to user
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM transactions t JOIN portfolios p ON p.id = t.portfolio_id WHERE t.id = ?1 AND p.user_id = ?2)",
-        rusqlite::params![transaction_id, user.id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Transaction not found".into()));
-    }
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Read)?;
 
     let mut stmt = conn.prepare(
         "SELECT l.id, l.user_id, l.name, l.color, l.created_at
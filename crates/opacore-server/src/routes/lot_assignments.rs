@@ -0,0 +1,71 @@
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
+use crate::error::AppResult;
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::costbasis::{self, LotAssignment, OpenLot};
+use crate::services::{lot_assignments, tax_locks};
+
+#[derive(Debug, Deserialize)]
+pub struct AssignLotsRequest {
+    pub assignments: Vec<LotAssignment>,
+}
+
+/// GET /api/v1/transactions/{transaction_id}/open-lots
+///
+/// The lots a disposal can be assigned: those still open just before it under `specid`,
+/// oldest first.
+pub async fn open_lots(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(transaction_id): Path<String>,
+) -> AppResult<Json<Vec<OpenLot>>> {
+    let portfolio_id = {
+        let conn = state.db.get()?;
+        policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Read)?
+    };
+    Ok(Json(costbasis::open_lots_before(&state.db, &portfolio_id, &transaction_id)?))
+}
+
+/// GET /api/v1/transactions/{transaction_id}/lot-assignments
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(transaction_id): Path<String>,
+) -> AppResult<Json<Vec<LotAssignment>>> {
+    let conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Read)?;
+    Ok(Json(lot_assignments::for_disposal(&conn, &transaction_id)?))
+}
+
+/// PUT /api/v1/transactions/{transaction_id}/lot-assignments
+///
+/// Pin the lots a disposal consumes when cost basis is calculated with `method=specid`.
+/// Replaces any earlier assignment; an empty list clears it.
+pub async fn replace(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(transaction_id): Path<String>,
+    Json(body): Json<AssignLotsRequest>,
+) -> AppResult<Json<Vec<LotAssignment>>> {
+    let portfolio_id = {
+        let conn = state.db.get()?;
+        let portfolio_id = policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+        tax_locks::check_transaction(&conn, &transaction_id, &user, &client)?;
+        portfolio_id
+    };
+    let assignments = lot_assignments::replace(&state.db, &portfolio_id, &transaction_id, &body.assignments)?;
+    tracing::info!(
+        "User {} assigned {} lots to transaction {transaction_id}",
+        user.id,
+        assignments.len()
+    );
+    Ok(Json(assignments))
+}
@@ -1,33 +1,65 @@
+mod admin;
 mod alerts;
 mod analysis;
+mod api_keys;
+mod attachments;
 mod auth;
 mod billing;
+mod customers;
+mod data_quality;
+mod exchange_connections;
 mod fees;
+mod import_templates;
+mod imports;
+mod invoice_notes;
 mod invoices;
+mod jobs;
 mod labels;
+mod lot_assignments;
+mod organizations;
+mod passkeys;
+mod portfolio_templates;
 mod portfolios;
 mod prices;
+mod recurring;
+mod share_links;
 mod sync;
 mod tax;
 mod transactions;
+mod transfers;
+mod utxos;
 mod wallets;
+mod watch;
+mod webhook_endpoints;
 
 use axum::{
-    middleware,
+    extract::{DefaultBodyLimit, Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Router,
 };
 use std::sync::Arc;
+use std::time::Duration;
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 
-use crate::auth::middleware::require_auth;
+use crate::auth::middleware::{require_admin, require_auth};
 use crate::config::Config;
 use crate::db::DbPool;
+use crate::error::AppError;
+use crate::services::cache::AppCache;
+use crate::services::esplora::EsploraBackends;
+use crate::services::sync_lock::WalletSyncLocks;
+use crate::services::sync_progress::SyncProgressHub;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DbPool,
     pub config: Config,
+    pub esplora: EsploraBackends,
+    pub cache: AppCache,
+    pub sync_progress: SyncProgressHub,
+    pub sync_locks: WalletSyncLocks,
 }
 
 async fn health() -> &'static str {
@@ -57,6 +89,27 @@ pub fn create_router(state: AppState) -> Router {
             .finish()
             .expect("email governor config"),
     );
+    let message_governor = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_second(10)  // 1 req per 10s = 6/min
+            .burst_size(3)
+            .finish()
+            .expect("message governor config"),
+    );
+    let passkey_challenge_governor = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_second(2)   // 30/min, each stores a short-lived challenge
+            .burst_size(10)
+            .finish()
+            .expect("passkey challenge governor config"),
+    );
+    let status_governor = Arc::new(
+        GovernorConfigBuilder::default()
+            .per_second(1)   // 60/min sustained, for shops polling several invoices
+            .burst_size(30)
+            .finish()
+            .expect("status governor config"),
+    );
 
     // Health checks
     let health_routes = Router::new()
@@ -67,7 +120,15 @@ pub fn create_router(state: AppState) -> Router {
     let auth_routes = Router::new()
         .route(
             "/api/v1/auth/login",
-            post(auth::login).layer(GovernorLayer::new(login_governor)),
+            post(auth::login).layer(GovernorLayer::new(login_governor.clone())),
+        )
+        .route(
+            "/api/v1/auth/passkeys/login/options",
+            post(passkeys::login_options).layer(GovernorLayer::new(passkey_challenge_governor.clone())),
+        )
+        .route(
+            "/api/v1/auth/passkeys/login",
+            post(passkeys::login).layer(GovernorLayer::new(login_governor)),
         )
         .route(
             "/api/v1/auth/register",
@@ -81,20 +142,95 @@ pub fn create_router(state: AppState) -> Router {
         )
         .route(
             "/api/v1/auth/forgot-password",
-            post(auth::forgot_password).layer(GovernorLayer::new(email_governor)),
+            post(auth::forgot_password).layer(GovernorLayer::new(email_governor.clone())),
         )
-        .route("/api/v1/auth/reset-password", post(auth::reset_password));
+        .route("/api/v1/auth/reset-password", post(auth::reset_password))
+        .route("/api/v1/auth/email/confirm", post(auth::confirm_email_change))
+        .route("/api/v1/auth/sessions/revoke", post(auth::revoke_session_from_link));
 
     // Public routes (no auth required)
     let public_invoice = Router::new()
         .route("/api/v1/invoices/pay/{share_token}", get(invoices::public_get))
+        .route("/api/v1/invoices/pay/{share_token}/qr", get(invoices::public_qr))
+        .route("/api/v1/invoices/pay/{share_token}/proof", get(invoices::public_proof))
+        .route("/api/v1/invoices/pay/{share_token}/messages", get(invoice_notes::public_list))
+        .route(
+            "/api/v1/invoices/pay/{share_token}/messages",
+            post(invoice_notes::public_create).layer(GovernorLayer::new(message_governor)),
+        )
+        .route(
+            "/api/v1/public/invoices/{share_token}/status",
+            get(invoices::public_status).layer(GovernorLayer::new(status_governor)),
+        )
+        .route("/api/v1/shared/{token}", get(share_links::public_get))
+        .route("/api/v1/shared/{token}/transactions", get(share_links::public_transactions))
+        .route("/api/v1/downloads/{token}", get(jobs::download))
+        .route("/api/v1/fees/estimates", get(fees::estimates))
         .route("/api/v1/webhooks/stripe", post(billing::webhook));
 
     let protected = Router::new()
         // Auth
-        .route("/api/v1/auth/me", get(auth::me))
+        .route("/api/v1/auth/me", get(auth::me).delete(auth::delete_account))
+        .route("/api/v1/auth/quotas", get(auth::quotas))
         .route("/api/v1/auth/change-password", post(auth::change_password))
-        .route("/api/v1/auth/account", delete(auth::delete_account))
+        .route(
+            "/api/v1/auth/email",
+            post(auth::change_email).layer(GovernorLayer::new(email_governor.clone())),
+        )
+        .route(
+            "/api/v1/auth/sessions",
+            get(auth::list_sessions).delete(auth::revoke_other_sessions),
+        )
+        .route("/api/v1/auth/sessions/{session_id}", delete(auth::revoke_session))
+        .route("/api/v1/auth/audit-log", get(auth::audit_log))
+        .route("/api/v1/auth/api-keys", get(api_keys::list).post(api_keys::create))
+        .route("/api/v1/auth/api-keys/{key_id}", delete(api_keys::delete))
+        .route("/api/v1/auth/passkeys", get(passkeys::list).post(passkeys::register))
+        .route(
+            "/api/v1/auth/passkeys/register/options",
+            post(passkeys::registration_options).layer(GovernorLayer::new(passkey_challenge_governor)),
+        )
+        .route(
+            "/api/v1/auth/passkeys/{passkey_id}",
+            put(passkeys::rename).delete(passkeys::delete),
+        )
+        .route("/api/v1/auth/account/export", post(jobs::export_account))
+        .route("/api/v1/export/{job_id}", get(jobs::export_status))
+        .route(
+            "/api/v1/import",
+            post(jobs::import_account)
+                .layer(DefaultBodyLimit::max(state.config.account_import_max_bytes)),
+        )
+        // Background jobs
+        .route("/api/v1/jobs", get(jobs::list))
+        .route("/api/v1/jobs/{id}", get(jobs::get))
+        // Organizations
+        .route("/api/v1/organizations", get(organizations::list).post(organizations::create))
+        .route("/api/v1/organizations/invitations", get(organizations::my_invitations))
+        .route("/api/v1/organizations/invitations/{id}", delete(organizations::decline_invitation))
+        .route("/api/v1/organizations/invitations/{id}/accept", post(organizations::accept_invitation))
+        .route(
+            "/api/v1/organizations/{org_id}",
+            get(organizations::get)
+                .put(organizations::update)
+                .delete(organizations::delete),
+        )
+        .route(
+            "/api/v1/organizations/{org_id}/members",
+            get(organizations::list_members).post(organizations::add_member),
+        )
+        .route(
+            "/api/v1/organizations/{org_id}/members/{user_id}",
+            put(organizations::update_member).delete(organizations::remove_member),
+        )
+        .route("/api/v1/organizations/{org_id}/invitations", get(organizations::list_invitations))
+        .route(
+            "/api/v1/organizations/{org_id}/invitations/{id}",
+            delete(organizations::revoke_invitation),
+        )
+        // Dashboard: totals across the portfolios
+        .route("/api/v1/dashboard", get(analysis::dashboard))
+        .route("/api/v1/networth", get(analysis::net_worth))
         // Portfolios
         .route("/api/v1/portfolios", get(portfolios::list).post(portfolios::create))
         .route(
@@ -103,6 +239,26 @@ pub fn create_router(state: AppState) -> Router {
                 .put(portfolios::update)
                 .delete(portfolios::delete),
         )
+        .route("/api/v1/portfolios/{id}/clone", post(portfolios::clone))
+        .route("/api/v1/portfolios/{id}/organization", put(portfolios::set_organization))
+        .route(
+            "/api/v1/portfolios/{id}/share-links",
+            get(share_links::list).post(share_links::create),
+        )
+        .route("/api/v1/portfolios/{id}/share-links/{link_id}", delete(share_links::delete))
+        .route(
+            "/api/v1/portfolios/{id}/branding",
+            get(portfolios::get_branding).put(portfolios::update_branding),
+        )
+        .route("/api/v1/portfolio-templates", get(portfolio_templates::list))
+        .route(
+            "/api/v1/portfolio-templates/{id}",
+            get(portfolio_templates::get).delete(portfolio_templates::delete),
+        )
+        .route(
+            "/api/v1/portfolio-templates/{id}/portfolios",
+            post(portfolios::create_from_template),
+        )
         // Wallets (nested under portfolios)
         .route(
             "/api/v1/portfolios/{portfolio_id}/wallets",
@@ -121,12 +277,91 @@ pub fn create_router(state: AppState) -> Router {
             get(transactions::list),
         )
         .route("/api/v1/transactions", post(transactions::create))
+        // Data quality: issues that distort reports, and batch fixes per issue class
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/data-quality",
+            get(data_quality::report),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/data-quality/imprecise-time",
+            post(data_quality::fix_imprecise_time),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/data-quality/negative-balance",
+            post(data_quality::fix_negative_balance),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/data-quality/oversold",
+            post(data_quality::fix_oversold),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/import/{format}",
+            post(imports::import_history),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/transactions/import",
+            post(imports::import_exchange),
+        )
+        .route(
+            "/api/v1/import-templates",
+            get(import_templates::list).post(import_templates::create),
+        )
+        .route(
+            "/api/v1/import-templates/{id}",
+            get(import_templates::get)
+                .put(import_templates::update)
+                .delete(import_templates::delete),
+        )
+        // Exchange API connections (read-only keys, background trade import)
+        .route(
+            "/api/v1/exchange-connections",
+            get(exchange_connections::list).post(exchange_connections::create),
+        )
+        .route(
+            "/api/v1/exchange-connections/{id}",
+            get(exchange_connections::get).delete(exchange_connections::delete),
+        )
+        .route("/api/v1/exchange-connections/{id}/test", post(exchange_connections::test))
+        // Webhook endpoints (signed invoice events for order fulfillment)
+        .route(
+            "/api/v1/webhook-endpoints",
+            get(webhook_endpoints::list).post(webhook_endpoints::create),
+        )
+        .route(
+            "/api/v1/webhook-endpoints/{id}",
+            put(webhook_endpoints::update).delete(webhook_endpoints::delete),
+        )
+        .route(
+            "/api/v1/webhook-endpoints/{id}/deliveries",
+            get(webhook_endpoints::deliveries),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/transactions/export",
+            get(transactions::export),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/transactions/audit",
+            get(transactions::audit),
+        )
         .route(
             "/api/v1/portfolios/{portfolio_id}/transactions/{tx_id}",
             get(transactions::get)
                 .put(transactions::update)
                 .delete(transactions::delete),
         )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/transactions/{tx_id}/details",
+            get(transactions::details),
+        )
+        // Recurring manual transactions (e.g. DCA buys without an exchange API)
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/recurring",
+            get(recurring::list).post(recurring::create),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/recurring/{recurring_id}",
+            put(recurring::update).delete(recurring::delete),
+        )
         // Labels
         .route("/api/v1/labels", get(labels::list).post(labels::create))
         .route(
@@ -138,10 +373,43 @@ pub fn create_router(state: AppState) -> Router {
             "/api/v1/transactions/{transaction_id}/labels",
             get(labels::get_transaction_labels).put(labels::assign_to_transaction),
         )
+        .route(
+            "/api/v1/transactions/{transaction_id}/split",
+            post(transactions::split).delete(transactions::unsplit),
+        )
+        // Transfers between the user's own wallets
+        .route(
+            "/api/v1/transactions/{transaction_id}/transfer",
+            put(transfers::link).delete(transfers::unlink),
+        )
+        .route("/api/v1/portfolios/{portfolio_id}/transfers/detect", post(transfers::detect))
+        // Specific identification: the lots a disposal consumes
+        .route(
+            "/api/v1/transactions/{transaction_id}/open-lots",
+            get(lot_assignments::open_lots),
+        )
+        .route(
+            "/api/v1/transactions/{transaction_id}/lot-assignments",
+            get(lot_assignments::get).put(lot_assignments::replace),
+        )
+        // Transaction attachments (receipts, invoices)
+        .route(
+            "/api/v1/transactions/{transaction_id}/attachments",
+            get(attachments::list).post(attachments::upload)
+                .layer(DefaultBodyLimit::max(state.config.attachment_max_bytes)),
+        )
+        .route(
+            "/api/v1/transactions/{transaction_id}/attachments/{attachment_id}",
+            get(attachments::download).delete(attachments::delete),
+        )
         // Wallet sync + BDK endpoints
         .route(
-            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/sync",
-            post(sync::sync_wallet),
+            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/sync/status",
+            get(sync::sync_status),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/sync/stream",
+            get(sync::sync_stream),
         )
         .route(
             "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/addresses",
@@ -151,6 +419,14 @@ pub fn create_router(state: AppState) -> Router {
             "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/utxos",
             get(sync::get_utxos),
         )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/psbt",
+            post(sync::create_psbt),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/utxos/{outpoint}",
+            get(utxos::get).put(utxos::update).delete(utxos::delete),
+        )
         // Analysis (cost basis + summary)
         .route(
             "/api/v1/portfolios/{id}/cost-basis",
@@ -160,15 +436,83 @@ pub fn create_router(state: AppState) -> Router {
             "/api/v1/portfolios/{id}/summary",
             get(analysis::summary),
         )
+        .route(
+            "/api/v1/portfolios/{id}/lots",
+            get(analysis::lots),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/value-history",
+            get(analysis::value_history),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/business-metrics",
+            get(analysis::business_metrics),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/by-label",
+            get(analysis::by_label),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/cash-flow",
+            get(analysis::cash_flow),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/dca",
+            get(analysis::dca),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/holdings-age",
+            get(analysis::holdings_age),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/fees",
+            get(analysis::fees),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/analysis/benchmark",
+            get(analysis::benchmark),
+        )
         // Tax reports
         .route(
             "/api/v1/portfolios/{id}/tax/report",
             get(tax::tax_report),
         )
+        .route(
+            "/api/v1/portfolios/{id}/tax/quarterly",
+            get(tax::tax_quarterly),
+        )
         .route(
             "/api/v1/portfolios/{id}/tax/csv",
             get(tax::tax_csv),
         )
+        .route(
+            "/api/v1/portfolios/{id}/tax/locks",
+            get(tax::list_locks),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/tax/locks/{year}",
+            put(tax::lock_year).delete(tax::unlock_year),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/tax/pdf",
+            get(tax::tax_pdf),
+        )
+        .route(
+            "/api/v1/portfolios/{id}/tax/export",
+            get(tax::tax_export),
+        )
+        .route(
+            "/api/v1/tax/report",
+            get(tax::combined_tax_report),
+        )
+        .route(
+            "/api/v1/tax/csv",
+            get(tax::combined_tax_csv),
+        )
+        .route(
+            "/api/v1/tax/settings",
+            get(tax::get_settings).put(tax::update_settings),
+        )
         // Invoices
         .route(
             "/api/v1/portfolios/{portfolio_id}/invoices",
@@ -185,12 +529,62 @@ pub fn create_router(state: AppState) -> Router {
             "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/check-payment",
             post(invoices::check_payment),
         )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/send",
+            post(invoices::send),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/pdf",
+            get(invoices::pdf),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/lightning",
+            post(invoices::renew_lightning),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/regenerate-token",
+            post(invoices::regenerate_token),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/events",
+            get(invoices::events),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/notes",
+            get(invoice_notes::list).post(invoice_notes::create),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/invoices/{invoice_id}/notes/{note_id}",
+            delete(invoice_notes::delete),
+        )
+        // Customer directory
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/customers",
+            get(customers::list).post(customers::create),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/customers/{customer_id}",
+            get(customers::get)
+                .put(customers::update)
+                .delete(customers::delete),
+        )
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/customers/{customer_id}/statement",
+            get(customers::statement),
+        )
         // Alerts
         .route("/api/v1/alerts", get(alerts::list).post(alerts::create))
         .route(
             "/api/v1/alerts/{id}",
             put(alerts::update).delete(alerts::delete),
         )
+        // Watched addresses (third-party, outside portfolios)
+        .route("/api/v1/watch-addresses", get(watch::list).post(watch::create))
+        .route(
+            "/api/v1/watch-addresses/{id}",
+            get(watch::get).put(watch::update).delete(watch::delete),
+        )
+        .route("/api/v1/watch-addresses/{id}/refresh", post(watch::refresh))
         // Billing
         .route("/api/v1/billing/status", get(billing::status))
         .route("/api/v1/billing/checkout", post(billing::checkout))
@@ -201,7 +595,6 @@ pub fn create_router(state: AppState) -> Router {
         .route("/api/v1/prices/current", get(prices::current))
         .route("/api/v1/prices/historical", get(prices::historical))
         .route("/api/v1/prices/range", get(prices::range))
-        .route("/api/v1/prices/backfill", post(prices::backfill))
         .route("/api/v1/portfolios/{portfolio_id}/prices/backfill", post(prices::backfill_portfolio))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
@@ -209,10 +602,63 @@ pub fn create_router(state: AppState) -> Router {
         ))
         ;
 
+    // Site administration; require_admin runs after require_auth has found the user
+    let admin_routes = Router::new()
+        .route("/api/v1/admin/stats", get(admin::stats))
+        .route("/api/v1/admin/audit-log", get(admin::audit_log))
+        .route("/api/v1/admin/users", get(admin::list_users))
+        .route("/api/v1/admin/users/{user_id}", get(admin::get_user))
+        .route("/api/v1/admin/users/{user_id}/disable", post(admin::disable_user))
+        .route("/api/v1/admin/users/{user_id}/enable", post(admin::enable_user))
+        .route(
+            "/api/v1/admin/users/{user_id}/resend-verification",
+            post(admin::resend_verification).layer(GovernorLayer::new(email_governor)),
+        )
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_auth,
+        ));
+
+    // Full wallet scans, exchange history imports and bulk price backfills (including
+    // pricing flagged transactions) can legitimately outlast the request deadline;
+    // their upstream calls are still individually bounded.
+    let long_running = Router::new()
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/sync",
+            post(sync::sync_wallet),
+        )
+        .route("/api/v1/exchange-connections/{id}/sync", post(exchange_connections::sync))
+        .route("/api/v1/prices/backfill", post(prices::backfill))
+        .route(
+            "/api/v1/portfolios/{portfolio_id}/data-quality/missing-price",
+            post(data_quality::fix_missing_price),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_auth,
+        ));
+
     Router::new()
         .merge(health_routes)
         .merge(auth_routes)
         .merge(public_invoice)
         .merge(protected)
+        .merge(admin_routes)
+        .layer(middleware::from_fn_with_state(state.clone(), request_deadline))
+        .merge(long_running)
         .with_state(state)
 }
+
+/// Fail requests that haven't produced a response within `REQUEST_TIMEOUT_SECS`.
+/// Streamed bodies (exports, SSE) are unaffected once their headers are sent.
+async fn request_deadline(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let deadline = Duration::from_secs(state.config.request_timeout_secs);
+    match tokio::time::timeout(deadline, next.run(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request exceeded its {}s deadline", deadline.as_secs());
+            AppError::Timeout.into_response()
+        }
+    }
+}
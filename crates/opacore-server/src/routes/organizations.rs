@@ -0,0 +1,543 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Role};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::email;
+
+const MAX_NAME_CHARS: usize = 100;
+/// Most members one organization can have.
+const MAX_MEMBERS: i64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    /// The requesting user's role in it.
+    pub role: String,
+    pub member_count: i64,
+    /// Portfolios shared with it.
+    pub portfolio_count: i64,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Member {
+    pub user_id: String,
+    pub email: String,
+    pub name: String,
+    pub role: String,
+    pub added_by: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invitation {
+    pub id: String,
+    pub organization_id: String,
+    pub organization_name: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrganizationRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddMemberRequest {
+    /// Whoever signs in with it can accept; they don't need an account yet.
+    pub email: String,
+    /// "owner", "admin", "accountant" or "viewer".
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: String,
+}
+
+/// Selects an organization with the role of the user bound to `?1`.
+const ORGANIZATION_COLS: &str = "o.id, o.name, m.role,
+    (SELECT COUNT(*) FROM organization_members WHERE organization_id = o.id),
+    (SELECT COUNT(*) FROM portfolios WHERE organization_id = o.id),
+    o.created_at, o.updated_at";
+
+fn row_to_organization(row: &rusqlite::Row) -> rusqlite::Result<Organization> {
+    Ok(Organization {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        role: row.get(2)?,
+        member_count: row.get(3)?,
+        portfolio_count: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+    })
+}
+
+const MEMBER_COLS: &str = "m.user_id, u.email, u.name, m.role, m.added_by, m.created_at, m.updated_at";
+
+fn row_to_member(row: &rusqlite::Row) -> rusqlite::Result<Member> {
+    Ok(Member {
+        user_id: row.get(0)?,
+        email: row.get(1)?,
+        name: row.get(2)?,
+        role: row.get(3)?,
+        added_by: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+    })
+}
+
+const INVITATION_COLS: &str =
+    "i.id, i.organization_id, o.name, i.email, i.role, i.invited_by, i.created_at, i.updated_at";
+
+fn row_to_invitation(row: &rusqlite::Row) -> rusqlite::Result<Invitation> {
+    Ok(Invitation {
+        id: row.get(0)?,
+        organization_id: row.get(1)?,
+        organization_name: row.get(2)?,
+        email: row.get(3)?,
+        role: row.get(4)?,
+        invited_by: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+fn load_organization(conn: &rusqlite::Connection, user_id: &str, organization_id: &str) -> AppResult<Organization> {
+    conn.query_row(
+        &format!(
+            "SELECT {ORGANIZATION_COLS} FROM organizations o
+             JOIN organization_members m ON m.organization_id = o.id AND m.user_id = ?1
+             WHERE o.id = ?2"
+        ),
+        rusqlite::params![user_id, organization_id],
+        row_to_organization,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Organization not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+fn load_member(conn: &rusqlite::Connection, organization_id: &str, user_id: &str) -> AppResult<Member> {
+    conn.query_row(
+        &format!(
+            "SELECT {MEMBER_COLS} FROM organization_members m JOIN users u ON u.id = m.user_id
+             WHERE m.organization_id = ?1 AND m.user_id = ?2"
+        ),
+        rusqlite::params![organization_id, user_id],
+        row_to_member,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Member not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+fn validate_name(name: &str) -> AppResult<String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AppError::BadRequest(format!("name must be 1 to {MAX_NAME_CHARS} characters")));
+    }
+    Ok(name.to_string())
+}
+
+fn parse_role(role: &str) -> AppResult<Role> {
+    Role::parse(role)
+        .ok_or_else(|| AppError::BadRequest("role must be 'owner', 'admin', 'accountant' or 'viewer'".into()))
+}
+
+/// Members can only hand out roles up to their own.
+fn require_grantable(actor: Role, role: Role) -> AppResult<()> {
+    if role > actor {
+        return Err(AppError::Forbidden(format!("The {} role can't grant the {} role", actor.name(), role.name())));
+    }
+    Ok(())
+}
+
+/// An organization always keeps at least one owner.
+fn require_other_owner(conn: &rusqlite::Connection, organization_id: &str, user_id: &str) -> AppResult<()> {
+    let others: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM organization_members WHERE organization_id = ?1 AND role = 'owner' AND user_id != ?2",
+        rusqlite::params![organization_id, user_id],
+        |row| row.get(0),
+    )?;
+    if others == 0 {
+        return Err(AppError::Conflict(
+            "An organization needs an owner; make someone else an owner first".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// GET /api/v1/organizations
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<Organization>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ORGANIZATION_COLS} FROM organizations o
+         JOIN organization_members m ON m.organization_id = o.id AND m.user_id = ?1
+         ORDER BY o.name"
+    ))?;
+    let organizations = stmt
+        .query_map(rusqlite::params![user.id], row_to_organization)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(organizations))
+}
+
+/// POST /api/v1/organizations
+///
+/// The creator becomes its first owner.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<OrganizationRequest>,
+) -> AppResult<(StatusCode, Json<Organization>)> {
+    let name = validate_name(&body.name)?;
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    let mut conn = state.db.get()?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO organizations (id, name, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)",
+        rusqlite::params![id, name, now],
+    )?;
+    tx.execute(
+        "INSERT INTO organization_members (organization_id, user_id, role, created_at, updated_at)
+         VALUES (?1, ?2, 'owner', ?3, ?3)",
+        rusqlite::params![id, user.id, now],
+    )?;
+    tx.commit()?;
+    state.cache.invalidate_member(&id, &user.id);
+
+    Ok((StatusCode::CREATED, Json(load_organization(&conn, &user.id, &id)?)))
+}
+
+/// GET /api/v1/organizations/{org_id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+) -> AppResult<Json<Organization>> {
+    let conn = state.db.get()?;
+    Ok(Json(load_organization(&conn, &user.id, &org_id)?))
+}
+
+/// PUT /api/v1/organizations/{org_id}
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+    Json(body): Json<OrganizationRequest>,
+) -> AppResult<Json<Organization>> {
+    let name = validate_name(&body.name)?;
+    let conn = state.db.get()?;
+    policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Admin)?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE organizations SET name = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![name, now, org_id],
+    )?;
+    Ok(Json(load_organization(&conn, &user.id, &org_id)?))
+}
+
+/// DELETE /api/v1/organizations/{org_id}
+///
+/// Portfolios shared with it go back to being visible to their creators only.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let mut conn = state.db.get()?;
+    policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Owner)?;
+
+    let tx = conn.transaction()?;
+    let ids = |sql: &str| -> AppResult<Vec<String>> {
+        Ok(tx
+            .prepare(sql)?
+            .query_map(rusqlite::params![org_id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?)
+    };
+    let members = ids("SELECT user_id FROM organization_members WHERE organization_id = ?1")?;
+    let portfolios = ids("SELECT id FROM portfolios WHERE organization_id = ?1")?;
+    tx.execute("DELETE FROM organizations WHERE id = ?1", rusqlite::params![org_id])?;
+    tx.commit()?;
+
+    for member in &members {
+        state.cache.invalidate_member(&org_id, member);
+    }
+    for portfolio in &portfolios {
+        state.cache.invalidate_portfolio(portfolio);
+    }
+    tracing::info!("User {} deleted organization {org_id}", user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/organizations/{org_id}/members
+pub async fn list_members(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+) -> AppResult<Json<Vec<Member>>> {
+    let conn = state.db.get()?;
+    policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Viewer)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {MEMBER_COLS} FROM organization_members m JOIN users u ON u.id = m.user_id
+         WHERE m.organization_id = ?1 ORDER BY m.created_at"
+    ))?;
+    let members = stmt
+        .query_map(rusqlite::params![org_id], row_to_member)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(members))
+}
+
+/// POST /api/v1/organizations/{org_id}/members
+///
+/// Invite someone by email. They join once they accept, and the answer is the same
+/// whether or not the address has an account yet.
+pub async fn add_member(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+    Json(body): Json<AddMemberRequest>,
+) -> AppResult<(StatusCode, Json<Invitation>)> {
+    let role = parse_role(&body.role)?;
+    let email = body.email.trim();
+    if email.is_empty() || !email.contains('@') || email.contains(char::is_whitespace) {
+        return Err(AppError::BadRequest("Invalid email address".into()));
+    }
+    let conn = state.db.get()?;
+    let actor = policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Admin)?;
+    require_grantable(actor, role)?;
+
+    let member: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM organization_members m JOIN users u ON u.id = m.user_id
+                        WHERE m.organization_id = ?1 AND u.email = ?2 COLLATE NOCASE)",
+        rusqlite::params![org_id, email],
+        |row| row.get(0),
+    )?;
+    if member {
+        return Err(AppError::Conflict("They are already a member".into()));
+    }
+
+    // Pending invitations hold their seat until they're accepted, declined or revoked.
+    let count: i64 = conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM organization_members WHERE organization_id = ?1)
+              + (SELECT COUNT(*) FROM organization_invitations WHERE organization_id = ?1 AND email != ?2)",
+        rusqlite::params![org_id, email],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_MEMBERS {
+        return Err(AppError::Forbidden(format!("An organization can have at most {MAX_MEMBERS} members")));
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO organization_invitations (id, organization_id, email, role, invited_by, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+         ON CONFLICT (organization_id, email) DO UPDATE SET
+             role = excluded.role, invited_by = excluded.invited_by, updated_at = excluded.updated_at",
+        rusqlite::params![Uuid::new_v4().to_string(), org_id, email, role.name(), user.id, now],
+    )?;
+    let invitation = conn.query_row(
+        &format!(
+            "SELECT {INVITATION_COLS} FROM organization_invitations i JOIN organizations o ON o.id = i.organization_id
+             WHERE i.organization_id = ?1 AND i.email = ?2"
+        ),
+        rusqlite::params![org_id, email],
+        row_to_invitation,
+    )?;
+    tracing::info!("User {} invited someone to organization {org_id} as {}", user.id, role.name());
+
+    let config = state.config.clone();
+    let (to, inviter, organization) = (invitation.email.clone(), user.name.clone(), invitation.organization_name.clone());
+    tokio::spawn(async move {
+        if let Err(e) = email::send_organization_invitation(&config, &to, &inviter, &organization).await {
+            tracing::error!("Failed to send organization invitation: {e}");
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(invitation)))
+}
+
+/// GET /api/v1/organizations/{org_id}/invitations
+pub async fn list_invitations(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(org_id): Path<String>,
+) -> AppResult<Json<Vec<Invitation>>> {
+    let conn = state.db.get()?;
+    policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Admin)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {INVITATION_COLS} FROM organization_invitations i JOIN organizations o ON o.id = i.organization_id
+         WHERE i.organization_id = ?1 ORDER BY i.created_at"
+    ))?;
+    let invitations = stmt
+        .query_map(rusqlite::params![org_id], row_to_invitation)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(invitations))
+}
+
+/// DELETE /api/v1/organizations/{org_id}/invitations/{id}
+pub async fn revoke_invitation(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((org_id, id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let actor = policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Admin)?;
+    let role: String = conn
+        .query_row(
+            "SELECT role FROM organization_invitations WHERE id = ?1 AND organization_id = ?2",
+            rusqlite::params![id, org_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invitation not found".into()),
+            e => AppError::Database(e),
+        })?;
+    require_grantable(actor, parse_role(&role)?)?;
+
+    conn.execute("DELETE FROM organization_invitations WHERE id = ?1", rusqlite::params![id])?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/organizations/invitations
+///
+/// Invitations waiting for the signed-in user.
+pub async fn my_invitations(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<Invitation>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {INVITATION_COLS} FROM organization_invitations i JOIN organizations o ON o.id = i.organization_id
+         WHERE i.email = ?1 ORDER BY i.created_at"
+    ))?;
+    let invitations = stmt
+        .query_map(rusqlite::params![user.email], row_to_invitation)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(invitations))
+}
+
+/// POST /api/v1/organizations/invitations/{id}/accept
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Organization>> {
+    let mut conn = state.db.get()?;
+    let tx = conn.transaction()?;
+    let (org_id, role, invited_by): (String, String, Option<String>) = tx
+        .query_row(
+            "SELECT organization_id, role, invited_by FROM organization_invitations WHERE id = ?1 AND email = ?2",
+            rusqlite::params![id, user.email],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invitation not found".into()),
+            e => AppError::Database(e),
+        })?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    tx.execute(
+        "INSERT OR IGNORE INTO organization_members (organization_id, user_id, role, added_by, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+        rusqlite::params![org_id, user.id, role, invited_by, now],
+    )?;
+    tx.execute("DELETE FROM organization_invitations WHERE id = ?1", rusqlite::params![id])?;
+    tx.commit()?;
+    state.cache.invalidate_member(&org_id, &user.id);
+    tracing::info!("User {} joined organization {org_id} as {role}", user.id);
+
+    Ok(Json(load_organization(&conn, &user.id, &org_id)?))
+}
+
+/// DELETE /api/v1/organizations/invitations/{id}
+///
+/// Decline an invitation.
+pub async fn decline_invitation(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let deleted = conn.execute(
+        "DELETE FROM organization_invitations WHERE id = ?1 AND email = ?2",
+        rusqlite::params![id, user.email],
+    )?;
+    if deleted == 0 {
+        return Err(AppError::NotFound("Invitation not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// PUT /api/v1/organizations/{org_id}/members/{user_id}
+pub async fn update_member(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((org_id, member_id)): Path<(String, String)>,
+    Json(body): Json<UpdateMemberRequest>,
+) -> AppResult<Json<Member>> {
+    let role = parse_role(&body.role)?;
+    let conn = state.db.get()?;
+    let actor = policy::organization(&state.cache, &conn, &user.id, &org_id, Role::Admin)?;
+    let current = parse_role(&load_member(&conn, &org_id, &member_id)?.role)?;
+    require_grantable(actor, current)?;
+    require_grantable(actor, role)?;
+    if current == Role::Owner && role != Role::Owner {
+        require_other_owner(&conn, &org_id, &member_id)?;
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE organization_members SET role = ?1, updated_at = ?2 WHERE organization_id = ?3 AND user_id = ?4",
+        rusqlite::params![role.name(), now, org_id, member_id],
+    )?;
+    state.cache.invalidate_member(&org_id, &member_id);
+
+    Ok(Json(load_member(&conn, &org_id, &member_id)?))
+}
+
+/// DELETE /api/v1/organizations/{org_id}/members/{user_id}
+///
+/// Remove a member, or leave when `user_id` is your own.
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((org_id, member_id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let minimum = if member_id == user.id { Role::Viewer } else { Role::Admin };
+    let actor = policy::organization(&state.cache, &conn, &user.id, &org_id, minimum)?;
+    let current = parse_role(&load_member(&conn, &org_id, &member_id)?.role)?;
+    require_grantable(actor, current)?;
+    if current == Role::Owner {
+        require_other_owner(&conn, &org_id, &member_id)?;
+    }
+
+    conn.execute(
+        "DELETE FROM organization_members WHERE organization_id = ?1 AND user_id = ?2",
+        rusqlite::params![org_id, member_id],
+    )?;
+    state.cache.invalidate_member(&org_id, &member_id);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,274 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::middleware::{build_csrf_cookie, build_session_cookie};
+use crate::auth::session;
+use crate::auth::webauthn::{self, AuthenticationCredential, Ceremony, RegistrationCredential};
+use crate::error::{AppError, AppResult};
+use crate::models::{User, UserPublic};
+use crate::routes::AppState;
+use crate::services;
+
+/// Most passkeys one account can register.
+const MAX_PASSKEYS: i64 = 20;
+const MAX_NAME_CHARS: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct Passkey {
+    pub id: String,
+    pub name: String,
+    /// base64url, as the browser reports it.
+    pub credential_id: String,
+    pub transports: Vec<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterPasskeyRequest {
+    /// Defaults to "Passkey".
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenamePasskeyRequest {
+    pub name: String,
+}
+
+const PASSKEY_COLS: &str = "id, name, credential_id, transports, last_used_at, created_at";
+
+fn row_to_passkey(row: &rusqlite::Row) -> rusqlite::Result<Passkey> {
+    let transports: Option<String> = row.get(3)?;
+    Ok(Passkey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        credential_id: row.get(2)?,
+        transports: transports.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default(),
+        last_used_at: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+fn validate_name(name: &str) -> AppResult<String> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AppError::BadRequest(format!("name must be 1 to {MAX_NAME_CHARS} characters")));
+    }
+    Ok(name.to_string())
+}
+
+/// GET /api/v1/auth/passkeys
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<Passkey>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {PASSKEY_COLS} FROM webauthn_credentials WHERE user_id = ?1 ORDER BY created_at"
+    ))?;
+    let passkeys = stmt
+        .query_map(rusqlite::params![user.id], row_to_passkey)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(passkeys))
+}
+
+/// POST /api/v1/auth/passkeys/register/options
+///
+/// Options for `navigator.credentials.create()`, in the JSON form taken by
+/// `PublicKeyCredential.parseCreationOptionsFromJSON()`.
+pub async fn registration_options(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<serde_json::Value>> {
+    // Existing passkeys are excluded so an authenticator isn't registered twice
+    let existing: Vec<(String, Option<String>)> = {
+        let conn = state.db.get()?;
+        let mut stmt = conn.prepare("SELECT credential_id, transports FROM webauthn_credentials WHERE user_id = ?1")?;
+        let rows = stmt
+            .query_map(rusqlite::params![user.id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+    if existing.len() as i64 >= MAX_PASSKEYS {
+        return Err(AppError::Forbidden(format!("An account can have at most {MAX_PASSKEYS} passkeys")));
+    }
+
+    let challenge = webauthn::create_challenge(&state.db, Ceremony::Register, Some(&user.id))?;
+    let rp = &state.config.webauthn;
+    let exclude: Vec<serde_json::Value> = existing
+        .into_iter()
+        .map(|(id, transports)| {
+            let transports: Vec<String> =
+                transports.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default();
+            serde_json::json!({ "type": "public-key", "id": id, "transports": transports })
+        })
+        .collect();
+
+    Ok(Json(serde_json::json!({
+        "challenge": challenge,
+        "rp": { "id": rp.rp_id, "name": rp.rp_name },
+        "user": {
+            "id": webauthn::user_handle(&user.id),
+            "name": user.email,
+            "displayName": user.name,
+        },
+        "pubKeyCredParams": webauthn::SUPPORTED_ALGORITHMS
+            .iter()
+            .map(|alg| serde_json::json!({ "type": "public-key", "alg": alg }))
+            .collect::<Vec<_>>(),
+        "timeout": webauthn::CHALLENGE_TTL_SECS * 1000,
+        "excludeCredentials": exclude,
+        "authenticatorSelection": { "residentKey": "required", "userVerification": "required" },
+        "attestation": "none",
+    })))
+}
+
+/// POST /api/v1/auth/passkeys
+///
+/// Store the passkey created with the options from `registration_options`.
+pub async fn register(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<RegisterPasskeyRequest>,
+) -> AppResult<impl IntoResponse> {
+    let name = validate_name(body.name.as_deref().unwrap_or("Passkey"))?;
+    let passkey = webauthn::verify_registration(&state.db, &state.config.webauthn, &user.id, &body.credential)?;
+
+    let conn = state.db.get()?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM webauthn_credentials WHERE user_id = ?1",
+        rusqlite::params![user.id],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_PASSKEYS {
+        return Err(AppError::Forbidden(format!("An account can have at most {MAX_PASSKEYS} passkeys")));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let transports = serde_json::to_string(&passkey.transports).unwrap_or_else(|_| "[]".to_string());
+    let result = conn.execute(
+        "INSERT INTO webauthn_credentials (id, user_id, credential_id, public_key, algorithm, sign_count, transports, name)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            id,
+            user.id,
+            passkey.credential_id,
+            passkey.public_key,
+            passkey.algorithm,
+            passkey.sign_count,
+            transports,
+            name
+        ],
+    );
+    match result {
+        Ok(_) => {}
+        Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
+            return Err(AppError::Conflict("This passkey is already registered".to_string()));
+        }
+        Err(e) => return Err(AppError::Database(e)),
+    }
+    tracing::info!("User {} registered passkey {id}", user.id);
+
+    let created = conn.query_row(
+        &format!("SELECT {PASSKEY_COLS} FROM webauthn_credentials WHERE id = ?1"),
+        rusqlite::params![id],
+        row_to_passkey,
+    )?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+/// PUT /api/v1/auth/passkeys/{passkey_id}
+pub async fn rename(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(passkey_id): Path<String>,
+    Json(body): Json<RenamePasskeyRequest>,
+) -> AppResult<Json<Passkey>> {
+    let name = validate_name(&body.name)?;
+    let conn = state.db.get()?;
+    conn.query_row(
+        &format!("UPDATE webauthn_credentials SET name = ?1 WHERE id = ?2 AND user_id = ?3 RETURNING {PASSKEY_COLS}"),
+        rusqlite::params![name, passkey_id, user.id],
+        row_to_passkey,
+    )
+    .map(Json)
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Passkey not found".to_string()),
+        _ => AppError::Database(e),
+    })
+}
+
+/// DELETE /api/v1/auth/passkeys/{passkey_id}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(passkey_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let deleted = conn.execute(
+        "DELETE FROM webauthn_credentials WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![passkey_id, user.id],
+    )?;
+    if deleted == 0 {
+        return Err(AppError::NotFound("Passkey not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/auth/passkeys/login/options
+///
+/// Options for `navigator.credentials.get()`. No credentials are listed, so the browser
+/// offers whichever of the user's passkeys it holds for this site.
+pub async fn login_options(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
+    let challenge = webauthn::create_challenge(&state.db, Ceremony::Authenticate, None)?;
+    Ok(Json(serde_json::json!({
+        "challenge": challenge,
+        "rpId": state.config.webauthn.rp_id,
+        "timeout": webauthn::CHALLENGE_TTL_SECS * 1000,
+        "allowCredentials": [],
+        "userVerification": "required",
+    })))
+}
+
+/// POST /api/v1/auth/passkeys/login
+///
+/// Sign in with a passkey instead of a password.
+pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(body): Json<AuthenticationCredential>,
+) -> AppResult<impl IntoResponse> {
+    let user_id = webauthn::verify_authentication(&state.db, &state.config.webauthn, &body)?;
+    let user = {
+        let conn = state.db.get()?;
+        state.cache.user(&conn, &user_id)?
+    };
+    session::require_enabled(&user)?;
+    if !user.email_verified {
+        return Err(AppError::Forbidden(
+            "Please verify your email before signing in. Check your inbox for the verification link.".to_string(),
+        ));
+    }
+
+    let client = session::client_info(&state.config, &headers, peer);
+    let sess = session::create_session(&state.db, &state.config.sessions, &user.id, Some(&client.ip_address), client.user_agent.as_deref())?;
+    services::audit::record_login(&state.db, &user.id, &sess.id, "passkey", &client)?;
+    services::login_alerts::on_login(&state.db, &state.config, &user, &sess.id, &client);
+    let cookies = jar
+        .add(build_session_cookie(&state.config, &sess.token))
+        .add(build_csrf_cookie(&state.config, &sess.id));
+    let user_public: UserPublic = user.into();
+    Ok((cookies, Json(user_public)))
+}
@@ -0,0 +1,54 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::portfolio_template::{self, PortfolioTemplate, TEMPLATE_COLS};
+
+/// GET /api/v1/portfolio-templates
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<PortfolioTemplate>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {TEMPLATE_COLS} FROM portfolio_templates WHERE user_id = ?1 ORDER BY name"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], portfolio_template::row_to_template)?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// GET /api/v1/portfolio-templates/{id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+) -> AppResult<Json<PortfolioTemplate>> {
+    let conn = state.db.get()?;
+    Ok(Json(portfolio_template::load_template(&conn, &user.id, &template_id)?))
+}
+
+/// DELETE /api/v1/portfolio-templates/{id}
+///
+/// Portfolios created from the template are unaffected.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let affected = conn.execute(
+        "DELETE FROM portfolio_templates WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![template_id, user.id],
+    )?;
+
+    if affected == 0 {
+        return Err(AppError::NotFound("Portfolio template not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
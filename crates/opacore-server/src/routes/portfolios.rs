@@ -6,9 +6,15 @@ use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access, Role};
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::branding::{self, Branding};
+use crate::services::costbasis::CostBasisMethod;
+use crate::services::portfolio_template::{self, PortfolioTemplate};
+use crate::services::prices;
+use crate::services::quotas::{self, Quota};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Portfolio {
@@ -16,6 +22,17 @@ pub struct Portfolio {
     pub user_id: String,
     pub name: String,
     pub description: Option<String>,
+    /// Hours until new one-time invoices expire when they don't set `expires_at`.
+    pub invoice_expiry_hours: Option<i64>,
+    /// The organization it is shared with, whose members get their role on it.
+    pub organization_id: Option<String>,
+    /// Used by the analysis and tax endpoints when a request doesn't name a method.
+    pub cost_basis_method: CostBasisMethod,
+    /// Lowercase ISO 4217 code its cost basis, gains and values are reported in.
+    pub base_currency: String,
+    /// The requesting user's role on it: "owner" for its creator, otherwise their role
+    /// in the organization.
+    pub role: String,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -24,32 +41,125 @@ pub struct Portfolio {
 pub struct CreatePortfolioRequest {
     pub name: String,
     pub description: Option<String>,
+    pub invoice_expiry_hours: Option<i64>,
+    pub cost_basis_method: Option<CostBasisMethod>,
+    pub base_currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClonePortfolioRequest {
+    /// Name of the new portfolio or template; defaults to "<name> (copy)".
+    pub name: Option<String>,
+    /// Overrides the source portfolio's description.
+    pub description: Option<String>,
+    /// Save the structure to the template library instead of creating a portfolio.
+    #[serde(default)]
+    pub as_template: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFromTemplateRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CloneResponse {
+    Portfolio(Portfolio),
+    Template(PortfolioTemplate),
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UpdatePortfolioRequest {
     pub name: Option<String>,
     pub description: Option<String>,
+    /// 0 clears it, leaving new invoices without an expiry.
+    pub invoice_expiry_hours: Option<i64>,
+    pub cost_basis_method: Option<CostBasisMethod>,
+    pub base_currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetOrganizationRequest {
+    /// `None` stops sharing the portfolio.
+    pub organization_id: Option<String>,
+}
+
+/// Fields left out are kept; an empty string clears one.
+#[derive(Debug, Deserialize)]
+pub struct UpdateBrandingRequest {
+    pub business_name: Option<String>,
+    pub logo_url: Option<String>,
+    pub accent_color: Option<String>,
+    pub payment_instructions: Option<String>,
+}
+
+/// Longest default invoice expiry, a year.
+const MAX_INVOICE_EXPIRY_HOURS: i64 = 24 * 365;
+
+/// Selects a portfolio with the role of the user bound to `?1`.
+const PORTFOLIO_COLS: &str = "id, user_id, name, description, invoice_expiry_hours, organization_id, created_at, updated_at,
+    cost_basis_method, base_currency,
+    CASE WHEN user_id = ?1 THEN 'owner' ELSE (SELECT m.role FROM organization_members m
+        WHERE m.organization_id = portfolios.organization_id AND m.user_id = ?1) END";
+
+fn row_to_portfolio(row: &rusqlite::Row) -> rusqlite::Result<Portfolio> {
+    Ok(Portfolio {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        description: row.get(3)?,
+        invoice_expiry_hours: row.get(4)?,
+        organization_id: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+        cost_basis_method: CostBasisMethod::from_name(&row.get::<_, String>(8)?).unwrap_or_default(),
+        base_currency: row.get(9)?,
+        role: row.get(10)?,
+    })
+}
+
+fn load_portfolio(conn: &rusqlite::Connection, user_id: &str, id: &str) -> AppResult<Portfolio> {
+    conn.query_row(
+        &format!("SELECT {PORTFOLIO_COLS} FROM portfolios WHERE id = ?2"),
+        rusqlite::params![user_id, id],
+        row_to_portfolio,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Portfolio not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+fn validate_currency(currency: &str) -> AppResult<String> {
+    prices::currency_code(currency)
+        .ok_or_else(|| AppError::BadRequest("base_currency must be a 3-letter currency code".into()))
 }
 
+fn validate_invoice_expiry_hours(hours: i64) -> AppResult<()> {
+    if !(1..=MAX_INVOICE_EXPIRY_HOURS).contains(&hours) {
+        return Err(AppError::BadRequest(format!(
+            "invoice_expiry_hours must be between 1 and {MAX_INVOICE_EXPIRY_HOURS}"
+        )));
+    }
+    Ok(())
+}
+
+/// GET /api/v1/portfolios
+///
+/// The user's own portfolios and those shared with their organizations.
 pub async fn list(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
 ) -> AppResult<Json<Vec<Portfolio>>> {
     let conn = state.db.get()?;
-    let mut stmt = conn.prepare(
-        "SELECT id, user_id, name, description, created_at, updated_at FROM portfolios WHERE user_id = ?1 ORDER BY created_at DESC",
-    )?;
-    let rows = stmt.query_map(rusqlite::params![user.id], |row| {
-        Ok(Portfolio {
-            id: row.get(0)?,
-            user_id: row.get(1)?,
-            name: row.get(2)?,
-            description: row.get(3)?,
-            created_at: row.get(4)?,
-            updated_at: row.get(5)?,
-        })
-    })?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {PORTFOLIO_COLS} FROM portfolios
+         WHERE user_id = ?1 OR organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = ?1)
+         ORDER BY created_at DESC"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], row_to_portfolio)?;
     let portfolios: Result<Vec<_>, _> = rows.collect();
     Ok(Json(portfolios?))
 }
@@ -60,26 +170,8 @@ pub async fn get(
     Path(id): Path<String>,
 ) -> AppResult<Json<Portfolio>> {
     let conn = state.db.get()?;
-    let portfolio = conn
-        .query_row(
-            "SELECT id, user_id, name, description, created_at, updated_at FROM portfolios WHERE id = ?1 AND user_id = ?2",
-            rusqlite::params![id, user.id],
-            |row| {
-                Ok(Portfolio {
-                    id: row.get(0)?,
-                    user_id: row.get(1)?,
-                    name: row.get(2)?,
-                    description: row.get(3)?,
-                    created_at: row.get(4)?,
-                    updated_at: row.get(5)?,
-                })
-            },
-        )
-        .map_err(|e| match e {
-            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Portfolio not found".into()),
-            e => AppError::Database(e),
-        })?;
-    Ok(Json(portfolio))
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Read)?;
+    Ok(Json(load_portfolio(&conn, &user.id, &id)?))
 }
 
 pub async fn create(
@@ -90,14 +182,27 @@ pub async fn create(
     if body.name.is_empty() {
         return Err(AppError::BadRequest("Name is required".into()));
     }
+    if let Some(hours) = body.invoice_expiry_hours {
+        validate_invoice_expiry_hours(hours)?;
+    }
+    let cost_basis_method = body.cost_basis_method.unwrap_or_default();
+    let base_currency = match &body.base_currency {
+        Some(currency) => validate_currency(currency)?,
+        None => "usd".to_string(),
+    };
 
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     let conn = state.db.get()?;
+    quotas::check(&conn, &state.config, &user.id, Quota::Portfolios)?;
 
     conn.execute(
-        "INSERT INTO portfolios (id, user_id, name, description, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        rusqlite::params![id, user.id, body.name, body.description, now, now],
+        "INSERT INTO portfolios (id, user_id, name, description, invoice_expiry_hours, cost_basis_method, base_currency, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            id, user.id, body.name, body.description, body.invoice_expiry_hours,
+            cost_basis_method.name(), base_currency, now, now
+        ],
     )?;
 
     let portfolio = Portfolio {
@@ -105,6 +210,11 @@ pub async fn create(
         user_id: user.id,
         name: body.name,
         description: body.description,
+        invoice_expiry_hours: body.invoice_expiry_hours,
+        organization_id: None,
+        cost_basis_method,
+        base_currency,
+        role: Role::Owner.name().to_string(),
         created_at: now.clone(),
         updated_at: now,
     };
@@ -119,44 +229,42 @@ pub async fn update(
     Json(body): Json<UpdatePortfolioRequest>,
 ) -> AppResult<Json<Portfolio>> {
     let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Manage)?;
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
 
     // Fetch existing
-    let existing = conn
-        .query_row(
-            "SELECT id, user_id, name, description, created_at, updated_at FROM portfolios WHERE id = ?1 AND user_id = ?2",
-            rusqlite::params![id, user.id],
-            |row| {
-                Ok(Portfolio {
-                    id: row.get(0)?,
-                    user_id: row.get(1)?,
-                    name: row.get(2)?,
-                    description: row.get(3)?,
-                    created_at: row.get(4)?,
-                    updated_at: row.get(5)?,
-                })
-            },
-        )
-        .map_err(|e| match e {
-            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Portfolio not found".into()),
-            e => AppError::Database(e),
-        })?;
+    let existing = load_portfolio(&conn, &user.id, &id)?;
 
     let name = body.name.unwrap_or(existing.name);
     let description = body.description.or(existing.description);
+    let invoice_expiry_hours = match body.invoice_expiry_hours {
+        Some(0) => None,
+        Some(hours) => {
+            validate_invoice_expiry_hours(hours)?;
+            Some(hours)
+        }
+        None => existing.invoice_expiry_hours,
+    };
+    let cost_basis_method = body.cost_basis_method.unwrap_or(existing.cost_basis_method);
+    let base_currency = match &body.base_currency {
+        Some(currency) => validate_currency(currency)?,
+        None => existing.base_currency,
+    };
 
     conn.execute(
-        "UPDATE portfolios SET name = ?1, description = ?2, updated_at = ?3 WHERE id = ?4",
-        rusqlite::params![name, description, now, id],
+        "UPDATE portfolios SET name = ?1, description = ?2, invoice_expiry_hours = ?3, cost_basis_method = ?4, base_currency = ?5, updated_at = ?6
+         WHERE id = ?7",
+        rusqlite::params![name, description, invoice_expiry_hours, cost_basis_method.name(), base_currency, now, id],
     )?;
 
     Ok(Json(Portfolio {
-        id,
-        user_id: user.id,
         name,
         description,
-        created_at: existing.created_at,
+        invoice_expiry_hours,
+        cost_basis_method,
+        base_currency,
         updated_at: now,
+        ..existing
     }))
 }
 
@@ -166,14 +274,210 @@ pub async fn delete(
     Path(id): Path<String>,
 ) -> AppResult<StatusCode> {
     let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Manage)?;
+    if policy::portfolio_role_of(&state.cache, &conn, &user.id, &id)? != Role::Owner {
+        return Err(AppError::Forbidden("Only owners can delete a portfolio".into()));
+    }
     let affected = conn.execute(
-        "DELETE FROM portfolios WHERE id = ?1 AND user_id = ?2",
-        rusqlite::params![id, user.id],
+        "DELETE FROM portfolios WHERE id = ?1",
+        rusqlite::params![id],
     )?;
 
     if affected == 0 {
         return Err(AppError::NotFound("Portfolio not found".into()));
     }
+    state.cache.invalidate_portfolio(&id);
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// PUT /api/v1/portfolios/{id}/organization
+///
+/// Share the portfolio with an organization in place of any other, or stop sharing it.
+/// Only its creator can, and only with an organization they administer.
+pub async fn set_organization(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Json(body): Json<SetOrganizationRequest>,
+) -> AppResult<Json<Portfolio>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Read)?;
+    if load_portfolio(&conn, &user.id, &id)?.user_id != user.id {
+        return Err(AppError::Forbidden(
+            "Only the portfolio's creator can change who it is shared with".into(),
+        ));
+    }
+    if let Some(organization_id) = &body.organization_id {
+        policy::organization(&state.cache, &conn, &user.id, organization_id, Role::Admin)?;
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE portfolios SET organization_id = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![body.organization_id, now, id],
+    )?;
+    state.cache.invalidate_portfolio(&id);
+    tracing::info!("User {} shared portfolio {id} with organization {:?}", user.id, body.organization_id);
+
+    Ok(Json(load_portfolio(&conn, &user.id, &id)?))
+}
+
+/// POST /api/v1/portfolios/{id}/clone
+///
+/// Copy a portfolio's settings, invoice branding, wallets (without their history) and
+/// the labels its transactions use, either into a new portfolio or into the template
+/// library. Only its managers may: the copy hands over the wallets' descriptors.
+pub async fn clone(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Json(body): Json<ClonePortfolioRequest>,
+) -> AppResult<(StatusCode, Json<CloneResponse>)> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Manage)?;
+
+    let source_name: String = conn.query_row(
+        "SELECT name FROM portfolios WHERE id = ?1",
+        rusqlite::params![id],
+        |row| row.get(0),
+    )?;
+    let name = match body.name.map(|n| n.trim().to_string()) {
+        Some(n) if n.is_empty() => return Err(AppError::BadRequest("Name is required".into())),
+        Some(n) => n,
+        None => format!("{source_name} (copy)"),
+    };
+    let mut structure = portfolio_template::snapshot(&conn, &id)?;
+    if body.description.is_some() {
+        structure.description = body.description;
+    }
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+
+    if body.as_template {
+        let template_id = Uuid::new_v4().to_string();
+        let json = serde_json::to_string(&structure)
+            .map_err(|e| AppError::Internal(format!("Failed to encode template: {e}")))?;
+        let result = conn.execute(
+            "INSERT INTO portfolio_templates (id, user_id, name, structure, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![template_id, user.id, name, json, now, now],
+        );
+        match result {
+            Ok(_) => {}
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                return Err(AppError::Conflict("Template with this name already exists".into()));
+            }
+            Err(e) => return Err(AppError::Database(e)),
+        }
+        return Ok((StatusCode::CREATED, Json(CloneResponse::Template(PortfolioTemplate {
+            id: template_id,
+            user_id: user.id,
+            name,
+            structure,
+            created_at: now.clone(),
+            updated_at: now,
+        }))));
+    }
+
+    let portfolio_id = Uuid::new_v4().to_string();
+    let tx = conn.transaction()?;
+    portfolio_template::create_portfolio(&tx, &state.config, &user.id, &portfolio_id, &name, &structure, &now)?;
+    tx.commit()?;
+
+    Ok((StatusCode::CREATED, Json(CloneResponse::Portfolio(load_portfolio(&conn, &user.id, &portfolio_id)?))))
+}
+
+/// POST /api/v1/portfolio-templates/{id}/portfolios
+pub async fn create_from_template(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(template_id): Path<String>,
+    Json(body): Json<CreateFromTemplateRequest>,
+) -> AppResult<(StatusCode, Json<Portfolio>)> {
+    let name = body.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Name is required".into()));
+    }
+
+    let mut conn = state.db.get()?;
+    let mut structure = portfolio_template::load_template(&conn, &user.id, &template_id)?.structure;
+    if body.description.is_some() {
+        structure.description = body.description;
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let tx = conn.transaction()?;
+    portfolio_template::create_portfolio(&tx, &state.config, &user.id, &id, &name, &structure, &now)?;
+    tx.commit()?;
+
+    Ok((StatusCode::CREATED, Json(load_portfolio(&conn, &user.id, &id)?)))
+}
+
+/// GET /api/v1/portfolios/{id}/branding
+pub async fn get_branding(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Branding>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Read)?;
+    Ok(Json(branding::load(&conn, &id)?))
+}
+
+/// PUT /api/v1/portfolios/{id}/branding
+///
+/// Set how the portfolio's invoices present the merchant to customers.
+pub async fn update_branding(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateBrandingRequest>,
+) -> AppResult<Json<Branding>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &id, Access::Manage)?;
+
+    // Empty clears; anything else is trimmed
+    let field = |value: Option<String>, existing: Option<String>| match value {
+        Some(v) if v.trim().is_empty() => None,
+        Some(v) => Some(v.trim().to_string()),
+        None => existing,
+    };
+    let existing = branding::load(&conn, &id)?;
+    let updated = Branding {
+        business_name: field(body.business_name, existing.business_name),
+        logo_url: field(body.logo_url, existing.logo_url),
+        accent_color: field(body.accent_color, existing.accent_color).map(|c| c.to_ascii_lowercase()),
+        payment_instructions: field(body.payment_instructions, existing.payment_instructions),
+    };
+    if let Some(name) = updated.business_name.as_deref() {
+        branding::validate_text("business_name", name, branding::BUSINESS_NAME_MAX_CHARS)?;
+    }
+    if let Some(url) = updated.logo_url.as_deref() {
+        branding::validate_logo_url(url)?;
+    }
+    if updated.accent_color.as_deref().is_some_and(|c| branding::parse_color(c).is_none()) {
+        return Err(AppError::BadRequest("accent_color must be a hex color like #1a73e8".into()));
+    }
+    if let Some(instructions) = updated.payment_instructions.as_deref() {
+        branding::validate_text("payment_instructions", instructions, branding::PAYMENT_INSTRUCTIONS_MAX_CHARS)?;
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO portfolio_branding (portfolio_id, business_name, logo_url, accent_color, payment_instructions, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(portfolio_id) DO UPDATE SET business_name = ?2, logo_url = ?3, accent_color = ?4,
+             payment_instructions = ?5, updated_at = ?6",
+        rusqlite::params![
+            id,
+            updated.business_name,
+            updated.logo_url,
+            updated.accent_color,
+            updated.payment_instructions,
+            now
+        ],
+    )?;
+    Ok(Json(updated))
+}
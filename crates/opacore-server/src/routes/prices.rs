@@ -1,13 +1,16 @@
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     Extension, Json,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::auth::policy::{self, Access};
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::jobs::{self, JobKind, JobParams};
 use crate::services::prices;
 
 #[derive(Debug, Deserialize)]
@@ -31,6 +34,10 @@ pub struct PriceRangeQuery {
 #[derive(Debug, Deserialize)]
 pub struct BackfillQuery {
     pub currency: Option<String>,
+    /// Run as a background job and email the user when done, instead of holding the
+    /// request open until every price is fetched.
+    #[serde(default)]
+    pub background: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -52,7 +59,7 @@ pub async fn current(
 ) -> AppResult<Json<CurrentPriceResponse>> {
     let currency = query.currency.as_deref().unwrap_or("usd");
 
-    let price = prices::fetch_current_price(&state.config.coingecko_api_url, currency).await?;
+    let price = prices::current_price(&state.db, &state.cache, &state.config.coingecko_api_url, currency).await?;
 
     Ok(Json(CurrentPriceResponse {
         currency: currency.to_string(),
@@ -121,17 +128,9 @@ pub async fn backfill_portfolio(
     Extension(user): Extension<User>,
     Path(portfolio_id): Path<String>,
 ) -> AppResult<StatusCode> {
-    let exists: bool = {
+    {
         let conn = state.db.get()?;
-        conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-            rusqlite::params![portfolio_id, user.id],
-            |row| row.get(0),
-        )?
-    };
-
-    if !exists {
-        return Err(AppError::NotFound("Portfolio not found".into()));
+        policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
     }
 
     let pool = state.db.clone();
@@ -144,11 +143,23 @@ pub async fn backfill_portfolio(
 }
 
 /// POST /api/v1/prices/backfill
+///
+/// With `"background": true`, returns 202 with a job to poll at `/api/v1/jobs/{id}`.
 pub async fn backfill(
     State(state): State<AppState>,
-    Extension(_user): Extension<User>,
+    Extension(user): Extension<User>,
     Json(body): Json<BackfillQuery>,
-) -> AppResult<Json<BackfillResponse>> {
+) -> AppResult<Response> {
+    if body.background {
+        let job = {
+            let conn = state.db.get()?;
+            jobs::start_job(&conn, &user.id, JobKind::PriceBackfill)?
+        };
+        let params = JobParams { currency: body.currency };
+        jobs::spawn(state.db.clone(), state.config.clone(), job.clone(), JobKind::PriceBackfill, params);
+        return Ok((StatusCode::ACCEPTED, Json(job)).into_response());
+    }
+
     let currency = body.currency.as_deref().unwrap_or("usd");
 
     let fetched = prices::backfill_transaction_prices(
@@ -158,5 +169,5 @@ pub async fn backfill(
     )
     .await?;
 
-    Ok(Json(BackfillResponse { fetched }))
+    Ok(Json(BackfillResponse { fetched }).into_response())
 }
@@ -0,0 +1,271 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::{costbasis, import};
+use crate::services::recurring::{self, Frequency};
+
+/// Most schedules one portfolio can have.
+const MAX_PER_PORTFOLIO: i64 = 50;
+
+#[derive(Debug, Serialize)]
+pub struct RecurringTransaction {
+    pub id: String,
+    pub portfolio_id: String,
+    pub wallet_id: Option<String>,
+    pub user_id: Option<String>,
+    pub tx_type: String,
+    pub amount_sat: Option<i64>,
+    pub fiat_amount: Option<f64>,
+    pub fiat_currency: String,
+    pub frequency: String,
+    pub starts_at: String,
+    pub ends_at: Option<String>,
+    /// Transactions created so far.
+    pub occurrences: i64,
+    /// `None` once the schedule has ended.
+    pub next_run_at: Option<String>,
+    pub is_active: bool,
+    /// Why the last occurrence couldn't be created, e.g. no price yet for a fixed spend.
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRecurringRequest {
+    pub wallet_id: Option<String>,
+    pub tx_type: String,
+    /// A fixed amount each time, or
+    pub amount_sat: Option<i64>,
+    /// a fixed spend converted at each occurrence's price (dollar-cost averaging).
+    pub fiat_amount: Option<f64>,
+    pub fiat_currency: Option<String>,
+    pub frequency: Frequency,
+    /// First occurrence (RFC 3339); later ones keep its time of day. May be in the past,
+    /// in which case the missed occurrences are created.
+    pub starts_at: String,
+    /// Last possible occurrence: a date (inclusive) or RFC 3339 timestamp.
+    pub ends_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRecurringRequest {
+    pub tx_type: Option<String>,
+    /// Setting one amount replaces the other.
+    pub amount_sat: Option<i64>,
+    pub fiat_amount: Option<f64>,
+    pub ends_at: Option<String>,
+    /// Resuming skips the occurrences missed while paused.
+    pub is_active: Option<bool>,
+}
+
+const RECURRING_COLS: &str = "id, portfolio_id, wallet_id, user_id, tx_type, amount_sat, fiat_amount, fiat_currency, frequency, starts_at, ends_at, occurrences, next_run_at, is_active, last_error, created_at, updated_at";
+
+fn row_to_recurring(row: &rusqlite::Row) -> rusqlite::Result<RecurringTransaction> {
+    Ok(RecurringTransaction {
+        id: row.get(0)?,
+        portfolio_id: row.get(1)?,
+        wallet_id: row.get(2)?,
+        user_id: row.get(3)?,
+        tx_type: row.get(4)?,
+        amount_sat: row.get(5)?,
+        fiat_amount: row.get(6)?,
+        fiat_currency: row.get(7)?,
+        frequency: row.get(8)?,
+        starts_at: row.get(9)?,
+        ends_at: row.get(10)?,
+        occurrences: row.get(11)?,
+        next_run_at: row.get(12)?,
+        is_active: row.get::<_, i32>(13).map(|v| v != 0)?,
+        last_error: row.get(14)?,
+        created_at: row.get(15)?,
+        updated_at: row.get(16)?,
+    })
+}
+
+fn load(conn: &rusqlite::Connection, portfolio_id: &str, recurring_id: &str) -> AppResult<RecurringTransaction> {
+    conn.query_row(
+        &format!("SELECT {RECURRING_COLS} FROM recurring_transactions WHERE id = ?1 AND portfolio_id = ?2"),
+        rusqlite::params![recurring_id, portfolio_id],
+        row_to_recurring,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Recurring transaction not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Exactly one of a fixed amount and a fixed spend, and positive.
+fn validate_amount(amount_sat: Option<i64>, fiat_amount: Option<f64>) -> AppResult<()> {
+    match (amount_sat, fiat_amount) {
+        (Some(sat), None) if sat > 0 => Ok(()),
+        (None, Some(fiat)) if fiat > 0.0 && fiat.is_finite() => Ok(()),
+        (Some(_), Some(_)) | (None, None) => {
+            Err(AppError::BadRequest("Give exactly one of amount_sat and fiat_amount".into()))
+        }
+        _ => Err(AppError::BadRequest("The amount must be positive".into())),
+    }
+}
+
+fn parse_timestamp(value: &str, name: &str) -> AppResult<String> {
+    import::parse_timestamp(value)
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid {name}: expected a date or timestamp, e.g. 2024-01-15T09:00:00Z")))
+}
+
+/// `ends_at` as a timestamp; a bare date covers that whole day.
+fn parse_ends_at(value: &str, starts_at: &str) -> AppResult<String> {
+    let ends_at = match chrono::NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+        Ok(date) => format!("{date}T23:59:59.999Z"),
+        Err(_) => parse_timestamp(value, "ends_at")?,
+    };
+    if ends_at.as_str() < starts_at {
+        return Err(AppError::BadRequest("ends_at must not be before starts_at".into()));
+    }
+    Ok(ends_at)
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/recurring
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<Vec<RecurringTransaction>>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {RECURRING_COLS} FROM recurring_transactions WHERE portfolio_id = ?1 ORDER BY created_at"
+    ))?;
+    let items = stmt
+        .query_map(rusqlite::params![portfolio_id], row_to_recurring)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(items))
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/recurring
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Json(body): Json<CreateRecurringRequest>,
+) -> AppResult<(StatusCode, Json<RecurringTransaction>)> {
+    costbasis::validate_tx_type(&body.tx_type)?;
+    validate_amount(body.amount_sat, body.fiat_amount)?;
+    let starts_at = parse_timestamp(&body.starts_at, "starts_at")?;
+    let ends_at = body.ends_at.as_deref().map(|e| parse_ends_at(e, &starts_at)).transpose()?;
+    let fiat_currency = body.fiat_currency.as_deref().unwrap_or("usd").trim().to_lowercase();
+
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    if let Some(wallet_id) = &body.wallet_id {
+        policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, wallet_id, Access::Write)?;
+    }
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM recurring_transactions WHERE portfolio_id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_PER_PORTFOLIO {
+        return Err(AppError::Conflict(format!(
+            "A portfolio can have at most {MAX_PER_PORTFOLIO} recurring transactions"
+        )));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let next_run_at = recurring::next_run_at(&starts_at, body.frequency, 0, ends_at.as_deref());
+    conn.execute(
+        "INSERT INTO recurring_transactions (id, portfolio_id, wallet_id, user_id, tx_type, amount_sat, fiat_amount, fiat_currency, frequency, starts_at, ends_at, next_run_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        rusqlite::params![
+            id, portfolio_id, body.wallet_id, user.id, body.tx_type,
+            body.amount_sat, body.fiat_amount, fiat_currency, body.frequency.as_str(),
+            starts_at, ends_at, next_run_at, now, now
+        ],
+    )?;
+
+    Ok((StatusCode::CREATED, Json(load(&conn, &portfolio_id, &id)?)))
+}
+
+/// PUT /api/v1/portfolios/{portfolio_id}/recurring/{recurring_id}
+///
+/// Changes apply to occurrences not yet created; past transactions are left alone.
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, recurring_id)): Path<(String, String)>,
+    Json(body): Json<UpdateRecurringRequest>,
+) -> AppResult<Json<RecurringTransaction>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    let existing = load(&conn, &portfolio_id, &recurring_id)?;
+
+    if let Some(tx_type) = &body.tx_type {
+        costbasis::validate_tx_type(tx_type)?;
+    }
+    let (amount_sat, fiat_amount) = match (body.amount_sat, body.fiat_amount) {
+        (None, None) => (existing.amount_sat, existing.fiat_amount),
+        given => given,
+    };
+    validate_amount(amount_sat, fiat_amount)?;
+    let ends_at = match &body.ends_at {
+        Some(ends_at) => Some(parse_ends_at(ends_at, &existing.starts_at)?),
+        None => existing.ends_at,
+    };
+    let frequency = Frequency::parse(&existing.frequency)
+        .ok_or_else(|| AppError::Internal(format!("Unknown frequency '{}'", existing.frequency)))?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let is_active = body.is_active.unwrap_or(existing.is_active);
+    let occurrences = if is_active && !existing.is_active {
+        recurring::skip_missed(&existing.starts_at, frequency, existing.occurrences, &now)
+    } else {
+        existing.occurrences
+    };
+    let next_run_at = recurring::next_run_at(&existing.starts_at, frequency, occurrences, ends_at.as_deref());
+
+    conn.execute(
+        "UPDATE recurring_transactions
+         SET tx_type = ?1, amount_sat = ?2, fiat_amount = ?3, ends_at = ?4, is_active = ?5,
+             occurrences = ?6, next_run_at = ?7, updated_at = ?8
+         WHERE id = ?9",
+        rusqlite::params![
+            body.tx_type.as_deref().unwrap_or(&existing.tx_type),
+            amount_sat, fiat_amount, ends_at, is_active as i32,
+            occurrences, next_run_at, now, recurring_id
+        ],
+    )?;
+
+    Ok(Json(load(&conn, &portfolio_id, &recurring_id)?))
+}
+
+/// DELETE /api/v1/portfolios/{portfolio_id}/recurring/{recurring_id}
+///
+/// Stops the schedule; transactions it already created stay.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, recurring_id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+
+    let affected = conn.execute(
+        "DELETE FROM recurring_transactions WHERE id = ?1 AND portfolio_id = ?2",
+        rusqlite::params![recurring_id, portfolio_id],
+    )?;
+    if affected == 0 {
+        return Err(AppError::NotFound("Recurring transaction not found".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
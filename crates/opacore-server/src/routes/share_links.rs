@@ -0,0 +1,357 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::auth::api_keys;
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::costbasis::{self, InCurrency, PortfolioSummary};
+use crate::services::pagination::{self, Cursor};
+use crate::services::{prices, tax};
+
+/// Every token starts with this, so a leaked link is easy to recognize.
+const TOKEN_PREFIX: &str = "ops_";
+/// Characters of a token kept in the clear to tell links apart.
+const DISPLAY_PREFIX_CHARS: usize = 12;
+/// Most links one portfolio can have at once.
+const MAX_LINKS: i64 = 25;
+const MAX_NAME_CHARS: usize = 100;
+const DEFAULT_EXPIRY_DAYS: i64 = 30;
+const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(Debug, Serialize)]
+pub struct ShareLink {
+    pub id: String,
+    pub portfolio_id: String,
+    pub name: String,
+    /// Start of the token, e.g. `ops_AbC123xy`.
+    pub prefix: String,
+    pub expires_at: String,
+    pub view_count: i64,
+    pub last_viewed_at: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: String,
+}
+
+/// A link as returned once, at creation: the only time its token is shown.
+#[derive(Debug, Serialize)]
+pub struct CreatedShareLink {
+    #[serde(flatten)]
+    pub link: ShareLink,
+    pub token: String,
+    /// Page in the web app showing the shared portfolio.
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareLinkRequest {
+    pub name: String,
+    /// 1 to 365; defaults to 30.
+    pub expires_in_days: Option<i64>,
+}
+
+/// What a share link shows: no wallet descriptors, xpubs or addresses.
+#[derive(Debug, Serialize)]
+pub struct SharedPortfolio {
+    pub name: String,
+    pub description: Option<String>,
+    pub wallets: Vec<SharedWallet>,
+    pub summary: InCurrency<PortfolioSummary>,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedWallet {
+    pub id: String,
+    pub label: String,
+    pub wallet_type: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedTransaction {
+    pub id: String,
+    pub wallet_id: Option<String>,
+    pub tx_type: String,
+    pub amount_sat: i64,
+    pub fee_sat: Option<i64>,
+    pub price_usd: Option<f64>,
+    pub fiat_amount: Option<f64>,
+    pub fiat_currency: String,
+    pub txid: Option<String>,
+    pub block_height: Option<i64>,
+    pub transacted_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedTransactionList {
+    pub data: Vec<SharedTransaction>,
+    pub total: i64,
+    /// Pass as `after` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharedTransactionsQuery {
+    pub limit: Option<i64>,
+    pub after: Option<String>,
+}
+
+const SHARE_LINK_COLS: &str =
+    "id, portfolio_id, name, prefix, expires_at, view_count, last_viewed_at, created_by, created_at";
+
+fn row_to_share_link(row: &rusqlite::Row) -> rusqlite::Result<ShareLink> {
+    Ok(ShareLink {
+        id: row.get(0)?,
+        portfolio_id: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        expires_at: row.get(4)?,
+        view_count: row.get(5)?,
+        last_viewed_at: row.get(6)?,
+        created_by: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+/// Split parts count in place of the transaction they divide, so only one of the two
+/// is listed.
+const SHARED_TX_COLS: &str = "id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, block_height, transacted_at";
+const SHARED_TX_FILTER: &str =
+    "portfolio_id = ?1 AND NOT EXISTS (SELECT 1 FROM transactions part WHERE part.parent_id = transactions.id)";
+
+fn row_to_shared_transaction(row: &rusqlite::Row) -> rusqlite::Result<SharedTransaction> {
+    Ok(SharedTransaction {
+        id: row.get(0)?,
+        wallet_id: row.get(1)?,
+        tx_type: row.get(2)?,
+        amount_sat: row.get(3)?,
+        fee_sat: row.get(4)?,
+        price_usd: row.get(5)?,
+        fiat_amount: row.get(6)?,
+        fiat_currency: row.get(7)?,
+        txid: row.get(8)?,
+        block_height: row.get(9)?,
+        transacted_at: row.get(10)?,
+    })
+}
+
+/// A new random token, its display prefix and the hash that is stored.
+fn generate_token() -> (String, String, String) {
+    use base64::Engine;
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = format!("{TOKEN_PREFIX}{}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes));
+    let prefix = token[..DISPLAY_PREFIX_CHARS].to_string();
+    let hash = api_keys::hash(&token);
+    (token, prefix, hash)
+}
+
+/// The link a token belongs to, if it hasn't expired or been revoked.
+fn live_link(conn: &rusqlite::Connection, token: &str) -> AppResult<ShareLink> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.query_row(
+        &format!("SELECT {SHARE_LINK_COLS} FROM portfolio_share_links WHERE token_hash = ?1 AND expires_at > ?2"),
+        rusqlite::params![api_keys::hash(token), now],
+        row_to_share_link,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Share link not found or expired".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// GET /api/v1/portfolios/{id}/share-links
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<Vec<ShareLink>>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Manage)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SHARE_LINK_COLS} FROM portfolio_share_links WHERE portfolio_id = ?1 ORDER BY created_at DESC"
+    ))?;
+    let links = stmt
+        .query_map(rusqlite::params![portfolio_id], row_to_share_link)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(links))
+}
+
+/// POST /api/v1/portfolios/{id}/share-links
+///
+/// Create a link anyone can open, without signing in, to see the portfolio's summary
+/// and transactions until it expires or is revoked.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Json(body): Json<CreateShareLinkRequest>,
+) -> AppResult<impl IntoResponse> {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(AppError::BadRequest(format!("name must be 1 to {MAX_NAME_CHARS} characters")));
+    }
+    let days = body.expires_in_days.unwrap_or(DEFAULT_EXPIRY_DAYS);
+    if !(1..=MAX_EXPIRY_DAYS).contains(&days) {
+        return Err(AppError::BadRequest(format!("expires_in_days must be from 1 to {MAX_EXPIRY_DAYS}")));
+    }
+    let expires_at = (chrono::Utc::now() + chrono::Duration::days(days))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Manage)?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM portfolio_share_links WHERE portfolio_id = ?1 AND expires_at > ?2",
+        rusqlite::params![portfolio_id, now],
+        |row| row.get(0),
+    )?;
+    if count >= MAX_LINKS {
+        return Err(AppError::Forbidden(format!("A portfolio can have at most {MAX_LINKS} share links")));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let (token, prefix, token_hash) = generate_token();
+    let link = conn.query_row(
+        &format!(
+            "INSERT INTO portfolio_share_links (id, portfolio_id, created_by, name, prefix, token_hash, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING {SHARE_LINK_COLS}"
+        ),
+        rusqlite::params![id, portfolio_id, user.id, name, prefix, token_hash, expires_at],
+        row_to_share_link,
+    )?;
+    tracing::info!("User {} created share link {id} for portfolio {portfolio_id}", user.id);
+
+    let url = format!("{}/shared/{token}", state.config.app_url);
+    Ok((StatusCode::CREATED, Json(CreatedShareLink { link, token, url })))
+}
+
+/// DELETE /api/v1/portfolios/{id}/share-links/{link_id}
+///
+/// Revoke a link; it stops working at once.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, link_id)): Path<(String, String)>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Manage)?;
+    let deleted = conn.execute(
+        "DELETE FROM portfolio_share_links WHERE id = ?1 AND portfolio_id = ?2",
+        rusqlite::params![link_id, portfolio_id],
+    )?;
+    if deleted == 0 {
+        return Err(AppError::NotFound("Share link not found".into()));
+    }
+    tracing::info!("User {} revoked share link {link_id}", user.id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/shared/{token} — Public endpoint (no auth)
+///
+/// Each load counts as a view.
+pub async fn public_get(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> AppResult<Json<SharedPortfolio>> {
+    let (link, name, description, wallets, rules, defaults) = {
+        let conn = state.db.get()?;
+        let link = live_link(&conn, &token)?;
+        let (owner, name, description): (String, String, Option<String>) = conn.query_row(
+            "SELECT user_id, name, description FROM portfolios WHERE id = ?1",
+            rusqlite::params![link.portfolio_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let wallets = conn
+            .prepare("SELECT id, label, wallet_type FROM wallets WHERE portfolio_id = ?1 ORDER BY created_at")?
+            .query_map(rusqlite::params![link.portfolio_id], |row| {
+                Ok(SharedWallet {
+                    id: row.get(0)?,
+                    label: row.get(1)?,
+                    wallet_type: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        conn.execute(
+            "UPDATE portfolio_share_links SET view_count = view_count + 1, last_viewed_at = ?1 WHERE id = ?2",
+            rusqlite::params![now, link.id],
+        )?;
+        let defaults = costbasis::portfolio_defaults(&conn, &link.portfolio_id)?;
+        (link, name, description, wallets, tax::tax_rules(&conn, &owner)?, defaults)
+    };
+
+    // Same fallback as the owner's own summary when the live price can't be had
+    let current_price = prices::current_price(&state.db, &state.cache, &state.config.coingecko_api_url, &defaults.currency)
+        .await
+        .unwrap_or_else(|_| prices::get_latest_cached_price(&state.db, &defaults.currency).unwrap_or(0.0));
+    let summary = costbasis::portfolio_summary(
+        &state.db,
+        Some(&state.cache),
+        &link.portfolio_id,
+        current_price,
+        defaults.method,
+        &defaults.currency,
+        &rules,
+    )?;
+    let summary = InCurrency { currency: defaults.currency, value: summary };
+
+    Ok(Json(SharedPortfolio {
+        name,
+        description,
+        wallets,
+        summary,
+        expires_at: link.expires_at,
+    }))
+}
+
+/// GET /api/v1/shared/{token}/transactions?limit=50&after= — Public endpoint (no auth)
+///
+/// Newest first.
+pub async fn public_transactions(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Query(query): Query<SharedTransactionsQuery>,
+) -> AppResult<Json<SharedTransactionList>> {
+    let conn = state.db.get()?;
+    let link = live_link(&conn, &token)?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM transactions WHERE {SHARED_TX_FILTER}"),
+        rusqlite::params![link.portfolio_id],
+        |row| row.get(0),
+    )?;
+
+    let mut params: Vec<rusqlite::types::Value> = vec![link.portfolio_id.into()];
+    let mut where_clause = SHARED_TX_FILTER.to_string();
+    if let Some(after) = &query.after {
+        where_clause.push_str(&Cursor::decode(after)?.condition("transacted_at", "id", true, &mut params));
+    }
+    params.push((limit + 1).into());
+    let limit_idx = params.len();
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SHARED_TX_COLS} FROM transactions WHERE {where_clause}
+         ORDER BY transacted_at DESC, id DESC LIMIT ?{limit_idx}"
+    ))?;
+    let mut data = stmt
+        .query_map(rusqlite::params_from_iter(params.iter()), row_to_shared_transaction)?
+        .collect::<Result<Vec<_>, _>>()?;
+    let next_cursor = pagination::next_cursor(&mut data, limit, |tx| Cursor {
+        key: tx.transacted_at.clone().into(),
+        id: tx.id.clone(),
+    });
+
+    Ok(Json(SharedTransactionList { data, total, next_cursor }))
+}
@@ -1,13 +1,21 @@
+use std::convert::Infallible;
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
     Extension, Json,
 };
+use futures::Stream;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 
+use crate::auth::policy::{self, Access};
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
-use crate::services::{prices, sync, wallet as wallet_svc};
+use crate::services::events::{self, DomainEvent};
+use crate::services::sync_progress::SyncEvent;
+use crate::services::{sync, utxos as utxo_svc, wallet as wallet_svc};
 
 #[derive(Debug, Deserialize)]
 pub struct SyncRequest {
@@ -20,11 +28,52 @@ pub struct SyncResponse {
     pub new_transactions: usize,
     pub balance_sat: u64,
     pub last_sync_height: Option<u32>,
+    /// Esplora instance that served this sync.
+    pub backend: String,
+    pub job_id: String,
+    /// Previously unconfirmed transactions that confirmed, were replaced or were evicted.
+    pub mempool: sync::MempoolChanges,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncStatusResponse {
+    /// Most recent sync job, including its scan checkpoint; `None` if never synced.
+    pub job: Option<sync::SyncJob>,
+    /// Current chain tip for the wallet's network, if a backend could be reached.
+    pub chain_tip_height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressesQuery {
+    /// "external" (receive) or "internal" (change); both if omitted.
+    pub keychain: Option<String>,
+    pub used: Option<bool>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct AddressesResponse {
     pub addresses: Vec<wallet_svc::AddressInfo>,
+    /// Matching addresses across all pages.
+    pub total: i64,
+    /// When the stats were last refreshed by a sync.
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PsbtRecipientRequest {
+    pub address: String,
+    pub amount_sat: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePsbtRequest {
+    pub recipients: Vec<PsbtRecipientRequest>,
+    /// sat/vB
+    pub fee_rate: f64,
+    /// Outpoints (`txid:vout`) to spend; coin selection picks from unfrozen UTXOs if omitted.
+    pub utxos: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,6 +82,57 @@ pub struct UtxosResponse {
     pub total_sat: u64,
 }
 
+/// Wallet fields needed to rebuild descriptors and pick a sync strategy.
+struct WalletRecord {
+    descriptor: Option<String>,
+    xpub: Option<String>,
+    derivation_path: Option<String>,
+    address: Option<String>,
+    network: String,
+    wallet_type: String,
+    gap_limit: i64,
+}
+
+fn load_wallet_record(conn: &rusqlite::Connection, wallet_id: &str) -> AppResult<WalletRecord> {
+    Ok(conn.query_row(
+        "SELECT descriptor, xpub, derivation_path, address, network, wallet_type, gap_limit FROM wallets WHERE id = ?1",
+        rusqlite::params![wallet_id],
+        |row| {
+            Ok(WalletRecord {
+                descriptor: row.get(0)?,
+                xpub: row.get(1)?,
+                derivation_path: row.get(2)?,
+                address: row.get(3)?,
+                network: row.get(4)?,
+                wallet_type: row.get(5)?,
+                gap_limit: row.get(6)?,
+            })
+        },
+    )?)
+}
+
+fn publish_started(progress: &broadcast::Sender<SyncEvent>, job: &sync::SyncJob) {
+    let _ = progress.send(SyncEvent::Started {
+        job_id: job.id.clone(),
+        keychain: job.keychain.clone(),
+        next_index: job.next_index,
+    });
+}
+
+/// Record a failed sync on its job and progress stream, handing the error back.
+fn fail_sync(
+    conn: &rusqlite::Connection,
+    progress: &broadcast::Sender<SyncEvent>,
+    job_id: &str,
+    err: AppError,
+) -> AppError {
+    if let Err(e) = sync::fail_sync_job(conn, job_id, &err) {
+        tracing::error!("Failed to record sync job {job_id} failure: {e}");
+    }
+    let _ = progress.send(SyncEvent::Failed { error: err.to_string() });
+    err
+}
+
 /// POST /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/sync
 pub async fn sync_wallet(
     State(state): State<AppState>,
@@ -42,44 +142,52 @@ pub async fn sync_wallet(
 ) -> AppResult<Json<SyncResponse>> {
     let conn = state.db.get()?;
 
-    // Verify ownership
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE w.id = ?1 AND p.user_id = ?2)",
-        rusqlite::params![wallet_id, user.id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Wallet not found".into()));
-    }
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Write)?;
+
+    // One sync per wallet at a time — held until this handler returns or is dropped
+    let _sync_guard = state.sync_locks.try_acquire(&wallet_id).ok_or_else(|| {
+        AppError::Conflict("A sync is already running for this wallet".into())
+    })?;
 
     // Get wallet details from app DB
-    let (descriptor, xpub, derivation_path, address, network_str, wallet_type, gap_limit_db): (
-        Option<String>, Option<String>, Option<String>, Option<String>, String, String, i64,
-    ) = conn.query_row(
-        "SELECT descriptor, xpub, derivation_path, address, network, wallet_type, gap_limit FROM wallets WHERE id = ?1",
-        rusqlite::params![wallet_id],
-        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)),
-    )?;
+    let WalletRecord {
+        descriptor, xpub, derivation_path, address,
+        network: network_str, wallet_type, gap_limit: gap_limit_db,
+    } = load_wallet_record(&conn, &wallet_id)?;
 
     let network = wallet_svc::parse_network(&network_str)?;
-
-    // Use correct Esplora URL based on network
-    let esplora_url = match network {
-        bdk_wallet::bitcoin::Network::Testnet => {
-            state.config.esplora_url.replace("/api", "/testnet/api")
-        }
-        bdk_wallet::bitcoin::Network::Signet => {
-            state.config.esplora_url.replace("/api", "/signet/api")
-        }
-        _ => state.config.esplora_url.clone(),
-    };
+    let progress = state.sync_progress.sender(&wallet_id);
 
     // For single address wallets, use direct Esplora API (BDK doesn't support addr() descriptors)
-    let result = if wallet_type == "address" {
+    let (result, backend, job_id) = if wallet_type == "address" {
         let addr = address.as_deref().ok_or_else(|| {
             AppError::BadRequest("Address wallet missing address field".into())
         })?;
-        sync::address_sync(&esplora_url, addr, &state.db, &wallet_id, &portfolio_id).await?
+        let job = sync::start_sync_job(&conn, &wallet_id, 0)?;
+        publish_started(&progress, &job);
+        let (db, wallet_id, portfolio_id) = (&state.db, &wallet_id, &portfolio_id);
+        let outcome = state
+            .esplora
+            .with_failover(network, |url| async move {
+                sync::address_sync(&url, addr, db, wallet_id, portfolio_id).await
+            })
+            .await;
+        let (result, backend) = match outcome {
+            Ok(v) => v,
+            Err(e) => return Err(fail_sync(&conn, &progress, &job.id, e)),
+        };
+        sync::complete_sync_job(&conn, &job.id, &result, &backend)?;
+
+        // Address stats are a second request; a failure here shouldn't fail the sync
+        match state
+            .esplora
+            .with_failover(network, |url| async move { sync::address_stats(&url, addr).await })
+            .await
+        {
+            Ok((info, _)) => wallet_svc::persist_addresses(&conn, wallet_id, &[info])?,
+            Err(e) => tracing::warn!("Address stats for wallet {wallet_id} not refreshed: {e}"),
+        }
+        (result, backend, job.id)
     } else {
         // Build descriptors for xpub/descriptor wallets
         let (external_desc, internal_desc) = wallet_svc::build_descriptors(
@@ -89,7 +197,7 @@ pub async fn sync_wallet(
             address.as_deref(),
         )?;
 
-        let gap_limit = body.gap_limit.unwrap_or(gap_limit_db as usize);
+        let gap_limit = body.gap_limit.unwrap_or(gap_limit_db as usize) as u32;
 
         // Load or create BDK wallet
         let (mut bdk_wallet, mut bdk_conn) = wallet_svc::load_or_create_bdk_wallet(
@@ -100,97 +208,277 @@ pub async fn sync_wallet(
             network,
         )?;
 
-        // Run the full scan
-        sync::full_scan(
-            &mut bdk_wallet,
-            &mut bdk_conn,
-            &esplora_url,
-            gap_limit,
-            &state.db,
-            &wallet_id,
-            &portfolio_id,
-        )
-        .await?
+        // Scan in chunks, persisting the wallet and checkpoint after each one so an
+        // interrupted sync resumes from the last index instead of starting over
+        let job = sync::resume_or_start_sync_job(&conn, &wallet_id, gap_limit)?;
+        let mut checkpoint = job.checkpoint();
+        let mut backend = job.backend.clone().unwrap_or_default();
+        publish_started(&progress, &job);
+
+        while !checkpoint.finished {
+            let outcome = {
+                let (wallet_ref, wallet_id, progress): (&bdk_wallet::Wallet, &str, _) =
+                    (&bdk_wallet, &wallet_id, &progress);
+                state
+                    .esplora
+                    .with_failover(network, |url| async move {
+                        sync::fetch_scan_chunk(wallet_ref, &url, checkpoint, wallet_id, progress).await
+                    })
+                    .await
+            };
+            let chunk = outcome.and_then(|(update, url)| {
+                let chunk_last_active = update.last_active_indices.get(&checkpoint.keychain).copied();
+                sync::apply_scan_chunk(&mut bdk_wallet, &mut bdk_conn, update)?;
+                Ok((chunk_last_active, url))
+            });
+            let (chunk_last_active, url) = match chunk {
+                Ok(v) => v,
+                Err(e) => return Err(fail_sync(&conn, &progress, &job.id, e)),
+            };
+
+            checkpoint.advance(chunk_last_active, gap_limit);
+            backend = url;
+            sync::save_scan_checkpoint(&conn, &job.id, &checkpoint, &backend)?;
+        }
+
+        let mut result = sync::record_wallet_transactions(&bdk_wallet, &state.db, &wallet_id, &portfolio_id)
+            .map_err(|e| fail_sync(&conn, &progress, &job.id, e))?;
+
+        // Re-check transactions still unconfirmed after the scan: the scan only adds
+        // what it sees, so replaced or dropped ones have to be looked up individually
+        let pending = sync::pending_txids(&state.db, &wallet_id)?;
+        if !pending.is_empty() {
+            let txids = &pending;
+            let outcome = state
+                .esplora
+                .with_failover(network, |url| async move { sync::fetch_pending_statuses(&url, txids).await })
+                .await
+                .and_then(|(statuses, _)| {
+                    sync::reconcile_wallet_mempool(
+                        &mut bdk_wallet,
+                        &mut bdk_conn,
+                        &state.db,
+                        &wallet_id,
+                        &statuses,
+                        &mut result,
+                    )
+                });
+            if let Err(e) = outcome {
+                return Err(fail_sync(&conn, &progress, &job.id, e));
+            }
+        }
+
+        let addresses = wallet_svc::get_wallet_addresses(&bdk_wallet, gap_limit);
+        wallet_svc::persist_addresses(&conn, &wallet_id, &addresses)?;
+
+        sync::complete_sync_job(&conn, &job.id, &result, &backend)?;
+        (result, backend, job.id)
     };
 
-    // Always kick off price backfill in background — skips already-priced transactions
-    {
-        let pool = state.db.clone();
-        let api_url = state.config.coingecko_api_url.clone();
-        let wid = wallet_id.clone();
-        tokio::spawn(async move {
-            prices::backfill_wallet_prices(pool, api_url, wid).await;
-        });
-    }
+    let _ = progress.send(SyncEvent::Completed {
+        transactions_found: result.transactions_found,
+        new_transactions: result.new_transactions,
+        balance_sat: result.balance_sat,
+    });
+
+    // Price backfill for the synced transactions runs off the wallet.synced event
+    let db_tx = conn.unchecked_transaction()?;
+    db_tx.execute(
+        "UPDATE wallets SET last_sync_backend = ?1 WHERE id = ?2",
+        rusqlite::params![backend, wallet_id],
+    )?;
+    events::record(
+        &db_tx,
+        &portfolio_id,
+        &DomainEvent::WalletSynced {
+            wallet_id: wallet_id.clone(),
+            new_transactions: result.new_transactions,
+            balance_sat: result.balance_sat,
+        },
+    )?;
+    db_tx.commit()?;
 
     Ok(Json(SyncResponse {
         transactions_found: result.transactions_found,
         new_transactions: result.new_transactions,
         balance_sat: result.balance_sat,
         last_sync_height: result.last_sync_height,
+        backend,
+        job_id,
+        mempool: result.mempool,
     }))
 }
 
+/// GET /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/sync/status
+pub async fn sync_status(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id)): Path<(String, String)>,
+) -> AppResult<Json<SyncStatusResponse>> {
+    let conn = state.db.get()?;
+
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Read)?;
+
+    let job = sync::latest_sync_job(&conn, &wallet_id)?;
+    let network_str: String = conn.query_row(
+        "SELECT network FROM wallets WHERE id = ?1",
+        rusqlite::params![wallet_id],
+        |row| row.get(0),
+    )?;
+    drop(conn);
+
+    let network = wallet_svc::parse_network(&network_str)?;
+    let chain_tip_height = state.esplora.tip_height(&state.cache, network).await.ok();
+
+    Ok(Json(SyncStatusResponse { job, chain_tip_height }))
+}
+
+/// GET /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/sync/stream
+/// Server-Sent Events feed of a wallet's sync progress. Subscribe before (or while)
+/// POSTing to /sync; the stream ends after the `completed` or `failed` event.
+pub async fn sync_stream(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id)): Path<(String, String)>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    {
+        let conn = state.db.get()?;
+
+        policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Read)?;
+    }
+
+    let rx = state.sync_progress.subscribe(&wallet_id);
+    let stream = futures::stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    let sse = Event::default()
+                        .event(event.name())
+                        .json_data(&event)
+                        .unwrap_or_else(|_| Event::default().event(event.name()));
+                    let next = if event.is_terminal() { None } else { Some(rx) };
+                    return Some((Ok(sse), next));
+                }
+                // A slow client missed some progress ticks — carry on from the newest
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
 /// GET /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/addresses
+///
+/// Reads the address table refreshed by each sync, receive addresses first, in
+/// index order. A wallet that has never been synced gets its table filled in on
+/// first request, from the local BDK wallet or, for single addresses, from Esplora.
 pub async fn get_addresses(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path((portfolio_id, wallet_id)): Path<(String, String)>,
+    Query(query): Query<AddressesQuery>,
 ) -> AppResult<Json<AddressesResponse>> {
     let conn = state.db.get()?;
 
-    // Verify ownership
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE w.id = ?1 AND p.user_id = ?2 AND w.portfolio_id = ?3)",
-        rusqlite::params![wallet_id, user.id, portfolio_id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Wallet not found".into()));
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Read)?;
+
+    if let Some(ref keychain) = query.keychain {
+        if !["external", "internal"].contains(&keychain.as_str()) {
+            return Err(AppError::BadRequest(
+                "Invalid keychain. Must be one of: external, internal".into(),
+            ));
+        }
     }
 
-    let (descriptor, xpub, derivation_path, address, network_str, wallet_type, gap_limit): (
-        Option<String>, Option<String>, Option<String>, Option<String>, String, String, i64,
-    ) = conn.query_row(
-        "SELECT descriptor, xpub, derivation_path, address, network, wallet_type, gap_limit FROM wallets WHERE id = ?1",
+    let persisted: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM wallet_addresses WHERE wallet_id = ?1)",
         rusqlite::params![wallet_id],
-        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?)),
+        |row| row.get(0),
     )?;
-
-    // For single address wallets, just return the address directly (no BDK)
-    if wallet_type == "address" {
-        let addresses = if let Some(addr) = address {
-            vec![wallet_svc::AddressInfo {
-                index: 0,
-                address: addr,
-                keychain: "external".to_string(),
-            }]
-        } else {
-            vec![]
+    if !persisted {
+        let record = load_wallet_record(&conn, &wallet_id)?;
+        let addresses = match (record.wallet_type.as_str(), record.address.as_deref()) {
+            // Single-address wallets have no BDK wallet; ask Esplora
+            ("address", Some(addr)) => {
+                let network = wallet_svc::parse_network(&record.network)?;
+                let (info, _backend) = state
+                    .esplora
+                    .with_failover(network, |url| async move { sync::address_stats(&url, addr).await })
+                    .await?;
+                vec![info]
+            }
+            ("address", None) => vec![],
+            _ => compute_wallet_addresses(&state, &wallet_id, &record)?,
         };
-        return Ok(Json(AddressesResponse { addresses }));
+        wallet_svc::persist_addresses(&conn, &wallet_id, &addresses)?;
     }
 
-    let (external_desc, internal_desc) = wallet_svc::build_descriptors(
-        descriptor.as_deref(),
-        xpub.as_deref(),
-        derivation_path.as_deref(),
-        address.as_deref(),
+    let limit = query.limit.unwrap_or(50).min(500);
+    let offset = query.offset.unwrap_or(0);
+
+    let mut where_clause = String::from("WHERE wallet_id = ?1");
+    let mut params: Vec<rusqlite::types::Value> = vec![wallet_id.clone().into()];
+    if let Some(keychain) = query.keychain {
+        params.push(keychain.into());
+        where_clause.push_str(&format!(" AND keychain = ?{}", params.len()));
+    }
+    if let Some(used) = query.used {
+        params.push((used as i64).into());
+        where_clause.push_str(&format!(" AND used = ?{}", params.len()));
+    }
+
+    let (total, updated_at): (i64, Option<String>) = conn.query_row(
+        &format!("SELECT COUNT(*), MAX(updated_at) FROM wallet_addresses {where_clause}"),
+        rusqlite::params_from_iter(params.iter()),
+        |row| Ok((row.get(0)?, row.get(1)?)),
     )?;
 
-    let network = wallet_svc::parse_network(&network_str)?;
+    params.push(limit.into());
+    let limit_idx = params.len();
+    params.push(offset.into());
+    let offset_idx = params.len();
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM wallet_addresses {where_clause}
+         ORDER BY keychain = 'internal', idx LIMIT ?{limit_idx} OFFSET ?{offset_idx}",
+        wallet_svc::ADDRESS_COLS
+    ))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), wallet_svc::row_to_address_info)?;
+    let addresses: Result<Vec<_>, _> = rows.collect();
+
+    Ok(Json(AddressesResponse {
+        addresses: addresses?,
+        total,
+        updated_at,
+    }))
+}
+
+/// Address stats from the local BDK wallet (no network access).
+fn compute_wallet_addresses(
+    state: &AppState,
+    wallet_id: &str,
+    record: &WalletRecord,
+) -> AppResult<Vec<wallet_svc::AddressInfo>> {
+    let (external_desc, internal_desc) = wallet_svc::build_descriptors(
+        record.descriptor.as_deref(),
+        record.xpub.as_deref(),
+        record.derivation_path.as_deref(),
+        record.address.as_deref(),
+    )?;
+    let network = wallet_svc::parse_network(&record.network)?;
 
     let (bdk_wallet, _bdk_conn) = wallet_svc::load_or_create_bdk_wallet(
         &state.config.bdk_wallets_dir,
-        &wallet_id,
+        wallet_id,
         &external_desc,
         &internal_desc,
         network,
     )?;
 
-    let addresses = wallet_svc::get_wallet_addresses(&bdk_wallet, gap_limit as u32);
-
-    Ok(Json(AddressesResponse { addresses }))
+    Ok(wallet_svc::get_wallet_addresses(&bdk_wallet, record.gap_limit as u32))
 }
 
 /// GET /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/utxos
@@ -201,15 +489,7 @@ pub async fn get_utxos(
 ) -> AppResult<Json<UtxosResponse>> {
     let conn = state.db.get()?;
 
-    // Verify ownership
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE w.id = ?1 AND p.user_id = ?2 AND w.portfolio_id = ?3)",
-        rusqlite::params![wallet_id, user.id, portfolio_id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Wallet not found".into()));
-    }
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Read)?;
 
     let (descriptor, xpub, derivation_path, address, network_str, wallet_type): (
         Option<String>, Option<String>, Option<String>, Option<String>, String, String,
@@ -226,17 +506,11 @@ pub async fn get_utxos(
         })?;
 
         let network = wallet_svc::parse_network(&network_str)?;
-        let esplora_url = match network {
-            bdk_wallet::bitcoin::Network::Testnet => {
-                state.config.esplora_url.replace("/api", "/testnet/api")
-            }
-            bdk_wallet::bitcoin::Network::Signet => {
-                state.config.esplora_url.replace("/api", "/signet/api")
-            }
-            _ => state.config.esplora_url.clone(),
-        };
-
-        let utxos = sync::address_utxos(&esplora_url, addr).await?;
+        let (mut utxos, _backend) = state
+            .esplora
+            .with_failover(network, |url| async move { sync::address_utxos(&url, addr).await })
+            .await?;
+        utxo_svc::attach_metadata(&conn, &wallet_id, &mut utxos)?;
         let total_sat: u64 = utxos.iter().map(|u| u.value_sat).sum();
 
         return Ok(Json(UtxosResponse { utxos, total_sat }));
@@ -259,8 +533,96 @@ pub async fn get_utxos(
         network,
     )?;
 
-    let utxos = wallet_svc::get_wallet_utxos(&bdk_wallet);
+    let mut utxos = wallet_svc::get_wallet_utxos(&bdk_wallet);
+    utxo_svc::attach_metadata(&conn, &wallet_id, &mut utxos)?;
     let total_sat: u64 = utxos.iter().map(|u| u.value_sat).sum();
 
     Ok(Json(UtxosResponse { utxos, total_sat }))
 }
+
+/// POST /api/v1/portfolios/:portfolio_id/wallets/:wallet_id/psbt
+///
+/// Builds an unsigned PSBT from the wallet's last synced state, for signing on a hardware
+/// wallet. Wallets added by bare xpub carry a placeholder key fingerprint, so signers that
+/// match on fingerprint need the wallet added by full descriptor instead.
+pub async fn create_psbt(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id)): Path<(String, String)>,
+    Json(body): Json<CreatePsbtRequest>,
+) -> AppResult<Json<wallet_svc::PsbtInfo>> {
+    if body.recipients.is_empty() {
+        return Err(AppError::BadRequest("At least one recipient is required".into()));
+    }
+    if !(body.fee_rate.is_finite() && body.fee_rate >= 1.0 && body.fee_rate <= 10_000.0) {
+        return Err(AppError::BadRequest("fee_rate must be between 1 and 10000 sat/vB".into()));
+    }
+
+    let conn = state.db.get()?;
+
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Write)?;
+
+    let record = load_wallet_record(&conn, &wallet_id)?;
+    if record.wallet_type == "address" {
+        return Err(AppError::BadRequest(
+            "PSBTs can only be built for descriptor or xpub wallets".into(),
+        ));
+    }
+
+    let frozen = utxo_svc::frozen_outpoints(&conn, &wallet_id)?;
+    let selected = match body.utxos {
+        Some(outpoints) => {
+            let mut selected = Vec::with_capacity(outpoints.len());
+            for outpoint in &outpoints {
+                let (txid, vout) = utxo_svc::parse_outpoint(outpoint)?;
+                if frozen.iter().any(|(t, v)| *t == txid && *v == vout) {
+                    return Err(AppError::BadRequest(format!("UTXO {outpoint} is frozen")));
+                }
+                selected.push(wallet_svc::to_outpoint(&txid, vout)?);
+            }
+            Some(selected)
+        }
+        None => None,
+    };
+    let frozen = frozen
+        .iter()
+        .map(|(txid, vout)| wallet_svc::to_outpoint(txid, *vout))
+        .collect::<AppResult<Vec<_>>>()?;
+    let recipients: Vec<wallet_svc::PsbtRecipient> = body
+        .recipients
+        .into_iter()
+        .map(|r| wallet_svc::PsbtRecipient { address: r.address, amount_sat: r.amount_sat })
+        .collect();
+
+    let (external_desc, internal_desc) = wallet_svc::build_descriptors(
+        record.descriptor.as_deref(),
+        record.xpub.as_deref(),
+        record.derivation_path.as_deref(),
+        record.address.as_deref(),
+    )?;
+    let network = wallet_svc::parse_network(&record.network)?;
+
+    // Held across the build so a concurrent sync can't persist over the revealed change address
+    let _sync_guard = state.sync_locks.try_acquire(&wallet_id).ok_or_else(|| {
+        AppError::Conflict("A sync is running for this wallet, try again shortly".into())
+    })?;
+
+    let (mut bdk_wallet, mut bdk_conn) = wallet_svc::load_or_create_bdk_wallet(
+        &state.config.bdk_wallets_dir,
+        &wallet_id,
+        &external_desc,
+        &internal_desc,
+        network,
+    )?;
+
+    let psbt = wallet_svc::build_psbt(
+        &mut bdk_wallet,
+        &mut bdk_conn,
+        &recipients,
+        body.fee_rate,
+        selected,
+        frozen,
+    )?;
+
+    Ok(Json(psbt))
+}
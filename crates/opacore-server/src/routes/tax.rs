@@ -6,44 +6,89 @@ use axum::{
 };
 use serde::Deserialize;
 
-use crate::error::AppResult;
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
-use crate::services::costbasis::CostBasisMethod;
-use crate::services::tax;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::costbasis::{self, CostBasisMethod, InCurrency, PortfolioDefaults};
+use crate::services::export;
+use crate::services::tax::{self, TaxRules};
+use crate::services::tax_locks::{self, TaxYearLock};
+use crate::services::tax_pdf;
+use crate::services::tax_software::{self, TaxSoftware};
 
 #[derive(Debug, Deserialize)]
 pub struct TaxQuery {
     pub year: i32,
     pub method: Option<CostBasisMethod>,
+    /// Recalculate rather than reuse a cached result.
+    #[serde(default)]
+    pub force: bool,
 }
 
-/// GET /api/v1/portfolios/:id/tax/report?year=2024&method=fifo
+/// GET /api/v1/portfolios/:id/tax/report?year=2024&method=fifo&force=true
+///
+/// In the portfolio's currency, with its method unless `method` is given.
 pub async fn tax_report(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path(portfolio_id): Path<String>,
     Query(query): Query<TaxQuery>,
-) -> AppResult<Json<tax::TaxReport>> {
-    verify_portfolio_ownership(&state, &user, &portfolio_id)?;
+) -> AppResult<Json<InCurrency<tax::TaxReport>>> {
+    let (rules, defaults) = portfolio_tax_settings(&state, &user, &portfolio_id)?;
 
-    let method = query.method.unwrap_or_default();
-    let report = tax::generate_tax_report(&state.db, &portfolio_id, query.year, method)?;
+    let method = query.method.unwrap_or(defaults.method);
+    let cache = (!query.force).then_some(&state.cache);
+    let report = tax::generate_tax_report(&state.db, cache, &portfolio_id, query.year, method, &defaults.currency, &rules)?;
+
+    Ok(Json(InCurrency { currency: defaults.currency, value: report }))
+}
+
+/// GET /api/v1/portfolios/:id/tax/quarterly?year=2024&method=fifo&force=true
+///
+/// The year's realized gains and income by quarter, for estimated tax payments.
+pub async fn tax_quarterly(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<TaxQuery>,
+) -> AppResult<Json<InCurrency<tax::QuarterlyGains>>> {
+    let (rules, defaults) = portfolio_tax_settings(&state, &user, &portfolio_id)?;
+
+    let method = query.method.unwrap_or(defaults.method);
+    let cache = (!query.force).then_some(&state.cache);
+    let quarters = tax::quarterly_gains(&state.db, cache, &portfolio_id, query.year, method, &defaults.currency, &rules)?;
 
-    Ok(Json(report))
+    Ok(Json(InCurrency { currency: defaults.currency, value: quarters }))
 }
 
 /// GET /api/v1/portfolios/:id/tax/csv?year=2024&method=fifo
 pub async fn tax_csv(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Path(portfolio_id): Path<String>,
     Query(query): Query<TaxQuery>,
 ) -> AppResult<impl IntoResponse> {
-    verify_portfolio_ownership(&state, &user, &portfolio_id)?;
+    let (rules, defaults) = portfolio_tax_settings(&state, &user, &portfolio_id)?;
 
-    let method = query.method.unwrap_or_default();
-    let csv = tax::generate_form_8949_csv(&state.db, &portfolio_id, query.year, method)?;
+    let method = query.method.unwrap_or(defaults.method);
+    {
+        let conn = state.db.get()?;
+        audit::record(
+            &conn,
+            &user.id,
+            AuditEvent::DataExported,
+            Some(&portfolio_id),
+            serde_json::json!({ "kind": "tax_csv", "year": query.year, "method": method_name(method) }),
+            &client,
+        )?;
+    }
+    let cache = (!query.force).then_some(&state.cache);
+    let report = tax::generate_tax_report(&state.db, cache, &portfolio_id, query.year, method, &defaults.currency, &rules)?;
+    let body = export::stream_body(move |out| tax::write_form_8949_csv(&report, out));
 
     let filename = format!("form_8949_{}_{}.csv", query.year, method_name(method));
 
@@ -56,23 +101,278 @@ pub async fn tax_csv(
                 format!("attachment; filename=\"{filename}\""),
             ),
         ],
-        csv,
+        body,
+    ))
+}
+
+/// GET /api/v1/tax/report?year=2024&method=fifo
+///
+/// The year's report across every portfolio the user owns, as one holding: a transfer
+/// between two of them carries its lots across, and disposals draw on lots from any.
+/// Always in USD, since the portfolios' own currencies can differ.
+pub async fn combined_tax_report(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<TaxQuery>,
+) -> AppResult<Json<InCurrency<tax::TaxReport>>> {
+    let rules = {
+        let conn = state.db.get()?;
+        tax::tax_rules(&conn, &user.id)?
+    };
+
+    let method = query.method.unwrap_or_default();
+    let report = tax::generate_user_tax_report(&state.db, &user.id, query.year, method, &rules)?;
+
+    Ok(Json(InCurrency { currency: "usd".to_string(), value: report }))
+}
+
+/// GET /api/v1/tax/csv?year=2024&method=fifo
+///
+/// Form 8949 CSV for the combined report.
+pub async fn combined_tax_csv(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Query(query): Query<TaxQuery>,
+) -> AppResult<impl IntoResponse> {
+    let method = query.method.unwrap_or_default();
+    let rules = {
+        let conn = state.db.get()?;
+        audit::record(
+            &conn,
+            &user.id,
+            AuditEvent::DataExported,
+            None,
+            serde_json::json!({ "kind": "tax_csv", "scope": "all", "year": query.year, "method": method_name(method) }),
+            &client,
+        )?;
+        tax::tax_rules(&conn, &user.id)?
+    };
+    let report = tax::generate_user_tax_report(&state.db, &user.id, query.year, method, &rules)?;
+    let body = export::stream_body(move |out| tax::write_form_8949_csv(&report, out));
+
+    let filename = format!("form_8949_all_{}_{}.csv", query.year, method_name(method));
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// GET /api/v1/portfolios/:id/tax/pdf?year=2024&method=fifo
+///
+/// The year's report as a PDF to hand to an accountant.
+pub async fn tax_pdf(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<TaxQuery>,
+) -> AppResult<impl IntoResponse> {
+    let (rules, defaults) = portfolio_tax_settings(&state, &user, &portfolio_id)?;
+
+    let method = query.method.unwrap_or(defaults.method);
+    let portfolio_name: String = {
+        let conn = state.db.get()?;
+        audit::record(
+            &conn,
+            &user.id,
+            AuditEvent::DataExported,
+            Some(&portfolio_id),
+            serde_json::json!({ "kind": "tax_pdf", "year": query.year, "method": method_name(method) }),
+            &client,
+        )?;
+        conn.query_row(
+            "SELECT name FROM portfolios WHERE id = ?1",
+            rusqlite::params![portfolio_id],
+            |row| row.get(0),
+        )?
+    };
+    let cache = (!query.force).then_some(&state.cache);
+    let report = tax::generate_tax_report(&state.db, cache, &portfolio_id, query.year, method, &defaults.currency, &rules)?;
+    let document = tax_pdf::tax_report_pdf(&report, &portfolio_name, &defaults.currency)?;
+
+    let filename = format!("tax_report_{}_{}.pdf", query.year, method_name(method));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+            (header::CACHE_CONTROL, "private, no-store".to_string()),
+        ],
+        document,
     ))
 }
 
-fn verify_portfolio_ownership(state: &AppState, user: &User, portfolio_id: &str) -> AppResult<()> {
+#[derive(Debug, Deserialize)]
+pub struct TaxExportQuery {
+    pub format: TaxSoftware,
+    /// Required for `turbotax`; limits the transaction formats to the history up to the
+    /// end of that tax year.
+    pub year: Option<i32>,
+    pub method: Option<CostBasisMethod>,
+}
+
+/// GET /api/v1/portfolios/:id/tax/export?format=koinly|cointracker|turbotax&year=2024
+///
+/// CSV for importing into other tax software.
+pub async fn tax_export(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<TaxExportQuery>,
+) -> AppResult<impl IntoResponse> {
+    let (rules, defaults) = portfolio_tax_settings(&state, &user, &portfolio_id)?;
+    if query.format == TaxSoftware::TurboTax && query.year.is_none() {
+        return Err(AppError::BadRequest("The TurboTax export needs a year".into()));
+    }
+
+    let method = query.method.unwrap_or(defaults.method);
+    {
+        let conn = state.db.get()?;
+        audit::record(
+            &conn,
+            &user.id,
+            AuditEvent::DataExported,
+            Some(&portfolio_id),
+            serde_json::json!({ "kind": "tax_export", "format": query.format.name(), "year": query.year }),
+            &client,
+        )?;
+    }
+    let pool = state.db.clone();
+    let (format, year) = (query.format, query.year);
+    let body = export::stream_body(move |out| {
+        tax_software::write_csv(&pool, &portfolio_id, format, year, method, &rules, out)
+    });
+
+    let filename = match year {
+        Some(year) => format!("{}_{year}.csv", format.name()),
+        None => format!("{}.csv", format.name()),
+    };
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// GET /api/v1/portfolios/:id/tax/locks
+pub async fn list_locks(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<Vec<TaxYearLock>>> {
     let conn = state.db.get()?;
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user.id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(crate::error::AppError::NotFound(
-            "Portfolio not found".into(),
-        ));
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+    Ok(Json(tax_locks::list(&conn, &portfolio_id)?))
+}
+
+/// PUT /api/v1/portfolios/:id/tax/locks/:year
+///
+/// Lock a filed tax year: its transactions can't be edited or deleted until it's unlocked.
+/// The year's dates are fixed by the user's tax rules at the time.
+pub async fn lock_year(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path((portfolio_id, year)): Path<(String, i32)>,
+) -> AppResult<Json<TaxYearLock>> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Manage)?;
+    let rules = tax::tax_rules(&conn, &user.id)?;
+
+    let db_tx = conn.transaction()?;
+    let (lock, created) = tax_locks::lock(&db_tx, &portfolio_id, year, &rules, &user.id)?;
+    if created {
+        audit::record(
+            &db_tx,
+            &user.id,
+            AuditEvent::TaxYearLocked,
+            Some(&portfolio_id),
+            serde_json::json!({ "year": year }),
+            &client,
+        )?;
+    }
+    db_tx.commit()?;
+    Ok(Json(lock))
+}
+
+/// DELETE /api/v1/portfolios/:id/tax/locks/:year
+pub async fn unlock_year(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path((portfolio_id, year)): Path<(String, i32)>,
+) -> AppResult<StatusCode> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Manage)?;
+
+    let db_tx = conn.transaction()?;
+    if !tax_locks::unlock(&db_tx, &portfolio_id, year)? {
+        return Err(AppError::NotFound(format!("Tax year {year} isn't locked")));
     }
-    Ok(())
+    audit::record(
+        &db_tx,
+        &user.id,
+        AuditEvent::TaxYearUnlocked,
+        Some(&portfolio_id),
+        serde_json::json!({ "year": year }),
+        &client,
+    )?;
+    db_tx.commit()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/tax/settings
+pub async fn get_settings(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<TaxRules>> {
+    let conn = state.db.get()?;
+    Ok(Json(tax::tax_rules(&conn, &user.id)?))
+}
+
+/// PUT /api/v1/tax/settings
+/// Replaces the user's tax rules; omitted fields take their defaults.
+pub async fn update_settings(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<TaxRules>,
+) -> AppResult<Json<TaxRules>> {
+    body.validate().map_err(AppError::BadRequest)?;
+
+    let conn = state.db.get()?;
+    tax::save_tax_rules(&conn, &user.id, &body)?;
+    Ok(Json(body))
+}
+
+/// Check the user can read the portfolio and return their tax rules and its defaults.
+fn portfolio_tax_settings(
+    state: &AppState,
+    user: &User,
+    portfolio_id: &str,
+) -> AppResult<(TaxRules, PortfolioDefaults)> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, portfolio_id, Access::Read)?;
+    Ok((tax::tax_rules(&conn, &user.id)?, costbasis::portfolio_defaults(&conn, portfolio_id)?))
 }
 
 fn method_name(method: CostBasisMethod) -> &'static str {
@@ -80,5 +380,7 @@ fn method_name(method: CostBasisMethod) -> &'static str {
         CostBasisMethod::Fifo => "fifo",
         CostBasisMethod::Lifo => "lifo",
         CostBasisMethod::Hifo => "hifo",
+        CostBasisMethod::SpecId => "specid",
+        CostBasisMethod::Section104 => "section104",
     }
 }
@@ -1,14 +1,27 @@
 use axum::{
     extract::{Path, Query, State},
+    http::header,
+    response::IntoResponse,
     Extension, Json,
 };
 use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::costbasis;
+use crate::services::events::{self, DomainEvent};
+use crate::services::export::{self, ExportFormat, JsonArrayWriter};
+use crate::services::pagination::{self, Cursor};
+use crate::services::sync::{TxInputDetail, TxOutputDetail};
+use crate::services::tax_locks;
+use crate::services::transaction_audit::{self, AuditAction, AuditEntry, AUDIT_COLS};
+use crate::services::transaction_splits::{self, SplitPart};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Transaction {
@@ -25,6 +38,17 @@ pub struct Transaction {
     pub block_height: Option<i64>,
     pub block_time: Option<String>,
     pub source: String,
+    /// Txid of the unconfirmed transaction this one replaced via RBF.
+    pub replaces_txid: Option<String>,
+    /// On a part of a split transaction, the transaction it divides.
+    pub parent_id: Option<String>,
+    /// Divided into parts, which count towards balances and cost basis in its place.
+    pub is_split: bool,
+    pub counterparty: Option<String>,
+    /// The paid invoice this transaction records.
+    pub invoice_id: Option<String>,
+    /// The other side of a transfer between the user's own wallets.
+    pub linked_transaction_id: Option<String>,
     pub transacted_at: String,
     pub created_at: String,
     pub updated_at: String,
@@ -44,6 +68,7 @@ pub struct CreateTransactionRequest {
     pub block_height: Option<i64>,
     pub block_time: Option<String>,
     pub source: Option<String>,
+    pub counterparty: Option<String>,
     pub transacted_at: String,
 }
 
@@ -55,6 +80,8 @@ pub struct UpdateTransactionRequest {
     pub price_usd: Option<f64>,
     pub fiat_amount: Option<f64>,
     pub fiat_currency: Option<String>,
+    /// An empty string clears it.
+    pub counterparty: Option<String>,
     pub transacted_at: Option<String>,
 }
 
@@ -62,14 +89,113 @@ pub struct UpdateTransactionRequest {
 pub struct ListTransactionsQuery {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
+    /// `next_cursor` from the previous page; takes precedence over `offset`. Only valid
+    /// with the same `sort` and `order` it was issued for.
+    pub after: Option<String>,
+    #[serde(default)]
+    pub sort: SortColumn,
+    pub order: Option<SortOrder>,
     pub tx_type: Option<String>,
     pub wallet_id: Option<String>,
+    /// Earliest `transacted_at`, inclusive: a date (YYYY-MM-DD) or RFC 3339 timestamp.
+    pub start: Option<String>,
+    /// Latest `transacted_at`, inclusive; a bare date covers that whole day.
+    pub end: Option<String>,
+    pub min_sat: Option<i64>,
+    pub max_sat: Option<i64>,
+    pub label_id: Option<String>,
+    pub source: Option<String>,
+}
+
+/// Columns the list can be sorted by. Each maps to a fixed SQL expression, so nothing
+/// from the query string reaches the SQL.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortColumn {
+    #[default]
+    TransactedAt,
+    AmountSat,
+    FiatAmount,
+}
+
+impl SortColumn {
+    fn expression(self) -> &'static str {
+        match self {
+            SortColumn::TransactedAt => "transacted_at",
+            SortColumn::AmountSat => "amount_sat",
+            // Unpriced rows sort as zero rather than before or after everything
+            SortColumn::FiatAmount => "COALESCE(fiat_amount, 0.0)",
+        }
+    }
+
+    fn cursor_key(self, tx: &Transaction) -> rusqlite::types::Value {
+        match self {
+            SortColumn::TransactedAt => tx.transacted_at.clone().into(),
+            SortColumn::AmountSat => tx.amount_sat.into(),
+            SortColumn::FiatAmount => tx.fiat_amount.unwrap_or(0.0).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportTransactionsQuery {
+    pub format: Option<ExportFormat>,
+    pub tx_type: Option<String>,
+    pub wallet_id: Option<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub min_sat: Option<i64>,
+    pub max_sat: Option<i64>,
+    pub label_id: Option<String>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SplitRequest {
+    pub parts: Vec<SplitPart>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub transaction_id: Option<String>,
+    /// Entries older than this id: the last `id` of the previous page.
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Filters shared by the list and export endpoints; every field narrows the result.
+struct TransactionFilter<'a> {
+    tx_type: Option<&'a str>,
+    wallet_id: Option<&'a str>,
+    start: Option<&'a str>,
+    end: Option<&'a str>,
+    min_sat: Option<i64>,
+    max_sat: Option<i64>,
+    label_id: Option<&'a str>,
+    source: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionDetailsResponse {
+    pub transaction: Transaction,
+    /// Empty for manual/imported transactions and chain transactions not yet re-synced.
+    pub inputs: Vec<TxInputDetail>,
+    pub outputs: Vec<TxOutputDetail>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct TransactionListResponse {
     pub data: Vec<Transaction>,
     pub total: i64,
+    /// Pass as `after` to fetch the next page; absent on the last one.
+    pub next_cursor: Option<String>,
 }
 
 fn row_to_transaction(row: &rusqlite::Row) -> rusqlite::Result<Transaction> {
@@ -90,26 +216,16 @@ fn row_to_transaction(row: &rusqlite::Row) -> rusqlite::Result<Transaction> {
         transacted_at: row.get(13)?,
         created_at: row.get(14)?,
         updated_at: row.get(15)?,
+        replaces_txid: row.get(16)?,
+        parent_id: row.get(17)?,
+        is_split: row.get(18)?,
+        counterparty: row.get(19)?,
+        invoice_id: row.get(20)?,
+        linked_transaction_id: row.get(21)?,
     })
 }
 
-const TX_COLS: &str = "id, portfolio_id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, block_height, block_time, source, transacted_at, created_at, updated_at";
-
-fn verify_portfolio_ownership(
-    conn: &rusqlite::Connection,
-    portfolio_id: &str,
-    user_id: &str,
-) -> AppResult<()> {
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user_id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Portfolio not found".into()));
-    }
-    Ok(())
-}
+const TX_COLS: &str = "id, portfolio_id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, block_height, block_time, source, transacted_at, created_at, updated_at, replaces_txid, parent_id, EXISTS(SELECT 1 FROM transactions part WHERE part.parent_id = transactions.id), counterparty, invoice_id, linked_transaction_id";
 
 pub async fn list(
     State(state): State<AppState>,
@@ -118,58 +234,260 @@ pub async fn list(
     Query(query): Query<ListTransactionsQuery>,
 ) -> AppResult<Json<TransactionListResponse>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
 
     let limit = query.limit.unwrap_or(50).min(200);
     let offset = query.offset.unwrap_or(0);
 
-    let mut where_clause = "WHERE portfolio_id = ?1".to_string();
-    let mut params: Vec<Box<dyn rusqlite::types::ToSql>> = vec![Box::new(portfolio_id.clone())];
-
-    if let Some(ref tx_type) = query.tx_type {
-        params.push(Box::new(tx_type.clone()));
-        where_clause.push_str(&format!(" AND tx_type = ?{}", params.len()));
-    }
-    if let Some(ref wallet_id) = query.wallet_id {
-        params.push(Box::new(wallet_id.clone()));
-        where_clause.push_str(&format!(" AND wallet_id = ?{}", params.len()));
-    }
+    let (mut where_clause, mut params) = filter_clause(
+        &portfolio_id,
+        &TransactionFilter {
+            tx_type: query.tx_type.as_deref(),
+            wallet_id: query.wallet_id.as_deref(),
+            start: query.start.as_deref(),
+            end: query.end.as_deref(),
+            min_sat: query.min_sat,
+            max_sat: query.max_sat,
+            label_id: query.label_id.as_deref(),
+            source: query.source.as_deref(),
+        },
+    )?;
 
     let total: i64 = conn.query_row(
         &format!("SELECT COUNT(*) FROM transactions {where_clause}"),
-        rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
+        rusqlite::params_from_iter(params.iter()),
         |row| row.get(0),
     )?;
 
-    params.push(Box::new(limit));
+    // Newest or largest first unless asked otherwise
+    let descending = query.order != Some(SortOrder::Asc);
+    let sort = query.sort.expression();
+    let direction = if descending { "DESC" } else { "ASC" };
+
+    // Keyset pagination when a cursor is given; offset is kept for older clients
+    let offset = match &query.after {
+        Some(after) => {
+            where_clause.push_str(&Cursor::decode(after)?.condition(sort, "id", descending, &mut params));
+            0
+        }
+        None => offset,
+    };
+
+    params.push((limit + 1).into());
     let limit_idx = params.len();
-    params.push(Box::new(offset));
+    params.push(offset.into());
     let offset_idx = params.len();
 
     let sql = format!(
-        "SELECT {TX_COLS} FROM transactions {where_clause} ORDER BY transacted_at DESC LIMIT ?{limit_idx} OFFSET ?{offset_idx}"
+        "SELECT {TX_COLS} FROM transactions {where_clause} ORDER BY {sort} {direction}, id {direction} LIMIT ?{limit_idx} OFFSET ?{offset_idx}"
     );
 
     let mut stmt = conn.prepare(&sql)?;
-    let rows = stmt.query_map(
-        rusqlite::params_from_iter(params.iter().map(|p| p.as_ref())),
-        row_to_transaction,
-    )?;
-    let data: Result<Vec<_>, _> = rows.collect();
+    let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), row_to_transaction)?;
+    let mut data = rows.collect::<Result<Vec<_>, _>>()?;
+    let next_cursor = pagination::next_cursor(&mut data, limit, |tx| Cursor {
+        key: query.sort.cursor_key(tx),
+        id: tx.id.clone(),
+    });
 
     Ok(Json(TransactionListResponse {
-        data: data?,
+        data,
         total,
+        next_cursor,
     }))
 }
 
+/// GET /api/v1/portfolios/{portfolio_id}/transactions/export?format=csv
+///
+/// Streams every matching transaction, oldest first, straight from the query cursor.
+pub async fn export(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<ExportTransactionsQuery>,
+) -> AppResult<impl IntoResponse> {
+    let format = query.format.unwrap_or_default();
+    {
+        let conn = state.db.get()?;
+        policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+        audit::record(
+            &conn,
+            &user.id,
+            AuditEvent::DataExported,
+            Some(&portfolio_id),
+            serde_json::json!({ "kind": "transactions", "format": format.extension() }),
+            &client,
+        )?;
+    }
+
+    let filename = format!("transactions_{portfolio_id}.{}", format.extension());
+    let (where_clause, params) = filter_clause(
+        &portfolio_id,
+        &TransactionFilter {
+            tx_type: query.tx_type.as_deref(),
+            wallet_id: query.wallet_id.as_deref(),
+            start: query.start.as_deref(),
+            end: query.end.as_deref(),
+            min_sat: query.min_sat,
+            max_sat: query.max_sat,
+            label_id: query.label_id.as_deref(),
+            source: query.source.as_deref(),
+        },
+    )?;
+    let pool = state.db.clone();
+
+    let body = export::stream_body(move |out| {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {TX_COLS} FROM transactions {where_clause} ORDER BY transacted_at ASC"
+        ))?;
+        let mut rows = stmt.query(rusqlite::params_from_iter(params.iter()))?;
+
+        match format {
+            ExportFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(out);
+                while let Some(row) = rows.next()? {
+                    wtr.serialize(row_to_transaction(row)?).map_err(export::write_failed)?;
+                }
+                wtr.flush().map_err(export::write_failed)?;
+            }
+            ExportFormat::Json => {
+                let mut json = JsonArrayWriter::new(out).map_err(export::write_failed)?;
+                while let Some(row) = rows.next()? {
+                    json.write(&row_to_transaction(row)?).map_err(export::write_failed)?;
+                }
+                json.finish().map_err(export::write_failed)?;
+            }
+        }
+        Ok(())
+    });
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// WHERE clause and parameters shared by the list and export queries.
+fn filter_clause(
+    portfolio_id: &str,
+    filter: &TransactionFilter,
+) -> AppResult<(String, Vec<rusqlite::types::Value>)> {
+    let start = filter.start.map(|s| date_bound(s, "start", false)).transpose()?;
+    let end = filter.end.map(|s| date_bound(s, "end", true)).transpose()?;
+    if let (Some(start), Some(end)) = (&start, &end) {
+        if start.timestamp > end.timestamp {
+            return Err(AppError::BadRequest("start must not be after end".into()));
+        }
+    }
+    if let (Some(min), Some(max)) = (filter.min_sat, filter.max_sat) {
+        if min > max {
+            return Err(AppError::BadRequest("min_sat must not be greater than max_sat".into()));
+        }
+    }
+
+    let mut where_clause = "WHERE portfolio_id = ?1".to_string();
+    let mut params: Vec<rusqlite::types::Value> = vec![portfolio_id.to_string().into()];
+
+    if let Some(tx_type) = filter.tx_type {
+        params.push(tx_type.to_string().into());
+        where_clause.push_str(&format!(" AND tx_type = ?{}", params.len()));
+    }
+    if let Some(wallet_id) = filter.wallet_id {
+        params.push(wallet_id.to_string().into());
+        where_clause.push_str(&format!(" AND wallet_id = ?{}", params.len()));
+    }
+    if let Some(start) = start {
+        let column = start.column();
+        params.push(start.value().into());
+        where_clause.push_str(&format!(" AND {column} >= ?{}", params.len()));
+    }
+    if let Some(end) = end {
+        let column = end.column();
+        params.push(end.value().into());
+        where_clause.push_str(&format!(" AND {column} <= ?{}", params.len()));
+    }
+    if let Some(min_sat) = filter.min_sat {
+        params.push(min_sat.into());
+        where_clause.push_str(&format!(" AND amount_sat >= ?{}", params.len()));
+    }
+    if let Some(max_sat) = filter.max_sat {
+        params.push(max_sat.into());
+        where_clause.push_str(&format!(" AND amount_sat <= ?{}", params.len()));
+    }
+    if let Some(label_id) = filter.label_id {
+        params.push(label_id.to_string().into());
+        where_clause.push_str(&format!(
+            " AND id IN (SELECT transaction_id FROM transaction_labels WHERE label_id = ?{})",
+            params.len()
+        ));
+    }
+    if let Some(source) = filter.source {
+        params.push(source.to_string().into());
+        where_clause.push_str(&format!(" AND source = ?{}", params.len()));
+    }
+    Ok((where_clause, params))
+}
+
+/// A `start`/`end` filter, compared as a string with `transacted_at`.
+struct DateBound {
+    /// In the stored `transacted_at` format. A bare date is the start of that day, or
+    /// its last millisecond for `end`.
+    timestamp: String,
+    /// Given as a bare date, so only the day of `transacted_at` matters.
+    date_only: bool,
+}
+
+impl DateBound {
+    /// Transactions can be stored with a bare date (`YYYY-MM-DD`), which would sort
+    /// before any timestamp on its own day: a day bound compares days, and a timestamp
+    /// bound takes a bare date as midnight.
+    fn column(&self) -> &'static str {
+        if self.date_only {
+            "substr(transacted_at, 1, 10)"
+        } else {
+            "CASE WHEN length(transacted_at) = 10 THEN transacted_at || 'T00:00:00.000Z' ELSE transacted_at END"
+        }
+    }
+
+    fn value(self) -> String {
+        if self.date_only {
+            self.timestamp[..10].to_string()
+        } else {
+            self.timestamp
+        }
+    }
+}
+
+fn date_bound(value: &str, name: &str, end_of_day: bool) -> AppResult<DateBound> {
+    let value = value.trim();
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let time = if end_of_day { "23:59:59.999" } else { "00:00:00.000" };
+        return Ok(DateBound { timestamp: format!("{date}T{time}Z"), date_only: true });
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|t| DateBound {
+            timestamp: t.with_timezone(&chrono::Utc).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            date_only: false,
+        })
+        .map_err(|_| AppError::BadRequest(format!("Invalid {name}: expected YYYY-MM-DD or an RFC 3339 timestamp")))
+}
+
 pub async fn get(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
     Path((portfolio_id, tx_id)): Path<(String, String)>,
 ) -> AppResult<Json<Transaction>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
 
     let tx = conn
         .query_row(
@@ -187,37 +505,103 @@ pub async fn get(
     Ok(Json(tx))
 }
 
+/// GET /api/v1/portfolios/:portfolio_id/transactions/:tx_id/details
+/// The transaction with its on-chain inputs and outputs.
+pub async fn details(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, tx_id)): Path<(String, String)>,
+) -> AppResult<Json<TransactionDetailsResponse>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+
+    let transaction = conn
+        .query_row(
+            &format!("SELECT {TX_COLS} FROM transactions WHERE id = ?1 AND portfolio_id = ?2"),
+            rusqlite::params![tx_id, portfolio_id],
+            row_to_transaction,
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                AppError::NotFound("Transaction not found".into())
+            }
+            e => AppError::Database(e),
+        })?;
+
+    let mut stmt = conn.prepare(
+        "SELECT vin, prev_txid, prev_vout, address, value_sat, is_mine
+         FROM transaction_inputs WHERE transaction_id = ?1 ORDER BY vin",
+    )?;
+    let inputs = stmt
+        .query_map(rusqlite::params![tx_id], |row| {
+            Ok(TxInputDetail {
+                vin: row.get(0)?,
+                prev_txid: row.get(1)?,
+                prev_vout: row.get(2)?,
+                address: row.get(3)?,
+                value_sat: row.get(4)?,
+                is_mine: row.get::<_, i32>(5)? != 0,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT vout, address, value_sat, is_mine
+         FROM transaction_outputs WHERE transaction_id = ?1 ORDER BY vout",
+    )?;
+    let outputs = stmt
+        .query_map(rusqlite::params![tx_id], |row| {
+            Ok(TxOutputDetail {
+                vout: row.get(0)?,
+                address: row.get(1)?,
+                value_sat: row.get(2)?,
+                is_mine: row.get::<_, i32>(3)? != 0,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(TransactionDetailsResponse { transaction, inputs, outputs }))
+}
+
 pub async fn create(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Json(body): Json<CreateTransactionRequest>,
 ) -> AppResult<(StatusCode, Json<Transaction>)> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &body.portfolio_id, &user.id)?;
-
-    let valid_types = ["buy", "sell", "receive", "send", "transfer"];
-    if !valid_types.contains(&body.tx_type.as_str()) {
-        return Err(AppError::BadRequest(format!(
-            "Invalid tx_type. Must be one of: {}",
-            valid_types.join(", ")
-        )));
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &body.portfolio_id, Access::Write)?;
+
+    costbasis::validate_tx_type(&body.tx_type)?;
+    if body.amount_sat <= 0 {
+        return Err(AppError::BadRequest("amount_sat must be positive".into()));
     }
 
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     let fiat_currency = body.fiat_currency.as_deref().unwrap_or("usd");
     let source = body.source.as_deref().unwrap_or("manual");
+    let counterparty = body.counterparty.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
 
-    conn.execute(
-        "INSERT INTO transactions (id, portfolio_id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, block_height, block_time, source, transacted_at, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+    let db_tx = conn.transaction()?;
+    tax_locks::check_dates(&db_tx, &body.portfolio_id, &id, &[&body.transacted_at], &user, &client)?;
+    db_tx.execute(
+        "INSERT INTO transactions (id, portfolio_id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, block_height, block_time, source, counterparty, transacted_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
         rusqlite::params![
             id, body.portfolio_id, body.wallet_id, body.tx_type,
             body.amount_sat, body.fee_sat, body.price_usd, body.fiat_amount,
             fiat_currency, body.txid, body.block_height, body.block_time,
-            source, body.transacted_at, now, now
+            source, counterparty, body.transacted_at, now, now
         ],
     )?;
+    events::record(
+        &db_tx,
+        &body.portfolio_id,
+        &DomainEvent::TransactionCreated { transaction_id: id.clone(), wallet_id: body.wallet_id.clone() },
+    )?;
+    transaction_audit::record_created(&db_tx, &body.portfolio_id, &id, Some(&user.id))?;
+    db_tx.commit()?;
 
     let tx = Transaction {
         id,
@@ -233,6 +617,12 @@ pub async fn create(
         block_height: body.block_height,
         block_time: body.block_time,
         source: source.to_string(),
+        replaces_txid: None,
+        parent_id: None,
+        is_split: false,
+        counterparty,
+        invoice_id: None,
+        linked_transaction_id: None,
         transacted_at: body.transacted_at,
         created_at: now.clone(),
         updated_at: now,
@@ -244,11 +634,18 @@ pub async fn create(
 pub async fn update(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Path((portfolio_id, tx_id)): Path<(String, String)>,
     Json(body): Json<UpdateTransactionRequest>,
 ) -> AppResult<Json<Transaction>> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+    if let Some(tx_type) = &body.tx_type {
+        costbasis::validate_tx_type(tx_type)?;
+    }
+    if body.amount_sat.is_some_and(|amount| amount <= 0) {
+        return Err(AppError::BadRequest("amount_sat must be positive".into()));
+    }
 
     let existing = conn
         .query_row(
@@ -263,6 +660,16 @@ pub async fn update(
             e => AppError::Database(e),
         })?;
 
+    let changed: Vec<&str> = [
+        ("amount_sat", body.amount_sat.is_some_and(|a| a != existing.amount_sat)),
+        ("fee_sat", body.fee_sat.is_some_and(|f| Some(f) != existing.fee_sat)),
+        ("transacted_at", body.transacted_at.as_ref().is_some_and(|t| *t != existing.transacted_at)),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect();
+    transaction_audit::check_chain_edit(&existing.source, Some(&changed))?;
+
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     let tx_type = body.tx_type.unwrap_or(existing.tx_type);
     let amount_sat = body.amount_sat.unwrap_or(existing.amount_sat);
@@ -270,12 +677,22 @@ pub async fn update(
     let price_usd = body.price_usd.or(existing.price_usd);
     let fiat_amount = body.fiat_amount.or(existing.fiat_amount);
     let fiat_currency = body.fiat_currency.unwrap_or(existing.fiat_currency);
-    let transacted_at = body.transacted_at.unwrap_or(existing.transacted_at);
-
-    conn.execute(
-        "UPDATE transactions SET tx_type = ?1, amount_sat = ?2, fee_sat = ?3, price_usd = ?4, fiat_amount = ?5, fiat_currency = ?6, transacted_at = ?7, updated_at = ?8 WHERE id = ?9",
-        rusqlite::params![tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, transacted_at, now, tx_id],
+    let counterparty = match body.counterparty {
+        Some(c) => Some(c.trim().to_string()).filter(|c| !c.is_empty()),
+        None => existing.counterparty,
+    };
+    let transacted_at = body.transacted_at.unwrap_or(existing.transacted_at.clone());
+
+    let db_tx = conn.transaction()?;
+    tax_locks::check_dates(&db_tx, &portfolio_id, &tx_id, &[&existing.transacted_at, &transacted_at], &user, &client)?;
+    let before = transaction_audit::snapshot(&db_tx, &tx_id)?.unwrap_or_default();
+    db_tx.execute(
+        "UPDATE transactions SET tx_type = ?1, amount_sat = ?2, fee_sat = ?3, price_usd = ?4, fiat_amount = ?5, fiat_currency = ?6, counterparty = ?7, transacted_at = ?8, updated_at = ?9 WHERE id = ?10",
+        rusqlite::params![tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, counterparty, transacted_at, now, tx_id],
     )?;
+    events::record(&db_tx, &portfolio_id, &DomainEvent::TransactionUpdated { transaction_id: tx_id.clone() })?;
+    transaction_audit::record_updated(&db_tx, &portfolio_id, &tx_id, Some(&user.id), &before)?;
+    db_tx.commit()?;
 
     Ok(Json(Transaction {
         id: tx_id,
@@ -286,6 +703,7 @@ pub async fn update(
         price_usd,
         fiat_amount,
         fiat_currency,
+        counterparty,
         transacted_at,
         updated_at: now,
         ..existing
@@ -295,19 +713,120 @@ pub async fn update(
 pub async fn delete(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Path((portfolio_id, tx_id)): Path<(String, String)>,
 ) -> AppResult<StatusCode> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
 
-    let affected = conn.execute(
+    let db_tx = conn.transaction()?;
+    let (source, transacted_at): (String, String) = db_tx
+        .query_row(
+            "SELECT source, transacted_at FROM transactions WHERE id = ?1 AND portfolio_id = ?2",
+            rusqlite::params![tx_id, portfolio_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Transaction not found".into()),
+            e => AppError::Database(e),
+        })?;
+    transaction_audit::check_chain_edit(&source, None)?;
+    tax_locks::check_dates(&db_tx, &portfolio_id, &tx_id, &[&transacted_at], &user, &client)?;
+
+    let before = transaction_audit::snapshot(&db_tx, &tx_id)?.unwrap_or_default();
+    db_tx.execute(
         "DELETE FROM transactions WHERE id = ?1 AND portfolio_id = ?2",
         rusqlite::params![tx_id, portfolio_id],
     )?;
+    transaction_audit::record(
+        &db_tx,
+        &portfolio_id,
+        &tx_id,
+        Some(&user.id),
+        AuditAction::Delete,
+        &serde_json::Value::Object(before),
+    )?;
+    events::record(&db_tx, &portfolio_id, &DomainEvent::TransactionDeleted { transaction_id: tx_id })?;
+    db_tx.commit()?;
 
-    if affected == 0 {
-        return Err(AppError::NotFound("Transaction not found".into()));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/transactions/{transaction_id}/split
+///
+/// Divide a chain transaction into parts with their own type, price and labels (say half
+/// payroll, half reimbursement). The parts must add up to the on-chain amount and replace
+/// any earlier split; the transaction itself stays, linked to them.
+pub async fn split(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(transaction_id): Path<String>,
+    Json(body): Json<SplitRequest>,
+) -> AppResult<(StatusCode, Json<Vec<Transaction>>)> {
+    let mut conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+
+    let db_tx = conn.transaction()?;
+    tax_locks::check_transaction(&db_tx, &transaction_id, &user, &client)?;
+    transaction_splits::split(&db_tx, &user.id, &transaction_id, &body.parts)?;
+    db_tx.commit()?;
+
+    let mut stmt = conn.prepare(&format!("SELECT {TX_COLS} FROM transactions WHERE parent_id = ?1 ORDER BY rowid"))?;
+    let parts = stmt
+        .query_map(rusqlite::params![transaction_id], row_to_transaction)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((StatusCode::CREATED, Json(parts)))
+}
+
+/// DELETE /api/v1/transactions/{transaction_id}/split
+///
+/// Remove the parts so the transaction counts as a whole again.
+pub async fn unsplit(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(transaction_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let mut conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+
+    let db_tx = conn.transaction()?;
+    tax_locks::check_transaction(&db_tx, &transaction_id, &user, &client)?;
+    if transaction_splits::unsplit(&db_tx, &user.id, &transaction_id)? == 0 {
+        return Err(AppError::NotFound("Transaction isn't split".into()));
     }
+    db_tx.commit()?;
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// GET /api/v1/portfolios/{portfolio_id}/transactions/audit
+///
+/// Changes made to the portfolio's transactions, newest first, including ones since
+/// deleted. Chain sync and automatic price backfills aren't recorded: they only ever
+/// bring a row in line with the chain or price history.
+pub async fn audit(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(portfolio_id): Path<String>,
+    Query(query): Query<AuditQuery>,
+) -> AppResult<Json<Vec<AuditEntry>>> {
+    let conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
+
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {AUDIT_COLS} FROM transaction_audit
+         WHERE portfolio_id = ?1 AND (?2 IS NULL OR transaction_id = ?2) AND (?3 IS NULL OR id < ?3)
+         ORDER BY id DESC LIMIT ?4"
+    ))?;
+    let entries = stmt
+        .query_map(
+            rusqlite::params![portfolio_id, query.transaction_id, query.before_id, limit],
+            transaction_audit::row_to_entry,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(entries))
+}
@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::Deserialize;
+
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
+use crate::error::AppResult;
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::tax_locks;
+use crate::services::transfers::{self, TransferLink};
+
+#[derive(Debug, Deserialize)]
+pub struct LinkTransferRequest {
+    /// The other side: a receive when linking a send, or a send when linking a receive.
+    pub linked_transaction_id: String,
+}
+
+/// PUT /api/v1/transactions/{transaction_id}/transfer
+///
+/// Mark a send and a receive as one transfer between the user's own wallets, so cost
+/// basis carries the coins' lots across instead of counting a new acquisition. The two
+/// must be in the same portfolio: each portfolio's cost basis is worked out on its own.
+pub async fn link(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(transaction_id): Path<String>,
+    Json(body): Json<LinkTransferRequest>,
+) -> AppResult<Json<TransferLink>> {
+    let mut conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+    policy::transaction(&state.cache, &conn, &user.id, &body.linked_transaction_id, Access::Write)?;
+
+    let db_tx = conn.transaction()?;
+    tax_locks::check_transaction(&db_tx, &transaction_id, &user, &client)?;
+    tax_locks::check_transaction(&db_tx, &body.linked_transaction_id, &user, &client)?;
+    let link = transfers::link(&db_tx, &transaction_id, &body.linked_transaction_id, Some(&user.id))?;
+    db_tx.commit()?;
+    Ok(Json(link))
+}
+
+/// DELETE /api/v1/transactions/{transaction_id}/transfer
+///
+/// Unlink both sides; each counts as a plain send or receive again.
+pub async fn unlink(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(transaction_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let mut conn = state.db.get()?;
+    policy::transaction(&state.cache, &conn, &user.id, &transaction_id, Access::Write)?;
+
+    let db_tx = conn.transaction()?;
+    let linked_id: Option<String> = db_tx.query_row(
+        "SELECT linked_transaction_id FROM transactions WHERE id = ?1",
+        rusqlite::params![transaction_id],
+        |row| row.get(0),
+    )?;
+    for id in std::iter::once(&transaction_id).chain(linked_id.as_ref()) {
+        tax_locks::check_transaction(&db_tx, id, &user, &client)?;
+    }
+    transfers::unlink(&db_tx, &transaction_id, Some(&user.id))?;
+    db_tx.commit()?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/portfolios/{portfolio_id}/transfers/detect
+///
+/// Link the sends and receives of the same on-chain transaction between the portfolio's
+/// wallets. Syncing does this for new transactions; this catches up older ones.
+pub async fn detect(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
+    Path(portfolio_id): Path<String>,
+) -> AppResult<Json<serde_json::Value>> {
+    let mut conn = state.db.get()?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
+
+    let db_tx = conn.transaction()?;
+    let linked = transfers::link_by_txid(&db_tx, &portfolio_id, Some((&user, &client)))?;
+    db_tx.commit()?;
+    Ok(Json(serde_json::json!({ "linked": linked })))
+}
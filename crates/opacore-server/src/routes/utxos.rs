@@ -0,0 +1,84 @@
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use crate::auth::policy::{self, Access};
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::utxos::{self, UtxoMetadata};
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateUtxoRequest {
+    pub label: Option<String>,
+    pub notes: Option<String>,
+    pub frozen: Option<bool>,
+}
+
+/// GET /api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/utxos/{outpoint}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id, outpoint)): Path<(String, String, String)>,
+) -> AppResult<Json<UtxoMetadata>> {
+    let (txid, vout) = utxos::parse_outpoint(&outpoint)?;
+    let conn = state.db.get()?;
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Read)?;
+
+    let metadata = utxos::get_metadata(&conn, &wallet_id, &txid, vout)?
+        .ok_or_else(|| AppError::NotFound("No metadata for this UTXO".into()))?;
+    Ok(Json(metadata))
+}
+
+/// PUT /api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/utxos/{outpoint}
+///
+/// Fields left out keep their current value. An empty label or notes clears it.
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id, outpoint)): Path<(String, String, String)>,
+    Json(body): Json<UpdateUtxoRequest>,
+) -> AppResult<Json<UtxoMetadata>> {
+    let (txid, vout) = utxos::parse_outpoint(&outpoint)?;
+    let conn = state.db.get()?;
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Write)?;
+
+    let existing = utxos::get_metadata(&conn, &wallet_id, &txid, vout)?;
+    let (old_label, old_notes, old_frozen) = match existing {
+        Some(m) => (m.label, m.notes, m.frozen),
+        None => (None, None, false),
+    };
+    let label = body.label.or(old_label).filter(|l| !l.is_empty());
+    let notes = body.notes.or(old_notes).filter(|n| !n.is_empty());
+    let frozen = body.frozen.unwrap_or(old_frozen);
+
+    let metadata = utxos::save_metadata(
+        &conn,
+        &wallet_id,
+        &txid,
+        vout,
+        label.as_deref(),
+        notes.as_deref(),
+        frozen,
+    )?;
+    Ok(Json(metadata))
+}
+
+/// DELETE /api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}/utxos/{outpoint}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path((portfolio_id, wallet_id, outpoint)): Path<(String, String, String)>,
+) -> AppResult<StatusCode> {
+    let (txid, vout) = utxos::parse_outpoint(&outpoint)?;
+    let conn = state.db.get()?;
+    policy::wallet(&state.cache, &conn, &user.id, &portfolio_id, &wallet_id, Access::Write)?;
+
+    if !utxos::delete_metadata(&conn, &wallet_id, &txid, vout)? {
+        return Err(AppError::NotFound("No metadata for this UTXO".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,14 +1,22 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Extension, Json,
 };
 use axum::http::StatusCode;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
+use crate::auth::policy::{self, Access};
+use crate::auth::session::ClientInfo;
 use crate::error::{AppError, AppResult};
 use crate::models::User;
 use crate::routes::AppState;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::quotas::{self, Quota};
+use crate::services::tax_locks;
+use crate::services::transaction_audit::{self, AuditAction};
+use crate::services::wallet as wallet_svc;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Wallet {
@@ -24,6 +32,7 @@ pub struct Wallet {
     pub gap_limit: i64,
     pub last_synced_at: Option<String>,
     pub last_sync_height: Option<i64>,
+    pub last_sync_backend: Option<String>,
     pub balance_sat: i64,
     pub created_at: String,
     pub updated_at: String,
@@ -48,6 +57,19 @@ pub struct UpdateWalletRequest {
     pub gap_limit: Option<i64>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DeleteWalletQuery {
+    /// What to do with the wallet's chain-synced transactions: "delete" (default) or "detach".
+    pub transactions: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteWalletResponse {
+    pub transactions_deleted: usize,
+    pub transactions_detached: usize,
+    pub bdk_files_removed: usize,
+}
+
 fn row_to_wallet(row: &rusqlite::Row) -> rusqlite::Result<Wallet> {
     Ok(Wallet {
         id: row.get(0)?,
@@ -65,26 +87,11 @@ fn row_to_wallet(row: &rusqlite::Row) -> rusqlite::Result<Wallet> {
         balance_sat: row.get(12)?,
         created_at: row.get(13)?,
         updated_at: row.get(14)?,
+        last_sync_backend: row.get(15)?,
     })
 }
 
-const WALLET_COLS: &str = "id, portfolio_id, label, wallet_type, descriptor, xpub, address, network, derivation_path, gap_limit, last_synced_at, last_sync_height, balance_sat, created_at, updated_at";
-
-fn verify_portfolio_ownership(
-    conn: &rusqlite::Connection,
-    portfolio_id: &str,
-    user_id: &str,
-) -> AppResult<()> {
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM portfolios WHERE id = ?1 AND user_id = ?2)",
-        rusqlite::params![portfolio_id, user_id],
-        |row| row.get(0),
-    )?;
-    if !exists {
-        return Err(AppError::NotFound("Portfolio not found".into()));
-    }
-    Ok(())
-}
+const WALLET_COLS: &str = "id, portfolio_id, label, wallet_type, descriptor, xpub, address, network, derivation_path, gap_limit, last_synced_at, last_sync_height, balance_sat, created_at, updated_at, last_sync_backend";
 
 pub async fn list(
     State(state): State<AppState>,
@@ -92,7 +99,7 @@ pub async fn list(
     Path(portfolio_id): Path<String>,
 ) -> AppResult<Json<Vec<Wallet>>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
 
     let mut stmt = conn.prepare(&format!(
         "SELECT {WALLET_COLS} FROM wallets WHERE portfolio_id = ?1 ORDER BY created_at DESC"
@@ -108,7 +115,7 @@ pub async fn get(
     Path((portfolio_id, wallet_id)): Path<(String, String)>,
 ) -> AppResult<Json<Wallet>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Read)?;
 
     let wallet = conn
         .query_row(
@@ -126,6 +133,7 @@ pub async fn get(
 pub async fn create(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Json(body): Json<CreateWalletRequest>,
 ) -> AppResult<(StatusCode, Json<Wallet>)> {
     if body.label.is_empty() {
@@ -133,12 +141,20 @@ pub async fn create(
     }
 
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &body.portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &body.portfolio_id, Access::Write)?;
+    quotas::check(&conn, &state.config, &user.id, Quota::Wallets)?;
 
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     let wallet_type = body.wallet_type.as_deref().unwrap_or("descriptor");
-    let network = body.network.as_deref().unwrap_or("bitcoin");
+    let requested = body.network.as_deref().map(wallet_svc::parse_network).transpose()?;
+    let network = wallet_svc::resolve_network(
+        body.descriptor.as_deref(),
+        body.xpub.as_deref(),
+        body.address.as_deref(),
+        requested,
+    )?
+    .to_string();
     let gap_limit = body.gap_limit.unwrap_or(100);
 
     conn.execute(
@@ -150,6 +166,14 @@ pub async fn create(
             body.derivation_path, gap_limit, now, now
         ],
     )?;
+    audit::record(
+        &conn,
+        &user.id,
+        AuditEvent::WalletCreated,
+        Some(&id),
+        serde_json::json!({ "portfolio_id": body.portfolio_id, "label": body.label, "wallet_type": wallet_type }),
+        &client,
+    )?;
 
     let wallet = Wallet {
         id,
@@ -159,11 +183,12 @@ pub async fn create(
         descriptor: body.descriptor,
         xpub: body.xpub,
         address: body.address,
-        network: network.to_string(),
+        network,
         derivation_path: body.derivation_path,
         gap_limit,
         last_synced_at: None,
         last_sync_height: None,
+        last_sync_backend: None,
         balance_sat: 0,
         created_at: now.clone(),
         updated_at: now,
@@ -179,7 +204,7 @@ pub async fn update(
     Json(body): Json<UpdateWalletRequest>,
 ) -> AppResult<Json<Wallet>> {
     let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    policy::portfolio(&state.cache, &conn, &user.id, &portfolio_id, Access::Write)?;
 
     let existing = conn
         .query_row(
@@ -211,28 +236,98 @@ pub async fn update(
     }))
 }
 
+/// DELETE /api/v1/portfolios/{portfolio_id}/wallets/{wallet_id}?transactions=delete|detach
+///
+/// Chain-synced transactions are deleted by default, or kept in the portfolio without a wallet
+/// when `transactions=detach`. Manually entered transactions are always detached, never deleted.
+/// The wallet's BDK database is removed from disk once the rows are gone.
 pub async fn delete(
     State(state): State<AppState>,
     Extension(user): Extension<User>,
+    Extension(client): Extension<ClientInfo>,
     Path((portfolio_id, wallet_id)): Path<(String, String)>,
-) -> AppResult<StatusCode> {
-    let conn = state.db.get()?;
-    verify_portfolio_ownership(&conn, &portfolio_id, &user.id)?;
+    Query(query): Query<DeleteWalletQuery>,
+) -> AppResult<Json<DeleteWalletResponse>> {
+    let detach_chain = match query.transactions.as_deref().unwrap_or("delete") {
+        "delete" => false,
+        "detach" => true,
+        _ => {
+            return Err(AppError::BadRequest(
+                "transactions must be 'delete' or 'detach'".into(),
+            ));
+        }
+    };
 
-    // Delete chain-synced transactions belonging to this wallet before deleting the wallet
-    conn.execute(
-        "DELETE FROM transactions WHERE wallet_id = ?1 AND source = 'chain'",
+    let mut conn = state.db.get()?;
+    let tx = conn.transaction()?;
+    policy::wallet(&state.cache, &tx, &user.id, &portfolio_id, &wallet_id, Access::Manage)?;
+
+    // Detach first — transactions.wallet_id cascades, so anything still linked is deleted with the wallet
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let detach_sql = if detach_chain {
+        "UPDATE transactions SET wallet_id = NULL, updated_at = ?1 WHERE wallet_id = ?2"
+    } else {
+        "UPDATE transactions SET wallet_id = NULL, updated_at = ?1 WHERE wallet_id = ?2 AND source != 'chain' AND parent_id IS NULL"
+    };
+    // Audit each row: snapshot everything linked, then compare once detach and delete are done
+    let linked: Vec<(String, Map<String, Value>)> = {
+        let mut stmt = tx.prepare("SELECT id, transacted_at FROM transactions WHERE wallet_id = ?1")?;
+        let rows = stmt
+            .query_map(rusqlite::params![wallet_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(id, transacted_at)| {
+                tax_locks::check_dates(&tx, &portfolio_id, &id, &[&transacted_at], &user, &client)?;
+                Ok((id.clone(), transaction_audit::snapshot(&tx, &id)?.unwrap_or_default()))
+            })
+            .collect::<AppResult<_>>()?
+    };
+
+    let transactions_detached = tx.execute(detach_sql, rusqlite::params![now, wallet_id])?;
+
+    let transactions_deleted = tx.execute(
+        "DELETE FROM transactions WHERE wallet_id = ?1",
         rusqlite::params![wallet_id],
     )?;
 
-    let affected = conn.execute(
-        "DELETE FROM wallets WHERE id = ?1 AND portfolio_id = ?2",
+    for (id, before) in linked {
+        if transaction_audit::snapshot(&tx, &id)?.is_some() {
+            transaction_audit::record_updated(&tx, &portfolio_id, &id, Some(&user.id), &before)?;
+        } else {
+            transaction_audit::record(&tx, &portfolio_id, &id, Some(&user.id), AuditAction::Delete, &Value::Object(before))?;
+        }
+    }
+
+    let label: String = tx.query_row(
+        "DELETE FROM wallets WHERE id = ?1 AND portfolio_id = ?2 RETURNING label",
         rusqlite::params![wallet_id, portfolio_id],
+        |row| row.get(0),
+    )?;
+    audit::record(
+        &tx,
+        &user.id,
+        AuditEvent::WalletDeleted,
+        Some(&wallet_id),
+        serde_json::json!({
+            "portfolio_id": portfolio_id,
+            "label": label,
+            "transactions_deleted": transactions_deleted,
+            "transactions_detached": transactions_detached,
+        }),
+        &client,
     )?;
+    tx.commit()?;
 
-    if affected == 0 {
-        return Err(AppError::NotFound("Wallet not found".into()));
-    }
+    let bdk_files_removed =
+        wallet_svc::remove_bdk_wallet_files(&state.config.bdk_wallets_dir, &wallet_id)?;
+
+    tracing::info!(
+        "Deleted wallet {wallet_id}: {transactions_deleted} transactions deleted, {transactions_detached} detached, {bdk_files_removed} BDK files removed"
+    );
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(Json(DeleteWalletResponse {
+        transactions_deleted,
+        transactions_detached,
+        bdk_files_removed,
+    }))
 }
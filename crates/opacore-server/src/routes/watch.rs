@@ -0,0 +1,202 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use bdk_wallet::bitcoin::Address;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::quotas::{self, Quota};
+use crate::services::wallet::parse_network;
+use crate::services::watch::{self, WatchedAddress, WatchedTx, WATCH_COLS};
+
+/// Transactions returned with a single watched address.
+const RECENT_TX_LIMIT: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWatchRequest {
+    pub address: String,
+    pub network: Option<String>,
+    pub label: Option<String>,
+    pub notify: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWatchRequest {
+    pub label: Option<String>,
+    pub notify: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchDetailResponse {
+    pub watch: WatchedAddress,
+    pub transactions: Vec<WatchedTx>,
+}
+
+/// GET /api/v1/watch-addresses
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<WatchedAddress>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {WATCH_COLS} FROM watched_addresses WHERE user_id = ?1 ORDER BY created_at DESC"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![user.id], watch::row_to_watch)?;
+    let data: Result<Vec<_>, _> = rows.collect();
+    Ok(Json(data?))
+}
+
+/// POST /api/v1/watch-addresses
+///
+/// The first Esplora check runs straight away to record existing history; only
+/// transactions seen after that trigger notifications.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<CreateWatchRequest>,
+) -> AppResult<(StatusCode, Json<WatchDetailResponse>)> {
+    let network_str = body.network.unwrap_or_else(|| "bitcoin".to_string());
+    let network = parse_network(&network_str)?;
+    let address = body.address.trim();
+    Address::from_str(address)
+        .map_err(|e| AppError::BadRequest(format!("Invalid address: {e}")))?
+        .require_network(network)
+        .map_err(|_| AppError::BadRequest(format!("Address is not a {network} address")))?;
+
+    let id = Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let label = body.label.filter(|l| !l.is_empty());
+    let notify = body.notify.unwrap_or(true);
+
+    {
+        let conn = state.db.get()?;
+        quotas::check(&conn, &state.config, &user.id, Quota::WatchedAddresses)?;
+
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM watched_addresses WHERE user_id = ?1 AND address = ?2)",
+            rusqlite::params![user.id, address],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Err(AppError::Conflict("You are already watching this address".into()));
+        }
+
+        conn.execute(
+            "INSERT INTO watched_addresses (id, user_id, address, network, label, notify, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![id, user.id, address, network_str, label, notify as i32, now, now],
+        )?;
+    }
+
+    let mut watch = load_watch(&state, &user, &id)?;
+    match watch::refresh(&state.db, &state.esplora, &watch).await {
+        Ok(_) => watch = load_watch(&state, &user, &id)?,
+        // The background checker will establish the baseline on its next pass
+        Err(e) => tracing::warn!("Initial check of watched address {id} failed: {e}"),
+    }
+
+    let conn = state.db.get()?;
+    let transactions = watch::recent_txs(&conn, &id, RECENT_TX_LIMIT)?;
+    Ok((StatusCode::CREATED, Json(WatchDetailResponse { watch, transactions })))
+}
+
+/// GET /api/v1/watch-addresses/{id}
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(watch_id): Path<String>,
+) -> AppResult<Json<WatchDetailResponse>> {
+    let watch = load_watch(&state, &user, &watch_id)?;
+    let conn = state.db.get()?;
+    let transactions = watch::recent_txs(&conn, &watch_id, RECENT_TX_LIMIT)?;
+    Ok(Json(WatchDetailResponse { watch, transactions }))
+}
+
+/// PUT /api/v1/watch-addresses/{id}
+///
+/// An empty label clears it.
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(watch_id): Path<String>,
+    Json(body): Json<UpdateWatchRequest>,
+) -> AppResult<Json<WatchedAddress>> {
+    let existing = load_watch(&state, &user, &watch_id)?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let label = match body.label {
+        Some(l) if l.is_empty() => None,
+        Some(l) => Some(l),
+        None => existing.label.clone(),
+    };
+    let notify = body.notify.unwrap_or(existing.notify);
+
+    let conn = state.db.get()?;
+    conn.execute(
+        "UPDATE watched_addresses SET label = ?1, notify = ?2, updated_at = ?3 WHERE id = ?4",
+        rusqlite::params![label, notify as i32, now, watch_id],
+    )?;
+
+    Ok(Json(WatchedAddress {
+        label,
+        notify,
+        updated_at: now,
+        ..existing
+    }))
+}
+
+/// DELETE /api/v1/watch-addresses/{id}
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(watch_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let affected = conn.execute(
+        "DELETE FROM watched_addresses WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![watch_id, user.id],
+    )?;
+
+    if affected == 0 {
+        return Err(AppError::NotFound("Watched address not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/watch-addresses/{id}/refresh
+///
+/// Check the address now instead of waiting for the background checker. Newly seen
+/// transactions are recorded but not emailed.
+pub async fn refresh(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(watch_id): Path<String>,
+) -> AppResult<Json<WatchDetailResponse>> {
+    let watch = load_watch(&state, &user, &watch_id)?;
+    watch::refresh(&state.db, &state.esplora, &watch).await?;
+
+    let watch = load_watch(&state, &user, &watch_id)?;
+    let conn = state.db.get()?;
+    let transactions = watch::recent_txs(&conn, &watch_id, RECENT_TX_LIMIT)?;
+    Ok(Json(WatchDetailResponse { watch, transactions }))
+}
+
+fn load_watch(state: &AppState, user: &User, watch_id: &str) -> AppResult<WatchedAddress> {
+    let conn = state.db.get()?;
+    conn.query_row(
+        &format!("SELECT {WATCH_COLS} FROM watched_addresses WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![watch_id, user.id],
+        watch::row_to_watch,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Watched address not found".into()),
+        e => AppError::Database(e),
+    })
+}
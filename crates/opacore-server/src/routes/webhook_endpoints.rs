@@ -0,0 +1,214 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::routes::AppState;
+use crate::services::http;
+use crate::services::quotas::{self, Quota};
+use crate::services::webhooks::{self, WebhookDelivery, WebhookEndpoint, DELIVERY_COLS, ENDPOINT_COLS};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEndpointRequest {
+    pub url: String,
+    /// Omit to subscribe to every invoice event.
+    pub event_types: Option<Vec<String>>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateEndpointRequest {
+    pub url: Option<String>,
+    /// An empty list subscribes to every invoice event again.
+    pub event_types: Option<Vec<String>>,
+    pub description: Option<String>,
+    /// Paused endpoints keep their queued deliveries until they are enabled again.
+    pub is_active: Option<bool>,
+}
+
+/// The new endpoint with its signing secret, which is not shown again.
+#[derive(Debug, Serialize)]
+pub struct CreatedEndpoint {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    /// `pending`, `delivered` or `failed`.
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// An http(s) URL on a public host. Checked again at each delivery, as DNS can change.
+async fn validate_url(url: &str) -> AppResult<String> {
+    let url = url.trim();
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.host().is_some() => {
+            http::resolve_public(&parsed).await?;
+            Ok(url.to_string())
+        }
+        _ => Err(AppError::BadRequest("url must be an http(s) URL".into())),
+    }
+}
+
+/// Subscribed event types as stored: comma-separated, `None` for all.
+fn validate_event_types(event_types: Option<Vec<String>>) -> AppResult<Option<String>> {
+    let Some(event_types) = event_types.filter(|t| !t.is_empty()) else {
+        return Ok(None);
+    };
+    for event_type in &event_types {
+        if !webhooks::EVENT_TYPES.contains(&event_type.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "Unknown event type '{event_type}'; expected one of {}",
+                webhooks::EVENT_TYPES.join(", ")
+            )));
+        }
+    }
+    Ok(Some(event_types.join(",")))
+}
+
+fn load_endpoint(conn: &rusqlite::Connection, user: &User, endpoint_id: &str) -> AppResult<WebhookEndpoint> {
+    conn.query_row(
+        &format!("SELECT {ENDPOINT_COLS} FROM webhook_endpoints WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![endpoint_id, user.id],
+        webhooks::row_to_endpoint,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Webhook endpoint not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// GET /api/v1/webhook-endpoints
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+) -> AppResult<Json<Vec<WebhookEndpoint>>> {
+    let conn = state.db.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ENDPOINT_COLS} FROM webhook_endpoints WHERE user_id = ?1 ORDER BY created_at"
+    ))?;
+    let endpoints = stmt
+        .query_map(rusqlite::params![user.id], webhooks::row_to_endpoint)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(endpoints))
+}
+
+/// POST /api/v1/webhook-endpoints
+///
+/// Each delivery is a JSON POST signed in `X-Opacore-Signature` as
+/// `t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>">` with the returned secret.
+/// Any 2xx response acknowledges it; anything else is retried with backoff.
+pub async fn create(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Json(body): Json<CreateEndpointRequest>,
+) -> AppResult<(StatusCode, Json<CreatedEndpoint>)> {
+    let url = validate_url(&body.url).await?;
+    let event_types = validate_event_types(body.event_types)?;
+    let description = body.description.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+
+    let conn = state.db.get()?;
+    quotas::check(&conn, &state.config, &user.id, Quota::WebhookEndpoints)?;
+
+    let id = Uuid::new_v4().to_string();
+    let secret = webhooks::generate_secret();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO webhook_endpoints (id, user_id, url, secret, event_types, description, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![id, user.id, url, secret, event_types, description, now, now],
+    )?;
+
+    let endpoint = load_endpoint(&conn, &user, &id)?;
+    Ok((StatusCode::CREATED, Json(CreatedEndpoint { endpoint, secret })))
+}
+
+/// PUT /api/v1/webhook-endpoints/{id}
+pub async fn update(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(endpoint_id): Path<String>,
+    Json(body): Json<UpdateEndpointRequest>,
+) -> AppResult<Json<WebhookEndpoint>> {
+    let conn = state.db.get()?;
+    let existing = load_endpoint(&conn, &user, &endpoint_id)?;
+
+    let url = match &body.url {
+        Some(url) => validate_url(url).await?,
+        None => existing.url,
+    };
+    let event_types = match body.event_types {
+        Some(event_types) => validate_event_types(Some(event_types))?,
+        None => existing.event_types.map(|types| types.join(",")),
+    };
+    let description = match body.description {
+        Some(d) => Some(d.trim().to_string()).filter(|d| !d.is_empty()),
+        None => existing.description,
+    };
+    let is_active = body.is_active.unwrap_or(existing.is_active);
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE webhook_endpoints SET url = ?1, event_types = ?2, description = ?3, is_active = ?4, updated_at = ?5
+         WHERE id = ?6",
+        rusqlite::params![url, event_types, description, is_active as i32, now, endpoint_id],
+    )?;
+
+    Ok(Json(load_endpoint(&conn, &user, &endpoint_id)?))
+}
+
+/// DELETE /api/v1/webhook-endpoints/{id}
+///
+/// Also drops the endpoint's delivery log, including deliveries not yet sent.
+pub async fn delete(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(endpoint_id): Path<String>,
+) -> AppResult<StatusCode> {
+    let conn = state.db.get()?;
+    let affected = conn.execute(
+        "DELETE FROM webhook_endpoints WHERE id = ?1 AND user_id = ?2",
+        rusqlite::params![endpoint_id, user.id],
+    )?;
+    if affected == 0 {
+        return Err(AppError::NotFound("Webhook endpoint not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /api/v1/webhook-endpoints/{id}/deliveries
+///
+/// Newest first, with each attempt's outcome.
+pub async fn deliveries(
+    State(state): State<AppState>,
+    Extension(user): Extension<User>,
+    Path(endpoint_id): Path<String>,
+    Query(query): Query<DeliveriesQuery>,
+) -> AppResult<Json<Vec<WebhookDelivery>>> {
+    if let Some(status) = &query.status {
+        if !matches!(status.as_str(), "pending" | "delivered" | "failed") {
+            return Err(AppError::BadRequest("status must be pending, delivered or failed".into()));
+        }
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+
+    let conn = state.db.get()?;
+    load_endpoint(&conn, &user, &endpoint_id)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {DELIVERY_COLS} FROM webhook_deliveries
+         WHERE endpoint_id = ?1 AND (?2 IS NULL OR status = ?2)
+         ORDER BY created_at DESC, event_id DESC LIMIT ?3"
+    ))?;
+    let deliveries = stmt
+        .query_map(rusqlite::params![endpoint_id, query.status, limit], webhooks::row_to_delivery)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(deliveries))
+}
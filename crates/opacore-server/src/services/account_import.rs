@@ -0,0 +1,594 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::services::quotas::{self, Quota};
+use crate::services::zip;
+
+/// Largest `account.json` inflated out of an archive.
+const MAX_EXPORT_JSON_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Rows whose ids other tables refer to, so their new ids are remembered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Portfolio,
+    Wallet,
+    Customer,
+    Invoice,
+    Transaction,
+    Label,
+}
+
+/// What to do with a column on the way in. Columns not listed are left out, to their
+/// database default, so an archive can't set anything a table doesn't expect it to.
+#[derive(Debug, Clone, Copy)]
+enum Col {
+    /// Copied as it is.
+    Copy,
+    /// A fresh UUID, remembered under the kind if other rows refer to it.
+    NewId(Option<Kind>),
+    /// The row's new id for the old one; a row whose reference can't be resolved is skipped.
+    Ref(Kind),
+    /// As `Ref`, but an unresolved reference is cleared instead.
+    OptRef(Kind),
+    /// As `Ref` where it resolves, otherwise the old id is kept (history of deleted rows).
+    LooseRef(Kind),
+    /// The importing user, wherever the export had a user.
+    User,
+    /// Left to the database default (autoincrement ids).
+    Drop,
+    /// Cleared; the state it describes doesn't exist on this instance.
+    Reset,
+    /// A fresh UUID that nothing refers to, for unique public tokens.
+    Token,
+}
+
+struct TableSpec {
+    table: &'static str,
+    conflict: &'static str,
+    columns: &'static [(&'static str, Col)],
+}
+
+/// The tables of an account export, in an order where every row's references are
+/// restored before it. Attachments aren't restored: their files aren't in the export.
+/// Nor are prices, which every user shares and only the providers may fill in.
+const TABLES: [TableSpec; 20] = [
+    TableSpec {
+        table: "portfolios",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Portfolio))),
+            ("user_id", Col::User),
+            ("name", Col::Copy),
+            ("description", Col::Copy),
+            ("invoice_expiry_hours", Col::Copy),
+            // Membership of an organization is granted there, not claimed by an archive
+            ("organization_id", Col::Reset),
+            ("cost_basis_method", Col::Copy),
+            ("base_currency", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "portfolio_branding",
+        conflict: "",
+        columns: &[
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("business_name", Col::Copy),
+            ("logo_url", Col::Copy),
+            ("accent_color", Col::Copy),
+            ("payment_instructions", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    // Unsynced, so the first sync scans the chain and creates the BDK wallet file
+    TableSpec {
+        table: "wallets",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Wallet))),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("label", Col::Copy),
+            ("wallet_type", Col::Copy),
+            ("descriptor", Col::Copy),
+            ("xpub", Col::Copy),
+            ("address", Col::Copy),
+            ("network", Col::Copy),
+            ("derivation_path", Col::Copy),
+            ("gap_limit", Col::Copy),
+            ("last_synced_at", Col::Reset),
+            ("last_sync_height", Col::Reset),
+            ("last_sync_backend", Col::Reset),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "utxo_metadata",
+        conflict: "",
+        columns: &[
+            ("wallet_id", Col::Ref(Kind::Wallet)),
+            ("txid", Col::Copy),
+            ("vout", Col::Copy),
+            ("label", Col::Copy),
+            ("notes", Col::Copy),
+            ("frozen", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "customers",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Customer))),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("name", Col::Copy),
+            ("email", Col::Copy),
+            ("company", Col::Copy),
+            ("billing_address", Col::Copy),
+            ("tax_id", Col::Copy),
+            ("default_currency", Col::Copy),
+            ("notes", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "invoices",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Invoice))),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("type", Col::Copy),
+            ("reusable", Col::Copy),
+            ("invoice_number", Col::Copy),
+            ("customer_name", Col::Copy),
+            ("customer_email", Col::Copy),
+            ("customer_id", Col::OptRef(Kind::Customer)),
+            ("description", Col::Copy),
+            ("amount_sat", Col::Copy),
+            ("amount_fiat", Col::Copy),
+            ("fiat_currency", Col::Copy),
+            ("btc_price_at_creation", Col::Copy),
+            ("fiat_denominated", Col::Copy),
+            ("rate_btc_price", Col::Copy),
+            ("rate_locked_at", Col::Copy),
+            ("rate_expires_at", Col::Copy),
+            ("btc_address", Col::Copy),
+            ("wallet_id", Col::OptRef(Kind::Wallet)),
+            ("status", Col::Copy),
+            ("share_token", Col::Token),
+            ("share_enabled", Col::Copy),
+            ("issued_at", Col::Copy),
+            ("due_at", Col::Copy),
+            ("expires_at", Col::Copy),
+            ("paid_at", Col::Copy),
+            ("paid_txid", Col::Copy),
+            ("paid_amount_sat", Col::Copy),
+            ("paid_via", Col::Copy),
+            ("lightning_invoice", Col::Copy),
+            ("lightning_payment_hash", Col::Copy),
+            ("lightning_expires_at", Col::Copy),
+            ("address_keychain", Col::Copy),
+            ("address_index", Col::Copy),
+            ("address_derivation_path", Col::Copy),
+            ("address_proof", Col::Copy),
+            ("min_confirmations", Col::Copy),
+            ("sent_at", Col::Copy),
+            ("last_sent_at", Col::Copy),
+            ("send_count", Col::Copy),
+            ("reminder_days_before", Col::Copy),
+            ("reminder_sent_at", Col::Copy),
+            ("view_count", Col::Copy),
+            ("last_viewed_at", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "transactions",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Transaction))),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("wallet_id", Col::Ref(Kind::Wallet)),
+            ("tx_type", Col::Copy),
+            ("amount_sat", Col::Copy),
+            ("fee_sat", Col::Copy),
+            ("price_usd", Col::Copy),
+            ("fiat_amount", Col::Copy),
+            ("fiat_currency", Col::Copy),
+            ("txid", Col::Copy),
+            ("block_height", Col::Copy),
+            ("block_time", Col::Copy),
+            ("source", Col::Copy),
+            ("replaces_txid", Col::Copy),
+            ("parent_id", Col::Ref(Kind::Transaction)),
+            ("counterparty", Col::Copy),
+            ("invoice_id", Col::OptRef(Kind::Invoice)),
+            // Either side may come first, so links are restored once every row is in
+            ("linked_transaction_id", Col::Reset),
+            ("transacted_at", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "transaction_audit",
+        conflict: "",
+        columns: &[
+            ("id", Col::Drop),
+            ("transaction_id", Col::LooseRef(Kind::Transaction)),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("user_id", Col::User),
+            ("action", Col::Copy),
+            ("changes", Col::Copy),
+            ("created_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "recurring_transactions",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("wallet_id", Col::Ref(Kind::Wallet)),
+            ("user_id", Col::User),
+            ("tx_type", Col::Copy),
+            ("amount_sat", Col::Copy),
+            ("fiat_amount", Col::Copy),
+            ("fiat_currency", Col::Copy),
+            ("frequency", Col::Copy),
+            ("starts_at", Col::Copy),
+            ("ends_at", Col::Copy),
+            ("occurrences", Col::Copy),
+            ("next_run_at", Col::Copy),
+            ("is_active", Col::Copy),
+            ("last_error", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "labels",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(Some(Kind::Label))),
+            ("user_id", Col::User),
+            ("name", Col::Copy),
+            ("color", Col::Copy),
+            ("created_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "transaction_labels",
+        conflict: "",
+        columns: &[("transaction_id", Col::Ref(Kind::Transaction)), ("label_id", Col::Ref(Kind::Label))],
+    },
+    TableSpec {
+        table: "invoice_notes",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("invoice_id", Col::Ref(Kind::Invoice)),
+            ("author", Col::Copy),
+            ("author_user_id", Col::User),
+            ("author_name", Col::Copy),
+            ("visibility", Col::Copy),
+            ("body", Col::Copy),
+            ("created_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "invoice_events",
+        conflict: "",
+        columns: &[
+            ("id", Col::Drop),
+            ("invoice_id", Col::Ref(Kind::Invoice)),
+            ("event", Col::Copy),
+            ("from_status", Col::Copy),
+            ("to_status", Col::Copy),
+            ("actor", Col::Copy),
+            ("actor_user_id", Col::User),
+            ("detail", Col::Copy),
+            ("created_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "alerts",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("user_id", Col::User),
+            ("alert_type", Col::Copy),
+            ("threshold_usd", Col::Copy),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("wallet_id", Col::Ref(Kind::Wallet)),
+            ("label", Col::Copy),
+            ("is_active", Col::Copy),
+            ("last_triggered_at", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "watched_addresses",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("user_id", Col::User),
+            ("address", Col::Copy),
+            ("network", Col::Copy),
+            ("label", Col::Copy),
+            ("notify", Col::Copy),
+            ("tx_count", Col::Copy),
+            ("total_received_sat", Col::Copy),
+            ("balance_sat", Col::Copy),
+            ("last_checked_at", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    // Templates are matched by name, so one already saved under the same name wins
+    TableSpec {
+        table: "import_templates",
+        conflict: "OR IGNORE",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("user_id", Col::User),
+            ("name", Col::Copy),
+            ("mapping", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "portfolio_templates",
+        conflict: "OR IGNORE",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("user_id", Col::User),
+            ("name", Col::Copy),
+            ("structure", Col::Copy),
+            ("created_at", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "tax_settings",
+        conflict: "OR REPLACE",
+        columns: &[
+            ("user_id", Col::User),
+            ("long_term_months", Col::Copy),
+            ("long_term_days", Col::Copy),
+            ("fiscal_year_start_month", Col::Copy),
+            ("fiscal_year_start_day", Col::Copy),
+            ("fees_taxable", Col::Copy),
+            ("require_prices", Col::Copy),
+            ("updated_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "disposal_lot_assignments",
+        conflict: "",
+        columns: &[
+            ("id", Col::NewId(None)),
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("disposal_id", Col::Ref(Kind::Transaction)),
+            ("lot_id", Col::Ref(Kind::Transaction)),
+            ("amount_sat", Col::Copy),
+            ("created_at", Col::Copy),
+        ],
+    },
+    TableSpec {
+        table: "tax_year_locks",
+        conflict: "",
+        columns: &[
+            ("portfolio_id", Col::Ref(Kind::Portfolio)),
+            ("year", Col::Copy),
+            ("period_start", Col::Copy),
+            ("period_end", Col::Copy),
+            ("locked_by", Col::User),
+            ("locked_at", Col::Copy),
+        ],
+    },
+];
+
+/// The `account.json` of an archive from `POST /api/v1/auth/account/export`, or the bare
+/// JSON file older exports produced.
+pub fn parse_export(body: &[u8]) -> AppResult<Map<String, Value>> {
+    let json = if body.starts_with(b"PK\x03\x04") {
+        zip::read_entry(body, "account.json", MAX_EXPORT_JSON_BYTES)
+            .map_err(|e| AppError::BadRequest(format!("Could not read the archive: {e}")))?
+            .ok_or_else(|| AppError::BadRequest("The archive has no account.json".into()))?
+    } else {
+        body.to_vec()
+    };
+    match serde_json::from_slice::<Value>(&json) {
+        Ok(Value::Object(export)) if export.contains_key("exported_at") && export.contains_key("portfolios") => Ok(export),
+        _ => Err(AppError::BadRequest("Not an Opacore account export".into())),
+    }
+}
+
+/// Restore `export` into the user's account, which must not hold any data yet (empty
+/// portfolios are replaced). Every row gets a new id, so an export can be restored on
+/// the instance it came from.
+/// Returns how many rows of each table were restored.
+pub fn restore(
+    conn: &mut rusqlite::Connection,
+    config: &Config,
+    user_id: &str,
+    export: &Map<String, Value>,
+) -> AppResult<Map<String, Value>> {
+    let tx = conn.transaction()?;
+    let has_data: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM portfolios p WHERE p.user_id = ?1 AND (
+                    EXISTS(SELECT 1 FROM wallets WHERE portfolio_id = p.id)
+                 OR EXISTS(SELECT 1 FROM transactions WHERE portfolio_id = p.id)
+                 OR EXISTS(SELECT 1 FROM invoices WHERE portfolio_id = p.id)
+                 OR EXISTS(SELECT 1 FROM customers WHERE portfolio_id = p.id)
+                 OR EXISTS(SELECT 1 FROM recurring_transactions WHERE portfolio_id = p.id)))
+             OR EXISTS(SELECT 1 FROM labels WHERE user_id = ?1)
+             OR EXISTS(SELECT 1 FROM watched_addresses WHERE user_id = ?1)",
+        rusqlite::params![user_id],
+        |row| row.get(0),
+    )?;
+    if has_data {
+        return Err(AppError::Conflict("Exports can only be restored into an account that holds no data yet".into()));
+    }
+    // Such as the one created at sign-up; the export brings its own
+    tx.execute("DELETE FROM portfolios WHERE user_id = ?1", rusqlite::params![user_id])?;
+
+    let mut ids: HashMap<(Kind, String), String> = HashMap::new();
+    let mut counts = Map::new();
+    for spec in &TABLES {
+        let restored = restore_table(&tx, spec, user_id, export, &mut ids)?;
+        counts.insert(spec.table.to_string(), restored.into());
+    }
+    restore_transfer_links(&tx, export, &ids)?;
+
+    // The export may come from an instance with higher limits
+    for quota in [Quota::Portfolios, Quota::Wallets, Quota::WatchedAddresses] {
+        if let Some(limit) = quotas::limit(&tx, config, user_id, quota)? {
+            if quotas::usage(&tx, user_id, quota)? > limit {
+                return Err(AppError::Forbidden(format!(
+                    "The export has more {} than your account's limit of {limit}",
+                    quota.name().replace('_', " ")
+                )));
+            }
+        }
+    }
+
+    tx.commit()?;
+    Ok(counts)
+}
+
+/// Link the restored sides of each transfer to one another again.
+fn restore_transfer_links(
+    tx: &rusqlite::Transaction,
+    export: &Map<String, Value>,
+    ids: &HashMap<(Kind, String), String>,
+) -> AppResult<()> {
+    let Some(rows) = export.get("transactions").and_then(Value::as_array) else {
+        return Ok(());
+    };
+    let new_id = |old: Option<&Value>| ids.get(&(Kind::Transaction, old?.as_str()?.to_string()));
+    for row in rows.iter().filter_map(Value::as_object) {
+        if let (Some(id), Some(linked)) = (new_id(row.get("id")), new_id(row.get("linked_transaction_id"))) {
+            tx.execute(
+                "UPDATE transactions SET linked_transaction_id = ?1 WHERE id = ?2",
+                rusqlite::params![linked, id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn restore_table(
+    tx: &rusqlite::Transaction,
+    spec: &TableSpec,
+    user_id: &str,
+    export: &Map<String, Value>,
+    ids: &mut HashMap<(Kind, String), String>,
+) -> AppResult<u64> {
+    let Some(rows) = export.get(spec.table).and_then(Value::as_array) else {
+        return Ok(0);
+    };
+    // Exports from other versions may have columns this schema doesn't, or lack some it has
+    let known: HashSet<String> = tx
+        .prepare(&format!("SELECT name FROM pragma_table_info('{}')", spec.table))?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let rule = |column: &str| spec.columns.iter().find(|(name, _)| *name == column).map(|(_, col)| *col);
+
+    // Split transactions refer to their parent, so parents go first
+    let mut rows: Vec<&Map<String, Value>> = rows.iter().filter_map(Value::as_object).collect();
+    if spec.table == "transactions" {
+        rows.sort_by_key(|row| !row.get("parent_id").is_none_or(Value::is_null));
+    }
+
+    let mut restored = 0;
+    'rows: for row in rows {
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+        let mut new_id = None;
+        for (column, value) in row {
+            if !known.contains(column) {
+                continue;
+            }
+            let Some(rule) = rule(column) else {
+                continue;
+            };
+            let value = match (rule, value) {
+                (Col::Copy, value) => value.clone(),
+                (Col::Drop, _) => continue,
+                (Col::Reset, _) => Value::Null,
+                (Col::NewId(kind), old) => {
+                    let id = uuid::Uuid::new_v4().to_string();
+                    if let (Some(kind), Some(old)) = (kind, old.as_str()) {
+                        new_id = Some(((kind, old.to_string()), id.clone()));
+                    }
+                    Value::String(id)
+                }
+                (Col::Token, _) => Value::String(uuid::Uuid::new_v4().to_string()),
+                (Col::User, Value::Null) => Value::Null,
+                (Col::User, _) => Value::String(user_id.to_string()),
+                (Col::Ref(_) | Col::OptRef(_) | Col::LooseRef(_), Value::Null) => Value::Null,
+                (Col::Ref(kind), Value::String(old)) => match ids.get(&(kind, old.clone())) {
+                    Some(id) => Value::String(id.clone()),
+                    None => continue 'rows,
+                },
+                (Col::OptRef(kind), Value::String(old)) => {
+                    ids.get(&(kind, old.clone())).cloned().map_or(Value::Null, Value::String)
+                }
+                (Col::LooseRef(kind), Value::String(old)) => {
+                    Value::String(ids.get(&(kind, old.clone())).unwrap_or(old).clone())
+                }
+                // A reference that isn't an id
+                (Col::Ref(_), _) => continue 'rows,
+                (Col::OptRef(_) | Col::LooseRef(_), _) => Value::Null,
+            };
+            columns.push(column.as_str());
+            values.push(sql_value(value));
+        }
+        if columns.is_empty() {
+            continue;
+        }
+
+        let placeholders = (1..=columns.len()).map(|i| format!("?{i}")).collect::<Vec<_>>().join(", ");
+        let sql = format!(
+            "INSERT {} INTO {} ({}) VALUES ({placeholders})",
+            spec.conflict,
+            spec.table,
+            columns.join(", ")
+        );
+        let inserted = tx
+            .execute(&sql, rusqlite::params_from_iter(values))
+            .map_err(|e| AppError::BadRequest(format!("Could not restore a row of {}: {e}", spec.table)))?;
+        if inserted > 0 {
+            restored += 1;
+            if let Some((old, id)) = new_id {
+                ids.insert(old, id);
+            }
+        }
+    }
+    Ok(restored)
+}
+
+fn sql_value(value: Value) -> rusqlite::types::Value {
+    use rusqlite::types::Value as Sql;
+    match value {
+        Value::Null => Sql::Null,
+        Value::Bool(b) => Sql::Integer(b.into()),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Sql::Integer(i),
+            None => n.as_f64().map_or(Sql::Null, Sql::Real),
+        },
+        Value::String(s) => Sql::Text(s),
+        // Nested JSON is stored as text, as the export read it
+        other => Sql::Text(other.to_string()),
+    }
+}
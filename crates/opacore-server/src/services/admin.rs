@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+
+/// Sync failures listed in the usage stats.
+const RECENT_SYNC_FAILURES: i64 = 20;
+
+#[derive(Debug, Serialize)]
+pub struct UsageStats {
+    pub users: i64,
+    pub verified_users: i64,
+    pub disabled_users: i64,
+    pub admins: i64,
+    pub signups_last_7_days: i64,
+    /// Users with a session used in the last 7 days.
+    pub active_users_last_7_days: i64,
+    pub portfolios: i64,
+    pub wallets: i64,
+    pub wallets_by_type: BTreeMap<String, i64>,
+    pub transactions: i64,
+    pub sync_failures_last_24_hours: i64,
+    pub sync_failures_last_7_days: i64,
+    /// Newest first.
+    pub recent_sync_failures: Vec<SyncFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncFailure {
+    pub sync_job_id: String,
+    pub wallet_id: String,
+    pub user_id: String,
+    pub email: String,
+    pub backend: Option<String>,
+    pub error: Option<String>,
+    pub failed_at: String,
+}
+
+/// Make the account registered with `ADMIN_EMAIL`, if any, an admin, so a fresh
+/// install has someone who can reach the admin routes.
+pub fn promote_configured_admin(pool: &DbPool, admin_email: Option<&str>) {
+    let Some(email) = admin_email else { return };
+    let promoted = pool.get().map_err(|e| e.to_string()).and_then(|conn| {
+        conn.execute(
+            "UPDATE users SET is_admin = 1 WHERE email = ?1 COLLATE NOCASE AND is_admin = 0",
+            rusqlite::params![email],
+        )
+        .map_err(|e| e.to_string())
+    });
+    match promoted {
+        Ok(0) => {}
+        Ok(_) => tracing::info!("Made {email} (ADMIN_EMAIL) an admin"),
+        Err(e) => tracing::warn!("Failed to make ADMIN_EMAIL an admin: {e}"),
+    }
+}
+
+pub fn usage_stats(conn: &rusqlite::Connection) -> AppResult<UsageStats> {
+    let now = chrono::Utc::now();
+    let ago = |duration: chrono::Duration| (now - duration).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let day_ago = ago(chrono::Duration::days(1));
+    let week_ago = ago(chrono::Duration::days(7));
+    let count = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> AppResult<i64> {
+        Ok(conn.query_row(sql, params, |row| row.get(0))?)
+    };
+
+    let failures = "SELECT COUNT(*) FROM sync_jobs WHERE status = 'failed' AND COALESCE(finished_at, updated_at) > ?1";
+    let wallets_by_type = conn
+        .prepare("SELECT wallet_type, COUNT(*) FROM wallets GROUP BY wallet_type")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<BTreeMap<String, i64>, _>>()?;
+    let recent_sync_failures = conn
+        .prepare(
+            "SELECT j.id, j.wallet_id, u.id, u.email, j.backend, j.error, COALESCE(j.finished_at, j.updated_at) AS failed_at
+             FROM sync_jobs j
+             JOIN wallets w ON w.id = j.wallet_id
+             JOIN portfolios p ON p.id = w.portfolio_id
+             JOIN users u ON u.id = p.user_id
+             WHERE j.status = 'failed'
+             ORDER BY failed_at DESC LIMIT ?1",
+        )?
+        .query_map(rusqlite::params![RECENT_SYNC_FAILURES], |row| {
+            Ok(SyncFailure {
+                sync_job_id: row.get(0)?,
+                wallet_id: row.get(1)?,
+                user_id: row.get(2)?,
+                email: row.get(3)?,
+                backend: row.get(4)?,
+                error: row.get(5)?,
+                failed_at: row.get(6)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(UsageStats {
+        users: count("SELECT COUNT(*) FROM users", &[])?,
+        verified_users: count("SELECT COUNT(*) FROM users WHERE email_verified = 1", &[])?,
+        disabled_users: count("SELECT COUNT(*) FROM users WHERE disabled_at IS NOT NULL", &[])?,
+        admins: count("SELECT COUNT(*) FROM users WHERE is_admin = 1", &[])?,
+        signups_last_7_days: count("SELECT COUNT(*) FROM users WHERE created_at > ?1", &[&week_ago])?,
+        active_users_last_7_days: count(
+            "SELECT COUNT(DISTINCT user_id) FROM sessions WHERE COALESCE(last_used_at, created_at) > ?1",
+            &[&week_ago],
+        )?,
+        portfolios: count("SELECT COUNT(*) FROM portfolios", &[])?,
+        wallets: wallets_by_type.values().sum(),
+        wallets_by_type,
+        transactions: count("SELECT COUNT(*) FROM transactions", &[])?,
+        sync_failures_last_24_hours: count(failures, &[&day_ago])?,
+        sync_failures_last_7_days: count(failures, &[&week_ago])?,
+        recent_sync_failures,
+    })
+}
@@ -1,7 +1,8 @@
 use crate::config::Config;
 use crate::db::DbPool;
 use crate::services::email::send_email;
-use crate::services::prices::fetch_current_price;
+use crate::services::cache::AppCache;
+use crate::services::prices::live_price;
 
 // ── Email templates ────────────────────────────────────────────────────────────
 
@@ -82,8 +83,8 @@ fn get_wallet_label(pool: &DbPool, wallet_id: &str) -> Option<String> {
 
 // ── Price alert checker ────────────────────────────────────────────────────────
 
-async fn check_price_alerts(pool: &DbPool, config: &Config) {
-    let current_price = match fetch_current_price(&config.coingecko_api_url, "usd").await {
+async fn check_price_alerts(pool: &DbPool, config: &Config, cache: &AppCache) {
+    let current_price = match live_price(pool, cache, &config.coingecko_api_url, "usd").await {
         Ok(p) => p,
         Err(e) => {
             tracing::warn!("Alert checker: failed to fetch BTC price: {e}");
@@ -187,16 +188,18 @@ async fn check_price_alerts(pool: &DbPool, config: &Config) {
             }
         });
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+        tokio::time::sleep(config.intervals.alert_email_delay).await;
     }
 }
 
 // ── Balance alert checker ──────────────────────────────────────────────────────
 
+/// (id, email, wallet_id, portfolio_id, last_triggered_at, label)
+type BalanceAlertRow = (String, String, Option<String>, Option<String>, Option<String>, Option<String>);
+
 async fn check_balance_alerts(pool: &DbPool, config: &Config) {
     // Collect active balance_change alerts — drop connection before any await
-    // Returns: (id, email, wallet_id, portfolio_id, last_triggered_at, label)
-    let alerts: Vec<(String, String, Option<String>, Option<String>, Option<String>, Option<String>)> = {
+    let alerts: Vec<BalanceAlertRow> = {
         let conn = match pool.get() {
             Ok(c) => c,
             Err(e) => {
@@ -258,8 +261,9 @@ async fn check_balance_alerts(pool: &DbPool, config: &Config) {
                 (
                     "SELECT id, amount_sat, COALESCE(txid, id) FROM transactions
                      WHERE wallet_id = ?1
-                       AND tx_type IN ('receive', 'buy')
+                       AND tx_type IN ('receive', 'buy', 'income', 'mining')
                        AND amount_sat > 0
+                       AND parent_id IS NULL
                        AND created_at > ?2
                      ORDER BY created_at ASC
                      LIMIT 10"
@@ -270,8 +274,9 @@ async fn check_balance_alerts(pool: &DbPool, config: &Config) {
                 (
                     "SELECT id, amount_sat, COALESCE(txid, id) FROM transactions
                      WHERE portfolio_id = ?1
-                       AND tx_type IN ('receive', 'buy')
+                       AND tx_type IN ('receive', 'buy', 'income', 'mining')
                        AND amount_sat > 0
+                       AND parent_id IS NULL
                        AND created_at > ?2
                      ORDER BY created_at ASC
                      LIMIT 10"
@@ -356,20 +361,23 @@ async fn check_balance_alerts(pool: &DbPool, config: &Config) {
                 }
             });
 
-            tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+            tokio::time::sleep(config.intervals.alert_email_delay).await;
         }
     }
 }
 
 // ── Background runner ──────────────────────────────────────────────────────────
 
-pub async fn run_alert_checker(pool: DbPool, config: Config) {
-    tracing::info!("Alert checker background task started (interval: 5 minutes)");
+pub async fn run_alert_checker(pool: DbPool, config: Config, cache: AppCache) {
+    tracing::info!(
+        "Alert checker background task started (interval: {}s)",
+        config.intervals.alert_check.as_secs()
+    );
 
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+        tokio::time::sleep(config.intervals.alert_check).await;
 
-        check_price_alerts(&pool, &config).await;
+        check_price_alerts(&pool, &config, &cache).await;
         check_balance_alerts(&pool, &config).await;
     }
 }
@@ -0,0 +1,247 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::{AttachmentStorage, Config, S3Config};
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::http;
+
+/// Stored files deleted per purge round.
+const PURGE_BATCH: i64 = 500;
+
+#[derive(Debug, Serialize)]
+pub struct Attachment {
+    pub id: String,
+    pub transaction_id: String,
+    pub user_id: Option<String>,
+    pub file_name: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    #[serde(skip)]
+    pub storage_key: String,
+    pub created_at: String,
+}
+
+pub const ATTACHMENT_COLS: &str =
+    "id, transaction_id, user_id, file_name, content_type, size_bytes, sha256, storage_key, created_at";
+
+pub fn row_to_attachment(row: &rusqlite::Row) -> rusqlite::Result<Attachment> {
+    Ok(Attachment {
+        id: row.get(0)?,
+        transaction_id: row.get(1)?,
+        user_id: row.get(2)?,
+        file_name: row.get(3)?,
+        content_type: row.get(4)?,
+        size_bytes: row.get(5)?,
+        sha256: row.get(6)?,
+        storage_key: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+pub fn load_attachment(conn: &rusqlite::Connection, transaction_id: &str, attachment_id: &str) -> AppResult<Attachment> {
+    conn.query_row(
+        &format!("SELECT {ATTACHMENT_COLS} FROM transaction_attachments WHERE id = ?1 AND transaction_id = ?2"),
+        rusqlite::params![attachment_id, transaction_id],
+        row_to_attachment,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Attachment not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// A file name safe to store and to echo back in `Content-Disposition`: no path, quotes
+/// or control characters, and at most 200 characters.
+pub fn clean_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '"' | ';'))
+        .take(200)
+        .collect();
+    match cleaned.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// ── Storage ──
+
+pub async fn put(storage: &AttachmentStorage, key: &str, data: Vec<u8>, content_type: &str) -> AppResult<()> {
+    match storage {
+        AttachmentStorage::Local { dir } => {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to create attachments dir: {e}")))?;
+            // Written aside and renamed so a half-written file is never served
+            let path = format!("{dir}/{key}");
+            let part = format!("{path}.part");
+            tokio::fs::write(&part, data)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to store attachment: {e}")))?;
+            tokio::fs::rename(&part, &path)
+                .await
+                .map_err(|e| AppError::Internal(format!("Failed to store attachment: {e}")))
+        }
+        AttachmentStorage::S3(s3) => {
+            s3_request(s3, reqwest::Method::PUT, key, data, Some(content_type)).await?;
+            Ok(())
+        }
+    }
+}
+
+pub async fn get(storage: &AttachmentStorage, key: &str) -> AppResult<Vec<u8>> {
+    match storage {
+        AttachmentStorage::Local { dir } => tokio::fs::read(format!("{dir}/{key}")).await.map_err(|e| {
+            tracing::warn!("Attachment file {key} unreadable: {e}");
+            AppError::NotFound("Attachment file is missing".into())
+        }),
+        AttachmentStorage::S3(s3) => s3_request(s3, reqwest::Method::GET, key, Vec::new(), None).await,
+    }
+}
+
+/// Delete a stored file. Already gone counts as deleted.
+pub async fn delete(storage: &AttachmentStorage, key: &str) -> AppResult<()> {
+    match storage {
+        AttachmentStorage::Local { dir } => match tokio::fs::remove_file(format!("{dir}/{key}")).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(AppError::Internal(format!("Failed to delete attachment {key}: {e}")))
+            }
+            _ => Ok(()),
+        },
+        AttachmentStorage::S3(s3) => {
+            s3_request(s3, reqwest::Method::DELETE, key, Vec::new(), None).await?;
+            Ok(())
+        }
+    }
+}
+
+/// One request to `{endpoint}/{bucket}/{key}`, signed with AWS Signature Version 4.
+async fn s3_request(
+    s3: &S3Config,
+    method: reqwest::Method,
+    key: &str,
+    body: Vec<u8>,
+    content_type: Option<&str>,
+) -> AppResult<Vec<u8>> {
+    let url = reqwest::Url::parse(&format!("{}/{}/{key}", s3.endpoint, s3.bucket))
+        .map_err(|e| AppError::Internal(format!("Invalid S3_ENDPOINT: {e}")))?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(AppError::Internal("S3_ENDPOINT has no host".into())),
+    };
+
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = sha256_hex(&body);
+    let scope = format!("{date}/{}/s3/aws4_request", s3.region);
+
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}",
+        path = url.path(),
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        sha256_hex(canonical_request.as_bytes())
+    );
+
+    let hmac = |key: &[u8], data: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    };
+    let signing_key = ["s3", "aws4_request"].iter().fold(
+        hmac(
+            &hmac(format!("AWS4{}", s3.secret_access_key).as_bytes(), &date),
+            &s3.region,
+        ),
+        |key, part| hmac(&key, part),
+    );
+    let signature = hex::encode(hmac(&signing_key, &string_to_sign));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        s3.access_key_id
+    );
+
+    let mut request = http::client()?
+        .request(method.clone(), url)
+        .header("x-amz-content-sha256", &payload_hash)
+        .header("x-amz-date", &amz_date)
+        .header(reqwest::header::AUTHORIZATION, authorization);
+    if let Some(content_type) = content_type {
+        request = request.header(reqwest::header::CONTENT_TYPE, content_type);
+    }
+    let resp = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("s3", "S3 request failed", e))?;
+
+    let status = resp.status();
+    if status == reqwest::StatusCode::NOT_FOUND && method == reqwest::Method::GET {
+        return Err(AppError::NotFound("Attachment file is missing".into()));
+    }
+    let already_gone = status == reqwest::StatusCode::NOT_FOUND && method == reqwest::Method::DELETE;
+    if !status.is_success() && !already_gone {
+        let detail = resp.text().await.unwrap_or_default();
+        return Err(AppError::Internal(format!("S3 {method} {key} returned {status}: {detail}")));
+    }
+    let data = resp
+        .bytes()
+        .await
+        .map_err(|e| http::upstream_error("s3", "S3 response read failed", e))?;
+    Ok(data.to_vec())
+}
+
+// ── Purging ──
+
+/// Delete the stored files of attachment rows that are gone, however they went.
+/// Files that fail to delete stay queued for the next round.
+pub async fn purge_deleted(pool: &DbPool, config: &Config) -> AppResult<usize> {
+    let keys: Vec<String> = {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare("SELECT storage_key FROM attachment_deletions ORDER BY created_at LIMIT ?1")?;
+        let keys = stmt
+            .query_map(rusqlite::params![PURGE_BATCH], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        keys
+    };
+
+    let mut purged = 0;
+    for key in keys {
+        if let Err(e) = delete(&config.attachment_storage, &key).await {
+            tracing::warn!("Attachment purge: {e}");
+            continue;
+        }
+        let conn = pool.get()?;
+        conn.execute("DELETE FROM attachment_deletions WHERE storage_key = ?1", rusqlite::params![key])?;
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+/// Background task that purges deleted attachments' files.
+pub async fn run_attachment_purger(pool: DbPool, config: Config) {
+    tracing::info!(
+        "Attachment purger started (interval: {}s)",
+        config.intervals.attachment_purge.as_secs()
+    );
+    loop {
+        match purge_deleted(&pool, &config).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Purged {n} deleted attachment files"),
+            Err(e) => tracing::warn!("Attachment purge failed: {e}"),
+        }
+        tokio::time::sleep(config.intervals.attachment_purge).await;
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::session::ClientInfo;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Security-sensitive actions kept in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditEvent {
+    /// Signed in; `details.method` is "password", "passkey" or "email_verification".
+    Login,
+    /// A wrong password for an existing account.
+    LoginFailed,
+    /// `details.via` is "change" (signed in) or "reset" (emailed link).
+    PasswordChanged,
+    ApiKeyCreated,
+    ApiKeyDeleted,
+    WalletCreated,
+    WalletDeleted,
+    /// `details.kind` says what was exported.
+    DataExported,
+    /// The target is the portfolio; `details.year` the tax year.
+    TaxYearLocked,
+    TaxYearUnlocked,
+    /// An admin changed a transaction (the target) dated in a locked tax year.
+    LockedTaxYearOverridden,
+}
+
+impl AuditEvent {
+    pub const ALL: [AuditEvent; 11] = [
+        AuditEvent::Login,
+        AuditEvent::LoginFailed,
+        AuditEvent::PasswordChanged,
+        AuditEvent::ApiKeyCreated,
+        AuditEvent::ApiKeyDeleted,
+        AuditEvent::WalletCreated,
+        AuditEvent::WalletDeleted,
+        AuditEvent::DataExported,
+        AuditEvent::TaxYearLocked,
+        AuditEvent::TaxYearUnlocked,
+        AuditEvent::LockedTaxYearOverridden,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditEvent::Login => "login",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::PasswordChanged => "password_changed",
+            AuditEvent::ApiKeyCreated => "api_key_created",
+            AuditEvent::ApiKeyDeleted => "api_key_deleted",
+            AuditEvent::WalletCreated => "wallet_created",
+            AuditEvent::WalletDeleted => "wallet_deleted",
+            AuditEvent::DataExported => "data_exported",
+            AuditEvent::TaxYearLocked => "tax_year_locked",
+            AuditEvent::TaxYearUnlocked => "tax_year_unlocked",
+            AuditEvent::LockedTaxYearOverridden => "locked_tax_year_overridden",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        AuditEvent::ALL.into_iter().find(|e| e.name() == name)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub user_id: String,
+    pub action: String,
+    pub target_id: Option<String>,
+    pub details: Value,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+}
+
+pub const AUDIT_LOG_COLS: &str = "id, user_id, action, target_id, details, ip_address, user_agent, created_at";
+
+pub fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<AuditLogEntry> {
+    let details: String = row.get(4)?;
+    Ok(AuditLogEntry {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        action: row.get(2)?,
+        target_id: row.get(3)?,
+        details: serde_json::from_str(&details).unwrap_or(Value::Null),
+        ip_address: row.get(5)?,
+        user_agent: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// Add an entry for `user_id`, made from `client`.
+pub fn record(
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    event: AuditEvent,
+    target_id: Option<&str>,
+    details: Value,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO audit_log (user_id, action, target_id, details, ip_address, user_agent, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            user_id,
+            event.name(),
+            target_id,
+            details.to_string(),
+            client.ip_address,
+            client.user_agent,
+            now,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Audit a sign-in; the new session is the target.
+pub fn record_login(pool: &DbPool, user_id: &str, session_id: &str, method: &str, client: &ClientInfo) -> AppResult<()> {
+    let conn = pool.get()?;
+    record(&conn, user_id, AuditEvent::Login, Some(session_id), serde_json::json!({ "method": method }), client)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogQuery {
+    /// Only this action, e.g. "login_failed".
+    pub action: Option<String>,
+    /// Only entries older than this one: the last `id` of the previous page.
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Entries newest first, for one user or (with `None`) everyone.
+pub fn list(conn: &rusqlite::Connection, user_id: Option<&str>, query: &AuditLogQuery) -> AppResult<Vec<AuditLogEntry>> {
+    let action = query
+        .action
+        .as_deref()
+        .map(|name| {
+            AuditEvent::parse(name).ok_or_else(|| {
+                let valid: Vec<&str> = AuditEvent::ALL.iter().map(|e| e.name()).collect();
+                AppError::BadRequest(format!("Unknown action '{name}'; expected one of {}", valid.join(", ")))
+            })
+        })
+        .transpose()?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {AUDIT_LOG_COLS} FROM audit_log
+         WHERE (?1 IS NULL OR user_id = ?1) AND (?2 IS NULL OR action = ?2) AND (?3 IS NULL OR id < ?3)
+         ORDER BY id DESC LIMIT ?4"
+    ))?;
+    let entries = stmt
+        .query_map(
+            rusqlite::params![user_id, action.map(AuditEvent::name), query.before_id, limit],
+            row_to_entry,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(entries)
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::costbasis::{self, TxKind};
+use crate::services::value_history;
+
+/// The portfolio's value against what the same money would be worth under simple
+/// strategies. Money in is every inflow at its recorded price; money out is every
+/// outflow at its recorded price, counted towards the actual value as if kept in cash.
+#[derive(Debug, Serialize)]
+pub struct Benchmark {
+    pub currency: String,
+    pub start: String,
+    pub end: String,
+    pub contributed: f64,
+    pub withdrawn: f64,
+    /// Inflows and outflows with no price, left out of the money in and out.
+    pub unpriced: i64,
+    /// At `end`. `None` with no price for it.
+    pub actual_value: Option<f64>,
+    pub lump_sum_value: Option<f64>,
+    pub dca_value: Option<f64>,
+    /// `actual_value` less each strategy's: what trading added (or, negative, cost).
+    pub vs_lump_sum: Option<f64>,
+    pub vs_dca: Option<f64>,
+    pub points: Vec<BenchmarkPoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchmarkPoint {
+    pub date: String,
+    pub price: Option<f64>,
+    /// Money in and out up to the end of the day.
+    pub contributed: f64,
+    pub withdrawn: f64,
+    /// The balance's value plus everything withdrawn.
+    pub actual_value: Option<f64>,
+    /// Everything ever contributed, spent at the first inflow's price and held.
+    pub lump_sum_value: Option<f64>,
+    /// Everything ever contributed, spent in equal parts at the start of each month from
+    /// the first inflow's to `end`'s, and held. A month with no price yet buys at the
+    /// next day that has one.
+    pub dca_value: Option<f64>,
+}
+
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn benchmark(pool: &DbPool, portfolio_id: &str, currency: &str) -> AppResult<Benchmark> {
+    let txs = costbasis::load_tx_records_in(pool, portfolio_id, currency)?;
+
+    // Money in and out per day, and the price of the first inflow
+    let mut flows: BTreeMap<NaiveDate, (f64, f64)> = BTreeMap::new();
+    let mut first_inflow: Option<(NaiveDate, f64)> = None;
+    let mut unpriced = 0;
+    for tx in &txs {
+        let Some(kind) = TxKind::of(&tx.tx_type) else {
+            continue;
+        };
+        if !kind.is_inflow() && !kind.is_outflow() {
+            continue;
+        }
+        let (Some(price), Some(day)) = (tx.price_usd, parse_day(&tx.transacted_at)) else {
+            unpriced += 1;
+            continue;
+        };
+        let amount = tx.amount_sat as f64 / 1e8 * price;
+        let (contributed, withdrawn) = flows.entry(day).or_default();
+        if kind.is_inflow() {
+            *contributed += amount;
+            if first_inflow.is_none_or(|(first, _)| day < first) {
+                first_inflow = Some((day, price));
+            }
+        } else {
+            *withdrawn += amount;
+        }
+    }
+    let total_contributed: f64 = flows.values().map(|(contributed, _)| contributed).sum();
+
+    let history = value_history::value_history(pool, portfolio_id, currency, None, None)?;
+    let end = parse_day(&history.end);
+
+    let lump_sum_btc = first_inflow.filter(|(_, price)| *price > 0.0).map(|(_, price)| total_contributed / price);
+    let monthly = match (first_inflow, end) {
+        (Some((first, _)), Some(end)) if end >= first => {
+            let months = (end.year() - first.year()) * 12 + end.month() as i32 - first.month() as i32 + 1;
+            total_contributed / months as f64
+        }
+        _ => 0.0,
+    };
+
+    let (mut contributed, mut withdrawn) = (0.0, 0.0);
+    let (mut dca_btc, mut dca_pending) = (0.0, 0.0);
+    let mut points = Vec::with_capacity(history.points.len());
+    for point in history.points {
+        let day = parse_day(&point.date);
+        if let Some((c, w)) = day.and_then(|day| flows.get(&day)) {
+            contributed += c;
+            withdrawn += w;
+        }
+
+        let started = first_inflow.zip(day).filter(|((first, _), day)| day >= first);
+        if let Some(((first, _), day)) = started {
+            if day == first || day.day() == 1 {
+                dca_pending += monthly;
+            }
+            if let Some(price) = point.price.filter(|price| *price > 0.0) {
+                dca_btc += dca_pending / price;
+                dca_pending = 0.0;
+            }
+        }
+
+        let lump_sum_value = match started {
+            Some(_) => lump_sum_btc.zip(point.price).map(|(btc, price)| btc * price),
+            None => Some(0.0),
+        };
+        points.push(BenchmarkPoint {
+            actual_value: point.value.map(|value| round2(value + withdrawn)),
+            lump_sum_value: lump_sum_value.map(round2),
+            dca_value: point.price.map(|price| round2(dca_btc * price + dca_pending)),
+            contributed: round2(contributed),
+            withdrawn: round2(withdrawn),
+            price: point.price,
+            date: point.date,
+        });
+    }
+
+    let last = points.last();
+    let actual_value = last.and_then(|p| p.actual_value);
+    let lump_sum_value = last.and_then(|p| p.lump_sum_value);
+    let dca_value = last.and_then(|p| p.dca_value);
+    Ok(Benchmark {
+        currency: currency.to_string(),
+        start: history.start,
+        end: history.end,
+        contributed: round2(total_contributed),
+        withdrawn: round2(flows.values().map(|(_, withdrawn)| withdrawn).sum()),
+        unpriced,
+        actual_value,
+        lump_sum_value,
+        dca_value,
+        vs_lump_sum: actual_value.zip(lump_sum_value).map(|(a, b)| round2(a - b)),
+        vs_dca: actual_value.zip(dca_value).map(|(a, b)| round2(a - b)),
+        points,
+    })
+}
+
+fn parse_day(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.get(..10).unwrap_or(date), "%Y-%m-%d").ok()
+}
+
+fn round2(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+
+pub const BUSINESS_NAME_MAX_CHARS: usize = 100;
+pub const LOGO_URL_MAX_CHARS: usize = 2048;
+pub const PAYMENT_INSTRUCTIONS_MAX_CHARS: usize = 1000;
+
+/// How a portfolio's invoices present the merchant: on the payment page, in the PDF and
+/// in invoice emails. Unset fields fall back to the owner's name and the default look.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Branding {
+    pub business_name: Option<String>,
+    /// Shown on the payment page; the PDF doesn't fetch it.
+    pub logo_url: Option<String>,
+    /// `#rrggbb`.
+    pub accent_color: Option<String>,
+    /// Footer text, e.g. terms or who to contact about a payment.
+    pub payment_instructions: Option<String>,
+}
+
+pub const BRANDING_COLS: &str = "business_name, logo_url, accent_color, payment_instructions";
+
+pub fn row_to_branding(row: &rusqlite::Row) -> rusqlite::Result<Branding> {
+    Ok(Branding {
+        business_name: row.get(0)?,
+        logo_url: row.get(1)?,
+        accent_color: row.get(2)?,
+        payment_instructions: row.get(3)?,
+    })
+}
+
+/// The portfolio's branding; all unset if it has none.
+pub fn load(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<Branding> {
+    match conn.query_row(
+        &format!("SELECT {BRANDING_COLS} FROM portfolio_branding WHERE portfolio_id = ?1"),
+        rusqlite::params![portfolio_id],
+        row_to_branding,
+    ) {
+        Ok(branding) => Ok(branding),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Branding::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse an accent color as `(r, g, b)`.
+pub fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#').filter(|h| h.len() == 6 && h.is_ascii())?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Check a field set by the merchant: trimmed, and at most `max_chars`.
+pub fn validate_text(field: &str, value: &str, max_chars: usize) -> AppResult<()> {
+    if value.trim().chars().count() > max_chars {
+        return Err(AppError::BadRequest(format!("{field} must be at most {max_chars} characters")));
+    }
+    Ok(())
+}
+
+/// Logos are loaded by customers' browsers, so only https URLs are accepted.
+pub fn validate_logo_url(url: &str) -> AppResult<()> {
+    validate_text("logo_url", url, LOGO_URL_MAX_CHARS)?;
+    let valid = reqwest::Url::parse(url).is_ok_and(|u| u.scheme() == "https" && u.host_str().is_some());
+    if !valid {
+        return Err(AppError::BadRequest("logo_url must be an https URL".into()));
+    }
+    Ok(())
+}
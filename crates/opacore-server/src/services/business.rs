@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::import::parse_datetime;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsPeriod {
+    Day,
+    Week,
+    #[default]
+    Month,
+    Quarter,
+    Year,
+}
+
+impl MetricsPeriod {
+    pub fn name(self) -> &'static str {
+        match self {
+            MetricsPeriod::Day => "day",
+            MetricsPeriod::Week => "week",
+            MetricsPeriod::Month => "month",
+            MetricsPeriod::Quarter => "quarter",
+            MetricsPeriod::Year => "year",
+        }
+    }
+
+    fn bucket(self, at: &DateTime<Utc>) -> String {
+        match self {
+            MetricsPeriod::Day => at.format("%Y-%m-%d").to_string(),
+            MetricsPeriod::Week => at.format("%G-W%V").to_string(),
+            MetricsPeriod::Month => at.format("%Y-%m").to_string(),
+            MetricsPeriod::Quarter => format!("{}-Q{}", at.year(), (at.month() - 1) / 3 + 1),
+            MetricsPeriod::Year => at.format("%Y").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeriodMetrics {
+    pub period: String,
+    pub invoices_paid: usize,
+    pub revenue_sat: i64,
+    /// Fiat value of paid invoices per currency: the invoiced fiat amount, or the
+    /// paid sats at the BTC price recorded when the invoice was created.
+    pub revenue_fiat: BTreeMap<String, f64>,
+    pub average_invoice_sat: i64,
+    pub average_time_to_payment_hours: Option<f64>,
+    pub paying_customers: usize,
+    /// Share of paying customers who had already paid at least once before.
+    pub repeat_customer_rate: Option<f64>,
+    /// Revenue from invoices priced in BTC.
+    pub btc_denominated_sat: i64,
+    /// Revenue from invoices priced in fiat.
+    pub fiat_denominated_sat: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BusinessMetrics {
+    pub period: String,
+    pub totals: PeriodMetrics,
+    pub periods: Vec<PeriodMetrics>,
+}
+
+struct PaidInvoice {
+    customer: Option<String>,
+    paid_sat: i64,
+    amount_fiat: Option<f64>,
+    fiat_currency: String,
+    btc_price_at_creation: Option<f64>,
+    paid_at: DateTime<Utc>,
+    issued_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    invoices_paid: usize,
+    revenue_sat: i64,
+    revenue_fiat: BTreeMap<String, f64>,
+    payment_hours: Vec<f64>,
+    customers: HashSet<String>,
+    repeat_customers: HashSet<String>,
+    btc_denominated_sat: i64,
+    fiat_denominated_sat: i64,
+}
+
+impl Accumulator {
+    fn add(&mut self, invoice: &PaidInvoice, is_repeat: bool) {
+        self.invoices_paid += 1;
+        self.revenue_sat += invoice.paid_sat;
+
+        let fiat = invoice.amount_fiat.or_else(|| {
+            invoice
+                .btc_price_at_creation
+                .map(|price| invoice.paid_sat as f64 / 1e8 * price)
+        });
+        if let Some(fiat) = fiat {
+            *self.revenue_fiat.entry(invoice.fiat_currency.clone()).or_insert(0.0) += fiat;
+        }
+
+        if let Some(issued) = invoice.issued_at {
+            let hours = (invoice.paid_at - issued).num_seconds() as f64 / 3600.0;
+            if hours >= 0.0 {
+                self.payment_hours.push(hours);
+            }
+        }
+
+        if let Some(ref customer) = invoice.customer {
+            self.customers.insert(customer.clone());
+            if is_repeat {
+                self.repeat_customers.insert(customer.clone());
+            }
+        }
+
+        if invoice.amount_fiat.is_some() {
+            self.fiat_denominated_sat += invoice.paid_sat;
+        } else {
+            self.btc_denominated_sat += invoice.paid_sat;
+        }
+    }
+
+    fn finish(self, period: String) -> PeriodMetrics {
+        let average_invoice_sat = if self.invoices_paid > 0 {
+            self.revenue_sat / self.invoices_paid as i64
+        } else {
+            0
+        };
+        let average_time_to_payment_hours = if self.payment_hours.is_empty() {
+            None
+        } else {
+            Some(self.payment_hours.iter().sum::<f64>() / self.payment_hours.len() as f64)
+        };
+        let repeat_customer_rate = if self.customers.is_empty() {
+            None
+        } else {
+            Some(self.repeat_customers.len() as f64 / self.customers.len() as f64)
+        };
+
+        PeriodMetrics {
+            period,
+            invoices_paid: self.invoices_paid,
+            revenue_sat: self.revenue_sat,
+            revenue_fiat: self.revenue_fiat,
+            average_invoice_sat,
+            average_time_to_payment_hours,
+            paying_customers: self.customers.len(),
+            repeat_customer_rate,
+            btc_denominated_sat: self.btc_denominated_sat,
+            fiat_denominated_sat: self.fiat_denominated_sat,
+        }
+    }
+}
+
+/// Revenue metrics from a portfolio's paid invoices and payment links, bucketed by `period`.
+pub fn business_metrics(
+    pool: &DbPool,
+    portfolio_id: &str,
+    period: MetricsPeriod,
+) -> AppResult<BusinessMetrics> {
+    let conn = pool.get()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT customer_email, customer_name, COALESCE(paid_amount_sat, amount_sat), amount_fiat,
+                fiat_currency, btc_price_at_creation, paid_at, COALESCE(issued_at, created_at)
+         FROM invoices
+         WHERE portfolio_id = ?1 AND paid_at IS NOT NULL
+         ORDER BY paid_at ASC",
+    )?;
+
+    let invoices: Vec<PaidInvoice> = stmt
+        .query_map(rusqlite::params![portfolio_id], |row| {
+            // Customers are matched by email, falling back to name
+            let email: Option<String> = row.get(0)?;
+            let name: Option<String> = row.get(1)?;
+            let customer = email
+                .or(name)
+                .map(|c| c.trim().to_lowercase())
+                .filter(|c| !c.is_empty());
+            let paid_sat: i64 = row.get(2)?;
+            let amount_fiat: Option<f64> = row.get(3)?;
+            let fiat_currency: String = row.get(4)?;
+            let btc_price_at_creation: Option<f64> = row.get(5)?;
+            let paid_at: String = row.get(6)?;
+            let issued_at: String = row.get(7)?;
+
+            Ok(parse_datetime(&paid_at).map(|paid_at| PaidInvoice {
+                customer,
+                paid_sat,
+                amount_fiat,
+                fiat_currency,
+                btc_price_at_creation,
+                paid_at,
+                issued_at: parse_datetime(&issued_at),
+            }))
+        })?
+        .filter_map(|r| r.ok().flatten())
+        .collect();
+
+    let mut totals = Accumulator::default();
+    let mut buckets: BTreeMap<String, Accumulator> = BTreeMap::new();
+    let mut payments_per_customer: HashMap<String, usize> = HashMap::new();
+
+    for invoice in invoices {
+        let is_repeat = match invoice.customer {
+            Some(ref customer) => {
+                let count = payments_per_customer.entry(customer.clone()).or_insert(0);
+                *count += 1;
+                *count > 1
+            }
+            None => false,
+        };
+
+        totals.add(&invoice, is_repeat);
+        buckets
+            .entry(period.bucket(&invoice.paid_at))
+            .or_default()
+            .add(&invoice, is_repeat);
+    }
+
+    Ok(BusinessMetrics {
+        period: period.name().to_string(),
+        totals: totals.finish("all".to_string()),
+        periods: buckets.into_iter().map(|(key, acc)| acc.finish(key)).collect(),
+    })
+}
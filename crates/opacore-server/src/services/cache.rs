@@ -0,0 +1,272 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bdk_wallet::bitcoin::Network;
+use moka::sync::Cache;
+
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::costbasis::{CostBasisResult, PortfolioSummary};
+use crate::services::fees::FeeEstimates;
+
+const OWNERSHIP_TTL: Duration = Duration::from_secs(600);
+const USER_TTL: Duration = Duration::from_secs(300);
+/// Default for `CURRENT_PRICE_TTL_SECS`.
+const CURRENT_PRICE_TTL: Duration = Duration::from_secs(60);
+const CHAIN_TIP_TTL: Duration = Duration::from_secs(30);
+const FEE_ESTIMATES_TTL: Duration = Duration::from_secs(60);
+const TX_HEIGHT_TTL: Duration = Duration::from_secs(600);
+const COST_BASIS_TTL: Duration = Duration::from_secs(3600);
+
+/// Who a portfolio belongs to.
+#[derive(Debug, Clone)]
+pub struct PortfolioOwner {
+    pub user_id: String,
+    pub organization_id: Option<String>,
+}
+
+/// What a cost-basis calculation depends on. `revision` is the portfolio's
+/// `portfolio_revisions` counter, so any write to its transactions or lot assignments
+/// makes a new key and earlier entries are never read again; `rates` does the same when
+/// prices for converting to another currency are added.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CostBasisKey {
+    pub portfolio_id: String,
+    pub revision: i64,
+    pub method: &'static str,
+    pub currency: String,
+    /// How many stored BTC prices there are to convert from USD with; 0 for USD.
+    pub rates: i64,
+    pub tax_year: Option<i32>,
+    /// The tax rules, serialized.
+    pub rules: String,
+}
+
+/// Typed in-memory caches for hot read paths. Cheap to clone — the underlying
+/// caches are shared between handlers and background tasks.
+///
+/// Anything that writes a cached row must call the matching `invalidate_*` hook;
+/// the TTLs only bound how stale an entry can get if a hook is missed.
+#[derive(Clone)]
+pub struct AppCache {
+    /// portfolio_id -> owning user and the organization it is shared with
+    portfolio_owners: Cache<String, PortfolioOwner>,
+    /// (organization_id, user_id) -> the member's role, or `None` for non-members
+    member_roles: Cache<(String, String), Option<String>>,
+    /// user_id -> user row (profile + settings)
+    users: Cache<String, User>,
+    /// currency -> BTC spot price
+    current_prices: Cache<String, f64>,
+    /// network -> chain tip height
+    chain_tips: Cache<Network, u32>,
+    /// network -> fee estimates by confirmation target
+    fee_estimates: Cache<Network, FeeEstimates>,
+    /// txid -> confirmed block height (unconfirmed transactions are never cached)
+    tx_heights: Cache<String, i64>,
+    /// Cost basis and realized gains, by what they were calculated from
+    cost_basis: Cache<CostBasisKey, Arc<CostBasisResult>>,
+    /// Holdings summaries valued at a price of 0 (see [`PortfolioSummary::at_price`])
+    summaries: Cache<CostBasisKey, PortfolioSummary>,
+}
+
+impl Default for AppCache {
+    fn default() -> Self {
+        Self::new(CURRENT_PRICE_TTL)
+    }
+}
+
+impl AppCache {
+    pub fn new(current_price_ttl: Duration) -> Self {
+        Self {
+            portfolio_owners: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(OWNERSHIP_TTL)
+                .build(),
+            member_roles: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(OWNERSHIP_TTL)
+                .build(),
+            users: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(USER_TTL)
+                .build(),
+            current_prices: Cache::builder()
+                .max_capacity(64)
+                .time_to_live(current_price_ttl)
+                .build(),
+            chain_tips: Cache::builder()
+                .max_capacity(8)
+                .time_to_live(CHAIN_TIP_TTL)
+                .build(),
+            fee_estimates: Cache::builder()
+                .max_capacity(8)
+                .time_to_live(FEE_ESTIMATES_TTL)
+                .build(),
+            tx_heights: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(TX_HEIGHT_TTL)
+                .build(),
+            cost_basis: Cache::builder()
+                .max_capacity(1_000)
+                .time_to_idle(COST_BASIS_TTL)
+                .build(),
+            summaries: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_idle(COST_BASIS_TTL)
+                .build(),
+        }
+    }
+
+    // ── Ownership and membership ──
+
+    /// Owner of `portfolio_id`, or `None` if it doesn't exist. Only existing portfolios
+    /// are cached, so a miss always falls through to the database.
+    pub fn portfolio_owner(
+        &self,
+        conn: &rusqlite::Connection,
+        portfolio_id: &str,
+    ) -> AppResult<Option<PortfolioOwner>> {
+        if let Some(owner) = self.portfolio_owners.get(portfolio_id) {
+            return Ok(Some(owner));
+        }
+
+        let owner = match conn.query_row(
+            "SELECT user_id, organization_id FROM portfolios WHERE id = ?1",
+            rusqlite::params![portfolio_id],
+            |row| Ok(PortfolioOwner { user_id: row.get(0)?, organization_id: row.get(1)? }),
+        ) {
+            Ok(owner) => owner,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+            Err(e) => return Err(AppError::Database(e)),
+        };
+        self.portfolio_owners.insert(portfolio_id.to_string(), owner.clone());
+        Ok(Some(owner))
+    }
+
+    pub fn invalidate_portfolio(&self, portfolio_id: &str) {
+        self.portfolio_owners.invalidate(portfolio_id);
+    }
+
+    /// `user_id`'s role in `organization_id`, or `None` if they aren't a member.
+    pub fn member_role(
+        &self,
+        conn: &rusqlite::Connection,
+        organization_id: &str,
+        user_id: &str,
+    ) -> AppResult<Option<String>> {
+        let key = (organization_id.to_string(), user_id.to_string());
+        if let Some(role) = self.member_roles.get(&key) {
+            return Ok(role);
+        }
+
+        let role = match conn.query_row(
+            "SELECT role FROM organization_members WHERE organization_id = ?1 AND user_id = ?2",
+            rusqlite::params![organization_id, user_id],
+            |row| row.get(0),
+        ) {
+            Ok(role) => Some(role),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(AppError::Database(e)),
+        };
+        self.member_roles.insert(key, role.clone());
+        Ok(role)
+    }
+
+    pub fn invalidate_member(&self, organization_id: &str, user_id: &str) {
+        self.member_roles
+            .invalidate(&(organization_id.to_string(), user_id.to_string()));
+    }
+
+    // ── Users ──
+
+    /// Load a user (including settings such as `default_currency`) through the cache.
+    pub fn user(&self, conn: &rusqlite::Connection, user_id: &str) -> AppResult<User> {
+        if let Some(user) = self.users.get(user_id) {
+            return Ok(user);
+        }
+
+        let user = conn.query_row(
+            "SELECT id, email, name, password_hash, default_currency, email_verified, created_at, updated_at, is_admin, disabled_at FROM users WHERE id = ?1",
+            rusqlite::params![user_id],
+            |row| {
+                Ok(User {
+                    id: row.get(0)?,
+                    email: row.get(1)?,
+                    name: row.get(2)?,
+                    password_hash: row.get(3)?,
+                    default_currency: row.get(4)?,
+                    email_verified: row.get::<_, i32>(5)? != 0,
+                    created_at: row.get(6)?,
+                    updated_at: row.get(7)?,
+                    is_admin: row.get::<_, i32>(8)? != 0,
+                    disabled_at: row.get(9)?,
+                })
+            },
+        )?;
+
+        self.users.insert(user_id.to_string(), user.clone());
+        Ok(user)
+    }
+
+    pub fn invalidate_user(&self, user_id: &str) {
+        self.users.invalidate(user_id);
+    }
+
+    // ── Prices ──
+
+    pub fn current_price(&self, currency: &str) -> Option<f64> {
+        self.current_prices.get(currency)
+    }
+
+    pub fn set_current_price(&self, currency: &str, price: f64) {
+        self.current_prices.insert(currency.to_string(), price);
+    }
+
+    // ── Chain tip ──
+
+    pub fn chain_tip(&self, network: Network) -> Option<u32> {
+        self.chain_tips.get(&network)
+    }
+
+    pub fn set_chain_tip(&self, network: Network, height: u32) {
+        self.chain_tips.insert(network, height);
+    }
+
+    // ── Fee estimates ──
+
+    pub fn fee_estimates(&self, network: Network) -> Option<FeeEstimates> {
+        self.fee_estimates.get(&network)
+    }
+
+    pub fn set_fee_estimates(&self, network: Network, estimates: FeeEstimates) {
+        self.fee_estimates.insert(network, estimates);
+    }
+
+    // ── Transaction confirmations ──
+
+    pub fn tx_height(&self, txid: &str) -> Option<i64> {
+        self.tx_heights.get(txid)
+    }
+
+    pub fn set_tx_height(&self, txid: &str, height: i64) {
+        self.tx_heights.insert(txid.to_string(), height);
+    }
+
+    // ── Cost basis ──
+
+    pub fn cost_basis(&self, key: &CostBasisKey) -> Option<Arc<CostBasisResult>> {
+        self.cost_basis.get(key)
+    }
+
+    pub fn set_cost_basis(&self, key: CostBasisKey, result: Arc<CostBasisResult>) {
+        self.cost_basis.insert(key, result);
+    }
+
+    pub fn portfolio_summary(&self, key: &CostBasisKey) -> Option<PortfolioSummary> {
+        self.summaries.get(key)
+    }
+
+    pub fn set_portfolio_summary(&self, key: CostBasisKey, summary: PortfolioSummary) {
+        self.summaries.insert(key, summary);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::costbasis::TxKind;
+use crate::services::prices;
+use crate::services::transaction_splits::COUNTED;
+
+#[derive(Debug, Serialize)]
+pub struct CashFlow {
+    pub currency: String,
+    pub months: Vec<MonthFlow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MonthFlow {
+    /// `YYYY-MM`.
+    pub month: String,
+    pub total: Flow,
+    /// Synced from a wallet.
+    pub chain: Flow,
+    /// Everything else: entered by hand, imported, or recorded from an invoice.
+    pub manual: Flow,
+}
+
+/// Sats in and out, and their value at each transaction's recorded price.
+#[derive(Debug, Default, Serialize)]
+pub struct Flow {
+    pub inflow_sat: i64,
+    pub outflow_sat: i64,
+    pub net_sat: i64,
+    pub inflow: f64,
+    pub outflow: f64,
+    pub net: f64,
+    /// Transactions with no price (or none convertible to the currency), left out of
+    /// the fiat amounts.
+    pub unpriced: i64,
+}
+
+impl Flow {
+    fn add(&mut self, inflow: bool, amount_sat: i64, value: Option<f64>) {
+        if inflow {
+            self.inflow_sat += amount_sat;
+        } else {
+            self.outflow_sat += amount_sat;
+        }
+        self.net_sat = self.inflow_sat - self.outflow_sat;
+        match value {
+            Some(value) if inflow => self.inflow += value,
+            Some(value) => self.outflow += value,
+            None => self.unpriced += 1,
+        }
+        self.net = self.inflow - self.outflow;
+    }
+
+    fn round(&mut self) {
+        for amount in [&mut self.inflow, &mut self.outflow, &mut self.net] {
+            *amount = (*amount * 100.0).round() / 100.0;
+        }
+    }
+}
+
+/// The portfolio's inflows and outflows per month from `start` to `end` (inclusive, by
+/// the day they happened), in the same terms as its balance, valued in `currency`. Months
+/// with nothing in them between the first and last that have are included as zeros.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn cash_flow(
+    pool: &DbPool,
+    portfolio_id: &str,
+    currency: &str,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+) -> AppResult<CashFlow> {
+    let conn = pool.get()?;
+    let rates = if currency == "usd" { None } else { Some(prices::usd_rates(&conn, currency)?) };
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT transacted_at, tx_type, amount_sat, price_usd, source
+         FROM transactions
+         WHERE portfolio_id = ?1 AND {COUNTED}
+           AND (?2 IS NULL OR substr(transacted_at, 1, 10) >= ?2)
+           AND (?3 IS NULL OR substr(transacted_at, 1, 10) <= ?3)"
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![portfolio_id, start.map(|d| d.to_string()), end.map(|d| d.to_string())],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<f64>>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        },
+    )?;
+
+    let mut months: BTreeMap<NaiveDate, MonthFlow> = BTreeMap::new();
+    for row in rows {
+        let (transacted_at, tx_type, amount_sat, price_usd, source) = row?;
+        let inflow = match TxKind::of(&tx_type) {
+            Some(kind) if kind.is_inflow() => true,
+            Some(kind) if kind.is_outflow() => false,
+            _ => continue,
+        };
+        let Some(month) = transacted_at
+            .get(..7)
+            .and_then(|month| NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d").ok())
+        else {
+            continue;
+        };
+        let price = match &rates {
+            None => price_usd,
+            Some(rates) => price_usd.zip(prices::rate_on(rates, &transacted_at)).map(|(price, rate)| price * rate),
+        };
+        let value = price.map(|price| amount_sat as f64 / 1e8 * price);
+
+        let flow = months.entry(month).or_insert_with(|| empty_month(month));
+        flow.total.add(inflow, amount_sat, value);
+        if source == "chain" {
+            flow.chain.add(inflow, amount_sat, value);
+        } else {
+            flow.manual.add(inflow, amount_sat, value);
+        }
+    }
+
+    if let (Some(&first), Some(&last)) = (months.keys().next(), months.keys().next_back()) {
+        let mut month = first;
+        while month < last {
+            months.entry(month).or_insert_with(|| empty_month(month));
+            month = month.checked_add_months(chrono::Months::new(1)).unwrap_or(last);
+        }
+    }
+
+    let months = months
+        .into_values()
+        .map(|mut flow| {
+            flow.total.round();
+            flow.chain.round();
+            flow.manual.round();
+            flow
+        })
+        .collect();
+
+    Ok(CashFlow { currency: currency.to_string(), months })
+}
+
+fn empty_month(month: NaiveDate) -> MonthFlow {
+    MonthFlow {
+        month: month.format("%Y-%m").to_string(),
+        total: Flow::default(),
+        chain: Flow::default(),
+        manual: Flow::default(),
+    }
+}
@@ -1,239 +1,251 @@
-use serde::{Deserialize, Serialize};
+pub use opacore_taxengine::costbasis::{
+    CostBasisMethod, CostBasisResult, LotAssignment, OpenLot, PortfolioSummary, UnrealizedLot,
+};
+pub use opacore_taxengine::{TxKind, TX_TYPES};
+use opacore_taxengine::{costbasis, TaxRules, TxRecord};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::Serialize;
 
 use crate::db::DbPool;
-use crate::error::AppResult;
-
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum CostBasisMethod {
-    Fifo,
-    Lifo,
-    Hifo,
+use crate::error::{AppError, AppResult};
+use crate::services::cache::{AppCache, CostBasisKey};
+use crate::services::{lot_assignments, prices};
+use crate::services::transaction_splits::COUNTED;
+
+/// Load a portfolio's transactions in the shape the tax engine works on, with the lots
+/// assigned to each disposal and the transfers they're linked to.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn load_tx_records(pool: &DbPool, portfolio_id: &str) -> AppResult<Vec<TxRecord>> {
+    let conn = pool.get()?;
+    let assignments = lot_assignments::for_portfolio(&conn, portfolio_id)?;
+    load_records(&conn, "portfolio_id = ?1", portfolio_id, assignments)
 }
 
-impl Default for CostBasisMethod {
-    fn default() -> Self {
-        Self::Fifo
+/// As [`load_tx_records`], with prices in `currency`: each USD price converted at the
+/// rate on its day (or the latest before it) from stored BTC prices. A price with no rate
+/// to convert it is dropped, so the engine reports it as missing.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn load_tx_records_in(pool: &DbPool, portfolio_id: &str, currency: &str) -> AppResult<Vec<TxRecord>> {
+    let mut txs = load_tx_records(pool, portfolio_id)?;
+    if currency == "usd" {
+        return Ok(txs);
+    }
+    let rates = prices::usd_rates(&*pool.get()?, currency)?;
+    for tx in &mut txs {
+        tx.price_usd = tx
+            .price_usd
+            .zip(prices::rate_on(&rates, &tx.transacted_at))
+            .map(|(price, rate)| price * rate);
     }
+    Ok(txs)
+}
+
+/// As [`load_tx_records`], for every portfolio `user_id` owns together, so transfers
+/// between them carry lots across and disposals draw on lots from any of them.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn load_user_tx_records(pool: &DbPool, user_id: &str) -> AppResult<Vec<TxRecord>> {
+    let conn = pool.get()?;
+    let assignments = lot_assignments::for_user(&conn, user_id)?;
+    load_records(
+        &conn,
+        "portfolio_id IN (SELECT id FROM portfolios WHERE user_id = ?1)",
+        user_id,
+        assignments,
+    )
+}
+
+/// The counted transactions matching `scope` (a condition on `?1`), oldest first.
+fn load_records(
+    conn: &rusqlite::Connection,
+    scope: &str,
+    param: &str,
+    mut assignments: HashMap<String, Vec<LotAssignment>>,
+) -> AppResult<Vec<TxRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, tx_type, amount_sat, fee_sat, price_usd, transacted_at, linked_transaction_id
+         FROM transactions
+         WHERE {scope} AND {COUNTED}
+         ORDER BY transacted_at ASC, id"
+    ))?;
+
+    let txs = stmt
+        .query_map(rusqlite::params![param], |row| {
+            let id: String = row.get(0)?;
+            Ok(TxRecord {
+                lot_assignments: assignments.remove(&id).unwrap_or_default(),
+                id: Some(id),
+                tx_type: row.get(1)?,
+                amount_sat: row.get(2)?,
+                fee_sat: row.get(3)?,
+                price_usd: row.get(4)?,
+                transacted_at: row.get(5)?,
+                linked_id: row.get(6)?,
+            })
+        })?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(txs)
 }
 
+/// How a portfolio's figures are reported when a request doesn't say.
 #[derive(Debug, Clone)]
-struct Lot {
-    amount_sat: i64,
-    price_usd: f64,
-    date: String,
+pub struct PortfolioDefaults {
+    pub method: CostBasisMethod,
+    /// Lowercase ISO 4217 code.
+    pub currency: String,
 }
 
-#[derive(Debug, Serialize)]
-pub struct GainLoss {
-    pub sell_date: String,
-    pub sell_amount_sat: i64,
-    pub sell_price_usd: f64,
-    pub cost_basis_usd: f64,
-    pub proceeds_usd: f64,
-    pub gain_usd: f64,
-    pub is_long_term: bool,
-    pub holding_period_days: i64,
+pub fn portfolio_defaults(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<PortfolioDefaults> {
+    let (method, currency): (String, String) = conn
+        .query_row(
+            "SELECT cost_basis_method, base_currency FROM portfolios WHERE id = ?1",
+            rusqlite::params![portfolio_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Portfolio not found".into()),
+            e => AppError::Database(e),
+        })?;
+    Ok(PortfolioDefaults {
+        method: CostBasisMethod::from_name(&method).unwrap_or_default(),
+        currency,
+    })
 }
 
+/// A result with the currency its amounts are in. Amount fields named `_usd` are in
+/// `currency` too; the names predate reporting in other currencies.
 #[derive(Debug, Serialize)]
-pub struct PortfolioSummary {
-    pub total_balance_sat: i64,
-    pub total_cost_basis_usd: f64,
-    pub current_value_usd: f64,
-    pub unrealized_gain_usd: f64,
-    pub realized_gain_usd: f64,
-    pub total_received_sat: i64,
-    pub total_sent_sat: i64,
-    pub transaction_count: i64,
+pub struct InCurrency<T> {
+    pub currency: String,
+    #[serde(flatten)]
+    pub value: T,
 }
 
-#[derive(Debug, Serialize)]
-pub struct CostBasisResult {
-    pub method: String,
-    pub gains: Vec<GainLoss>,
-    pub total_realized_gain_usd: f64,
-    pub total_short_term_gain_usd: f64,
-    pub total_long_term_gain_usd: f64,
-    pub remaining_lots: usize,
-    pub remaining_balance_sat: i64,
-    pub remaining_cost_basis_usd: f64,
+/// Reject a `tx_type` the tax engine wouldn't know how to treat.
+pub fn validate_tx_type(tx_type: &str) -> AppResult<()> {
+    if TX_TYPES.contains(&tx_type) {
+        return Ok(());
+    }
+    Err(AppError::BadRequest(format!(
+        "Invalid tx_type. Must be one of: {}",
+        TX_TYPES.join(", ")
+    )))
 }
 
-/// Calculate cost basis and realized gains/losses for a portfolio.
-pub fn calculate_cost_basis(
+/// The key a calculation over the portfolio's transactions as they are now is cached
+/// under. Read before the transactions, so a write in between leaves the result under
+/// an already-stale key rather than a current one.
+fn cache_key(
     pool: &DbPool,
     portfolio_id: &str,
     method: CostBasisMethod,
+    currency: &str,
     tax_year: Option<i32>,
-) -> AppResult<CostBasisResult> {
+    rules: &TaxRules,
+) -> AppResult<CostBasisKey> {
     let conn = pool.get()?;
-
-    // Get all transactions sorted by date
-    let mut stmt = conn.prepare(
-        "SELECT tx_type, amount_sat, price_usd, transacted_at
-         FROM transactions
-         WHERE portfolio_id = ?1
-         ORDER BY transacted_at ASC",
-    )?;
-
-    let txs: Vec<(String, i64, Option<f64>, String)> = stmt
-        .query_map(rusqlite::params![portfolio_id], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-        })?
-        .filter_map(|r| r.ok())
-        .collect();
-
-    let mut lots: Vec<Lot> = Vec::new();
-    let mut gains: Vec<GainLoss> = Vec::new();
-
-    for (tx_type, amount_sat, price_usd, date) in &txs {
-        let price = price_usd.unwrap_or(0.0);
-
-        match tx_type.as_str() {
-            "buy" | "receive" => {
-                lots.push(Lot {
-                    amount_sat: *amount_sat,
-                    price_usd: price,
-                    date: date.clone(),
-                });
-            }
-            "sell" => {
-                let mut remaining = *amount_sat;
-                let sell_price = price;
-
-                // Sort lots based on method before depleting
-                sort_lots(&mut lots, method);
-
-                while remaining > 0 && !lots.is_empty() {
-                    let lot = &mut lots[0];
-                    let disposed = remaining.min(lot.amount_sat);
-
-                    // Calculate gain/loss
-                    let cost_basis = (disposed as f64 / 1e8) * lot.price_usd;
-                    let proceeds = (disposed as f64 / 1e8) * sell_price;
-                    let gain = proceeds - cost_basis;
-
-                    let holding_days = days_between(&lot.date, date);
-                    let is_long_term = holding_days > 365;
-
-                    // Filter by tax year if specified
-                    let sell_year = date.get(..4).and_then(|y| y.parse::<i32>().ok());
-                    let include = tax_year
-                        .map(|ty| sell_year == Some(ty))
-                        .unwrap_or(true);
-
-                    if include {
-                        gains.push(GainLoss {
-                            sell_date: date.clone(),
-                            sell_amount_sat: disposed,
-                            sell_price_usd: sell_price,
-                            cost_basis_usd: cost_basis,
-                            proceeds_usd: proceeds,
-                            gain_usd: gain,
-                            is_long_term,
-                            holding_period_days: holding_days,
-                        });
-                    }
-
-                    lot.amount_sat -= disposed;
-                    remaining -= disposed;
-
-                    if lot.amount_sat == 0 {
-                        lots.remove(0);
-                    }
-                }
-            }
-            _ => {} // transfer, etc. — no tax event
-        }
-    }
-
-    let total_realized = gains.iter().map(|g| g.gain_usd).sum();
-    let short_term: f64 = gains.iter().filter(|g| !g.is_long_term).map(|g| g.gain_usd).sum();
-    let long_term: f64 = gains.iter().filter(|g| g.is_long_term).map(|g| g.gain_usd).sum();
-    let remaining_sat: i64 = lots.iter().map(|l| l.amount_sat).sum();
-    let remaining_basis: f64 = lots
-        .iter()
-        .map(|l| (l.amount_sat as f64 / 1e8) * l.price_usd)
-        .sum();
-
-    let method_name = match method {
-        CostBasisMethod::Fifo => "fifo",
-        CostBasisMethod::Lifo => "lifo",
-        CostBasisMethod::Hifo => "hifo",
+    // Converted prices also depend on the stored rates, which only ever gain days
+    let rates: i64 = match currency {
+        "usd" => 0,
+        _ => conn.query_row(
+            "SELECT COUNT(*) FROM price_history WHERE currency IN ('usd', ?1)",
+            rusqlite::params![currency],
+            |row| row.get(0),
+        )?,
     };
-
-    Ok(CostBasisResult {
-        method: method_name.to_string(),
-        gains,
-        total_realized_gain_usd: total_realized,
-        total_short_term_gain_usd: short_term,
-        total_long_term_gain_usd: long_term,
-        remaining_lots: lots.len(),
-        remaining_balance_sat: remaining_sat,
-        remaining_cost_basis_usd: remaining_basis,
+    let revision = match conn.query_row(
+        "SELECT revision FROM portfolio_revisions WHERE portfolio_id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| row.get(0),
+    ) {
+        Ok(revision) => revision,
+        Err(rusqlite::Error::QueryReturnedNoRows) => 0,
+        Err(e) => return Err(AppError::Database(e)),
+    };
+    Ok(CostBasisKey {
+        portfolio_id: portfolio_id.to_string(),
+        revision,
+        method: method.name(),
+        currency: currency.to_string(),
+        rates,
+        tax_year,
+        rules: serde_json::to_string(rules).map_err(|e| AppError::Internal(e.to_string()))?,
     })
 }
 
-/// Get a summary of a portfolio's holdings.
-pub fn portfolio_summary(
+/// Calculate cost basis and realized gains/losses for a portfolio in `currency`, reusing
+/// the result in `cache` until its transactions change. Without a cache it's recalculated.
+#[tracing::instrument(level = "debug", skip(pool, cache, rules))]
+pub fn calculate_cost_basis(
     pool: &DbPool,
+    cache: Option<&AppCache>,
     portfolio_id: &str,
-    current_price_usd: f64,
     method: CostBasisMethod,
-) -> AppResult<PortfolioSummary> {
-    let conn = pool.get()?;
-
-    let (total_received, total_sent, tx_count): (i64, i64, i64) = conn.query_row(
-        "SELECT
-            COALESCE(SUM(CASE WHEN tx_type IN ('buy','receive') THEN amount_sat ELSE 0 END), 0),
-            COALESCE(SUM(CASE WHEN tx_type IN ('sell','send') THEN amount_sat ELSE 0 END), 0),
-            COUNT(*)
-         FROM transactions WHERE portfolio_id = ?1",
-        rusqlite::params![portfolio_id],
-        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
-    )?;
-
-    let balance = total_received - total_sent;
-    let current_value = (balance as f64 / 1e8) * current_price_usd;
-
-    let basis = calculate_cost_basis(pool, portfolio_id, method, None)?;
-    let cost_basis = basis.remaining_cost_basis_usd;
-    let unrealized = current_value - cost_basis;
-
-    Ok(PortfolioSummary {
-        total_balance_sat: balance,
-        total_cost_basis_usd: cost_basis,
-        current_value_usd: current_value,
-        unrealized_gain_usd: unrealized,
-        realized_gain_usd: basis.total_realized_gain_usd,
-        total_received_sat: total_received,
-        total_sent_sat: total_sent,
-        transaction_count: tx_count,
-    })
+    currency: &str,
+    tax_year: Option<i32>,
+    rules: &TaxRules,
+) -> AppResult<CostBasisResult> {
+    let Some(cache) = cache else {
+        let txs = load_tx_records_in(pool, portfolio_id, currency)?;
+        return Ok(costbasis::calculate_cost_basis(txs, method, tax_year, rules));
+    };
+    let key = cache_key(pool, portfolio_id, method, currency, tax_year, rules)?;
+    if let Some(result) = cache.cost_basis(&key) {
+        return Ok(CostBasisResult::clone(&result));
+    }
+    let txs = load_tx_records_in(pool, portfolio_id, currency)?;
+    let result = costbasis::calculate_cost_basis(txs, method, tax_year, rules);
+    cache.set_cost_basis(key, Arc::new(result.clone()));
+    Ok(result)
 }
 
-fn sort_lots(lots: &mut [Lot], method: CostBasisMethod) {
-    match method {
-        CostBasisMethod::Fifo => {} // already in chronological order
-        CostBasisMethod::Lifo => lots.reverse(),
-        CostBasisMethod::Hifo => lots.sort_by(|a, b| {
-            b.price_usd
-                .partial_cmp(&a.price_usd)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        }),
+/// Get a summary of a portfolio's holdings in `currency`, valued at `current_price` (in
+/// that currency), cached as for [`calculate_cost_basis`].
+#[tracing::instrument(level = "debug", skip(pool, cache, rules))]
+pub fn portfolio_summary(
+    pool: &DbPool,
+    cache: Option<&AppCache>,
+    portfolio_id: &str,
+    current_price: f64,
+    method: CostBasisMethod,
+    currency: &str,
+    rules: &TaxRules,
+) -> AppResult<PortfolioSummary> {
+    let Some(cache) = cache else {
+        let txs = load_tx_records_in(pool, portfolio_id, currency)?;
+        return Ok(costbasis::portfolio_summary(txs, current_price, method, rules));
+    };
+    let key = cache_key(pool, portfolio_id, method, currency, None, rules)?;
+    if let Some(summary) = cache.portfolio_summary(&key) {
+        return Ok(summary.at_price(current_price));
     }
+    let txs = load_tx_records_in(pool, portfolio_id, currency)?;
+    let summary = costbasis::portfolio_summary(txs, 0.0, method, rules);
+    cache.set_portfolio_summary(key, summary.clone());
+    Ok(summary.at_price(current_price))
 }
 
-fn days_between(start: &str, end: &str) -> i64 {
-    let parse = |s: &str| -> Option<chrono::NaiveDate> {
-        // Handle both "YYYY-MM-DD" and "YYYY-MM-DDTHH:MM:SS..." formats
-        let date_part = &s[..s.len().min(10)];
-        chrono::NaiveDate::parse_from_str(date_part, "%Y-%m-%d").ok()
-    };
+/// The lots open just before the disposal `disposal_id`, as specific identification
+/// leaves them: what it can be assigned.
+pub fn open_lots_before(pool: &DbPool, portfolio_id: &str, disposal_id: &str) -> AppResult<Vec<OpenLot>> {
+    let txs = load_tx_records(pool, portfolio_id)?;
+    Ok(costbasis::open_lots(txs, CostBasisMethod::SpecId, Some(disposal_id)))
+}
 
-    match (parse(start), parse(end)) {
-        (Some(s), Some(e)) => (e - s).num_days(),
-        _ => 0,
-    }
+/// The portfolio's open lots in `currency`, valued at `current_price` as of today.
+pub fn unrealized_lots(
+    pool: &DbPool,
+    portfolio_id: &str,
+    method: CostBasisMethod,
+    currency: &str,
+    current_price: f64,
+    rules: &TaxRules,
+) -> AppResult<Vec<UnrealizedLot>> {
+    let txs = load_tx_records_in(pool, portfolio_id, currency)?;
+    let today = chrono::Utc::now().date_naive();
+    Ok(costbasis::unrealized_lots(txs, method, current_price, today, rules))
 }
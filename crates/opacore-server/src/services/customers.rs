@@ -0,0 +1,50 @@
+use serde::Serialize;
+
+use crate::error::{AppError, AppResult};
+
+#[derive(Debug, Serialize)]
+pub struct Customer {
+    pub id: String,
+    pub portfolio_id: String,
+    pub name: String,
+    pub email: Option<String>,
+    pub company: Option<String>,
+    /// Free-form postal address, printed as given.
+    pub billing_address: Option<String>,
+    pub tax_id: Option<String>,
+    /// Fiat currency for this customer's new invoices.
+    pub default_currency: Option<String>,
+    pub notes: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub const CUSTOMER_COLS: &str = "id, portfolio_id, name, email, company, billing_address, tax_id, default_currency, notes, created_at, updated_at";
+
+pub fn row_to_customer(row: &rusqlite::Row) -> rusqlite::Result<Customer> {
+    Ok(Customer {
+        id: row.get(0)?,
+        portfolio_id: row.get(1)?,
+        name: row.get(2)?,
+        email: row.get(3)?,
+        company: row.get(4)?,
+        billing_address: row.get(5)?,
+        tax_id: row.get(6)?,
+        default_currency: row.get(7)?,
+        notes: row.get(8)?,
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+    })
+}
+
+pub fn load(conn: &rusqlite::Connection, portfolio_id: &str, customer_id: &str) -> AppResult<Customer> {
+    conn.query_row(
+        &format!("SELECT {CUSTOMER_COLS} FROM customers WHERE id = ?1 AND portfolio_id = ?2"),
+        rusqlite::params![customer_id, portfolio_id],
+        row_to_customer,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Customer not found".into()),
+        e => AppError::Database(e),
+    })
+}
@@ -0,0 +1,172 @@
+use chrono::{Days, Utc};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::cache::AppCache;
+use crate::services::{costbasis, tax, value_history};
+
+/// The portfolios a user sees in their list: their own and their organizations'.
+const VISIBLE_PORTFOLIOS: &str = "SELECT id FROM portfolios
+     WHERE user_id = ?1 OR organization_id IN (SELECT organization_id FROM organization_members WHERE user_id = ?1)";
+
+/// Everything the dashboard shows, across every portfolio the user can see, in USD.
+#[derive(Debug, Serialize)]
+pub struct Dashboard {
+    pub currency: String,
+    pub current_price: f64,
+    pub total_balance_sat: i64,
+    pub total_value: f64,
+    pub total_cost_basis: f64,
+    pub unrealized_gain: f64,
+    /// Against the value at the end of yesterday, and of 30 days ago, at that day's
+    /// cached price. `None` when there's no price for it.
+    pub change_24h: Option<ValueChange>,
+    pub change_30d: Option<ValueChange>,
+    pub pending_invoices: PendingInvoices,
+    pub portfolios: Vec<DashboardPortfolio>,
+    pub wallets: Vec<WalletStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValueChange {
+    pub amount: f64,
+    /// `None` when the earlier value was 0.
+    pub percent: Option<f64>,
+}
+
+/// Invoices sent and awaiting payment, or paid and awaiting confirmation.
+#[derive(Debug, Serialize)]
+pub struct PendingInvoices {
+    pub count: i64,
+    pub amount_sat: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DashboardPortfolio {
+    pub id: String,
+    pub name: String,
+    pub balance_sat: i64,
+    pub value: f64,
+    pub cost_basis: f64,
+    pub unrealized_gain: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletStatus {
+    pub id: String,
+    pub portfolio_id: String,
+    pub label: String,
+    pub balance_sat: i64,
+    pub last_synced_at: Option<String>,
+    pub last_sync_backend: Option<String>,
+    /// The latest sync job's status (running, completed, failed or interrupted) and
+    /// error, if the wallet has ever had a full sync.
+    pub sync_status: Option<String>,
+    pub sync_error: Option<String>,
+}
+
+/// Build the dashboard for `user_id`, valuing holdings at `current_price` (USD). Each
+/// portfolio uses its own cost-basis method, with summaries from `cache`.
+#[tracing::instrument(level = "debug", skip(pool, cache))]
+pub fn dashboard(pool: &DbPool, cache: &AppCache, user_id: &str, current_price: f64) -> AppResult<Dashboard> {
+    let (rules, listed) = {
+        let conn = pool.get()?;
+        let names: Vec<(String, String)> = conn
+            .prepare(&format!(
+                "SELECT id, name FROM portfolios WHERE id IN ({VISIBLE_PORTFOLIOS}) ORDER BY created_at DESC"
+            ))?
+            .query_map(rusqlite::params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let mut listed = Vec::with_capacity(names.len());
+        for (id, name) in names {
+            let defaults = costbasis::portfolio_defaults(&conn, &id)?;
+            listed.push((id, name, defaults.method));
+        }
+        (tax::tax_rules(&conn, user_id)?, listed)
+    };
+
+    let today = Utc::now().date_naive();
+    let month_ago = today - Days::new(30);
+    let mut portfolios = Vec::with_capacity(listed.len());
+    let (mut day_ago_value, mut month_ago_value) = (Some(0.0), Some(0.0));
+    for (id, name, method) in listed {
+        let summary = costbasis::portfolio_summary(pool, Some(cache), &id, current_price, method, "usd", &rules)?;
+
+        let history = value_history::value_history(pool, &id, "usd", Some(month_ago), Some(today))?;
+        let value_on = |days_back: usize| history.points.iter().rev().nth(days_back).and_then(|p| p.value);
+        day_ago_value = day_ago_value.zip(value_on(1)).map(|(total, value)| total + value);
+        month_ago_value = month_ago_value.zip(value_on(30)).map(|(total, value)| total + value);
+
+        portfolios.push(DashboardPortfolio {
+            id,
+            name,
+            balance_sat: summary.total_balance_sat,
+            value: summary.current_value_usd,
+            cost_basis: summary.total_cost_basis_usd,
+            unrealized_gain: summary.unrealized_gain_usd,
+        });
+    }
+
+    let total_value: f64 = portfolios.iter().map(|p| p.value).sum();
+    let total_cost_basis: f64 = portfolios.iter().map(|p| p.cost_basis).sum();
+    let change = |earlier: Option<f64>| {
+        earlier.map(|earlier| ValueChange {
+            amount: total_value - earlier,
+            percent: (earlier != 0.0).then(|| (total_value - earlier) / earlier * 100.0),
+        })
+    };
+
+    let conn = pool.get()?;
+    let pending_invoices = conn.query_row(
+        &format!(
+            "SELECT COUNT(*), COALESCE(SUM(amount_sat), 0) FROM invoices
+             WHERE portfolio_id IN ({VISIBLE_PORTFOLIOS}) AND status IN ('sent', 'pending_confirmation')"
+        ),
+        rusqlite::params![user_id],
+        |row| {
+            Ok(PendingInvoices {
+                count: row.get(0)?,
+                amount_sat: row.get(1)?,
+            })
+        },
+    )?;
+
+    let wallets = conn
+        .prepare(&format!(
+            "SELECT w.id, w.portfolio_id, w.label, w.balance_sat, w.last_synced_at, w.last_sync_backend, j.status, j.error
+             FROM wallets w
+             LEFT JOIN sync_jobs j ON j.id = (
+                 SELECT id FROM sync_jobs WHERE wallet_id = w.id ORDER BY started_at DESC, rowid DESC LIMIT 1
+             )
+             WHERE w.portfolio_id IN ({VISIBLE_PORTFOLIOS})
+             ORDER BY w.created_at"
+        ))?
+        .query_map(rusqlite::params![user_id], |row| {
+            Ok(WalletStatus {
+                id: row.get(0)?,
+                portfolio_id: row.get(1)?,
+                label: row.get(2)?,
+                balance_sat: row.get(3)?,
+                last_synced_at: row.get(4)?,
+                last_sync_backend: row.get(5)?,
+                sync_status: row.get(6)?,
+                sync_error: row.get(7)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(Dashboard {
+        currency: "usd".to_string(),
+        current_price,
+        total_balance_sat: portfolios.iter().map(|p| p.balance_sat).sum(),
+        total_value,
+        total_cost_basis,
+        unrealized_gain: total_value - total_cost_basis,
+        change_24h: change(day_ago_value),
+        change_30d: change(month_ago_value),
+        pending_invoices,
+        portfolios,
+        wallets,
+    })
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::auth::session::ClientInfo;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::costbasis::TxKind;
+use crate::services::events::{self, DomainEvent};
+use crate::services::import::{self, ImportResult, ImportedTransaction};
+use crate::services::prices;
+use crate::services::tax_locks;
+use crate::services::transaction_audit;
+use crate::services::transaction_splits::COUNTED;
+
+/// Source recorded on transactions inserted to close a balance or lot shortfall.
+pub const CORRECTION_SOURCE: &str = "correction";
+
+/// Problems that make cost basis and reports wrong without anything failing: the engine
+/// treats a missing price as $0, orders same-day transactions arbitrarily, and lets a
+/// sell run out of lots without complaint.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A transaction the engine values with no USD price (or a price of 0).
+    MissingPrice,
+    /// Only a date is known, and other transactions fall on the same day.
+    ImpreciseTime,
+    /// Outflows take the running balance below zero, i.e. history is missing.
+    NegativeBalance,
+    /// A sell disposes of more than the buys and receives before it acquired.
+    Oversold,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    pub transaction_id: String,
+    pub tx_type: String,
+    pub amount_sat: i64,
+    pub transacted_at: String,
+    /// Sats missing at this point, for `negative_balance` and `oversold`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shortfall_sat: Option<i64>,
+    /// The on-chain block time a date-only transaction can take, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IssueCounts {
+    pub missing_price: usize,
+    pub imprecise_time: usize,
+    pub negative_balance: usize,
+    pub oversold: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataQualityReport {
+    pub transaction_count: usize,
+    pub counts: IssueCounts,
+    pub issues: Vec<Issue>,
+}
+
+struct Row {
+    id: String,
+    tx_type: String,
+    amount_sat: i64,
+    price_usd: Option<f64>,
+    transacted_at: String,
+    block_time: Option<String>,
+}
+
+/// Rows in the order the tax engine processes them.
+fn load_rows(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<Vec<Row>> {
+    let mut stmt = conn.prepare(
+        &format!(
+            "SELECT id, tx_type, amount_sat, price_usd, transacted_at, block_time FROM transactions
+             WHERE portfolio_id = ?1 AND {COUNTED} ORDER BY transacted_at ASC, rowid ASC"
+        ),
+    )?;
+    let rows = stmt
+        .query_map(rusqlite::params![portfolio_id], |row| {
+            Ok(Row {
+                id: row.get(0)?,
+                tx_type: row.get(1)?,
+                amount_sat: row.get(2)?,
+                price_usd: row.get(3)?,
+                transacted_at: row.get(4)?,
+                block_time: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Everything whose value the engine uses: acquisitions and income for basis, sales
+/// for proceeds, gifts and donations for their reported fair value.
+fn needs_price(row: &Row) -> bool {
+    let priced = TxKind::of(&row.tx_type)
+        .is_some_and(|kind| kind.is_inflow() || matches!(kind, TxKind::Sale | TxKind::NonTaxableDisposal));
+    priced && row.price_usd.is_none_or(|p| p <= 0.0)
+}
+
+/// A bare date, or midnight exactly — what imports produce when the source has no time.
+fn is_date_only(transacted_at: &str) -> bool {
+    transacted_at.len() == 10
+        || transacted_at.ends_with("T00:00:00Z")
+        || transacted_at.ends_with("T00:00:00.000Z")
+}
+
+pub fn scan(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<DataQualityReport> {
+    let rows = load_rows(conn, portfolio_id)?;
+    let mut per_day: HashMap<&str, usize> = HashMap::new();
+    for row in &rows {
+        *per_day.entry(row.transacted_at.get(..10).unwrap_or(&row.transacted_at)).or_default() += 1;
+    }
+
+    let mut issues = Vec::new();
+    let mut counts = IssueCounts::default();
+    let issue = |kind: IssueKind, row: &Row, shortfall_sat: Option<i64>| Issue {
+        kind,
+        transaction_id: row.id.clone(),
+        tx_type: row.tx_type.clone(),
+        amount_sat: row.amount_sat,
+        transacted_at: row.transacted_at.clone(),
+        shortfall_sat,
+        block_time: if kind == IssueKind::ImpreciseTime { row.block_time.clone() } else { None },
+    };
+
+    // Balance counts every in- and outflow; lots only what the engine matches sells against
+    let (mut balance, mut lots) = (0i64, 0i64);
+    for row in &rows {
+        if needs_price(row) {
+            counts.missing_price += 1;
+            issues.push(issue(IssueKind::MissingPrice, row, None));
+        }
+        let day = row.transacted_at.get(..10).unwrap_or(&row.transacted_at);
+        if is_date_only(&row.transacted_at) && per_day.get(day).copied().unwrap_or(0) > 1 {
+            counts.imprecise_time += 1;
+            issues.push(issue(IssueKind::ImpreciseTime, row, None));
+        }
+
+        let Some(kind) = TxKind::of(&row.tx_type) else {
+            continue;
+        };
+        if kind.is_inflow() {
+            balance += row.amount_sat;
+            lots += row.amount_sat;
+        } else if kind.is_outflow() {
+            if kind.disposes_lots() {
+                if kind == TxKind::Sale && row.amount_sat > lots {
+                    counts.oversold += 1;
+                    issues.push(issue(IssueKind::Oversold, row, Some(row.amount_sat - lots.max(0))));
+                }
+                lots = (lots - row.amount_sat).max(0);
+            }
+            balance -= row.amount_sat;
+            if balance < 0 {
+                counts.negative_balance += 1;
+                issues.push(issue(IssueKind::NegativeBalance, row, Some(-balance)));
+                // Report each gap once: carry on as if it had been filled
+                balance = 0;
+            }
+        }
+    }
+
+    Ok(DataQualityReport {
+        transaction_count: rows.len(),
+        counts,
+        issues,
+    })
+}
+
+// ── Fixes ──
+
+/// Price every flagged transaction: at `price_usd` if given, otherwise from historical
+/// daily prices. Returns how many were updated. Fails without changing anything if one is
+/// in a locked tax year, unless `user` is an admin.
+pub async fn fix_missing_prices(
+    pool: &DbPool,
+    api_url: &str,
+    user: &User,
+    client: &ClientInfo,
+    portfolio_id: &str,
+    transaction_ids: Option<&[String]>,
+    price_usd: Option<f64>,
+) -> AppResult<usize> {
+    let targets: Vec<(String, String)> = {
+        let conn = pool.get()?;
+        load_rows(&conn, portfolio_id)?
+            .into_iter()
+            .filter(|row| needs_price(row) && transaction_ids.is_none_or(|ids| ids.contains(&row.id)))
+            .map(|row| (row.id, row.transacted_at.chars().take(10).collect()))
+            .collect()
+    };
+    if targets.is_empty() {
+        return Ok(0);
+    }
+    {
+        let conn = pool.get()?;
+        for (id, date) in &targets {
+            tax_locks::check_dates(&conn, portfolio_id, id, &[date], user, client)?;
+        }
+    }
+
+    let Some(price) = price_usd else {
+        return Ok(prices::backfill_transactions(pool, api_url, &targets).await);
+    };
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    for (id, _) in &targets {
+        let before = transaction_audit::snapshot(&db_tx, id)?.unwrap_or_default();
+        db_tx.execute(
+            "UPDATE transactions SET price_usd = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![price, now, id],
+        )?;
+        events::record(&db_tx, portfolio_id, &DomainEvent::TransactionUpdated { transaction_id: id.clone() })?;
+        transaction_audit::record_updated(&db_tx, portfolio_id, id, Some(&user.id), &before)?;
+    }
+    db_tx.commit()?;
+    Ok(targets.len())
+}
+
+/// Give date-only transactions a full timestamp: the one in `times` if given, otherwise
+/// their block time. Flagged transactions with neither are left alone.
+pub fn fix_imprecise_times(
+    conn: &rusqlite::Connection,
+    user: &User,
+    client: &ClientInfo,
+    portfolio_id: &str,
+    times: &HashMap<String, String>,
+) -> AppResult<usize> {
+    let report = scan(conn, portfolio_id)?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut fixed = 0;
+    for issue in report.issues.iter().filter(|i| i.kind == IssueKind::ImpreciseTime) {
+        let Some(time) = times.get(&issue.transaction_id).or(issue.block_time.as_ref()) else {
+            continue;
+        };
+        let time = chrono::DateTime::parse_from_rfc3339(time)
+            .map_err(|_| AppError::BadRequest(format!("Invalid timestamp for {}: {time}", issue.transaction_id)))?
+            .with_timezone(&chrono::Utc)
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string();
+        if time.get(..10) != issue.transacted_at.get(..10) {
+            return Err(AppError::BadRequest(format!(
+                "Timestamp for {} must fall on {}",
+                issue.transaction_id,
+                &issue.transacted_at[..10.min(issue.transacted_at.len())]
+            )));
+        }
+        tax_locks::check_dates(conn, portfolio_id, &issue.transaction_id, &[&issue.transacted_at], user, client)?;
+        let before = transaction_audit::snapshot(conn, &issue.transaction_id)?.unwrap_or_default();
+        conn.execute(
+            "UPDATE transactions SET transacted_at = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![time, now, issue.transaction_id],
+        )?;
+        events::record(
+            conn,
+            portfolio_id,
+            &DomainEvent::TransactionUpdated { transaction_id: issue.transaction_id.clone() },
+        )?;
+        transaction_audit::record_updated(conn, portfolio_id, &issue.transaction_id, Some(&user.id), &before)?;
+        fixed += 1;
+    }
+    Ok(fixed)
+}
+
+/// Close every shortfall of `kind` (`negative_balance` with receives, `oversold` with
+/// buys) by inserting a correction one second before the transaction that exposed it.
+/// Without `price_usd` the corrections are priced from history like any new transaction.
+/// Run inside a SQLite transaction.
+pub fn fix_shortfalls(
+    conn: &rusqlite::Connection,
+    user: &User,
+    client: &ClientInfo,
+    portfolio_id: &str,
+    kind: IssueKind,
+    price_usd: Option<f64>,
+) -> AppResult<ImportResult> {
+    let tx_type = match kind {
+        IssueKind::NegativeBalance => "receive",
+        IssueKind::Oversold => "buy",
+        _ => return Err(AppError::BadRequest("Only shortfalls can be fixed with corrections".into())),
+    };
+    let report = scan(conn, portfolio_id)?;
+    let corrections = report
+        .issues
+        .iter()
+        .filter(|i| i.kind == kind)
+        .map(|issue| {
+            let at = chrono::DateTime::parse_from_rfc3339(&issue.transacted_at)
+                .map(|t| t.with_timezone(&chrono::Utc))
+                .or_else(|_| {
+                    chrono::NaiveDate::parse_from_str(&issue.transacted_at, "%Y-%m-%d")
+                        .map(|d| d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+                })
+                .map_err(|_| AppError::BadRequest(format!("Unreadable date on {}", issue.transaction_id)))?;
+            Ok(ImportedTransaction {
+                line: 0,
+                txid: None,
+                tx_type,
+                amount_sat: issue.shortfall_sat.unwrap_or(0),
+                fee_sat: None,
+                price_usd,
+                fiat_amount: price_usd.map(|p| p * issue.shortfall_sat.unwrap_or(0) as f64 / 1e8),
+                fiat_currency: "usd".into(),
+                transacted_at: (at - chrono::Duration::seconds(1)).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                labels: vec![],
+            })
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+
+    import::insert_transactions(conn, &user.id, Some((user, client)), portfolio_id, None, CORRECTION_SOURCE, &corrections)
+}
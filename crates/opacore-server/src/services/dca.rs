@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::cache::AppCache;
+use crate::services::costbasis::{self, CostBasisMethod};
+use crate::services::tax::TaxRules;
+
+/// What the portfolio's purchases (`buy` transactions) cost on average, in `currency`.
+#[derive(Debug, Serialize)]
+pub struct DcaAnalysis {
+    pub currency: String,
+    /// Spent on purchases, fees included.
+    pub total_invested: f64,
+    pub total_bought_sat: i64,
+    pub purchase_count: i64,
+    /// Purchases with no price, left out of every figure here.
+    pub unpriced: i64,
+    /// Per BTC, over every purchase. `None` before the first.
+    pub average_cost: Option<f64>,
+    /// The price at which what's held now is worth its cost basis under the method.
+    /// `None` with nothing held.
+    pub break_even_price: Option<f64>,
+    pub months: Vec<DcaMonth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DcaMonth {
+    /// `YYYY-MM`.
+    pub month: String,
+    pub invested: f64,
+    pub bought_sat: i64,
+    /// Sats bought per unit of currency spent that month.
+    pub sats_per_unit: Option<f64>,
+    /// Per BTC that month.
+    pub average_price: Option<f64>,
+    /// Totals up to the end of the month.
+    pub cumulative_invested: f64,
+    pub cumulative_bought_sat: i64,
+    pub average_cost: Option<f64>,
+}
+
+#[tracing::instrument(level = "debug", skip(pool, cache, rules))]
+pub fn dca_analysis(
+    pool: &DbPool,
+    cache: Option<&AppCache>,
+    portfolio_id: &str,
+    method: CostBasisMethod,
+    currency: &str,
+    rules: &TaxRules,
+) -> AppResult<DcaAnalysis> {
+    let txs = costbasis::load_tx_records_in(pool, portfolio_id, currency)?;
+
+    let mut unpriced = 0;
+    let mut purchase_count = 0;
+    let mut by_month: BTreeMap<String, (f64, i64)> = BTreeMap::new();
+    for tx in txs.iter().filter(|tx| tx.tx_type == "buy") {
+        let (Some(price), Some(month)) = (tx.price_usd, tx.transacted_at.get(..7)) else {
+            unpriced += 1;
+            continue;
+        };
+        purchase_count += 1;
+        let cost = (tx.amount_sat + tx.fee_sat.unwrap_or(0)) as f64 / 1e8 * price;
+        let (invested, bought_sat) = by_month.entry(month.to_string()).or_default();
+        *invested += cost;
+        *bought_sat += tx.amount_sat;
+    }
+
+    let mut cumulative_invested = 0.0;
+    let mut cumulative_bought_sat = 0;
+    let months: Vec<DcaMonth> = by_month
+        .into_iter()
+        .map(|(month, (invested, bought_sat))| {
+            cumulative_invested += invested;
+            cumulative_bought_sat += bought_sat;
+            DcaMonth {
+                month,
+                invested: round2(invested),
+                bought_sat,
+                sats_per_unit: (invested > 0.0).then(|| (bought_sat as f64 / invested * 100.0).round() / 100.0),
+                average_price: per_btc(invested, bought_sat),
+                cumulative_invested: round2(cumulative_invested),
+                cumulative_bought_sat,
+                average_cost: per_btc(cumulative_invested, cumulative_bought_sat),
+            }
+        })
+        .collect();
+
+    let summary = costbasis::portfolio_summary(pool, cache, portfolio_id, 0.0, method, currency, rules)?;
+
+    Ok(DcaAnalysis {
+        currency: currency.to_string(),
+        total_invested: round2(cumulative_invested),
+        total_bought_sat: cumulative_bought_sat,
+        purchase_count,
+        unpriced,
+        average_cost: per_btc(cumulative_invested, cumulative_bought_sat),
+        break_even_price: per_btc(summary.total_cost_basis_usd, summary.total_balance_sat),
+        months,
+    })
+}
+
+/// `amount` per BTC of `sat`, or `None` for no sats.
+fn per_btc(amount: f64, sat: i64) -> Option<f64> {
+    (sat > 0).then(|| round2(amount / (sat as f64 / 1e8)))
+}
+
+fn round2(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
@@ -1,5 +1,6 @@
 use crate::config::Config;
 use crate::error::{AppError, AppResult};
+use crate::services::http;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -8,9 +9,33 @@ struct ResendEmail {
     to: Vec<String>,
     subject: String,
     html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+}
+
+/// Text from users or their clients (names, invoice fields, user agents) is escaped
+/// before it goes into an email's HTML.
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
 }
 
 pub async fn send_email(config: &Config, to: &str, subject: &str, html: &str) -> AppResult<()> {
+    send_email_with_reply_to(config, to, None, subject, html).await
+}
+
+/// Like `send_email`, with replies going to `reply_to` instead of `FROM_EMAIL`; used for
+/// mail sent on a user's behalf to someone else.
+pub async fn send_email_with_reply_to(
+    config: &Config,
+    to: &str,
+    reply_to: Option<&str>,
+    subject: &str,
+    html: &str,
+) -> AppResult<()> {
     let api_key = match &config.resend_api_key {
         Some(key) => key,
         None => {
@@ -19,12 +44,13 @@ pub async fn send_email(config: &Config, to: &str, subject: &str, html: &str) ->
         }
     };
 
-    let client = reqwest::Client::new();
+    let client = http::client()?;
     let payload = ResendEmail {
         from: config.from_email.clone(),
         to: vec![to.to_string()],
         subject: subject.to_string(),
         html: html.to_string(),
+        reply_to: reply_to.map(String::from),
     };
 
     let res = client
@@ -33,7 +59,7 @@ pub async fn send_email(config: &Config, to: &str, subject: &str, html: &str) ->
         .json(&payload)
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Failed to send email: {e}")))?;
+        .map_err(|e| http::upstream_error("resend", "Failed to send email", e))?;
 
     if !res.status().is_success() {
         let body = res.text().await.unwrap_or_default();
@@ -98,6 +124,80 @@ pub async fn send_password_reset_email(
     send_email(config, to, subject, &html).await
 }
 
+pub async fn send_email_change_confirmation(
+    config: &Config,
+    to: &str,
+    name: &str,
+    token: &str,
+) -> AppResult<()> {
+    let confirm_url = format!("{}/confirm-email?token={}", config.app_url, token);
+    let subject = "Confirm your new Opacore email address";
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Confirm your new email, {name}</h2>
+  <p>Click the button below to start using this address for your Opacore account:</p>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{confirm_url}" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">Confirm Email</a>
+  </p>
+  <p style="font-size: 14px; color: #666;">Or copy and paste this link into your browser:</p>
+  <p style="font-size: 14px; word-break: break-all; color: #666;">{confirm_url}</p>
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">This link expires in 24 hours. Until then your account keeps its current address. If you didn't ask for this, you can safely ignore this email.</p>
+</body>
+</html>"#
+    );
+    send_email(config, to, subject, &html).await
+}
+
+/// Sent to the old address once the change is confirmed, so a takeover doesn't go unnoticed.
+pub async fn send_email_changed_notice(config: &Config, to: &str, name: &str, new_email: &str) -> AppResult<()> {
+    let subject = "Your Opacore email address was changed";
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Email address changed</h2>
+  <p>Hi {name}, your Opacore account now uses <strong>{new_email}</strong>. Sign-ins and account emails go to that address from now on.</p>
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">If you didn't make this change, reply to this email right away.</p>
+</body>
+</html>"#
+    );
+    send_email(config, to, subject, &html).await
+}
+
+pub async fn send_new_login_alert(
+    config: &Config,
+    to: &str,
+    name: &str,
+    ip_address: &str,
+    user_agent: &str,
+    revoke_url: &str,
+) -> AppResult<()> {
+    let subject = "New sign-in to your Opacore account";
+    let (name, ip_address, user_agent) = (escape(name), escape(ip_address), escape(user_agent));
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">New sign-in</h2>
+  <p>Hi {name}, your Opacore account was just signed in to from a device or network it hasn't been used from before.</p>
+  <p><strong>Time:</strong> {}<br /><strong>IP address:</strong> {ip_address}<br /><strong>Device:</strong> {user_agent}</p>
+  <p>If this was you, there's nothing to do. If not, sign that session out now and then change your password:</p>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{revoke_url}" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">This wasn't me</a>
+  </p>
+  <p style="font-size: 14px; color: #666;">Or copy and paste this link into your browser:</p>
+  <p style="font-size: 14px; word-break: break-all; color: #666;">{revoke_url}</p>
+</body>
+</html>"#,
+        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    send_email(config, to, subject, &html).await
+}
+
 pub async fn send_admin_notification(
     config: &Config,
     user_name: &str,
@@ -126,3 +226,80 @@ pub async fn send_admin_notification(
     );
     send_email(config, &admin_email, &subject, &html).await
 }
+
+/// Tell a user their background job finished, with a download button if it produced a file.
+pub async fn send_job_email(
+    config: &Config,
+    to: &str,
+    name: &str,
+    subject: &str,
+    summary: &str,
+    download_url: Option<&str>,
+) -> AppResult<()> {
+    let download = match download_url {
+        Some(url) => format!(
+            r#"<p style="text-align: center; margin: 30px 0;">
+    <a href="{url}" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">Download</a>
+  </p>
+  <p style="font-size: 14px; color: #666;">Or copy and paste this link into your browser:</p>
+  <p style="font-size: 14px; word-break: break-all; color: #666;">{url}</p>"#
+        ),
+        None => String::new(),
+    };
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Hi {name},</h2>
+  <p>{summary}</p>
+  {download}
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">Anyone with the link can download the file until it expires, so don't forward this email.</p>
+</body>
+</html>"#
+    );
+    send_email(config, to, subject, &html).await
+}
+
+pub async fn send_account_deleted_email(config: &Config, to: &str, name: &str) -> AppResult<()> {
+    let subject = "Your Opacore account has been deleted";
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Goodbye, {name}</h2>
+  <p>Your Opacore account and everything in it (portfolios, wallets, transactions, invoices and exports) has been deleted. This can't be undone.</p>
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">If you didn't delete your account, reply to this email right away.</p>
+</body>
+</html>"#
+    );
+    send_email(config, to, subject, &html).await
+}
+
+/// Sent whether or not `to` has an account; accepting it needs one with that address.
+pub async fn send_organization_invitation(
+    config: &Config,
+    to: &str,
+    inviter: &str,
+    organization: &str,
+) -> AppResult<()> {
+    let subject = "You've been invited to an organization on Opacore";
+    let (inviter, organization) = (escape(inviter), escape(organization));
+    let invitations_url = format!("{}/organizations", config.app_url);
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Join {organization}</h2>
+  <p>{inviter} invited you to the <strong>{organization}</strong> organization on Opacore. Sign in or create an account with this address to accept or decline:</p>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{invitations_url}" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">View Invitation</a>
+  </p>
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">You won't join unless you accept. If you don't know them, you can safely ignore this email.</p>
+</body>
+</html>"#
+    );
+    send_email(config, to, subject, &html).await
+}
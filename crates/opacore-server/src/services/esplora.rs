@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use bdk_wallet::bitcoin::Network;
+use tracing::Instrument;
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
+use crate::services::http;
+
+/// How long a failed backend is pushed to the back of the queue before it's preferred again.
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Default, Clone)]
+struct BackendHealth {
+    consecutive_failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl BackendHealth {
+    fn is_cooling_down(&self) -> bool {
+        self.last_failure
+            .is_some_and(|t| t.elapsed() < FAILURE_COOLDOWN)
+    }
+}
+
+/// The configured Esplora instances for each network plus their in-memory health.
+/// Cheap to clone — state is shared between the request handlers and background tasks.
+#[derive(Clone)]
+pub struct EsploraBackends {
+    networks: Arc<HashMap<Network, Vec<String>>>,
+    health: Arc<RwLock<HashMap<String, BackendHealth>>>,
+}
+
+impl EsploraBackends {
+    pub fn from_config(config: &Config) -> Self {
+        let mut networks = HashMap::new();
+        networks.insert(Network::Bitcoin, config.esplora_urls.clone());
+        networks.insert(Network::Testnet, config.esplora_testnet_urls.clone());
+        networks.insert(Network::Signet, config.esplora_signet_urls.clone());
+        networks.insert(Network::Regtest, config.esplora_regtest_urls.clone());
+
+        Self {
+            networks: Arc::new(networks),
+            health: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Backends for a network in the order they should be tried: healthy ones in
+    /// configured order, then recently failed ones (least recently failed first).
+    pub fn urls(&self, network: Network) -> Vec<String> {
+        let configured = self
+            .networks
+            .get(&network)
+            .or_else(|| self.networks.get(&Network::Bitcoin))
+            .cloned()
+            .unwrap_or_default();
+
+        let health = self.health.read().unwrap_or_else(|e| e.into_inner());
+        let (mut healthy, mut cooling): (Vec<String>, Vec<String>) = configured
+            .into_iter()
+            .partition(|url| !health.get(url).is_some_and(|h| h.is_cooling_down()));
+
+        cooling.sort_by_key(|url| health.get(url).and_then(|h| h.last_failure));
+        healthy.extend(cooling);
+        healthy
+    }
+
+    pub fn mark_success(&self, url: &str) {
+        let mut health = self.health.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(h) = health.get_mut(url) {
+            if h.consecutive_failures > 0 {
+                tracing::info!("Esplora backend {url} recovered after {} failures", h.consecutive_failures);
+            }
+            *h = BackendHealth::default();
+        }
+    }
+
+    pub fn mark_failure(&self, url: &str) {
+        let mut health = self.health.write().unwrap_or_else(|e| e.into_inner());
+        let h = health.entry(url.to_string()).or_default();
+        h.consecutive_failures += 1;
+        h.last_failure = Some(Instant::now());
+    }
+
+    /// Run `op` against each backend for `network` until one succeeds.
+    /// Returns the result together with the URL of the backend that served it.
+    ///
+    /// Only upstream failures (`AppError::Internal` and `UpstreamTimeout`) trigger failover — client and
+    /// database errors are returned straight away since another backend won't help.
+    pub async fn with_failover<T, F, Fut>(&self, network: Network, mut op: F) -> AppResult<(T, String)>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = AppResult<T>>,
+    {
+        let urls = self.urls(network);
+        let mut last_err = None;
+
+        for url in urls {
+            let span = tracing::debug_span!("esplora.request", otel.kind = "client", backend = %url, %network);
+            match op(url.clone()).instrument(span).await {
+                Ok(value) => {
+                    self.mark_success(&url);
+                    return Ok((value, url));
+                }
+                Err(e @ (AppError::Internal(_) | AppError::UpstreamTimeout(_))) => {
+                    tracing::warn!("Esplora backend {url} failed, trying next: {e}");
+                    self.mark_failure(&url);
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            AppError::Internal(format!("No Esplora backends configured for {network}"))
+        }))
+    }
+
+    /// Current chain tip height for `network`, served from the cache when fresh.
+    pub async fn tip_height(&self, cache: &AppCache, network: Network) -> AppResult<u32> {
+        if let Some(height) = cache.chain_tip(network) {
+            return Ok(height);
+        }
+
+        let (height, _url) = self
+            .with_failover(network, |url| async move { fetch_tip_height(&url).await })
+            .await?;
+        cache.set_chain_tip(network, height);
+        Ok(height)
+    }
+
+    fn all_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = self.networks.values().flatten().cloned().collect();
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+}
+
+async fn fetch_tip_height(esplora_url: &str) -> AppResult<u32> {
+    let http = http::client()?;
+
+    let url = format!("{esplora_url}/blocks/tip/height");
+    let resp = http
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("esplora", format!("Esplora request failed for {url}"), e))?;
+
+    if !resp.status().is_success() {
+        return Err(AppError::Internal(format!("Esplora returned {} for {url}", resp.status())));
+    }
+
+    resp.text()
+        .await
+        .map_err(|e| http::upstream_error("esplora", "Esplora response read failed", e))?
+        .trim()
+        .parse()
+        .map_err(|e| AppError::Internal(format!("Esplora tip height parse failed: {e}")))
+}
+
+/// Background task that pings every configured backend's tip height so a dead
+/// instance is demoted before a user-facing sync has to discover it.
+pub async fn run_health_checker(backends: EsploraBackends, interval: Duration) {
+    let http = match reqwest::Client::builder()
+        .user_agent("opacore/0.1")
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Esplora health checker: failed to build HTTP client: {e}");
+            return;
+        }
+    };
+
+    loop {
+        for url in backends.all_urls() {
+            let ok = match http.get(format!("{url}/blocks/tip/height")).send().await {
+                Ok(resp) => resp.status().is_success(),
+                Err(_) => false,
+            };
+            if ok {
+                backends.mark_success(&url);
+            } else {
+                tracing::warn!("Esplora health check failed for {url}");
+                backends.mark_failure(&url);
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::{email, invoice_transactions, prices, quotas, webhooks};
+
+/// Events handed to a consumer per round.
+const BATCH_SIZE: i64 = 200;
+
+/// Events every consumer has processed are deleted after this many days.
+const RETENTION_DAYS: i64 = 30;
+
+/// A balance-affecting change, recorded in `domain_events` alongside the change itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DomainEvent {
+    #[serde(rename = "transaction.created")]
+    TransactionCreated {
+        transaction_id: String,
+        wallet_id: Option<String>,
+    },
+    #[serde(rename = "transaction.updated")]
+    TransactionUpdated { transaction_id: String },
+    #[serde(rename = "transaction.deleted")]
+    TransactionDeleted { transaction_id: String },
+    /// Paid on-chain (`txid`) or over Lightning (`payment_hash`).
+    #[serde(rename = "invoice.paid")]
+    InvoicePaid {
+        invoice_id: String,
+        txid: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payment_hash: Option<String>,
+        amount_sat: i64,
+    },
+    /// A transaction paid less than the invoice amount; one event per transaction.
+    #[serde(rename = "invoice.partially_paid")]
+    InvoicePartiallyPaid {
+        invoice_id: String,
+        txid: String,
+        amount_sat: i64,
+    },
+    #[serde(rename = "invoice.expired")]
+    InvoiceExpired { invoice_id: String },
+    #[serde(rename = "wallet.synced")]
+    WalletSynced {
+        wallet_id: String,
+        new_transactions: usize,
+        balance_sat: u64,
+    },
+}
+
+impl DomainEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::TransactionCreated { .. } => "transaction.created",
+            DomainEvent::TransactionUpdated { .. } => "transaction.updated",
+            DomainEvent::TransactionDeleted { .. } => "transaction.deleted",
+            DomainEvent::InvoicePaid { .. } => "invoice.paid",
+            DomainEvent::InvoicePartiallyPaid { .. } => "invoice.partially_paid",
+            DomainEvent::InvoiceExpired { .. } => "invoice.expired",
+            DomainEvent::WalletSynced { .. } => "wallet.synced",
+        }
+    }
+}
+
+/// An event read back from the log.
+struct StoredEvent {
+    id: i64,
+    portfolio_id: String,
+    event: DomainEvent,
+    created_at: String,
+}
+
+/// Append an event to the log. Pass the connection (or SQLite transaction) that
+/// made the change so the event commits or rolls back with it.
+pub fn record(conn: &rusqlite::Connection, portfolio_id: &str, event: &DomainEvent) -> AppResult<i64> {
+    let payload = serde_json::to_string(event)
+        .map_err(|e| AppError::Internal(format!("Failed to encode event: {e}")))?;
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO domain_events (event_type, portfolio_id, payload, created_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![event.name(), portfolio_id, payload, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Background consumers of the event log. Each keeps its own cursor, so a slow or
+/// failing consumer never holds the others back.
+#[derive(Debug, Clone, Copy)]
+enum Consumer {
+    /// Prices new and edited transactions, and whatever a wallet sync brought in.
+    Prices,
+    /// Emails the portfolio owner when an invoice is paid.
+    Notifications,
+    /// Queues invoice events for the owner's webhook endpoints.
+    Webhooks,
+    /// Books invoice payments into the portfolio as transactions.
+    InvoiceTransactions,
+}
+
+const CONSUMERS: [Consumer; 4] = [
+    Consumer::Prices,
+    Consumer::Notifications,
+    Consumer::Webhooks,
+    Consumer::InvoiceTransactions,
+];
+
+impl Consumer {
+    fn name(self) -> &'static str {
+        match self {
+            Consumer::Prices => "prices",
+            Consumer::Notifications => "notifications",
+            Consumer::Webhooks => "webhooks",
+            Consumer::InvoiceTransactions => "invoice_transactions",
+        }
+    }
+}
+
+/// Position of a consumer in the log. A consumer seen for the first time starts at
+/// the current end rather than replaying history.
+fn cursor(conn: &rusqlite::Connection, consumer: Consumer) -> AppResult<i64> {
+    conn.execute(
+        "INSERT OR IGNORE INTO domain_event_cursors (consumer, last_event_id)
+         SELECT ?1, COALESCE(MAX(id), 0) FROM domain_events",
+        rusqlite::params![consumer.name()],
+    )?;
+    let last: i64 = conn.query_row(
+        "SELECT last_event_id FROM domain_event_cursors WHERE consumer = ?1",
+        rusqlite::params![consumer.name()],
+        |row| row.get(0),
+    )?;
+    Ok(last)
+}
+
+fn advance_cursor(conn: &rusqlite::Connection, consumer: Consumer, event_id: i64) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE domain_event_cursors SET last_event_id = ?1, updated_at = ?2 WHERE consumer = ?3",
+        rusqlite::params![event_id, now, consumer.name()],
+    )?;
+    Ok(())
+}
+
+fn events_after(conn: &rusqlite::Connection, after_id: i64) -> AppResult<Vec<StoredEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, portfolio_id, payload, created_at FROM domain_events
+         WHERE id > ?1 ORDER BY id ASC LIMIT ?2",
+    )?;
+    let rows = stmt
+        .query_map(rusqlite::params![after_id, BATCH_SIZE], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .filter_map(|r| r.ok())
+        .filter_map(|(id, portfolio_id, payload, created_at)| match serde_json::from_str(&payload) {
+            Ok(event) => Some(StoredEvent { id, portfolio_id, event, created_at }),
+            Err(e) => {
+                tracing::warn!("Skipping undecodable domain event {id}: {e}");
+                None
+            }
+        })
+        .collect();
+    Ok(rows)
+}
+
+/// Background task that feeds new domain events to each consumer.
+pub async fn run_event_dispatcher(pool: DbPool, config: Config) {
+    tracing::info!(
+        "Domain event dispatcher started (interval: {}s)",
+        config.intervals.event_poll.as_secs()
+    );
+    let lookups = prices::spawn_lookup_worker(pool.clone(), config.coingecko_api_url.clone());
+    let mut last_prune = std::time::Instant::now();
+
+    loop {
+        tokio::time::sleep(config.intervals.event_poll).await;
+
+        for consumer in CONSUMERS {
+            if let Err(e) = dispatch(&pool, &config, &lookups, consumer).await {
+                tracing::error!("Event consumer {}: {e}", consumer.name());
+            }
+        }
+
+        if last_prune.elapsed() >= config.intervals.event_prune {
+            last_prune = std::time::Instant::now();
+            if let Err(e) = prune(&pool) {
+                tracing::warn!("Failed to prune domain events: {e}");
+            }
+        }
+    }
+}
+
+async fn dispatch(
+    pool: &DbPool,
+    config: &Config,
+    lookups: &prices::PriceLookups,
+    consumer: Consumer,
+) -> AppResult<()> {
+    let events = {
+        let conn = pool.get()?;
+        let after = cursor(&conn, consumer)?;
+        events_after(&conn, after)?
+    };
+    let Some(last) = events.last().map(|e| e.id) else {
+        return Ok(());
+    };
+
+    match consumer {
+        Consumer::Prices => backfill_prices(pool, config, lookups, &events).await,
+        Consumer::Notifications => notify(pool, config, &events).await,
+        Consumer::Webhooks => {
+            for stored in &events {
+                webhooks::enqueue(pool, stored.id, &stored.portfolio_id, &stored.event)?;
+            }
+        }
+        // Booking is idempotent, so a batch that fails part-way is simply retried
+        Consumer::InvoiceTransactions => {
+            for stored in &events {
+                if let DomainEvent::InvoicePaid { invoice_id, txid, amount_sat, .. } = &stored.event {
+                    invoice_transactions::book_payment(
+                        pool,
+                        config,
+                        invoice_id,
+                        txid.as_deref(),
+                        *amount_sat,
+                        &stored.created_at,
+                    )
+                    .await?;
+                }
+            }
+        }
+    }
+
+    let conn = pool.get()?;
+    advance_cursor(&conn, consumer, last)
+}
+
+async fn backfill_prices(pool: &DbPool, config: &Config, lookups: &prices::PriceLookups, events: &[StoredEvent]) {
+    // One backfill per wallet/portfolio touched in the batch; each skips already-priced rows
+    let mut wallets = BTreeSet::new();
+    let mut portfolios = BTreeSet::new();
+    let mut transactions = BTreeSet::new();
+    for stored in events {
+        match &stored.event {
+            DomainEvent::WalletSynced { wallet_id, .. } => {
+                wallets.insert(wallet_id.clone());
+            }
+            DomainEvent::TransactionCreated { transaction_id, .. }
+            | DomainEvent::TransactionUpdated { transaction_id } => {
+                portfolios.insert(stored.portfolio_id.clone());
+                transactions.insert(transaction_id.clone());
+            }
+            _ => {}
+        }
+    }
+
+    for wallet_id in &wallets {
+        prices::backfill_wallet_prices(pool.clone(), config.coingecko_api_url.clone(), wallet_id.clone()).await;
+    }
+    for portfolio_id in portfolios {
+        prices::backfill_portfolio_prices(pool.clone(), config.coingecko_api_url.clone(), portfolio_id).await;
+    }
+
+    // Whatever the bulk sources couldn't price gets a lookup of its own date
+    let unpriced = pool.get().map_err(AppError::from).and_then(|conn| {
+        let transactions: Vec<String> = transactions.into_iter().collect();
+        let wallets: Vec<String> = wallets.into_iter().collect();
+        prices::unpriced_transactions(&conn, &transactions, &wallets)
+    });
+    match unpriced {
+        Ok(rows) if !rows.is_empty() => {
+            // Never hold up the other consumers; rows left unpriced are queued again on
+            // their wallet's next sync, or by the startup backfill
+            if let Err(e) = lookups.try_send(rows) {
+                tracing::warn!("Price lookups not queued: {e}");
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to find unpriced transactions: {e}"),
+    }
+}
+
+async fn notify(pool: &DbPool, config: &Config, events: &[StoredEvent]) {
+    for stored in events {
+        let DomainEvent::InvoicePaid { invoice_id, txid, payment_hash, amount_sat } = &stored.event else {
+            continue;
+        };
+
+        let Ok(conn) = pool.get() else {
+            continue;
+        };
+        let recipient: Option<(String, String, String, Option<String>)> = conn
+            .query_row(
+                "SELECT u.id, u.email, u.name, i.invoice_number FROM invoices i
+                 JOIN portfolios p ON p.id = i.portfolio_id
+                 JOIN users u ON u.id = p.user_id
+                 WHERE i.id = ?1",
+                rusqlite::params![invoice_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .ok();
+        // Invoice or portfolio deleted since the payment was seen
+        let Some((user_id, to, name, invoice_number)) = recipient else {
+            continue;
+        };
+        if !quotas::consume_email(&conn, config, &user_id).unwrap_or(false) {
+            continue;
+        }
+        drop(conn);
+
+        let reference = invoice_number.unwrap_or_else(|| invoice_id.clone());
+        let payment = match (txid, payment_hash) {
+            (Some(txid), _) => format!("<strong>Transaction:</strong> <code>{txid}</code>"),
+            (None, Some(hash)) => format!("<strong>Lightning payment hash:</strong> <code>{hash}</code>"),
+            (None, None) => String::new(),
+        };
+        let subject = format!("Invoice {reference} paid");
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Payment received</h2>
+  <p>Hi {name}, invoice <strong>{reference}</strong> has been paid.</p>
+  <p><strong>Amount:</strong> {amount_sat} sats<br />{payment}</p>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{app_url}/invoices" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">View Invoices</a>
+  </p>
+</body>
+</html>"#,
+            app_url = config.app_url,
+        );
+
+        if let Err(e) = email::send_email(config, &to, &subject, &html).await {
+            tracing::warn!("Failed to send invoice paid notification for {invoice_id}: {e}");
+        }
+    }
+}
+
+/// Drop old events that every consumer has already processed.
+fn prune(pool: &DbPool) -> AppResult<()> {
+    let conn = pool.get()?;
+    let cutoff = (chrono::Utc::now() - chrono::Duration::days(RETENTION_DAYS))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    conn.execute(
+        "DELETE FROM domain_events
+         WHERE created_at < ?1 AND id <= (SELECT COALESCE(MIN(last_event_id), 0) FROM domain_event_cursors)",
+        rusqlite::params![cutoff],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::exchange_import::{self, is_btc, kraken_fiat};
+use crate::services::http;
+use crate::services::import::{self, ImportResult, ImportedTransaction};
+
+/// Pause between Kraken history pages, to stay under its private API call budget.
+const KRAKEN_PAGE_DELAY: Duration = Duration::from_secs(3);
+/// `CB-VERSION` sent with Coinbase requests, pinning the response format.
+const COINBASE_API_VERSION: &str = "2024-01-01";
+
+/// Exchanges whose trade history can be pulled with an API key.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Exchange {
+    Coinbase,
+    Kraken,
+}
+
+impl Exchange {
+    /// Value stored in `exchange_connections.exchange` and `transactions.source`.
+    pub fn source(self) -> &'static str {
+        match self {
+            Exchange::Coinbase => "coinbase",
+            Exchange::Kraken => "kraken",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Exchange::Coinbase => "Coinbase",
+            Exchange::Kraken => "Kraken",
+        }
+    }
+}
+
+/// A user's read-only API key for an exchange. Trades are imported into
+/// `portfolio_id`; the secret and import cursor never leave the server.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExchangeConnection {
+    pub id: String,
+    pub user_id: String,
+    pub portfolio_id: String,
+    pub exchange: Exchange,
+    pub label: Option<String>,
+    /// The API key with all but its last four characters masked.
+    pub api_key_hint: String,
+    #[serde(skip)]
+    pub api_key: String,
+    #[serde(skip)]
+    pub api_secret: String,
+    #[serde(skip)]
+    pub cursor: Option<String>,
+    pub last_synced_at: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub const CONNECTION_COLS: &str = "id, user_id, portfolio_id, exchange, label, api_key, api_secret, cursor, last_synced_at, last_error, created_at, updated_at";
+
+pub fn row_to_connection(row: &rusqlite::Row) -> rusqlite::Result<ExchangeConnection> {
+    let api_key: String = row.get(5)?;
+    Ok(ExchangeConnection {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        portfolio_id: row.get(2)?,
+        exchange: match row.get::<_, String>(3)?.as_str() {
+            "kraken" => Exchange::Kraken,
+            _ => Exchange::Coinbase,
+        },
+        label: row.get(4)?,
+        api_key_hint: mask_key(&api_key),
+        api_key,
+        api_secret: row.get(6)?,
+        cursor: row.get(7)?,
+        last_synced_at: row.get(8)?,
+        last_error: row.get(9)?,
+        created_at: row.get(10)?,
+        updated_at: row.get(11)?,
+    })
+}
+
+fn mask_key(key: &str) -> String {
+    let tail: String = key.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("****{tail}")
+}
+
+/// Check the credentials with a harmless read and return the account's BTC balance.
+pub async fn test(config: &Config, exchange: Exchange, api_key: &str, api_secret: &str) -> AppResult<i64> {
+    match exchange {
+        Exchange::Kraken => kraken_btc_balance(config, api_key, api_secret).await,
+        Exchange::Coinbase => {
+            let account = coinbase_btc_account(config, api_key, api_secret).await?;
+            Ok(account.map(|a| btc_to_sat(&a.balance.amount)).unwrap_or(0))
+        }
+    }
+}
+
+/// Pull trades made since the connection's cursor and import them into its portfolio.
+/// Failures are recorded in `last_error` as well as returned.
+pub async fn sync_connection(pool: &DbPool, config: &Config, connection: &ExchangeConnection) -> AppResult<ImportResult> {
+    let fetched = match connection.exchange {
+        Exchange::Kraken => fetch_kraken_trades(config, connection).await,
+        Exchange::Coinbase => fetch_coinbase_trades(config, connection).await,
+    };
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut conn = pool.get()?;
+    let (txs, cursor) = match fetched {
+        Ok(f) => f,
+        Err(e) => {
+            conn.execute(
+                "UPDATE exchange_connections SET last_error = ?1, updated_at = ?2 WHERE id = ?3",
+                rusqlite::params![e.to_string(), now, connection.id],
+            )?;
+            return Err(e);
+        }
+    };
+
+    let db_tx = conn.transaction()?;
+    let result = import::insert_transactions(
+        &db_tx,
+        &connection.user_id,
+        None,
+        &connection.portfolio_id,
+        None,
+        connection.exchange.source(),
+        &txs,
+    )?;
+    db_tx.execute(
+        "UPDATE exchange_connections
+         SET cursor = COALESCE(?1, cursor), last_synced_at = ?2, last_error = NULL, updated_at = ?2
+         WHERE id = ?3",
+        rusqlite::params![cursor, now, connection.id],
+    )?;
+    db_tx.commit()?;
+
+    Ok(result)
+}
+
+/// Background task that imports new trades for every exchange connection.
+pub async fn run_exchange_sync(pool: DbPool, config: Config) {
+    let interval = config.intervals.exchange_sync;
+    tracing::info!("Exchange importer started (interval: {}s)", interval.as_secs());
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let connections = match load_all(&pool) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!("Exchange importer: failed to load connections: {e}");
+                continue;
+            }
+        };
+
+        for connection in connections {
+            match sync_connection(&pool, &config, &connection).await {
+                Ok(result) if result.imported > 0 => {
+                    tracing::info!("Exchange connection {}: imported {} trade(s)", connection.id, result.imported);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Exchange connection {} sync failed: {e}", connection.id),
+            }
+        }
+    }
+}
+
+fn load_all(pool: &DbPool) -> AppResult<Vec<ExchangeConnection>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(&format!("SELECT {CONNECTION_COLS} FROM exchange_connections ORDER BY created_at"))?;
+    let rows = stmt.query_map([], row_to_connection)?;
+    let connections: Result<Vec<_>, _> = rows.collect();
+    Ok(connections?)
+}
+
+fn btc_to_sat(amount: &str) -> i64 {
+    amount.trim().parse::<f64>().map(|btc| (btc * 1e8).round() as i64).unwrap_or(0)
+}
+
+fn rejected(exchange: Exchange, detail: impl std::fmt::Display) -> AppError {
+    AppError::BadRequest(format!("{} rejected the request: {detail}", exchange.name()))
+}
+
+// ── Kraken ──
+
+#[derive(Deserialize)]
+struct KrakenResponse<T> {
+    #[serde(default)]
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct KrakenTradesHistory {
+    trades: HashMap<String, KrakenTrade>,
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct KrakenTrade {
+    pair: String,
+    time: f64,
+    #[serde(rename = "type")]
+    kind: String,
+    price: String,
+    cost: String,
+    vol: String,
+}
+
+/// Signed call to a Kraken private endpoint:
+/// `API-Sign = base64(HMAC-SHA512(path + SHA256(nonce + body), base64decode(secret)))`.
+async fn kraken_private<T: DeserializeOwned>(
+    config: &Config,
+    api_key: &str,
+    api_secret: &str,
+    path: &str,
+    params: &[(&str, String)],
+) -> AppResult<T> {
+    let secret = base64::engine::general_purpose::STANDARD
+        .decode(api_secret.trim())
+        .map_err(|_| AppError::BadRequest("Kraken API secret is not valid base64".into()))?;
+    let nonce = chrono::Utc::now().timestamp_millis().to_string();
+    // Parameter values are numbers and trade ids, so need no escaping
+    let body = std::iter::once(("nonce", nonce.as_str()))
+        .chain(params.iter().map(|(k, v)| (*k, v.as_str())))
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut mac = Hmac::<Sha512>::new_from_slice(&secret).expect("HMAC accepts any key length");
+    mac.update(path.as_bytes());
+    mac.update(&Sha256::digest(format!("{nonce}{body}").as_bytes()));
+    let signature = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+
+    let url = format!("{}{path}", config.kraken_api_url);
+    let resp = http::client()?
+        .post(&url)
+        .header("API-Key", api_key.trim())
+        .header("API-Sign", signature)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("kraken", "Kraken request failed", e))?;
+    if !resp.status().is_success() {
+        return Err(AppError::Internal(format!("Kraken returned {} for {path}", resp.status())));
+    }
+
+    let parsed: KrakenResponse<T> = resp
+        .json()
+        .await
+        .map_err(|e| http::upstream_error("kraken", "Kraken response parse failed", e))?;
+    if !parsed.error.is_empty() {
+        return Err(rejected(Exchange::Kraken, parsed.error.join(", ")));
+    }
+    parsed
+        .result
+        .ok_or_else(|| AppError::Internal(format!("Kraken returned no result for {path}")))
+}
+
+async fn kraken_btc_balance(config: &Config, api_key: &str, api_secret: &str) -> AppResult<i64> {
+    let balances: HashMap<String, String> =
+        kraken_private(config, api_key, api_secret, "/0/private/Balance", &[]).await?;
+    Ok(balances
+        .iter()
+        .filter(|(asset, _)| is_btc(asset))
+        .map(|(_, amount)| btc_to_sat(amount))
+        .sum())
+}
+
+/// Trades after the cursor (a trade time, exclusive) on BTC/fiat pairs. Kraken
+/// returns newest first, 50 at a time; the new cursor is the latest trade's time.
+async fn fetch_kraken_trades(
+    config: &Config,
+    connection: &ExchangeConnection,
+) -> AppResult<(Vec<ImportedTransaction>, Option<String>)> {
+    let mut trades: Vec<KrakenTrade> = Vec::new();
+    loop {
+        let mut params = vec![("ofs", trades.len().to_string())];
+        if let Some(ref start) = connection.cursor {
+            params.push(("start", start.clone()));
+        }
+        let page: KrakenTradesHistory = kraken_private(
+            config,
+            &connection.api_key,
+            &connection.api_secret,
+            "/0/private/TradesHistory",
+            &params,
+        )
+        .await?;
+
+        let received = page.trades.len();
+        trades.extend(page.trades.into_values());
+        if received == 0 || trades.len() >= page.count {
+            break;
+        }
+        tokio::time::sleep(KRAKEN_PAGE_DELAY).await;
+    }
+
+    let cursor = trades
+        .iter()
+        .map(|t| t.time)
+        .max_by(f64::total_cmp)
+        .map(|t| t.to_string());
+
+    let mut txs: Vec<ImportedTransaction> = trades.iter().filter_map(kraken_transaction).collect();
+    txs.sort_by(|a, b| a.transacted_at.cmp(&b.transacted_at));
+    Ok((txs, cursor))
+}
+
+/// A BTC/fiat trade as a buy or sell; None for other pairs.
+fn kraken_transaction(trade: &KrakenTrade) -> Option<ImportedTransaction> {
+    let quote = trade
+        .pair
+        .strip_prefix("XXBT")
+        .or_else(|| trade.pair.strip_prefix("XBT"))?;
+    let currency = kraken_fiat(quote)?;
+    let tx_type = match trade.kind.as_str() {
+        "buy" => "buy",
+        "sell" => "sell",
+        _ => return None,
+    };
+    let amount_sat = import::parse_btc_amount(&trade.vol)?;
+    let transacted_at = chrono::DateTime::from_timestamp_millis((trade.time * 1000.0).round() as i64)?
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+
+    Some(exchange_import::transaction(
+        0,
+        tx_type,
+        amount_sat,
+        None,
+        trade.price.parse().ok(),
+        trade.cost.parse().ok(),
+        &currency,
+        transacted_at,
+        None,
+    ))
+}
+
+// ── Coinbase ──
+
+#[derive(Deserialize)]
+struct CoinbasePage<T> {
+    data: Vec<T>,
+    pagination: Option<CoinbasePagination>,
+}
+
+#[derive(Deserialize)]
+struct CoinbasePagination {
+    next_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseMoney {
+    amount: String,
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseAccount {
+    id: String,
+    /// A currency code in older API versions, an object with `code` in newer ones.
+    currency: serde_json::Value,
+    balance: CoinbaseMoney,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseTransaction {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    status: String,
+    amount: CoinbaseMoney,
+    native_amount: Option<CoinbaseMoney>,
+    created_at: String,
+}
+
+/// Signed GET against the Coinbase v2 API:
+/// `CB-ACCESS-SIGN = hex(HMAC-SHA256(timestamp + method + path, secret))`.
+async fn coinbase_get<T: DeserializeOwned>(
+    config: &Config,
+    api_key: &str,
+    api_secret: &str,
+    path: &str,
+) -> AppResult<CoinbasePage<T>> {
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+    let mut mac = Hmac::<Sha256>::new_from_slice(api_secret.trim().as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{timestamp}GET{path}").as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    let url = format!("{}{path}", config.coinbase_api_url);
+    let resp = http::client()?
+        .get(&url)
+        .header("CB-ACCESS-KEY", api_key.trim())
+        .header("CB-ACCESS-SIGN", signature)
+        .header("CB-ACCESS-TIMESTAMP", timestamp)
+        .header("CB-VERSION", COINBASE_API_VERSION)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("coinbase", "Coinbase request failed", e))?;
+
+    let status = resp.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(rejected(Exchange::Coinbase, "invalid API key or missing read permission"));
+    }
+    if !status.is_success() {
+        return Err(AppError::Internal(format!("Coinbase returned {status} for {path}")));
+    }
+    resp.json()
+        .await
+        .map_err(|e| http::upstream_error("coinbase", "Coinbase response parse failed", e))
+}
+
+async fn coinbase_btc_account(config: &Config, api_key: &str, api_secret: &str) -> AppResult<Option<CoinbaseAccount>> {
+    let mut path = "/v2/accounts?limit=100".to_string();
+    loop {
+        let page: CoinbasePage<CoinbaseAccount> = coinbase_get(config, api_key, api_secret, &path).await?;
+        if let Some(account) = page.data.into_iter().find(|a| {
+            let code = a.currency.get("code").unwrap_or(&a.currency);
+            code.as_str() == Some("BTC")
+        }) {
+            return Ok(Some(account));
+        }
+        match page.pagination.and_then(|p| p.next_uri) {
+            Some(next) => path = next,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Completed trades on the BTC account after the cursor (a transaction id), oldest
+/// first. Stops at the first pending transaction so it is picked up once it settles.
+async fn fetch_coinbase_trades(
+    config: &Config,
+    connection: &ExchangeConnection,
+) -> AppResult<(Vec<ImportedTransaction>, Option<String>)> {
+    let (key, secret) = (connection.api_key.as_str(), connection.api_secret.as_str());
+    let Some(account) = coinbase_btc_account(config, key, secret).await? else {
+        return Ok((Vec::new(), None));
+    };
+
+    let mut path = format!("/v2/accounts/{}/transactions?order=asc&limit=100", account.id);
+    if let Some(ref after) = connection.cursor {
+        path.push_str(&format!("&starting_after={after}"));
+    }
+
+    let mut txs = Vec::new();
+    let mut cursor = None;
+    'pages: loop {
+        let page: CoinbasePage<CoinbaseTransaction> = coinbase_get(config, key, secret, &path).await?;
+        for tx in page.data {
+            if tx.status != "completed" {
+                if matches!(tx.status.as_str(), "pending" | "waiting_for_clearing" | "waiting_for_signature") {
+                    break 'pages;
+                }
+                // Failed, canceled or expired: never going to settle
+                cursor = Some(tx.id);
+                continue;
+            }
+            if let Some(t) = coinbase_transaction(&tx) {
+                txs.push(t);
+            }
+            cursor = Some(tx.id);
+        }
+        match page.pagination.and_then(|p| p.next_uri) {
+            Some(next) => path = next,
+            None => break,
+        }
+    }
+    Ok((txs, cursor))
+}
+
+/// A buy, sell or trade fill as a transaction; None for transfers and other activity,
+/// which wallet sync already covers.
+fn coinbase_transaction(tx: &CoinbaseTransaction) -> Option<ImportedTransaction> {
+    if !tx.amount.currency.eq_ignore_ascii_case("BTC") {
+        return None;
+    }
+    let amount_sat = import::parse_btc_amount(&tx.amount.amount)?;
+    let tx_type = match tx.kind.as_str() {
+        "buy" => "buy",
+        "sell" => "sell",
+        "trade" | "advanced_trade_fill" | "retail_simple_dca" if amount_sat >= 0 => "buy",
+        "trade" | "advanced_trade_fill" | "retail_simple_dca" => "sell",
+        _ => return None,
+    };
+    let transacted_at = import::parse_timestamp(&tx.created_at)?;
+    let (fiat_amount, currency) = match tx.native_amount {
+        Some(ref native) => (native.amount.parse().ok(), native.currency.as_str()),
+        None => (None, "usd"),
+    };
+
+    Some(exchange_import::transaction(
+        0,
+        tx_type,
+        amount_sat,
+        None,
+        None,
+        fiat_amount,
+        currency,
+        transacted_at,
+        None,
+    ))
+}
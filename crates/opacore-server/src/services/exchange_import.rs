@@ -0,0 +1,762 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+use crate::services::costbasis::TX_TYPES;
+use crate::services::import::{
+    normalize_header, parse_btc_amount, parse_timestamp, Columns, ImportRowError, ImportedTransaction,
+};
+
+/// Exchanges whose transaction reports can be imported.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeFormat {
+    Coinbase,
+    Kraken,
+    Strike,
+    River,
+    /// Our own column layout, for everything else. See [`parse_generic`].
+    Generic,
+    /// Columns named by a saved import template. See [`parse_mapped`].
+    Custom,
+}
+
+impl ExchangeFormat {
+    /// Value stored in `transactions.source` for imported rows.
+    pub fn source(self) -> &'static str {
+        match self {
+            ExchangeFormat::Coinbase => "coinbase",
+            ExchangeFormat::Kraken => "kraken",
+            ExchangeFormat::Strike => "strike",
+            ExchangeFormat::River => "river",
+            ExchangeFormat::Generic | ExchangeFormat::Custom => "csv",
+        }
+    }
+
+    /// Columns that identify the header row. Coinbase puts a few lines of prose
+    /// above it, so the header isn't always the first line.
+    fn header_columns(self) -> &'static [&'static [&'static str]] {
+        match self {
+            ExchangeFormat::Coinbase => &[&["timestamp"], &["transactiontype"], &["asset"]],
+            ExchangeFormat::Kraken => &[&["refid"], &["asset"], &["amount"]],
+            ExchangeFormat::Strike => &[&["transactiontype"], &["amountbtc"]],
+            ExchangeFormat::River => &[&["sentcurrency"], &["receivedcurrency"]],
+            ExchangeFormat::Generic => &[&["date", "timestamp", "time"]],
+            ExchangeFormat::Custom => &[],
+        }
+    }
+}
+
+/// Result of parsing an exchange report.
+#[derive(Debug)]
+pub struct ParsedExchangeImport {
+    pub transactions: Vec<ImportedTransaction>,
+    pub errors: Vec<ImportRowError>,
+    /// Rows for other assets or fiat-only movements, which have nothing to import.
+    pub ignored_rows: usize,
+}
+
+/// One CSV row with the line it started on.
+struct Row {
+    line: usize,
+    record: csv::StringRecord,
+}
+
+impl Row {
+    fn get(&self, col: Option<usize>) -> Option<&str> {
+        col.and_then(|c| self.record.get(c)).filter(|s| !s.is_empty())
+    }
+}
+
+/// Parse an exchange report. Rows that can't be read are reported in the returned
+/// errors rather than failing the whole file.
+///
+/// Custom imports need a template's mapping and go through [`parse_mapped`] instead.
+pub fn parse(format: ExchangeFormat, data: &str) -> AppResult<ParsedExchangeImport> {
+    let is_header = |fields: &[String]| {
+        format
+            .header_columns()
+            .iter()
+            .all(|names| names.iter().any(|name| fields.iter().any(|f| f == name)))
+    };
+    let (columns, rows, errors) = read_rows(format.source(), is_header, data)?;
+    let parsed = match format {
+        ExchangeFormat::Coinbase => parse_coinbase(&columns, &rows)?,
+        ExchangeFormat::Kraken => parse_kraken(&columns, &rows)?,
+        ExchangeFormat::Strike => parse_strike(&columns, &rows)?,
+        ExchangeFormat::River => parse_river(&columns, &rows)?,
+        ExchangeFormat::Generic => parse_generic(&columns, &rows)?,
+        ExchangeFormat::Custom => {
+            return Err(AppError::BadRequest("Custom imports require a template_id".into()));
+        }
+    };
+    Ok(with_read_errors(parsed, errors))
+}
+
+fn with_read_errors(mut parsed: ParsedExchangeImport, mut errors: Vec<ImportRowError>) -> ParsedExchangeImport {
+    errors.append(&mut parsed.errors);
+    errors.sort_by_key(|e| e.line);
+    parsed.errors = errors;
+    parsed
+}
+
+/// Find the header row and read every record after it. `is_header` is given each
+/// line's fields, normalized as in [`Columns`].
+fn read_rows(
+    name: &str,
+    is_header: impl Fn(&[String]) -> bool,
+    data: &str,
+) -> AppResult<(Columns, Vec<Row>, Vec<ImportRowError>)> {
+    let data = data.trim_start_matches('\u{feff}');
+    let is_header = |line: &str| {
+        let fields: Vec<String> = line.split(',').map(normalize_header).collect();
+        is_header(&fields)
+    };
+
+    let mut offset = 0;
+    let mut skipped_lines = 0;
+    for line in data.split_inclusive('\n') {
+        if is_header(line) {
+            break;
+        }
+        offset += line.len();
+        skipped_lines += 1;
+    }
+    if offset >= data.len() {
+        return Err(AppError::BadRequest(format!(
+            "Not a {name} export: header row not found"
+        )));
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(&data.as_bytes()[offset..]);
+    let headers = reader
+        .headers()
+        .map_err(|e| AppError::BadRequest(format!("Could not read CSV header: {e}")))?
+        .clone();
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        match record {
+            Ok(record) => {
+                let line = skipped_lines + record.position().map_or(0, |p| p.line() as usize);
+                if record.iter().all(|f| f.is_empty()) {
+                    continue;
+                }
+                rows.push(Row { line, record });
+            }
+            Err(e) => {
+                let line = skipped_lines + e.position().map_or(0, |p| p.line() as usize);
+                errors.push(ImportRowError { line, message: e.to_string() });
+            }
+        }
+    }
+
+    Ok((Columns::new(&headers), rows, errors))
+}
+
+pub fn is_btc(asset: &str) -> bool {
+    matches!(asset.to_ascii_uppercase().as_str(), "BTC" | "XBT" | "XXBT")
+}
+
+/// Parse a fiat amount such as "$1,234.56", "-$12.00" or "1234.56 USD".
+fn parse_fiat(s: &str) -> Option<f64> {
+    let cleaned: String = s.chars().filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-').collect();
+    cleaned.parse().ok()
+}
+
+/// Per-BTC USD price for a trade, from the exchange's quoted price or, failing that,
+/// the fiat total. Only USD prices are recorded; other currencies keep `fiat_amount`.
+fn price_usd(currency: &str, price: Option<f64>, fiat_amount: Option<f64>, amount_sat: i64) -> Option<f64> {
+    if !currency.eq_ignore_ascii_case("usd") {
+        return None;
+    }
+    price.or_else(|| match fiat_amount {
+        Some(fiat) if amount_sat > 0 => Some(fiat / (amount_sat as f64 / 1e8)),
+        _ => None,
+    })
+}
+
+/// Build a transaction from a signed BTC amount and the fiat side of the row.
+#[allow(clippy::too_many_arguments)]
+pub fn transaction(
+    line: usize,
+    tx_type: &'static str,
+    amount_sat: i64,
+    fee_sat: Option<i64>,
+    price: Option<f64>,
+    fiat_amount: Option<f64>,
+    fiat_currency: &str,
+    transacted_at: String,
+    note: Option<&str>,
+) -> ImportedTransaction {
+    let fiat_currency = fiat_currency.to_ascii_lowercase();
+    let amount_sat = amount_sat.abs();
+    let fiat_amount = fiat_amount.map(f64::abs);
+    ImportedTransaction {
+        line,
+        txid: None,
+        tx_type,
+        amount_sat,
+        fee_sat: fee_sat.map(i64::abs).filter(|f| *f > 0),
+        price_usd: price_usd(&fiat_currency, price.map(f64::abs), fiat_amount, amount_sat),
+        fiat_amount,
+        fiat_currency,
+        transacted_at,
+        labels: note.map(|n| vec![n.to_string()]).unwrap_or_default(),
+    }
+}
+
+fn missing_columns(format: ExchangeFormat) -> AppError {
+    AppError::BadRequest(format!("Not a {} export: missing required columns", format.source()))
+}
+
+// ── Coinbase ──
+
+/// Coinbase "Transaction history" report:
+/// Timestamp,Transaction Type,Asset,Quantity Transacted,Spot Price Currency,
+/// Spot Price at Transaction,Subtotal,Total (inclusive of fees and/or spread),...
+fn parse_coinbase(columns: &Columns, rows: &[Row]) -> AppResult<ParsedExchangeImport> {
+    let (Some(date_col), Some(type_col), Some(asset_col), Some(qty_col)) = (
+        columns.find(&["timestamp"]),
+        columns.find(&["transactiontype"]),
+        columns.find(&["asset"]),
+        columns.find(&["quantitytransacted"]),
+    ) else {
+        return Err(missing_columns(ExchangeFormat::Coinbase));
+    };
+    let currency_col = columns.find(&["spotpricecurrency", "pricecurrency"]);
+    let price_col = columns.find(&["spotpriceattransaction", "priceattransaction"]);
+    let subtotal_col = columns.find(&["subtotal"]);
+    let total_col = columns.find(&["totalinclusiveoffeesandorspread", "total"]);
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in rows {
+        if !row.get(Some(asset_col)).is_some_and(is_btc) {
+            parsed.ignored_rows += 1;
+            continue;
+        }
+        let kind = row.get(Some(type_col)).unwrap_or_default().to_ascii_lowercase();
+        let tx_type = match kind.as_str() {
+            "buy" | "advanced trade buy" => "buy",
+            // Converting BTC into another asset disposes of it
+            "sell" | "advanced trade sell" | "convert" => "sell",
+            "send" | "withdrawal" => "send",
+            "receive" | "deposit" => "receive",
+            k if k.contains("reward") || k.contains("income") || k.contains("earn") => "receive",
+            _ => {
+                parsed.errors.push(ImportRowError { line: row.line, message: format!("Unsupported transaction type '{kind}'") });
+                continue;
+            }
+        };
+        let Some(amount_sat) = row.get(Some(qty_col)).and_then(parse_btc_amount) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing quantity".into() });
+            continue;
+        };
+        let Some(transacted_at) = row.get(Some(date_col)).and_then(parse_timestamp) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing timestamp".into() });
+            continue;
+        };
+
+        let currency = row.get(currency_col).unwrap_or("usd");
+        let fiat_amount = row.get(subtotal_col).or(row.get(total_col)).and_then(parse_fiat);
+        parsed.transactions.push(transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            None,
+            row.get(price_col).and_then(parse_fiat),
+            fiat_amount,
+            currency,
+            transacted_at,
+            None,
+        ));
+    }
+    Ok(parsed)
+}
+
+// ── Kraken ──
+
+/// Kraken ledger export: "txid","refid","time","type","subtype","aclass","asset","amount","fee","balance".
+/// A trade is two ledger rows sharing a refid; the fiat row supplies the price.
+fn parse_kraken(columns: &Columns, rows: &[Row]) -> AppResult<ParsedExchangeImport> {
+    let (Some(refid_col), Some(date_col), Some(type_col), Some(asset_col), Some(amount_col)) = (
+        columns.find(&["refid"]),
+        columns.find(&["time"]),
+        columns.find(&["type"]),
+        columns.find(&["asset"]),
+        columns.find(&["amount"]),
+    ) else {
+        return Err(missing_columns(ExchangeFormat::Kraken));
+    };
+    let txid_col = columns.find(&["txid"]);
+    let fee_col = columns.find(&["fee"]);
+
+    // Fiat legs of trades, by refid
+    let fiat_legs: HashMap<&str, (f64, String)> = rows
+        .iter()
+        .filter_map(|row| {
+            let currency = kraken_fiat(row.get(Some(asset_col))?)?;
+            let amount = row.get(Some(amount_col)).and_then(parse_fiat)?;
+            Some((row.get(Some(refid_col))?, (amount, currency)))
+        })
+        .collect();
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in rows {
+        // Deposits and withdrawals appear twice; the pending copy has no ledger id
+        if !row.get(Some(asset_col)).is_some_and(is_btc) || (txid_col.is_some() && row.get(txid_col).is_none()) {
+            parsed.ignored_rows += 1;
+            continue;
+        }
+        let Some(amount_sat) = row.get(Some(amount_col)).and_then(parse_btc_amount) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing amount".into() });
+            continue;
+        };
+        let Some(transacted_at) = row.get(Some(date_col)).and_then(parse_timestamp) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing time".into() });
+            continue;
+        };
+
+        let kind = row.get(Some(type_col)).unwrap_or_default().to_ascii_lowercase();
+        let tx_type = match kind.as_str() {
+            "trade" | "spend" | "receive" if amount_sat >= 0 => "buy",
+            "trade" | "spend" | "receive" => "sell",
+            "deposit" => "receive",
+            "withdrawal" => "send",
+            "staking" | "earn" | "dividend" => "receive",
+            // Moves between spot and staking wallets inside Kraken
+            "transfer" | "margin" | "rollover" | "settled" | "adjustment" => {
+                parsed.ignored_rows += 1;
+                continue;
+            }
+            _ => {
+                parsed.errors.push(ImportRowError { line: row.line, message: format!("Unsupported ledger type '{kind}'") });
+                continue;
+            }
+        };
+
+        let fiat = matches!(tx_type, "buy" | "sell")
+            .then(|| row.get(Some(refid_col)).and_then(|r| fiat_legs.get(r)))
+            .flatten();
+        let (fiat_amount, currency) = match fiat {
+            Some((amount, currency)) => (Some(*amount), currency.as_str()),
+            None => (None, "usd"),
+        };
+        parsed.transactions.push(transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            row.get(fee_col).and_then(parse_btc_amount),
+            None,
+            fiat_amount,
+            currency,
+            transacted_at,
+            None,
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Currency code for a Kraken fiat asset ("ZUSD" -> "usd"), or None for crypto.
+pub fn kraken_fiat(asset: &str) -> Option<String> {
+    let code = match asset.to_ascii_uppercase() {
+        a if a.len() == 4 && a.starts_with('Z') => a[1..].to_string(),
+        a => a,
+    };
+    matches!(code.as_str(), "USD" | "EUR" | "GBP" | "CAD" | "AUD" | "CHF" | "JPY").then(|| code.to_ascii_lowercase())
+}
+
+// ── Strike ──
+
+/// Strike account statement:
+/// Transaction ID,Time (UTC),Status,Transaction Type,Amount USD,Fee USD,Amount BTC,Fee BTC,BTC Price,...
+fn parse_strike(columns: &Columns, rows: &[Row]) -> AppResult<ParsedExchangeImport> {
+    let (Some(date_col), Some(type_col), Some(btc_col)) = (
+        columns.find(&["timeutc", "datetimeutc", "completeddateutc", "time"]),
+        columns.find(&["transactiontype"]),
+        columns.find(&["amountbtc"]),
+    ) else {
+        return Err(missing_columns(ExchangeFormat::Strike));
+    };
+    let status_col = columns.find(&["status", "state"]);
+    let usd_col = columns.find(&["amountusd"]);
+    let fee_col = columns.find(&["feebtc"]);
+    let price_col = columns.find(&["btcprice"]);
+    let note_col = columns.find(&["description", "note"]);
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in rows {
+        let completed = row.get(status_col).is_none_or(|s| s.eq_ignore_ascii_case("completed"));
+        let btc = row.get(Some(btc_col));
+        // Bank deposits, card spends and the like have no BTC leg
+        if !completed || btc.is_none_or(|s| parse_fiat(s) == Some(0.0)) {
+            parsed.ignored_rows += 1;
+            continue;
+        }
+        let Some(amount_sat) = btc.and_then(parse_btc_amount) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid BTC amount".into() });
+            continue;
+        };
+        let Some(transacted_at) = row.get(Some(date_col)).and_then(parse_timestamp) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing time".into() });
+            continue;
+        };
+
+        let kind = row.get(Some(type_col)).unwrap_or_default().to_ascii_lowercase();
+        let trade = ["trade", "purchase", "buy", "sell", "exchange"].iter().any(|k| kind.contains(k));
+        let tx_type = match (trade, amount_sat >= 0) {
+            (true, true) => "buy",
+            (true, false) => "sell",
+            (false, true) => "receive",
+            (false, false) => "send",
+        };
+        parsed.transactions.push(transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            row.get(fee_col).and_then(parse_btc_amount),
+            row.get(price_col).and_then(parse_fiat),
+            row.get(usd_col).and_then(parse_fiat).filter(|_| trade),
+            "usd",
+            transacted_at,
+            row.get(note_col),
+        ));
+    }
+    Ok(parsed)
+}
+
+// ── River ──
+
+/// River account activity:
+/// Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Tag
+fn parse_river(columns: &Columns, rows: &[Row]) -> AppResult<ParsedExchangeImport> {
+    let (Some(date_col), Some(sent_col), Some(sent_cur_col), Some(recv_col), Some(recv_cur_col)) = (
+        columns.find(&["date"]),
+        columns.find(&["sentamount"]),
+        columns.find(&["sentcurrency"]),
+        columns.find(&["receivedamount"]),
+        columns.find(&["receivedcurrency"]),
+    ) else {
+        return Err(missing_columns(ExchangeFormat::River));
+    };
+    let fee_col = columns.find(&["feeamount"]);
+    let fee_cur_col = columns.find(&["feecurrency"]);
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in rows {
+        let sent_cur = row.get(Some(sent_cur_col));
+        let recv_cur = row.get(Some(recv_cur_col));
+        let sent_btc = sent_cur.is_some_and(is_btc);
+        let recv_btc = recv_cur.is_some_and(is_btc);
+        if !sent_btc && !recv_btc {
+            parsed.ignored_rows += 1;
+            continue;
+        }
+
+        let (btc_col, fiat_col, fiat_cur) = if recv_btc {
+            (recv_col, sent_col, sent_cur)
+        } else {
+            (sent_col, recv_col, recv_cur)
+        };
+        let Some(amount_sat) = row.get(Some(btc_col)).and_then(parse_btc_amount) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing BTC amount".into() });
+            continue;
+        };
+        let Some(transacted_at) = row.get(Some(date_col)).and_then(parse_timestamp) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing date".into() });
+            continue;
+        };
+
+        let fiat = fiat_cur
+            .filter(|c| !is_btc(c))
+            .and_then(|c| Some((row.get(Some(fiat_col)).and_then(parse_fiat)?, c)));
+        let tx_type = match (recv_btc, fiat.is_some()) {
+            (true, true) => "buy",
+            (false, true) => "sell",
+            (true, false) => "receive",
+            (false, false) => "send",
+        };
+        let fee_sat = row
+            .get(fee_cur_col)
+            .is_some_and(is_btc)
+            .then(|| row.get(fee_col).and_then(parse_btc_amount))
+            .flatten();
+        let (fiat_amount, currency) = match fiat {
+            Some((amount, currency)) => (Some(amount), currency),
+            None => (None, "usd"),
+        };
+        parsed.transactions.push(transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            fee_sat,
+            None,
+            fiat_amount,
+            currency,
+            transacted_at,
+            None,
+        ));
+    }
+    Ok(parsed)
+}
+
+// ── Generic ──
+
+/// Our generic layout, matched case-insensitively:
+/// `date`, `type` (buy/sell/receive/send/transfer), `amount_btc` or `amount_sat`,
+/// and optionally `fee_btc`/`fee_sat`, `price_usd`, `fiat_amount`, `fiat_currency`,
+/// `txid` and `label`. Without a type, positive amounts are receives and negative sends.
+fn parse_generic(columns: &Columns, rows: &[Row]) -> AppResult<ParsedExchangeImport> {
+    let date_col = columns.find(&["date", "timestamp", "time", "transactedat"]);
+    let btc_col = columns.find(&["amountbtc", "amount", "quantity"]);
+    let sat_col = columns.find(&["amountsat", "amountsats", "sats"]);
+    let (Some(date_col), true) = (date_col, btc_col.is_some() || sat_col.is_some()) else {
+        return Err(missing_columns(ExchangeFormat::Generic));
+    };
+    let type_col = columns.find(&["type", "txtype"]);
+    let fee_btc_col = columns.find(&["feebtc", "fee"]);
+    let fee_sat_col = columns.find(&["feesat", "feesats"]);
+    let price_col = columns.find(&["priceusd", "price"]);
+    let fiat_col = columns.find(&["fiatamount", "total", "value"]);
+    let currency_col = columns.find(&["fiatcurrency", "currency"]);
+    let txid_col = columns.find(&["txid", "transactionid", "hash"]);
+    let label_col = columns.find(&["label", "labels", "notes", "note"]);
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in rows {
+        let amount = match sat_col.and_then(|c| row.get(Some(c))) {
+            Some(s) => s.parse::<i64>().ok(),
+            None => row.get(btc_col).and_then(parse_btc_amount),
+        };
+        let Some(amount_sat) = amount else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing amount".into() });
+            continue;
+        };
+        let Some(transacted_at) = row.get(Some(date_col)).and_then(parse_timestamp) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing date".into() });
+            continue;
+        };
+        let tx_type = match row.get(type_col).map(str::to_ascii_lowercase).as_deref() {
+            None if amount_sat >= 0 => "receive",
+            None => "send",
+            Some("buy") => "buy",
+            Some("sell") => "sell",
+            Some("receive") => "receive",
+            Some("send") => "send",
+            Some("transfer") => "transfer",
+            Some(other) => {
+                parsed.errors.push(ImportRowError { line: row.line, message: format!("Unsupported type '{other}'") });
+                continue;
+            }
+        };
+        let fee_sat = match fee_sat_col.and_then(|c| row.get(Some(c))) {
+            Some(s) => s.parse::<i64>().ok(),
+            None => row.get(fee_btc_col).and_then(parse_btc_amount),
+        };
+
+        let mut tx = transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            fee_sat,
+            row.get(price_col).and_then(parse_fiat),
+            row.get(fiat_col).and_then(parse_fiat),
+            row.get(currency_col).unwrap_or("usd"),
+            transacted_at,
+            row.get(label_col),
+        );
+        tx.txid = row.get(txid_col).map(String::from);
+        parsed.transactions.push(tx);
+    }
+    Ok(parsed)
+}
+
+// ── Custom ──
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountUnit {
+    #[default]
+    Btc,
+    Sat,
+}
+
+/// Which columns of a user's spreadsheet hold what, saved as an import template.
+/// Column names are matched ignoring case, spaces and punctuation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub date: String,
+    /// chrono format for the date column, when it isn't one we recognise.
+    #[serde(default)]
+    pub date_format: Option<String>,
+    pub amount: String,
+    #[serde(default)]
+    pub amount_unit: AmountUnit,
+    /// Without a type column, positive amounts are receives and negative sends.
+    #[serde(default, rename = "type")]
+    pub tx_type: Option<String>,
+    /// The export's own type names mapped to ours, e.g. `{"Purchase": "buy"}`.
+    #[serde(default)]
+    pub type_values: HashMap<String, String>,
+    #[serde(default)]
+    pub price: Option<String>,
+    #[serde(default)]
+    pub fiat_amount: Option<String>,
+    #[serde(default)]
+    pub fee: Option<String>,
+    #[serde(default)]
+    pub fee_unit: AmountUnit,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub txid: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl ColumnMapping {
+    pub fn validate(&self) -> AppResult<()> {
+        if self.date.trim().is_empty() || self.amount.trim().is_empty() {
+            return Err(AppError::BadRequest("Mapping needs date and amount columns".into()));
+        }
+        if let Some(bad) = self.type_values.values().find(|t| !TX_TYPES.contains(&t.as_str())) {
+            return Err(AppError::BadRequest(format!(
+                "Invalid mapped type '{bad}'. Must be one of: {}",
+                TX_TYPES.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    fn tx_type(&self, value: &str) -> Option<&'static str> {
+        let mapped = self
+            .type_values
+            .iter()
+            .find(|(from, _)| from.eq_ignore_ascii_case(value))
+            .map_or(value, |(_, to)| to.as_str());
+        TX_TYPES.into_iter().find(|t| t.eq_ignore_ascii_case(mapped))
+    }
+}
+
+fn parse_amount(s: &str, unit: AmountUnit) -> Option<i64> {
+    match unit {
+        AmountUnit::Btc => parse_btc_amount(s),
+        AmountUnit::Sat => s.replace([',', ' '], "").parse().ok(),
+    }
+}
+
+/// Parse a CSV laid out as described by a template's mapping.
+pub fn parse_mapped(mapping: &ColumnMapping, data: &str) -> AppResult<ParsedExchangeImport> {
+    let date_name = normalize_header(&mapping.date);
+    let amount_name = normalize_header(&mapping.amount);
+    let is_header = |fields: &[String]| fields.contains(&date_name) && fields.contains(&amount_name);
+    let (columns, rows, errors) = read_rows("template", is_header, data)?;
+
+    let col = |name: &Option<String>| name.as_deref().and_then(|n| columns.find(&[normalize_header(n).as_str()]));
+    let (Some(date_col), Some(amount_col)) = (columns.find(&[&date_name]), columns.find(&[&amount_name])) else {
+        return Err(AppError::BadRequest("Mapped date or amount column not found".into()));
+    };
+    let type_col = col(&mapping.tx_type);
+    let price_col = col(&mapping.price);
+    let fiat_col = col(&mapping.fiat_amount);
+    let fee_col = col(&mapping.fee);
+    let currency_col = col(&mapping.currency);
+    let txid_col = col(&mapping.txid);
+    let label_col = col(&mapping.label);
+
+    let mut parsed = ParsedExchangeImport { transactions: Vec::new(), errors: Vec::new(), ignored_rows: 0 };
+    for row in &rows {
+        let Some(amount_sat) = row.get(Some(amount_col)).and_then(|s| parse_amount(s, mapping.amount_unit)) else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing amount".into() });
+            continue;
+        };
+        let date = row.get(Some(date_col));
+        let transacted_at = match &mapping.date_format {
+            Some(fmt) => date.and_then(|d| parse_with_format(d, fmt)),
+            None => date.and_then(parse_timestamp),
+        };
+        let Some(transacted_at) = transacted_at else {
+            parsed.errors.push(ImportRowError { line: row.line, message: "Invalid or missing date".into() });
+            continue;
+        };
+        let tx_type = match row.get(type_col) {
+            None if amount_sat >= 0 => "receive",
+            None => "send",
+            Some(value) => match mapping.tx_type(value) {
+                Some(t) => t,
+                None => {
+                    parsed.errors.push(ImportRowError { line: row.line, message: format!("Unmapped type '{value}'") });
+                    continue;
+                }
+            },
+        };
+
+        let mut tx = transaction(
+            row.line,
+            tx_type,
+            amount_sat,
+            row.get(fee_col).and_then(|s| parse_amount(s, mapping.fee_unit)),
+            row.get(price_col).and_then(parse_fiat),
+            row.get(fiat_col).and_then(parse_fiat),
+            row.get(currency_col).unwrap_or("usd"),
+            transacted_at,
+            row.get(label_col),
+        );
+        tx.txid = row.get(txid_col).map(String::from);
+        parsed.transactions.push(tx);
+    }
+    Ok(with_read_errors(parsed, errors))
+}
+
+/// Parse a date with a template's explicit format, with or without a time part.
+fn parse_with_format(s: &str, fmt: &str) -> Option<String> {
+    let parsed = NaiveDateTime::parse_from_str(s, fmt)
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(s, fmt).ok().and_then(|d| d.and_hms_opt(0, 0, 0)))?;
+    Some(parsed.and_utc().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+}
+
+/// A user's saved column mapping.
+#[derive(Debug, Serialize)]
+pub struct ImportTemplate {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub mapping: ColumnMapping,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub const TEMPLATE_COLS: &str = "id, user_id, name, mapping, created_at, updated_at";
+
+pub fn row_to_template(row: &rusqlite::Row) -> rusqlite::Result<ImportTemplate> {
+    let mapping: String = row.get(3)?;
+    Ok(ImportTemplate {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        mapping: serde_json::from_str(&mapping).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+    })
+}
+
+pub fn load_template(conn: &rusqlite::Connection, user_id: &str, template_id: &str) -> AppResult<ImportTemplate> {
+    conn.query_row(
+        &format!("SELECT {TEMPLATE_COLS} FROM import_templates WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![template_id, user_id],
+        row_to_template,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Import template not found".into()),
+        e => AppError::Database(e),
+    })
+}
@@ -0,0 +1,125 @@
+use std::io::{self, Write};
+
+use axum::body::{Body, Bytes};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::error::{AppError, AppResult};
+
+/// Bytes buffered before a chunk is handed to the response body.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks in flight between the producer and the client. Once full the producer
+/// blocks, so memory stays bounded no matter how slowly the client reads.
+const CHUNKS_IN_FLIGHT: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// `io::Write` end of a streamed response body. Writes fail with `BrokenPipe` once the
+/// client has gone away, which stops the producer.
+pub struct ChunkWriter {
+    buf: Vec<u8>,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE)));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "export client disconnected"))
+    }
+}
+
+/// Stream a response body produced by `produce` on a blocking thread.
+///
+/// The status and headers are already sent by the time `produce` runs, so a failure
+/// part-way through can only abort the body; the client sees a truncated download.
+pub fn stream_body<F>(produce: F) -> Body
+where
+    F: FnOnce(&mut ChunkWriter) -> AppResult<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = ChunkWriter {
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            tx: tx.clone(),
+        };
+        let result = produce(&mut writer).and_then(|()| writer.flush().map_err(write_failed));
+        if let Err(e) = result {
+            if tx.is_closed() {
+                tracing::debug!("Export client disconnected");
+            } else {
+                tracing::warn!("Export aborted: {e}");
+                let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+            }
+        }
+    });
+
+    Body::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
+
+/// Map an encoder or `ChunkWriter` error into an `AppError`.
+pub fn write_failed(e: impl std::fmt::Display) -> AppError {
+    AppError::Internal(format!("Export write failed: {e}"))
+}
+
+/// Writes rows as a JSON array one element at a time.
+pub struct JsonArrayWriter<'a, W: Write> {
+    out: &'a mut W,
+    first: bool,
+}
+
+impl<'a, W: Write> JsonArrayWriter<'a, W> {
+    pub fn new(out: &'a mut W) -> io::Result<Self> {
+        out.write_all(b"[")?;
+        Ok(Self { out, first: true })
+    }
+
+    pub fn write<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        if !self.first {
+            self.out.write_all(b",")?;
+        }
+        self.first = false;
+        serde_json::to_writer(&mut *self.out, value).map_err(io::Error::from)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.out.write_all(b"]")
+    }
+}
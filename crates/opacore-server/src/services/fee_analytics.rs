@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::prices;
+use crate::services::transaction_splits::COUNTED;
+
+#[derive(Debug, Serialize)]
+pub struct FeeAnalytics {
+    pub currency: String,
+    pub total: FeeTotal,
+    pub wallets: Vec<WalletFees>,
+    pub months: Vec<MonthFees>,
+}
+
+/// Miner fees on transactions in the period, and their value at the time.
+#[derive(Debug, Default, Serialize)]
+pub struct FeeTotal {
+    pub tx_count: i64,
+    pub fee_sat: i64,
+    pub fee_value: f64,
+    /// Transactions with no price for their day, left out of `fee_value`.
+    pub unpriced: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletFees {
+    pub wallet_id: String,
+    pub label: String,
+    #[serde(flatten)]
+    pub fees: FeeTotal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MonthFees {
+    /// `YYYY-MM`.
+    pub month: String,
+    #[serde(flatten)]
+    pub fees: FeeTotal,
+}
+
+impl FeeTotal {
+    fn add(&mut self, fee_sat: i64, value: Option<f64>) {
+        self.tx_count += 1;
+        self.fee_sat += fee_sat;
+        match value {
+            Some(value) => self.fee_value += value,
+            None => self.unpriced += 1,
+        }
+    }
+
+    fn rounded(mut self) -> Self {
+        self.fee_value = (self.fee_value * 100.0).round() / 100.0;
+        self
+    }
+}
+
+/// Fees paid on transactions synced from the portfolio's wallets between `start` and `end`
+/// (inclusive, by the day they happened), per wallet and per month. Each is valued in
+/// `currency` at the transaction's recorded price, or failing that the cached price for its
+/// day. Receives are left out: their fee was the sender's.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn fee_analytics(
+    pool: &DbPool,
+    portfolio_id: &str,
+    currency: &str,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+) -> AppResult<FeeAnalytics> {
+    let conn = pool.get()?;
+    let rates = if currency == "usd" { None } else { Some(prices::usd_rates(&conn, currency)?) };
+    let daily: HashMap<String, f64> = conn
+        .prepare("SELECT date, price FROM price_history WHERE currency = ?1")?
+        .query_map(rusqlite::params![currency], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT transactions.wallet_id, w.label, transactions.transacted_at, transactions.fee_sat, transactions.price_usd
+         FROM transactions
+         JOIN wallets w ON w.id = transactions.wallet_id
+         WHERE transactions.portfolio_id = ?1 AND {COUNTED}
+           AND transactions.source = 'chain' AND transactions.tx_type != 'receive' AND transactions.fee_sat > 0
+           AND (?2 IS NULL OR substr(transactions.transacted_at, 1, 10) >= ?2)
+           AND (?3 IS NULL OR substr(transactions.transacted_at, 1, 10) <= ?3)
+         ORDER BY w.label, w.id"
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![portfolio_id, start.map(|d| d.to_string()), end.map(|d| d.to_string())],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, Option<f64>>(4)?,
+            ))
+        },
+    )?;
+
+    let mut total = FeeTotal::default();
+    let mut wallets: Vec<WalletFees> = Vec::new();
+    let mut months: BTreeMap<String, FeeTotal> = BTreeMap::new();
+    for row in rows {
+        let (wallet_id, label, transacted_at, fee_sat, price_usd) = row?;
+        let recorded = match &rates {
+            None => price_usd,
+            Some(rates) => price_usd.zip(prices::rate_on(rates, &transacted_at)).map(|(price, rate)| price * rate),
+        };
+        let price = recorded.or_else(|| transacted_at.get(..10).and_then(|day| daily.get(day).copied()));
+        let value = price.map(|price| fee_sat as f64 / 1e8 * price);
+
+        total.add(fee_sat, value);
+        match wallets.last_mut() {
+            Some(last) if last.wallet_id == wallet_id => last.fees.add(fee_sat, value),
+            _ => {
+                let mut fees = FeeTotal::default();
+                fees.add(fee_sat, value);
+                wallets.push(WalletFees { wallet_id, label, fees });
+            }
+        }
+        let month = transacted_at.get(..7).unwrap_or(&transacted_at).to_string();
+        months.entry(month).or_default().add(fee_sat, value);
+    }
+
+    Ok(FeeAnalytics {
+        currency: currency.to_string(),
+        total: total.rounded(),
+        wallets: wallets
+            .into_iter()
+            .map(|w| WalletFees { fees: w.fees.rounded(), ..w })
+            .collect(),
+        months: months
+            .into_iter()
+            .map(|(month, fees)| MonthFees { month, fees: fees.rounded() })
+            .collect(),
+    })
+}
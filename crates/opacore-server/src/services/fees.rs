@@ -1,7 +1,18 @@
-use reqwest::Client;
+use std::collections::{BTreeMap, HashMap};
+
+use bdk_wallet::bitcoin::Network;
 use serde::{Deserialize, Serialize};
 
 use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
+use crate::services::esplora::EsploraBackends;
+use crate::services::http;
+
+/// Confirmation targets (in blocks) reported by `estimates`.
+pub const ESTIMATE_TARGETS: [u16; 4] = [1, 3, 6, 144];
+
+/// Esplora's floor when it has no estimate for a target.
+const MIN_RELAY_FEE: f64 = 1.0;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,20 +26,84 @@ pub struct FeeRates {
 
 /// Fetch current recommended fee rates from mempool.space.
 pub async fn fetch_fee_rates() -> AppResult<FeeRates> {
-    let client = Client::builder()
-        .user_agent("opacore/0.1")
-        .build()
-        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))?;
+    let client = http::client()?;
 
     let resp: FeeRates = client
         .get("https://mempool.space/api/v1/fees/recommended")
         .header("Accept", "application/json")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("mempool.space request failed: {e}")))?
+        .map_err(|e| http::upstream_error("mempool.space", "mempool.space request failed", e))?
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("mempool.space response parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("mempool.space", "mempool.space response parse failed", e))?;
 
     Ok(resp)
 }
+
+/// Fee rates in sat/vB keyed by confirmation target in blocks.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimates {
+    pub network: String,
+    pub estimates: BTreeMap<u16, f64>,
+    pub fetched_at: String,
+}
+
+/// Fee estimates for `network` from Esplora's `/fee-estimates`, served from the cache when fresh.
+pub async fn estimates(esplora: &EsploraBackends, cache: &AppCache, network: Network) -> AppResult<FeeEstimates> {
+    if let Some(estimates) = cache.fee_estimates(network) {
+        return Ok(estimates);
+    }
+
+    let (raw, _url) = esplora
+        .with_failover(network, |url| async move { fetch_fee_estimates(&url).await })
+        .await?;
+
+    let estimates = FeeEstimates {
+        network: network.to_string(),
+        estimates: ESTIMATE_TARGETS
+            .iter()
+            .map(|&target| (target, pick_estimate(&raw, target)))
+            .collect(),
+        fetched_at: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+    };
+    cache.set_fee_estimates(network, estimates.clone());
+    Ok(estimates)
+}
+
+/// Esplora only reports the targets its node estimates for; fall back to the nearest
+/// slower target it does have, which never overstates the fee needed.
+fn pick_estimate(raw: &HashMap<u16, f64>, target: u16) -> f64 {
+    raw.iter()
+        .filter(|(&t, _)| t >= target)
+        .min_by_key(|(&t, _)| t)
+        .map(|(_, &rate)| rate)
+        .unwrap_or(MIN_RELAY_FEE)
+        .max(MIN_RELAY_FEE)
+}
+
+async fn fetch_fee_estimates(esplora_url: &str) -> AppResult<HashMap<u16, f64>> {
+    let http = http::client()?;
+
+    let url = format!("{esplora_url}/fee-estimates");
+    let resp = http
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("esplora", format!("Esplora request failed for {url}"), e))?;
+
+    if !resp.status().is_success() {
+        return Err(AppError::Internal(format!("Esplora returned {} for {url}", resp.status())));
+    }
+
+    // Keys are block targets as strings ("1", "2", ... "1008")
+    let raw: HashMap<String, f64> = resp
+        .json()
+        .await
+        .map_err(|e| http::upstream_error("esplora", "Esplora fee estimates parse failed", e))?;
+
+    Ok(raw
+        .into_iter()
+        .filter_map(|(target, rate)| Some((target.parse().ok()?, rate)))
+        .collect())
+}
@@ -0,0 +1,132 @@
+use chrono::{Days, Months, NaiveDate};
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::costbasis::{self, CostBasisMethod};
+use crate::services::tax::TaxRules;
+
+/// Age buckets, by the months a lot has been held: up to the first bound, up to the
+/// second, and so on, with the last open-ended.
+const BUCKETS: [(&str, Option<u32>); 4] = [("<3m", Some(3)), ("3-12m", Some(12)), ("1-2y", Some(24)), (">2y", None)];
+
+/// Windows, in days from today, for what becomes long-term soon.
+const UPCOMING_DAYS: [u64; 3] = [30, 60, 90];
+
+#[derive(Debug, Serialize)]
+pub struct HoldingsAge {
+    pub currency: String,
+    pub current_price: f64,
+    pub total_balance_sat: i64,
+    pub buckets: Vec<AgeBucket>,
+    /// Held short-term now, long-term within each window. Each window includes the
+    /// shorter ones.
+    pub becoming_long_term: Vec<UpcomingLongTerm>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Holding {
+    pub lot_count: usize,
+    pub balance_sat: i64,
+    pub cost_basis: f64,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgeBucket {
+    pub bucket: &'static str,
+    #[serde(flatten)]
+    pub holding: Holding,
+    /// Of the total balance, from 0 to 1. 0 with nothing held.
+    pub share: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpcomingLongTerm {
+    pub within_days: u64,
+    #[serde(flatten)]
+    pub holding: Holding,
+}
+
+impl Holding {
+    fn add(&mut self, lot: &costbasis::UnrealizedLot) {
+        self.lot_count += 1;
+        self.balance_sat += lot.lot.amount_sat;
+        self.cost_basis += lot.lot.cost_basis_usd;
+        self.value += lot.current_value_usd;
+    }
+
+    fn rounded(mut self) -> Self {
+        self.cost_basis = (self.cost_basis * 100.0).round() / 100.0;
+        self.value = (self.value * 100.0).round() / 100.0;
+        self
+    }
+}
+
+/// The portfolio's open lots under `method`, bucketed by how long they've been held as
+/// of today and valued at `current_price`, all in `currency`. A lot with no readable
+/// acquisition date counts as acquired today, as it does for the long-term test.
+#[tracing::instrument(level = "debug", skip(pool, rules))]
+pub fn holdings_age(
+    pool: &DbPool,
+    portfolio_id: &str,
+    method: CostBasisMethod,
+    currency: &str,
+    current_price: f64,
+    rules: &TaxRules,
+) -> AppResult<HoldingsAge> {
+    let lots = costbasis::unrealized_lots(pool, portfolio_id, method, currency, current_price, rules)?;
+    let today = chrono::Utc::now().date_naive();
+
+    let mut buckets: Vec<Holding> = BUCKETS.iter().map(|_| Holding::default()).collect();
+    let mut upcoming: Vec<Holding> = UPCOMING_DAYS.iter().map(|_| Holding::default()).collect();
+    for lot in &lots {
+        let acquired = lot
+            .lot
+            .acquired_at
+            .get(..10)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .unwrap_or(today);
+
+        let bucket = BUCKETS
+            .iter()
+            .position(|(_, months)| {
+                months.is_none_or(|months| acquired.checked_add_months(Months::new(months)).is_none_or(|d| d > today))
+            })
+            .unwrap_or(BUCKETS.len() - 1);
+        buckets[bucket].add(lot);
+
+        if !lot.is_long_term {
+            for (holding, days) in upcoming.iter_mut().zip(UPCOMING_DAYS) {
+                if today.checked_add_days(Days::new(days)).is_some_and(|on| rules.is_long_term(acquired, on)) {
+                    holding.add(lot);
+                }
+            }
+        }
+    }
+
+    let total_balance_sat: i64 = lots.iter().map(|lot| lot.lot.amount_sat).sum();
+    Ok(HoldingsAge {
+        currency: currency.to_string(),
+        current_price,
+        total_balance_sat,
+        buckets: BUCKETS
+            .iter()
+            .zip(buckets)
+            .map(|((bucket, _), holding)| AgeBucket {
+                bucket,
+                share: if total_balance_sat > 0 {
+                    holding.balance_sat as f64 / total_balance_sat as f64
+                } else {
+                    0.0
+                },
+                holding: holding.rounded(),
+            })
+            .collect(),
+        becoming_long_term: UPCOMING_DAYS
+            .into_iter()
+            .zip(upcoming)
+            .map(|(within_days, holding)| UpcomingLongTerm { within_days, holding: holding.rounded() })
+            .collect(),
+    })
+}
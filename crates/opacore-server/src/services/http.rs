@@ -0,0 +1,134 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::Client;
+
+use crate::error::{AppError, AppResult};
+
+/// Upper bound on a single outbound call, from connecting to reading the whole body.
+pub const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(15);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// HTTP client for third-party APIs with the standard user agent and timeouts.
+pub fn client() -> AppResult<Client> {
+    Client::builder()
+        .user_agent("opacore/0.1")
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(UPSTREAM_TIMEOUT)
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))
+}
+
+/// Map a failed outbound call. Timeouts become a 504 naming `upstream`; anything else
+/// is an internal error prefixed with `context`.
+pub fn upstream_error(upstream: &str, context: impl std::fmt::Display, e: reqwest::Error) -> AppError {
+    if e.is_timeout() {
+        AppError::UpstreamTimeout(upstream.to_string())
+    } else {
+        AppError::Internal(format!("{context}: {e}"))
+    }
+}
+
+/// Resolve the host of a user-supplied `url` and check every address it resolves to is
+/// on the public internet, so the server can't be pointed at itself, the private network
+/// or a cloud metadata service. Returns the addresses, for [`pinned_client`].
+pub async fn resolve_public(url: &reqwest::Url) -> AppResult<Vec<SocketAddr>> {
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(AppError::BadRequest("The URL has no host".into()));
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| AppError::BadRequest(format!("Could not resolve {host}")))?
+        .collect();
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_global(addr.ip())) {
+        return Err(AppError::BadRequest(format!("{host} isn't a public address")));
+    }
+    Ok(addrs)
+}
+
+/// [`client`] that connects to `url`'s host only at `addrs`, checked by [`resolve_public`]
+/// (so a second DNS lookup can't swap in another address), and doesn't follow redirects.
+pub fn pinned_client(url: &reqwest::Url, addrs: &[SocketAddr]) -> AppResult<Client> {
+    let mut builder = Client::builder()
+        .user_agent("opacore/0.1")
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(UPSTREAM_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    if let Some(domain) = url.domain() {
+        builder = builder.resolve_to_addrs(domain, addrs);
+    }
+    builder
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))
+}
+
+/// Whether `ip` is routable on the public internet: not loopback, private, link-local,
+/// shared, reserved, documentation or multicast, however it's written.
+pub fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Shared address space (carrier-grade NAT), 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments, 192.0.0.0/24
+                || (a == 192 && b == 0 && ip.octets()[2] == 0)
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved, 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_global(IpAddr::V4(v4));
+            }
+            let segments = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, fc00::/7
+                || (segments[0] & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10
+                || (segments[0] & 0xffc0) == 0xfe80
+                // Documentation, 2001:db8::/32
+                || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+                // NAT64 and IPv4-compatible addresses embed an IPv4 one
+                || (segments[0] == 0x0064 && segments[1] == 0xff9b)
+                || segments[..6].iter().all(|s| *s == 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global(ip: &str) -> bool {
+        is_global(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn internal_addresses_are_not_global() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "255.255.255.255", "::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::ffff:169.254.169.254",
+        ] {
+            assert!(!global(ip), "{ip} counted as global");
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_global() {
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(global(ip), "{ip} not counted as global");
+        }
+    }
+}
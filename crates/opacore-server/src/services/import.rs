@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::events::{self, DomainEvent};
+use crate::services::tax_locks;
+use crate::services::transaction_audit;
+
+/// Wallet software whose history exports can be imported.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Electrum,
+    Wasabi,
+}
+
+impl ImportFormat {
+    /// Value stored in `transactions.source` for imported rows.
+    pub fn source(self) -> &'static str {
+        match self {
+            ImportFormat::Electrum => "electrum",
+            ImportFormat::Wasabi => "wasabi",
+        }
+    }
+}
+
+/// A transaction parsed from an export, ready to insert.
+#[derive(Debug, Serialize)]
+pub struct ImportedTransaction {
+    /// Line in the uploaded file the transaction came from.
+    pub line: usize,
+    pub txid: Option<String>,
+    pub tx_type: &'static str,
+    pub amount_sat: i64,
+    pub fee_sat: Option<i64>,
+    pub price_usd: Option<f64>,
+    pub fiat_amount: Option<f64>,
+    pub fiat_currency: String,
+    pub transacted_at: String,
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowError {
+    /// 1-based line number in the uploaded file (the header is line 1).
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    pub imported: usize,
+    pub skipped_duplicates: usize,
+    /// Dated in a locked tax year, on an import nobody could override the lock for.
+    pub skipped_locked: usize,
+    pub labels_created: usize,
+    pub errors: Vec<ImportRowError>,
+}
+
+/// Parse an export into transactions. Rows that can't be read are reported in the
+/// returned errors rather than failing the whole file.
+pub fn parse(format: ImportFormat, data: &str) -> AppResult<(Vec<ImportedTransaction>, Vec<ImportRowError>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+
+    let headers = reader
+        .headers()
+        .map_err(|e| AppError::BadRequest(format!("Could not read CSV header: {e}")))?
+        .clone();
+    let columns = Columns::new(&headers);
+
+    let (txid_col, amount_col, date_col, label_col, fee_col) = match format {
+        // transaction_hash,label,confirmations,value,fiat_value,fee,fiat_fee,timestamp
+        ImportFormat::Electrum => (
+            columns.find(&["transactionhash", "txid"]),
+            columns.find(&["value", "amount"]),
+            columns.find(&["timestamp", "date"]),
+            columns.find(&["label", "description"]),
+            columns.find(&["fee"]),
+        ),
+        // Date,Amount (BTC),Labels,Transaction ID
+        ImportFormat::Wasabi => (
+            columns.find(&["transactionid", "txid", "id"]),
+            columns.find(&["amountbtc", "amount"]),
+            columns.find(&["date", "datetime", "timestamp"]),
+            columns.find(&["labels", "label"]),
+            None,
+        ),
+    };
+
+    let (Some(amount_col), Some(date_col)) = (amount_col, date_col) else {
+        return Err(AppError::BadRequest(format!(
+            "Not a {} export: missing amount or date column",
+            format.source()
+        )));
+    };
+
+    let mut txs = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, record) in reader.records().enumerate() {
+        let line = i + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(ImportRowError { line, message: e.to_string() });
+                continue;
+            }
+        };
+        let field = |col: Option<usize>| col.and_then(|c| record.get(c)).filter(|s| !s.is_empty());
+
+        let Some(net_sat) = field(Some(amount_col)).and_then(parse_btc_amount) else {
+            errors.push(ImportRowError { line, message: "Invalid or missing amount".into() });
+            continue;
+        };
+        let Some(transacted_at) = field(Some(date_col)).and_then(parse_timestamp) else {
+            errors.push(ImportRowError { line, message: "Invalid or missing date (unconfirmed?)".into() });
+            continue;
+        };
+
+        let labels = match (format, field(label_col)) {
+            (_, None) => vec![],
+            // Wasabi keeps several labels in one comma-separated field
+            (ImportFormat::Wasabi, Some(l)) => l
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            (ImportFormat::Electrum, Some(l)) => vec![l.to_string()],
+        };
+
+        let (tx_type, amount_sat) = if net_sat >= 0 {
+            ("receive", net_sat)
+        } else {
+            ("send", -net_sat)
+        };
+
+        txs.push(ImportedTransaction {
+            line,
+            txid: field(txid_col).map(|s| s.to_string()),
+            tx_type,
+            amount_sat,
+            fee_sat: field(fee_col).and_then(parse_btc_amount).map(i64::abs),
+            price_usd: None,
+            fiat_amount: None,
+            fiat_currency: "usd".into(),
+            transacted_at,
+            labels,
+        });
+    }
+
+    Ok((txs, errors))
+}
+
+/// Whether the portfolio already holds this transaction: the same txid or, for rows
+/// without one (exchange trades), the same type, amount and time.
+pub fn is_duplicate(conn: &rusqlite::Connection, portfolio_id: &str, tx: &ImportedTransaction) -> AppResult<bool> {
+    let exists = match tx.txid {
+        Some(ref txid) => conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM transactions WHERE txid = ?1 AND portfolio_id = ?2)",
+            rusqlite::params![txid, portfolio_id],
+            |row| row.get(0),
+        )?,
+        None => conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM transactions
+             WHERE portfolio_id = ?1 AND tx_type = ?2 AND amount_sat = ?3 AND transacted_at = ?4)",
+            rusqlite::params![portfolio_id, tx.tx_type, tx.amount_sat, tx.transacted_at],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(exists)
+}
+
+/// Insert parsed transactions into a portfolio, creating the user's labels as needed.
+/// Transactions already in the portfolio (see [`is_duplicate`]) are skipped. Run inside
+/// a SQLite transaction: each insert also records a `transaction.created` event and an
+/// audit entry attributed to `user_id`.
+///
+/// A transaction dated in a locked tax year goes through [`tax_locks::check_dates`] for
+/// `by`, the user importing: refused, or an audited admin override. Without one (a
+/// background sync) it's skipped.
+pub fn insert_transactions(
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    by: Option<(&User, &ClientInfo)>,
+    portfolio_id: &str,
+    wallet_id: Option<&str>,
+    source: &str,
+    txs: &[ImportedTransaction],
+) -> AppResult<ImportResult> {
+    let mut label_ids: HashMap<String, String> = HashMap::new();
+    let mut imported = 0;
+    let mut skipped_duplicates = 0;
+    let mut skipped_locked = 0;
+    let mut labels_created = 0;
+
+    for tx in txs {
+        if is_duplicate(conn, portfolio_id, tx)? {
+            skipped_duplicates += 1;
+            continue;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        match by {
+            Some((user, client)) => tax_locks::check_dates(conn, portfolio_id, &id, &[&tx.transacted_at], user, client)?,
+            None if tax_locks::locked_year(conn, portfolio_id, &tx.transacted_at)?.is_some() => {
+                skipped_locked += 1;
+                continue;
+            }
+            None => {}
+        }
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        conn.execute(
+            "INSERT INTO transactions (id, portfolio_id, wallet_id, tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, source, transacted_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            rusqlite::params![
+                id, portfolio_id, wallet_id, tx.tx_type, tx.amount_sat, tx.fee_sat,
+                tx.price_usd, tx.fiat_amount, tx.fiat_currency, tx.txid, source,
+                tx.transacted_at, now, now
+            ],
+        )?;
+        events::record(
+            conn,
+            portfolio_id,
+            &DomainEvent::TransactionCreated { transaction_id: id.clone(), wallet_id: wallet_id.map(String::from) },
+        )?;
+        transaction_audit::record_created(conn, portfolio_id, &id, Some(user_id))?;
+        imported += 1;
+
+        for name in &tx.labels {
+            let label_id = match label_ids.get(name) {
+                Some(id) => id.clone(),
+                None => {
+                    let (label_id, created) = get_or_create_label(conn, user_id, name)?;
+                    if created {
+                        labels_created += 1;
+                    }
+                    label_ids.insert(name.clone(), label_id.clone());
+                    label_id
+                }
+            };
+            conn.execute(
+                "INSERT OR IGNORE INTO transaction_labels (transaction_id, label_id) VALUES (?1, ?2)",
+                rusqlite::params![id, label_id],
+            )?;
+        }
+    }
+
+    Ok(ImportResult {
+        imported,
+        skipped_duplicates,
+        skipped_locked,
+        labels_created,
+        errors: vec![],
+    })
+}
+
+fn get_or_create_label(conn: &rusqlite::Connection, user_id: &str, name: &str) -> AppResult<(String, bool)> {
+    match conn.query_row(
+        "SELECT id FROM labels WHERE user_id = ?1 AND name = ?2",
+        rusqlite::params![user_id, name],
+        |row| row.get(0),
+    ) {
+        Ok(id) => Ok((id, false)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let id = uuid::Uuid::new_v4().to_string();
+            let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+            conn.execute(
+                "INSERT INTO labels (id, user_id, name, created_at) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![id, user_id, name, now],
+            )?;
+            Ok((id, true))
+        }
+        Err(e) => Err(AppError::Database(e)),
+    }
+}
+
+/// Header lookup that ignores case, spaces and punctuation ("Amount (BTC)" == "amountbtc").
+pub struct Columns(Vec<String>);
+
+impl Columns {
+    pub fn new(headers: &csv::StringRecord) -> Self {
+        Self(headers.iter().map(normalize_header).collect())
+    }
+
+    pub fn find(&self, names: &[&str]) -> Option<usize> {
+        names
+            .iter()
+            .find_map(|name| self.0.iter().position(|h| h == name))
+    }
+}
+
+pub fn normalize_header(h: &str) -> String {
+    h.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Parse a signed BTC decimal ("-0.0015", "+1.2", "1,000.5") into sats without
+/// going through floating point.
+pub fn parse_btc_amount(s: &str) -> Option<i64> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace() && *c != ',').collect();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(&s)),
+    };
+
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && frac.is_empty() {
+        return None;
+    }
+    // Exchanges print up to 10 decimals; anything past the 8th must be zero padding
+    let frac = match frac.split_at_checked(8) {
+        Some((sats, padding)) if padding.chars().all(|c| c == '0') => sats,
+        Some(_) => return None,
+        None => frac,
+    };
+    if !whole.chars().all(|c| c.is_ascii_digit()) || !frac.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+    let frac: i64 = format!("{frac:0<8}").parse().ok()?;
+    let sats = whole.checked_mul(100_000_000)?.checked_add(frac)?;
+    Some(if negative { -sats } else { sats })
+}
+
+/// Parse the timestamp formats wallet and exchange exports use, normalized to how
+/// timestamps are stored. Times without an offset are taken as UTC.
+pub fn parse_timestamp(s: &str) -> Option<String> {
+    parse_datetime(s).map(|dt| dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+}
+
+/// As [`parse_timestamp`], unformatted.
+pub(crate) fn parse_datetime(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    let s = s.strip_suffix(" UTC").unwrap_or(s);
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            [
+                "%Y-%m-%d %H:%M:%S%.f",
+                "%Y-%m-%d %H:%M",
+                "%Y-%m-%dT%H:%M:%S%.f",
+                "%m/%d/%Y %H:%M:%S",
+                "%m/%d/%Y %H:%M",
+                "%b %d %Y %H:%M:%S",
+            ]
+                .iter()
+                .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+                .map(|naive| naive.and_utc())
+        })
+        .or_else(|| {
+            DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z")
+                .map(|dt| dt.with_timezone(&Utc))
+                .ok()
+        })
+        .or_else(|| {
+            // Date-only rows (generic imports) are taken as midnight UTC
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .map(|naive| naive.and_utc())
+        })
+}
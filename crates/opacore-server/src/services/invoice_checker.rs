@@ -1,21 +1,32 @@
+use bdk_wallet::bitcoin::Network;
 use serde::Deserialize;
+use crate::config::{LightningConfig, TaskIntervals};
 use crate::db::DbPool;
 use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
+use crate::services::esplora::EsploraBackends;
+use crate::services::http;
+use crate::services::lightning;
+use crate::services::sync::{self, PendingTxStatus};
+use crate::services::events::{self, DomainEvent};
+use crate::services::invoice_history::{self, Actor, InvoiceEvent};
+use crate::services::mempool_ws::WatchStatus;
 
 #[derive(Debug, Deserialize)]
 struct EsploraTx {
     txid: String,
-    status: EsploraTxStatus,
     #[serde(default)]
     vout: Vec<EsploraVout>,
+    #[serde(default)]
+    status: EsploraStatus,
 }
 
-#[derive(Debug, Deserialize)]
-struct EsploraTxStatus {
+#[derive(Debug, Default, Deserialize)]
+struct EsploraStatus {
     #[serde(default)]
     confirmed: bool,
     #[serde(default)]
-    block_time: Option<u64>,
+    block_height: Option<i64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -26,20 +37,9 @@ struct EsploraVout {
     value: u64,
 }
 
-/// Check if a specific invoice has been paid by querying Esplora.
-/// Returns true if payment was detected and the invoice was updated.
-pub async fn check_invoice_payment(
-    esplora_url: &str,
-    pool: &DbPool,
-    invoice_id: &str,
-    btc_address: &str,
-    amount_sat: i64,
-    reusable: bool,
-) -> AppResult<bool> {
-    let http = reqwest::Client::builder()
-        .user_agent("opacore/0.1")
-        .build()
-        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))?;
+/// Fetch the transaction history of an address from a single Esplora backend.
+async fn fetch_address_txs(esplora_url: &str, btc_address: &str) -> AppResult<Vec<EsploraTx>> {
+    let http = http::client()?;
 
     let url = format!("{esplora_url}/address/{btc_address}/txs");
     let resp = http
@@ -47,68 +47,384 @@ pub async fn check_invoice_payment(
         .header("Accept", "application/json")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora request failed: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", "Esplora request failed", e))?;
 
     if !resp.status().is_success() {
         let status = resp.status();
         let body = resp.text().await.unwrap_or_default();
-        tracing::warn!("Esplora returned {status} for invoice check on {btc_address}: {body}");
-        return Ok(false);
+        return Err(AppError::Internal(format!(
+            "Esplora returned {status} for invoice check on {btc_address}: {body}"
+        )));
     }
 
-    let txs: Vec<EsploraTx> = resp
-        .json()
+    resp.json()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", "Esplora parse failed", e))
+}
+
+/// Check if a specific invoice has been paid by querying Esplora.
+///
+/// A payment counts once it has the invoice's `min_confirmations`; until then a one-time
+/// invoice waits in `pending_confirmation`, and goes back to `sent` if the payment drops
+/// out of the mempool. Returns true if the invoice was updated.
+pub async fn check_invoice_payment(
+    esplora: &EsploraBackends,
+    cache: &AppCache,
+    pool: &DbPool,
+    invoice_id: &str,
+    btc_address: &str,
+    amount_sat: i64,
+    reusable: bool,
+) -> AppResult<bool> {
+    let (txs, _backend) = esplora
+        .with_failover(Network::Bitcoin, |url| async move {
+            fetch_address_txs(&url, btc_address).await
+        })
+        .await?;
+
+    let (status, min_confirmations): (String, i64) = {
+        let conn = pool.get()?;
+        conn.query_row(
+            "SELECT status, min_confirmations FROM invoices WHERE id = ?1",
+            rusqlite::params![invoice_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+    };
 
-    // Look for any transaction that pays to this address with sufficient amount
+    // For open-ended payment links (amount_sat = 0), any received amount qualifies
+    let threshold = if amount_sat == 0 { 1 } else { amount_sat as u64 };
+
+    // Transactions paying enough to this address, newest first, with their confirmations
+    let mut payments = Vec::new();
+    let mut underpayments = Vec::new();
+    let mut tip = None;
     for tx in &txs {
         let received: u64 = tx.vout.iter()
             .filter(|v| v.scriptpubkey_address.as_deref() == Some(btc_address))
             .map(|v| v.value)
             .sum();
+        if received < threshold {
+            if received > 0 {
+                underpayments.push((tx, received));
+            }
+            continue;
+        }
+        let confirmations = match (tx.status.confirmed, tx.status.block_height) {
+            (true, Some(height)) => {
+                let tip = match tip {
+                    Some(tip) => tip,
+                    None => *tip.insert(esplora.tip_height(cache, Network::Bitcoin).await? as i64),
+                };
+                (tip - height + 1).max(1)
+            }
+            _ => 0,
+        };
+        payments.push((tx, received, confirmations));
+    }
 
-        // For open-ended payment links (amount_sat = 0), any received amount qualifies
-        let threshold = if amount_sat == 0 { 1 } else { amount_sat as u64 };
+    if !reusable && status != "paid" && !underpayments.is_empty() {
+        record_underpayments(pool, invoice_id, &underpayments)?;
+    }
 
-        if received >= threshold {
+    // Reusable links record the latest settled payment; one-time invoices the best-confirmed one
+    let payment = if reusable {
+        payments.iter().find(|(_, _, confirmations)| *confirmations >= min_confirmations)
+    } else {
+        payments.iter().max_by_key(|(_, _, confirmations)| *confirmations)
+    };
+    let Some(&(tx, received, confirmations)) = payment else {
+        if status == "pending_confirmation" {
             let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
-            let conn = pool.get()?;
-
-            if reusable {
-                // Reusable payment links: record payment but keep status as 'sent'
-                conn.execute(
-                    "UPDATE invoices SET paid_at = ?1, paid_txid = ?2, paid_amount_sat = ?3, updated_at = ?4 WHERE id = ?5",
-                    rusqlite::params![now, tx.txid, received as i64, now, invoice_id],
-                )?;
-            } else {
-                // One-time: mark as paid
-                conn.execute(
-                    "UPDATE invoices SET status = 'paid', paid_at = ?1, paid_txid = ?2, paid_amount_sat = ?3, updated_at = ?4 WHERE id = ?5 AND status != 'paid'",
-                    rusqlite::params![now, tx.txid, received as i64, now, invoice_id],
+            let mut conn = pool.get()?;
+            let db_tx = conn.transaction()?;
+            let updated = db_tx.execute(
+                "UPDATE invoices SET status = 'sent', paid_txid = NULL, paid_amount_sat = NULL, updated_at = ?1
+                 WHERE id = ?2 AND status = 'pending_confirmation'",
+                rusqlite::params![now, invoice_id],
+            )?;
+            if updated > 0 {
+                invoice_history::record(
+                    &db_tx,
+                    invoice_id,
+                    InvoiceEvent::PaymentDropped,
+                    Some(("pending_confirmation", "sent")),
+                    Actor::System,
+                    None,
                 )?;
             }
-
-            tracing::info!("Invoice {invoice_id} paid via txid {} ({} sats)", tx.txid, received);
+            db_tx.commit()?;
+            tracing::warn!("Invoice {invoice_id} payment is no longer seen, back to sent");
             return Ok(true);
         }
+        return Ok(false);
+    };
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+
+    if !reusable && confirmations < min_confirmations {
+        let updated = db_tx.execute(
+            "UPDATE invoices SET status = 'pending_confirmation', paid_txid = ?1, paid_amount_sat = ?2, updated_at = ?3
+             WHERE id = ?4 AND status != 'paid' AND (status != 'pending_confirmation' OR paid_txid IS NOT ?1)",
+            rusqlite::params![tx.txid, received as i64, now, invoice_id],
+        )?;
+        if updated > 0 {
+            invoice_history::record(
+                &db_tx,
+                invoice_id,
+                InvoiceEvent::PaymentDetected,
+                Some((&status, "pending_confirmation")),
+                Actor::System,
+                Some(&serde_json::json!({ "txid": tx.txid, "amount_sat": received, "confirmations": confirmations })),
+            )?;
+        }
+        db_tx.commit()?;
+        if updated > 0 {
+            tracing::info!(
+                "Invoice {invoice_id} payment {} seen, {confirmations}/{min_confirmations} confirmations",
+                tx.txid
+            );
+        }
+        return Ok(updated > 0);
+    }
+
+    let updated = if reusable {
+        // Reusable payment links: record the latest payment but keep status as 'sent'
+        db_tx.execute(
+            "UPDATE invoices SET paid_at = ?1, paid_txid = ?2, paid_amount_sat = ?3, paid_via = 'onchain', updated_at = ?4 WHERE id = ?5 AND paid_txid IS NOT ?2",
+            rusqlite::params![now, tx.txid, received as i64, now, invoice_id],
+        )?
+    } else {
+        // One-time: mark as paid
+        db_tx.execute(
+            "UPDATE invoices SET status = 'paid', paid_at = ?1, paid_txid = ?2, paid_amount_sat = ?3, paid_via = 'onchain', updated_at = ?4 WHERE id = ?5 AND status != 'paid'",
+            rusqlite::params![now, tx.txid, received as i64, now, invoice_id],
+        )?
+    };
+
+    // Only a newly seen payment is an event; re-checks of a known one are not
+    if updated > 0 {
+        let portfolio_id: String = db_tx.query_row(
+            "SELECT portfolio_id FROM invoices WHERE id = ?1",
+            rusqlite::params![invoice_id],
+            |row| row.get(0),
+        )?;
+        events::record(
+            &db_tx,
+            &portfolio_id,
+            &DomainEvent::InvoicePaid {
+                invoice_id: invoice_id.to_string(),
+                txid: Some(tx.txid.clone()),
+                payment_hash: None,
+                amount_sat: received as i64,
+            },
+        )?;
+        invoice_history::record(
+            &db_tx,
+            invoice_id,
+            InvoiceEvent::Paid,
+            (!reusable).then_some((status.as_str(), "paid")),
+            Actor::System,
+            Some(&serde_json::json!({ "via": "onchain", "txid": tx.txid, "amount_sat": received })),
+        )?;
+    }
+    db_tx.commit()?;
+
+    tracing::info!("Invoice {invoice_id} paid via txid {} ({} sats)", tx.txid, received);
+    Ok(true)
+}
+
+/// Mark the invoice whose BOLT11 has `payment_hash` paid, if it isn't already. The node
+/// only settles a BOLT11 for its full amount, so there is no underpayment to track; when
+/// it doesn't report the amount, the invoice's own is recorded. Returns false if no open
+/// invoice has that payment hash.
+pub fn settle_lightning(pool: &DbPool, payment_hash: &str, amount_sat: Option<i64>) -> AppResult<bool> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let invoice: Option<(String, String, i64, String)> = match db_tx.query_row(
+        "SELECT id, portfolio_id, amount_sat, status FROM invoices WHERE lightning_payment_hash = ?1 AND status != 'paid'",
+        rusqlite::params![payment_hash],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    ) {
+        Ok(invoice) => Some(invoice),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into()),
+    };
+    let Some((invoice_id, portfolio_id, invoice_amount_sat, status)) = invoice else {
+        return Ok(false);
+    };
+    let amount_sat = amount_sat.filter(|&a| a > 0).unwrap_or(invoice_amount_sat);
+
+    db_tx.execute(
+        "UPDATE invoices SET status = 'paid', paid_at = ?1, paid_txid = NULL, paid_amount_sat = ?2,
+             paid_via = 'lightning', updated_at = ?1
+         WHERE id = ?3",
+        rusqlite::params![now, amount_sat, invoice_id],
+    )?;
+    events::record(
+        &db_tx,
+        &portfolio_id,
+        &DomainEvent::InvoicePaid {
+            invoice_id: invoice_id.clone(),
+            txid: None,
+            payment_hash: Some(payment_hash.to_string()),
+            amount_sat,
+        },
+    )?;
+    invoice_history::record(
+        &db_tx,
+        &invoice_id,
+        InvoiceEvent::Paid,
+        Some((&status, "paid")),
+        Actor::System,
+        Some(&serde_json::json!({ "via": "lightning", "payment_hash": payment_hash, "amount_sat": amount_sat })),
+    )?;
+    db_tx.commit()?;
+
+    tracing::info!("Invoice {invoice_id} paid over Lightning ({amount_sat} sats)");
+    Ok(true)
+}
+
+/// Poll the Lightning backend for an invoice's BOLT11, for settlements the stream missed.
+/// Returns true if it settled the invoice.
+pub async fn check_lightning_payment(pool: &DbPool, config: &LightningConfig, payment_hash: &str) -> AppResult<bool> {
+    match lightning::settled_amount(config, payment_hash).await? {
+        Some(amount_sat) => settle_lightning(pool, payment_hash, Some(amount_sat)),
+        None => Ok(false),
+    }
+}
+
+/// Announce each transaction that paid less than the invoice amount, once. They don't
+/// change the invoice's status; the merchant decides what to do with a shortfall.
+fn record_underpayments(pool: &DbPool, invoice_id: &str, underpayments: &[(&EsploraTx, u64)]) -> AppResult<()> {
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let portfolio_id: String = db_tx.query_row(
+        "SELECT portfolio_id FROM invoices WHERE id = ?1",
+        rusqlite::params![invoice_id],
+        |row| row.get(0),
+    )?;
+    for (tx, received) in underpayments {
+        let inserted = db_tx.execute(
+            "INSERT OR IGNORE INTO invoice_partial_payments (invoice_id, txid, amount_sat) VALUES (?1, ?2, ?3)",
+            rusqlite::params![invoice_id, tx.txid, *received as i64],
+        )?;
+        if inserted > 0 {
+            events::record(
+                &db_tx,
+                &portfolio_id,
+                &DomainEvent::InvoicePartiallyPaid {
+                    invoice_id: invoice_id.to_string(),
+                    txid: tx.txid.clone(),
+                    amount_sat: *received as i64,
+                },
+            )?;
+            invoice_history::record(
+                &db_tx,
+                invoice_id,
+                InvoiceEvent::PartiallyPaid,
+                None,
+                Actor::System,
+                Some(&serde_json::json!({ "txid": tx.txid, "amount_sat": received })),
+            )?;
+            tracing::info!("Invoice {invoice_id} partially paid via txid {} ({received} sats)", tx.txid);
+        }
     }
+    db_tx.commit()?;
+    Ok(())
+}
 
-    Ok(false)
+/// Expire overdue one-time invoices still waiting for payment, or never sent (reusable
+/// links never expire), recording an event for each.
+fn expire_overdue(conn: &mut rusqlite::Connection, now: &str) -> AppResult<()> {
+    let db_tx = conn.transaction()?;
+    let overdue: Vec<(String, String, String)> = {
+        let mut stmt = db_tx.prepare(
+            "SELECT id, portfolio_id, status FROM invoices
+             WHERE status IN ('draft', 'sent') AND reusable = 0 AND expires_at IS NOT NULL AND expires_at < ?1",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![now], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    for (invoice_id, portfolio_id, status) in overdue {
+        db_tx.execute(
+            "UPDATE invoices SET status = 'expired', updated_at = ?1 WHERE id = ?2",
+            rusqlite::params![now, invoice_id],
+        )?;
+        invoice_history::record(&db_tx, &invoice_id, InvoiceEvent::Expired, Some((&status, "expired")), Actor::System, None)?;
+        events::record(&db_tx, &portfolio_id, &DomainEvent::InvoiceExpired { invoice_id })?;
+    }
+    db_tx.commit()?;
+    Ok(())
+}
+
+/// Confirmations of an invoice's payment transaction; 0 while it is in the mempool or
+/// if the backend no longer knows it. Confirmed heights and the tip come from the cache
+/// when fresh, so repeated polling rarely reaches Esplora.
+pub async fn payment_confirmations(esplora: &EsploraBackends, cache: &AppCache, txid: &str) -> AppResult<i64> {
+    let height = match cache.tx_height(txid) {
+        Some(height) => height,
+        None => {
+            let txids = [txid.to_string()];
+            let (statuses, _backend) = esplora
+                .with_failover(Network::Bitcoin, |url| {
+                    let txids = &txids;
+                    async move { sync::fetch_pending_statuses(&url, txids).await }
+                })
+                .await?;
+            match statuses.get(txid) {
+                Some(PendingTxStatus::Confirmed { block_height, .. }) => {
+                    cache.set_tx_height(txid, *block_height);
+                    *block_height
+                }
+                _ => return Ok(0),
+            }
+        }
+    };
+
+    let tip = esplora.tip_height(cache, Network::Bitcoin).await? as i64;
+    Ok((tip - height + 1).max(1))
 }
 
 /// Background task that periodically checks pending invoices for payments.
-pub async fn run_invoice_checker(pool: DbPool, esplora_url: String) {
-    tracing::info!("Invoice checker background task started");
+/// While the mempool WebSocket watch is connected it detects payments itself, so polling
+/// drops to an occasional safety-net sweep; payments waiting for confirmations are still
+/// re-checked every round, as the watch doesn't announce new blocks.
+///
+/// Each round checks a batch of open invoices, continuing after the last one checked so
+/// a backlog larger than the batch is worked through in turn. Invoices with a BOLT11 are
+/// also looked up on the Lightning backend, in case its settlement stream missed them.
+pub async fn run_invoice_checker(
+    pool: DbPool,
+    esplora: EsploraBackends,
+    cache: AppCache,
+    ws_status: WatchStatus,
+    lightning: Option<LightningConfig>,
+    intervals: TaskIntervals,
+) {
+    tracing::info!(
+        "Invoice checker background task started (interval: {}s, batch: {})",
+        intervals.invoice_check.as_secs(),
+        intervals.invoice_check_batch
+    );
+    let mut last_sweep = tokio::time::Instant::now();
+    let mut last_checked = String::new();
 
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+        tokio::time::sleep(intervals.invoice_check).await;
+
+        let sweep = !ws_status.is_connected() || last_sweep.elapsed() >= intervals.invoice_ws_sweep;
+        if sweep {
+            last_sweep = tokio::time::Instant::now();
+        }
 
         let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
 
-        // Get pending invoices (status = 'sent', not expired)
-        let invoices_to_check: Vec<(String, String, i64, bool)> = {
-            let conn = match pool.get() {
+        // Get pending invoices (status = 'sent' or awaiting confirmations, not expired)
+        let invoices_to_check: Vec<(String, String, i64, bool, Option<String>)> = {
+            let mut conn = match pool.get() {
                 Ok(c) => c,
                 Err(e) => {
                     tracing::error!("Invoice checker: failed to get DB connection: {e}");
@@ -116,17 +432,16 @@ pub async fn run_invoice_checker(pool: DbPool, esplora_url: String) {
                 }
             };
 
-            // Expire overdue invoices first (skip reusable — they never auto-expire)
-            if let Err(e) = conn.execute(
-                "UPDATE invoices SET status = 'expired', updated_at = ?1 WHERE status = 'sent' AND reusable = 0 AND expires_at IS NOT NULL AND expires_at < ?2",
-                rusqlite::params![now, now],
-            ) {
+            // Expire overdue invoices first
+            if let Err(e) = expire_overdue(&mut conn, &now) {
                 tracing::error!("Invoice checker: failed to expire invoices: {e}");
             }
 
-            // Fetch sent invoices to check for payment
+            // Fetch the next batch of invoices to check for payment or confirmations
             let mut stmt = match conn.prepare(
-                "SELECT id, btc_address, amount_sat, reusable FROM invoices WHERE status = 'sent' LIMIT 10"
+                "SELECT id, btc_address, amount_sat, reusable, lightning_payment_hash FROM invoices
+                 WHERE (status = 'pending_confirmation' OR (status = 'sent' AND ?3)) AND id > ?1
+                 ORDER BY id LIMIT ?2"
             ) {
                 Ok(s) => s,
                 Err(e) => {
@@ -135,17 +450,18 @@ pub async fn run_invoice_checker(pool: DbPool, esplora_url: String) {
                 }
             };
 
-            let rows = stmt.query_map([], |row| {
+            let rows = stmt.query_map(rusqlite::params![last_checked, intervals.invoice_check_batch, sweep], |row| {
                 Ok((
                     row.get::<_, String>(0)?,
                     row.get::<_, String>(1)?,
                     row.get::<_, i64>(2)?,
                     row.get::<_, i32>(3).map(|v| v != 0)?,
+                    row.get::<_, Option<String>>(4)?,
                 ))
             });
 
             match rows {
-                Ok(r) => r.filter_map(|r| r.ok()).collect(),
+                Ok(r) => r.filter_map(|r| r.ok()).collect::<Vec<_>>(),
                 Err(e) => {
                     tracing::error!("Invoice checker: failed to query invoices: {e}");
                     continue;
@@ -153,21 +469,33 @@ pub async fn run_invoice_checker(pool: DbPool, esplora_url: String) {
             }
         };
 
+        // A short batch means the end was reached; start from the top next round
+        last_checked = match invoices_to_check.last() {
+            Some((id, ..)) if invoices_to_check.len() as i64 == intervals.invoice_check_batch => id.clone(),
+            _ => String::new(),
+        };
         if invoices_to_check.is_empty() {
             continue;
         }
 
         tracing::debug!("Checking {} pending invoices for payment", invoices_to_check.len());
 
-        for (invoice_id, btc_address, amount_sat, reusable) in &invoices_to_check {
-            match check_invoice_payment(&esplora_url, &pool, invoice_id, btc_address, *amount_sat, *reusable).await {
-                Ok(true) => tracing::info!("Invoice {invoice_id} payment detected"),
+        for (invoice_id, btc_address, amount_sat, reusable, payment_hash) in &invoices_to_check {
+            if let (Some(config), Some(payment_hash)) = (&lightning, payment_hash) {
+                match check_lightning_payment(&pool, config, payment_hash).await {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("Invoice {invoice_id} Lightning check failed: {e}"),
+                }
+            }
+            match check_invoice_payment(&esplora, &cache, &pool, invoice_id, btc_address, *amount_sat, *reusable).await {
+                Ok(true) => tracing::info!("Invoice {invoice_id} payment status updated"),
                 Ok(false) => {}
                 Err(e) => tracing::warn!("Invoice {invoice_id} check failed: {e}"),
             }
 
             // Small delay between checks to avoid rate limiting
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            tokio::time::sleep(intervals.invoice_check_delay).await;
         }
     }
 }
@@ -0,0 +1,227 @@
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::email::escape;
+use crate::services::{email, quotas};
+
+/// Longest note a merchant can add to an invoice email.
+pub const MESSAGE_MAX_CHARS: usize = 2000;
+
+/// What an invoice email shows, read from the invoice and the merchant who owns it.
+pub struct InvoiceEmail {
+    pub invoice_number: Option<String>,
+    pub customer_name: Option<String>,
+    pub customer_email: Option<String>,
+    pub description: Option<String>,
+    pub amount_sat: i64,
+    pub amount_fiat: Option<f64>,
+    pub fiat_currency: String,
+    /// The fiat amount is owed, and `amount_sat` only its current quote.
+    pub fiat_denominated: bool,
+    pub btc_address: String,
+    pub share_token: String,
+    pub due_at: Option<String>,
+    pub merchant_id: String,
+    /// The portfolio's business name, or its owner's name.
+    pub merchant_name: String,
+    pub merchant_email: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Invoice,
+    /// Sent ahead of `due_at` while the invoice is still unpaid.
+    Reminder,
+}
+
+pub fn load(conn: &rusqlite::Connection, invoice_id: &str) -> AppResult<InvoiceEmail> {
+    conn.query_row(
+        "SELECT i.invoice_number, i.customer_name, i.customer_email, i.description, i.amount_sat,
+                i.amount_fiat, i.fiat_currency, i.btc_address, i.share_token, i.due_at, u.id, COALESCE(b.business_name, u.name), u.email,
+                i.fiat_denominated
+         FROM invoices i
+         JOIN portfolios p ON p.id = i.portfolio_id
+         JOIN users u ON u.id = p.user_id
+         LEFT JOIN portfolio_branding b ON b.portfolio_id = p.id
+         WHERE i.id = ?1",
+        rusqlite::params![invoice_id],
+        |row| {
+            Ok(InvoiceEmail {
+                invoice_number: row.get(0)?,
+                customer_name: row.get(1)?,
+                customer_email: row.get(2)?,
+                description: row.get(3)?,
+                amount_sat: row.get(4)?,
+                amount_fiat: row.get(5)?,
+                fiat_currency: row.get(6)?,
+                btc_address: row.get(7)?,
+                share_token: row.get(8)?,
+                due_at: row.get(9)?,
+                merchant_id: row.get(10)?,
+                merchant_name: row.get(11)?,
+                merchant_email: row.get(12)?,
+                fiat_denominated: row.get::<_, i32>(13).map(|v| v != 0)?,
+            })
+        },
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Invoice not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Satoshis as a BTC amount without trailing zeros, e.g. `0.0012345`.
+pub fn format_btc(sat: i64) -> String {
+    let btc = format!("{}.{:08}", sat / 100_000_000, sat % 100_000_000);
+    btc.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn render(config: &Config, invoice: &InvoiceEmail, kind: Kind, message: Option<&str>) -> (String, String) {
+    let merchant = escape(&invoice.merchant_name);
+    let reference = invoice.invoice_number.as_deref().filter(|n| !n.is_empty());
+    let subject = match (kind, reference) {
+        (Kind::Invoice, Some(number)) => format!("Invoice {number} from {}", invoice.merchant_name),
+        (Kind::Invoice, None) => format!("Invoice from {}", invoice.merchant_name),
+        (Kind::Reminder, Some(number)) => format!("Reminder: invoice {number} from {} is due soon", invoice.merchant_name),
+        (Kind::Reminder, None) => format!("Reminder: invoice from {} is due soon", invoice.merchant_name),
+    };
+
+    let greeting = match invoice.customer_name.as_deref().filter(|n| !n.is_empty()) {
+        Some(name) => format!("Hi {},", escape(name)),
+        None => "Hello,".to_string(),
+    };
+    let intro = match kind {
+        Kind::Invoice => format!("{merchant} has sent you an invoice payable in bitcoin."),
+        Kind::Reminder => format!("This is a reminder that your invoice from {merchant} has not been paid yet."),
+    };
+
+    let mut details = Vec::new();
+    if let Some(number) = reference {
+        details.push(format!("<strong>Invoice:</strong> {}", escape(number)));
+    }
+    if let Some(description) = invoice.description.as_deref().filter(|d| !d.is_empty()) {
+        details.push(format!("<strong>For:</strong> {}", escape(description)));
+    }
+    if let Some(fiat) = invoice.amount_fiat.filter(|_| invoice.fiat_denominated) {
+        // The BTC amount is quoted when the customer opens the payment page
+        details.push(format!(
+            "<strong>Amount:</strong> {fiat:.2} {}, payable in bitcoin at the rate on the payment page",
+            invoice.fiat_currency.to_uppercase()
+        ));
+    } else if invoice.amount_sat > 0 {
+        let fiat = invoice
+            .amount_fiat
+            .map(|fiat| format!(" (≈ {fiat:.2} {})", invoice.fiat_currency.to_uppercase()))
+            .unwrap_or_default();
+        details.push(format!(
+            "<strong>Amount:</strong> {} BTC ({} sats){fiat}",
+            format_btc(invoice.amount_sat),
+            invoice.amount_sat
+        ));
+    }
+    details.push(format!("<strong>Address:</strong> <code>{}</code>", escape(&invoice.btc_address)));
+    if let Some(due_at) = invoice.due_at.as_deref() {
+        details.push(format!("<strong>Due:</strong> {}", escape(due_at.get(..10).unwrap_or(due_at))));
+    }
+
+    let note = message
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            format!(
+                r#"<p style="white-space: pre-wrap; border-left: 3px solid #eee; padding-left: 12px; color: #555;">{}</p>"#,
+                escape(m.trim())
+            )
+        })
+        .unwrap_or_default();
+    let pay_url = format!("{}/pay/{}", config.app_url, invoice.share_token);
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <p>{greeting}</p>
+  <p>{intro}</p>
+  {note}
+  <p>{details}</p>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{pay_url}" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">View and Pay Invoice</a>
+  </p>
+  <p style="font-size: 14px; color: #666;">The payment page has a QR code for your wallet and shows when the payment is received.</p>
+  <hr style="border: none; border-top: 1px solid #eee; margin: 30px 0;" />
+  <p style="font-size: 12px; color: #999;">Questions about this invoice? Reply to this email to reach {merchant}.</p>
+</body>
+</html>"#,
+        details = details.join("<br />"),
+    );
+    (subject, html)
+}
+
+/// Email `invoice` to its customer, with replies going to the merchant.
+pub async fn send(config: &Config, invoice: &InvoiceEmail, kind: Kind, message: Option<&str>) -> AppResult<()> {
+    let to = invoice
+        .customer_email
+        .as_deref()
+        .filter(|e| !e.is_empty())
+        .ok_or_else(|| AppError::BadRequest("The invoice has no customer email".into()))?;
+    let (subject, html) = render(config, invoice, kind, message);
+    email::send_email_with_reply_to(config, to, Some(&invoice.merchant_email), &subject, &html).await
+}
+
+/// Background task that emails a reminder for unpaid invoices once they are within
+/// their `reminder_days_before` of `due_at`. Each invoice gets at most one reminder.
+pub async fn run_invoice_reminders(pool: DbPool, config: Config) {
+    tracing::info!(
+        "Invoice reminder task started (interval: {}s)",
+        config.intervals.invoice_reminder.as_secs()
+    );
+
+    loop {
+        tokio::time::sleep(config.intervals.invoice_reminder).await;
+
+        if let Err(e) = send_due_reminders(&pool, &config).await {
+            tracing::error!("Invoice reminders: {e}");
+        }
+    }
+}
+
+async fn send_due_reminders(pool: &DbPool, config: &Config) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let due: Vec<String> = {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM invoices
+             WHERE status = 'sent' AND reusable = 0 AND reminder_days_before IS NOT NULL
+               AND reminder_sent_at IS NULL AND customer_email IS NOT NULL AND due_at IS NOT NULL
+               AND share_enabled = 1
+               AND julianday(due_at) > julianday(?1)
+               AND julianday(due_at) - reminder_days_before <= julianday(?1)",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![now], |row| row.get(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    for invoice_id in due {
+        let invoice = {
+            let conn = pool.get()?;
+            // Claim the reminder first so a failed send is not retried every round
+            let claimed = conn.execute(
+                "UPDATE invoices SET reminder_sent_at = ?1 WHERE id = ?2 AND reminder_sent_at IS NULL",
+                rusqlite::params![now, invoice_id],
+            )?;
+            if claimed == 0 {
+                continue;
+            }
+            let invoice = load(&conn, &invoice_id)?;
+            if !quotas::consume_email(&conn, config, &invoice.merchant_id)? {
+                continue;
+            }
+            invoice
+        };
+
+        match send(config, &invoice, Kind::Reminder, None).await {
+            Ok(()) => tracing::info!("Sent payment reminder for invoice {invoice_id}"),
+            Err(e) => tracing::warn!("Failed to send payment reminder for invoice {invoice_id}: {e}"),
+        }
+    }
+    Ok(())
+}
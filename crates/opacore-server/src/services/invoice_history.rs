@@ -0,0 +1,118 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::AppResult;
+
+/// What happened to an invoice.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvoiceEvent {
+    Created,
+    /// Emailed to the customer; a draft becomes `sent`.
+    Sent,
+    /// The customer first opened the payment page.
+    Viewed,
+    PartiallyPaid,
+    /// A payment waiting for its confirmations.
+    PaymentDetected,
+    /// A detected payment dropped out of the mempool.
+    PaymentDropped,
+    Paid,
+    Expired,
+    /// Set by hand by the merchant.
+    StatusChanged,
+}
+
+impl InvoiceEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            InvoiceEvent::Created => "created",
+            InvoiceEvent::Sent => "sent",
+            InvoiceEvent::Viewed => "viewed",
+            InvoiceEvent::PartiallyPaid => "partially_paid",
+            InvoiceEvent::PaymentDetected => "payment_detected",
+            InvoiceEvent::PaymentDropped => "payment_dropped",
+            InvoiceEvent::Paid => "paid",
+            InvoiceEvent::Expired => "expired",
+            InvoiceEvent::StatusChanged => "status_changed",
+        }
+    }
+}
+
+/// Who caused an event.
+#[derive(Debug, Clone, Copy)]
+pub enum Actor<'a> {
+    /// A user with access to the invoice's portfolio.
+    Merchant(&'a str),
+    /// Whoever has the payment link.
+    Customer,
+    /// The payment checker and other background tasks.
+    System,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvoiceHistoryEntry {
+    pub id: i64,
+    pub invoice_id: String,
+    pub event: String,
+    /// Set, with `to_status`, when the event changed the invoice's status.
+    pub from_status: Option<String>,
+    pub to_status: Option<String>,
+    /// "merchant", "customer" or "system".
+    pub actor: String,
+    /// The merchant user; `None` for others, or once that user is deleted.
+    pub actor_user_id: Option<String>,
+    pub detail: Option<Value>,
+    pub created_at: String,
+}
+
+pub const HISTORY_COLS: &str = "id, invoice_id, event, from_status, to_status, actor, actor_user_id, detail, created_at";
+
+pub fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<InvoiceHistoryEntry> {
+    let detail: Option<String> = row.get(7)?;
+    Ok(InvoiceHistoryEntry {
+        id: row.get(0)?,
+        invoice_id: row.get(1)?,
+        event: row.get(2)?,
+        from_status: row.get(3)?,
+        to_status: row.get(4)?,
+        actor: row.get(5)?,
+        actor_user_id: row.get(6)?,
+        detail: detail.and_then(|d| serde_json::from_str(&d).ok()),
+        created_at: row.get(8)?,
+    })
+}
+
+/// Append an event. `transition` is the `(from, to)` status when it changed; a status
+/// "changing" to itself is recorded without one. Pass the connection (or SQLite
+/// transaction) that made the change.
+pub fn record(
+    conn: &rusqlite::Connection,
+    invoice_id: &str,
+    event: InvoiceEvent,
+    transition: Option<(&str, &str)>,
+    actor: Actor,
+    detail: Option<&Value>,
+) -> AppResult<()> {
+    let transition = transition.filter(|(from, to)| from != to);
+    let (actor, actor_user_id) = match actor {
+        Actor::Merchant(user_id) => ("merchant", Some(user_id)),
+        Actor::Customer => ("customer", None),
+        Actor::System => ("system", None),
+    };
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO invoice_events (invoice_id, event, from_status, to_status, actor, actor_user_id, detail, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            invoice_id,
+            event.name(),
+            transition.map(|(from, _)| from),
+            transition.map(|(_, to)| to),
+            actor,
+            actor_user_id,
+            detail.map(Value::to_string),
+            now
+        ],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::events::{self, DomainEvent};
+use crate::services::prices;
+use crate::services::transaction_audit::{self, AuditAction};
+
+/// Label put on every transaction booked from an invoice.
+const INVOICE_LABEL: &str = "invoice";
+
+/// Payments booked within this long of being seen are priced at the spot price; later
+/// ones are left to the price backfill, which uses the day's.
+const SPOT_PRICE_WINDOW_SECS: i64 = 600;
+
+struct PaidInvoice {
+    portfolio_id: String,
+    owner_id: String,
+    wallet_id: Option<String>,
+    btc_address: String,
+    customer_name: Option<String>,
+    fiat_currency: String,
+}
+
+/// Book a payment of `invoice_id`, seen at `seen_at`, as a `receive` transaction in its
+/// portfolio, with the customer as counterparty and the "invoice" label. An on-chain
+/// payment a wallet sync already recorded is linked to the invoice instead, so it isn't
+/// counted twice. Booking the same payment again does nothing.
+pub async fn book_payment(
+    pool: &DbPool,
+    config: &Config,
+    invoice_id: &str,
+    txid: Option<&str>,
+    amount_sat: i64,
+    seen_at: &str,
+) -> AppResult<()> {
+    let invoice = {
+        let conn = pool.get()?;
+        let invoice = conn.query_row(
+            "SELECT i.portfolio_id, p.user_id, i.wallet_id, i.btc_address, i.customer_name, i.fiat_currency
+             FROM invoices i JOIN portfolios p ON p.id = i.portfolio_id
+             WHERE i.id = ?1",
+            rusqlite::params![invoice_id],
+            |row| {
+                Ok(PaidInvoice {
+                    portfolio_id: row.get(0)?,
+                    owner_id: row.get(1)?,
+                    wallet_id: row.get(2)?,
+                    btc_address: row.get(3)?,
+                    customer_name: row.get(4)?,
+                    fiat_currency: row.get(5)?,
+                })
+            },
+        );
+        match invoice {
+            Ok(invoice) => invoice,
+            // Deleted since the payment was seen
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let seen = chrono::DateTime::parse_from_rfc3339(seen_at).map(|t| t.with_timezone(&chrono::Utc));
+    let fresh = seen.is_ok_and(|t| (chrono::Utc::now() - t).num_seconds() <= SPOT_PRICE_WINDOW_SECS);
+    let (price_usd, fiat_price) = if fresh {
+        let price_usd = prices::fetch_current_price(&config.coingecko_api_url, "usd")
+            .await
+            .inspect_err(|e| tracing::warn!("Invoice {invoice_id} booked unpriced: {e}"))
+            .ok();
+        let fiat_price = match invoice.fiat_currency.as_str() {
+            "usd" => price_usd,
+            currency => prices::fetch_current_price(&config.coingecko_api_url, currency).await.ok(),
+        };
+        (price_usd, fiat_price)
+    } else {
+        (None, None)
+    };
+    let fiat_amount = fiat_price.map(|price| amount_sat as f64 / 100_000_000.0 * price);
+
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let booked: bool = db_tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM transactions WHERE invoice_id = ?1 AND txid IS ?2)",
+        rusqlite::params![invoice_id, txid],
+        |row| row.get(0),
+    )?;
+    if booked {
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let synced: Option<String> = match txid {
+        Some(txid) => db_tx
+            .query_row(
+                "SELECT id FROM transactions
+                 WHERE portfolio_id = ?1 AND txid = ?2 AND tx_type = 'receive' AND parent_id IS NULL
+                   AND invoice_id IS NULL
+                 ORDER BY created_at LIMIT 1",
+                rusqlite::params![invoice.portfolio_id, txid],
+                |row| row.get(0),
+            )
+            .map(Some)
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                e => Err(e),
+            })?,
+        None => None,
+    };
+
+    let transaction_id = match synced {
+        Some(id) => {
+            let before = transaction_audit::snapshot(&db_tx, &id)?.unwrap_or_default();
+            db_tx.execute(
+                "UPDATE transactions SET invoice_id = ?1, counterparty = COALESCE(counterparty, ?2), updated_at = ?3
+                 WHERE id = ?4",
+                rusqlite::params![invoice_id, invoice.customer_name, now, id],
+            )?;
+            events::record(
+                &db_tx,
+                &invoice.portfolio_id,
+                &DomainEvent::TransactionUpdated { transaction_id: id.clone() },
+            )?;
+            transaction_audit::record_updated(&db_tx, &invoice.portfolio_id, &id, None, &before)?;
+            id
+        }
+        None => {
+            // Kept in the wallet that owns the address, where a later sync finds it by txid
+            let wallet_id = match (txid, invoice.wallet_id) {
+                (Some(_), Some(wallet_id)) => Some(wallet_id),
+                (Some(_), None) => db_tx
+                    .query_row(
+                        "SELECT a.wallet_id FROM wallet_addresses a JOIN wallets w ON w.id = a.wallet_id
+                         WHERE w.portfolio_id = ?1 AND a.address = ?2 LIMIT 1",
+                        rusqlite::params![invoice.portfolio_id, invoice.btc_address],
+                        |row| row.get(0),
+                    )
+                    .map(Some)
+                    .or_else(|e| match e {
+                        rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                        e => Err(e),
+                    })?,
+                // Lightning funds sit in the node, outside any wallet here
+                (None, _) => None,
+            };
+            let id = Uuid::new_v4().to_string();
+            db_tx.execute(
+                "INSERT INTO transactions (id, portfolio_id, wallet_id, tx_type, amount_sat, price_usd, fiat_amount, fiat_currency, txid, source, counterparty, invoice_id, transacted_at, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, 'receive', ?4, ?5, ?6, ?7, ?8, 'invoice', ?9, ?10, ?11, ?12, ?13)",
+                rusqlite::params![
+                    id, invoice.portfolio_id, wallet_id, amount_sat, price_usd, fiat_amount,
+                    invoice.fiat_currency, txid, invoice.customer_name, invoice_id, seen_at, now, now
+                ],
+            )?;
+            events::record(
+                &db_tx,
+                &invoice.portfolio_id,
+                &DomainEvent::TransactionCreated { transaction_id: id.clone(), wallet_id },
+            )?;
+            transaction_audit::record_created(&db_tx, &invoice.portfolio_id, &id, None)?;
+            id
+        }
+    };
+
+    // Labels belong to the portfolio's owner
+    db_tx.execute(
+        "INSERT OR IGNORE INTO labels (id, user_id, name, created_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![Uuid::new_v4().to_string(), invoice.owner_id, INVOICE_LABEL, now],
+    )?;
+    let label_id: String = db_tx.query_row(
+        "SELECT id FROM labels WHERE user_id = ?1 AND name = ?2",
+        rusqlite::params![invoice.owner_id, INVOICE_LABEL],
+        |row| row.get(0),
+    )?;
+    let before = label_ids(&db_tx, &transaction_id)?;
+    db_tx.execute(
+        "INSERT OR IGNORE INTO transaction_labels (transaction_id, label_id) VALUES (?1, ?2)",
+        rusqlite::params![transaction_id, label_id],
+    )?;
+    let after = label_ids(&db_tx, &transaction_id)?;
+    if before != after {
+        transaction_audit::record(
+            &db_tx,
+            &invoice.portfolio_id,
+            &transaction_id,
+            None,
+            AuditAction::Labels,
+            &serde_json::json!({ "from": before, "to": after }),
+        )?;
+    }
+    db_tx.commit()?;
+
+    tracing::info!("Invoice {invoice_id} payment booked as transaction {transaction_id}");
+    Ok(())
+}
+
+fn label_ids(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT label_id FROM transaction_labels WHERE transaction_id = ?1 ORDER BY label_id")?;
+    let ids = stmt
+        .query_map(rusqlite::params![transaction_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
@@ -0,0 +1,472 @@
+use std::io::{BufWriter, Write};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::email;
+use crate::services::export::{self, JsonArrayWriter};
+use crate::services::prices;
+use crate::services::quotas;
+use crate::services::zip::ZipWriter;
+
+/// KeyRing purpose for download links, so a session cookie can't be replayed as one.
+const DOWNLOAD_PURPOSE: &str = "download";
+
+/// Work too slow to hold a request open for. The client gets the job back at once and
+/// is emailed when it finishes; files it produced are fetched through a signed link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobKind {
+    AccountExport,
+    PriceBackfill,
+}
+
+impl JobKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            JobKind::AccountExport => "account_export",
+            JobKind::PriceBackfill => "price_backfill",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            JobKind::AccountExport => "account export",
+            JobKind::PriceBackfill => "price backfill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub user_id: String,
+    pub kind: String,
+    pub status: String,
+    /// Summary of what the job did, once completed.
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub file_path: Option<String>,
+    /// When the download link stops working and the file is deleted.
+    pub expires_at: Option<String>,
+    /// Signed link to the job's file while it is still available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+}
+
+pub const JOB_COLS: &str = "id, user_id, kind, status, result, error, file_path, expires_at, created_at, updated_at, finished_at";
+
+pub fn row_to_job(row: &rusqlite::Row) -> rusqlite::Result<Job> {
+    let result: Option<String> = row.get(4)?;
+    Ok(Job {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        kind: row.get(2)?,
+        status: row.get(3)?,
+        result: result.and_then(|r| serde_json::from_str(&r).ok()),
+        error: row.get(5)?,
+        file_path: row.get(6)?,
+        expires_at: row.get(7)?,
+        download_url: None,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+        finished_at: row.get(10)?,
+    })
+}
+
+impl Job {
+    /// Fill in `download_url` if the job left a file that hasn't expired yet.
+    pub fn with_download_url(mut self, config: &Config) -> Self {
+        self.download_url = download_url(config, &self);
+        self
+    }
+}
+
+pub fn load_job(conn: &rusqlite::Connection, user_id: &str, job_id: &str) -> AppResult<Job> {
+    conn.query_row(
+        &format!("SELECT {JOB_COLS} FROM jobs WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![job_id, user_id],
+        row_to_job,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Job not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+pub fn start_job(conn: &rusqlite::Connection, user_id: &str, kind: JobKind) -> AppResult<Job> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO jobs (id, user_id, kind, status, created_at, updated_at) VALUES (?1, ?2, ?3, 'running', ?4, ?4)",
+        rusqlite::params![id, user_id, kind.name(), now],
+    )?;
+    load_job(conn, user_id, &id)
+}
+
+fn complete_job(
+    conn: &rusqlite::Connection,
+    job_id: &str,
+    result: &serde_json::Value,
+    file: Option<(&str, &str)>,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let (file_path, expires_at) = file.unzip();
+    conn.execute(
+        "UPDATE jobs SET status = 'completed', result = ?1, file_path = ?2, expires_at = ?3, error = NULL,
+         updated_at = ?4, finished_at = ?4 WHERE id = ?5",
+        rusqlite::params![result.to_string(), file_path, expires_at, now, job_id],
+    )?;
+    Ok(())
+}
+
+fn fail_job(conn: &rusqlite::Connection, job_id: &str, error: &AppError) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE jobs SET status = 'failed', error = ?1, updated_at = ?2, finished_at = ?2 WHERE id = ?3",
+        rusqlite::params![error.to_string(), now, job_id],
+    )?;
+    Ok(())
+}
+
+/// Jobs still marked running at startup were cut off by a restart or crash. Unlike wallet
+/// scans they keep no checkpoint, so the user has to start them again.
+pub fn mark_interrupted_jobs(pool: &DbPool) {
+    let result = pool.get().map_err(AppError::from).and_then(|conn| {
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        Ok(conn.execute(
+            "UPDATE jobs SET status = 'interrupted', updated_at = ?1, finished_at = ?1 WHERE status = 'running'",
+            rusqlite::params![now],
+        )?)
+    });
+    match result {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Marked {n} unfinished background job(s) as interrupted"),
+        Err(e) => tracing::error!("Failed to mark interrupted jobs: {e}"),
+    }
+}
+
+// ── Download links ──
+
+/// `{app_url}/api/v1/downloads/{token}`, where the token carries the job id and the
+/// link's expiry under the server's signature.
+fn download_url(config: &Config, job: &Job) -> Option<String> {
+    job.file_path.as_ref()?;
+    let expires = chrono::DateTime::parse_from_rfc3339(job.expires_at.as_deref()?).ok()?;
+    if expires <= chrono::Utc::now() {
+        return None;
+    }
+    let token = config
+        .session_keys
+        .sign(DOWNLOAD_PURPOSE, &format!("{}:{}", job.id, expires.timestamp()));
+    Some(format!("{}/api/v1/downloads/{token}", config.app_url))
+}
+
+/// The job id a download token was signed for, if the signature holds and the link
+/// hasn't expired.
+pub fn verify_download(config: &Config, token: &str) -> Option<String> {
+    let value = config.session_keys.verify(DOWNLOAD_PURPOSE, token)?;
+    let (job_id, expires) = value.rsplit_once(':')?;
+    if expires.parse::<i64>().ok()? <= chrono::Utc::now().timestamp() {
+        return None;
+    }
+    Some(job_id.to_string())
+}
+
+/// Path of a completed job's file, if it is still being kept.
+pub fn download_file(conn: &rusqlite::Connection, job_id: &str) -> AppResult<String> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.query_row(
+        "SELECT file_path FROM jobs
+         WHERE id = ?1 AND status = 'completed' AND file_path IS NOT NULL AND expires_at > ?2",
+        rusqlite::params![job_id, now],
+        |row| row.get(0),
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Download has expired".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Delete files whose download links have expired.
+pub fn prune_expired_downloads(pool: &DbPool) {
+    let result = pool.get().map_err(AppError::from).and_then(|conn| {
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let expired: Vec<(String, String)> = conn
+            .prepare("SELECT id, file_path FROM jobs WHERE file_path IS NOT NULL AND expires_at <= ?1")?
+            .query_map(rusqlite::params![now], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (id, path) in &expired {
+            remove_file(path);
+            conn.execute("UPDATE jobs SET file_path = NULL WHERE id = ?1", rusqlite::params![id])?;
+        }
+        Ok(expired.len())
+    });
+    match result {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Deleted {n} expired download(s)"),
+        Err(e) => tracing::error!("Failed to prune expired downloads: {e}"),
+    }
+}
+
+/// Delete files jobs left behind, once their rows are gone.
+pub fn remove_files(paths: &[String]) {
+    for path in paths {
+        remove_file(path);
+    }
+}
+
+fn remove_file(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::warn!("Failed to delete {path}: {e}");
+        }
+    }
+}
+
+// ── Running jobs ──
+
+/// Run a started job to completion in the background, then email the user.
+pub fn spawn(pool: DbPool, config: Config, job: Job, kind: JobKind, params: JobParams) {
+    tokio::spawn(async move {
+        let outcome = match kind {
+            JobKind::AccountExport => run_account_export(&pool, &config, &job).await,
+            JobKind::PriceBackfill => run_price_backfill(&pool, &config, &params).await,
+        };
+
+        let recorded = pool.get().map_err(AppError::from).and_then(|conn| match &outcome {
+            Ok((result, file)) => {
+                let file = file.as_ref().map(|(path, expires)| (path.as_str(), expires.as_str()));
+                complete_job(&conn, &job.id, result, file)
+            }
+            Err(e) => fail_job(&conn, &job.id, e),
+        });
+        if let Err(e) = recorded {
+            tracing::error!("Failed to record outcome of job {}: {e}", job.id);
+            return;
+        }
+        match &outcome {
+            Ok(_) => tracing::info!("Job {} ({}) completed", job.id, kind.name()),
+            Err(e) => tracing::warn!("Job {} ({}) failed: {e}", job.id, kind.name()),
+        }
+
+        notify(&pool, &config, &job.id, kind).await;
+    });
+}
+
+/// Options for a job, beyond who it runs for.
+#[derive(Debug, Default)]
+pub struct JobParams {
+    pub currency: Option<String>,
+}
+
+type JobOutcome = AppResult<(serde_json::Value, Option<(String, String)>)>;
+
+async fn run_price_backfill(pool: &DbPool, config: &Config, params: &JobParams) -> JobOutcome {
+    let currency = params.currency.as_deref().unwrap_or("usd");
+    let fetched = prices::backfill_transaction_prices(pool, &config.coingecko_api_url, currency).await?;
+    Ok((serde_json::json!({ "fetched": fetched }), None))
+}
+
+/// Tables in an account export, with the query selecting the user's rows. Exchange API
+/// credentials, sessions and billing records are deliberately left out.
+const EXPORT_TABLES: [(&str, &str); 22] = [
+    ("portfolios", "SELECT * FROM portfolios WHERE user_id = ?1 ORDER BY created_at"),
+    ("portfolio_branding", "SELECT b.* FROM portfolio_branding b JOIN portfolios p ON p.id = b.portfolio_id WHERE p.user_id = ?1"),
+    ("wallets", "SELECT w.* FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE p.user_id = ?1 ORDER BY w.created_at"),
+    ("utxo_metadata", "SELECT m.* FROM utxo_metadata m JOIN wallets w ON w.id = m.wallet_id JOIN portfolios p ON p.id = w.portfolio_id WHERE p.user_id = ?1"),
+    ("transactions", "SELECT t.* FROM transactions t JOIN portfolios p ON p.id = t.portfolio_id WHERE p.user_id = ?1 ORDER BY t.transacted_at"),
+    ("transaction_audit", "SELECT a.* FROM transaction_audit a JOIN portfolios p ON p.id = a.portfolio_id WHERE p.user_id = ?1 ORDER BY a.id"),
+    ("transaction_attachments", "SELECT ta.* FROM transaction_attachments ta JOIN transactions t ON t.id = ta.transaction_id JOIN portfolios p ON p.id = t.portfolio_id WHERE p.user_id = ?1 ORDER BY ta.created_at"),
+    ("recurring_transactions", "SELECT r.* FROM recurring_transactions r JOIN portfolios p ON p.id = r.portfolio_id WHERE p.user_id = ?1 ORDER BY r.created_at"),
+    ("labels", "SELECT * FROM labels WHERE user_id = ?1 ORDER BY name"),
+    ("transaction_labels", "SELECT tl.* FROM transaction_labels tl JOIN labels l ON l.id = tl.label_id WHERE l.user_id = ?1"),
+    ("invoices", "SELECT i.* FROM invoices i JOIN portfolios p ON p.id = i.portfolio_id WHERE p.user_id = ?1 ORDER BY i.created_at"),
+    ("invoice_notes", "SELECT n.* FROM invoice_notes n JOIN invoices i ON i.id = n.invoice_id JOIN portfolios p ON p.id = i.portfolio_id WHERE p.user_id = ?1 ORDER BY n.created_at"),
+    ("invoice_events", "SELECT e.* FROM invoice_events e JOIN invoices i ON i.id = e.invoice_id JOIN portfolios p ON p.id = i.portfolio_id WHERE p.user_id = ?1 ORDER BY e.id"),
+    ("customers", "SELECT c.* FROM customers c JOIN portfolios p ON p.id = c.portfolio_id WHERE p.user_id = ?1 ORDER BY c.created_at"),
+    ("alerts", "SELECT * FROM alerts WHERE user_id = ?1 ORDER BY created_at"),
+    ("watched_addresses", "SELECT * FROM watched_addresses WHERE user_id = ?1 ORDER BY created_at"),
+    ("import_templates", "SELECT * FROM import_templates WHERE user_id = ?1 ORDER BY name"),
+    ("portfolio_templates", "SELECT * FROM portfolio_templates WHERE user_id = ?1 ORDER BY name"),
+    ("tax_settings", "SELECT * FROM tax_settings WHERE user_id = ?1"),
+    ("disposal_lot_assignments", "SELECT a.* FROM disposal_lot_assignments a JOIN portfolios p ON p.id = a.portfolio_id WHERE p.user_id = ?1 ORDER BY a.created_at"),
+    ("tax_year_locks", "SELECT l.* FROM tax_year_locks l JOIN portfolios p ON p.id = l.portfolio_id WHERE p.user_id = ?1 ORDER BY l.year"),
+    // Only the days and currencies the user's transactions were valued in
+    ("price_history", "SELECT ph.* FROM price_history ph JOIN (SELECT DISTINCT t.fiat_currency AS currency, substr(t.transacted_at, 1, 10) AS date FROM transactions t JOIN portfolios p ON p.id = t.portfolio_id WHERE p.user_id = ?1) d ON d.currency = ph.currency AND d.date = ph.date ORDER BY ph.currency, ph.date"),
+];
+
+/// Tables from `EXPORT_TABLES` also written as CSV, for opening in a spreadsheet.
+const CSV_TABLES: [&str; 6] = ["portfolios", "wallets", "transactions", "labels", "invoices", "price_history"];
+
+/// Write everything the user owns to a ZIP archive, kept for `DOWNLOAD_LINK_TTL_HOURS`:
+/// `account.json` holds every table as `table: [rows]`, and the main ones are repeated
+/// as CSV files alongside it.
+async fn run_account_export(pool: &DbPool, config: &Config, job: &Job) -> JobOutcome {
+    prune_expired_downloads(pool);
+    std::fs::create_dir_all(&config.exports_dir)
+        .map_err(|e| AppError::Internal(format!("Failed to create exports directory: {e}")))?;
+    let path = format!("{}/{}.zip", config.exports_dir.trim_end_matches('/'), job.id);
+
+    let (pool, user_id, target) = (pool.clone(), job.user_id.clone(), path.clone());
+    let counts = tokio::task::spawn_blocking(move || write_account_export(&pool, &user_id, &target))
+        .await
+        .map_err(|e| AppError::Internal(format!("Export task failed: {e}")))?
+        .inspect_err(|_| remove_file(&path))?;
+
+    let expires_at = (chrono::Utc::now() + chrono::Duration::hours(config.download_link_ttl_hours as i64))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    Ok((serde_json::Value::Object(counts), Some((path, expires_at))))
+}
+
+fn write_account_export(
+    pool: &DbPool,
+    user_id: &str,
+    path: &str,
+) -> AppResult<serde_json::Map<String, serde_json::Value>> {
+    let conn = pool.get()?;
+    let partial = format!("{path}.part");
+    let file = std::fs::File::create(&partial).map_err(export::write_failed)?;
+    let now = chrono::Utc::now();
+    let mut zip = ZipWriter::new(BufWriter::new(file), now);
+
+    let user: serde_json::Value = conn.query_row(
+        "SELECT id, email, name, default_currency, created_at FROM users WHERE id = ?1",
+        rusqlite::params![user_id],
+        |row| {
+            Ok(serde_json::json!({
+                "id": row.get::<_, String>(0)?,
+                "email": row.get::<_, String>(1)?,
+                "name": row.get::<_, String>(2)?,
+                "default_currency": row.get::<_, String>(3)?,
+                "created_at": row.get::<_, String>(4)?,
+            }))
+        },
+    )?;
+    let exported_at = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    zip.start_file("account.json").map_err(export::write_failed)?;
+    write!(zip, "{{\"exported_at\":\"{exported_at}\",\"user\":{user}").map_err(export::write_failed)?;
+
+    let mut counts = serde_json::Map::new();
+    for (table, sql) in EXPORT_TABLES {
+        write!(zip, ",\"{table}\":").map_err(export::write_failed)?;
+        let mut stmt = conn.prepare(sql)?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        let mut rows = stmt.query(rusqlite::params![user_id])?;
+        let mut json = JsonArrayWriter::new(&mut zip).map_err(export::write_failed)?;
+        let mut count = 0u64;
+        while let Some(row) = rows.next()? {
+            let mut object = serde_json::Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), column_value(row.get_ref(i)?));
+            }
+            json.write(&object).map_err(export::write_failed)?;
+            count += 1;
+        }
+        json.finish().map_err(export::write_failed)?;
+        counts.insert(table.to_string(), count.into());
+    }
+    zip.write_all(b"}").map_err(export::write_failed)?;
+
+    for (table, sql) in EXPORT_TABLES.into_iter().filter(|(table, _)| CSV_TABLES.contains(table)) {
+        zip.start_file(&format!("{table}.csv")).map_err(export::write_failed)?;
+        let mut stmt = conn.prepare(sql)?;
+        let mut csv = csv::Writer::from_writer(&mut zip);
+        csv.write_record(stmt.column_names()).map_err(export::write_failed)?;
+        let column_count = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::params![user_id])?;
+        while let Some(row) = rows.next()? {
+            let record = (0..column_count)
+                .map(|i| Ok(column_text(row.get_ref(i)?)))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            csv.write_record(&record).map_err(export::write_failed)?;
+        }
+        csv.flush().map_err(export::write_failed)?;
+    }
+
+    zip.finish()
+        .map_err(export::write_failed)?
+        .into_inner()
+        .map_err(export::write_failed)?
+        .sync_all()
+        .map_err(export::write_failed)?;
+    std::fs::rename(&partial, path).map_err(export::write_failed)?;
+    Ok(counts)
+}
+
+pub fn column_value(value: rusqlite::types::ValueRef) -> serde_json::Value {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => serde_json::Number::from_f64(f).map_or(serde_json::Value::Null, Into::into),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into_owned().into(),
+        ValueRef::Blob(b) => hex::encode(b).into(),
+    }
+}
+
+/// A CSV cell: empty for NULL, otherwise as `column_value` would give it.
+fn column_text(value: rusqlite::types::ValueRef) -> String {
+    match column_value(value) {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+// ── Notification ──
+
+async fn notify(pool: &DbPool, config: &Config, job_id: &str, kind: JobKind) {
+    let recipient = pool.get().map_err(AppError::from).and_then(|conn| {
+        let (email, name): (String, String) = conn.query_row(
+            "SELECT u.email, u.name FROM jobs j JOIN users u ON u.id = j.user_id WHERE j.id = ?1",
+            rusqlite::params![job_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let job = conn.query_row(
+            &format!("SELECT {JOB_COLS} FROM jobs WHERE id = ?1"),
+            rusqlite::params![job_id],
+            row_to_job,
+        )?;
+        let allowed = quotas::consume_email(&conn, config, &job.user_id)?;
+        Ok((email, name, job, allowed))
+    });
+    let (to, name, job, allowed) = match recipient {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::warn!("Job {job_id}: could not load recipient for notification: {e}");
+            return;
+        }
+    };
+    if !allowed {
+        return;
+    }
+
+    let job = job.with_download_url(config);
+    let subject = match job.status.as_str() {
+        "completed" => format!("Your Opacore {} is ready", kind.describe()),
+        _ => format!("Your Opacore {} failed", kind.describe()),
+    };
+    let summary = match (kind, &job.result, &job.error) {
+        (_, _, Some(error)) => format!("It stopped with an error: {error}. You can start it again from the app."),
+        (JobKind::AccountExport, _, _) => format!(
+            "Everything in your account is in one ZIP archive: a JSON file with all of it, plus CSV files of your portfolios, wallets, transactions, labels, invoices and the prices they were valued at. The link works until {}.",
+            job.expires_at.as_deref().unwrap_or("it expires")
+        ),
+        (JobKind::PriceBackfill, Some(result), _) => format!(
+            "{} missing prices were fetched and applied to your transactions.",
+            result.get("fetched").and_then(|f| f.as_u64()).unwrap_or(0)
+        ),
+        (JobKind::PriceBackfill, None, _) => "Missing prices were fetched and applied to your transactions.".into(),
+    };
+
+    if let Err(e) = email::send_job_email(config, &to, &name, &subject, &summary, job.download_url.as_deref()).await {
+        tracing::warn!("Job {job_id}: notification email failed: {e}");
+    }
+}
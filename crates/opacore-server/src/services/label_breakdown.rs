@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::transaction_splits::COUNTED;
+
+#[derive(Debug, Serialize)]
+pub struct LabelBreakdown {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub groups: Vec<LabelGroup>,
+}
+
+/// Transactions with one label and type. A transaction with several labels counts
+/// towards each of them; one with none is in the groups with no `label_id`.
+#[derive(Debug, Serialize)]
+pub struct LabelGroup {
+    pub label_id: Option<String>,
+    pub label: Option<String>,
+    pub tx_type: String,
+    pub count: i64,
+    pub amount_sat: i64,
+    pub fee_sat: i64,
+    /// Fiat value per currency: the recorded fiat amount, or the sats at the recorded
+    /// USD price.
+    pub fiat: BTreeMap<String, f64>,
+    /// Transactions with neither, left out of `fiat`.
+    pub unpriced: i64,
+}
+
+/// Sats and fiat value of the portfolio's transactions from `start` to `end` (inclusive,
+/// by the day they happened), by label and type. Ordered by label name, unlabelled last.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn label_breakdown(
+    pool: &DbPool,
+    portfolio_id: &str,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+) -> AppResult<LabelBreakdown> {
+    let conn = pool.get()?;
+    let (start, end) = (start.map(|d| d.to_string()), end.map(|d| d.to_string()));
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT l.id, l.name, transactions.tx_type,
+                CASE WHEN transactions.fiat_amount IS NOT NULL THEN transactions.fiat_currency ELSE 'usd' END,
+                COUNT(*), SUM(transactions.amount_sat), SUM(COALESCE(transactions.fee_sat, 0)),
+                SUM(COALESCE(transactions.fiat_amount, transactions.amount_sat / 1e8 * transactions.price_usd)),
+                SUM(transactions.fiat_amount IS NULL AND transactions.price_usd IS NULL)
+         FROM transactions
+         LEFT JOIN transaction_labels tl ON tl.transaction_id = transactions.id
+         LEFT JOIN labels l ON l.id = tl.label_id
+         WHERE transactions.portfolio_id = ?1 AND {COUNTED}
+           AND (?2 IS NULL OR substr(transactions.transacted_at, 1, 10) >= ?2)
+           AND (?3 IS NULL OR substr(transactions.transacted_at, 1, 10) <= ?3)
+         GROUP BY 1, 2, 3, 4
+         ORDER BY l.name IS NULL, l.name, l.id, transactions.tx_type"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![portfolio_id, start, end], |row| {
+        Ok((
+            row.get::<_, Option<String>>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, i64>(4)?,
+            row.get::<_, i64>(5)?,
+            row.get::<_, i64>(6)?,
+            row.get::<_, Option<f64>>(7)?,
+            row.get::<_, i64>(8)?,
+        ))
+    })?;
+
+    // One row per fiat currency: fold them into their group
+    let mut groups: Vec<LabelGroup> = Vec::new();
+    for row in rows {
+        let (label_id, label, tx_type, currency, count, amount_sat, fee_sat, fiat, unpriced) = row?;
+        let group = match groups.last_mut() {
+            Some(last) if last.label_id == label_id && last.tx_type == tx_type => last,
+            _ => {
+                groups.push(LabelGroup {
+                    label_id,
+                    label,
+                    tx_type,
+                    count: 0,
+                    amount_sat: 0,
+                    fee_sat: 0,
+                    fiat: BTreeMap::new(),
+                    unpriced: 0,
+                });
+                groups.last_mut().expect("just pushed")
+            }
+        };
+        group.count += count;
+        group.amount_sat += amount_sat;
+        group.fee_sat += fee_sat;
+        group.unpriced += unpriced;
+        if let Some(fiat) = fiat {
+            group.fiat.insert(currency, (fiat * 100.0).round() / 100.0);
+        }
+    }
+
+    Ok(LabelBreakdown { start, end, groups })
+}
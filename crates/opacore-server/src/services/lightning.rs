@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use base64::Engine;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+use crate::config::{LightningBackend, LightningConfig};
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::{http, invoice_checker};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Longest line accepted from a settlement stream, to bound memory on a misbehaving node.
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// A BOLT11 issued by the node for an invoice.
+pub struct IssuedInvoice {
+    pub bolt11: String,
+    /// Hex, as the backends report settlements.
+    pub payment_hash: String,
+    pub expires_at: String,
+}
+
+/// HTTP client for the configured node. Settlement streams stay open indefinitely, so
+/// they only get a connect timeout and TCP keepalives.
+fn client(config: &LightningConfig, streaming: bool) -> AppResult<Client> {
+    let mut builder = Client::builder()
+        .user_agent("opacore/0.1")
+        .connect_timeout(Duration::from_secs(5));
+    builder = if streaming {
+        builder.tcp_keepalive(Duration::from_secs(60))
+    } else {
+        builder.timeout(http::UPSTREAM_TIMEOUT)
+    };
+    if let Some(pem) = &config.tls_cert {
+        let cert = reqwest::Certificate::from_pem(pem)
+            .map_err(|e| AppError::Internal(format!("Invalid LIGHTNING_TLS_CERT_PATH certificate: {e}")))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    builder
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))
+}
+
+fn authorize(config: &LightningConfig, request: RequestBuilder) -> RequestBuilder {
+    match &config.backend {
+        LightningBackend::Lnd { macaroon_hex } => request.header("Grpc-Metadata-macaroon", macaroon_hex),
+        LightningBackend::Cln { rune } => request.header("Rune", rune),
+        LightningBackend::Lnbits { api_key } => request.header("X-Api-Key", api_key),
+    }
+}
+
+async fn send(config: &LightningConfig, request: RequestBuilder) -> AppResult<reqwest::Response> {
+    let resp = authorize(config, request)
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("lightning", "Lightning node request failed", e))?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(AppError::Internal(format!(
+            "Lightning node returned {status}: {}",
+            body.chars().take(500).collect::<String>()
+        )));
+    }
+    Ok(resp)
+}
+
+async fn call(config: &LightningConfig, request: RequestBuilder) -> AppResult<Value> {
+    send(config, request)
+        .await?
+        .json()
+        .await
+        .map_err(|e| http::upstream_error("lightning", "Lightning node response parse failed", e))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> AppResult<&'a str> {
+    value
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| AppError::Internal(format!("Lightning node response has no {name}")))
+}
+
+/// LND encodes hashes as base64 in JSON; everything else here uses hex.
+fn lnd_hash(value: &Value) -> Option<String> {
+    let encoded = value.get("r_hash")?.as_str()?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .or_else(|_| base64::engine::general_purpose::URL_SAFE.decode(encoded))
+        .ok()?;
+    Some(hex::encode(bytes))
+}
+
+/// A whole-satoshi amount from a millisatoshi field, which CLN may give as `"1000msat"`.
+fn msat_to_sat(value: Option<&Value>) -> Option<i64> {
+    let msat = match value? {
+        Value::Number(n) => n.as_i64()?,
+        Value::String(s) => s.trim_end_matches("msat").parse().ok()?,
+        _ => return None,
+    };
+    Some(msat.abs() / 1000)
+}
+
+/// Issue a BOLT11 for `amount_sat`, payable for `expiry_secs`.
+pub async fn create_invoice(
+    config: &LightningConfig,
+    amount_sat: i64,
+    memo: &str,
+    expiry_secs: u64,
+) -> AppResult<IssuedInvoice> {
+    let http = client(config, false)?;
+    let (bolt11, payment_hash) = match &config.backend {
+        LightningBackend::Lnd { .. } => {
+            let body = json!({ "value": amount_sat.to_string(), "memo": memo, "expiry": expiry_secs.to_string() });
+            let resp = call(config, http.post(format!("{}/v1/invoices", config.url)).json(&body)).await?;
+            let hash = lnd_hash(&resp).ok_or_else(|| AppError::Internal("Lightning node response has no r_hash".into()))?;
+            (field(&resp, "payment_request")?.to_string(), hash)
+        }
+        LightningBackend::Cln { .. } => {
+            let body = json!({
+                "amount_msat": amount_sat * 1000,
+                "label": format!("opacore-{}", uuid::Uuid::new_v4()),
+                "description": memo,
+                "expiry": expiry_secs,
+            });
+            let resp = call(config, http.post(format!("{}/v1/invoice", config.url)).json(&body)).await?;
+            (field(&resp, "bolt11")?.to_string(), field(&resp, "payment_hash")?.to_string())
+        }
+        LightningBackend::Lnbits { .. } => {
+            let body = json!({ "out": false, "amount": amount_sat, "memo": memo, "expiry": expiry_secs });
+            let resp = call(config, http.post(format!("{}/api/v1/payments", config.url)).json(&body)).await?;
+            // Newer LNbits calls it bolt11, older versions payment_request
+            let bolt11 = field(&resp, "bolt11").or_else(|_| field(&resp, "payment_request"))?;
+            (bolt11.to_string(), field(&resp, "payment_hash")?.to_string())
+        }
+    };
+
+    let expires_at = (chrono::Utc::now() + chrono::Duration::seconds(expiry_secs as i64))
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    Ok(IssuedInvoice { bolt11, payment_hash: payment_hash.to_lowercase(), expires_at })
+}
+
+/// The amount received for `payment_hash`, or `None` while it is unpaid.
+pub async fn settled_amount(config: &LightningConfig, payment_hash: &str) -> AppResult<Option<i64>> {
+    let http = client(config, false)?;
+    match &config.backend {
+        LightningBackend::Lnd { .. } => {
+            let resp = call(config, http.get(format!("{}/v1/invoice/{payment_hash}", config.url))).await?;
+            if resp.get("state").and_then(Value::as_str) != Some("SETTLED") {
+                return Ok(None);
+            }
+            Ok(Some(lnd_amount(&resp)))
+        }
+        LightningBackend::Cln { .. } => {
+            let body = json!({ "payment_hash": payment_hash });
+            let resp = call(config, http.post(format!("{}/v1/listinvoices", config.url)).json(&body)).await?;
+            let invoice = resp.get("invoices").and_then(|i| i.get(0));
+            match invoice {
+                Some(invoice) if invoice.get("status").and_then(Value::as_str) == Some("paid") => {
+                    Ok(Some(msat_to_sat(invoice.get("amount_received_msat")).unwrap_or(0)))
+                }
+                _ => Ok(None),
+            }
+        }
+        LightningBackend::Lnbits { .. } => {
+            let resp = call(config, http.get(format!("{}/api/v1/payments/{payment_hash}", config.url))).await?;
+            if resp.get("paid").and_then(Value::as_bool) != Some(true) {
+                return Ok(None);
+            }
+            Ok(Some(msat_to_sat(resp.get("details").and_then(|d| d.get("amount"))).unwrap_or(0)))
+        }
+    }
+}
+
+/// LND reports int64 fields as strings.
+fn lnd_amount(invoice: &Value) -> i64 {
+    match invoice.get("amt_paid_sat") {
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        Some(Value::Number(n)) => n.as_i64().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Background task: follows the node's settled-invoice stream (LND's invoice subscription,
+/// CLN's `waitanyinvoice`, LNbits' payment events) and marks invoices paid as soon as
+/// their BOLT11 settles. Reconnects with exponential backoff; settlements missed while
+/// disconnected are caught by the invoice checker's polling.
+pub async fn run_settlement_watch(pool: DbPool, config: LightningConfig) {
+    tracing::info!("Lightning settlement watch started ({})", config.url);
+    let mut backoff = Duration::from_secs(5);
+
+    loop {
+        let started = tokio::time::Instant::now();
+        match watch_session(&pool, &config).await {
+            Ok(()) => tracing::warn!("Lightning settlement stream closed by the node"),
+            Err(e) => tracing::warn!("Lightning settlement stream dropped: {e}"),
+        }
+        // A session that lasted a while was healthy; start over from a short delay
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = Duration::from_secs(5);
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn watch_session(pool: &DbPool, config: &LightningConfig) -> AppResult<()> {
+    let http = client(config, true)?;
+    match &config.backend {
+        LightningBackend::Lnd { .. } => {
+            let resp = send(config, http.get(format!("{}/v1/invoices/subscribe", config.url))).await?;
+            tracing::info!("Lightning settlement stream connected");
+            let mut lines = Lines::new(resp);
+            while let Some(line) = lines.next().await? {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                if let Some(error) = message.get("error") {
+                    return Err(AppError::Internal(format!("LND invoice subscription failed: {error}")));
+                }
+                let Some(invoice) = message.get("result") else {
+                    continue;
+                };
+                if invoice.get("state").and_then(Value::as_str) == Some("SETTLED") {
+                    if let Some(hash) = lnd_hash(invoice) {
+                        settle(pool, &hash, Some(lnd_amount(invoice)));
+                    }
+                }
+            }
+            Ok(())
+        }
+        LightningBackend::Cln { .. } => {
+            tracing::info!("Lightning settlement stream connected");
+            // Without an index waitanyinvoice waits for the next payment from now on
+            let mut lastpay_index: Option<i64> = None;
+            loop {
+                let body = match lastpay_index {
+                    Some(index) => json!({ "lastpay_index": index }),
+                    None => json!({}),
+                };
+                let invoice = call(config, http.post(format!("{}/v1/waitanyinvoice", config.url)).json(&body)).await?;
+                if let Some(index) = invoice.get("pay_index").and_then(Value::as_i64) {
+                    lastpay_index = Some(index);
+                }
+                if invoice.get("status").and_then(Value::as_str) == Some("paid") {
+                    if let Some(hash) = invoice.get("payment_hash").and_then(Value::as_str) {
+                        settle(pool, hash, msat_to_sat(invoice.get("amount_received_msat")));
+                    }
+                }
+            }
+        }
+        LightningBackend::Lnbits { .. } => {
+            let resp = send(
+                config,
+                http.get(format!("{}/api/v1/payments/sse", config.url)).header("Accept", "text/event-stream"),
+            )
+            .await?;
+            tracing::info!("Lightning settlement stream connected");
+            let mut lines = Lines::new(resp);
+            let mut event = String::new();
+            while let Some(line) = lines.next().await? {
+                if let Some(name) = line.strip_prefix("event:") {
+                    event = name.trim().to_string();
+                } else if let Some(data) = line.strip_prefix("data:") {
+                    if event != "payment-received" {
+                        continue;
+                    }
+                    let Ok(payment) = serde_json::from_str::<Value>(data.trim()) else {
+                        continue;
+                    };
+                    if let Some(hash) = payment.get("payment_hash").and_then(Value::as_str) {
+                        settle(pool, hash, msat_to_sat(payment.get("amount")));
+                    }
+                } else if line.is_empty() {
+                    event.clear();
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn settle(pool: &DbPool, payment_hash: &str, amount_sat: Option<i64>) {
+    match invoice_checker::settle_lightning(pool, &payment_hash.to_lowercase(), amount_sat) {
+        Ok(true) => tracing::info!("Lightning payment {payment_hash} settled an invoice"),
+        // Not one of ours: the node also serves other invoices
+        Ok(false) => {}
+        Err(e) => tracing::warn!("Failed to record Lightning payment {payment_hash}: {e}"),
+    }
+}
+
+/// Newline-delimited text from a streaming response.
+struct Lines {
+    resp: reqwest::Response,
+    buf: Vec<u8>,
+}
+
+impl Lines {
+    fn new(resp: reqwest::Response) -> Self {
+        Self { resp, buf: Vec::new() }
+    }
+
+    /// The next line without its terminator, or `None` once the stream ends.
+    async fn next(&mut self) -> AppResult<Option<String>> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                return Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()));
+            }
+            if self.buf.len() > MAX_LINE_BYTES {
+                return Err(AppError::Internal("Lightning stream line too long".into()));
+            }
+            match self
+                .resp
+                .chunk()
+                .await
+                .map_err(|e| AppError::Internal(format!("Lightning stream read failed: {e}")))?
+            {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
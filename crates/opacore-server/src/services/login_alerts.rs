@@ -0,0 +1,87 @@
+use chrono::{Duration, Utc};
+
+use crate::auth::session::ClientInfo;
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::models::User;
+use crate::services::email;
+
+const REVOKE_PURPOSE: &str = "session-revoke";
+/// Revoke links outlive the sessions they name, which last 30 days.
+const REVOKE_LINK_TTL_DAYS: i64 = 30;
+
+/// Remember where a user signed in from, returning whether it's somewhere new: an IP
+/// and device pair not seen before. The first sign-in ever isn't new, there being
+/// nothing to compare it with.
+pub fn note_login(conn: &rusqlite::Connection, user_id: &str, client: &ClientInfo) -> AppResult<bool> {
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let user_agent = client.user_agent.as_deref().unwrap_or("");
+    let known_before: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM known_logins WHERE user_id = ?1",
+        rusqlite::params![user_id],
+        |row| row.get(0),
+    )?;
+    let inserted = conn.execute(
+        "INSERT INTO known_logins (user_id, ip_address, user_agent, first_seen_at, last_seen_at)
+         VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT (user_id, ip_address, user_agent) DO NOTHING",
+        rusqlite::params![user_id, client.ip_address, user_agent, now],
+    )?;
+    if inserted == 0 {
+        conn.execute(
+            "UPDATE known_logins SET last_seen_at = ?4 WHERE user_id = ?1 AND ip_address = ?2 AND user_agent = ?3",
+            rusqlite::params![user_id, client.ip_address, user_agent, now],
+        )?;
+    }
+    Ok(inserted > 0 && known_before > 0)
+}
+
+/// After a sign-in: if it came from somewhere new, email the user with a link that
+/// signs out the new session. Never fails the sign-in.
+pub fn on_login(pool: &DbPool, config: &Config, user: &User, session_id: &str, client: &ClientInfo) {
+    let is_new = pool
+        .get()
+        .map_err(Into::into)
+        .and_then(|conn| note_login(&conn, &user.id, client));
+    match is_new {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            tracing::warn!("Failed to check sign-in of user {} against known devices: {e}", user.id);
+            return;
+        }
+    }
+
+    let config = config.clone();
+    let (to, name) = (user.email.clone(), user.name.clone());
+    let ip_address = client.ip_address.clone();
+    let user_agent = client.user_agent.clone().unwrap_or_else(|| "Unknown device".to_string());
+    let revoke_url = revoke_url(&config, session_id);
+    tokio::spawn(async move {
+        if let Err(e) =
+            email::send_new_login_alert(&config, &to, &name, &ip_address, &user_agent, &revoke_url).await
+        {
+            tracing::warn!("Failed to send new sign-in alert: {e}");
+        }
+    });
+}
+
+/// `{app_url}/revoke-session?token=`, where the token carries the session id and the
+/// link's expiry under the server's signature.
+fn revoke_url(config: &Config, session_id: &str) -> String {
+    let expires = (Utc::now() + Duration::days(REVOKE_LINK_TTL_DAYS)).timestamp();
+    let token = config.session_keys.sign(REVOKE_PURPOSE, &format!("{session_id}:{expires}"));
+    format!("{}/revoke-session?token={token}", config.app_url)
+}
+
+/// The session a revoke link was signed for, if the signature holds and the link
+/// hasn't expired.
+pub fn verify_revoke_token(config: &Config, token: &str) -> Option<String> {
+    let value = config.session_keys.verify(REVOKE_PURPOSE, token)?;
+    let (session_id, expires) = value.rsplit_once(':')?;
+    if expires.parse::<i64>().ok()? <= Utc::now().timestamp() {
+        return None;
+    }
+    Some(session_id.to_string())
+}
@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::costbasis::{self, LotAssignment, TxKind};
+use crate::services::transaction_splits::COUNTED;
+
+/// Most lots one disposal can be assigned.
+const MAX_ASSIGNMENTS: usize = 100;
+
+/// Every assignment in a portfolio, by disposal.
+pub fn for_portfolio(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+) -> AppResult<HashMap<String, Vec<LotAssignment>>> {
+    let mut stmt = conn.prepare(
+        "SELECT disposal_id, lot_id, amount_sat FROM disposal_lot_assignments
+         WHERE portfolio_id = ?1 ORDER BY created_at, rowid",
+    )?;
+    let rows = stmt.query(rusqlite::params![portfolio_id])?;
+    by_disposal(rows)
+}
+
+/// Every assignment in the portfolios `user_id` owns, by disposal.
+pub fn for_user(conn: &rusqlite::Connection, user_id: &str) -> AppResult<HashMap<String, Vec<LotAssignment>>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.disposal_id, a.lot_id, a.amount_sat FROM disposal_lot_assignments a
+         JOIN portfolios p ON p.id = a.portfolio_id
+         WHERE p.user_id = ?1 ORDER BY a.created_at, a.rowid",
+    )?;
+    let rows = stmt.query(rusqlite::params![user_id])?;
+    by_disposal(rows)
+}
+
+fn by_disposal(mut rows: rusqlite::Rows) -> AppResult<HashMap<String, Vec<LotAssignment>>> {
+    let mut by_disposal: HashMap<String, Vec<LotAssignment>> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        by_disposal.entry(row.get(0)?).or_default().push(LotAssignment {
+            lot_id: row.get(1)?,
+            amount_sat: row.get(2)?,
+        });
+    }
+    Ok(by_disposal)
+}
+
+/// One disposal's assignments, in the order they're applied.
+pub fn for_disposal(conn: &rusqlite::Connection, disposal_id: &str) -> AppResult<Vec<LotAssignment>> {
+    let mut stmt = conn.prepare(
+        "SELECT lot_id, amount_sat FROM disposal_lot_assignments
+         WHERE disposal_id = ?1 ORDER BY created_at, rowid",
+    )?;
+    let assignments = stmt
+        .query_map(rusqlite::params![disposal_id], |row| {
+            Ok(LotAssignment {
+                lot_id: row.get(0)?,
+                amount_sat: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(assignments)
+}
+
+/// Replace the lots the disposal `disposal_id` consumes. Each must be open just before it
+/// with at least the assigned sats left, and together they can't exceed its amount. An
+/// empty list clears the assignment, leaving the disposal to FIFO.
+pub fn replace(
+    pool: &DbPool,
+    portfolio_id: &str,
+    disposal_id: &str,
+    assignments: &[LotAssignment],
+) -> AppResult<Vec<LotAssignment>> {
+    let (tx_type, amount_sat, counted): (String, i64, bool) = {
+        let conn = pool.get()?;
+        conn.query_row(
+            &format!("SELECT tx_type, amount_sat, {COUNTED} FROM transactions WHERE id = ?1 AND portfolio_id = ?2"),
+            rusqlite::params![disposal_id, portfolio_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Transaction not found".into()),
+            e => AppError::Database(e),
+        })?
+    };
+    if !TxKind::of(&tx_type).is_some_and(TxKind::disposes_lots) {
+        return Err(AppError::BadRequest(format!("A {tx_type} transaction doesn't dispose of lots")));
+    }
+    if !counted {
+        return Err(AppError::BadRequest("Assign lots to the parts of a split transaction instead".into()));
+    }
+    if assignments.len() > MAX_ASSIGNMENTS {
+        return Err(AppError::BadRequest(format!("A disposal can be assigned at most {MAX_ASSIGNMENTS} lots")));
+    }
+
+    let mut seen = HashSet::new();
+    for assignment in assignments {
+        if assignment.amount_sat <= 0 {
+            return Err(AppError::BadRequest("amount_sat must be positive".into()));
+        }
+        if !seen.insert(assignment.lot_id.as_str()) {
+            return Err(AppError::BadRequest(format!("Lot {} is assigned twice", assignment.lot_id)));
+        }
+    }
+    let assigned: i64 = assignments.iter().map(|a| a.amount_sat).sum();
+    if assigned > amount_sat {
+        return Err(AppError::BadRequest(format!(
+            "The lots add up to {assigned} sats, more than the {amount_sat} disposed of"
+        )));
+    }
+
+    let open = costbasis::open_lots_before(pool, portfolio_id, disposal_id)?;
+    for assignment in assignments {
+        let lot = open
+            .iter()
+            .find(|lot| lot.lot_id.as_deref() == Some(assignment.lot_id.as_str()))
+            .ok_or_else(|| AppError::BadRequest(format!("Lot {} isn't open before this disposal", assignment.lot_id)))?;
+        if assignment.amount_sat > lot.amount_sat {
+            return Err(AppError::BadRequest(format!(
+                "Lot {} has only {} sats left before this disposal",
+                assignment.lot_id, lot.amount_sat
+            )));
+        }
+    }
+
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    db_tx.execute(
+        "DELETE FROM disposal_lot_assignments WHERE disposal_id = ?1",
+        rusqlite::params![disposal_id],
+    )?;
+    for assignment in assignments {
+        db_tx.execute(
+            "INSERT INTO disposal_lot_assignments (id, portfolio_id, disposal_id, lot_id, amount_sat)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                Uuid::new_v4().to_string(),
+                portfolio_id,
+                disposal_id,
+                assignment.lot_id,
+                assignment.amount_sat
+            ],
+        )?;
+    }
+    db_tx.commit()?;
+
+    for_disposal(&conn, disposal_id)
+}
@@ -0,0 +1,391 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
+use crate::services::esplora::EsploraBackends;
+use crate::services::invoice_checker;
+
+/// Addresses per subscription; the rest are left to the polling checker.
+const MAX_TRACKED_ADDRESSES: usize = 100;
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// Upper bound on a single (reassembled) message, to bound memory on a misbehaving server.
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether the address watch is currently connected. Shared with the polling checker.
+#[derive(Clone, Default)]
+pub struct WatchStatus(Arc<AtomicBool>);
+
+impl WatchStatus {
+    pub fn is_connected(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, connected: bool) {
+        self.0.store(connected, Ordering::Relaxed);
+    }
+}
+
+/// A pending invoice waiting on a payment to its address.
+struct WatchedInvoice {
+    id: String,
+    amount_sat: i64,
+    reusable: bool,
+}
+
+/// Background task: push-based invoice payment detection via the mempool.space WebSocket API.
+///
+/// Subscribes to every address with an outstanding invoice (`track-addresses`) and runs the
+/// regular Esplora payment check as soon as a transaction touching one of them is announced.
+/// While the socket is up, [`WatchStatus`] lets the polling checker back off; when it drops,
+/// polling resumes at its normal interval while this task reconnects with exponential backoff.
+/// Every `refresh` the subscription is re-read from the invoices table and the socket pinged.
+pub async fn run_address_watch(
+    pool: DbPool,
+    esplora: EsploraBackends,
+    cache: AppCache,
+    ws_url: String,
+    status: WatchStatus,
+    refresh: Duration,
+) {
+    tracing::info!("Mempool WebSocket address watch started ({ws_url})");
+    let mut backoff = Duration::from_secs(5);
+
+    loop {
+        match watch_session(&pool, &esplora, &cache, &ws_url, &status, refresh).await {
+            Ok(()) => tracing::warn!("Mempool WebSocket closed by server, falling back to polling"),
+            Err(e) => tracing::warn!("Mempool WebSocket dropped: {e}, falling back to polling"),
+        }
+        // Start over from a short delay if the session got as far as being marked live
+        if status.is_connected() {
+            backoff = Duration::from_secs(5);
+        }
+        status.set(false);
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// One connection's lifetime. Returns when the socket closes or errors.
+async fn watch_session(
+    pool: &DbPool,
+    esplora: &EsploraBackends,
+    cache: &AppCache,
+    ws_url: &str,
+    status: &WatchStatus,
+    refresh_interval: Duration,
+) -> AppResult<()> {
+    let stream = connect(ws_url).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    tracing::info!("Mempool WebSocket connected");
+
+    // Frames are read on their own task: read_frame isn't cancel-safe, so it can't sit in select!
+    let (frame_tx, mut frame_rx) = mpsc::channel::<AppResult<Frame>>(32);
+    let reader_task = tokio::spawn(async move {
+        let mut reader = reader;
+        loop {
+            let frame = read_message(&mut reader).await;
+            let failed = frame.is_err();
+            if frame_tx.send(frame).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let result = async {
+        let mut watched = watched_invoices(pool)?;
+        let mut tracked = subscribe(&mut writer, &watched, None).await?;
+        let mut refresh = tokio::time::interval(refresh_interval);
+        refresh.tick().await;
+        let mut connected_once = false;
+
+        loop {
+            tokio::select! {
+                frame = frame_rx.recv() => {
+                    let Some(frame) = frame else { return Ok(()) };
+                    match frame? {
+                        Frame::Text(text) => {
+                            for address in announced_addresses(&text) {
+                                if let Some(invoices) = watched.get(&address) {
+                                    check_invoices(pool, esplora, cache, &address, invoices).await;
+                                }
+                            }
+                        }
+                        Frame::Ping(payload) => write_frame(&mut writer, OP_PONG, &payload).await?,
+                        Frame::Close => return Ok(()),
+                    }
+                }
+                _ = refresh.tick() => {
+                    // Only report the socket as live once it has survived a refresh cycle
+                    if !connected_once {
+                        connected_once = true;
+                        status.set(true);
+                    }
+                    watched = watched_invoices(pool)?;
+                    tracked = subscribe(&mut writer, &watched, Some(tracked)).await?;
+                    write_frame(&mut writer, OP_PING, b"").await?;
+                }
+            }
+        }
+    }
+    .await;
+
+    reader_task.abort();
+    result
+}
+
+/// Outstanding invoices grouped by address.
+fn watched_invoices(pool: &DbPool) -> AppResult<HashMap<String, Vec<WatchedInvoice>>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, btc_address, amount_sat, reusable FROM invoices
+         WHERE status IN ('sent', 'pending_confirmation') ORDER BY created_at DESC",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(1)?,
+            WatchedInvoice {
+                id: row.get(0)?,
+                amount_sat: row.get(2)?,
+                reusable: row.get::<_, i32>(3).map(|v| v != 0)?,
+            },
+        ))
+    })?;
+
+    let mut watched: HashMap<String, Vec<WatchedInvoice>> = HashMap::new();
+    for (address, invoice) in rows.filter_map(|r| r.ok()) {
+        watched.entry(address).or_default().push(invoice);
+    }
+    Ok(watched)
+}
+
+/// Send `track-addresses` if the set of addresses changed since `previous`.
+async fn subscribe<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    watched: &HashMap<String, Vec<WatchedInvoice>>,
+    previous: Option<HashSet<String>>,
+) -> AppResult<HashSet<String>> {
+    let mut addresses: Vec<&String> = watched.keys().collect();
+    let pending = addresses.len();
+    addresses.sort();
+    addresses.truncate(MAX_TRACKED_ADDRESSES);
+    let tracked: HashSet<String> = addresses.into_iter().cloned().collect();
+    if previous.as_ref() == Some(&tracked) {
+        return Ok(tracked);
+    }
+
+    if pending > MAX_TRACKED_ADDRESSES {
+        tracing::warn!("{pending} invoice addresses pending, only watching {MAX_TRACKED_ADDRESSES} over WebSocket");
+    }
+
+    let message = serde_json::json!({ "track-addresses": tracked.iter().collect::<Vec<_>>() });
+    write_frame(writer, OP_TEXT, message.to_string().as_bytes()).await?;
+    tracing::debug!("Watching {} invoice addresses over WebSocket", tracked.len());
+    Ok(tracked)
+}
+
+/// Addresses with new activity in a `multi-address-transactions` push.
+fn announced_addresses(text: &str) -> Vec<String> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(text) else {
+        return Vec::new();
+    };
+    value
+        .get("multi-address-transactions")
+        .and_then(|v| v.as_object())
+        .map(|by_address| by_address.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+async fn check_invoices(
+    pool: &DbPool,
+    esplora: &EsploraBackends,
+    cache: &AppCache,
+    address: &str,
+    invoices: &[WatchedInvoice],
+) {
+    for invoice in invoices {
+        match invoice_checker::check_invoice_payment(
+            esplora,
+            cache,
+            pool,
+            &invoice.id,
+            address,
+            invoice.amount_sat,
+            invoice.reusable,
+        )
+        .await
+        {
+            Ok(true) => tracing::info!("Invoice {} payment status updated via WebSocket", invoice.id),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Invoice {} check failed: {e}", invoice.id),
+        }
+    }
+}
+
+// ── Minimal RFC 6455 client ──
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Open a WebSocket over an HTTP/1.1 upgrade (TLS for `wss://` is handled by reqwest).
+async fn connect(ws_url: &str) -> AppResult<reqwest::Upgraded> {
+    let http_url = if let Some(rest) = ws_url.strip_prefix("wss://") {
+        format!("https://{rest}")
+    } else if let Some(rest) = ws_url.strip_prefix("ws://") {
+        format!("http://{rest}")
+    } else {
+        return Err(AppError::Internal(format!("Not a WebSocket URL: {ws_url}")));
+    };
+
+    let key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+    let http = reqwest::Client::builder()
+        .user_agent("opacore/0.1")
+        .http1_only()
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))?;
+
+    let resp = http
+        .get(&http_url)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", &key)
+        .send()
+        .await
+        .map_err(|e| AppError::Internal(format!("WebSocket handshake failed: {e}")))?;
+
+    if resp.status() != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+        return Err(AppError::Internal(format!(
+            "WebSocket handshake returned {} for {ws_url}",
+            resp.status()
+        )));
+    }
+
+    let expected = base64::engine::general_purpose::STANDARD
+        .encode(Sha1::digest(format!("{key}{WS_GUID}").as_bytes()));
+    let accept = resp
+        .headers()
+        .get("Sec-WebSocket-Accept")
+        .and_then(|v| v.to_str().ok());
+    if accept != Some(expected.as_str()) {
+        return Err(AppError::Internal("WebSocket handshake returned a bad accept key".into()));
+    }
+
+    resp.upgrade()
+        .await
+        .map_err(|e| AppError::Internal(format!("WebSocket upgrade failed: {e}")))
+}
+
+/// Read the next complete message, reassembling fragments. Pong frames are skipped.
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> AppResult<Frame> {
+    let mut message = Vec::new();
+    let mut message_opcode = None;
+
+    loop {
+        let (fin, opcode, payload) = read_frame(reader).await?;
+        match opcode {
+            OP_PING => return Ok(Frame::Ping(payload)),
+            OP_PONG => continue,
+            OP_CLOSE => return Ok(Frame::Close),
+            OP_TEXT | OP_BINARY => {
+                message_opcode = Some(opcode);
+                message = payload;
+            }
+            OP_CONTINUATION if message_opcode.is_some() => message.extend_from_slice(&payload),
+            _ => return Err(AppError::Internal(format!("Unexpected WebSocket opcode {opcode:#x}"))),
+        }
+
+        if message.len() > MAX_MESSAGE_BYTES {
+            return Err(AppError::Internal("WebSocket message too large".into()));
+        }
+        if fin {
+            // Binary messages aren't part of the mempool.space protocol; hand them on as text anyway
+            return Ok(Frame::Text(String::from_utf8_lossy(&message).into_owned()));
+        }
+    }
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> AppResult<(bool, u8, Vec<u8>)> {
+    let io_err = |e: std::io::Error| AppError::Internal(format!("WebSocket read failed: {e}"));
+
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header).await.map_err(io_err)?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+
+    let len = match header[1] & 0x7F {
+        126 => reader.read_u16().await.map_err(io_err)? as u64,
+        127 => reader.read_u64().await.map_err(io_err)?,
+        n => n as u64,
+    };
+    if len > MAX_MESSAGE_BYTES as u64 {
+        return Err(AppError::Internal("WebSocket frame too large".into()));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await.map_err(io_err)?;
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await.map_err(io_err)?;
+    if masked {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+
+    Ok((fin, opcode, payload))
+}
+
+/// Write a single masked frame (clients must mask everything they send).
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, opcode: u8, payload: &[u8]) -> AppResult<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+
+    let len = payload.len();
+    if len < 126 {
+        frame.push(0x80 | len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(0x80 | 127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+
+    let mask: [u8; 4] = rand::random();
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+
+    writer
+        .write_all(&frame)
+        .await
+        .map_err(|e| AppError::Internal(format!("WebSocket write failed: {e}")))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| AppError::Internal(format!("WebSocket write failed: {e}")))
+}
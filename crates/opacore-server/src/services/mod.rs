@@ -1,9 +1,60 @@
+pub mod account_import;
+pub mod admin;
 pub mod alerts;
+pub mod attachments;
+pub mod audit;
+pub mod benchmark;
+pub mod branding;
+pub mod business;
+pub mod cache;
+pub mod cash_flow;
 pub mod costbasis;
+pub mod customers;
+pub mod dashboard;
+pub mod dca;
+pub mod data_quality;
 pub mod email;
+pub mod esplora;
+pub mod events;
+pub mod exchange_api;
+pub mod exchange_import;
+pub mod export;
+pub mod fee_analytics;
 pub mod fees;
+pub mod holdings_age;
+pub mod http;
+pub mod import;
 pub mod invoice_checker;
+pub mod invoice_emails;
+pub mod invoice_history;
+pub mod invoice_transactions;
+pub mod jobs;
+pub mod label_breakdown;
+pub mod login_alerts;
+pub mod lot_assignments;
+pub mod lightning;
+pub mod mempool_ws;
+pub mod networth;
+pub mod pagination;
+pub mod pdf;
+pub mod portfolio_template;
 pub mod prices;
+pub mod qr;
+pub mod quotas;
+pub mod recurring;
 pub mod sync;
+pub mod sync_lock;
+pub mod sync_progress;
 pub mod tax;
+pub mod tax_locks;
+pub mod tax_pdf;
+pub mod tax_software;
+pub mod transaction_audit;
+pub mod transaction_splits;
+pub mod transfers;
+pub mod utxos;
+pub mod value_history;
 pub mod wallet;
+pub mod watch;
+pub mod webhooks;
+pub mod zip;
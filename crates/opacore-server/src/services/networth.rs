@@ -0,0 +1,87 @@
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::AppResult;
+use crate::services::cache::AppCache;
+use crate::services::{costbasis, tax};
+
+/// Everything the user owns, in one currency.
+#[derive(Debug, Serialize)]
+pub struct NetWorth {
+    pub currency: String,
+    pub current_price: f64,
+    pub total_balance_sat: i64,
+    pub total_value: f64,
+    pub total_cost_basis: f64,
+    pub unrealized_gain: f64,
+    pub portfolios: Vec<PortfolioContribution>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PortfolioContribution {
+    pub id: String,
+    pub name: String,
+    /// The currency the portfolio reports in by itself.
+    pub base_currency: String,
+    pub balance_sat: i64,
+    pub value: f64,
+    pub cost_basis: f64,
+    pub unrealized_gain: f64,
+    /// Of the total value, from 0 to 1. 0 when the total is 0.
+    pub share: f64,
+}
+
+/// Sum the portfolios `user_id` owns, valued at `current_price` (in `currency`). Each
+/// uses its own cost-basis method, with costs converted to `currency` through the cached
+/// daily prices and summaries from `cache`.
+#[tracing::instrument(level = "debug", skip(pool, cache))]
+pub fn net_worth(
+    pool: &DbPool,
+    cache: &AppCache,
+    user_id: &str,
+    currency: &str,
+    current_price: f64,
+) -> AppResult<NetWorth> {
+    let (rules, owned) = {
+        let conn = pool.get()?;
+        let owned: Vec<(String, String, String)> = conn
+            .prepare("SELECT id, name, base_currency FROM portfolios WHERE user_id = ?1 ORDER BY created_at DESC")?
+            .query_map(rusqlite::params![user_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        (tax::tax_rules(&conn, user_id)?, owned)
+    };
+
+    let mut portfolios = Vec::with_capacity(owned.len());
+    for (id, name, base_currency) in owned {
+        let method = costbasis::portfolio_defaults(&*pool.get()?, &id)?.method;
+        let summary = costbasis::portfolio_summary(pool, Some(cache), &id, current_price, method, currency, &rules)?;
+        portfolios.push(PortfolioContribution {
+            id,
+            name,
+            base_currency,
+            balance_sat: summary.total_balance_sat,
+            value: summary.current_value_usd,
+            cost_basis: summary.total_cost_basis_usd,
+            unrealized_gain: summary.unrealized_gain_usd,
+            share: 0.0,
+        });
+    }
+
+    let total_value: f64 = portfolios.iter().map(|p| p.value).sum();
+    let total_cost_basis: f64 = portfolios.iter().map(|p| p.cost_basis).sum();
+    if total_value != 0.0 {
+        for portfolio in &mut portfolios {
+            portfolio.share = portfolio.value / total_value;
+        }
+    }
+
+    Ok(NetWorth {
+        currency: currency.to_string(),
+        current_price,
+        total_balance_sat: portfolios.iter().map(|p| p.balance_sat).sum(),
+        total_value,
+        total_cost_basis,
+        unrealized_gain: total_value - total_cost_basis,
+        portfolios,
+    })
+}
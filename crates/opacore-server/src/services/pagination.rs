@@ -0,0 +1,72 @@
+use base64::Engine;
+use rusqlite::types::Value;
+
+use crate::error::{AppError, AppResult};
+
+/// Position in a list ordered by `(sort key, id)`: the last row of the previous page.
+/// Handed to clients as an opaque string, so rows arriving mid-scroll don't shift later
+/// pages the way an offset does. The key keeps its SQL type so numeric sorts compare
+/// as numbers.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    pub key: Value,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let key = match &self.key {
+            Value::Integer(i) => format!("i:{i}"),
+            Value::Real(f) => format!("r:{f}"),
+            Value::Text(t) => format!("t:{t}"),
+            Value::Null | Value::Blob(_) => "n:".to_string(),
+        };
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(format!("{key}|{}", self.id))
+    }
+
+    pub fn decode(value: &str) -> AppResult<Self> {
+        let invalid = || AppError::BadRequest("Invalid cursor".into());
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|_| invalid())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (key, id) = text.rsplit_once('|').ok_or_else(invalid)?;
+        let key = match key.split_once(':').ok_or_else(invalid)? {
+            ("i", i) => Value::Integer(i.parse().map_err(|_| invalid())?),
+            ("r", f) => Value::Real(f.parse().map_err(|_| invalid())?),
+            ("t", t) => Value::Text(t.to_string()),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { key, id: id.to_string() })
+    }
+
+    /// ` AND` condition selecting rows after this cursor in a list ordered by
+    /// `key_column` then `id_column`, both in the same direction. Binds the cursor's
+    /// values as the next two parameters.
+    pub fn condition(
+        &self,
+        key_column: &str,
+        id_column: &str,
+        descending: bool,
+        params: &mut Vec<Value>,
+    ) -> String {
+        let op = if descending { "<" } else { ">" };
+        params.push(self.key.clone());
+        let key_idx = params.len();
+        params.push(self.id.clone().into());
+        let id_idx = params.len();
+        format!(
+            " AND ({key_column} {op} ?{key_idx} OR ({key_column} = ?{key_idx} AND {id_column} {op} ?{id_idx}))"
+        )
+    }
+}
+
+/// Trim a page fetched with `limit + 1` rows back to `limit`, returning the cursor for
+/// the next page if there is one.
+pub fn next_cursor<T>(rows: &mut Vec<T>, limit: i64, cursor: impl Fn(&T) -> Cursor) -> Option<String> {
+    if rows.len() as i64 <= limit {
+        return None;
+    }
+    rows.truncate(limit as usize);
+    rows.last().map(|row| cursor(row).encode())
+}
@@ -0,0 +1,295 @@
+use std::io::Write;
+
+use crate::error::{AppError, AppResult};
+use crate::services::qr::QrCode;
+
+/// A4 in points.
+pub const PAGE_WIDTH: f64 = 595.0;
+pub const PAGE_HEIGHT: f64 = 842.0;
+
+/// Glyph widths (1/1000 em) of printable ASCII, from the Adobe metrics of the standard
+/// fonts every PDF reader ships, so no font needs embedding.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // ' '..'/'
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // '0'..'?'
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // '@'..'O'
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // 'P'..'_'
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // '`'..'o'
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // 'p'..'~'
+];
+
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+    /// Courier, for addresses and transaction ids.
+    Mono,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Mono => "F3",
+        }
+    }
+}
+
+/// Map a character to WinAnsiEncoding; anything the standard fonts can't show becomes `?`.
+fn win_ansi(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        '\u{a0}'..='\u{ff}' => c as u32 as u8,
+        '€' => 0x80,
+        '…' => 0x85,
+        '•' => 0x95,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '–' => 0x96,
+        '—' => 0x97,
+        _ => b'?',
+    }
+}
+
+/// `text` as the body of a PDF string literal, in WinAnsiEncoding.
+fn literal(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len());
+    for c in text.chars() {
+        match win_ansi(c) {
+            code @ (b'(' | b')' | b'\\') => {
+                literal.push('\\');
+                literal.push(code as char);
+            }
+            code @ 0x20..=0x7e => literal.push(code as char),
+            code => literal.push_str(&format!("\\{code:03o}")),
+        }
+    }
+    literal
+}
+
+/// Width of `text` in points.
+pub fn text_width(font: Font, size: f64, text: &str) -> f64 {
+    let units: u32 = text
+        .chars()
+        .map(|c| {
+            let code = win_ansi(c);
+            match font {
+                Font::Mono => 600,
+                Font::Regular if (0x20..0x7f).contains(&code) => HELVETICA_WIDTHS[(code - 0x20) as usize] as u32,
+                Font::Bold if (0x20..0x7f).contains(&code) => HELVETICA_BOLD_WIDTHS[(code - 0x20) as usize] as u32,
+                // Accented letters are close to the average lowercase width
+                _ => 556,
+            }
+        })
+        .sum();
+    units as f64 * size / 1000.0
+}
+
+/// `1234567.891` as `1,234,567.89`.
+pub fn format_amount(amount: f64) -> String {
+    let cents = (amount * 100.0).round() as i64;
+    let whole = (cents.abs() / 100).to_string();
+    let mut grouped = String::new();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}{grouped}.{:02}", cents.abs() % 100)
+}
+
+/// `1234567.891` as `1,234,567.89 USD`.
+pub fn format_fiat(amount: f64, currency: &str) -> String {
+    format!("{} {}", format_amount(amount), currency.to_uppercase())
+}
+
+/// Break `text` into lines no wider than `max_width`, at spaces where possible.
+pub fn wrap(font: Font, size: f64, text: &str, max_width: f64) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{line} {word}") };
+            if text_width(font, size, &candidate) <= max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            // A single word wider than the line is split wherever it overflows
+            for c in word.chars() {
+                if !line.is_empty() && text_width(font, size, &format!("{line}{c}")) > max_width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(c);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn rgb_operands((r, g, b): (u8, u8, u8)) -> String {
+    format!("{:.3} {:.3} {:.3}", r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0)
+}
+
+/// A PDF page drawn with the standard fonts. Coordinates are in points from the
+/// top left, unlike PDF's own bottom-left origin.
+#[derive(Default)]
+pub struct PdfPage {
+    content: String,
+}
+
+impl PdfPage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Text with its baseline at `y`, in grey level `grey` (0 black, 1 white).
+    pub fn text(&mut self, x: f64, y: f64, font: Font, size: f64, grey: f64, text: &str) {
+        self.text_in(&format!("{grey:.3} g"), x, y, font, size, text);
+    }
+
+    /// Text in the color `(r, g, b)`.
+    pub fn text_rgb(&mut self, x: f64, y: f64, font: Font, size: f64, rgb: (u8, u8, u8), text: &str) {
+        self.text_in(&format!("{} rg", rgb_operands(rgb)), x, y, font, size, text);
+    }
+
+    fn text_in(&mut self, fill: &str, x: f64, y: f64, font: Font, size: f64, text: &str) {
+        self.content.push_str(&format!(
+            "BT {fill} /{} {size:.2} Tf {x:.2} {:.2} Td ({}) Tj ET\n",
+            font.resource(),
+            PAGE_HEIGHT - y,
+            literal(text)
+        ));
+    }
+
+    /// Text whose right edge is at `right`.
+    pub fn text_right(&mut self, right: f64, y: f64, font: Font, size: f64, grey: f64, text: &str) {
+        let x = right - text_width(font, size, text);
+        self.text(x, y, font, size, grey, text);
+    }
+
+    /// A filled rectangle with its top left corner at `(x, y)`.
+    pub fn rect(&mut self, x: f64, y: f64, width: f64, height: f64, grey: f64) {
+        self.content.push_str(&format!(
+            "{grey:.3} g {x:.2} {:.2} {width:.2} {height:.2} re f\n",
+            PAGE_HEIGHT - y - height
+        ));
+    }
+
+    /// A horizontal rule.
+    pub fn hline(&mut self, x1: f64, x2: f64, y: f64, thickness: f64, grey: f64) {
+        let y = PAGE_HEIGHT - y;
+        self.content.push_str(&format!("{grey:.3} G {thickness:.2} w {x1:.2} {y:.2} m {x2:.2} {y:.2} l S\n"));
+    }
+
+    /// A horizontal rule in the color `(r, g, b)`.
+    pub fn hline_rgb(&mut self, x1: f64, x2: f64, y: f64, thickness: f64, rgb: (u8, u8, u8)) {
+        let y = PAGE_HEIGHT - y;
+        self.content.push_str(&format!(
+            "{} RG {thickness:.2} w {x1:.2} {y:.2} m {x2:.2} {y:.2} l S\n",
+            rgb_operands(rgb)
+        ));
+    }
+
+    /// `qr` as vector modules in a `side` × `side` square, quiet zone excluded.
+    pub fn qr(&mut self, x: f64, y: f64, side: f64, qr: &QrCode) {
+        let module = side / qr.size() as f64;
+        self.content.push_str("0 g\n");
+        for row in 0..qr.size() {
+            for col in 0..qr.size() {
+                if qr.is_dark(col, row) {
+                    self.content.push_str(&format!(
+                        "{:.3} {:.3} {module:.3} {module:.3} re\n",
+                        x + col as f64 * module,
+                        PAGE_HEIGHT - y - (row + 1) as f64 * module
+                    ));
+                }
+            }
+        }
+        self.content.push_str("f\n");
+    }
+
+    /// The finished document, titled `title` in its metadata.
+    pub fn finish(self, title: &str) -> AppResult<Vec<u8>> {
+        finish_document(vec![self], title)
+    }
+}
+
+/// `pages` as one document, in order, titled `title` in its metadata.
+pub fn finish_document(pages: Vec<PdfPage>, title: &str) -> AppResult<Vec<u8>> {
+    let font = |name: &str| {
+        format!("<< /Type /Font /Subtype /Type1 /BaseFont /{name} /Encoding /WinAnsiEncoding >>").into_bytes()
+    };
+    // Catalog, page tree, fonts and info first, then each page and its contents
+    const FIRST_PAGE: usize = 7;
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", FIRST_PAGE + 2 * i)).collect();
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).into_bytes(),
+        font("Helvetica"),
+        font("Helvetica-Bold"),
+        font("Courier"),
+        format!("<< /Title ({}) /Producer (Opacore) >>", literal(title)).into_bytes(),
+    ];
+    for page in pages {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(page.content.as_bytes())
+            .map_err(|e| AppError::Internal(format!("Failed to compress PDF: {e}")))?;
+        let stream = encoder
+            .finish()
+            .map_err(|e| AppError::Internal(format!("Failed to compress PDF: {e}")))?;
+
+        let mut content = format!("<< /Length {} /Filter /FlateDecode >>\nstream\n", stream.len()).into_bytes();
+        content.extend_from_slice(&stream);
+        content.extend_from_slice(b"\nendstream");
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> /Contents {} 0 R >>",
+                objects.len() + 2
+            )
+            .into_bytes(),
+        );
+        objects.push(content);
+    }
+
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info 6 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    Ok(pdf)
+}
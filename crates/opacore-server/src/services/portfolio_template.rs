@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::services::branding::{self, Branding};
+use crate::services::costbasis::CostBasisMethod;
+use crate::services::quotas::{self, Quota};
+
+/// What a portfolio is made of, without any of its history: its settings, the wallets to
+/// track and the labels its transactions use. Cloning copies this; templates store it.
+/// Settings missing from templates saved before they were copied take their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioStructure {
+    pub description: Option<String>,
+    #[serde(default)]
+    pub invoice_expiry_hours: Option<i64>,
+    #[serde(default)]
+    pub branding: Branding,
+    #[serde(default)]
+    pub cost_basis_method: CostBasisMethod,
+    #[serde(default = "default_base_currency")]
+    pub base_currency: String,
+    pub wallets: Vec<WalletDefinition>,
+    pub labels: Vec<LabelDefinition>,
+}
+
+fn default_base_currency() -> String {
+    "usd".to_string()
+}
+
+/// A wallet as configured, minus sync state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletDefinition {
+    pub label: String,
+    pub wallet_type: String,
+    pub descriptor: Option<String>,
+    pub xpub: Option<String>,
+    pub address: Option<String>,
+    pub network: String,
+    pub derivation_path: Option<String>,
+    pub gap_limit: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelDefinition {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+/// A saved portfolio structure that new portfolios can be created from.
+#[derive(Debug, Serialize)]
+pub struct PortfolioTemplate {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub structure: PortfolioStructure,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub const TEMPLATE_COLS: &str = "id, user_id, name, structure, created_at, updated_at";
+
+pub fn row_to_template(row: &rusqlite::Row) -> rusqlite::Result<PortfolioTemplate> {
+    let structure: String = row.get(3)?;
+    Ok(PortfolioTemplate {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        structure: serde_json::from_str(&structure).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+    })
+}
+
+pub fn load_template(conn: &rusqlite::Connection, user_id: &str, template_id: &str) -> AppResult<PortfolioTemplate> {
+    conn.query_row(
+        &format!("SELECT {TEMPLATE_COLS} FROM portfolio_templates WHERE id = ?1 AND user_id = ?2"),
+        rusqlite::params![template_id, user_id],
+        row_to_template,
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Portfolio template not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Read a portfolio's structure. Labels are those attached to any of its transactions.
+pub fn snapshot(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<PortfolioStructure> {
+    let (description, invoice_expiry_hours, cost_basis_method, base_currency) = conn.query_row(
+        "SELECT description, invoice_expiry_hours, cost_basis_method, base_currency FROM portfolios WHERE id = ?1",
+        rusqlite::params![portfolio_id],
+        |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        },
+    )?;
+
+    let mut stmt = conn.prepare(
+        "SELECT label, wallet_type, descriptor, xpub, address, network, derivation_path, gap_limit
+         FROM wallets WHERE portfolio_id = ?1 ORDER BY created_at",
+    )?;
+    let wallets = stmt
+        .query_map(rusqlite::params![portfolio_id], |row| {
+            Ok(WalletDefinition {
+                label: row.get(0)?,
+                wallet_type: row.get(1)?,
+                descriptor: row.get(2)?,
+                xpub: row.get(3)?,
+                address: row.get(4)?,
+                network: row.get(5)?,
+                derivation_path: row.get(6)?,
+                gap_limit: row.get(7)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT l.name, l.color FROM labels l
+         JOIN transaction_labels tl ON tl.label_id = l.id
+         JOIN transactions t ON t.id = tl.transaction_id
+         WHERE t.portfolio_id = ?1
+         ORDER BY l.name",
+    )?;
+    let labels = stmt
+        .query_map(rusqlite::params![portfolio_id], |row| {
+            Ok(LabelDefinition { name: row.get(0)?, color: row.get(1)? })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(PortfolioStructure {
+        description,
+        invoice_expiry_hours,
+        branding: branding::load(conn, portfolio_id)?,
+        cost_basis_method: CostBasisMethod::from_name(&cost_basis_method).unwrap_or_default(),
+        base_currency,
+        wallets,
+        labels,
+    })
+}
+
+/// Create a portfolio with the given structure, checking the user's portfolio and
+/// wallet quotas along the way. Labels are per user, so only missing ones are
+/// created. Run inside a SQLite transaction so a quota failure leaves nothing behind.
+pub fn create_portfolio(
+    conn: &rusqlite::Connection,
+    config: &Config,
+    user_id: &str,
+    portfolio_id: &str,
+    name: &str,
+    structure: &PortfolioStructure,
+    now: &str,
+) -> AppResult<()> {
+    quotas::check(conn, config, user_id, Quota::Portfolios)?;
+    conn.execute(
+        "INSERT INTO portfolios (id, user_id, name, description, invoice_expiry_hours, cost_basis_method, base_currency, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![
+            portfolio_id, user_id, name, structure.description, structure.invoice_expiry_hours,
+            structure.cost_basis_method.name(), structure.base_currency, now, now
+        ],
+    )?;
+
+    let branding = &structure.branding;
+    if branding.business_name.is_some()
+        || branding.logo_url.is_some()
+        || branding.accent_color.is_some()
+        || branding.payment_instructions.is_some()
+    {
+        conn.execute(
+            "INSERT INTO portfolio_branding (portfolio_id, business_name, logo_url, accent_color, payment_instructions, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                portfolio_id, branding.business_name, branding.logo_url, branding.accent_color,
+                branding.payment_instructions, now
+            ],
+        )?;
+    }
+
+    for wallet in &structure.wallets {
+        quotas::check(conn, config, user_id, Quota::Wallets)?;
+        conn.execute(
+            "INSERT INTO wallets (id, portfolio_id, label, wallet_type, descriptor, xpub, address, network, derivation_path, gap_limit, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            rusqlite::params![
+                uuid::Uuid::new_v4().to_string(), portfolio_id, wallet.label, wallet.wallet_type,
+                wallet.descriptor, wallet.xpub, wallet.address, wallet.network,
+                wallet.derivation_path, wallet.gap_limit, now, now
+            ],
+        )?;
+    }
+
+    for label in &structure.labels {
+        conn.execute(
+            "INSERT OR IGNORE INTO labels (id, user_id, name, color, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![uuid::Uuid::new_v4().to_string(), user_id, label.name, label.color, now],
+        )?;
+    }
+
+    Ok(())
+}
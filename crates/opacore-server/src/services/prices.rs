@@ -1,15 +1,10 @@
-use reqwest::Client;
 use serde::Deserialize;
 
 use crate::db::DbPool;
 use crate::error::{AppError, AppResult};
-
-#[derive(Debug, serde::Serialize)]
-pub struct PriceInfo {
-    pub currency: String,
-    pub price: f64,
-    pub source: String,
-}
+use crate::services::cache::AppCache;
+use crate::services::http;
+use crate::services::tax_locks;
 
 #[derive(Debug, serde::Serialize)]
 pub struct HistoricalPrice {
@@ -19,11 +14,6 @@ pub struct HistoricalPrice {
     pub source: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct CoinGeckoSimplePrice {
-    bitcoin: std::collections::HashMap<String, f64>,
-}
-
 #[derive(Debug, Deserialize)]
 struct CoinGeckoHistoryResponse {
     market_data: Option<CoinGeckoMarketData>,
@@ -36,6 +26,7 @@ struct CoinGeckoMarketData {
 
 /// Fetch current BTC price. Tries Kraken ticker first (no key, no rate limit),
 /// falls back to CoinGecko if Kraken fails or currency isn't USD.
+#[tracing::instrument(level = "debug", skip_all, fields(otel.kind = "client"))]
 pub async fn fetch_current_price(
     api_url: &str,
     currency: &str,
@@ -48,7 +39,7 @@ pub async fn fetch_current_price(
         tracing::warn!("Kraken ticker failed, falling back to CoinGecko");
     }
 
-    let client = Client::new();
+    let client = http::client()?;
     let url = format!("{api_url}/simple/price?ids=bitcoin&vs_currencies={currency}");
 
     let body = client
@@ -57,10 +48,10 @@ pub async fn fetch_current_price(
         .header("User-Agent", "opacore/0.1")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("CoinGecko request failed: {e}")))?
+        .map_err(|e| http::upstream_error("coingecko", "CoinGecko request failed", e))?
         .json::<serde_json::Value>()
         .await
-        .map_err(|e| AppError::Internal(format!("CoinGecko parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("coingecko", "CoinGecko parse failed", e))?;
 
     body.get("bitcoin")
         .and_then(|b| b.get(currency))
@@ -68,10 +59,64 @@ pub async fn fetch_current_price(
         .ok_or_else(|| AppError::Internal(format!("No price for currency: {currency} (response: {body})")))
 }
 
+/// Current BTC price through the shared cache, so upstream APIs are hit at most
+/// once per TTL regardless of how many handlers ask. If the providers are down, the
+/// last price fetched is used instead, however old: fine for valuing holdings, but
+/// anything that prices a payment should use [`live_price`].
+pub async fn current_price(pool: &DbPool, cache: &AppCache, api_url: &str, currency: &str) -> AppResult<f64> {
+    match live_price(pool, cache, api_url, currency).await {
+        Ok(price) => Ok(price),
+        Err(e) => match stored_spot_price(pool, currency) {
+            Some((price, fetched_at)) => {
+                tracing::warn!("Current {currency} price unavailable ({e}), using the one from {fetched_at}");
+                Ok(price)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// As [`current_price`], but never older than the cache TTL. Each price fetched is
+/// stored for [`current_price`] to fall back on.
+pub async fn live_price(pool: &DbPool, cache: &AppCache, api_url: &str, currency: &str) -> AppResult<f64> {
+    if let Some(price) = cache.current_price(currency) {
+        return Ok(price);
+    }
+    let price = fetch_current_price(api_url, currency).await?;
+    cache.set_current_price(currency, price);
+    if let Err(e) = store_spot_price(pool, currency, price) {
+        tracing::warn!("Failed to store {currency} spot price: {e}");
+    }
+    Ok(price)
+}
+
+fn store_spot_price(pool: &DbPool, currency: &str, price: f64) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    pool.get()?.execute(
+        "INSERT INTO spot_prices (currency, price, fetched_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(currency) DO UPDATE SET price = excluded.price, fetched_at = excluded.fetched_at",
+        rusqlite::params![currency, price, now],
+    )?;
+    Ok(())
+}
+
+/// The last spot price stored for `currency`, and when it was fetched.
+fn stored_spot_price(pool: &DbPool, currency: &str) -> Option<(f64, String)> {
+    pool.get()
+        .ok()?
+        .query_row(
+            "SELECT price, fetched_at FROM spot_prices WHERE currency = ?1",
+            rusqlite::params![currency],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()
+}
+
 /// Fetch current BTC/USD price from Kraken's public ticker API.
 /// Returns None on any error so the caller can fall back gracefully.
+#[tracing::instrument(level = "debug", skip_all, fields(otel.kind = "client"))]
 async fn fetch_current_price_kraken() -> Option<f64> {
-    let client = Client::new();
+    let client = http::client().ok()?;
     let resp: serde_json::Value = client
         .get("https://api.kraken.com/0/public/Ticker?pair=XBTUSD")
         .header("User-Agent", "opacore/0.1")
@@ -106,12 +151,13 @@ pub fn get_latest_cached_price(pool: &DbPool, currency: &str) -> Option<f64> {
 
 /// Fetch historical BTC price for a specific date from CoinGecko.
 /// Date format: "dd-mm-yyyy" (CoinGecko format)
+#[tracing::instrument(level = "debug", skip_all, fields(otel.kind = "client"))]
 pub async fn fetch_historical_price(
     api_url: &str,
     date: &str,
     currency: &str,
 ) -> AppResult<f64> {
-    let client = Client::new();
+    let client = http::client()?;
     let url = format!(
         "{api_url}/coins/bitcoin/history?date={date}&localization=false"
     );
@@ -122,10 +168,10 @@ pub async fn fetch_historical_price(
         .header("User-Agent", "opacore/0.1")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("CoinGecko history request failed: {e}")))?
+        .map_err(|e| http::upstream_error("coingecko", "CoinGecko history request failed", e))?
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("CoinGecko history parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("coingecko", "CoinGecko history parse failed", e))?;
 
     resp.market_data
         .and_then(|md| md.current_price.get(currency).copied())
@@ -144,12 +190,7 @@ pub async fn get_or_fetch_price(
     // Check cache first — scope the connection so it's dropped before await
     let cached = {
         let conn = pool.get()?;
-        conn.query_row(
-            "SELECT price FROM price_history WHERE date = ?1 AND currency = ?2",
-            rusqlite::params![date, currency],
-            |row| row.get::<_, f64>(0),
-        )
-        .ok()
+        cached_price(&conn, date, currency)
     };
 
     if let Some(price) = cached {
@@ -177,6 +218,40 @@ pub async fn get_or_fetch_price(
     Ok(price)
 }
 
+pub fn cached_price(conn: &rusqlite::Connection, date: &str, currency: &str) -> Option<f64> {
+    conn.query_row(
+        "SELECT price FROM price_history WHERE date = ?1 AND currency = ?2",
+        rusqlite::params![date, currency],
+        |row| row.get::<_, f64>(0),
+    )
+    .ok()
+}
+
+/// `currency` as a lowercase ISO 4217 code, as prices are stored, if it looks like one.
+pub fn currency_code(currency: &str) -> Option<String> {
+    (currency.len() == 3 && currency.chars().all(|c| c.is_ascii_alphabetic())).then(|| currency.to_lowercase())
+}
+
+/// How much of `currency` a US dollar bought each day there are BTC prices for in both,
+/// by `YYYY-MM-DD`.
+pub fn usd_rates(conn: &rusqlite::Connection, currency: &str) -> AppResult<std::collections::BTreeMap<String, f64>> {
+    let mut stmt = conn.prepare(
+        "SELECT c.date, c.price / u.price FROM price_history c
+         JOIN price_history u ON u.date = c.date AND u.currency = 'usd'
+         WHERE c.currency = ?1 AND u.price > 0",
+    )?;
+    let rates = stmt
+        .query_map(rusqlite::params![currency], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(rates)
+}
+
+/// The rate in `rates` for `date`'s day, or failing that the latest before it.
+pub fn rate_on(rates: &std::collections::BTreeMap<String, f64>, date: &str) -> Option<f64> {
+    let day = date.get(..10).unwrap_or(date);
+    rates.range(..=day.to_string()).next_back().map(|(_, rate)| *rate)
+}
+
 /// Get cached prices for a date range.
 pub fn get_cached_prices(
     pool: &DbPool,
@@ -213,7 +288,7 @@ pub async fn backfill_date_range(
     // First, find which dates in the range are missing
     let mut missing_dates: Vec<String> = Vec::new();
     let mut current = start_date.to_string();
-    while current <= end_date.to_string() {
+    while current.as_str() <= end_date {
         missing_dates.push(current.clone());
         // Advance by one day
         if let Ok(date) = chrono::NaiveDate::parse_from_str(&current, "%Y-%m-%d") {
@@ -260,11 +335,12 @@ pub async fn backfill_date_range(
 /// Fetch daily BTC/USD close prices from Kraken's free OHLC API.
 /// Returns a map of YYYY-MM-DD -> close price for all available dates in [start_date, end_date].
 /// Kraken returns up to 720 candles per call; makes additional calls for wider ranges.
+#[tracing::instrument(level = "debug", skip_all, fields(otel.kind = "client"))]
 async fn fetch_kraken_ohlc_range(
     start_date: &str,
     end_date: &str,
 ) -> AppResult<std::collections::HashMap<String, f64>> {
-    let client = Client::new();
+    let client = http::client()?;
     let mut price_map = std::collections::HashMap::new();
 
     let start = chrono::NaiveDate::parse_from_str(start_date, "%Y-%m-%d")
@@ -359,8 +435,9 @@ async fn fetch_kraken_ohlc_range(
 /// Fetch daily BTC/USD prices from blockchain.info (5 years of history, no key required).
 /// Returns a date → price map. Blockchain.info timestamps may be ~1 day off UTC midnight,
 /// so the caller should try adjacent dates if an exact match is missing.
+#[tracing::instrument(level = "debug", skip_all, fields(otel.kind = "client"))]
 async fn fetch_blockchain_info_prices() -> AppResult<std::collections::HashMap<String, f64>> {
-    let client = Client::new();
+    let client = http::client()?;
 
     #[derive(Deserialize)]
     struct Response {
@@ -378,10 +455,10 @@ async fn fetch_blockchain_info_prices() -> AppResult<std::collections::HashMap<S
         .header("Accept", "application/json")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("blockchain.info request failed: {e}")))?
+        .map_err(|e| http::upstream_error("blockchain.info", "blockchain.info request failed", e))?
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("blockchain.info parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("blockchain.info", "blockchain.info parse failed", e))?;
 
     let mut map = std::collections::HashMap::new();
     for point in &resp.values {
@@ -397,10 +474,14 @@ async fn fetch_blockchain_info_prices() -> AppResult<std::collections::HashMap<S
 /// Bulk-backfill prices for a set of (tx_id, date) pairs.
 /// Uses Kraken OHLC for the last ~720 days and blockchain.info for older dates.
 /// Both are free with no API key required.
+///
+/// Transactions in a locked tax year are skipped unless `include_locked`, for a caller
+/// that has already checked the locks for someone allowed past them.
 async fn bulk_backfill_prices(
     pool: &DbPool,
     _api_url: &str,
     rows: &[(String, String)],
+    include_locked: bool,
 ) -> std::collections::HashMap<String, f64> {
     let unique_dates: Vec<String> = {
         let mut seen = std::collections::HashSet::new();
@@ -476,17 +557,18 @@ async fn bulk_backfill_prices(
         .format("%Y-%m-%dT%H:%M:%S%.3fZ")
         .to_string();
     let mut updated = 0usize;
+    let mut sql = "UPDATE transactions SET price_usd = ?1, updated_at = ?2 WHERE id = ?3".to_string();
+    if !include_locked {
+        sql = format!("{sql} AND {}", tax_locks::unlocked("transactions"));
+    }
     if let Ok(conn) = pool.get() {
         for (tx_id, date) in rows {
             if let Some(&price) = date_price.get(date) {
-                if conn
-                    .execute(
-                        "UPDATE transactions SET price_usd = ?1, updated_at = ?2 WHERE id = ?3",
-                        rusqlite::params![price, now, tx_id],
-                    )
-                    .is_ok()
-                {
-                    updated += 1;
+                if let Ok(n) = conn.execute(
+                    &sql,
+                    rusqlite::params![price, now, tx_id],
+                ) {
+                    updated += n;
                 }
             }
         }
@@ -496,6 +578,14 @@ async fn bulk_backfill_prices(
     date_price
 }
 
+/// Price the given (tx_id, date) pairs from historical daily prices, returning how many
+/// were updated. Unlike the portfolio backfill this also overwrites prices of 0, and
+/// prices transactions in locked tax years: the caller checks the locks.
+pub async fn backfill_transactions(pool: &DbPool, api_url: &str, rows: &[(String, String)]) -> usize {
+    let date_price = bulk_backfill_prices(pool, api_url, rows, true).await;
+    rows.iter().filter(|(_, date)| date_price.contains_key(date)).count()
+}
+
 /// Backfill price_usd for all transactions in a wallet that are missing it.
 /// Uses Kraken OHLC for fast bulk fetching; falls back to CoinGecko per-date.
 /// Designed to run as a background task — errors are logged, not propagated.
@@ -538,7 +628,7 @@ pub async fn backfill_wallet_prices(
         rows.len()
     );
 
-    bulk_backfill_prices(&pool, &api_url, &rows).await;
+    bulk_backfill_prices(&pool, &api_url, &rows, false).await;
 }
 
 /// Backfill price_usd for all transactions across every wallet in a portfolio.
@@ -580,7 +670,7 @@ pub async fn backfill_portfolio_prices(pool: DbPool, api_url: String, portfolio_
         rows.len()
     );
 
-    bulk_backfill_prices(&pool, &api_url, &rows).await;
+    bulk_backfill_prices(&pool, &api_url, &rows, false).await;
 }
 
 /// Backfill prices across ALL portfolios at server startup.
@@ -670,3 +760,110 @@ pub async fn backfill_transaction_prices(
 
     Ok(fetched)
 }
+
+// ── Per-transaction lookups ──
+
+/// Batches waiting for the lookup worker; more are dropped until it catches up.
+const LOOKUP_QUEUE_CAPACITY: usize = 64;
+
+/// (tx_id, date) pairs waiting for a per-date price lookup.
+pub type PriceLookups = tokio::sync::mpsc::Sender<Vec<(String, String)>>;
+
+/// Start the worker that prices queued transactions one date at a time through
+/// [`get_or_fetch_price`]. This catches what the bulk backfills miss — dates neither
+/// Kraken nor blockchain.info cover, or a provider that was down — which would
+/// otherwise stay unpriced until someone runs the global backfill.
+pub fn spawn_lookup_worker(pool: DbPool, api_url: String) -> PriceLookups {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<(String, String)>>(LOOKUP_QUEUE_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(rows) = rx.recv().await {
+            let updated = lookup_transaction_prices(&pool, &api_url, &rows).await;
+            if updated > 0 {
+                tracing::info!("Price lookups: priced {updated}/{} transactions", rows.len());
+            }
+        }
+    });
+    tx
+}
+
+/// (tx_id, date) of the given transactions, and of every transaction in the given
+/// wallets, that still have no price.
+pub fn unpriced_transactions(
+    conn: &rusqlite::Connection,
+    transaction_ids: &[String],
+    wallet_ids: &[String],
+) -> AppResult<Vec<(String, String)>> {
+    let mut rows = Vec::new();
+    for (column, ids) in [("id", transaction_ids), ("wallet_id", wallet_ids)] {
+        if ids.is_empty() {
+            continue;
+        }
+        let placeholders = (1..=ids.len()).map(|i| format!("?{i}")).collect::<Vec<_>>().join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, substr(transacted_at, 1, 10) FROM transactions
+             WHERE {column} IN ({placeholders}) AND price_usd IS NULL AND transacted_at IS NOT NULL"
+        ))?;
+        let found = stmt
+            .query_map(rusqlite::params_from_iter(ids), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        rows.extend(found);
+    }
+    Ok(rows)
+}
+
+async fn lookup_transaction_prices(pool: &DbPool, api_url: &str, rows: &[(String, String)]) -> usize {
+    let mut by_date: std::collections::BTreeMap<&str, Vec<&str>> = std::collections::BTreeMap::new();
+    for (tx_id, date) in rows {
+        by_date.entry(date).or_default().push(tx_id);
+    }
+
+    let mut updated = 0usize;
+    for (date, tx_ids) in by_date {
+        // Another path may have priced these or cached the date since they were queued
+        let (pending, cached) = {
+            let Ok(conn) = pool.get() else { return updated };
+            let pending: Vec<&str> = tx_ids
+                .into_iter()
+                .filter(|id| {
+                    conn.query_row(
+                        "SELECT price_usd IS NULL FROM transactions WHERE id = ?1",
+                        rusqlite::params![id],
+                        |row| row.get::<_, bool>(0),
+                    )
+                    .unwrap_or(false)
+                })
+                .collect();
+            (pending, cached_price(&conn, date, "usd").is_some())
+        };
+        if pending.is_empty() {
+            continue;
+        }
+
+        match get_or_fetch_price(pool, api_url, date, "usd").await {
+            Ok(price) => {
+                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+                if let Ok(conn) = pool.get() {
+                    let sql = format!(
+                        "UPDATE transactions SET price_usd = ?1, updated_at = ?2 WHERE id = ?3 AND price_usd IS NULL AND {}",
+                        tax_locks::unlocked("transactions")
+                    );
+                    for tx_id in pending {
+                        if let Ok(n) = conn.execute(
+                            &sql,
+                            rusqlite::params![price, now, tx_id],
+                        ) {
+                            updated += n;
+                        }
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Price lookup for {date} failed: {e}"),
+        }
+
+        // Rate limit: CoinGecko free tier allows ~10-30 req/min
+        if !cached {
+            tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+        }
+    }
+    updated
+}
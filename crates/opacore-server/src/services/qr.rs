@@ -0,0 +1,473 @@
+use std::io::Write;
+
+use crate::error::{AppError, AppResult};
+
+/// Light modules around the code; scanners need at least 4.
+const QUIET_ZONE: usize = 4;
+
+/// Error correction codewords per block at level M, by version.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 40] = [
+    10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26,
+    26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// Error correction blocks at level M, by version.
+const NUM_BLOCKS: [usize; 40] = [
+    1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16,
+    17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// A QR code (ISO/IEC 18004) in byte mode at error correction level M, for payment URIs.
+pub struct QrCode {
+    size: usize,
+    /// Row-major, `true` for dark.
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that fits.
+    pub fn encode(data: &[u8]) -> AppResult<Self> {
+        let version = (1..=40)
+            .find(|&v| 4 + char_count_bits(v) + data.len() * 8 <= num_data_codewords(v) * 8)
+            .ok_or_else(|| AppError::Internal("Too much data for a QR code".into()))?;
+
+        // Mode indicator (byte), character count, data, then terminator and padding
+        let mut bits = BitBuffer::default();
+        bits.push(0b0100, 4);
+        bits.push(data.len() as u32, char_count_bits(version));
+        for &b in data {
+            bits.push(b as u32, 8);
+        }
+        let capacity = num_data_codewords(version) * 8;
+        bits.push(0, (capacity - bits.len()).min(4));
+        bits.push(0, (8 - bits.len() % 8) % 8);
+        for pad in [0xEC, 0x11].into_iter().cycle() {
+            if bits.len() >= capacity {
+                break;
+            }
+            bits.push(pad, 8);
+        }
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&add_ecc_and_interleave(&bits.into_bytes(), version));
+
+        // Keep the mask that leaves the fewest scanner-confusing patterns
+        let mut best = (u32::MAX, 0);
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+            let penalty = qr.penalty();
+            if penalty < best.0 {
+                best = (penalty, mask);
+            }
+            qr.apply_mask(mask);
+        }
+        qr.apply_mask(best.1);
+        qr.draw_format_bits(best.1);
+        Ok(qr)
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, dist != 2 && dist != 4);
+                    }
+                }
+            }
+        }
+
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &cx) in positions.iter().enumerate() {
+            for (j, &cy) in positions.iter().enumerate() {
+                // These would overlap the finder patterns
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let (x, y) = ((cx as i32 + dx) as usize, (cy as i32 + dy) as usize);
+                        self.set_function(x, y, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserve the format areas; the real bits are drawn once the mask is chosen
+        self.draw_format_bits(0);
+
+        if version >= 7 {
+            let mut rem = version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+            }
+            let bits = (version as u32) << 12 | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, dark);
+                self.set_function(b, a, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        // Level M is 0b00
+        let data = mask;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = (data << 10 | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        let size = self.size;
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Place codewords in the zigzag of two-module columns, right to left.
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !self.is_function[y * size + x] && i < data.len() * 8 {
+                        self.modules[y * size + x] = (data[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// XOR the mask pattern over the data modules; applying it twice undoes it.
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let idx = y * self.size + x;
+                if invert && !self.is_function[idx] {
+                    self.modules[idx] = !self.modules[idx];
+                }
+            }
+        }
+    }
+
+    /// The standard mask evaluation: long runs, 2x2 blocks, finder-like patterns and
+    /// dark/light imbalance all score against a mask.
+    fn penalty(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+
+        let lines = |horizontal: bool| {
+            (0..size).map(move |a| {
+                (0..size)
+                    .map(|b| if horizontal { self.get(b, a) } else { self.get(a, b) })
+                    .collect::<Vec<_>>()
+            })
+        };
+        for line in lines(true).chain(lines(false)) {
+            let mut run = 1;
+            for i in 1..=size {
+                if i < size && line[i] == line[i - 1] {
+                    run += 1;
+                    continue;
+                }
+                if run >= 5 {
+                    penalty += 3 + (run - 5) as u32;
+                }
+                run = 1;
+            }
+            const FINDER_LIKE: [[bool; 11]; 2] = [
+                [true, false, true, true, true, false, true, false, false, false, false],
+                [false, false, false, false, true, false, true, true, true, false, true],
+            ];
+            for window in line.windows(11) {
+                if FINDER_LIKE.iter().any(|p| p == window) {
+                    penalty += 40;
+                }
+            }
+        }
+
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let c = self.get(x, y);
+                if c == self.get(x + 1, y) && c == self.get(x, y + 1) && c == self.get(x + 1, y + 1) {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().filter(|&&m| m).count();
+        let percent = dark * 100 / (size * size);
+        penalty += (percent.abs_diff(50) / 5) as u32 * 10;
+        penalty
+    }
+
+    /// Modules per side, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at `(x, y)` is dark, counting from the top left.
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.get(x, y)
+    }
+
+    /// Scalable SVG, one unit per module.
+    pub fn to_svg(&self) -> String {
+        let full = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.get(x, y) {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+                }
+            }
+        }
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {full} {full}\" shape-rendering=\"crispEdges\">\
+             <rect width=\"{full}\" height=\"{full}\" fill=\"#fff\"/><path d=\"{path}\" fill=\"#000\"/></svg>"
+        )
+    }
+
+    /// Greyscale PNG with `scale` pixels per module.
+    pub fn to_png(&self, scale: usize) -> AppResult<Vec<u8>> {
+        let width = (self.size + QUIET_ZONE * 2) * scale;
+        let mut raw = Vec::with_capacity((width + 1) * width);
+        for py in 0..width {
+            // Filter type 0 (none) per scanline
+            raw.push(0);
+            for px in 0..width {
+                let (x, y) = ((px / scale).wrapping_sub(QUIET_ZONE), (py / scale).wrapping_sub(QUIET_ZONE));
+                let dark = x < self.size && y < self.size && self.get(x, y);
+                raw.push(if dark { 0x00 } else { 0xFF });
+            }
+        }
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(&raw)
+            .map_err(|e| AppError::Internal(format!("Failed to compress QR code: {e}")))?;
+        let idat = encoder
+            .finish()
+            .map_err(|e| AppError::Internal(format!("Failed to compress QR code: {e}")))?;
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+        // 8-bit greyscale, default compression and filtering, no interlace
+        ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(b"IHDR", ihdr.as_slice()), (b"IDAT", idat.as_slice()), (b"IEND", &[])] {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            let mut crc = flate2::Crc::new();
+            crc.update(kind);
+            crc.update(data);
+            png.extend_from_slice(&crc.sum().to_be_bytes());
+        }
+        Ok(png)
+    }
+}
+
+#[derive(Default)]
+struct BitBuffer {
+    bits: Vec<bool>,
+}
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, len: usize) {
+        for i in (0..len).rev() {
+            self.bits.push((value >> i) & 1 != 0);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bits
+            .chunks(8)
+            .map(|chunk| chunk.iter().fold(0u8, |byte, &bit| byte << 1 | bit as u8))
+            .collect()
+    }
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version <= 9 { 8 } else { 16 }
+}
+
+/// Modules available for data and error correction once the function patterns are placed.
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize) -> usize {
+    num_raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version - 1] * NUM_BLOCKS[version - 1]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let num_align = version / 7 + 2;
+    let step = if version == 32 {
+        26
+    } else {
+        (version * 4 + num_align * 2 + 1) / (num_align * 2 - 2) * 2
+    };
+    let mut result = vec![6];
+    let mut pos = version * 4 + 10;
+    for _ in 0..num_align - 1 {
+        result.insert(1, pos);
+        pos -= step;
+    }
+    result
+}
+
+/// Split the data into blocks, append each block's Reed-Solomon codewords and interleave.
+fn add_ecc_and_interleave(data: &[u8], version: usize) -> Vec<u8> {
+    let num_blocks = NUM_BLOCKS[version - 1];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version - 1];
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = rs_divisor(ecc_len);
+    let mut blocks = Vec::with_capacity(num_blocks);
+    let mut k = 0;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - ecc_len + usize::from(i >= num_short_blocks);
+        let mut block = data[k..k + data_len].to_vec();
+        k += data_len;
+        let ecc = rs_remainder(&block, &divisor);
+        if i < num_short_blocks {
+            // Placeholder so every block has the same length; skipped when interleaving
+            block.push(0);
+        }
+        block.extend_from_slice(&ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
@@ -0,0 +1,185 @@
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+
+/// A limited per-user resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quota {
+    Portfolios,
+    Wallets,
+    /// Emails sent on the user's behalf per calendar month.
+    MonthlyEmails,
+    WatchedAddresses,
+    ApiKeys,
+    WebhookEndpoints,
+}
+
+const ALL_QUOTAS: [Quota; 6] = [
+    Quota::Portfolios,
+    Quota::Wallets,
+    Quota::MonthlyEmails,
+    Quota::WatchedAddresses,
+    Quota::ApiKeys,
+    Quota::WebhookEndpoints,
+];
+
+impl Quota {
+    pub fn name(self) -> &'static str {
+        match self {
+            Quota::Portfolios => "portfolios",
+            Quota::Wallets => "wallets",
+            Quota::MonthlyEmails => "monthly_emails",
+            Quota::WatchedAddresses => "watched_addresses",
+            Quota::ApiKeys => "api_keys",
+            Quota::WebhookEndpoints => "webhook_endpoints",
+        }
+    }
+
+    /// Column in `user_quotas` holding the per-user override.
+    fn column(self) -> &'static str {
+        match self {
+            Quota::Portfolios => "max_portfolios",
+            Quota::Wallets => "max_wallets",
+            Quota::MonthlyEmails => "monthly_emails",
+            Quota::WatchedAddresses => "max_watched_addresses",
+            Quota::ApiKeys => "max_api_keys",
+            Quota::WebhookEndpoints => "max_webhook_endpoints",
+        }
+    }
+
+    fn describe(self, limit: u32) -> String {
+        match self {
+            Quota::Portfolios => format!("at most {limit} portfolios"),
+            Quota::Wallets => format!("at most {limit} wallets"),
+            Quota::MonthlyEmails => format!("at most {limit} emails per month"),
+            Quota::WatchedAddresses => format!("at most {limit} watched addresses"),
+            Quota::ApiKeys => format!("at most {limit} API keys"),
+            Quota::WebhookEndpoints => format!("at most {limit} webhook endpoints"),
+        }
+    }
+
+    fn default_limit(self, config: &Config) -> Option<u32> {
+        match self {
+            Quota::Portfolios => config.quotas.max_portfolios,
+            Quota::Wallets => config.quotas.max_wallets,
+            Quota::MonthlyEmails => config.quotas.monthly_emails,
+            Quota::WatchedAddresses => config.quotas.max_watched_addresses,
+            Quota::ApiKeys => config.quotas.max_api_keys,
+            Quota::WebhookEndpoints => config.quotas.max_webhook_endpoints,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuotaStatus {
+    pub quota: &'static str,
+    /// `None` if unlimited.
+    pub limit: Option<u32>,
+    pub used: u32,
+}
+
+/// Effective limit for a user: their override if set, else the configured default.
+pub fn limit(conn: &rusqlite::Connection, config: &Config, user_id: &str, quota: Quota) -> AppResult<Option<u32>> {
+    let sql = format!("SELECT {} FROM user_quotas WHERE user_id = ?1", quota.column());
+    let override_value: Option<i64> = match conn.query_row(&sql, rusqlite::params![user_id], |row| row.get(0)) {
+        Ok(v) => v,
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(AppError::Database(e)),
+    };
+
+    Ok(match override_value {
+        Some(v) if v < 0 => None,
+        Some(v) => Some(v as u32),
+        None => quota.default_limit(config),
+    })
+}
+
+/// Current usage of a quota.
+pub fn usage(conn: &rusqlite::Connection, user_id: &str, quota: Quota) -> AppResult<u32> {
+    let used: i64 = match quota {
+        Quota::Portfolios => conn.query_row(
+            "SELECT COUNT(*) FROM portfolios WHERE user_id = ?1",
+            rusqlite::params![user_id],
+            |row| row.get(0),
+        )?,
+        Quota::Wallets => conn.query_row(
+            "SELECT COUNT(*) FROM wallets w JOIN portfolios p ON p.id = w.portfolio_id WHERE p.user_id = ?1",
+            rusqlite::params![user_id],
+            |row| row.get(0),
+        )?,
+        Quota::MonthlyEmails => conn
+            .query_row(
+                "SELECT count FROM usage_counters WHERE user_id = ?1 AND resource = ?2 AND period = ?3",
+                rusqlite::params![user_id, quota.name(), current_period()],
+                |row| row.get(0),
+            )
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(0),
+                e => Err(e),
+            })?,
+        Quota::WatchedAddresses => conn.query_row(
+            "SELECT COUNT(*) FROM watched_addresses WHERE user_id = ?1",
+            rusqlite::params![user_id],
+            |row| row.get(0),
+        )?,
+        Quota::ApiKeys => conn.query_row(
+            "SELECT COUNT(*) FROM api_keys WHERE user_id = ?1",
+            rusqlite::params![user_id],
+            |row| row.get(0),
+        )?,
+        Quota::WebhookEndpoints => conn.query_row(
+            "SELECT COUNT(*) FROM webhook_endpoints WHERE user_id = ?1",
+            rusqlite::params![user_id],
+            |row| row.get(0),
+        )?,
+    };
+    Ok(used as u32)
+}
+
+/// Fail with 403 if creating one more of `quota`'s resource would exceed the limit.
+pub fn check(conn: &rusqlite::Connection, config: &Config, user_id: &str, quota: Quota) -> AppResult<()> {
+    let Some(limit) = limit(conn, config, user_id, quota)? else {
+        return Ok(());
+    };
+    if usage(conn, user_id, quota)? >= limit {
+        return Err(AppError::Forbidden(format!(
+            "Quota exceeded: your account is limited to {}",
+            quota.describe(limit)
+        )));
+    }
+    Ok(())
+}
+
+/// Count an email against the user's monthly quota. Returns false (and counts
+/// nothing) if the quota is used up, in which case the email should not be sent.
+pub fn consume_email(conn: &rusqlite::Connection, config: &Config, user_id: &str) -> AppResult<bool> {
+    if check(conn, config, user_id, Quota::MonthlyEmails).is_err() {
+        tracing::warn!("Monthly email quota reached for user {user_id}");
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT INTO usage_counters (user_id, resource, period, count) VALUES (?1, ?2, ?3, 1)
+         ON CONFLICT(user_id, resource, period) DO UPDATE SET count = count + 1",
+        rusqlite::params![user_id, Quota::MonthlyEmails.name(), current_period()],
+    )?;
+    Ok(true)
+}
+
+/// Limits and usage of every quota for a user.
+pub fn statuses(conn: &rusqlite::Connection, config: &Config, user_id: &str) -> AppResult<Vec<QuotaStatus>> {
+    ALL_QUOTAS
+        .iter()
+        .map(|&quota| {
+            Ok(QuotaStatus {
+                quota: quota.name(),
+                limit: limit(conn, config, user_id, quota)?,
+                used: usage(conn, user_id, quota)?,
+            })
+        })
+        .collect()
+}
+
+fn current_period() -> String {
+    chrono::Utc::now().format("%Y-%m").to_string()
+}
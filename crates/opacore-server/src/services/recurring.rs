@@ -0,0 +1,266 @@
+use chrono::{DateTime, Months, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::events::{self, DomainEvent};
+use crate::services::prices;
+use crate::services::transaction_audit;
+
+/// Occurrences one schedule materializes per round, so a schedule started far in the
+/// past catches up over several rounds instead of holding up the others.
+const CATCH_UP_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+            Frequency::Monthly => "monthly",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "daily" => Some(Frequency::Daily),
+            "weekly" => Some(Frequency::Weekly),
+            "monthly" => Some(Frequency::Monthly),
+            _ => None,
+        }
+    }
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+/// Occurrence `n` (from 0) of a schedule. Monthly occurrences keep the start's day of
+/// the month, falling back to the month's last day when it's shorter.
+fn occurrence_at(starts_at: &str, frequency: Frequency, n: i64) -> Option<DateTime<Utc>> {
+    let start = DateTime::parse_from_rfc3339(starts_at).ok()?.with_timezone(&Utc);
+    match frequency {
+        Frequency::Daily => start.checked_add_signed(chrono::Duration::days(n)),
+        Frequency::Weekly => start.checked_add_signed(chrono::Duration::weeks(n)),
+        Frequency::Monthly => start.checked_add_months(Months::new(u32::try_from(n).ok()?)),
+    }
+}
+
+/// When the schedule runs next after `occurrences` have been materialized, or `None`
+/// once that would be past `ends_at`.
+pub fn next_run_at(starts_at: &str, frequency: Frequency, occurrences: i64, ends_at: Option<&str>) -> Option<String> {
+    let at = timestamp(occurrence_at(starts_at, frequency, occurrences)?);
+    match ends_at {
+        Some(end) if at.as_str() > end => None,
+        _ => Some(at),
+    }
+}
+
+/// The number of occurrences to skip to so the next one isn't before `now`: resuming a
+/// paused schedule doesn't back-fill what it missed meanwhile.
+pub fn skip_missed(starts_at: &str, frequency: Frequency, occurrences: i64, now: &str) -> i64 {
+    let mut n = occurrences;
+    while occurrence_at(starts_at, frequency, n).is_some_and(|at| timestamp(at).as_str() < now) {
+        n += 1;
+    }
+    n
+}
+
+// ── Materializing ──
+
+struct Schedule {
+    id: String,
+    portfolio_id: String,
+    wallet_id: Option<String>,
+    user_id: Option<String>,
+    tx_type: String,
+    amount_sat: Option<i64>,
+    fiat_amount: Option<f64>,
+    fiat_currency: String,
+    frequency: Frequency,
+    starts_at: String,
+    ends_at: Option<String>,
+    occurrences: i64,
+    next_run_at: String,
+    updated_at: String,
+}
+
+fn due_schedules(pool: &DbPool, now: &str) -> AppResult<Vec<Schedule>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, portfolio_id, wallet_id, user_id, tx_type, amount_sat, fiat_amount, fiat_currency,
+                frequency, starts_at, ends_at, occurrences, next_run_at, updated_at
+         FROM recurring_transactions
+         WHERE is_active = 1 AND next_run_at IS NOT NULL AND next_run_at <= ?1
+         ORDER BY next_run_at",
+    )?;
+    let rows = stmt
+        .query_map(rusqlite::params![now], |row| {
+            let frequency: String = row.get(8)?;
+            Ok(Schedule {
+                id: row.get(0)?,
+                portfolio_id: row.get(1)?,
+                wallet_id: row.get(2)?,
+                user_id: row.get(3)?,
+                tx_type: row.get(4)?,
+                amount_sat: row.get(5)?,
+                fiat_amount: row.get(6)?,
+                fiat_currency: row.get(7)?,
+                frequency: Frequency::parse(&frequency).ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        8,
+                        rusqlite::types::Type::Text,
+                        format!("unknown frequency '{frequency}'").into(),
+                    )
+                })?,
+                starts_at: row.get(9)?,
+                ends_at: row.get(10)?,
+                occurrences: row.get(11)?,
+                next_run_at: row.get(12)?,
+                updated_at: row.get(13)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// BTC price on `date`, from the cache or fetched at the pace the price API allows.
+async fn price_on(pool: &DbPool, api_url: &str, date: &str, currency: &str) -> AppResult<f64> {
+    let cached = {
+        let conn = pool.get()?;
+        prices::cached_price(&conn, date, currency)
+    };
+    if let Some(price) = cached {
+        return Ok(price);
+    }
+    let price = prices::get_or_fetch_price(pool, api_url, date, currency).await;
+    // Rate limit: CoinGecko free tier allows ~10-30 req/min
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    price
+}
+
+/// Materialize the occurrence at `schedule.next_run_at`. Returns `false` if the
+/// schedule was changed or removed meanwhile, in which case nothing is written.
+async fn materialize_next(pool: &DbPool, api_url: &str, schedule: &mut Schedule) -> AppResult<bool> {
+    let at = schedule.next_run_at.clone();
+    let date = &at[..10];
+    let in_usd = schedule.fiat_currency == "usd";
+
+    let (amount_sat, price_usd, fiat_amount) = match (schedule.amount_sat, schedule.fiat_amount) {
+        // A fixed amount can go in unpriced; the event dispatcher looks the price up later
+        (Some(amount_sat), _) => {
+            let price_usd = price_on(pool, api_url, date, "usd")
+                .await
+                .inspect_err(|e| tracing::warn!("Recurring {}: no price for {date} yet: {e}", schedule.id))
+                .ok();
+            let fiat_price = if in_usd {
+                price_usd
+            } else {
+                price_on(pool, api_url, date, &schedule.fiat_currency).await.ok()
+            };
+            (amount_sat, price_usd, fiat_price.map(|p| amount_sat as f64 / 1e8 * p))
+        }
+        // A fixed spend can't be converted without the price; retried next round
+        (None, Some(fiat_amount)) => {
+            let price = price_on(pool, api_url, date, &schedule.fiat_currency).await?;
+            let price_usd = if in_usd {
+                Some(price)
+            } else {
+                price_on(pool, api_url, date, "usd").await.ok()
+            };
+            ((fiat_amount / price * 1e8).round() as i64, price_usd, Some(fiat_amount))
+        }
+        (None, None) => return Err(AppError::Internal("Recurring transaction has no amount".into())),
+    };
+
+    let occurrences = schedule.occurrences + 1;
+    let next = next_run_at(&schedule.starts_at, schedule.frequency, occurrences, schedule.ends_at.as_deref());
+    let now = timestamp(Utc::now());
+    let tx_id = uuid::Uuid::new_v4().to_string();
+
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let advanced = db_tx.execute(
+        "UPDATE recurring_transactions SET occurrences = ?1, next_run_at = ?2, last_error = NULL, updated_at = ?3
+         WHERE id = ?4 AND is_active = 1 AND occurrences = ?5 AND updated_at = ?6",
+        rusqlite::params![occurrences, next, now, schedule.id, schedule.occurrences, schedule.updated_at],
+    )?;
+    if advanced == 0 {
+        return Ok(false);
+    }
+    db_tx.execute(
+        "INSERT INTO transactions (id, portfolio_id, wallet_id, tx_type, amount_sat, price_usd, fiat_amount, fiat_currency, source, transacted_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 'recurring', ?9, ?10, ?11)",
+        rusqlite::params![
+            tx_id, schedule.portfolio_id, schedule.wallet_id, schedule.tx_type,
+            amount_sat, price_usd, fiat_amount, schedule.fiat_currency, at, now, now
+        ],
+    )?;
+    events::record(
+        &db_tx,
+        &schedule.portfolio_id,
+        &DomainEvent::TransactionCreated { transaction_id: tx_id.clone(), wallet_id: schedule.wallet_id.clone() },
+    )?;
+    transaction_audit::record_created(&db_tx, &schedule.portfolio_id, &tx_id, schedule.user_id.as_deref())?;
+    db_tx.commit()?;
+
+    schedule.occurrences = occurrences;
+    schedule.updated_at = now;
+    match next {
+        Some(next) => schedule.next_run_at = next,
+        // Ended: an empty next_run_at is never due
+        None => schedule.next_run_at.clear(),
+    }
+    Ok(true)
+}
+
+/// Turn every due occurrence into a transaction. Returns how many were created.
+pub async fn materialize_due(pool: &DbPool, api_url: &str) -> AppResult<usize> {
+    let now = timestamp(Utc::now());
+    let mut created = 0;
+    for mut schedule in due_schedules(pool, &now)? {
+        for _ in 0..CATCH_UP_LIMIT {
+            if schedule.next_run_at.is_empty() || schedule.next_run_at > now {
+                break;
+            }
+            match materialize_next(pool, api_url, &mut schedule).await {
+                Ok(true) => created += 1,
+                Ok(false) => break,
+                Err(e) => {
+                    tracing::warn!("Recurring {}: occurrence at {} failed: {e}", schedule.id, schedule.next_run_at);
+                    let conn = pool.get()?;
+                    conn.execute(
+                        "UPDATE recurring_transactions SET last_error = ?1 WHERE id = ?2",
+                        rusqlite::params![e.to_string(), schedule.id],
+                    )?;
+                    break;
+                }
+            }
+        }
+    }
+    Ok(created)
+}
+
+/// Background task that materializes due recurring transactions.
+pub async fn run_recurring_materializer(pool: DbPool, config: Config) {
+    tracing::info!(
+        "Recurring transaction materializer started (interval: {}s)",
+        config.intervals.recurring_check.as_secs()
+    );
+    loop {
+        match materialize_due(&pool, &config.coingecko_api_url).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Materialized {n} recurring transactions"),
+            Err(e) => tracing::warn!("Recurring transaction round failed: {e}"),
+        }
+        tokio::time::sleep(config.intervals.recurring_check).await;
+    }
+}
@@ -1,12 +1,19 @@
+use std::collections::HashMap;
+
 use bdk_esplora::EsploraAsyncExt;
-use bdk_wallet::chain::ChainPosition;
+use bdk_wallet::bitcoin::{Address, Transaction, Txid};
+use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse};
+use bdk_wallet::chain::{ChainPosition, SpkIterator};
 use bdk_wallet::rusqlite::Connection as BdkConnection;
-use bdk_wallet::PersistedWallet;
+use bdk_wallet::{KeychainKind, PersistedWallet};
 use esplora_client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 
 use crate::db::DbPool;
 use crate::error::{AppError, AppResult};
+use crate::services::{http, transfers};
+use crate::services::sync_progress::SyncEvent;
 
 const PARALLEL_REQUESTS: usize = 1;
 
@@ -16,56 +23,134 @@ pub struct SyncResult {
     pub new_transactions: usize,
     pub balance_sat: u64,
     pub last_sync_height: Option<u32>,
+    pub mempool: MempoolChanges,
 }
 
-/// Run a full chain scan for a wallet and store discovered transactions
-/// in the application database.
-pub async fn full_scan(
-    wallet: &mut PersistedWallet<BdkConnection>,
-    bdk_conn: &mut BdkConnection,
+/// Number of derivation indexes scanned per keychain before progress is persisted.
+pub const SCAN_CHUNK_SIZE: u32 = 100;
+
+/// Position of a checkpointed full scan: the keychain being scanned, the first
+/// index not yet scanned on it, and the last index seen with activity.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanCheckpoint {
+    pub keychain: KeychainKind,
+    pub next_index: u32,
+    pub last_active_index: Option<u32>,
+    pub finished: bool,
+}
+
+impl ScanCheckpoint {
+    /// Record a scanned chunk and move on to the next one. Once `stop_gap` unused
+    /// indexes follow the last active one, the scan moves to the internal keychain,
+    /// and after that it's finished.
+    pub fn advance(&mut self, chunk_last_active: Option<u32>, stop_gap: u32) {
+        if chunk_last_active.is_some() {
+            self.last_active_index = chunk_last_active;
+        }
+        self.next_index = self.next_index.saturating_add(SCAN_CHUNK_SIZE);
+
+        let unused_run = match self.last_active_index {
+            Some(i) => self.next_index.saturating_sub(i + 1),
+            None => self.next_index,
+        };
+        if unused_run < stop_gap.max(1) {
+            return;
+        }
+
+        match self.keychain {
+            KeychainKind::External => {
+                self.keychain = KeychainKind::Internal;
+                self.next_index = 0;
+                self.last_active_index = None;
+            }
+            KeychainKind::Internal => self.finished = true,
+        }
+    }
+}
+
+/// Scan one chunk of derivation indexes on a single keychain against one Esplora
+/// backend. Nothing is applied to the wallet, so a failed chunk can be retried on
+/// another backend. Each script pubkey scanned is published on `progress`.
+pub async fn fetch_scan_chunk(
+    wallet: &bdk_wallet::Wallet,
     esplora_url: &str,
-    stop_gap: usize,
-    app_pool: &DbPool,
+    checkpoint: ScanCheckpoint,
     app_wallet_id: &str,
-    portfolio_id: &str,
-) -> AppResult<SyncResult> {
+    progress: &broadcast::Sender<SyncEvent>,
+) -> AppResult<FullScanResponse<KeychainKind>> {
     let client = esplora_client::Builder::new(esplora_url)
+        .timeout(http::UPSTREAM_TIMEOUT.as_secs())
         .build_async()
         .map_err(|e| AppError::Internal(format!("Failed to build Esplora client: {e}")))?;
 
-    tracing::info!("Starting full scan for wallet {app_wallet_id} via {esplora_url}");
+    let ScanCheckpoint { keychain, next_index, .. } = checkpoint;
+    let end = next_index.saturating_add(SCAN_CHUNK_SIZE);
+    tracing::debug!(
+        "Wallet {app_wallet_id}: scanning keychain {keychain:?} indexes {next_index}..{end} via {esplora_url}"
+    );
 
-    let request = wallet.start_full_scan().inspect({
-        let wallet_id = app_wallet_id.to_string();
-        let mut last_keychain = None;
-        move |keychain, spk_i, _| {
-            if last_keychain != Some(keychain) {
-                tracing::debug!("Wallet {wallet_id}: scanning keychain {keychain:?}");
-                last_keychain = Some(keychain);
-            }
-            if spk_i % 10 == 0 {
-                tracing::debug!("Wallet {wallet_id}: keychain {keychain:?} index {spk_i}");
+    let spks = SpkIterator::new_with_range(wallet.public_descriptor(keychain).clone(), next_index..end);
+    let request = FullScanRequest::builder()
+        .chain_tip(wallet.latest_checkpoint())
+        .spks_for_keychain(keychain, spks)
+        .inspect({
+            let wallet_id = app_wallet_id.to_string();
+            let progress = progress.clone();
+            let txs_found = wallet.transactions().count();
+            move |keychain, spk_i, _| {
+                if spk_i % 10 == 0 {
+                    tracing::debug!("Wallet {wallet_id}: keychain {keychain:?} index {spk_i}");
+                }
+                let _ = progress.send(SyncEvent::Progress {
+                    keychain: keychain_name(keychain).to_string(),
+                    index: spk_i,
+                    txs_found,
+                });
             }
-        }
-    });
+        });
 
-    let update = client
-        .full_scan(request, stop_gap, PARALLEL_REQUESTS)
+    // The chunk bounds the scan; the stop gap is applied across chunks by ScanCheckpoint,
+    // so don't let the client stop early on indexes relative to zero.
+    client
+        .full_scan(request, u32::MAX as usize, PARALLEL_REQUESTS)
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora full scan failed: {e}")))?;
+        .map_err(|e| match *e {
+            esplora_client::Error::Reqwest(e) => http::upstream_error("esplora", "Esplora full scan failed", e),
+            e => AppError::Internal(format!("Esplora full scan failed: {e}")),
+        })
+}
 
+/// Apply a scanned chunk to the BDK wallet and persist it, so the chunk survives
+/// an interrupted sync.
+pub fn apply_scan_chunk(
+    wallet: &mut PersistedWallet<BdkConnection>,
+    bdk_conn: &mut BdkConnection,
+    update: FullScanResponse<KeychainKind>,
+) -> AppResult<()> {
     wallet.apply_update(update)
         .map_err(|e| AppError::Internal(format!("Failed to apply scan update: {e}")))?;
 
     wallet.persist(bdk_conn)
         .map_err(|e| AppError::Internal(format!("Failed to persist BDK wallet: {e}")))?;
 
+    Ok(())
+}
+
+/// Store the BDK wallet's transactions in the application database and update
+/// the wallet's sync metadata. Run once a scan has finished.
+pub fn record_wallet_transactions(
+    wallet: &bdk_wallet::Wallet,
+    app_pool: &DbPool,
+    app_wallet_id: &str,
+    portfolio_id: &str,
+) -> AppResult<SyncResult> {
     // Extract transactions and store in app DB
     let balance = wallet.balance();
     let txs: Vec<_> = wallet.transactions().collect();
     let total_txs = txs.len();
 
     let mut new_tx_count = 0;
+    let mut confirmed_count = 0;
     let mut max_height: Option<u32> = None;
 
     let app_conn = app_pool.get()?;
@@ -74,22 +159,11 @@ pub async fn full_scan(
         let tx = &wallet_tx.tx_node.tx;
         let txid = tx.compute_txid().to_string();
 
-        // Check if this transaction already exists in the app DB
-        let exists: bool = app_conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM transactions WHERE txid = ?1 AND wallet_id = ?2)",
-            rusqlite::params![txid, app_wallet_id],
-            |row| row.get(0),
-        )?;
-
-        if exists {
-            continue;
-        }
-
         // Determine confirmation status
         let (block_height, block_time) = match &wallet_tx.chain_position {
             ChainPosition::Confirmed { anchor, .. } => {
                 let height = anchor.block_id.height;
-                if max_height.map_or(true, |h| height > h) {
+                if max_height.is_none_or(|h| height > h) {
                     max_height = Some(height);
                 }
                 (Some(height as i64), Some(
@@ -101,6 +175,22 @@ pub async fn full_scan(
             ChainPosition::Unconfirmed { .. } => (None, None),
         };
 
+        // Check if this transaction already exists in the app DB
+        if let Some(existing_id) = stored_tx_id(&app_conn, app_wallet_id, &txid)? {
+            // A previously pending transaction may have confirmed since the last sync
+            if let Some(height) = block_height {
+                if mark_confirmed(&app_conn, app_wallet_id, &txid, height, block_time.as_deref())? {
+                    confirmed_count += 1;
+                }
+            }
+            // Rows synced before inputs/outputs were recorded get them now
+            if !has_tx_details(&app_conn, &existing_id)? {
+                let (inputs, outputs) = wallet_tx_details(wallet, tx);
+                store_tx_details(&app_conn, &existing_id, &inputs, &outputs)?;
+            }
+            continue;
+        }
+
         // Calculate net amount for this wallet using sent_and_received
         let (sent, received) = wallet.sent_and_received(tx);
         let sent_sat = sent.to_sat() as i64;
@@ -131,10 +221,16 @@ pub async fn full_scan(
                 block_height, block_time, transacted_at, now, now
             ],
         )?;
+        let (inputs, outputs) = wallet_tx_details(wallet, tx);
+        store_tx_details(&app_conn, &tx_id, &inputs, &outputs)?;
 
         new_tx_count += 1;
     }
 
+    if new_tx_count > 0 {
+        transfers::link_by_txid(&app_conn, portfolio_id, None)?;
+    }
+
     // Update wallet sync metadata in app DB
     let now = chrono::Utc::now()
         .format("%Y-%m-%dT%H:%M:%S%.3fZ")
@@ -155,9 +251,571 @@ pub async fn full_scan(
         new_transactions: new_tx_count,
         balance_sat: balance_total,
         last_sync_height: max_height,
+        mempool: MempoolChanges {
+            confirmed: confirmed_count,
+            ..Default::default()
+        },
     })
 }
 
+// ── Mempool reconciliation ──
+
+/// What happened to a wallet's pending (unconfirmed) transactions during a sync.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct MempoolChanges {
+    /// Pending transactions that have since confirmed.
+    pub confirmed: usize,
+    /// Pending transactions replaced by a fee-bumped version (RBF).
+    pub replaced: usize,
+    /// Pending transactions dropped from the mempool without a replacement.
+    pub evicted: usize,
+}
+
+/// Current state of a transaction that was pending at the last sync.
+#[derive(Debug, Clone)]
+pub enum PendingTxStatus {
+    Pending,
+    Confirmed { block_height: i64, block_time: Option<String> },
+    /// The backend no longer knows the transaction: dropped or replaced.
+    Gone,
+}
+
+/// Chain transactions stored for a wallet that haven't confirmed yet.
+pub fn pending_txids(app_pool: &DbPool, app_wallet_id: &str) -> AppResult<Vec<String>> {
+    let conn = app_pool.get()?;
+    let mut stmt = conn.prepare(
+        "SELECT txid FROM transactions
+         WHERE wallet_id = ?1 AND source = 'chain' AND block_height IS NULL AND txid IS NOT NULL",
+    )?;
+    let txids = stmt
+        .query_map(rusqlite::params![app_wallet_id], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(txids)
+}
+
+/// Look up each pending transaction on one Esplora backend.
+pub async fn fetch_pending_statuses(
+    esplora_url: &str,
+    txids: &[String],
+) -> AppResult<HashMap<String, PendingTxStatus>> {
+    let http = http::client()?;
+
+    let mut statuses = HashMap::new();
+    for txid in txids {
+        let url = format!("{esplora_url}/tx/{txid}/status");
+        let resp = http
+            .get(&url)
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .map_err(|e| http::upstream_error("esplora", format!("Esplora request failed for {url}"), e))?;
+
+        let status = if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            PendingTxStatus::Gone
+        } else if !resp.status().is_success() {
+            let status = resp.status();
+            return Err(AppError::Internal(format!("Esplora returned {status} for {url}")));
+        } else {
+            let status: EsploraTxStatus = resp
+                .json()
+                .await
+                .map_err(|e| http::upstream_error("esplora", "Esplora response parse failed", e))?;
+            pending_status(&status)
+        };
+        statuses.insert(txid.clone(), status);
+    }
+
+    Ok(statuses)
+}
+
+fn pending_status(status: &EsploraTxStatus) -> PendingTxStatus {
+    match (status.confirmed, status.block_height) {
+        (true, Some(height)) => PendingTxStatus::Confirmed {
+            block_height: height as i64,
+            block_time: status.block_time.map(format_unix_time),
+        },
+        _ => PendingTxStatus::Pending,
+    }
+}
+
+/// Apply pending-transaction statuses for a BDK wallet: record confirmations,
+/// link RBF replacements to the transaction they replaced, and drop evicted
+/// transactions from both the BDK wallet and the transactions table.
+pub fn reconcile_wallet_mempool(
+    wallet: &mut PersistedWallet<BdkConnection>,
+    bdk_conn: &mut BdkConnection,
+    app_pool: &DbPool,
+    app_wallet_id: &str,
+    statuses: &HashMap<String, PendingTxStatus>,
+    result: &mut SyncResult,
+) -> AppResult<()> {
+    let app_conn = app_pool.get()?;
+    let now_unix = chrono::Utc::now().timestamp() as u64;
+    let mut evicted = Vec::new();
+
+    for (txid, status) in statuses {
+        match status {
+            PendingTxStatus::Pending => {}
+            PendingTxStatus::Confirmed { block_height, block_time } => {
+                if mark_confirmed(&app_conn, app_wallet_id, txid, *block_height, block_time.as_deref())? {
+                    result.mempool.confirmed += 1;
+                }
+            }
+            PendingTxStatus::Gone => {
+                let Ok(parsed) = txid.parse::<Txid>() else {
+                    continue;
+                };
+
+                // A canonical transaction spending the same inputs is the replacement
+                let conflicts: Vec<Txid> = match wallet.tx_graph().get_tx(parsed) {
+                    Some(tx) => wallet.tx_graph().direct_conflicts(&tx).map(|(_, c)| c).collect(),
+                    None => vec![],
+                };
+                let replacement = conflicts.into_iter().find(|c| wallet.get_tx(*c).is_some());
+                evicted.push((parsed, now_unix));
+
+                match replacement {
+                    Some(new_txid) => {
+                        mark_replaced(&app_conn, app_wallet_id, txid, &new_txid.to_string())?;
+                        result.mempool.replaced += 1;
+                    }
+                    None => {
+                        remove_evicted(&app_conn, app_wallet_id, txid)?;
+                        result.mempool.evicted += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if !evicted.is_empty() {
+        wallet.apply_evicted_txs(evicted);
+        wallet.persist(bdk_conn)
+            .map_err(|e| AppError::Internal(format!("Failed to persist BDK wallet: {e}")))?;
+
+        let balance_total = wallet.balance().total().to_sat();
+        app_conn.execute(
+            "UPDATE wallets SET balance_sat = ?1 WHERE id = ?2",
+            rusqlite::params![balance_total as i64, app_wallet_id],
+        )?;
+        result.balance_sat = balance_total;
+    }
+
+    if result.mempool.replaced + result.mempool.evicted > 0 {
+        tracing::info!(
+            "Wallet {app_wallet_id}: {} pending txs replaced, {} evicted",
+            result.mempool.replaced, result.mempool.evicted
+        );
+    }
+
+    Ok(())
+}
+
+/// Fill in block height/time for a transaction stored while pending.
+/// Returns whether a pending row was updated.
+fn mark_confirmed(
+    conn: &rusqlite::Connection,
+    wallet_id: &str,
+    txid: &str,
+    block_height: i64,
+    block_time: Option<&str>,
+) -> AppResult<bool> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let updated = conn.execute(
+        "UPDATE transactions SET block_height = ?1, block_time = ?2, transacted_at = COALESCE(?2, transacted_at), updated_at = ?3
+         WHERE wallet_id = ?4 AND txid = ?5 AND block_height IS NULL",
+        rusqlite::params![block_height, block_time, now, wallet_id, txid],
+    )?;
+    // Parts of a split transaction keep the parent's block and time
+    conn.execute(
+        "UPDATE transactions SET block_height = ?1, block_time = ?2, transacted_at = COALESCE(?2, transacted_at), updated_at = ?3
+         WHERE block_height IS NULL AND parent_id IN (SELECT id FROM transactions WHERE wallet_id = ?4 AND txid = ?5)",
+        rusqlite::params![block_height, block_time, now, wallet_id, txid],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Point the replacement at the transaction it replaced, carry the labels over,
+/// and drop the replaced row.
+fn mark_replaced(conn: &rusqlite::Connection, wallet_id: &str, old_txid: &str, new_txid: &str) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE transactions SET replaces_txid = ?1, updated_at = ?2 WHERE wallet_id = ?3 AND txid = ?4",
+        rusqlite::params![old_txid, now, wallet_id, new_txid],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO transaction_labels (transaction_id, label_id)
+         SELECT new.id, tl.label_id
+         FROM transaction_labels tl
+         JOIN transactions old ON old.id = tl.transaction_id AND old.wallet_id = ?1 AND old.txid = ?2
+         JOIN transactions new ON new.wallet_id = ?1 AND new.txid = ?3",
+        rusqlite::params![wallet_id, old_txid, new_txid],
+    )?;
+    remove_evicted(conn, wallet_id, old_txid)
+}
+
+fn remove_evicted(conn: &rusqlite::Connection, wallet_id: &str, txid: &str) -> AppResult<()> {
+    conn.execute(
+        "DELETE FROM transactions WHERE wallet_id = ?1 AND txid = ?2 AND source = 'chain' AND block_height IS NULL",
+        rusqlite::params![wallet_id, txid],
+    )?;
+    Ok(())
+}
+
+fn format_unix_time(t: u64) -> String {
+    chrono::DateTime::from_timestamp(t as i64, 0)
+        .map(|dt| dt.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string())
+        .unwrap_or_else(|| t.to_string())
+}
+
+// ── Transaction details ──
+
+/// An input of a stored on-chain transaction.
+#[derive(Debug, Serialize)]
+pub struct TxInputDetail {
+    pub vin: u32,
+    pub prev_txid: String,
+    pub prev_vout: u32,
+    /// Address of the spent output, when the output is known and has one.
+    pub address: Option<String>,
+    pub value_sat: Option<i64>,
+    pub is_mine: bool,
+}
+
+/// An output of a stored on-chain transaction.
+#[derive(Debug, Serialize)]
+pub struct TxOutputDetail {
+    pub vout: u32,
+    pub address: Option<String>,
+    pub value_sat: i64,
+    pub is_mine: bool,
+}
+
+/// The app transaction for `txid` in this wallet. An invoice payment booked before it
+/// was known which wallet received it is claimed for this one rather than added twice.
+fn stored_tx_id(conn: &rusqlite::Connection, wallet_id: &str, txid: &str) -> AppResult<Option<String>> {
+    let find = || conn.query_row(
+        "SELECT id FROM transactions WHERE txid = ?1 AND wallet_id = ?2",
+        rusqlite::params![txid, wallet_id],
+        |row| row.get(0),
+    );
+    match find() {
+        Ok(id) => return Ok(Some(id)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {}
+        Err(e) => return Err(AppError::Database(e)),
+    }
+    let claimed = conn.execute(
+        "UPDATE transactions SET wallet_id = ?1
+         WHERE txid = ?2 AND wallet_id IS NULL AND source = 'invoice'
+           AND portfolio_id = (SELECT portfolio_id FROM wallets WHERE id = ?1)",
+        rusqlite::params![wallet_id, txid],
+    )?;
+    if claimed == 0 {
+        return Ok(None);
+    }
+    Ok(Some(find()?))
+}
+
+fn has_tx_details(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<bool> {
+    let exists = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM transaction_outputs WHERE transaction_id = ?1)",
+        rusqlite::params![transaction_id],
+        |row| row.get(0),
+    )?;
+    Ok(exists)
+}
+
+fn store_tx_details(
+    conn: &rusqlite::Connection,
+    transaction_id: &str,
+    inputs: &[TxInputDetail],
+    outputs: &[TxOutputDetail],
+) -> AppResult<()> {
+    for input in inputs {
+        conn.execute(
+            "INSERT OR REPLACE INTO transaction_inputs (transaction_id, vin, prev_txid, prev_vout, address, value_sat, is_mine)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                transaction_id, input.vin, input.prev_txid, input.prev_vout,
+                input.address, input.value_sat, input.is_mine as i32
+            ],
+        )?;
+    }
+    for output in outputs {
+        conn.execute(
+            "INSERT OR REPLACE INTO transaction_outputs (transaction_id, vout, address, value_sat, is_mine)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![transaction_id, output.vout, output.address, output.value_sat, output.is_mine as i32],
+        )?;
+    }
+    Ok(())
+}
+
+/// Outpoints spent by a stored transaction.
+fn stored_prevouts(conn: &rusqlite::Connection, wallet_id: &str, txid: &str) -> AppResult<Vec<(String, u32)>> {
+    let mut stmt = conn.prepare(
+        "SELECT i.prev_txid, i.prev_vout FROM transaction_inputs i
+         JOIN transactions t ON t.id = i.transaction_id
+         WHERE t.wallet_id = ?1 AND t.txid = ?2",
+    )?;
+    let prevouts = stmt
+        .query_map(rusqlite::params![wallet_id, txid], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(prevouts)
+}
+
+/// Inputs and outputs of a BDK wallet transaction. Spent outputs are looked up in the
+/// wallet's graph, which holds the prevouts Esplora returned during the scan.
+fn wallet_tx_details(wallet: &bdk_wallet::Wallet, tx: &Transaction) -> (Vec<TxInputDetail>, Vec<TxOutputDetail>) {
+    let network = wallet.network();
+    let address_of = |script: &bdk_wallet::bitcoin::Script| {
+        Address::from_script(script, network).ok().map(|a| a.to_string())
+    };
+
+    let inputs = tx
+        .input
+        .iter()
+        .enumerate()
+        .map(|(vin, input)| {
+            let prevout = wallet.tx_graph().get_txout(input.previous_output);
+            TxInputDetail {
+                vin: vin as u32,
+                prev_txid: input.previous_output.txid.to_string(),
+                prev_vout: input.previous_output.vout,
+                address: prevout.and_then(|o| address_of(&o.script_pubkey)),
+                value_sat: prevout.map(|o| o.value.to_sat() as i64),
+                is_mine: prevout.is_some_and(|o| wallet.is_mine(o.script_pubkey.clone())),
+            }
+        })
+        .collect();
+
+    let outputs = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(vout, output)| TxOutputDetail {
+            vout: vout as u32,
+            address: address_of(&output.script_pubkey),
+            value_sat: output.value.to_sat() as i64,
+            is_mine: wallet.is_mine(output.script_pubkey.clone()),
+        })
+        .collect();
+
+    (inputs, outputs)
+}
+
+/// Inputs and outputs of an Esplora transaction, relative to a single watched address.
+fn esplora_tx_details(tx: &EsploraTx, address: &str) -> (Vec<TxInputDetail>, Vec<TxOutputDetail>) {
+    let inputs = tx
+        .vin
+        .iter()
+        .enumerate()
+        .map(|(vin, input)| {
+            let prev_address = input.prevout.as_ref().and_then(|p| p.scriptpubkey_address.clone());
+            TxInputDetail {
+                vin: vin as u32,
+                prev_txid: input.txid.clone(),
+                prev_vout: input.vout,
+                is_mine: prev_address.as_deref() == Some(address),
+                address: prev_address,
+                value_sat: input.prevout.as_ref().map(|p| p.value as i64),
+            }
+        })
+        .collect();
+
+    let outputs = tx
+        .vout
+        .iter()
+        .enumerate()
+        .map(|(vout, output)| TxOutputDetail {
+            vout: vout as u32,
+            address: output.scriptpubkey_address.clone(),
+            value_sat: output.value as i64,
+            is_mine: output.scriptpubkey_address.as_deref() == Some(address),
+        })
+        .collect();
+
+    (inputs, outputs)
+}
+
+// ── Sync jobs ──
+
+/// A recorded sync run. For descriptor/xpub wallets it carries the scan checkpoint
+/// so an interrupted run picks up where it stopped.
+#[derive(Debug, Serialize)]
+pub struct SyncJob {
+    pub id: String,
+    pub wallet_id: String,
+    pub status: String,
+    pub stop_gap: u32,
+    pub keychain: String,
+    pub next_index: u32,
+    pub last_active_index: Option<u32>,
+    pub backend: Option<String>,
+    pub transactions_found: Option<i64>,
+    pub new_transactions: Option<i64>,
+    pub error: Option<String>,
+    pub started_at: String,
+    pub updated_at: String,
+    pub finished_at: Option<String>,
+}
+
+impl SyncJob {
+    pub fn checkpoint(&self) -> ScanCheckpoint {
+        ScanCheckpoint {
+            keychain: if self.keychain == "internal" {
+                KeychainKind::Internal
+            } else {
+                KeychainKind::External
+            },
+            next_index: self.next_index,
+            last_active_index: self.last_active_index,
+            finished: self.status == "completed",
+        }
+    }
+}
+
+const SYNC_JOB_COLS: &str = "id, wallet_id, status, stop_gap, keychain, next_index, last_active_index, backend, transactions_found, new_transactions, error, started_at, updated_at, finished_at";
+
+fn row_to_sync_job(row: &rusqlite::Row) -> rusqlite::Result<SyncJob> {
+    Ok(SyncJob {
+        id: row.get(0)?,
+        wallet_id: row.get(1)?,
+        status: row.get(2)?,
+        stop_gap: row.get(3)?,
+        keychain: row.get(4)?,
+        next_index: row.get(5)?,
+        last_active_index: row.get(6)?,
+        backend: row.get(7)?,
+        transactions_found: row.get(8)?,
+        new_transactions: row.get(9)?,
+        error: row.get(10)?,
+        started_at: row.get(11)?,
+        updated_at: row.get(12)?,
+        finished_at: row.get(13)?,
+    })
+}
+
+pub fn keychain_name(keychain: KeychainKind) -> &'static str {
+    match keychain {
+        KeychainKind::External => "external",
+        KeychainKind::Internal => "internal",
+    }
+}
+
+fn get_sync_job(conn: &rusqlite::Connection, job_id: &str) -> AppResult<SyncJob> {
+    Ok(conn.query_row(
+        &format!("SELECT {SYNC_JOB_COLS} FROM sync_jobs WHERE id = ?1"),
+        rusqlite::params![job_id],
+        row_to_sync_job,
+    )?)
+}
+
+/// Most recent sync job for a wallet, if it has ever been synced.
+pub fn latest_sync_job(conn: &rusqlite::Connection, wallet_id: &str) -> AppResult<Option<SyncJob>> {
+    match conn.query_row(
+        &format!("SELECT {SYNC_JOB_COLS} FROM sync_jobs WHERE wallet_id = ?1 ORDER BY started_at DESC, rowid DESC LIMIT 1"),
+        rusqlite::params![wallet_id],
+        row_to_sync_job,
+    ) {
+        Ok(job) => Ok(Some(job)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(AppError::Database(e)),
+    }
+}
+
+/// Record a new sync job starting from index 0 on the external keychain.
+pub fn start_sync_job(conn: &rusqlite::Connection, wallet_id: &str, stop_gap: u32) -> AppResult<SyncJob> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO sync_jobs (id, wallet_id, status, stop_gap, started_at, updated_at) VALUES (?1, ?2, 'running', ?3, ?4, ?4)",
+        rusqlite::params![id, wallet_id, stop_gap, now],
+    )?;
+    get_sync_job(conn, &id)
+}
+
+/// Pick up the wallet's latest unfinished scan if it used the same stop gap,
+/// otherwise start a new one.
+pub fn resume_or_start_sync_job(conn: &rusqlite::Connection, wallet_id: &str, stop_gap: u32) -> AppResult<SyncJob> {
+    if let Some(job) = latest_sync_job(conn, wallet_id)? {
+        if job.status != "completed" && job.stop_gap == stop_gap {
+            let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+            conn.execute(
+                "UPDATE sync_jobs SET status = 'running', error = NULL, updated_at = ?1 WHERE id = ?2",
+                rusqlite::params![now, job.id],
+            )?;
+            tracing::info!(
+                "Wallet {wallet_id}: resuming sync job {} at {} index {}",
+                job.id, job.keychain, job.next_index
+            );
+            return get_sync_job(conn, &job.id);
+        }
+    }
+    start_sync_job(conn, wallet_id, stop_gap)
+}
+
+/// Persist scan progress after a chunk has been applied to the BDK wallet.
+pub fn save_scan_checkpoint(
+    conn: &rusqlite::Connection,
+    job_id: &str,
+    checkpoint: &ScanCheckpoint,
+    backend: &str,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE sync_jobs SET keychain = ?1, next_index = ?2, last_active_index = ?3, backend = ?4, updated_at = ?5 WHERE id = ?6",
+        rusqlite::params![
+            keychain_name(checkpoint.keychain), checkpoint.next_index,
+            checkpoint.last_active_index, backend, now, job_id
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn complete_sync_job(
+    conn: &rusqlite::Connection,
+    job_id: &str,
+    result: &SyncResult,
+    backend: &str,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE sync_jobs SET status = 'completed', backend = ?1, transactions_found = ?2, new_transactions = ?3,
+         error = NULL, updated_at = ?4, finished_at = ?4 WHERE id = ?5",
+        rusqlite::params![
+            backend, result.transactions_found as i64, result.new_transactions as i64, now, job_id
+        ],
+    )?;
+    Ok(())
+}
+
+/// Mark a job failed. Its checkpoint is kept so the next sync resumes from it.
+pub fn fail_sync_job(conn: &rusqlite::Connection, job_id: &str, error: &AppError) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE sync_jobs SET status = 'failed', error = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![error.to_string(), now, job_id],
+    )?;
+    Ok(())
+}
+
+/// Jobs still marked running at startup were cut off by a restart or crash.
+pub fn mark_interrupted_sync_jobs(pool: &DbPool) {
+    let result = pool.get().map_err(AppError::from).and_then(|conn| {
+        let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        Ok(conn.execute(
+            "UPDATE sync_jobs SET status = 'interrupted', updated_at = ?1 WHERE status = 'running'",
+            rusqlite::params![now],
+        )?)
+    });
+    match result {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Marked {n} unfinished sync job(s) as interrupted"),
+        Err(e) => tracing::error!("Failed to mark interrupted sync jobs: {e}"),
+    }
+}
+
 // ── Single address sync via Esplora REST API ──
 
 // Esplora API response types — only capture fields we need,
@@ -187,6 +845,10 @@ struct EsploraTxStatus {
 
 #[derive(Debug, Deserialize)]
 struct EsploraVin {
+    #[serde(default)]
+    txid: String,
+    #[serde(default)]
+    vout: u32,
     #[serde(default)]
     prevout: Option<EsploraVout>,
 }
@@ -199,12 +861,67 @@ struct EsploraVout {
     value: u64,
 }
 
+/// Usage stats for a single address via Esplora REST API, confirmed and mempool combined.
+/// Used by the get_addresses endpoint for address-type wallets.
+pub async fn address_stats(
+    esplora_url: &str,
+    address: &str,
+) -> AppResult<super::wallet::AddressInfo> {
+    let http = http::client()?;
+
+    let url = format!("{esplora_url}/address/{address}");
+    let resp = http
+        .get(&url)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("esplora", "Esplora address request failed", e))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(AppError::Internal(format!("Esplora returned {status} for {url}: {body}")));
+    }
+
+    let info: EsploraAddress = resp
+        .json()
+        .await
+        .map_err(|e| http::upstream_error("esplora", "Esplora address parse failed", e))?;
+
+    let (chain, mempool) = (info.chain_stats, info.mempool_stats);
+    let funded = chain.funded_txo_sum + mempool.funded_txo_sum;
+    let spent = chain.spent_txo_sum + mempool.spent_txo_sum;
+    let tx_count = chain.tx_count + mempool.tx_count;
+
+    Ok(super::wallet::AddressInfo {
+        index: 0,
+        address: address.to_string(),
+        keychain: "external".to_string(),
+        used: tx_count > 0,
+        total_received_sat: funded,
+        balance_sat: funded.saturating_sub(spent),
+        tx_count,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraAddress {
+    chain_stats: EsploraAddressStats,
+    mempool_stats: EsploraAddressStats,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraAddressStats {
+    tx_count: u32,
+    funded_txo_sum: u64,
+    spent_txo_sum: u64,
+}
+
 #[derive(Debug, Deserialize)]
 struct EsploraUtxo {
     txid: String,
     vout: u32,
     value: u64,
-    status: EsploraTxStatus,
 }
 
 /// Sync a single address wallet by querying Esplora REST API directly
@@ -216,10 +933,7 @@ pub async fn address_sync(
     app_wallet_id: &str,
     portfolio_id: &str,
 ) -> AppResult<SyncResult> {
-    let http = reqwest::Client::builder()
-        .user_agent("opacore/0.1")
-        .build()
-        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))?;
+    let http = http::client()?;
 
     tracing::info!("Starting address sync for {address} via {esplora_url}");
 
@@ -231,7 +945,7 @@ pub async fn address_sync(
         .header("Accept", "application/json")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora request failed for {tx_url}: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", format!("Esplora request failed for {tx_url}"), e))?;
 
     if !tx_resp.status().is_success() {
         let status = tx_resp.status();
@@ -242,7 +956,7 @@ pub async fn address_sync(
     let txs: Vec<EsploraTx> = tx_resp
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora response parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", "Esplora response parse failed", e))?;
 
     let utxo_url = format!("{esplora_url}/address/{address}/utxo");
     tracing::debug!("Fetching UTXOs from {utxo_url}");
@@ -272,17 +986,20 @@ pub async fn address_sync(
     let total_txs = txs.len();
     let mut new_tx_count = 0;
     let mut max_height: Option<u32> = None;
+    let mut mempool = MempoolChanges::default();
 
     let app_conn = app_pool.get()?;
 
+    // Transactions still pending from the last sync, checked once the new ones are stored
+    let pending = pending_txids(app_pool, app_wallet_id)?;
+
     for tx in &txs {
-        // Skip if already exists
-        let exists: bool = app_conn.query_row(
-            "SELECT EXISTS(SELECT 1 FROM transactions WHERE txid = ?1 AND wallet_id = ?2)",
-            rusqlite::params![tx.txid, app_wallet_id],
-            |row| row.get(0),
-        )?;
-        if exists {
+        // Skip if already exists, filling in inputs/outputs for rows stored without them
+        if let Some(existing_id) = stored_tx_id(&app_conn, app_wallet_id, &tx.txid)? {
+            if !has_tx_details(&app_conn, &existing_id)? {
+                let (inputs, outputs) = esplora_tx_details(tx, address);
+                store_tx_details(&app_conn, &existing_id, &inputs, &outputs)?;
+            }
             continue;
         }
 
@@ -307,7 +1024,7 @@ pub async fn address_sync(
 
         let (block_height, block_time) = if tx.status.confirmed {
             let h = tx.status.block_height.unwrap_or(0) as u32;
-            if max_height.map_or(true, |mh| h > mh) {
+            if max_height.is_none_or(|mh| h > mh) {
                 max_height = Some(h);
             }
             (
@@ -335,10 +1052,48 @@ pub async fn address_sync(
                 block_height, block_time, transacted_at, now, now
             ],
         )?;
+        let (inputs, outputs) = esplora_tx_details(tx, address);
+        store_tx_details(&app_conn, &tx_id, &inputs, &outputs)?;
 
         new_tx_count += 1;
     }
 
+    // Reconcile transactions that were pending at the last sync. The address history
+    // includes mempool transactions, so one missing from it has been dropped, or
+    // replaced if another transaction now spends the same inputs.
+    let seen: HashMap<&str, &EsploraTx> = txs.iter().map(|tx| (tx.txid.as_str(), tx)).collect();
+    for txid in pending {
+        match seen.get(txid.as_str()) {
+            Some(tx) => {
+                if let PendingTxStatus::Confirmed { block_height, block_time } = pending_status(&tx.status) {
+                    if mark_confirmed(&app_conn, app_wallet_id, &txid, block_height, block_time.as_deref())? {
+                        mempool.confirmed += 1;
+                    }
+                }
+            }
+            None => {
+                let spent = stored_prevouts(&app_conn, app_wallet_id, &txid)?;
+                let replacement = txs.iter().find(|tx| {
+                    tx.vin.iter().any(|vin| spent.contains(&(vin.txid.clone(), vin.vout)))
+                });
+                match replacement {
+                    Some(new_tx) => {
+                        mark_replaced(&app_conn, app_wallet_id, &txid, &new_tx.txid)?;
+                        mempool.replaced += 1;
+                    }
+                    None => {
+                        remove_evicted(&app_conn, app_wallet_id, &txid)?;
+                        mempool.evicted += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if new_tx_count > 0 {
+        transfers::link_by_txid(&app_conn, portfolio_id, None)?;
+    }
+
     // Update wallet sync metadata
     let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
     app_conn.execute(
@@ -356,6 +1111,7 @@ pub async fn address_sync(
         new_transactions: new_tx_count,
         balance_sat,
         last_sync_height: max_height,
+        mempool,
     })
 }
 
@@ -365,10 +1121,7 @@ pub async fn address_utxos(
     esplora_url: &str,
     address: &str,
 ) -> AppResult<Vec<super::wallet::UtxoInfo>> {
-    let http = reqwest::Client::builder()
-        .user_agent("opacore/0.1")
-        .build()
-        .map_err(|e| AppError::Internal(format!("Failed to build HTTP client: {e}")))?;
+    let http = http::client()?;
 
     let url = format!("{esplora_url}/address/{address}/utxo");
     let resp = http
@@ -376,7 +1129,7 @@ pub async fn address_utxos(
         .header("Accept", "application/json")
         .send()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora UTXO request failed: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", "Esplora UTXO request failed", e))?;
 
     if !resp.status().is_success() {
         let status = resp.status();
@@ -387,7 +1140,7 @@ pub async fn address_utxos(
     let utxos: Vec<EsploraUtxo> = resp
         .json()
         .await
-        .map_err(|e| AppError::Internal(format!("Esplora UTXO parse failed: {e}")))?;
+        .map_err(|e| http::upstream_error("esplora", "Esplora UTXO parse failed", e))?;
 
     Ok(utxos
         .into_iter()
@@ -396,6 +1149,9 @@ pub async fn address_utxos(
             vout: u.vout,
             value_sat: u.value,
             keychain: "external".to_string(),
+            label: None,
+            notes: None,
+            frozen: false,
         })
         .collect())
 }
@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Wallets with a sync in flight. Two overlapping syncs would open the same BDK
+/// SQLite file and corrupt it, so the second one is turned away instead.
+#[derive(Clone, Default)]
+pub struct WalletSyncLocks {
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl WalletSyncLocks {
+    /// Claim a wallet for syncing, or `None` if a sync is already running.
+    /// The claim is released when the guard drops, including when the request is
+    /// cancelled mid-scan.
+    pub fn try_acquire(&self, wallet_id: &str) -> Option<WalletSyncGuard> {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        if !running.insert(wallet_id.to_string()) {
+            return None;
+        }
+        Some(WalletSyncGuard {
+            running: self.running.clone(),
+            wallet_id: wallet_id.to_string(),
+        })
+    }
+}
+
+pub struct WalletSyncGuard {
+    running: Arc<Mutex<HashSet<String>>>,
+    wallet_id: String,
+}
+
+impl Drop for WalletSyncGuard {
+    fn drop(&mut self) {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        running.remove(&self.wallet_id);
+    }
+}
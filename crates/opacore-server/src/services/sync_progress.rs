@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// Events per wallet buffered for slow subscribers before they start skipping ahead.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Progress of a running wallet sync, streamed to clients over SSE.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncEvent {
+    Started {
+        job_id: String,
+        keychain: String,
+        next_index: u32,
+    },
+    Progress {
+        keychain: String,
+        index: u32,
+        txs_found: usize,
+    },
+    Completed {
+        transactions_found: usize,
+        new_transactions: usize,
+        balance_sat: u64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl SyncEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncEvent::Started { .. } => "started",
+            SyncEvent::Progress { .. } => "progress",
+            SyncEvent::Completed { .. } => "completed",
+            SyncEvent::Failed { .. } => "failed",
+        }
+    }
+
+    /// Completed and failed are the last event of a sync.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, SyncEvent::Completed { .. } | SyncEvent::Failed { .. })
+    }
+}
+
+/// Per-wallet broadcast channels linking a running sync to its SSE subscribers.
+/// Cheap to clone — the channel map is shared.
+#[derive(Clone, Default)]
+pub struct SyncProgressHub {
+    channels: Arc<Mutex<HashMap<String, broadcast::Sender<SyncEvent>>>>,
+}
+
+impl SyncProgressHub {
+    /// Sender for a wallet's sync to publish on. Sends never fail the sync —
+    /// with no subscribers the event is just dropped.
+    pub fn sender(&self, wallet_id: &str) -> broadcast::Sender<SyncEvent> {
+        self.channel(wallet_id)
+    }
+
+    /// Subscribe to a wallet's progress, including a sync that hasn't started yet.
+    pub fn subscribe(&self, wallet_id: &str) -> broadcast::Receiver<SyncEvent> {
+        self.channel(wallet_id).subscribe()
+    }
+
+    fn channel(&self, wallet_id: &str) -> broadcast::Sender<SyncEvent> {
+        let mut channels = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Drop channels nobody is publishing on or listening to any more
+        channels.retain(|_, tx| tx.receiver_count() > 0 || tx.strong_count() > 1);
+
+        channels
+            .entry(wallet_id.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .clone()
+    }
+}
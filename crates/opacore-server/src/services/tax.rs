@@ -1,144 +1,110 @@
-use serde::Serialize;
+pub use opacore_taxengine::tax::{QuarterlyGains, TaxReport};
+pub use opacore_taxengine::TaxRules;
+use opacore_taxengine::tax;
 
 use crate::db::DbPool;
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+use crate::services::cache::AppCache;
 use crate::services::costbasis::{self, CostBasisMethod};
 
-#[derive(Debug, Serialize)]
-pub struct TaxReport {
-    pub year: i32,
-    pub method: String,
-    pub short_term_gains: f64,
-    pub long_term_gains: f64,
-    pub total_gains: f64,
-    pub total_proceeds: f64,
-    pub total_cost_basis: f64,
-    pub disposition_count: usize,
-    pub dispositions: Vec<TaxDisposition>,
+/// A user's tax rules, or the defaults (US) if they haven't set any.
+pub fn tax_rules(conn: &rusqlite::Connection, user_id: &str) -> AppResult<TaxRules> {
+    match conn.query_row(
+        "SELECT long_term_months, long_term_days, fiscal_year_start_month, fiscal_year_start_day, fees_taxable, require_prices
+         FROM tax_settings WHERE user_id = ?1",
+        rusqlite::params![user_id],
+        |row| {
+            Ok(TaxRules {
+                long_term_months: row.get(0)?,
+                long_term_days: row.get(1)?,
+                fiscal_year_start_month: row.get(2)?,
+                fiscal_year_start_day: row.get(3)?,
+                fees_taxable: row.get(4)?,
+                require_prices: row.get(5)?,
+            })
+        },
+    ) {
+        Ok(rules) => Ok(rules),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(TaxRules::default()),
+        Err(e) => Err(AppError::Database(e)),
+    }
 }
 
-#[derive(Debug, Serialize)]
-pub struct TaxDisposition {
-    pub description: String,
-    pub date_acquired: String,
-    pub date_sold: String,
-    pub proceeds: f64,
-    pub cost_basis: f64,
-    pub gain_or_loss: f64,
-    pub holding_period: String, // "Short-term" or "Long-term"
-    pub holding_days: i64,
+pub fn save_tax_rules(conn: &rusqlite::Connection, user_id: &str, rules: &TaxRules) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO tax_settings (user_id, long_term_months, long_term_days, fiscal_year_start_month, fiscal_year_start_day, fees_taxable, require_prices, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(user_id) DO UPDATE SET
+            long_term_months = excluded.long_term_months,
+            long_term_days = excluded.long_term_days,
+            fiscal_year_start_month = excluded.fiscal_year_start_month,
+            fiscal_year_start_day = excluded.fiscal_year_start_day,
+            fees_taxable = excluded.fees_taxable,
+            require_prices = excluded.require_prices,
+            updated_at = excluded.updated_at",
+        rusqlite::params![
+            user_id, rules.long_term_months, rules.long_term_days,
+            rules.fiscal_year_start_month, rules.fiscal_year_start_day, rules.fees_taxable, rules.require_prices, now
+        ],
+    )?;
+    Ok(())
 }
 
-/// Generate a tax report for a given year.
+/// Generate a tax report for a given tax year in `currency`, from the cost basis in
+/// `cache` if it's there (see [`costbasis::calculate_cost_basis`]).
 pub fn generate_tax_report(
     pool: &DbPool,
+    cache: Option<&AppCache>,
     portfolio_id: &str,
     year: i32,
     method: CostBasisMethod,
+    currency: &str,
+    rules: &TaxRules,
 ) -> AppResult<TaxReport> {
-    let result = costbasis::calculate_cost_basis(pool, portfolio_id, method, Some(year))?;
-
-    let dispositions: Vec<TaxDisposition> = result
-        .gains
-        .iter()
-        .map(|g| {
-            let btc_amount = g.sell_amount_sat as f64 / 1e8;
-            TaxDisposition {
-                description: format!("{:.8} BTC", btc_amount),
-                date_acquired: "Various".to_string(),
-                date_sold: g.sell_date[..10.min(g.sell_date.len())].to_string(),
-                proceeds: round2(g.proceeds_usd),
-                cost_basis: round2(g.cost_basis_usd),
-                gain_or_loss: round2(g.gain_usd),
-                holding_period: if g.is_long_term {
-                    "Long-term".to_string()
-                } else {
-                    "Short-term".to_string()
-                },
-                holding_days: g.holding_period_days,
-            }
-        })
-        .collect();
-
-    let total_proceeds: f64 = dispositions.iter().map(|d| d.proceeds).sum();
-    let total_cost: f64 = dispositions.iter().map(|d| d.cost_basis).sum();
-
-    let method_name = match method {
-        CostBasisMethod::Fifo => "fifo",
-        CostBasisMethod::Lifo => "lifo",
-        CostBasisMethod::Hifo => "hifo",
-    };
-
-    Ok(TaxReport {
-        year,
-        method: method_name.to_string(),
-        short_term_gains: round2(result.total_short_term_gain_usd),
-        long_term_gains: round2(result.total_long_term_gain_usd),
-        total_gains: round2(result.total_realized_gain_usd),
-        total_proceeds: round2(total_proceeds),
-        total_cost_basis: round2(total_cost),
-        disposition_count: dispositions.len(),
-        dispositions,
-    })
+    let result = costbasis::calculate_cost_basis(pool, cache, portfolio_id, method, currency, Some(year), rules)?;
+    check_prices(tax::tax_report(result, year, method, rules), rules)
 }
 
-/// Generate Form 8949 CSV content.
-/// Columns: Description, Date Acquired, Date Sold, Proceeds, Cost Basis, Gain/Loss, Term
-pub fn generate_form_8949_csv(
+/// The tax report for `year` split into quarters, cached as [`generate_tax_report`] is.
+pub fn quarterly_gains(
     pool: &DbPool,
+    cache: Option<&AppCache>,
     portfolio_id: &str,
     year: i32,
     method: CostBasisMethod,
-) -> AppResult<String> {
-    let report = generate_tax_report(pool, portfolio_id, year, method)?;
-
-    let mut wtr = csv::Writer::from_writer(Vec::new());
-
-    // Header
-    wtr.write_record([
-        "Description of Property",
-        "Date Acquired",
-        "Date Sold or Disposed Of",
-        "Proceeds (Sales Price)",
-        "Cost or Other Basis",
-        "Gain or (Loss)",
-        "Term",
-    ])
-    .map_err(|e| crate::error::AppError::Internal(format!("CSV write error: {e}")))?;
-
-    for d in &report.dispositions {
-        wtr.write_record([
-            &d.description,
-            &d.date_acquired,
-            &d.date_sold,
-            &format!("{:.2}", d.proceeds),
-            &format!("{:.2}", d.cost_basis),
-            &format!("{:.2}", d.gain_or_loss),
-            &d.holding_period,
-        ])
-        .map_err(|e| crate::error::AppError::Internal(format!("CSV write error: {e}")))?;
-    }
-
-    // Summary row
-    wtr.write_record([
-        "TOTALS",
-        "",
-        "",
-        &format!("{:.2}", report.total_proceeds),
-        &format!("{:.2}", report.total_cost_basis),
-        &format!("{:.2}", report.total_gains),
-        "",
-    ])
-    .map_err(|e| crate::error::AppError::Internal(format!("CSV write error: {e}")))?;
+    currency: &str,
+    rules: &TaxRules,
+) -> AppResult<QuarterlyGains> {
+    let report = generate_tax_report(pool, cache, portfolio_id, year, method, currency, rules)?;
+    Ok(tax::quarterly_gains(report, rules))
+}
 
-    let data = wtr
-        .into_inner()
-        .map_err(|e| crate::error::AppError::Internal(format!("CSV flush error: {e}")))?;
+/// Write a report as Form 8949 CSV to `out`.
+pub fn write_form_8949_csv<W: std::io::Write>(report: &TaxReport, out: W) -> AppResult<()> {
+    tax::write_form_8949_csv(report, out).map_err(|e| AppError::Internal(e.to_string()))
+}
 
-    String::from_utf8(data)
-        .map_err(|e| crate::error::AppError::Internal(format!("CSV encoding error: {e}")))
+/// Generate a tax report in USD across all of `user_id`'s portfolios, as one holding.
+pub fn generate_user_tax_report(
+    pool: &DbPool,
+    user_id: &str,
+    year: i32,
+    method: CostBasisMethod,
+    rules: &TaxRules,
+) -> AppResult<TaxReport> {
+    let txs = costbasis::load_user_tx_records(pool, user_id)?;
+    check_prices(tax::generate_tax_report(txs, year, method, rules), rules)
 }
 
-fn round2(v: f64) -> f64 {
-    (v * 100.0).round() / 100.0
+/// With [`TaxRules::require_prices`], refuse a report that counts missing prices as 0.
+fn check_prices(report: TaxReport, rules: &TaxRules) -> AppResult<TaxReport> {
+    if !rules.require_prices || report.warnings.is_empty() {
+        return Ok(report);
+    }
+    Err(AppError::Conflict(format!(
+        "{} transaction(s) the {} tax report depends on have no price; backfill or enter their prices first",
+        report.warnings.len(),
+        report.year
+    )))
 }
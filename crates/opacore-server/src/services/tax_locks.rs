@@ -0,0 +1,156 @@
+use serde::Serialize;
+
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::audit::{self, AuditEvent};
+use crate::services::tax::TaxRules;
+
+/// A tax year whose transactions are frozen because its figures have been filed.
+#[derive(Debug, Serialize)]
+pub struct TaxYearLock {
+    pub portfolio_id: String,
+    pub year: i32,
+    /// First and last day of the year (`YYYY-MM-DD`) under the rules it was locked with.
+    pub period_start: String,
+    pub period_end: String,
+    pub locked_by: Option<String>,
+    pub locked_at: String,
+}
+
+pub const TAX_YEAR_LOCK_COLS: &str = "portfolio_id, year, period_start, period_end, locked_by, locked_at";
+
+pub fn row_to_lock(row: &rusqlite::Row) -> rusqlite::Result<TaxYearLock> {
+    Ok(TaxYearLock {
+        portfolio_id: row.get(0)?,
+        year: row.get(1)?,
+        period_start: row.get(2)?,
+        period_end: row.get(3)?,
+        locked_by: row.get(4)?,
+        locked_at: row.get(5)?,
+    })
+}
+
+/// The portfolio's locked years, oldest first.
+pub fn list(conn: &rusqlite::Connection, portfolio_id: &str) -> AppResult<Vec<TaxYearLock>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {TAX_YEAR_LOCK_COLS} FROM tax_year_locks WHERE portfolio_id = ?1 ORDER BY year"
+    ))?;
+    let locks = stmt
+        .query_map(rusqlite::params![portfolio_id], row_to_lock)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(locks)
+}
+
+/// Lock tax `year`, its dates fixed by `rules`. Locking a year that already is keeps the
+/// existing lock. Returns the lock and whether it's new.
+pub fn lock(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    year: i32,
+    rules: &TaxRules,
+    user_id: &str,
+) -> AppResult<(TaxYearLock, bool)> {
+    let (Some(start), Some(end)) = (rules.tax_year_start(year), rules.tax_year_end(year)) else {
+        return Err(AppError::BadRequest(format!("{year} isn't a valid tax year")));
+    };
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO tax_year_locks (portfolio_id, year, period_start, period_end, locked_by, locked_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![portfolio_id, year, start.to_string(), end.to_string(), user_id, now],
+    )?;
+    let lock = conn.query_row(
+        &format!("SELECT {TAX_YEAR_LOCK_COLS} FROM tax_year_locks WHERE portfolio_id = ?1 AND year = ?2"),
+        rusqlite::params![portfolio_id, year],
+        row_to_lock,
+    )?;
+    Ok((lock, inserted > 0))
+}
+
+/// Unlock tax `year`. Returns whether it was locked.
+pub fn unlock(conn: &rusqlite::Connection, portfolio_id: &str, year: i32) -> AppResult<bool> {
+    let removed = conn.execute(
+        "DELETE FROM tax_year_locks WHERE portfolio_id = ?1 AND year = ?2",
+        rusqlite::params![portfolio_id, year],
+    )?;
+    Ok(removed > 0)
+}
+
+/// The locked tax year a timestamp or date falls in, if any.
+pub fn locked_year(conn: &rusqlite::Connection, portfolio_id: &str, date: &str) -> AppResult<Option<i32>> {
+    let day = date.get(..10).unwrap_or(date);
+    match conn.query_row(
+        "SELECT year FROM tax_year_locks WHERE portfolio_id = ?1 AND period_start <= ?2 AND period_end >= ?2",
+        rusqlite::params![portfolio_id, day],
+        |row| row.get(0),
+    ) {
+        Ok(year) => Ok(Some(year)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(AppError::Database(e)),
+    }
+}
+
+/// SQL condition on the `transactions` row `alias`: true when its date isn't in one of its
+/// portfolio's locked tax years. For background jobs (price backfills, transfer detection
+/// on sync), which have nobody to override a lock and leave those rows alone instead.
+pub fn unlocked(alias: &str) -> String {
+    format!(
+        "NOT EXISTS (SELECT 1 FROM tax_year_locks l WHERE l.portfolio_id = {alias}.portfolio_id
+           AND l.period_start <= substr({alias}.transacted_at, 1, 10) AND l.period_end >= substr({alias}.transacted_at, 1, 10))"
+    )
+}
+
+/// Reject a change to the transaction `transaction_id` if any of `dates` (its current
+/// date, and a new one it would move to) is in a locked tax year. Site admins may go
+/// ahead, and the override is audit-logged.
+pub fn check_dates(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    transaction_id: &str,
+    dates: &[&str],
+    user: &User,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    for date in dates {
+        let Some(year) = locked_year(conn, portfolio_id, date)? else {
+            continue;
+        };
+        if !user.is_admin {
+            return Err(AppError::Forbidden(format!(
+                "Tax year {year} is locked; unlock it to change its transactions"
+            )));
+        }
+        return audit::record(
+            conn,
+            &user.id,
+            AuditEvent::LockedTaxYearOverridden,
+            Some(transaction_id),
+            serde_json::json!({ "portfolio_id": portfolio_id, "year": year }),
+            client,
+        );
+    }
+    Ok(())
+}
+
+/// As [`check_dates`] for an existing transaction at its current date. A transaction that
+/// doesn't exist passes, for the caller to report.
+pub fn check_transaction(
+    conn: &rusqlite::Connection,
+    transaction_id: &str,
+    user: &User,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    let found = conn.query_row(
+        "SELECT portfolio_id, transacted_at FROM transactions WHERE id = ?1",
+        rusqlite::params![transaction_id],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+    );
+    match found {
+        Ok((portfolio_id, transacted_at)) => {
+            check_dates(conn, &portfolio_id, transaction_id, &[&transacted_at], user, client)
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(()),
+        Err(e) => Err(AppError::Database(e)),
+    }
+}
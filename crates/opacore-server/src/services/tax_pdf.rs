@@ -0,0 +1,286 @@
+use crate::error::AppResult;
+use crate::services::pdf::{self, Font, PdfPage};
+use crate::services::tax::TaxReport;
+
+const LEFT: f64 = 50.0;
+const RIGHT: f64 = pdf::PAGE_WIDTH - 50.0;
+/// Baseline of the first line on a page, and the lowest a line may go.
+const TOP: f64 = 60.0;
+const BOTTOM: f64 = pdf::PAGE_HEIGHT - 60.0;
+const ROW_HEIGHT: f64 = 13.0;
+const TABLE_SIZE: f64 = 8.5;
+
+/// A table column: its heading, and where its text starts, or ends when right-aligned.
+struct Column {
+    heading: &'static str,
+    x: f64,
+    right: bool,
+}
+
+const fn left(heading: &'static str, x: f64) -> Column {
+    Column { heading, x, right: false }
+}
+
+const fn right(heading: &'static str, x: f64) -> Column {
+    Column { heading, x, right: true }
+}
+
+const DISPOSITION_COLUMNS: [Column; 7] = [
+    left("Description", LEFT),
+    left("Acquired", 145.0),
+    left("Sold", 205.0),
+    right("Proceeds", 330.0),
+    right("Cost basis", 400.0),
+    right("Gain or (loss)", 475.0),
+    right("Days held", RIGHT),
+];
+
+const INCOME_COLUMNS: [Column; 4] = [
+    left("Date", LEFT),
+    left("Type", 130.0),
+    right("Amount (BTC)", 330.0),
+    right("Fair value", RIGHT),
+];
+
+const NON_TAXABLE_COLUMNS: [Column; 5] = [
+    left("Date", LEFT),
+    left("Type", 130.0),
+    right("Amount (BTC)", 330.0),
+    right("Fair value", 430.0),
+    right("Cost basis", RIGHT),
+];
+
+/// Flows content down the page, starting another when it runs out of room.
+struct Pages {
+    pages: Vec<PdfPage>,
+    y: f64,
+}
+
+impl Pages {
+    fn page(&mut self) -> &mut PdfPage {
+        self.pages.last_mut().expect("there is always a page")
+    }
+
+    /// Make room for `height` more, on a new page if this one is full. Returns whether a
+    /// page was started.
+    fn reserve(&mut self, height: f64) -> bool {
+        if self.y + height <= BOTTOM {
+            return false;
+        }
+        self.pages.push(PdfPage::new());
+        self.y = TOP;
+        true
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.reserve(40.0);
+        self.y += 14.0;
+        let y = self.y;
+        self.page().text(LEFT, y, Font::Bold, 12.0, 0.0, text);
+        self.y += 8.0;
+    }
+
+    fn note(&mut self, text: &str) {
+        for line in pdf::wrap(Font::Regular, 9.0, text, RIGHT - LEFT) {
+            self.reserve(ROW_HEIGHT);
+            self.y += 12.0;
+            let y = self.y;
+            self.page().text(LEFT, y, Font::Regular, 9.0, 0.4, &line);
+        }
+    }
+
+    /// `rows` under `columns`' headings, repeated at the top of each page the table runs
+    /// onto, and a bold `totals` row if given.
+    fn table(&mut self, columns: &[Column], rows: &[Vec<String>], totals: Option<Vec<String>>) {
+        self.reserve(ROW_HEIGHT * 3.0);
+        self.table_header(columns);
+        for row in rows {
+            if self.reserve(ROW_HEIGHT) {
+                self.table_header(columns);
+            }
+            self.row(columns, row, Font::Regular);
+        }
+        if let Some(totals) = totals {
+            if self.reserve(ROW_HEIGHT + 4.0) {
+                self.table_header(columns);
+            }
+            self.y += 4.0;
+            let y = self.y;
+            self.page().hline(LEFT, RIGHT, y - 1.0, 0.5, 0.6);
+            self.row(columns, &totals, Font::Bold);
+        }
+        self.y += 6.0;
+    }
+
+    fn table_header(&mut self, columns: &[Column]) {
+        self.y += ROW_HEIGHT;
+        let y = self.y;
+        let page = self.page();
+        for column in columns {
+            draw(page, column, y, Font::Bold, 0.3, column.heading);
+        }
+        page.hline(LEFT, RIGHT, y + 4.0, 0.5, 0.8);
+        self.y += 3.0;
+    }
+
+    fn row(&mut self, columns: &[Column], cells: &[String], font: Font) {
+        self.y += ROW_HEIGHT;
+        let y = self.y;
+        let page = self.page();
+        for (column, cell) in columns.iter().zip(cells) {
+            draw(page, column, y, font, 0.0, cell);
+        }
+    }
+}
+
+fn method_label(method: &str) -> &str {
+    match method {
+        "fifo" => "FIFO",
+        "lifo" => "LIFO",
+        "hifo" => "HIFO",
+        "specid" => "specific identification",
+        "section104" => "UK share pooling",
+        other => other,
+    }
+}
+
+fn draw(page: &mut PdfPage, column: &Column, y: f64, font: Font, grey: f64, text: &str) {
+    if column.right {
+        page.text_right(column.x, y, font, TABLE_SIZE, grey, text);
+    } else {
+        page.text(column.x, y, font, TABLE_SIZE, grey, text);
+    }
+}
+
+/// The report as an A4 document for an accountant: the year's totals, its dispositions
+/// in Form 8949's short- and long-term parts, income, and the gifts, donations and fees
+/// that realize no gain, with amounts in `currency`. Pages are numbered in the footer.
+pub fn tax_report_pdf(report: &TaxReport, portfolio_name: &str, currency: &str) -> AppResult<Vec<u8>> {
+    let mut pages = Pages {
+        pages: vec![PdfPage::new()],
+        y: TOP,
+    };
+    let generated = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+    // Title
+    let page = pages.page();
+    page.text(LEFT, 72.0, Font::Bold, 20.0, 0.0, &format!("Tax report {}", report.year));
+    page.text(LEFT, 90.0, Font::Regular, 10.0, 0.3, portfolio_name);
+    page.text_right(RIGHT, 72.0, Font::Regular, 10.0, 0.3, &format!("{} to {}", report.period_start, report.period_end));
+    page.text_right(RIGHT, 86.0, Font::Regular, 10.0, 0.3, &format!("Cost basis: {}", method_label(&report.method)));
+    page.text_right(RIGHT, 100.0, Font::Regular, 10.0, 0.3, &format!("Generated {generated}"));
+    page.hline(LEFT, RIGHT, 114.0, 0.75, 0.8);
+    pages.y = 120.0;
+
+    // Summary
+    pages.heading(&format!("Summary ({})", currency.to_uppercase()));
+    let summary = [
+        ("Short-term gain or (loss)", report.short_term_gains),
+        ("Long-term gain or (loss)", report.long_term_gains),
+        ("Net capital gain or (loss)", report.total_gains),
+        ("Total proceeds", report.total_proceeds),
+        ("Total cost basis", report.total_cost_basis),
+        ("Income", report.total_income),
+    ];
+    for (label, amount) in summary {
+        pages.y += 15.0;
+        let y = pages.y;
+        let page = pages.page();
+        page.text(LEFT, y, Font::Regular, 10.0, 0.2, label);
+        page.text_right(RIGHT, y, Font::Bold, 10.0, 0.0, &pdf::format_amount(amount));
+    }
+    pages.y += 8.0;
+    if !report.warnings.is_empty() {
+        pages.note(&format!(
+            "{} transaction(s) have no recorded price and are counted at 0; figures involving them are understated.",
+            report.warnings.len()
+        ));
+        pages.y += 6.0;
+    }
+
+    // Dispositions, short-term then long-term as on Form 8949
+    for (long_term, title) in [(false, "Part I — Short-term dispositions"), (true, "Part II — Long-term dispositions")] {
+        let part: Vec<_> = report
+            .dispositions
+            .iter()
+            .filter(|d| (d.holding_period == "Long-term") == long_term)
+            .collect();
+        pages.heading(title);
+        if part.is_empty() {
+            pages.note("None.");
+            continue;
+        }
+        let rows: Vec<Vec<String>> = part
+            .iter()
+            .map(|d| {
+                vec![
+                    d.description.clone(),
+                    d.date_acquired.clone(),
+                    d.date_sold.clone(),
+                    pdf::format_amount(d.proceeds),
+                    pdf::format_amount(d.cost_basis),
+                    pdf::format_amount(d.gain_or_loss),
+                    d.holding_days.to_string(),
+                ]
+            })
+            .collect();
+        let totals = vec![
+            "Total".to_string(),
+            String::new(),
+            String::new(),
+            pdf::format_amount(part.iter().map(|d| d.proceeds).sum()),
+            pdf::format_amount(part.iter().map(|d| d.cost_basis).sum()),
+            pdf::format_amount(part.iter().map(|d| d.gain_or_loss).sum()),
+            String::new(),
+        ];
+        pages.table(&DISPOSITION_COLUMNS, &rows, Some(totals));
+    }
+
+    // Income
+    pages.heading("Income");
+    if report.income.is_empty() {
+        pages.note("None.");
+    } else {
+        let rows: Vec<Vec<String>> = report
+            .income
+            .iter()
+            .map(|i| {
+                vec![
+                    i.date.get(..10).unwrap_or(&i.date).to_string(),
+                    i.tx_type.clone(),
+                    format!("{:.8}", i.amount_sat as f64 / 1e8),
+                    pdf::format_amount(i.fair_value_usd),
+                ]
+            })
+            .collect();
+        let totals = vec![String::from("Total"), String::new(), String::new(), pdf::format_amount(report.total_income)];
+        pages.table(&INCOME_COLUMNS, &rows, Some(totals));
+    }
+
+    // Gifts, donations and fees
+    if !report.non_taxable_disposals.is_empty() {
+        pages.heading("Gifts, donations and fees");
+        pages.note("No gain or loss is realized on these; listed for gift and charitable contribution reporting.");
+        let rows: Vec<Vec<String>> = report
+            .non_taxable_disposals
+            .iter()
+            .map(|d| {
+                vec![
+                    d.date.get(..10).unwrap_or(&d.date).to_string(),
+                    d.tx_type.clone(),
+                    format!("{:.8}", d.amount_sat as f64 / 1e8),
+                    pdf::format_amount(d.fair_value_usd),
+                    pdf::format_amount(d.cost_basis_usd),
+                ]
+            })
+            .collect();
+        pages.table(&NON_TAXABLE_COLUMNS, &rows, None);
+    }
+
+    let count = pages.pages.len();
+    for (i, page) in pages.pages.iter_mut().enumerate() {
+        let footer = format!("{portfolio_name} — tax report {} — page {} of {count}", report.year, i + 1);
+        page.text_right(RIGHT, pdf::PAGE_HEIGHT - 30.0, Font::Regular, 8.0, 0.5, &footer);
+    }
+    pdf::finish_document(pages.pages, &format!("Tax report {}", report.year))
+}
@@ -0,0 +1,277 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::costbasis::CostBasisMethod;
+use crate::services::export;
+use crate::services::import::parse_datetime;
+use crate::services::tax::{self, TaxRules};
+use crate::services::transaction_splits::COUNTED;
+
+/// Tax software whose CSV import a portfolio can be exported for.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaxSoftware {
+    /// Koinly's universal transaction format.
+    Koinly,
+    /// CoinTracker's transaction import format.
+    CoinTracker,
+    /// TurboTax's crypto gains format: one row per disposition, for one tax year.
+    TurboTax,
+}
+
+impl TaxSoftware {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaxSoftware::Koinly => "koinly",
+            TaxSoftware::CoinTracker => "cointracker",
+            TaxSoftware::TurboTax => "turbotax",
+        }
+    }
+}
+
+/// A counted transaction, as the transaction formats need it.
+struct ExportTx {
+    tx_type: String,
+    amount_sat: i64,
+    fee_sat: Option<i64>,
+    price_usd: Option<f64>,
+    fiat_amount: Option<f64>,
+    fiat_currency: String,
+    txid: Option<String>,
+    transacted_at: DateTime<Utc>,
+}
+
+impl ExportTx {
+    /// BTC that left or arrived, without the network fee a send includes.
+    fn btc(&self) -> String {
+        let amount = match self.tx_type.as_str() {
+            "send" => self.amount_sat - self.fee_sat.unwrap_or(0).clamp(0, self.amount_sat),
+            _ => self.amount_sat,
+        };
+        format_btc(amount)
+    }
+
+    fn fee_btc(&self) -> String {
+        match self.fee_sat {
+            Some(fee) if fee > 0 => format_btc(fee),
+            _ => String::new(),
+        }
+    }
+
+    /// What a buy paid or a sell received: the recorded fiat amount, or the USD value at
+    /// `price_usd`.
+    fn fiat(&self) -> (String, String) {
+        match (self.fiat_amount, self.price_usd) {
+            (Some(amount), _) => (format!("{amount:.2}"), self.fiat_currency.to_uppercase()),
+            (None, Some(price)) => (format!("{:.2}", self.usd_value(price)), "USD".to_string()),
+            (None, None) => (String::new(), String::new()),
+        }
+    }
+
+    fn usd_value(&self, price: f64) -> f64 {
+        (self.amount_sat as f64 / 1e8) * price
+    }
+}
+
+/// Write the portfolio in `software`'s import format to `out`. The transaction formats
+/// (Koinly, CoinTracker) cover the whole history — the software needs earlier
+/// acquisitions to work out basis — or with `year`, everything up to the end of that
+/// tax year. TurboTax's is the year's dispositions under `method`, in USD, so needs `year`.
+pub fn write_csv<W: std::io::Write>(
+    pool: &DbPool,
+    portfolio_id: &str,
+    software: TaxSoftware,
+    year: Option<i32>,
+    method: CostBasisMethod,
+    rules: &TaxRules,
+    out: W,
+) -> AppResult<()> {
+    let before = year.and_then(|y| rules.tax_year_start(y + 1)).map(|d| d.to_string());
+    match software {
+        TaxSoftware::Koinly => write_koinly(&load(pool, portfolio_id, before.as_deref())?, out),
+        TaxSoftware::CoinTracker => write_cointracker(&load(pool, portfolio_id, before.as_deref())?, out),
+        TaxSoftware::TurboTax => {
+            let year = year.ok_or_else(|| AppError::BadRequest("The TurboTax export needs a year".into()))?;
+            let report = tax::generate_tax_report(pool, None, portfolio_id, year, method, "usd", rules)?;
+            write_turbotax(&report, out)
+        }
+    }
+}
+
+fn load(pool: &DbPool, portfolio_id: &str, before: Option<&str>) -> AppResult<Vec<ExportTx>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT tx_type, amount_sat, fee_sat, price_usd, fiat_amount, fiat_currency, txid, transacted_at
+         FROM transactions
+         WHERE portfolio_id = ?1 AND {COUNTED} AND (?2 IS NULL OR transacted_at < ?2)
+         ORDER BY transacted_at ASC, id"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![portfolio_id, before], |row| {
+        let transacted_at: String = row.get(7)?;
+        Ok(ExportTx {
+            tx_type: row.get(0)?,
+            amount_sat: row.get(1)?,
+            fee_sat: row.get(2)?,
+            price_usd: row.get(3)?,
+            fiat_amount: row.get(4)?,
+            fiat_currency: row.get(5)?,
+            txid: row.get(6)?,
+            transacted_at: parse_datetime(&transacted_at).unwrap_or_default(),
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Koinly universal format. Koinly pairs sends and receives between the user's own
+/// wallets itself; `transfer` records move nothing in or out, so they're left out.
+fn write_koinly<W: std::io::Write>(txs: &[ExportTx], out: W) -> AppResult<()> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Date",
+        "Sent Amount",
+        "Sent Currency",
+        "Received Amount",
+        "Received Currency",
+        "Fee Amount",
+        "Fee Currency",
+        "Net Worth Amount",
+        "Net Worth Currency",
+        "Label",
+        "Description",
+        "TxHash",
+    ])
+    .map_err(export::write_failed)?;
+
+    for tx in txs {
+        let btc = || (tx.btc(), "BTC".to_string());
+        let none = || (String::new(), String::new());
+        let ((sent, sent_currency), (received, received_currency), label) = match tx.tx_type.as_str() {
+            "buy" => (tx.fiat(), btc(), ""),
+            "sell" => (btc(), tx.fiat(), ""),
+            "receive" => (none(), btc(), ""),
+            "income" => (none(), btc(), "income"),
+            "mining" => (none(), btc(), "mining"),
+            "send" => (btc(), none(), ""),
+            "gift" => (btc(), none(), "gift"),
+            "donation" => (btc(), none(), "donation"),
+            "lost" => (btc(), none(), "lost"),
+            "fee" => (btc(), none(), "cost"),
+            _ => continue,
+        };
+        let fee = tx.fee_btc();
+        let (net_worth, net_worth_currency) = match tx.price_usd {
+            Some(price) => (format!("{:.2}", tx.usd_value(price)), "USD"),
+            None => (String::new(), ""),
+        };
+        wtr.write_record([
+            tx.transacted_at.format("%Y-%m-%d %H:%M UTC").to_string().as_str(),
+            &sent,
+            &sent_currency,
+            &received,
+            &received_currency,
+            &fee,
+            if fee.is_empty() { "" } else { "BTC" },
+            &net_worth,
+            net_worth_currency,
+            label,
+            &tx.tx_type,
+            tx.txid.as_deref().unwrap_or(""),
+        ])
+        .map_err(export::write_failed)?;
+    }
+    wtr.flush().map_err(export::write_failed)?;
+    Ok(())
+}
+
+/// CoinTracker's format. It has no tag for a bare fee, so `fee` records are a fee with
+/// nothing sent; `transfer` records are left out as for Koinly.
+fn write_cointracker<W: std::io::Write>(txs: &[ExportTx], out: W) -> AppResult<()> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record([
+        "Date",
+        "Received Quantity",
+        "Received Currency",
+        "Sent Quantity",
+        "Sent Currency",
+        "Fee Amount",
+        "Fee Currency",
+        "Tag",
+    ])
+    .map_err(export::write_failed)?;
+
+    for tx in txs {
+        let btc = || (tx.btc(), "BTC".to_string());
+        let none = || (String::new(), String::new());
+        let ((received, received_currency), (sent, sent_currency), tag) = match tx.tx_type.as_str() {
+            "buy" => (btc(), tx.fiat(), ""),
+            "sell" => (tx.fiat(), btc(), ""),
+            "receive" => (btc(), none(), ""),
+            "income" => (btc(), none(), "payment"),
+            "mining" => (btc(), none(), "mined"),
+            "send" => (none(), btc(), ""),
+            "gift" => (none(), btc(), "gift"),
+            "donation" => (none(), btc(), "donation"),
+            "lost" => (none(), btc(), "lost"),
+            "fee" => {
+                wtr.write_record([
+                    tx.transacted_at.format("%m/%d/%Y %H:%M:%S").to_string().as_str(),
+                    "",
+                    "",
+                    "",
+                    "",
+                    &format_btc(tx.amount_sat),
+                    "BTC",
+                    "",
+                ])
+                .map_err(export::write_failed)?;
+                continue;
+            }
+            _ => continue,
+        };
+        let fee = tx.fee_btc();
+        wtr.write_record([
+            tx.transacted_at.format("%m/%d/%Y %H:%M:%S").to_string().as_str(),
+            &received,
+            &received_currency,
+            &sent,
+            &sent_currency,
+            &fee,
+            if fee.is_empty() { "" } else { "BTC" },
+            tag,
+        ])
+        .map_err(export::write_failed)?;
+    }
+    wtr.flush().map_err(export::write_failed)?;
+    Ok(())
+}
+
+/// TurboTax's gains format, one row per disposition with the lot's purchase date.
+fn write_turbotax<W: std::io::Write>(report: &tax::TaxReport, out: W) -> AppResult<()> {
+    let mut wtr = csv::Writer::from_writer(out);
+    wtr.write_record(["Currency Name", "Purchase Date", "Cost Basis", "Date Sold", "Proceeds"])
+        .map_err(export::write_failed)?;
+
+    let us_date = |date: &str| match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(d) => d.format("%m/%d/%Y").to_string(),
+        Err(_) => date.to_string(),
+    };
+    for d in &report.dispositions {
+        wtr.write_record([
+            "BTC",
+            &us_date(&d.date_acquired),
+            &format!("{:.2}", d.cost_basis),
+            &us_date(&d.date_sold),
+            &format!("{:.2}", d.proceeds),
+        ])
+        .map_err(export::write_failed)?;
+    }
+    wtr.flush().map_err(export::write_failed)?;
+    Ok(())
+}
+
+fn format_btc(sat: i64) -> String {
+    format!("{:.8}", sat as f64 / 1e8)
+}
+
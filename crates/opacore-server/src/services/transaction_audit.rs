@@ -0,0 +1,172 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::{AppError, AppResult};
+use crate::services::jobs::column_value;
+
+/// Columns whose history is kept. Timestamps of the row itself are left out: the
+/// audit entry carries its own.
+const AUDITED_COLS: [&str; 15] = [
+    "wallet_id",
+    "tx_type",
+    "amount_sat",
+    "fee_sat",
+    "price_usd",
+    "fiat_amount",
+    "fiat_currency",
+    "txid",
+    "block_height",
+    "block_time",
+    "source",
+    "parent_id",
+    "counterparty",
+    "linked_transaction_id",
+    "transacted_at",
+];
+
+/// What a chain-synced transaction took from the chain. Changing these would make the
+/// row disagree with the blockchain, so only the type, price and labels stay editable.
+pub const CHAIN_FIELDS: [&str; 3] = ["amount_sat", "fee_sat", "transacted_at"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Labels,
+}
+
+impl AuditAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Labels => "labels",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub transaction_id: String,
+    /// Who made the change; `None` once that user is deleted.
+    pub user_id: Option<String>,
+    pub action: String,
+    /// The row as created or deleted, `{field: {from, to}}` for updates, or
+    /// `{from, to}` label ids for label changes.
+    pub changes: Value,
+    pub created_at: String,
+}
+
+pub const AUDIT_COLS: &str = "id, transaction_id, user_id, action, changes, created_at";
+
+pub fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<AuditEntry> {
+    let changes: String = row.get(4)?;
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        transaction_id: row.get(1)?,
+        user_id: row.get(2)?,
+        action: row.get(3)?,
+        changes: serde_json::from_str(&changes).unwrap_or(Value::Null),
+        created_at: row.get(5)?,
+    })
+}
+
+/// Reject a destructive edit of a chain-synced transaction or a part of one split.
+/// `changed` names the fields the edit would change; `None` means the row would be deleted.
+pub fn check_chain_edit(source: &str, changed: Option<&[&str]>) -> AppResult<()> {
+    let (what, on_delete) = match source {
+        "chain" => (
+            "a chain transaction",
+            "Chain transactions can't be deleted; reclassify them or remove the wallet instead",
+        ),
+        "split" => (
+            "a split part",
+            "Split parts can't be deleted on their own; split the transaction again or remove the split",
+        ),
+        _ => return Ok(()),
+    };
+    match changed {
+        None => Err(AppError::Forbidden(on_delete.into())),
+        Some(fields) => match fields.iter().find(|f| CHAIN_FIELDS.contains(f)) {
+            Some(field) => Err(AppError::Forbidden(format!(
+                "{field} of {what} comes from the blockchain and can't be edited"
+            ))),
+            None => Ok(()),
+        },
+    }
+}
+
+/// The audited columns of a transaction, or `None` if it doesn't exist.
+pub fn snapshot(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Option<Map<String, Value>>> {
+    let result = conn.query_row(
+        &format!("SELECT {} FROM transactions WHERE id = ?1", AUDITED_COLS.join(", ")),
+        rusqlite::params![transaction_id],
+        |row| {
+            let mut fields = Map::new();
+            for (i, name) in AUDITED_COLS.iter().enumerate() {
+                fields.insert(name.to_string(), column_value(row.get_ref(i)?));
+            }
+            Ok(fields)
+        },
+    );
+    match result {
+        Ok(fields) => Ok(Some(fields)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Append an entry. Pass the connection (or SQLite transaction) that made the change.
+pub fn record(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    transaction_id: &str,
+    user_id: Option<&str>,
+    action: AuditAction,
+    changes: &Value,
+) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO transaction_audit (transaction_id, portfolio_id, user_id, action, changes, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![transaction_id, portfolio_id, user_id, action.name(), changes.to_string(), now],
+    )?;
+    Ok(())
+}
+
+/// Record a transaction that was just inserted.
+pub fn record_created(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    transaction_id: &str,
+    user_id: Option<&str>,
+) -> AppResult<()> {
+    let row = snapshot(conn, transaction_id)?.unwrap_or_default();
+    record(conn, portfolio_id, transaction_id, user_id, AuditAction::Create, &Value::Object(row))
+}
+
+/// Record an update by comparing the row now with `before`, its [`snapshot`] from
+/// ahead of the change. Nothing is recorded if no audited field changed.
+pub fn record_updated(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    transaction_id: &str,
+    user_id: Option<&str>,
+    before: &Map<String, Value>,
+) -> AppResult<()> {
+    let after = snapshot(conn, transaction_id)?.unwrap_or_default();
+    let mut changes = Map::new();
+    for (field, to) in &after {
+        let from = before.get(field).unwrap_or(&Value::Null);
+        if from != to {
+            changes.insert(field.clone(), serde_json::json!({ "from": from, "to": to }));
+        }
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+    record(conn, portfolio_id, transaction_id, user_id, AuditAction::Update, &Value::Object(changes))
+}
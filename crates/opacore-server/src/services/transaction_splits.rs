@@ -0,0 +1,182 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::{AppError, AppResult};
+use crate::services::costbasis::{self, TxKind};
+use crate::services::events::{self, DomainEvent};
+use crate::services::transaction_audit::{self, AuditAction};
+
+/// Most parts one transaction can be split into.
+const MAX_PARTS: usize = 20;
+
+/// Condition (on an unaliased `transactions`) for rows that count towards balances and
+/// cost basis. A split transaction counts through its parts only, so it isn't counted twice.
+pub const COUNTED: &str = "NOT EXISTS (SELECT 1 FROM transactions part WHERE part.parent_id = transactions.id)";
+
+#[derive(Debug, Deserialize)]
+pub struct SplitPart {
+    pub amount_sat: i64,
+    pub tx_type: String,
+    /// Defaults to the parent's price.
+    pub price_usd: Option<f64>,
+    #[serde(default)]
+    pub label_ids: Vec<String>,
+}
+
+struct Parent {
+    portfolio_id: String,
+    wallet_id: Option<String>,
+    tx_type: String,
+    amount_sat: i64,
+    price_usd: Option<f64>,
+    fiat_amount: Option<f64>,
+    fiat_currency: String,
+    block_height: Option<i64>,
+    block_time: Option<String>,
+    source: String,
+    transacted_at: String,
+}
+
+fn load_parent(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Parent> {
+    conn.query_row(
+        "SELECT portfolio_id, wallet_id, tx_type, amount_sat, price_usd, fiat_amount, fiat_currency,
+                block_height, block_time, source, transacted_at
+         FROM transactions WHERE id = ?1",
+        rusqlite::params![transaction_id],
+        |row| {
+            Ok(Parent {
+                portfolio_id: row.get(0)?,
+                wallet_id: row.get(1)?,
+                tx_type: row.get(2)?,
+                amount_sat: row.get(3)?,
+                price_usd: row.get(4)?,
+                fiat_amount: row.get(5)?,
+                fiat_currency: row.get(6)?,
+                block_height: row.get(7)?,
+                block_time: row.get(8)?,
+                source: row.get(9)?,
+                transacted_at: row.get(10)?,
+            })
+        },
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Transaction not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Ids of a transaction's parts, in the order they were given.
+fn part_ids(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT id FROM transactions WHERE parent_id = ?1 ORDER BY rowid")?;
+    let ids = stmt
+        .query_map(rusqlite::params![transaction_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
+/// Divide a chain transaction into `parts`, replacing any earlier split. The parts carry
+/// the parent's wallet, time and block and must add up to its amount; the parent stays as
+/// the on-chain record.
+pub fn split(
+    conn: &rusqlite::Connection,
+    user_id: &str,
+    transaction_id: &str,
+    parts: &[SplitPart],
+) -> AppResult<()> {
+    let parent = load_parent(conn, transaction_id)?;
+    if parent.source != "chain" {
+        return Err(AppError::BadRequest("Only chain transactions can be split".into()));
+    }
+    if parts.len() < 2 || parts.len() > MAX_PARTS {
+        return Err(AppError::BadRequest(format!("A split needs between 2 and {MAX_PARTS} parts")));
+    }
+    if parts.iter().any(|p| p.amount_sat <= 0) {
+        return Err(AppError::BadRequest("Every part needs a positive amount_sat".into()));
+    }
+    let total: i64 = parts.iter().map(|p| p.amount_sat).sum();
+    if total != parent.amount_sat {
+        return Err(AppError::BadRequest(format!(
+            "Parts add up to {total} sat but the transaction is {} sat",
+            parent.amount_sat
+        )));
+    }
+    let parent_kind = TxKind::of(&parent.tx_type);
+    for part in parts {
+        costbasis::validate_tx_type(&part.tx_type)?;
+        // A part can be reclassified, but can't move coins the other way
+        let opposite = match (parent_kind, TxKind::of(&part.tx_type)) {
+            (Some(p), Some(k)) => (p.is_inflow() && k.is_outflow()) || (p.is_outflow() && k.is_inflow()),
+            _ => false,
+        };
+        if opposite {
+            return Err(AppError::BadRequest(format!(
+                "A part of a {} can't be a {}",
+                parent.tx_type, part.tx_type
+            )));
+        }
+        for label_id in &part.label_ids {
+            let label_exists: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM labels WHERE id = ?1 AND user_id = ?2)",
+                rusqlite::params![label_id, user_id],
+                |row| row.get(0),
+            )?;
+            if !label_exists {
+                return Err(AppError::NotFound(format!("Label {label_id} not found")));
+            }
+        }
+    }
+
+    remove_parts(conn, user_id, &parent.portfolio_id, transaction_id)?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    for part in parts {
+        let id = uuid::Uuid::new_v4().to_string();
+        // The parent's fiat value shared out, unless the part is priced on its own
+        let fiat_amount = match part.price_usd {
+            None => parent.fiat_amount.map(|f| f * part.amount_sat as f64 / parent.amount_sat as f64),
+            Some(price) if parent.fiat_currency == "usd" => Some(part.amount_sat as f64 / 1e8 * price),
+            Some(_) => None,
+        };
+        conn.execute(
+            "INSERT INTO transactions (id, portfolio_id, wallet_id, parent_id, tx_type, amount_sat, price_usd, fiat_amount, fiat_currency, block_height, block_time, source, transacted_at, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, 'split', ?12, ?13, ?14)",
+            rusqlite::params![
+                id, parent.portfolio_id, parent.wallet_id, transaction_id, part.tx_type,
+                part.amount_sat, part.price_usd.or(parent.price_usd), fiat_amount,
+                parent.fiat_currency, parent.block_height, parent.block_time,
+                parent.transacted_at, now, now
+            ],
+        )?;
+        for label_id in &part.label_ids {
+            conn.execute(
+                "INSERT OR IGNORE INTO transaction_labels (transaction_id, label_id) VALUES (?1, ?2)",
+                rusqlite::params![id, label_id],
+            )?;
+        }
+        events::record(
+            conn,
+            &parent.portfolio_id,
+            &DomainEvent::TransactionCreated { transaction_id: id.clone(), wallet_id: parent.wallet_id.clone() },
+        )?;
+        transaction_audit::record_created(conn, &parent.portfolio_id, &id, Some(user_id))?;
+    }
+    Ok(())
+}
+
+/// Merge a split transaction back: delete its parts so the parent counts again.
+/// Returns how many parts were removed.
+pub fn unsplit(conn: &rusqlite::Connection, user_id: &str, transaction_id: &str) -> AppResult<usize> {
+    let parent = load_parent(conn, transaction_id)?;
+    remove_parts(conn, user_id, &parent.portfolio_id, transaction_id)
+}
+
+fn remove_parts(conn: &rusqlite::Connection, user_id: &str, portfolio_id: &str, transaction_id: &str) -> AppResult<usize> {
+    let ids = part_ids(conn, transaction_id)?;
+    for id in &ids {
+        let before = transaction_audit::snapshot(conn, id)?.unwrap_or_default();
+        conn.execute("DELETE FROM transactions WHERE id = ?1", rusqlite::params![id])?;
+        transaction_audit::record(conn, portfolio_id, id, Some(user_id), AuditAction::Delete, &Value::Object(before))?;
+        events::record(conn, portfolio_id, &DomainEvent::TransactionDeleted { transaction_id: id.clone() })?;
+    }
+    Ok(ids.len())
+}
@@ -0,0 +1,159 @@
+use serde::Serialize;
+
+use crate::auth::session::ClientInfo;
+use crate::error::{AppError, AppResult};
+use crate::models::User;
+use crate::services::events::{self, DomainEvent};
+use crate::services::tax_locks;
+use crate::services::transaction_audit;
+use crate::services::transaction_splits::COUNTED;
+
+/// A send and the receive it paid, between two of the user's own wallets. Cost basis
+/// carries the coins' lots across, and only the network fee is spent.
+#[derive(Debug, Serialize)]
+pub struct TransferLink {
+    pub send_id: String,
+    pub receive_id: String,
+}
+
+struct Side {
+    portfolio_id: String,
+    tx_type: String,
+    amount_sat: i64,
+    linked_transaction_id: Option<String>,
+    counted: bool,
+}
+
+fn load_side(conn: &rusqlite::Connection, transaction_id: &str) -> AppResult<Side> {
+    conn.query_row(
+        &format!("SELECT portfolio_id, tx_type, amount_sat, linked_transaction_id, {COUNTED} FROM transactions WHERE id = ?1"),
+        rusqlite::params![transaction_id],
+        |row| {
+            Ok(Side {
+                portfolio_id: row.get(0)?,
+                tx_type: row.get(1)?,
+                amount_sat: row.get(2)?,
+                linked_transaction_id: row.get(3)?,
+                counted: row.get(4)?,
+            })
+        },
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => AppError::NotFound("Transaction not found".into()),
+        e => AppError::Database(e),
+    })
+}
+
+/// Link two transactions as a transfer: one must be a `send`, the other a `receive` of no
+/// more than was sent, both in the same portfolio, and neither linked already.
+pub fn link(conn: &rusqlite::Connection, a: &str, b: &str, user_id: Option<&str>) -> AppResult<TransferLink> {
+    if a == b {
+        return Err(AppError::BadRequest("A transaction can't be linked to itself".into()));
+    }
+    let (first, second) = (load_side(conn, a)?, load_side(conn, b)?);
+    let (send_id, send, receive_id, receive) = match (first.tx_type.as_str(), second.tx_type.as_str()) {
+        ("send", "receive") => (a, first, b, second),
+        ("receive", "send") => (b, second, a, first),
+        _ => return Err(AppError::BadRequest("A transfer links a send with a receive".into())),
+    };
+    if send.portfolio_id != receive.portfolio_id {
+        return Err(AppError::BadRequest("A transfer links two transactions in the same portfolio".into()));
+    }
+    if send.linked_transaction_id.is_some() || receive.linked_transaction_id.is_some() {
+        return Err(AppError::Conflict("Unlink the transaction's current transfer first".into()));
+    }
+    if !send.counted || !receive.counted {
+        return Err(AppError::BadRequest("Link the parts of a split transaction instead".into()));
+    }
+    if receive.amount_sat > send.amount_sat {
+        return Err(AppError::BadRequest("The receive is larger than the send".into()));
+    }
+
+    set_link(conn, send_id, &send.portfolio_id, Some(receive_id), user_id)?;
+    set_link(conn, receive_id, &receive.portfolio_id, Some(send_id), user_id)?;
+    Ok(TransferLink {
+        send_id: send_id.to_string(),
+        receive_id: receive_id.to_string(),
+    })
+}
+
+/// Remove a transaction's transfer link, from both sides.
+pub fn unlink(conn: &rusqlite::Connection, transaction_id: &str, user_id: Option<&str>) -> AppResult<()> {
+    let side = load_side(conn, transaction_id)?;
+    let Some(other_id) = side.linked_transaction_id else {
+        return Err(AppError::NotFound("The transaction isn't linked to a transfer".into()));
+    };
+    set_link(conn, transaction_id, &side.portfolio_id, None, user_id)?;
+    if let Ok(other) = load_side(conn, &other_id) {
+        if other.linked_transaction_id.as_deref() == Some(transaction_id) {
+            set_link(conn, &other_id, &other.portfolio_id, None, user_id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Link the unlinked sends and receives of the same on-chain transaction between two of
+/// the portfolio's wallets. Returns how many transfers were linked.
+///
+/// A pair in a locked tax year goes through [`tax_locks::check_transaction`] for `by`, the
+/// user asking: refused, or an audited admin override. Without one (on sync) it's skipped.
+pub fn link_by_txid(
+    conn: &rusqlite::Connection,
+    portfolio_id: &str,
+    by: Option<(&User, &ClientInfo)>,
+) -> AppResult<usize> {
+    let unlocked = match by {
+        Some(_) => String::new(),
+        None => format!("AND {} AND {}", tax_locks::unlocked("s"), tax_locks::unlocked("r")),
+    };
+    let pairs: Vec<(String, String)> = conn
+        .prepare(&format!(
+            "SELECT s.id, r.id FROM transactions s
+             JOIN transactions r ON r.portfolio_id = s.portfolio_id AND r.txid = s.txid AND r.wallet_id != s.wallet_id
+             WHERE s.portfolio_id = ?1 AND s.tx_type = 'send' AND r.tx_type = 'receive'
+               AND s.linked_transaction_id IS NULL AND r.linked_transaction_id IS NULL
+               AND r.amount_sat <= s.amount_sat
+               AND NOT EXISTS (SELECT 1 FROM transactions part WHERE part.parent_id IN (s.id, r.id))
+               {unlocked}
+             ORDER BY s.transacted_at, s.id"
+        ))?
+        .query_map(rusqlite::params![portfolio_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    // A send paying two of the wallets is linked to the first receive only
+    let mut used = std::collections::HashSet::new();
+    let mut linked = 0;
+    for (send_id, receive_id) in pairs {
+        if used.contains(&send_id) || used.contains(&receive_id) {
+            continue;
+        }
+        if let Some((user, client)) = by {
+            tax_locks::check_transaction(conn, &send_id, user, client)?;
+            tax_locks::check_transaction(conn, &receive_id, user, client)?;
+        }
+        let user_id = by.map(|(user, _)| user.id.as_str());
+        set_link(conn, &send_id, portfolio_id, Some(&receive_id), user_id)?;
+        set_link(conn, &receive_id, portfolio_id, Some(&send_id), user_id)?;
+        used.insert(send_id);
+        used.insert(receive_id);
+        linked += 1;
+    }
+    Ok(linked)
+}
+
+fn set_link(
+    conn: &rusqlite::Connection,
+    transaction_id: &str,
+    portfolio_id: &str,
+    linked_transaction_id: Option<&str>,
+    user_id: Option<&str>,
+) -> AppResult<()> {
+    let before = transaction_audit::snapshot(conn, transaction_id)?.unwrap_or_default();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "UPDATE transactions SET linked_transaction_id = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![linked_transaction_id, now, transaction_id],
+    )?;
+    events::record(conn, portfolio_id, &DomainEvent::TransactionUpdated { transaction_id: transaction_id.to_string() })?;
+    transaction_audit::record_updated(conn, portfolio_id, transaction_id, user_id, &before)
+}
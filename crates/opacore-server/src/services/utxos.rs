@@ -0,0 +1,123 @@
+use serde::Serialize;
+
+use crate::error::{AppError, AppResult};
+use crate::services::wallet::UtxoInfo;
+
+/// Label, notes and frozen flag attached to a single UTXO.
+#[derive(Debug, Serialize)]
+pub struct UtxoMetadata {
+    pub txid: String,
+    pub vout: u32,
+    pub label: Option<String>,
+    pub notes: Option<String>,
+    pub frozen: bool,
+    pub updated_at: String,
+}
+
+/// Parse a `txid:vout` outpoint.
+pub fn parse_outpoint(outpoint: &str) -> AppResult<(String, u32)> {
+    let invalid = || AppError::BadRequest("Outpoint must be in the form <txid>:<vout>".into());
+    let (txid, vout) = outpoint.split_once(':').ok_or_else(invalid)?;
+    if txid.len() != 64 || !txid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let vout = vout.parse::<u32>().map_err(|_| invalid())?;
+    Ok((txid.to_ascii_lowercase(), vout))
+}
+
+pub fn get_metadata(
+    conn: &rusqlite::Connection,
+    wallet_id: &str,
+    txid: &str,
+    vout: u32,
+) -> AppResult<Option<UtxoMetadata>> {
+    match conn.query_row(
+        "SELECT txid, vout, label, notes, frozen, updated_at FROM utxo_metadata
+         WHERE wallet_id = ?1 AND txid = ?2 AND vout = ?3",
+        rusqlite::params![wallet_id, txid, vout],
+        |row| {
+            Ok(UtxoMetadata {
+                txid: row.get(0)?,
+                vout: row.get(1)?,
+                label: row.get(2)?,
+                notes: row.get(3)?,
+                frozen: row.get(4)?,
+                updated_at: row.get(5)?,
+            })
+        },
+    ) {
+        Ok(m) => Ok(Some(m)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(AppError::Database(e)),
+    }
+}
+
+pub fn save_metadata(
+    conn: &rusqlite::Connection,
+    wallet_id: &str,
+    txid: &str,
+    vout: u32,
+    label: Option<&str>,
+    notes: Option<&str>,
+    frozen: bool,
+) -> AppResult<UtxoMetadata> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    conn.execute(
+        "INSERT INTO utxo_metadata (wallet_id, txid, vout, label, notes, frozen, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(wallet_id, txid, vout) DO UPDATE SET
+            label = excluded.label,
+            notes = excluded.notes,
+            frozen = excluded.frozen,
+            updated_at = excluded.updated_at",
+        rusqlite::params![wallet_id, txid, vout, label, notes, frozen, now],
+    )?;
+
+    Ok(UtxoMetadata {
+        txid: txid.to_string(),
+        vout,
+        label: label.map(String::from),
+        notes: notes.map(String::from),
+        frozen,
+        updated_at: now,
+    })
+}
+
+/// Returns false if there was no metadata to delete.
+pub fn delete_metadata(conn: &rusqlite::Connection, wallet_id: &str, txid: &str, vout: u32) -> AppResult<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM utxo_metadata WHERE wallet_id = ?1 AND txid = ?2 AND vout = ?3",
+        rusqlite::params![wallet_id, txid, vout],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// UTXOs the user has frozen, which coin selection must not spend.
+pub fn frozen_outpoints(conn: &rusqlite::Connection, wallet_id: &str) -> AppResult<Vec<(String, u32)>> {
+    let mut stmt = conn.prepare("SELECT txid, vout FROM utxo_metadata WHERE wallet_id = ?1 AND frozen = 1")?;
+    let rows = stmt.query_map(rusqlite::params![wallet_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let frozen: Result<Vec<_>, _> = rows.collect();
+    Ok(frozen?)
+}
+
+/// Fill in label, notes and frozen flag on a wallet's UTXOs.
+pub fn attach_metadata(conn: &rusqlite::Connection, wallet_id: &str, utxos: &mut [UtxoInfo]) -> AppResult<()> {
+    let mut stmt = conn.prepare(
+        "SELECT label, notes, frozen FROM utxo_metadata WHERE wallet_id = ?1 AND txid = ?2 AND vout = ?3",
+    )?;
+    for utxo in utxos.iter_mut() {
+        let found = stmt.query_row(rusqlite::params![wallet_id, utxo.txid, utxo.vout], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        });
+        match found {
+            Ok((label, notes, frozen)) => {
+                utxo.label = label;
+                utxo.notes = notes;
+                utxo.frozen = frozen;
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => {}
+            Err(e) => return Err(AppError::Database(e)),
+        }
+    }
+    Ok(())
+}
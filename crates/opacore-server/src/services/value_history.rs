@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::costbasis::TxKind;
+use crate::services::transaction_splits::COUNTED;
+
+/// Longest range one request may ask for, in days.
+pub const MAX_DAYS: i64 = 3660;
+
+#[derive(Debug, Serialize)]
+pub struct ValueHistory {
+    pub currency: String,
+    pub start: String,
+    pub end: String,
+    pub points: Vec<ValuePoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValuePoint {
+    pub date: String,
+    /// Balance at the end of the day.
+    pub balance_sat: i64,
+    /// The latest cached BTC price on or before `date`, and the day it's from. `None`
+    /// before the first cached price.
+    pub price: Option<f64>,
+    pub price_date: Option<String>,
+    pub value: Option<f64>,
+}
+
+/// The portfolio's balance at the end of each day from `start` to `end` (by default, the
+/// day of its first transaction to today), valued at cached daily prices in `currency`.
+/// Nothing is fetched: days with no cached price carry the last one forward.
+#[tracing::instrument(level = "debug", skip(pool))]
+pub fn value_history(
+    pool: &DbPool,
+    portfolio_id: &str,
+    currency: &str,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+) -> AppResult<ValueHistory> {
+    let conn = pool.get()?;
+
+    // Net change in balance per day, in the same terms as the portfolio summary's balance
+    let mut deltas: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT substr(transacted_at, 1, 10), tx_type, amount_sat
+         FROM transactions
+         WHERE portfolio_id = ?1 AND {COUNTED}"
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![portfolio_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+    })?;
+    for row in rows {
+        let (day, tx_type, amount_sat) = row?;
+        let Ok(day) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") else {
+            continue;
+        };
+        let delta = match TxKind::of(&tx_type) {
+            Some(kind) if kind.is_inflow() => amount_sat,
+            Some(kind) if kind.is_outflow() => -amount_sat,
+            _ => continue,
+        };
+        *deltas.entry(day).or_default() += delta;
+    }
+
+    let end = end.unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let start = start.or_else(|| deltas.keys().next().copied()).unwrap_or(end).min(end);
+    if (end - start).num_days() >= MAX_DAYS {
+        return Err(AppError::BadRequest(format!(
+            "start to end must be at most {MAX_DAYS} days"
+        )));
+    }
+
+    let prices: BTreeMap<String, f64> = conn
+        .prepare("SELECT date, price FROM price_history WHERE currency = ?1 AND date <= ?2")?
+        .query_map(rusqlite::params![currency, end.to_string()], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+
+    let mut balance: i64 = deltas.range(..start).map(|(_, delta)| delta).sum();
+    let mut points = Vec::with_capacity((end - start).num_days() as usize + 1);
+    for day in start.iter_days().take_while(|day| *day <= end) {
+        balance += deltas.get(&day).copied().unwrap_or(0);
+        let date = day.to_string();
+        let latest = prices.range(..=date.clone()).next_back();
+        points.push(ValuePoint {
+            balance_sat: balance,
+            price: latest.map(|(_, price)| *price),
+            price_date: latest.map(|(date, _)| date.clone()),
+            value: latest.map(|(_, price)| (balance as f64 / 1e8 * price * 100.0).round() / 100.0),
+            date,
+        });
+    }
+
+    Ok(ValueHistory {
+        currency: currency.to_string(),
+        start: start.to_string(),
+        end: end.to_string(),
+        points,
+    })
+}
@@ -1,10 +1,15 @@
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
-use bdk_wallet::bitcoin::Network;
+use std::str::FromStr;
+
+use base64::Engine;
+use bdk_wallet::bitcoin::{Address, Amount, FeeRate, Network, NetworkKind, OutPoint, Txid};
 use bdk_wallet::rusqlite::Connection as BdkConnection;
 use bdk_wallet::{KeychainKind, PersistedWallet};
 
 use crate::error::{AppError, AppResult};
+use crate::services::sync::keychain_name;
 
 /// Parse a network string into a BDK Network enum.
 pub fn parse_network(network: &str) -> AppResult<Network> {
@@ -17,6 +22,98 @@ pub fn parse_network(network: &str) -> AppResult<Network> {
     }
 }
 
+/// Resolve a new wallet's network from its keys or address, checked against the one the
+/// user asked for. Extended keys only tell mainnet from the test networks, so a test key
+/// with no explicit network is assumed to be testnet.
+pub fn resolve_network(
+    descriptor: Option<&str>,
+    xpub: Option<&str>,
+    address: Option<&str>,
+    requested: Option<Network>,
+) -> AppResult<Network> {
+    if let Some(addr) = address {
+        let unchecked = Address::from_str(addr.trim())
+            .map_err(|e| AppError::BadRequest(format!("Invalid address: {e}")))?;
+        return match requested {
+            Some(network) if unchecked.is_valid_for_network(network) => Ok(network),
+            Some(network) => Err(AppError::BadRequest(format!(
+                "Address is not a {network} address"
+            ))),
+            None => [Network::Bitcoin, Network::Testnet, Network::Regtest]
+                .into_iter()
+                .find(|&n| unchecked.is_valid_for_network(n))
+                .ok_or_else(|| AppError::BadRequest("Address network not recognised".into())),
+        };
+    }
+
+    let kind = match (descriptor, xpub) {
+        (Some(desc), _) => descriptor_network_kind(desc)?,
+        (None, Some(xpub)) => Some(xpub_network_kind(xpub)?),
+        (None, None) => None,
+    };
+
+    match (kind, requested) {
+        (Some(kind), Some(network)) if NetworkKind::from(network) != kind => {
+            Err(AppError::BadRequest(format!(
+                "Keys are for {}, but the wallet network is {network}",
+                network_kind_name(kind)
+            )))
+        }
+        (_, Some(network)) => Ok(network),
+        (Some(NetworkKind::Test), None) => Ok(Network::Testnet),
+        (_, None) => Ok(Network::Bitcoin),
+    }
+}
+
+/// Network of an extended public key from its version prefix, including the
+/// SLIP-132 variants wallets export for segwit accounts.
+fn xpub_network_kind(xpub: &str) -> AppResult<NetworkKind> {
+    match xpub.trim().get(..4) {
+        Some("xpub" | "ypub" | "zpub" | "Ypub" | "Zpub") => Ok(NetworkKind::Main),
+        Some("tpub" | "upub" | "vpub" | "Upub" | "Vpub") => Ok(NetworkKind::Test),
+        _ => Err(AppError::BadRequest(
+            "Unrecognised extended key prefix (expected xpub, ypub, zpub, tpub, upub or vpub)".into(),
+        )),
+    }
+}
+
+/// Network of a descriptor's extended keys, or None if it only has single keys.
+fn descriptor_network_kind(descriptor: &str) -> AppResult<Option<NetworkKind>> {
+    use bdk_wallet::bitcoin::secp256k1::Secp256k1;
+    use bdk_wallet::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+    use bdk_wallet::miniscript::ForEachKey;
+
+    let (desc, _keymap) = Descriptor::<DescriptorPublicKey>::parse_descriptor(
+        &Secp256k1::new(),
+        &sanitize_descriptor(descriptor),
+    )
+    .map_err(|e| AppError::BadRequest(format!("Invalid descriptor: {e}")))?;
+
+    let mut kinds = Vec::new();
+    desc.for_each_key(|key| {
+        let kind = match key {
+            DescriptorPublicKey::XPub(x) => Some(x.xkey.network),
+            DescriptorPublicKey::MultiXPub(x) => Some(x.xkey.network),
+            DescriptorPublicKey::Single(_) => None,
+        };
+        kinds.extend(kind);
+        true
+    });
+
+    match kinds.split_first() {
+        None => Ok(None),
+        Some((first, rest)) if rest.iter().all(|k| k == first) => Ok(Some(*first)),
+        Some(_) => Err(AppError::BadRequest("Descriptor mixes mainnet and test network keys".into())),
+    }
+}
+
+fn network_kind_name(kind: NetworkKind) -> &'static str {
+    match kind {
+        NetworkKind::Main => "mainnet",
+        NetworkKind::Test => "a test network",
+    }
+}
+
 /// Strip non-ASCII characters from a descriptor string (e.g. curly quotes from copy-paste).
 fn sanitize_descriptor(s: &str) -> String {
     s.chars().filter(|c| c.is_ascii()).collect()
@@ -105,30 +202,201 @@ pub fn load_or_create_bdk_wallet(
     Ok((wallet, conn))
 }
 
-/// Get addresses from a BDK wallet.
+/// Remove a wallet's BDK SQLite database (and any WAL/SHM sidecar files) from disk.
+/// Returns the number of files removed; missing files are not an error.
+pub fn remove_bdk_wallet_files(wallets_dir: &str, wallet_id: &str) -> AppResult<usize> {
+    let mut removed = 0;
+    for suffix in ["db", "db-wal", "db-shm", "db-journal"] {
+        let path = Path::new(wallets_dir).join(format!("{wallet_id}.{suffix}"));
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(AppError::Internal(format!(
+                    "Failed to remove BDK wallet file {}: {e}",
+                    path.display()
+                )));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Find the keychain and derivation index of `address`, searching the first `search_limit`
+/// indexes of each keychain (and anything already revealed beyond that).
+pub fn find_address_derivation(
+    wallet: &bdk_wallet::Wallet,
+    address: &str,
+    search_limit: u32,
+) -> Option<(KeychainKind, u32)> {
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        let revealed = wallet.derivation_index(keychain).map(|i| i + 1).unwrap_or(0);
+        for index in 0..search_limit.max(revealed) {
+            if wallet.peek_address(keychain, index).address.to_string() == address {
+                return Some((keychain, index));
+            }
+        }
+    }
+    None
+}
+
+/// Full BIP32 paths (`m/...`, from the key origin down to the address) of every key
+/// behind the address at `index`. Empty for descriptors without key origins.
+pub fn address_derivation_paths(wallet: &bdk_wallet::Wallet, keychain: KeychainKind, index: u32) -> Vec<String> {
+    use bdk_wallet::miniscript::ForEachKey;
+
+    let mut paths = Vec::new();
+    if let Ok(derived) = wallet.public_descriptor(keychain).at_derivation_index(index) {
+        derived.for_each_key(|key| {
+            if let Some(path) = key.full_derivation_path() {
+                paths.push(format!("m/{path}"));
+            }
+            true
+        });
+    }
+    paths
+}
+
+/// Reveal the next receive address that hasn't been used on chain and isn't in `taken`,
+/// and persist the reveal so it's never handed out again.
+pub fn reveal_unused_address(
+    wallet: &mut PersistedWallet<BdkConnection>,
+    conn: &mut BdkConnection,
+    taken: &HashSet<String>,
+) -> AppResult<(u32, String)> {
+    // Bounded so a wallet with a runaway `taken` list can't spin forever
+    for _ in 0..1000 {
+        let info = wallet.reveal_next_address(KeychainKind::External);
+        let address = info.address.to_string();
+        if !wallet.spk_index().is_used(KeychainKind::External, info.index) && !taken.contains(&address) {
+            wallet
+                .persist(conn)
+                .map_err(|e| AppError::Internal(format!("Failed to persist BDK wallet: {e}")))?;
+            return Ok((info.index, address));
+        }
+    }
+    Err(AppError::Internal("No unused receive address found".into()))
+}
+
+/// Get addresses from a BDK wallet with usage stats from its transaction graph.
+///
+/// Lists at least `count` receive addresses, extended to cover every used one, plus
+/// change addresses up to the highest used index.
 pub fn get_wallet_addresses(
     wallet: &bdk_wallet::Wallet,
     count: u32,
 ) -> Vec<AddressInfo> {
-    let mut addresses = Vec::new();
+    let mut stats: HashMap<(KeychainKind, u32), AddressStats> = HashMap::new();
 
-    for index in 0..count {
-        let addr = wallet.peek_address(KeychainKind::External, index);
-        addresses.push(AddressInfo {
-            index,
-            address: addr.address.to_string(),
-            keychain: "external".to_string(),
-        });
+    for wtx in wallet.transactions() {
+        let tx = &wtx.tx_node.tx;
+        let mut touched = HashSet::new();
+        for output in &tx.output {
+            if let Some(derivation) = wallet.derivation_of_spk(output.script_pubkey.clone()) {
+                stats.entry(derivation).or_default().total_received_sat += output.value.to_sat();
+                touched.insert(derivation);
+            }
+        }
+        for input in &tx.input {
+            let prev = wallet.tx_graph().get_txout(input.previous_output);
+            if let Some(derivation) = prev.and_then(|txout| wallet.derivation_of_spk(txout.script_pubkey.clone())) {
+                touched.insert(derivation);
+            }
+        }
+        for derivation in touched {
+            stats.entry(derivation).or_default().tx_count += 1;
+        }
+    }
+
+    for utxo in wallet.list_unspent() {
+        stats.entry((utxo.keychain, utxo.derivation_index)).or_default().balance_sat += utxo.txout.value.to_sat();
+    }
+
+    let highest_used = |keychain: KeychainKind| {
+        stats
+            .iter()
+            .filter(|((k, _), s)| *k == keychain && s.tx_count > 0)
+            .map(|((_, index), _)| *index + 1)
+            .max()
+            .unwrap_or(0)
+    };
+    let ranges = [
+        (KeychainKind::External, count.max(highest_used(KeychainKind::External))),
+        (KeychainKind::Internal, highest_used(KeychainKind::Internal)),
+    ];
+
+    let mut addresses = Vec::new();
+    for (keychain, end) in ranges {
+        for index in 0..end {
+            let addr = wallet.peek_address(keychain, index);
+            let s = stats.get(&(keychain, index)).cloned().unwrap_or_default();
+            addresses.push(AddressInfo {
+                index,
+                address: addr.address.to_string(),
+                keychain: keychain_name(keychain).to_string(),
+                used: s.tx_count > 0,
+                total_received_sat: s.total_received_sat,
+                balance_sat: s.balance_sat,
+                tx_count: s.tx_count,
+            });
+        }
     }
 
     addresses
 }
 
+#[derive(Debug, Default, Clone)]
+struct AddressStats {
+    total_received_sat: u64,
+    balance_sat: u64,
+    tx_count: u32,
+}
+
 #[derive(Debug, serde::Serialize)]
 pub struct AddressInfo {
     pub index: u32,
     pub address: String,
     pub keychain: String,
+    pub used: bool,
+    /// Includes unconfirmed transactions.
+    pub total_received_sat: u64,
+    pub balance_sat: u64,
+    pub tx_count: u32,
+}
+
+pub const ADDRESS_COLS: &str = "idx, address, keychain, used, total_received_sat, balance_sat, tx_count";
+
+pub fn row_to_address_info(row: &rusqlite::Row) -> rusqlite::Result<AddressInfo> {
+    Ok(AddressInfo {
+        index: row.get(0)?,
+        address: row.get(1)?,
+        keychain: row.get(2)?,
+        used: row.get::<_, i32>(3)? != 0,
+        total_received_sat: row.get::<_, i64>(4)? as u64,
+        balance_sat: row.get::<_, i64>(5)? as u64,
+        tx_count: row.get(6)?,
+    })
+}
+
+/// Replace the wallet's rows in `wallet_addresses` with freshly computed stats.
+pub fn persist_addresses(conn: &rusqlite::Connection, wallet_id: &str, addresses: &[AddressInfo]) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let db_tx = conn.unchecked_transaction()?;
+    db_tx.execute("DELETE FROM wallet_addresses WHERE wallet_id = ?1", rusqlite::params![wallet_id])?;
+    {
+        let mut stmt = db_tx.prepare(
+            "INSERT INTO wallet_addresses (wallet_id, keychain, idx, address, used, total_received_sat, balance_sat, tx_count, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for a in addresses {
+            stmt.execute(rusqlite::params![
+                wallet_id, a.keychain, a.index, a.address, a.used as i32,
+                a.total_received_sat as i64, a.balance_sat as i64, a.tx_count, now
+            ])?;
+        }
+    }
+    db_tx.commit()?;
+    Ok(())
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -137,6 +405,10 @@ pub struct UtxoInfo {
     pub vout: u32,
     pub value_sat: u64,
     pub keychain: String,
+    /// Coin control metadata, filled in from `utxo_metadata`.
+    pub label: Option<String>,
+    pub notes: Option<String>,
+    pub frozen: bool,
 }
 
 /// Get UTXOs from a BDK wallet.
@@ -148,6 +420,99 @@ pub fn get_wallet_utxos(wallet: &bdk_wallet::Wallet) -> Vec<UtxoInfo> {
             vout: utxo.outpoint.vout,
             value_sat: utxo.txout.value.to_sat(),
             keychain: format!("{:?}", utxo.keychain),
+            label: None,
+            notes: None,
+            frozen: false,
         })
         .collect()
 }
+
+/// A payment in a PSBT being built.
+pub struct PsbtRecipient {
+    pub address: String,
+    pub amount_sat: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PsbtInfo {
+    /// Unsigned PSBT, base64-encoded (BIP-174).
+    pub psbt: String,
+    pub fee_sat: u64,
+    pub inputs: Vec<String>,
+    pub change_sat: u64,
+}
+
+pub fn to_outpoint(txid: &str, vout: u32) -> AppResult<OutPoint> {
+    let txid = Txid::from_str(txid).map_err(|e| AppError::BadRequest(format!("Invalid txid: {e}")))?;
+    Ok(OutPoint::new(txid, vout))
+}
+
+/// Build an unsigned PSBT paying `recipients` at `fee_rate_sat_vb`.
+///
+/// With `selected`, only those UTXOs are spent; otherwise coin selection runs over the
+/// wallet's UTXOs minus `frozen`. Reveals (and persists) a fresh change address, so
+/// successive PSBTs don't share one.
+pub fn build_psbt(
+    wallet: &mut PersistedWallet<BdkConnection>,
+    conn: &mut BdkConnection,
+    recipients: &[PsbtRecipient],
+    fee_rate_sat_vb: f64,
+    selected: Option<Vec<OutPoint>>,
+    frozen: Vec<OutPoint>,
+) -> AppResult<PsbtInfo> {
+    let network = wallet.network();
+    let fee_rate = FeeRate::from_sat_per_kwu((fee_rate_sat_vb * 250.0).round() as u64);
+
+    let mut builder = wallet.build_tx();
+    for recipient in recipients {
+        let address = Address::from_str(&recipient.address)
+            .map_err(|e| AppError::BadRequest(format!("Invalid address {}: {e}", recipient.address)))?
+            .require_network(network)
+            .map_err(|_| {
+                AppError::BadRequest(format!("Address {} is not a {network} address", recipient.address))
+            })?;
+        builder.add_recipient(address.script_pubkey(), Amount::from_sat(recipient.amount_sat));
+    }
+    builder.fee_rate(fee_rate).unspendable(frozen);
+    if let Some(outpoints) = selected {
+        builder
+            .add_utxos(&outpoints)
+            .map_err(|e| AppError::BadRequest(format!("Cannot spend selected UTXO: {e}")))?
+            .manually_selected_only();
+    }
+
+    let psbt = builder
+        .finish()
+        .map_err(|e| AppError::BadRequest(format!("Failed to build transaction: {e}")))?;
+
+    wallet
+        .persist(conn)
+        .map_err(|e| AppError::Internal(format!("Failed to persist BDK wallet: {e}")))?;
+
+    let fee_sat = psbt
+        .fee()
+        .map_err(|e| AppError::Internal(format!("Failed to compute PSBT fee: {e}")))?
+        .to_sat();
+    let inputs = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|i| i.previous_output.to_string())
+        .collect();
+    let change_sat = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|o| {
+            matches!(wallet.derivation_of_spk(o.script_pubkey.clone()), Some((KeychainKind::Internal, _)))
+        })
+        .map(|o| o.value.to_sat())
+        .sum();
+
+    Ok(PsbtInfo {
+        psbt: base64::engine::general_purpose::STANDARD.encode(psbt.serialize()),
+        fee_sat,
+        inputs,
+        change_sat,
+    })
+}
@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::esplora::EsploraBackends;
+use crate::services::wallet::parse_network;
+use crate::services::{email, http, quotas, sync};
+
+/// A third-party address tracked for activity. Not a wallet: its transactions are
+/// kept in `watched_address_txs` and never reach a portfolio.
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchedAddress {
+    pub id: String,
+    pub user_id: String,
+    pub address: String,
+    pub network: String,
+    pub label: Option<String>,
+    pub notify: bool,
+    pub tx_count: i64,
+    pub total_received_sat: i64,
+    pub balance_sat: i64,
+    pub last_checked_at: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchedTx {
+    pub txid: String,
+    /// Received minus spent by the watched address.
+    pub net_sat: i64,
+    pub block_height: Option<i64>,
+    pub block_time: Option<String>,
+    pub first_seen_at: String,
+}
+
+pub const WATCH_COLS: &str = "id, user_id, address, network, label, notify, tx_count, total_received_sat, balance_sat, last_checked_at, created_at, updated_at";
+
+pub fn row_to_watch(row: &rusqlite::Row) -> rusqlite::Result<WatchedAddress> {
+    Ok(WatchedAddress {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        address: row.get(2)?,
+        network: row.get(3)?,
+        label: row.get(4)?,
+        notify: row.get::<_, i32>(5)? != 0,
+        tx_count: row.get(6)?,
+        total_received_sat: row.get(7)?,
+        balance_sat: row.get(8)?,
+        last_checked_at: row.get(9)?,
+        created_at: row.get(10)?,
+        updated_at: row.get(11)?,
+    })
+}
+
+/// Most recent transactions recorded for a watched address.
+pub fn recent_txs(conn: &rusqlite::Connection, watch_id: &str, limit: i64) -> AppResult<Vec<WatchedTx>> {
+    let mut stmt = conn.prepare(
+        "SELECT txid, net_sat, block_height, block_time, first_seen_at FROM watched_address_txs
+         WHERE watch_id = ?1
+         ORDER BY block_height IS NOT NULL, block_height DESC, first_seen_at DESC
+         LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![watch_id, limit], |row| {
+        Ok(WatchedTx {
+            txid: row.get(0)?,
+            net_sat: row.get(1)?,
+            block_height: row.get(2)?,
+            block_time: row.get(3)?,
+            first_seen_at: row.get(4)?,
+        })
+    })?;
+    let txs: Result<Vec<_>, _> = rows.collect();
+    Ok(txs?)
+}
+
+/// Fetch the address's latest activity and stats from Esplora and record them.
+///
+/// Returns the transactions seen for the first time. The very first check only
+/// establishes a baseline, so it returns nothing even if the address has history.
+pub async fn refresh(pool: &DbPool, esplora: &EsploraBackends, watch: &WatchedAddress) -> AppResult<Vec<WatchedTx>> {
+    let network = parse_network(&watch.network)?;
+    let address = watch.address.as_str();
+
+    let (txs, _backend) = esplora
+        .with_failover(network, |url| async move { fetch_address_txs(&url, address).await })
+        .await?;
+    let (stats, _backend) = esplora
+        .with_failover(network, |url| async move { sync::address_stats(&url, address).await })
+        .await?;
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    let mut conn = pool.get()?;
+    let db_tx = conn.transaction()?;
+    let mut new_txs = Vec::new();
+
+    for tx in &txs {
+        let received: u64 = tx
+            .vout
+            .iter()
+            .filter(|v| v.scriptpubkey_address.as_deref() == Some(address))
+            .map(|v| v.value)
+            .sum();
+        let spent: u64 = tx
+            .vin
+            .iter()
+            .filter_map(|vin| vin.prevout.as_ref())
+            .filter(|prev| prev.scriptpubkey_address.as_deref() == Some(address))
+            .map(|prev| prev.value)
+            .sum();
+        let net_sat = received as i64 - spent as i64;
+        let block_height = tx.status.block_height.map(|h| h as i64);
+        let block_time = tx
+            .status
+            .block_time
+            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+            .map(|t| t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string());
+
+        let inserted = db_tx.execute(
+            "INSERT OR IGNORE INTO watched_address_txs (watch_id, txid, net_sat, block_height, block_time, first_seen_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![watch.id, tx.txid, net_sat, block_height, block_time, now],
+        )?;
+        if inserted > 0 {
+            new_txs.push(WatchedTx {
+                txid: tx.txid.clone(),
+                net_sat,
+                block_height,
+                block_time,
+                first_seen_at: now.clone(),
+            });
+        } else {
+            // Known transaction: pick up its confirmation (or a reorg back to the mempool)
+            db_tx.execute(
+                "UPDATE watched_address_txs SET block_height = ?1, block_time = ?2 WHERE watch_id = ?3 AND txid = ?4",
+                rusqlite::params![block_height, block_time, watch.id, tx.txid],
+            )?;
+        }
+    }
+
+    db_tx.execute(
+        "UPDATE watched_addresses
+         SET tx_count = ?1, total_received_sat = ?2, balance_sat = ?3, last_checked_at = ?4, updated_at = ?4
+         WHERE id = ?5",
+        rusqlite::params![
+            stats.tx_count, stats.total_received_sat as i64, stats.balance_sat as i64, now, watch.id
+        ],
+    )?;
+    db_tx.commit()?;
+
+    if watch.last_checked_at.is_none() {
+        return Ok(Vec::new());
+    }
+    Ok(new_txs)
+}
+
+/// Background task that refreshes every watched address and emails owners who asked
+/// to be notified about new transactions.
+pub async fn run_watch_checker(pool: DbPool, config: Config, esplora: EsploraBackends) {
+    let interval = config.intervals.watch_check;
+    tracing::info!("Watched address checker started (interval: {}s)", interval.as_secs());
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let watches: Vec<WatchedAddress> = match load_all(&pool) {
+            Ok(w) => w,
+            Err(e) => {
+                tracing::error!("Watched address checker: failed to load addresses: {e}");
+                continue;
+            }
+        };
+
+        for watch in watches {
+            let new_txs = match refresh(&pool, &esplora, &watch).await {
+                Ok(txs) => txs,
+                Err(e) => {
+                    tracing::warn!("Watched address {} refresh failed: {e}", watch.id);
+                    continue;
+                }
+            };
+            if new_txs.is_empty() || !watch.notify {
+                continue;
+            }
+            tracing::info!("Watched address {}: {} new tx(s)", watch.id, new_txs.len());
+            if let Err(e) = notify(&pool, &config, &watch, &new_txs).await {
+                tracing::warn!("Watched address {} notification failed: {e}", watch.id);
+            }
+        }
+    }
+}
+
+fn load_all(pool: &DbPool) -> AppResult<Vec<WatchedAddress>> {
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(&format!("SELECT {WATCH_COLS} FROM watched_addresses ORDER BY created_at"))?;
+    let rows = stmt.query_map([], row_to_watch)?;
+    let watches: Result<Vec<_>, _> = rows.collect();
+    Ok(watches?)
+}
+
+async fn notify(pool: &DbPool, config: &Config, watch: &WatchedAddress, txs: &[WatchedTx]) -> AppResult<()> {
+    let (to, name) = {
+        let conn = pool.get()?;
+        let recipient: (String, String) = conn.query_row(
+            "SELECT email, name FROM users WHERE id = ?1",
+            rusqlite::params![watch.user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if !quotas::consume_email(&conn, config, &watch.user_id)? {
+            return Ok(());
+        }
+        recipient
+    };
+
+    let title = watch.label.clone().unwrap_or_else(|| watch.address.clone());
+    let subject = if txs.len() == 1 {
+        format!("New transaction on {title}")
+    } else {
+        format!("{} new transactions on {title}", txs.len())
+    };
+    let rows: String = txs
+        .iter()
+        .map(|tx| {
+            let status = if tx.block_height.is_some() { "confirmed" } else { "unconfirmed" };
+            format!(
+                "<li><strong>{:+.8} BTC</strong> ({status})<br /><code>{}</code></li>",
+                tx.net_sat as f64 / 1e8,
+                tx.txid
+            )
+        })
+        .collect();
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px; color: #333;">
+  <h2 style="color: #1a1a1a;">Address activity</h2>
+  <p>Hi {name}, there is new activity on the address you're watching:</p>
+  <p><strong>{title}</strong><br /><code>{address}</code></p>
+  <ul>{rows}</ul>
+  <p style="text-align: center; margin: 30px 0;">
+    <a href="{app_url}/watch" style="display: inline-block; padding: 14px 28px; background: #f7931a; color: #fff; text-decoration: none; border-radius: 6px; font-weight: 600; font-size: 16px;">View Watched Addresses</a>
+  </p>
+</body>
+</html>"#,
+        address = watch.address,
+        app_url = config.app_url,
+    );
+
+    email::send_email(config, &to, &subject, &html).await
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTx {
+    txid: String,
+    #[serde(default)]
+    vin: Vec<EsploraVin>,
+    #[serde(default)]
+    vout: Vec<EsploraTxOut>,
+    status: EsploraTxStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraVin {
+    #[serde(default)]
+    prevout: Option<EsploraTxOut>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxOut {
+    #[serde(default)]
+    scriptpubkey_address: Option<String>,
+    #[serde(default)]
+    value: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTxStatus {
+    #[serde(default)]
+    block_height: Option<u32>,
+    #[serde(default)]
+    block_time: Option<i64>,
+}
+
+/// Mempool transactions plus the latest confirmed page for an address.
+async fn fetch_address_txs(esplora_url: &str, address: &str) -> AppResult<Vec<EsploraTx>> {
+    let http = http::client()?;
+    let url = format!("{esplora_url}/address/{address}/txs");
+    let resp = http
+        .get(&url)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| http::upstream_error("esplora", format!("Esplora request failed for {url}"), e))?;
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(AppError::Internal(format!("Esplora returned {status} for {url}: {body}")));
+    }
+
+    resp.json()
+        .await
+        .map_err(|e| http::upstream_error("esplora", "Esplora response parse failed", e))
+}
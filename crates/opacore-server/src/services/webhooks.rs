@@ -0,0 +1,285 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::TaskIntervals;
+use crate::db::DbPool;
+use crate::error::{AppError, AppResult};
+use crate::services::events::DomainEvent;
+use crate::services::http;
+
+/// Events an endpoint can subscribe to.
+pub const EVENT_TYPES: [&str; 3] = ["invoice.paid", "invoice.partially_paid", "invoice.expired"];
+
+/// Attempts before a delivery is given up as failed; with the backoff below the last
+/// one is about two hours after the event.
+const MAX_ATTEMPTS: i64 = 8;
+
+/// Wait before the first retry, doubled after each further failure.
+const RETRY_BASE_SECS: i64 = 60;
+
+/// Deliveries attempted per round.
+const BATCH_SIZE: i64 = 50;
+
+/// A merchant URL that receives signed invoice events. The secret is only returned
+/// when the endpoint is created.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEndpoint {
+    pub id: String,
+    pub user_id: String,
+    pub url: String,
+    /// `None` subscribes to every event type.
+    pub event_types: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub is_active: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub const ENDPOINT_COLS: &str = "id, user_id, url, event_types, description, is_active, created_at, updated_at";
+
+pub fn row_to_endpoint(row: &rusqlite::Row) -> rusqlite::Result<WebhookEndpoint> {
+    Ok(WebhookEndpoint {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        url: row.get(2)?,
+        event_types: row
+            .get::<_, Option<String>>(3)?
+            .map(|types| types.split(',').map(String::from).collect()),
+        description: row.get(4)?,
+        is_active: row.get::<_, i32>(5).map(|v| v != 0)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+/// One event sent (or still to be sent) to one endpoint.
+#[derive(Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub endpoint_id: String,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    /// `pending`, `delivered` or `failed`.
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: Option<String>,
+    pub last_status_code: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+}
+
+pub const DELIVERY_COLS: &str = "id, endpoint_id, event_type, payload, status, attempts, next_attempt_at, last_status_code, last_error, created_at, delivered_at";
+
+pub fn row_to_delivery(row: &rusqlite::Row) -> rusqlite::Result<WebhookDelivery> {
+    let payload: String = row.get(3)?;
+    Ok(WebhookDelivery {
+        id: row.get(0)?,
+        endpoint_id: row.get(1)?,
+        event_type: row.get(2)?,
+        payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+        status: row.get(4)?,
+        attempts: row.get(5)?,
+        next_attempt_at: row.get(6)?,
+        last_status_code: row.get(7)?,
+        last_error: row.get(8)?,
+        created_at: row.get(9)?,
+        delivered_at: row.get(10)?,
+    })
+}
+
+/// A new signing secret, shaped like Stripe's so receivers can tell it apart.
+pub fn generate_secret() -> String {
+    use rand::RngCore;
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+/// `X-Opacore-Signature` value: `t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>">`,
+/// the same scheme Stripe uses, so receivers can reuse their verification code.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!("t={timestamp},v1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queue a delivery of `event` to every active endpoint of the portfolio owner that
+/// subscribes to it. Safe to repeat for the same event.
+pub fn enqueue(pool: &DbPool, event_id: i64, portfolio_id: &str, event: &DomainEvent) -> AppResult<()> {
+    let event_type = event.name();
+    if !EVENT_TYPES.contains(&event_type) {
+        return Ok(());
+    }
+
+    let conn = pool.get()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ENDPOINT_COLS} FROM webhook_endpoints
+         WHERE is_active = 1 AND user_id = (SELECT user_id FROM portfolios WHERE id = ?1)"
+    ))?;
+    let endpoints: Vec<WebhookEndpoint> = stmt
+        .query_map(rusqlite::params![portfolio_id], row_to_endpoint)?
+        .collect::<Result<_, _>>()?;
+    let endpoints: Vec<_> = endpoints
+        .into_iter()
+        .filter(|e| e.event_types.as_ref().is_none_or(|types| types.iter().any(|t| t == event_type)))
+        .collect();
+    if endpoints.is_empty() {
+        return Ok(());
+    }
+
+    // The event's own fields, plus what a merchant needs to match it to an order
+    let mut data = serde_json::to_value(event)
+        .map_err(|e| AppError::Internal(format!("Failed to encode event: {e}")))?;
+    if let Some(fields) = data.as_object_mut() {
+        fields.remove("type");
+        fields.insert("portfolio_id".into(), portfolio_id.into());
+        if let Some(invoice_id) = fields.get("invoice_id").and_then(|v| v.as_str()).map(String::from) {
+            let invoice: Option<(Option<String>, String, i64)> = conn
+                .query_row(
+                    "SELECT invoice_number, status, amount_sat FROM invoices WHERE id = ?1",
+                    rusqlite::params![invoice_id],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .ok();
+            if let Some((invoice_number, status, invoice_amount_sat)) = invoice {
+                fields.insert("invoice_number".into(), invoice_number.into());
+                fields.insert("invoice_status".into(), status.into());
+                fields.insert("invoice_amount_sat".into(), invoice_amount_sat.into());
+            }
+        }
+    }
+
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    for endpoint in endpoints {
+        let id = Uuid::new_v4().to_string();
+        let payload = serde_json::json!({
+            "id": id,
+            "type": event_type,
+            "created_at": now,
+            "data": data,
+        });
+        conn.execute(
+            "INSERT OR IGNORE INTO webhook_deliveries (id, endpoint_id, event_id, event_type, payload, next_attempt_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![id, endpoint.id, event_id, event_type, payload.to_string(), now, now],
+        )?;
+    }
+    Ok(())
+}
+
+/// POST one delivery to `url`, which must still resolve to a public address. Redirects
+/// aren't followed.
+async fn send(
+    url: &str,
+    event_type: &str,
+    delivery_id: &str,
+    signature: &str,
+    payload: String,
+) -> Result<reqwest::StatusCode, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL: {e}"))?;
+    let addrs = http::resolve_public(&url).await.map_err(|e| match e {
+        AppError::BadRequest(reason) => reason,
+        e => e.to_string(),
+    })?;
+    let client = http::pinned_client(&url, &addrs).map_err(|e| e.to_string())?;
+    let resp = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Opacore-Event", event_type)
+        .header("X-Opacore-Delivery", delivery_id)
+        .header("X-Opacore-Signature", signature)
+        .body(payload)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {e}"))?;
+    Ok(resp.status())
+}
+
+/// Background task that sends due webhook deliveries, retrying failures with
+/// exponential backoff until `MAX_ATTEMPTS`.
+pub async fn run_webhook_deliveries(pool: DbPool, intervals: TaskIntervals) {
+    tracing::info!(
+        "Webhook delivery task started (interval: {}s)",
+        intervals.webhook_delivery.as_secs()
+    );
+
+    loop {
+        tokio::time::sleep(intervals.webhook_delivery).await;
+
+        if let Err(e) = deliver_due(&pool).await {
+            tracing::error!("Webhook deliveries: {e}");
+        }
+    }
+}
+
+async fn deliver_due(pool: &DbPool) -> AppResult<()> {
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+    // Deliveries of a disabled endpoint wait until it is enabled again
+    let due: Vec<(String, String, String, String, i64, String, String)> = {
+        let conn = pool.get()?;
+        let mut stmt = conn.prepare(
+            "SELECT d.id, d.event_type, d.payload, d.endpoint_id, d.attempts, e.url, e.secret
+             FROM webhook_deliveries d JOIN webhook_endpoints e ON e.id = d.endpoint_id
+             WHERE d.status = 'pending' AND d.next_attempt_at <= ?1 AND e.is_active = 1
+             ORDER BY d.next_attempt_at LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![now, BATCH_SIZE], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?))
+        })?;
+        rows.collect::<Result<_, _>>()?
+    };
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    for (delivery_id, event_type, payload, endpoint_id, attempts, url, secret) in due {
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = signature(&secret, timestamp, &payload);
+        let result = send(&url, &event_type, &delivery_id, &signature, payload).await;
+
+        // Only the status is kept: the response body is the endpoint's, not ours to show
+        let (status_code, error) = match result {
+            Ok(status) if status.is_success() => (Some(status.as_u16() as i64), None),
+            Ok(status) => (Some(status.as_u16() as i64), Some(format!("Endpoint returned {status}"))),
+            Err(e) => (None, Some(e)),
+        };
+
+        let attempts = attempts + 1;
+        let now = chrono::Utc::now();
+        let now_str = now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let conn = pool.get()?;
+        match error {
+            None => {
+                conn.execute(
+                    "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?1, next_attempt_at = NULL,
+                         last_status_code = ?2, last_error = NULL, delivered_at = ?3
+                     WHERE id = ?4",
+                    rusqlite::params![attempts, status_code, now_str, delivery_id],
+                )?;
+            }
+            Some(error) if attempts >= MAX_ATTEMPTS => {
+                tracing::warn!("Webhook delivery {delivery_id} to endpoint {endpoint_id} failed for good: {error}");
+                conn.execute(
+                    "UPDATE webhook_deliveries SET status = 'failed', attempts = ?1, next_attempt_at = NULL,
+                         last_status_code = ?2, last_error = ?3
+                     WHERE id = ?4",
+                    rusqlite::params![attempts, status_code, error, delivery_id],
+                )?;
+            }
+            Some(error) => {
+                let delay = chrono::Duration::seconds(RETRY_BASE_SECS << (attempts - 1));
+                let next_attempt_at = (now + delay).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+                tracing::debug!("Webhook delivery {delivery_id} failed (attempt {attempts}): {error}");
+                conn.execute(
+                    "UPDATE webhook_deliveries SET attempts = ?1, next_attempt_at = ?2, last_status_code = ?3, last_error = ?4
+                     WHERE id = ?5",
+                    rusqlite::params![attempts, next_attempt_at, status_code, error, delivery_id],
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::io::{self, Read, Write};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+const VERSION_NEEDED: u16 = 20;
+/// Unix host, so extractors apply `FILE_MODE` instead of guessing permissions.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_NEEDED;
+const FILE_MODE: u32 = 0o100644;
+/// Sizes follow the data in a descriptor (bit 3); names are UTF-8 (bit 11).
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+const METHOD_STORE: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// Writes a ZIP archive one deflated entry at a time, without seeking, so it can go
+/// straight to a file or a response body. Entries are written with `io::Write` after
+/// `start_file`. No ZIP64: an archive over 4 GiB or 65535 entries is an error.
+pub struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    /// DOS time and date stamped on every entry.
+    modified: (u16, u16),
+    entries: Vec<Entry>,
+    current: Option<Current>,
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+}
+
+struct Current {
+    entry: Entry,
+    encoder: DeflateEncoder<Vec<u8>>,
+    crc: Crc,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W, modified: DateTime<Utc>) -> Self {
+        Self {
+            out,
+            offset: 0,
+            modified: dos_time(modified),
+            entries: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Finish the entry being written, if any, and start one called `name`.
+    pub fn start_file(&mut self, name: &str) -> io::Result<()> {
+        self.finish_file()?;
+        let offset = self.offset;
+        let (time, date) = self.modified;
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&[0; 12]); // CRC and sizes are in the data descriptor
+        header.extend_from_slice(&u16_field(name.len())?.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.emit(&header)?;
+
+        self.current = Some(Current {
+            entry: Entry {
+                name: name.to_string(),
+                crc: 0,
+                compressed: 0,
+                size: 0,
+                offset,
+            },
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+            crc: Crc::new(),
+        });
+        Ok(())
+    }
+
+    /// Write the central directory and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.finish_file()?;
+        let start = self.offset;
+        let (time, date) = self.modified;
+
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+            directory.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+            directory.extend_from_slice(&FLAGS.to_le_bytes());
+            directory.extend_from_slice(&METHOD_DEFLATE.to_le_bytes());
+            directory.extend_from_slice(&time.to_le_bytes());
+            directory.extend_from_slice(&date.to_le_bytes());
+            directory.extend_from_slice(&entry.crc.to_le_bytes());
+            directory.extend_from_slice(&u32_field(entry.compressed)?.to_le_bytes());
+            directory.extend_from_slice(&u32_field(entry.size)?.to_le_bytes());
+            directory.extend_from_slice(&u16_field(entry.name.len())?.to_le_bytes());
+            directory.extend_from_slice(&[0; 8]); // extra and comment lengths, disk, internal attributes
+            directory.extend_from_slice(&(FILE_MODE << 16).to_le_bytes());
+            directory.extend_from_slice(&u32_field(entry.offset)?.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let count = u16_field(self.entries.len())?;
+        let size = u32_field(directory.len() as u64)?;
+        directory.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        directory.extend_from_slice(&[0; 4]); // this disk, directory's disk
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&count.to_le_bytes());
+        directory.extend_from_slice(&size.to_le_bytes());
+        directory.extend_from_slice(&u32_field(start)?.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes());
+        self.emit(&directory)?;
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn finish_file(&mut self) -> io::Result<()> {
+        let Some(Current { mut entry, encoder, crc }) = self.current.take() else {
+            return Ok(());
+        };
+        let rest = encoder.finish()?;
+        entry.compressed += rest.len() as u64;
+        self.emit(&rest)?;
+        entry.crc = crc.sum();
+        entry.size = crc.amount() as u64;
+
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc.to_le_bytes());
+        descriptor.extend_from_slice(&u32_field(entry.compressed)?.to_le_bytes());
+        descriptor.extend_from_slice(&u32_field(entry.size)?.to_le_bytes());
+        self.emit(&descriptor)?;
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ZipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let current = self
+            .current
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no ZIP entry started"))?;
+        current.crc.update(buf);
+        current.encoder.write_all(buf)?;
+        // Hand compressed output on as it appears rather than holding the entry in memory
+        let compressed = std::mem::take(current.encoder.get_mut());
+        current.entry.compressed += compressed.len() as u64;
+        self.emit(&compressed)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Extract the entry called `name` from a whole archive in memory, or `None` if there is
+/// no such entry. Stored and deflated entries are read; anything inflating past
+/// `max_size` bytes is refused rather than decompressed.
+pub fn read_entry(archive: &[u8], name: &str, max_size: u64) -> io::Result<Option<Vec<u8>>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid ZIP archive: {what}"));
+    let u16_at = |at: usize| -> io::Result<u16> {
+        let bytes = archive.get(at..at + 2).ok_or_else(|| invalid("truncated"))?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let u32_at = |at: usize| -> io::Result<u32> {
+        let bytes = archive.get(at..at + 4).ok_or_else(|| invalid("truncated"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    // The end record is the last 22 bytes plus a comment of up to 64 KiB
+    let search_from = archive.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (search_from..archive.len().saturating_sub(21))
+        .rev()
+        .find(|&at| u32_at(at).is_ok_and(|sig| sig == END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| invalid("no end of central directory"))?;
+    let count = u16_at(end + 10)?;
+    let mut at = u32_at(end + 16)? as usize;
+
+    for _ in 0..count {
+        if u32_at(at)? != CENTRAL_HEADER {
+            return Err(invalid("bad central directory entry"));
+        }
+        let entry = at;
+        let method = u16_at(at + 10)?;
+        let compressed = u32_at(at + 20)? as usize;
+        let size = u32_at(at + 24)? as u64;
+        let name_len = u16_at(at + 28)? as usize;
+        let extra_len = u16_at(at + 30)? as usize;
+        let comment_len = u16_at(at + 32)? as usize;
+        let local = u32_at(at + 42)? as usize;
+        let entry_name = archive.get(at + 46..at + 46 + name_len).ok_or_else(|| invalid("truncated"))?;
+        at += 46 + name_len + extra_len + comment_len;
+        if entry_name != name.as_bytes() {
+            continue;
+        }
+
+        if size > max_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{name} is too large")));
+        }
+        if u32_at(local)? != LOCAL_HEADER {
+            return Err(invalid("bad local header"));
+        }
+        let start = local + 30 + u16_at(local + 26)? as usize + u16_at(local + 28)? as usize;
+        let data = archive.get(start..start + compressed).ok_or_else(|| invalid("truncated"))?;
+        let mut out = Vec::with_capacity(size as usize);
+        // The declared size can lie, so the limit is applied to what actually inflates
+        match method {
+            METHOD_STORE => out.extend_from_slice(data),
+            METHOD_DEFLATE => {
+                DeflateDecoder::new(data).take(max_size + 1).read_to_end(&mut out)?;
+            }
+            _ => return Err(invalid("unsupported compression method")),
+        }
+        if out.len() as u64 > max_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{name} is too large")));
+        }
+        let mut crc = Crc::new();
+        crc.update(&out);
+        if crc.sum() != u32_at(entry + 16)? {
+            return Err(invalid("checksum mismatch"));
+        }
+        return Ok(Some(out));
+    }
+    Ok(None)
+}
+
+/// MS-DOS time and date, which ZIP entries are stamped with (two-second resolution,
+/// years from 1980).
+fn dos_time(at: DateTime<Utc>) -> (u16, u16) {
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = ((at.year().clamp(1980, 2107) - 1980) as u32) << 9 | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+fn u16_field(value: usize) -> io::Result<u16> {
+    u16::try_from(value).map_err(|_| io::Error::other("too many entries or name too long for ZIP"))
+}
+
+fn u32_field(value: u64) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| io::Error::other("archive too large for ZIP"))
+}
@@ -0,0 +1,363 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::Config;
+
+/// Name of the event `db::create_pool` emits after every SQL statement; exported as a child span.
+pub const DB_QUERY_EVENT: &str = "db.query";
+
+const EXPORT_BATCH: usize = 512;
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Finished spans waiting for export; beyond this they are dropped rather than buffered.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Install the global subscriber: formatted logs filtered by `RUST_LOG`, plus OTLP/HTTP span
+/// export when `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Must run inside the Tokio runtime.
+///
+/// Spans follow the `tracing-opentelemetry` conventions: `otel.name` and `otel.kind` fields
+/// override the exported name and kind, and a root span carrying a W3C `traceparent` field
+/// joins the caller's trace.
+pub fn init(config: &Config) {
+    let fmt = tracing_subscriber::fmt::layer().with_filter(
+        EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new("opacore_server=debug,tower_http=debug")),
+    );
+
+    let otlp = config.otlp_endpoint.as_ref().map(|endpoint| {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(export_spans(
+            rx,
+            format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            config.otel_service_name.clone(),
+        ));
+        OtlpLayer { tx }.with_filter(
+            Targets::new()
+                .with_target("opacore_server", Level::TRACE)
+                .with_target("tower_http", Level::DEBUG),
+        )
+    });
+
+    tracing_subscriber::registry().with(fmt).with(otlp).init();
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        tracing::info!("Exporting traces to {endpoint} as {}", config.otel_service_name);
+    }
+}
+
+/// Span state kept in the registry's extensions while the span is open.
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: String,
+    kind: u8,
+    start: SystemTime,
+    attributes: Vec<(String, Value)>,
+    events: Vec<Value>,
+    error: bool,
+}
+
+struct FinishedSpan {
+    data: SpanData,
+    end: SystemTime,
+}
+
+struct OtlpLayer {
+    tx: mpsc::Sender<FinishedSpan>,
+}
+
+impl OtlpLayer {
+    fn send(&self, span: FinishedSpan) {
+        // Dropping spans beats blocking request handling when the collector is slow or down
+        let _ = self.tx.try_send(span);
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+
+        let mut fields = FieldVisitor::default();
+        attrs.record(&mut fields);
+
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|p| (p.trace_id, p.span_id))
+        });
+        let (trace_id, parent_span_id) = match parent.or_else(|| fields.traceparent()) {
+            Some((trace_id, parent_id)) => (trace_id, Some(parent_id)),
+            None => (random_nonzero_u128(), None),
+        };
+
+        let data = SpanData {
+            trace_id,
+            span_id: random_nonzero_u64(),
+            parent_span_id,
+            name: fields.name.take().unwrap_or_else(|| attrs.metadata().name().to_string()),
+            kind: fields.kind,
+            start: SystemTime::now(),
+            attributes: fields.attributes,
+            events: Vec::new(),
+            error: false,
+        };
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut fields = FieldVisitor::default();
+        values.record(&mut fields);
+
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            if let Some(name) = fields.name {
+                data.name = name;
+            }
+            data.attributes.extend(fields.attributes);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else { return };
+        let mut fields = FieldVisitor::default();
+        event.record(&mut fields);
+        let now = SystemTime::now();
+
+        let mut extensions = span.extensions_mut();
+        let Some(data) = extensions.get_mut::<SpanData>() else { return };
+
+        // SQL statements are reported after the fact with their duration; turn them into child spans
+        if event.metadata().name() == DB_QUERY_EVENT {
+            let elapsed = fields
+                .attributes
+                .iter()
+                .find(|(k, _)| k == "elapsed_us")
+                .and_then(|(_, v)| v.as_u64())
+                .unwrap_or(0);
+            let child = SpanData {
+                trace_id: data.trace_id,
+                span_id: random_nonzero_u64(),
+                parent_span_id: Some(data.span_id),
+                name: DB_QUERY_EVENT.to_string(),
+                kind: SPAN_KIND_CLIENT,
+                start: now - Duration::from_micros(elapsed),
+                attributes: fields.attributes,
+                events: Vec::new(),
+                error: false,
+            };
+            drop(extensions);
+            self.send(FinishedSpan { data: child, end: now });
+            return;
+        }
+
+        if *event.metadata().level() == Level::ERROR {
+            data.error = true;
+        }
+        let mut attributes = fields.attributes;
+        attributes.push(("level".to_string(), json!(event.metadata().level().as_str())));
+        data.events.push(json!({
+            "timeUnixNano": unix_nanos(now),
+            "name": fields.message.unwrap_or_else(|| event.metadata().name().to_string()),
+            "attributes": otlp_attributes(attributes),
+        }));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let data = span.extensions_mut().remove::<SpanData>();
+        if let Some(data) = data {
+            self.send(FinishedSpan { data, end: SystemTime::now() });
+        }
+    }
+}
+
+const SPAN_KIND_INTERNAL: u8 = 1;
+const SPAN_KIND_SERVER: u8 = 2;
+const SPAN_KIND_CLIENT: u8 = 3;
+
+struct FieldVisitor {
+    message: Option<String>,
+    name: Option<String>,
+    kind: u8,
+    traceparent: Option<String>,
+    attributes: Vec<(String, Value)>,
+}
+
+impl Default for FieldVisitor {
+    fn default() -> Self {
+        Self {
+            message: None,
+            name: None,
+            kind: SPAN_KIND_INTERNAL,
+            traceparent: None,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: Value) {
+        match field.name() {
+            "message" => self.message = value.as_str().map(String::from),
+            "otel.name" => self.name = value.as_str().map(String::from),
+            "otel.kind" => {
+                self.kind = match value.as_str() {
+                    Some("server") => SPAN_KIND_SERVER,
+                    Some("client") => SPAN_KIND_CLIENT,
+                    _ => SPAN_KIND_INTERNAL,
+                }
+            }
+            "traceparent" => self.traceparent = value.as_str().map(String::from),
+            name => self.attributes.push((name.to_string(), value)),
+        }
+    }
+
+    /// Trace and parent span IDs from a W3C `traceparent` (`00-<trace>-<span>-<flags>`).
+    fn traceparent(&self) -> Option<(u128, u64)> {
+        let mut parts = self.traceparent.as_deref()?.split('-');
+        let (_version, trace, span) = (parts.next()?, parts.next()?, parts.next()?);
+        let trace_id = u128::from_str_radix(trace, 16).ok().filter(|&t| t != 0)?;
+        let span_id = u64::from_str_radix(span, 16).ok().filter(|&s| s != 0)?;
+        Some((trace_id, span_id))
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, json!(format!("{value:?}")));
+    }
+}
+
+/// Batch finished spans and POST them to the collector as OTLP/HTTP JSON.
+async fn export_spans(mut rx: mpsc::Receiver<FinishedSpan>, url: String, service_name: String) {
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build OTLP HTTP client");
+    let mut batch = Vec::with_capacity(EXPORT_BATCH);
+    let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < EXPORT_BATCH {
+                        continue;
+                    }
+                }
+                None => return,
+            },
+            _ = ticker.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        let spans: Vec<Value> = batch.drain(..).map(otlp_span).collect();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": otlp_attributes(vec![("service.name".to_string(), json!(service_name))]),
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "opacore-server" },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        // Not traced: logging here would feed back into the exporter
+        match http.post(&url).json(&body).send().await {
+            Ok(resp) if !resp.status().is_success() => {
+                eprintln!("OTLP export to {url} returned {}", resp.status());
+            }
+            Err(e) => eprintln!("OTLP export to {url} failed: {e}"),
+            Ok(_) => {}
+        }
+    }
+}
+
+fn otlp_span(span: FinishedSpan) -> Value {
+    let data = span.data;
+    json!({
+        "traceId": format!("{:032x}", data.trace_id),
+        "spanId": format!("{:016x}", data.span_id),
+        "parentSpanId": data.parent_span_id.map(|p| format!("{p:016x}")).unwrap_or_default(),
+        "name": data.name,
+        "kind": data.kind,
+        "startTimeUnixNano": unix_nanos(data.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": otlp_attributes(data.attributes),
+        "events": data.events,
+        // 1 = OK is reserved for explicit success; unset (0) unless an error was logged
+        "status": { "code": if data.error { 2 } else { 0 } },
+    })
+}
+
+fn otlp_attributes(attributes: Vec<(String, Value)>) -> Vec<Value> {
+    attributes
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(b) => json!({ "boolValue": b }),
+                Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+                Value::Number(n) => json!({ "intValue": n.to_string() }),
+                Value::String(s) => json!({ "stringValue": s }),
+                other => json!({ "stringValue": other.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+fn random_nonzero_u64() -> u64 {
+    rand::random::<u64>().max(1)
+}
+
+fn random_nonzero_u128() -> u128 {
+    rand::random::<u128>().max(1)
+}
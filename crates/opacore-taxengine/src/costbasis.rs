@@ -0,0 +1,507 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::rules::{parse_date, TaxRules};
+use crate::{resolve_transfers, section104, TxKind, TxRecord};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CostBasisMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    Hifo,
+    /// Specific identification: each disposal consumes the lots assigned to it in its
+    /// `lot_assignments`, and anything left unassigned FIFO.
+    SpecId,
+    /// UK same-day, 30-day and Section 104 pool matching. Not lot-based, so [`open_lots`]
+    /// treats it as FIFO.
+    Section104,
+}
+
+impl CostBasisMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            CostBasisMethod::Fifo => "fifo",
+            CostBasisMethod::Lifo => "lifo",
+            CostBasisMethod::Hifo => "hifo",
+            CostBasisMethod::SpecId => "specid",
+            CostBasisMethod::Section104 => "section104",
+        }
+    }
+
+    /// The method [`name`](Self::name) returns `name` for.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Fifo, Self::Lifo, Self::Hifo, Self::SpecId, Self::Section104]
+            .into_iter()
+            .find(|m| m.name() == name)
+    }
+}
+
+/// Sats of the lot `lot_id` (the acquiring record's id) that a disposal consumes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LotAssignment {
+    pub lot_id: String,
+    pub amount_sat: i64,
+}
+
+#[derive(Debug, Clone)]
+struct Lot {
+    id: Option<String>,
+    tx_type: String,
+    amount_sat: i64,
+    /// Cost per BTC, the acquisition fee included.
+    price_usd: f64,
+    date: String,
+}
+
+impl Lot {
+    fn acquired_by(tx: &TxRecord) -> Self {
+        let price_usd = if tx.amount_sat > 0 {
+            tx.acquisition_cost_usd() / (tx.amount_sat as f64 / 1e8)
+        } else {
+            tx.price_usd.unwrap_or(0.0)
+        };
+        Lot {
+            id: tx.id.clone(),
+            tx_type: tx.tx_type.clone(),
+            amount_sat: tx.amount_sat,
+            price_usd,
+            date: tx.transacted_at.clone(),
+        }
+    }
+}
+
+/// What remains of an acquisition after the disposals matched against it.
+#[derive(Debug, Serialize)]
+pub struct OpenLot {
+    /// Id of the acquiring record.
+    pub lot_id: Option<String>,
+    pub tx_type: String,
+    pub acquired_at: String,
+    pub amount_sat: i64,
+    /// Cost per BTC, the acquisition fee included.
+    pub price_usd: f64,
+    pub cost_basis_usd: f64,
+}
+
+/// An open lot valued at the current price: what selling it now would realize.
+#[derive(Debug, Serialize)]
+pub struct UnrealizedLot {
+    #[serde(flatten)]
+    pub lot: OpenLot,
+    pub current_value_usd: f64,
+    pub unrealized_gain_usd: f64,
+    /// Whether selling it on the valuation date would be a long-term disposal.
+    pub is_long_term: bool,
+    pub holding_period_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GainLoss {
+    /// `sell`, `lost` for a zero-proceeds disposal, or `fee` when
+    /// [`TaxRules::fees_taxable`] is set.
+    pub tx_type: String,
+    /// When the lot disposed of was acquired, or `None` if the sats came from a pool of
+    /// acquisitions made on different days.
+    pub acquired_date: Option<String>,
+    pub sell_date: String,
+    pub sell_amount_sat: i64,
+    pub sell_price_usd: f64,
+    pub cost_basis_usd: f64,
+    /// At `sell_price_usd`, less this part's share of the sale's fee.
+    pub proceeds_usd: f64,
+    pub gain_usd: f64,
+    pub is_long_term: bool,
+    pub holding_period_days: i64,
+}
+
+/// BTC earned (`income`, `mining`), taxable as ordinary income at fair market value.
+#[derive(Debug, Clone, Serialize)]
+pub struct IncomeEvent {
+    pub tx_type: String,
+    pub date: String,
+    pub amount_sat: i64,
+    pub fair_value_usd: f64,
+}
+
+/// A lot given away or spent (`gift`, `donation`, `fee`). No gain is realized, but gift
+/// and donation reporting needs the basis, fair value and holding period.
+#[derive(Debug, Clone, Serialize)]
+pub struct NonTaxableDisposal {
+    pub tx_type: String,
+    pub date: String,
+    pub amount_sat: i64,
+    pub fair_value_usd: f64,
+    pub cost_basis_usd: f64,
+    pub is_long_term: bool,
+    pub holding_period_days: i64,
+}
+
+/// A transaction with no `price_usd` whose price the figures depend on. It's counted at
+/// 0, so basis, proceeds or income involving it are understated until a price is filled in.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingPrice {
+    pub transaction_id: Option<String>,
+    pub tx_type: String,
+    pub transacted_at: String,
+    pub amount_sat: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortfolioSummary {
+    pub total_balance_sat: i64,
+    pub total_cost_basis_usd: f64,
+    pub current_value_usd: f64,
+    pub unrealized_gain_usd: f64,
+    pub realized_gain_usd: f64,
+    pub total_received_sat: i64,
+    pub total_sent_sat: i64,
+    pub transaction_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CostBasisResult {
+    pub method: String,
+    pub gains: Vec<GainLoss>,
+    pub total_realized_gain_usd: f64,
+    pub total_short_term_gain_usd: f64,
+    pub total_long_term_gain_usd: f64,
+    pub remaining_lots: usize,
+    pub remaining_balance_sat: i64,
+    pub remaining_cost_basis_usd: f64,
+    /// Sats sold or lost with no lot left to match them against. Non-zero means acquisitions are
+    /// missing, and those disposals have no gain or loss reported at all.
+    pub unmatched_sell_sat: i64,
+    pub income: Vec<IncomeEvent>,
+    pub total_income_usd: f64,
+    pub non_taxable_disposals: Vec<NonTaxableDisposal>,
+    /// Transactions counted at a price of 0 because theirs is missing: acquisitions up to
+    /// the end of `tax_year`, and the year's sales, income and other disposals.
+    pub warnings: Vec<MissingPrice>,
+}
+
+/// Calculate cost basis and realized gains/losses over a set of transactions.
+/// Records are processed in date order regardless of the order they're passed in.
+/// With `tax_year`, only disposals in that tax year (per `rules`) are reported.
+pub fn calculate_cost_basis(
+    txs: impl IntoIterator<Item = TxRecord>,
+    method: CostBasisMethod,
+    tax_year: Option<i32>,
+    rules: &TaxRules,
+) -> CostBasisResult {
+    let mut txs = resolve_transfers(txs.into_iter().collect());
+    txs.sort_by(|a, b| a.transacted_at.cmp(&b.transacted_at));
+    let warnings = missing_prices(&txs, tax_year, rules);
+    if method == CostBasisMethod::Section104 {
+        return CostBasisResult {
+            warnings,
+            ..section104::calculate_cost_basis(&txs, tax_year, rules)
+        };
+    }
+
+    let mut lots: Vec<Lot> = Vec::new();
+    let mut gains: Vec<GainLoss> = Vec::new();
+    let mut income: Vec<IncomeEvent> = Vec::new();
+    let mut non_taxable: Vec<NonTaxableDisposal> = Vec::new();
+    let mut unmatched_sell_sat = 0;
+
+    for tx in &txs {
+        let Some(kind) = rules.kind_of(&tx.tx_type) else {
+            continue;
+        };
+        let price = tx.price_usd.unwrap_or(0.0);
+        let date = &tx.transacted_at;
+        let sold = parse_date(date);
+
+        // Filter by tax year if specified
+        let include = tax_year
+            .map(|ty| sold.map(|d| rules.tax_year_of(d)) == Some(ty))
+            .unwrap_or(true);
+
+        if kind.is_inflow() {
+            lots.push(Lot::acquired_by(tx));
+            if kind == TxKind::Income && include {
+                income.push(IncomeEvent {
+                    tx_type: tx.tx_type.clone(),
+                    date: date.clone(),
+                    amount_sat: tx.amount_sat,
+                    fair_value_usd: (tx.amount_sat as f64 / 1e8) * price,
+                });
+            }
+            continue;
+        }
+        if !kind.disposes_lots() {
+            continue; // send, transfer — no tax event
+        }
+
+        // Lost coins are disposed of for nothing
+        let sell_price = if kind == TxKind::Loss { 0.0 } else { price };
+        let (taken, unmatched) = take_lots(&mut lots, tx, method);
+        if kind != TxKind::NonTaxableDisposal {
+            unmatched_sell_sat += unmatched;
+        }
+        if !include {
+            continue;
+        }
+
+        for (lot, disposed) in taken {
+            let cost_basis = (disposed as f64 / 1e8) * lot.price_usd;
+            let (holding_days, is_long_term) = match (parse_date(&lot.date), sold) {
+                (Some(a), Some(s)) => ((s - a).num_days(), rules.is_long_term(a, s)),
+                _ => (0, false),
+            };
+
+            if kind == TxKind::NonTaxableDisposal {
+                non_taxable.push(NonTaxableDisposal {
+                    tx_type: tx.tx_type.clone(),
+                    date: date.clone(),
+                    amount_sat: disposed,
+                    fair_value_usd: (disposed as f64 / 1e8) * price,
+                    cost_basis_usd: cost_basis,
+                    is_long_term,
+                    holding_period_days: holding_days,
+                });
+                continue;
+            }
+
+            let proceeds = (disposed as f64 / 1e8) * sell_price - sale_fee_share(tx, kind, disposed);
+            gains.push(GainLoss {
+                tx_type: tx.tx_type.clone(),
+                acquired_date: Some(lot.date.clone()),
+                sell_date: date.clone(),
+                sell_amount_sat: disposed,
+                sell_price_usd: sell_price,
+                cost_basis_usd: cost_basis,
+                proceeds_usd: proceeds,
+                gain_usd: proceeds - cost_basis,
+                is_long_term,
+                holding_period_days: holding_days,
+            });
+        }
+    }
+
+    let total_realized = gains.iter().map(|g| g.gain_usd).sum();
+    let short_term: f64 = gains.iter().filter(|g| !g.is_long_term).map(|g| g.gain_usd).sum();
+    let long_term: f64 = gains.iter().filter(|g| g.is_long_term).map(|g| g.gain_usd).sum();
+    let remaining_sat: i64 = lots.iter().map(|l| l.amount_sat).sum();
+    let remaining_basis: f64 = lots
+        .iter()
+        .map(|l| (l.amount_sat as f64 / 1e8) * l.price_usd)
+        .sum();
+
+    CostBasisResult {
+        method: method.name().to_string(),
+        gains,
+        total_realized_gain_usd: total_realized,
+        total_short_term_gain_usd: short_term,
+        total_long_term_gain_usd: long_term,
+        remaining_lots: lots.len(),
+        remaining_balance_sat: remaining_sat,
+        remaining_cost_basis_usd: remaining_basis,
+        unmatched_sell_sat,
+        total_income_usd: income.iter().map(|i| i.fair_value_usd).sum(),
+        income,
+        non_taxable_disposals: non_taxable,
+        warnings,
+    }
+}
+
+/// Records without a price that a calculation over `tax_year` values: any acquisition
+/// up to its end (later sales draw on their basis), and the year's other priced events.
+/// Sends, transfers and losses use no price of their own.
+fn missing_prices(txs: &[TxRecord], tax_year: Option<i32>, rules: &TaxRules) -> Vec<MissingPrice> {
+    let year_of = |tx: &TxRecord| parse_date(&tx.transacted_at).map(|d| rules.tax_year_of(d));
+    txs.iter()
+        .filter(|tx| tx.price_usd.is_none())
+        .filter(|tx| match rules.kind_of(&tx.tx_type) {
+            Some(TxKind::Acquisition | TxKind::Income) => {
+                tax_year.is_none_or(|ty| year_of(tx).is_some_and(|y| y <= ty))
+            }
+            Some(TxKind::Sale | TxKind::NonTaxableDisposal) => {
+                tax_year.is_none_or(|ty| year_of(tx) == Some(ty))
+            }
+            _ => false,
+        })
+        .map(|tx| MissingPrice {
+            transaction_id: tx.id.clone(),
+            tx_type: tx.tx_type.clone(),
+            transacted_at: tx.transacted_at.clone(),
+            amount_sat: tx.amount_sat,
+        })
+        .collect()
+}
+
+/// The part of a sale's fee borne by `disposed` of its sats. Only sales have proceeds to
+/// take a fee from.
+pub(crate) fn sale_fee_share(tx: &TxRecord, kind: TxKind, disposed: i64) -> f64 {
+    if kind != TxKind::Sale || tx.amount_sat <= 0 {
+        return 0.0;
+    }
+    tx.fee_usd() * disposed as f64 / tx.amount_sat as f64
+}
+
+/// Summarize holdings: balance, remaining cost basis and unrealized gain at `current_price_usd`.
+pub fn portfolio_summary(
+    txs: impl IntoIterator<Item = TxRecord>,
+    current_price_usd: f64,
+    method: CostBasisMethod,
+    rules: &TaxRules,
+) -> PortfolioSummary {
+    let txs: Vec<TxRecord> = txs.into_iter().collect();
+
+    let total_received: i64 = txs
+        .iter()
+        .filter(|t| TxKind::of(&t.tx_type).is_some_and(TxKind::is_inflow))
+        .map(|t| t.amount_sat)
+        .sum();
+    let total_sent: i64 = txs
+        .iter()
+        .filter(|t| TxKind::of(&t.tx_type).is_some_and(TxKind::is_outflow))
+        .map(|t| t.amount_sat)
+        .sum();
+    let tx_count = txs.len() as i64;
+
+    let basis = calculate_cost_basis(txs, method, None, rules);
+    PortfolioSummary {
+        total_balance_sat: total_received - total_sent,
+        total_cost_basis_usd: basis.remaining_cost_basis_usd,
+        current_value_usd: 0.0,
+        unrealized_gain_usd: 0.0,
+        realized_gain_usd: basis.total_realized_gain_usd,
+        total_received_sat: total_received,
+        total_sent_sat: total_sent,
+        transaction_count: tx_count,
+    }
+    .at_price(current_price_usd)
+}
+
+impl PortfolioSummary {
+    /// The same holdings valued at `current_price_usd`.
+    pub fn at_price(mut self, current_price_usd: f64) -> Self {
+        self.current_value_usd = (self.total_balance_sat as f64 / 1e8) * current_price_usd;
+        self.unrealized_gain_usd = self.current_value_usd - self.total_cost_basis_usd;
+        self
+    }
+}
+
+/// The lots still held once every record is processed, or with `before_id` only the
+/// records before that one: what a disposal with that id has to choose from. Oldest first.
+pub fn open_lots(
+    txs: impl IntoIterator<Item = TxRecord>,
+    method: CostBasisMethod,
+    before_id: Option<&str>,
+) -> Vec<OpenLot> {
+    let mut txs = resolve_transfers(txs.into_iter().collect());
+    txs.sort_by(|a, b| a.transacted_at.cmp(&b.transacted_at));
+
+    let mut lots: Vec<Lot> = Vec::new();
+    for tx in &txs {
+        if before_id.is_some() && tx.id.as_deref() == before_id {
+            break;
+        }
+        match TxKind::of(&tx.tx_type) {
+            Some(kind) if kind.is_inflow() => lots.push(Lot::acquired_by(tx)),
+            Some(kind) if kind.disposes_lots() => {
+                take_lots(&mut lots, tx, method);
+            }
+            _ => {}
+        }
+    }
+
+    lots.sort_by(|a, b| a.date.cmp(&b.date));
+    lots.into_iter()
+        .map(|lot| OpenLot {
+            cost_basis_usd: (lot.amount_sat as f64 / 1e8) * lot.price_usd,
+            lot_id: lot.id,
+            tx_type: lot.tx_type,
+            acquired_at: lot.date,
+            amount_sat: lot.amount_sat,
+            price_usd: lot.price_usd,
+        })
+        .collect()
+}
+
+/// The lots still held, valued at `current_price_usd` on `as_of`, oldest first.
+/// [`CostBasisMethod::Section104`] pools rather than keeping lots, so it's treated as FIFO.
+pub fn unrealized_lots(
+    txs: impl IntoIterator<Item = TxRecord>,
+    method: CostBasisMethod,
+    current_price_usd: f64,
+    as_of: NaiveDate,
+    rules: &TaxRules,
+) -> Vec<UnrealizedLot> {
+    open_lots(txs, method, None)
+        .into_iter()
+        .map(|lot| {
+            let current_value_usd = (lot.amount_sat as f64 / 1e8) * current_price_usd;
+            let (holding_period_days, is_long_term) = match parse_date(&lot.acquired_at) {
+                Some(acquired) => ((as_of - acquired).num_days(), rules.is_long_term(acquired, as_of)),
+                None => (0, false),
+            };
+            UnrealizedLot {
+                unrealized_gain_usd: current_value_usd - lot.cost_basis_usd,
+                current_value_usd,
+                is_long_term,
+                holding_period_days,
+                lot,
+            }
+        })
+        .collect()
+}
+
+/// Take the disposal `tx` out of `lots` in `method` order. Returns each lot drawn on with
+/// the sats taken from it, and the sats no lot was left to cover.
+fn take_lots(lots: &mut Vec<Lot>, tx: &TxRecord, method: CostBasisMethod) -> (Vec<(Lot, i64)>, i64) {
+    let mut remaining = tx.amount_sat;
+    let mut taken = Vec::new();
+
+    // Assigned lots first; an assignment larger than what's left of its lot takes the rest
+    if method == CostBasisMethod::SpecId {
+        for assignment in &tx.lot_assignments {
+            let Some(i) = lots.iter().position(|l| l.id.as_deref() == Some(assignment.lot_id.as_str())) else {
+                continue;
+            };
+            let disposed = remaining.min(assignment.amount_sat).min(lots[i].amount_sat);
+            if disposed <= 0 {
+                continue;
+            }
+            taken.push((lots[i].clone(), disposed));
+            lots[i].amount_sat -= disposed;
+            remaining -= disposed;
+            if lots[i].amount_sat == 0 {
+                lots.remove(i);
+            }
+        }
+    }
+
+    // Sort lots based on method before depleting
+    sort_lots(lots, method);
+
+    while remaining > 0 && !lots.is_empty() {
+        let lot = &mut lots[0];
+        let disposed = remaining.min(lot.amount_sat);
+        taken.push((lot.clone(), disposed));
+
+        lot.amount_sat -= disposed;
+        remaining -= disposed;
+
+        if lot.amount_sat == 0 {
+            lots.remove(0);
+        }
+    }
+    (taken, remaining)
+}
+
+fn sort_lots(lots: &mut [Lot], method: CostBasisMethod) {
+    match method {
+        // Already in chronological order
+        CostBasisMethod::Fifo | CostBasisMethod::SpecId | CostBasisMethod::Section104 => {}
+        CostBasisMethod::Lifo => lots.reverse(),
+        CostBasisMethod::Hifo => lots.sort_by(|a, b| {
+            b.price_usd
+                .partial_cmp(&a.price_usd)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        }),
+    }
+}
@@ -0,0 +1,162 @@
+//! Cost-basis and tax calculations for Bitcoin portfolios.
+//!
+//! Everything here works on plain [`TxRecord`]s — callers load transactions from
+//! wherever they live (the server's SQLite database, a CLI import, a browser
+//! preview) and pass them in. No I/O happens in this crate.
+
+pub mod costbasis;
+pub mod rules;
+mod section104;
+pub mod tax;
+
+pub use rules::TaxRules;
+
+use serde::{Deserialize, Serialize};
+
+/// Every transaction type the engine knows how to treat.
+pub const TX_TYPES: [&str; 11] = [
+    "buy", "sell", "receive", "send", "transfer", "income", "mining", "gift", "donation", "lost", "fee",
+];
+
+/// How a transaction type affects lots and what gets reported for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    /// `buy`, `receive`: a new lot at `price_usd`.
+    Acquisition,
+    /// `income`, `mining`: a new lot at fair market value, also reported as income.
+    Income,
+    /// `sell`: disposes of lots, realizing a gain or loss.
+    Sale,
+    /// `gift`, `donation`, `fee`: disposes of lots without a gain or loss. Reported
+    /// separately with their basis and fair value, since gifts and donations have
+    /// rules of their own.
+    NonTaxableDisposal,
+    /// `lost`: disposes of lots for zero proceeds.
+    Loss,
+    /// `send`: leaves the portfolio's balance but not its lots.
+    Outflow,
+    /// `transfer`: moved between the user's own wallets.
+    Transfer,
+}
+
+impl TxKind {
+    /// Kind of a `tx_type`, or `None` for types the engine doesn't know (ignored).
+    pub fn of(tx_type: &str) -> Option<Self> {
+        Some(match tx_type {
+            "buy" | "receive" => TxKind::Acquisition,
+            "income" | "mining" => TxKind::Income,
+            "sell" => TxKind::Sale,
+            "gift" | "donation" | "fee" => TxKind::NonTaxableDisposal,
+            "lost" => TxKind::Loss,
+            "send" => TxKind::Outflow,
+            "transfer" => TxKind::Transfer,
+            _ => return None,
+        })
+    }
+
+    /// Adds to the balance and creates a lot.
+    pub fn is_inflow(self) -> bool {
+        matches!(self, TxKind::Acquisition | TxKind::Income)
+    }
+
+    /// Takes from the balance.
+    pub fn is_outflow(self) -> bool {
+        matches!(self, TxKind::Sale | TxKind::NonTaxableDisposal | TxKind::Loss | TxKind::Outflow)
+    }
+
+    /// Takes lots out of the pool matched against later sales.
+    pub fn disposes_lots(self) -> bool {
+        matches!(self, TxKind::Sale | TxKind::NonTaxableDisposal | TxKind::Loss)
+    }
+}
+
+/// A transaction as seen by the engine. Only the fields that affect cost basis.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxRecord {
+    /// Names the lot an acquisition creates, so disposals can be assigned to it.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// One of [`TX_TYPES`]; anything else is ignored.
+    pub tx_type: String,
+    pub amount_sat: i64,
+    /// Network or exchange fee, valued at `price_usd`. Included in a `send`'s
+    /// `amount_sat`; added to a `buy`'s cost and taken off a `sell`'s proceeds.
+    #[serde(default)]
+    pub fee_sat: Option<i64>,
+    /// BTC price in USD at the time of the transaction. Missing prices count as 0, and
+    /// are listed in [`costbasis::CostBasisResult::warnings`].
+    pub price_usd: Option<f64>,
+    /// RFC 3339 timestamp or `YYYY-MM-DD` date.
+    pub transacted_at: String,
+    /// For a disposal, the lots it consumes under [`CostBasisMethod::SpecId`]. Other
+    /// methods ignore them.
+    ///
+    /// [`CostBasisMethod::SpecId`]: costbasis::CostBasisMethod::SpecId
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lot_assignments: Vec<costbasis::LotAssignment>,
+    /// On a `send` or `receive`, the id of the other side of a transfer between the
+    /// owner's own wallets. See [`resolve_transfers`].
+    #[serde(default)]
+    pub linked_id: Option<String>,
+}
+
+impl TxRecord {
+    /// USD value of `fee_sat` at the record's price.
+    pub(crate) fn fee_usd(&self) -> f64 {
+        (self.fee_sat.unwrap_or(0).max(0) as f64 / 1e8) * self.price_usd.unwrap_or(0.0)
+    }
+
+    /// What an acquisition cost in USD: the coins at `price_usd`, plus the fee on a `buy`.
+    pub(crate) fn acquisition_cost_usd(&self) -> f64 {
+        let cost = (self.amount_sat as f64 / 1e8) * self.price_usd.unwrap_or(0.0);
+        if self.tx_type == "buy" {
+            cost + self.fee_usd()
+        } else {
+            cost
+        }
+    }
+}
+
+/// Rewrite linked transfer pairs so they leave the lots alone: the coins keep their
+/// acquisition dates and basis in the wallet they moved to, and only the send's network
+/// fee is spent, as a `fee` (taxed like a sale with [`TaxRules::fees_taxable`]). A link
+/// whose other side isn't among `txs` (in another portfolio, say) is ignored, and the
+/// record counts as a plain send or receive.
+pub fn resolve_transfers(txs: Vec<TxRecord>) -> Vec<TxRecord> {
+    let ids: std::collections::HashSet<String> = txs.iter().filter_map(|t| t.id.clone()).collect();
+    let mut resolved = Vec::with_capacity(txs.len());
+    for tx in txs {
+        let linked = tx.linked_id.as_ref().is_some_and(|id| ids.contains(id));
+        match TxKind::of(&tx.tx_type) {
+            Some(TxKind::Outflow) if linked => {
+                let fee_sat = tx.fee_sat.unwrap_or(0).min(tx.amount_sat).max(0);
+                if fee_sat > 0 {
+                    resolved.push(TxRecord {
+                        tx_type: "fee".to_string(),
+                        amount_sat: fee_sat,
+                        fee_sat: None,
+                        ..tx.clone()
+                    });
+                }
+                resolved.push(TxRecord { tx_type: "transfer".to_string(), ..tx });
+            }
+            Some(TxKind::Acquisition) if linked => {
+                resolved.push(TxRecord { tx_type: "transfer".to_string(), ..tx });
+            }
+            _ => resolved.push(tx),
+        }
+    }
+    resolved
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TaxEngineError {
+    #[error("CSV write error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("CSV flush error: {0}")]
+    CsvFlush(String),
+
+    #[error("CSV encoding error: {0}")]
+    Encoding(#[from] std::string::FromUtf8Error),
+}
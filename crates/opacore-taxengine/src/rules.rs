@@ -0,0 +1,106 @@
+use chrono::{Datelike, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::TxKind;
+
+/// Jurisdiction-specific parameters: when a holding becomes long-term and where
+/// the tax year starts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TaxRules {
+    /// Holding period in calendar months after which a disposal is long-term. The
+    /// disposal must fall after the anniversary date, so leap days don't shift the
+    /// boundary: a lot bought 2023-03-01 is long-term from 2024-03-02.
+    pub long_term_months: u32,
+    /// Count the holding period in days instead (long-term once held more than
+    /// this many days). Overrides `long_term_months` when set.
+    pub long_term_days: Option<u32>,
+    /// Month (1-12) the tax year starts in.
+    pub fiscal_year_start_month: u32,
+    /// Day of month the tax year starts on. Tax year `N` starts on this date in
+    /// calendar year `N`, e.g. UK 2024 = 2024-04-06 to 2025-04-05.
+    pub fiscal_year_start_day: u32,
+    /// Treat BTC spent on network fees (`fee` records, including the fee of a linked
+    /// transfer) as a disposal that realizes a gain or loss, rather than a non-taxable one.
+    pub fees_taxable: bool,
+    /// Refuse to produce a tax report while a transaction it depends on has no price
+    /// (see [`CostBasisResult::warnings`]), rather than counting that price as 0.
+    ///
+    /// [`CostBasisResult::warnings`]: crate::costbasis::CostBasisResult::warnings
+    pub require_prices: bool,
+}
+
+impl Default for TaxRules {
+    /// US rules: long-term after more than one year, calendar tax year.
+    fn default() -> Self {
+        Self {
+            long_term_months: 12,
+            long_term_days: None,
+            fiscal_year_start_month: 1,
+            fiscal_year_start_day: 1,
+            fees_taxable: false,
+            require_prices: false,
+        }
+    }
+}
+
+impl TaxRules {
+    /// Check the rules make sense, returning a message describing the first problem.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.long_term_months > 1200 {
+            return Err("long_term_months must be at most 1200".into());
+        }
+        if self.long_term_days.is_some_and(|d| d > 36_500) {
+            return Err("long_term_days must be at most 36500".into());
+        }
+        // Checked against a non-leap year: a Feb 29 start wouldn't exist most years
+        if NaiveDate::from_ymd_opt(2001, self.fiscal_year_start_month, self.fiscal_year_start_day).is_none() {
+            return Err("fiscal_year_start_month/day is not a valid date".into());
+        }
+        Ok(())
+    }
+
+    /// How a `tx_type` is treated under these rules: [`TxKind::of`], except that `fee` is a
+    /// sale when `fees_taxable` is set.
+    pub fn kind_of(&self, tx_type: &str) -> Option<TxKind> {
+        match TxKind::of(tx_type)? {
+            TxKind::NonTaxableDisposal if self.fees_taxable && tx_type == "fee" => Some(TxKind::Sale),
+            kind => Some(kind),
+        }
+    }
+
+    /// Whether a lot acquired on `acquired` and disposed of on `disposed` is long-term.
+    pub fn is_long_term(&self, acquired: NaiveDate, disposed: NaiveDate) -> bool {
+        match self.long_term_days {
+            Some(days) => (disposed - acquired).num_days() > days as i64,
+            // Past the end of the month-clamped anniversary (Feb 29 + 12 months = Feb 28)
+            None => acquired
+                .checked_add_months(Months::new(self.long_term_months))
+                .is_some_and(|anniversary| disposed > anniversary),
+        }
+    }
+
+    /// First day of tax year `year`.
+    pub fn tax_year_start(&self, year: i32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, self.fiscal_year_start_month, self.fiscal_year_start_day)
+    }
+
+    /// Last day of tax year `year`.
+    pub fn tax_year_end(&self, year: i32) -> Option<NaiveDate> {
+        self.tax_year_start(year + 1).and_then(|d| d.pred_opt())
+    }
+
+    /// Tax year a date falls in.
+    pub fn tax_year_of(&self, date: NaiveDate) -> i32 {
+        match self.tax_year_start(date.year()) {
+            Some(start) if date < start => date.year() - 1,
+            _ => date.year(),
+        }
+    }
+}
+
+/// Date part of an RFC 3339 timestamp or a plain `YYYY-MM-DD` date.
+pub(crate) fn parse_date(s: &str) -> Option<NaiveDate> {
+    let date_part = s.get(..10).unwrap_or(s);
+    NaiveDate::parse_from_str(date_part, "%Y-%m-%d").ok()
+}
@@ -0,0 +1,298 @@
+//! UK pooling of cryptoassets (HMRC Cryptoassets Manual, CRYPTO22200 onwards).
+//!
+//! A disposal is matched, in this order, against:
+//! 1. acquisitions on the same day, taken together at their average cost;
+//! 2. acquisitions in the 30 days after it ("bed and breakfast"), earliest first;
+//! 3. the Section 104 pool: everything else acquired so far, at its average cost.
+//!
+//! Same-day matches take priority over every 30-day match, and earlier disposals over
+//! later ones. For example, with a pool of 1,500 tokens that cost £6,500, selling 700 for
+//! £5,600 and buying 200 back 14 days later for £1,800 gives a £200 loss on the 200
+//! matched with the repurchase and a £1,833.33 gain on the 500 from the pool, which is
+//! left with 1,000 tokens at £4,333.33.
+
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate};
+
+use crate::costbasis::{sale_fee_share, CostBasisMethod, CostBasisResult, GainLoss, IncomeEvent, NonTaxableDisposal};
+use crate::rules::{parse_date, TaxRules};
+use crate::{TxKind, TxRecord};
+
+/// Days after a disposal whose acquisitions are matched with it.
+const BED_AND_BREAKFAST_DAYS: i64 = 30;
+
+/// Acquisitions of one day not yet matched, taken together.
+#[derive(Debug, Default, Clone, Copy)]
+struct Holding {
+    amount_sat: i64,
+    cost_usd: f64,
+}
+
+impl Holding {
+    fn add(&mut self, amount_sat: i64, cost_usd: f64) {
+        self.amount_sat += amount_sat;
+        self.cost_usd += cost_usd;
+    }
+
+    /// Take up to `amount_sat` at the average cost. Returns the sats taken and their cost.
+    fn take(&mut self, amount_sat: i64) -> (i64, f64) {
+        let taken = amount_sat.min(self.amount_sat);
+        if taken <= 0 {
+            return (0, 0.0);
+        }
+        let cost = self.cost_usd * taken as f64 / self.amount_sat as f64;
+        self.amount_sat -= taken;
+        self.cost_usd -= cost;
+        (taken, cost)
+    }
+}
+
+struct Disposal<'a> {
+    tx: &'a TxRecord,
+    kind: TxKind,
+    date: NaiveDate,
+    remaining_sat: i64,
+    /// Sats matched, their cost, and the day they were acquired unless they came from the pool.
+    matched: Vec<(i64, f64, Option<NaiveDate>)>,
+}
+
+/// Cost basis under the UK rules. Records without a valid date are ignored, and since
+/// the UK has no long-term rate every gain is reported as short-term with no holding
+/// period.
+pub(crate) fn calculate_cost_basis(txs: &[TxRecord], tax_year: Option<i32>, rules: &TaxRules) -> CostBasisResult {
+    let mut days: BTreeMap<NaiveDate, Holding> = BTreeMap::new();
+    let mut disposals: Vec<Disposal> = Vec::new();
+    let mut income: Vec<IncomeEvent> = Vec::new();
+    let in_year = |date: NaiveDate| tax_year.is_none_or(|ty| rules.tax_year_of(date) == ty);
+
+    for tx in txs {
+        let (Some(kind), Some(date)) = (rules.kind_of(&tx.tx_type), parse_date(&tx.transacted_at)) else {
+            continue;
+        };
+        let price = tx.price_usd.unwrap_or(0.0);
+        if kind.is_inflow() {
+            days.entry(date).or_default().add(tx.amount_sat, tx.acquisition_cost_usd());
+            if kind == TxKind::Income && in_year(date) {
+                income.push(IncomeEvent {
+                    tx_type: tx.tx_type.clone(),
+                    date: tx.transacted_at.clone(),
+                    amount_sat: tx.amount_sat,
+                    fair_value_usd: (tx.amount_sat as f64 / 1e8) * price,
+                });
+            }
+        } else if kind.disposes_lots() {
+            disposals.push(Disposal {
+                tx,
+                kind,
+                date,
+                remaining_sat: tx.amount_sat,
+                matched: Vec::new(),
+            });
+        }
+    }
+
+    // 1. Same day
+    for disposal in &mut disposals {
+        if let Some(day) = days.get_mut(&disposal.date) {
+            let date = disposal.date;
+            match_from(disposal, day, Some(date));
+        }
+    }
+
+    // 2. The next 30 days
+    for disposal in &mut disposals {
+        let window = (disposal.date + Duration::days(1))..=(disposal.date + Duration::days(BED_AND_BREAKFAST_DAYS));
+        for (date, day) in days.range_mut(window) {
+            if disposal.remaining_sat == 0 {
+                break;
+            }
+            match_from(disposal, day, Some(*date));
+        }
+    }
+
+    // 3. The pool, holding whatever was acquired up to the disposal's day and not matched
+    let mut pool = Holding::default();
+    let mut pending = days.into_iter().peekable();
+    let mut unmatched_sell_sat = 0;
+    for disposal in &mut disposals {
+        while let Some((_, day)) = pending.next_if(|(date, _)| *date <= disposal.date) {
+            pool.add(day.amount_sat, day.cost_usd);
+        }
+        if disposal.remaining_sat > 0 {
+            match_from(disposal, &mut pool, None);
+        }
+        if disposal.kind != TxKind::NonTaxableDisposal {
+            unmatched_sell_sat += disposal.remaining_sat;
+        }
+    }
+    for (_, day) in pending {
+        pool.add(day.amount_sat, day.cost_usd);
+    }
+
+    let mut gains: Vec<GainLoss> = Vec::new();
+    let mut non_taxable: Vec<NonTaxableDisposal> = Vec::new();
+    for disposal in disposals.iter().filter(|d| in_year(d.date)) {
+        let tx = disposal.tx;
+        let price = tx.price_usd.unwrap_or(0.0);
+        let sell_price = if disposal.kind == TxKind::Loss { 0.0 } else { price };
+        for &(amount_sat, cost_basis, acquired) in &disposal.matched {
+            let btc = amount_sat as f64 / 1e8;
+            if disposal.kind == TxKind::NonTaxableDisposal {
+                non_taxable.push(NonTaxableDisposal {
+                    tx_type: tx.tx_type.clone(),
+                    date: tx.transacted_at.clone(),
+                    amount_sat,
+                    fair_value_usd: btc * price,
+                    cost_basis_usd: cost_basis,
+                    is_long_term: false,
+                    holding_period_days: 0,
+                });
+                continue;
+            }
+            let proceeds = btc * sell_price - sale_fee_share(tx, disposal.kind, amount_sat);
+            gains.push(GainLoss {
+                tx_type: tx.tx_type.clone(),
+                acquired_date: acquired.map(|d| d.to_string()),
+                sell_date: tx.transacted_at.clone(),
+                sell_amount_sat: amount_sat,
+                sell_price_usd: sell_price,
+                cost_basis_usd: cost_basis,
+                proceeds_usd: proceeds,
+                gain_usd: proceeds - cost_basis,
+                is_long_term: false,
+                holding_period_days: 0,
+            });
+        }
+    }
+
+    let total_realized: f64 = gains.iter().map(|g| g.gain_usd).sum();
+    CostBasisResult {
+        method: CostBasisMethod::Section104.name().to_string(),
+        gains,
+        total_realized_gain_usd: total_realized,
+        total_short_term_gain_usd: total_realized,
+        total_long_term_gain_usd: 0.0,
+        remaining_lots: usize::from(pool.amount_sat > 0),
+        remaining_balance_sat: pool.amount_sat,
+        remaining_cost_basis_usd: pool.cost_usd,
+        unmatched_sell_sat,
+        total_income_usd: income.iter().map(|i| i.fair_value_usd).sum(),
+        income,
+        non_taxable_disposals: non_taxable,
+        // Filled in by the caller, the same for every method
+        warnings: Vec::new(),
+    }
+}
+
+fn match_from(disposal: &mut Disposal, holding: &mut Holding, acquired: Option<NaiveDate>) {
+    let (taken, cost) = holding.take(disposal.remaining_sat);
+    if taken > 0 {
+        disposal.remaining_sat -= taken;
+        disposal.matched.push((taken, cost, acquired));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(tx_type: &str, btc: i64, price_usd: f64, transacted_at: &str) -> TxRecord {
+        TxRecord {
+            id: None,
+            tx_type: tx_type.to_string(),
+            amount_sat: btc * 100_000_000,
+            fee_sat: None,
+            price_usd: Some(price_usd),
+            transacted_at: transacted_at.to_string(),
+            lot_assignments: Vec::new(),
+            linked_id: None,
+        }
+    }
+
+    fn calculate(txs: &[TxRecord]) -> CostBasisResult {
+        calculate_cost_basis(txs, None, &TaxRules::default())
+    }
+
+    fn assert_money(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.005, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn same_day_acquisitions_match_first() {
+        let result = calculate(&[
+            tx("buy", 1, 100.0, "2024-01-01T12:00:00Z"),
+            tx("sell", 1, 400.0, "2024-06-01T09:00:00Z"),
+            tx("buy", 1, 300.0, "2024-06-01T15:00:00Z"),
+        ]);
+
+        assert_eq!(result.gains.len(), 1);
+        let gain = &result.gains[0];
+        assert_eq!(gain.acquired_date.as_deref(), Some("2024-06-01"));
+        assert_money(gain.cost_basis_usd, 300.0);
+        assert_money(gain.gain_usd, 100.0);
+        assert_eq!(result.remaining_balance_sat, 100_000_000);
+        assert_money(result.remaining_cost_basis_usd, 100.0);
+    }
+
+    #[test]
+    fn bed_and_breakfast_matches_the_next_30_days_only() {
+        let result = calculate(&[
+            tx("buy", 2, 100.0, "2024-01-01"),
+            tx("sell", 2, 200.0, "2024-03-01"),
+            tx("buy", 1, 150.0, "2024-03-31"),
+            tx("buy", 1, 500.0, "2024-04-01"),
+        ]);
+
+        assert_eq!(result.gains.len(), 2);
+        assert_eq!(result.gains[0].acquired_date.as_deref(), Some("2024-03-31"));
+        assert_eq!(result.gains[0].sell_amount_sat, 100_000_000);
+        assert_money(result.gains[0].gain_usd, 50.0);
+        assert_eq!(result.gains[1].acquired_date, None);
+        assert_eq!(result.gains[1].sell_amount_sat, 100_000_000);
+        assert_money(result.gains[1].gain_usd, 100.0);
+        assert_eq!(result.remaining_balance_sat, 200_000_000);
+        assert_money(result.remaining_cost_basis_usd, 600.0);
+    }
+
+    #[test]
+    fn pool_at_average_cost_when_nothing_else_matches() {
+        let result = calculate(&[
+            tx("buy", 1, 100.0, "2024-01-01"),
+            tx("buy", 1, 300.0, "2024-02-01"),
+            tx("sell", 1, 400.0, "2024-06-01"),
+        ]);
+
+        assert_eq!(result.gains.len(), 1);
+        assert_eq!(result.gains[0].acquired_date, None);
+        assert_money(result.gains[0].cost_basis_usd, 200.0);
+        assert_money(result.gains[0].gain_usd, 200.0);
+        assert_eq!(result.unmatched_sell_sat, 0);
+        assert_eq!(result.remaining_lots, 1);
+        assert_money(result.remaining_cost_basis_usd, 200.0);
+    }
+
+    #[test]
+    fn hmrc_example_from_the_module_doc() {
+        // A pool of 1,500 that cost 6,500; 700 sold for 5,600; 200 bought back 14 days later for 1,800
+        let result = calculate(&[
+            tx("buy", 1_000, 4.0, "2024-01-01"),
+            tx("buy", 500, 5.0, "2024-02-01"),
+            tx("sell", 700, 8.0, "2024-06-01"),
+            tx("buy", 200, 9.0, "2024-06-15"),
+        ]);
+
+        assert_eq!(result.gains.len(), 2);
+        let (repurchase, pool) = (&result.gains[0], &result.gains[1]);
+        assert_eq!(repurchase.acquired_date.as_deref(), Some("2024-06-15"));
+        assert_eq!(repurchase.sell_amount_sat, 200 * 100_000_000);
+        assert_money(repurchase.gain_usd, -200.0);
+        assert_eq!(pool.acquired_date, None);
+        assert_eq!(pool.sell_amount_sat, 500 * 100_000_000);
+        assert_money(pool.cost_basis_usd, 2_166.67);
+        assert_money(pool.gain_usd, 1_833.33);
+        assert_money(result.total_realized_gain_usd, 1_633.33);
+        assert_eq!(result.remaining_balance_sat, 1_000 * 100_000_000);
+        assert_money(result.remaining_cost_basis_usd, 4_333.33);
+    }
+}
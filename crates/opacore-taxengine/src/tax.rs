@@ -0,0 +1,255 @@
+use serde::Serialize;
+
+use crate::costbasis::{self, CostBasisMethod, CostBasisResult, IncomeEvent, MissingPrice, NonTaxableDisposal};
+use crate::{TaxEngineError, TaxRules, TxRecord};
+
+#[derive(Debug, Serialize)]
+pub struct TaxReport {
+    pub year: i32,
+    /// First and last day of the tax year (`YYYY-MM-DD`).
+    pub period_start: String,
+    pub period_end: String,
+    pub method: String,
+    pub short_term_gains: f64,
+    pub long_term_gains: f64,
+    pub total_gains: f64,
+    pub total_proceeds: f64,
+    pub total_cost_basis: f64,
+    pub disposition_count: usize,
+    pub dispositions: Vec<TaxDisposition>,
+    /// Fair value of BTC earned in the year, reported as ordinary income.
+    pub total_income: f64,
+    pub income: Vec<IncomeEvent>,
+    /// Gifts, donations and fees: no gain, but listed for gift and charitable reporting.
+    pub non_taxable_disposals: Vec<NonTaxableDisposal>,
+    /// Transactions the figures count at a price of 0 because theirs is missing.
+    pub warnings: Vec<MissingPrice>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxDisposition {
+    pub description: String,
+    /// `YYYY-MM-DD`, or "Various" for sats from a pool. A sale drawing on several lots is
+    /// reported as one disposition per lot.
+    pub date_acquired: String,
+    pub date_sold: String,
+    pub proceeds: f64,
+    pub cost_basis: f64,
+    pub gain_or_loss: f64,
+    pub holding_period: String, // "Short-term" or "Long-term"
+    pub holding_days: i64,
+}
+
+/// Generate a tax report for a given tax year.
+pub fn generate_tax_report(
+    txs: impl IntoIterator<Item = TxRecord>,
+    year: i32,
+    method: CostBasisMethod,
+    rules: &TaxRules,
+) -> TaxReport {
+    tax_report(costbasis::calculate_cost_basis(txs, method, Some(year), rules), year, method, rules)
+}
+
+/// The tax report for `year` from a cost-basis `result` already calculated for that
+/// tax year with `method` and `rules`.
+pub fn tax_report(result: CostBasisResult, year: i32, method: CostBasisMethod, rules: &TaxRules) -> TaxReport {
+    let format_day = |d: Option<chrono::NaiveDate>| d.map(|d| d.to_string()).unwrap_or_default();
+
+    let dispositions: Vec<TaxDisposition> = result
+        .gains
+        .iter()
+        .map(|g| {
+            let btc_amount = g.sell_amount_sat as f64 / 1e8;
+            TaxDisposition {
+                description: match g.tx_type.as_str() {
+                    "lost" => format!("{:.8} BTC (lost)", btc_amount),
+                    _ => format!("{:.8} BTC", btc_amount),
+                },
+                date_acquired: match &g.acquired_date {
+                    Some(date) => date[..10.min(date.len())].to_string(),
+                    None => "Various".to_string(),
+                },
+                date_sold: g.sell_date[..10.min(g.sell_date.len())].to_string(),
+                proceeds: round2(g.proceeds_usd),
+                cost_basis: round2(g.cost_basis_usd),
+                gain_or_loss: round2(g.gain_usd),
+                holding_period: if g.is_long_term {
+                    "Long-term".to_string()
+                } else {
+                    "Short-term".to_string()
+                },
+                holding_days: g.holding_period_days,
+            }
+        })
+        .collect();
+
+    let total_proceeds: f64 = dispositions.iter().map(|d| d.proceeds).sum();
+    let total_cost: f64 = dispositions.iter().map(|d| d.cost_basis).sum();
+
+    TaxReport {
+        year,
+        period_start: format_day(rules.tax_year_start(year)),
+        period_end: format_day(rules.tax_year_end(year)),
+        method: method.name().to_string(),
+        short_term_gains: round2(result.total_short_term_gain_usd),
+        long_term_gains: round2(result.total_long_term_gain_usd),
+        total_gains: round2(result.total_realized_gain_usd),
+        total_proceeds: round2(total_proceeds),
+        total_cost_basis: round2(total_cost),
+        disposition_count: dispositions.len(),
+        dispositions,
+        total_income: round2(result.total_income_usd),
+        income: result.income,
+        non_taxable_disposals: result.non_taxable_disposals,
+        warnings: result.warnings,
+    }
+}
+
+/// A tax report's realized gains and income broken into the tax year's quarters, for
+/// estimated tax payments. Under the default (US) rules these are calendar quarters.
+#[derive(Debug, Serialize)]
+pub struct QuarterlyGains {
+    pub year: i32,
+    pub method: String,
+    pub quarters: Vec<QuarterGains>,
+    pub total_gains: f64,
+    pub total_income: f64,
+    /// As [`TaxReport::warnings`]; they can put a quarter's figures off too.
+    pub warnings: Vec<MissingPrice>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuarterGains {
+    /// 1 to 4.
+    pub quarter: u32,
+    /// First and last day of the quarter (`YYYY-MM-DD`).
+    pub period_start: String,
+    pub period_end: String,
+    pub short_term_gains: f64,
+    pub long_term_gains: f64,
+    pub total_gains: f64,
+    pub total_proceeds: f64,
+    pub disposition_count: usize,
+    pub total_income: f64,
+    /// Gains realized from the start of the year to the end of this quarter.
+    pub year_to_date_gains: f64,
+}
+
+/// Split `report` by the quarter of its tax year each disposition and income falls in.
+pub fn quarterly_gains(report: TaxReport, rules: &TaxRules) -> QuarterlyGains {
+    let start = rules
+        .tax_year_start(report.year)
+        .unwrap_or_else(|| chrono::NaiveDate::from_ymd_opt(report.year, 1, 1).expect("January 1st exists"));
+    let quarter_start = |q: u32| start + chrono::Months::new(3 * q);
+
+    let mut year_to_date = 0.0;
+    let quarters = (0..4)
+        .map(|q| {
+            let from = quarter_start(q).to_string();
+            let to = quarter_start(q + 1).pred_opt().unwrap_or(quarter_start(q + 1)).to_string();
+            let in_quarter = |date: &str| {
+                let day = &date[..10.min(date.len())];
+                day >= from.as_str() && day <= to.as_str()
+            };
+
+            let mut quarter = QuarterGains {
+                quarter: q + 1,
+                period_start: from.clone(),
+                period_end: to.clone(),
+                short_term_gains: 0.0,
+                long_term_gains: 0.0,
+                total_gains: 0.0,
+                total_proceeds: 0.0,
+                disposition_count: 0,
+                total_income: 0.0,
+                year_to_date_gains: 0.0,
+            };
+            for d in report.dispositions.iter().filter(|d| in_quarter(&d.date_sold)) {
+                if d.holding_period == "Long-term" {
+                    quarter.long_term_gains += d.gain_or_loss;
+                } else {
+                    quarter.short_term_gains += d.gain_or_loss;
+                }
+                quarter.total_proceeds += d.proceeds;
+                quarter.disposition_count += 1;
+            }
+            quarter.total_income = report
+                .income
+                .iter()
+                .filter(|i| in_quarter(&i.date))
+                .fold(0.0, |total, i| total + i.fair_value_usd);
+
+            quarter.short_term_gains = round2(quarter.short_term_gains);
+            quarter.long_term_gains = round2(quarter.long_term_gains);
+            quarter.total_gains = round2(quarter.short_term_gains + quarter.long_term_gains);
+            quarter.total_proceeds = round2(quarter.total_proceeds);
+            quarter.total_income = round2(quarter.total_income);
+            year_to_date += quarter.total_gains;
+            quarter.year_to_date_gains = round2(year_to_date);
+            quarter
+        })
+        .collect();
+
+    QuarterlyGains {
+        year: report.year,
+        method: report.method,
+        quarters,
+        total_gains: report.total_gains,
+        total_income: report.total_income,
+        warnings: report.warnings,
+    }
+}
+
+/// Render a tax report as Form 8949 CSV.
+/// Columns: Description, Date Acquired, Date Sold, Proceeds, Cost Basis, Gain/Loss, Term
+pub fn form_8949_csv(report: &TaxReport) -> Result<String, TaxEngineError> {
+    let mut data = Vec::new();
+    write_form_8949_csv(report, &mut data)?;
+    Ok(String::from_utf8(data)?)
+}
+
+/// Write a tax report as Form 8949 CSV to `out`, row by row.
+pub fn write_form_8949_csv<W: std::io::Write>(report: &TaxReport, out: W) -> Result<(), TaxEngineError> {
+    let mut wtr = csv::Writer::from_writer(out);
+
+    // Header
+    wtr.write_record([
+        "Description of Property",
+        "Date Acquired",
+        "Date Sold or Disposed Of",
+        "Proceeds (Sales Price)",
+        "Cost or Other Basis",
+        "Gain or (Loss)",
+        "Term",
+    ])?;
+
+    for d in &report.dispositions {
+        wtr.write_record([
+            &d.description,
+            &d.date_acquired,
+            &d.date_sold,
+            &format!("{:.2}", d.proceeds),
+            &format!("{:.2}", d.cost_basis),
+            &format!("{:.2}", d.gain_or_loss),
+            &d.holding_period,
+        ])?;
+    }
+
+    // Summary row
+    wtr.write_record([
+        "TOTALS",
+        "",
+        "",
+        &format!("{:.2}", report.total_proceeds),
+        &format!("{:.2}", report.total_cost_basis),
+        &format!("{:.2}", report.total_gains),
+        "",
+    ])?;
+
+    wtr.flush().map_err(|e| TaxEngineError::CsvFlush(e.to_string()))?;
+    Ok(())
+}
+
+fn round2(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}